@@ -2,6 +2,7 @@
 
 // Demo the usual measurement and calibration operations in an async environment
 // Demo how to visualize tokio tasks start/stop in a tokio worker thread pool
+// Demo how to measure the tokio runtime metrics (feature tokio_metrics)
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
@@ -19,6 +20,7 @@ const APP_NAME: &str = "tokio_demo";
 
 const XCP_QUEUE_SIZE: u32 = 1024 * 64; // 64kB
 const MAINLOOP_CYCLE_TIME_US: u32 = 1000; // 1ms
+const RUNTIME_METRICS_CYCLE_TIME_MS: u64 = 10; // 10ms
 
 //-----------------------------------------------------------------------------
 // Command line arguments (shared parser, see examples/common)
@@ -141,6 +143,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let params = CalSeg::new("params", &CALPAGE1);
     params.register();
 
+    // XCP: Measure the metrics of the tokio runtime (worker busy ratio, alive tasks, global queue depth, parks) on event "tokio_runtime"
+    // Correlate them with the task start/stop events in CANape
+    let _ = tokio_metrics::TokioMetrics::new("tokio_runtime", tokio::runtime::Handle::current())?.spawn(std::time::Duration::from_millis(RUNTIME_METRICS_CYCLE_TIME_MS));
+
     // Mainloop
     info!("Start mainloop");
 
@@ -0,0 +1,157 @@
+//----------------------------------------------------------------------------------------------
+// Module demo_signals
+// Signal generator as demo measurement source and load generator (feature demo_signals)
+// Each generator has its own event and calibration segment, the signal parameters are calibration parameters
+
+use std::time::{Duration, Instant};
+
+use crate::registry::{McRegisterTarget, McRegisterType};
+use crate::{CalSeg, Xcp, XcpClientError, XcpEvent};
+
+//-------------------------------------------------------------------------------------------------
+// Parameters and signals
+
+/// Calibration parameters of a signal generator
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, McRegisterType)]
+pub struct DemoSignalParams {
+    #[characteristic(comment = "Generator on/off")]
+    pub active: bool,
+    #[characteristic(comment = "Amplitude of sine and square", min = 0, max = 1000)]
+    pub ampl: f64,
+    #[characteristic(comment = "Frequency of sine and square", min = 0, max = 1000, unit = "Hz")]
+    pub frequency: f64,
+    #[characteristic(comment = "Offset of all signals", min = -1000, max = 1000)]
+    pub offset: f64,
+    #[characteristic(comment = "Duty cycle of square", min = 0, max = 1)]
+    pub duty: f64,
+    #[characteristic(comment = "Amplitude of noise", min = 0, max = 1000)]
+    pub noise: f64,
+    #[characteristic(comment = "Wrap around value of counter", min = 0, max = 0xFFFF_FFFF)]
+    pub counter_max: u32,
+}
+
+/// Default parameters, sine and square with amplitude 1.0 and 1 Hz, noise amplitude 0.1, counter 0..255
+pub const DEMO_SIGNAL_PARAMS: DemoSignalParams = DemoSignalParams {
+    active: true,
+    ampl: 1.0,
+    frequency: 1.0,
+    offset: 0.0,
+    duty: 0.5,
+    noise: 0.1,
+    counter_max: 255,
+};
+
+// Generated signals, measured relative to the generator event
+#[repr(C)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, McRegisterType)]
+struct DemoSignalValues {
+    #[measurement(comment = "Sine signal")]
+    sine: f64,
+    #[measurement(comment = "Square signal")]
+    square: f64,
+    #[measurement(comment = "Uniform noise signal")]
+    noise: f64,
+    #[measurement(comment = "Counter, incremented on each cycle")]
+    counter: u32,
+    #[measurement(comment = "Cycles of the generator")]
+    cycles: u32,
+}
+
+//-------------------------------------------------------------------------------------------------
+// DemoSignalGenerator
+
+/// Signal generator with sine, square, counter and noise signals "<name>.sine", ... on the event "<name>"
+/// The parameters are in the calibration segment "<name>_params"
+pub struct DemoSignalGenerator {
+    event: XcpEvent,
+    params: CalSeg<DemoSignalParams>,
+    values: DemoSignalValues,
+    start: Instant,
+    seed: u64, // xorshift state of the noise generator
+}
+
+impl DemoSignalGenerator {
+    /// Create the event and the calibration segment of a generator and register its signals and parameters
+    /// Must be called before the registry is finalized
+    pub fn new(name: &'static str) -> Result<DemoSignalGenerator, XcpClientError> {
+        let event = Xcp::get().try_create_event_ext(name, false)?;
+        let params = CalSeg::try_new(Box::leak(format!("{}_params", name).into_boxed_str()), &DEMO_SIGNAL_PARAMS)?;
+        params.register();
+        let values = DemoSignalValues::default();
+        values.mc_register(McRegisterTarget::Event(event.get_id()), Some(name));
+        Ok(DemoSignalGenerator {
+            event,
+            params,
+            values,
+            start: Instant::now(),
+            seed: 0x2545_F491_4F6C_DD1D ^ u64::from(event.get_id()),
+        })
+    }
+
+    /// Get the event of the generator
+    pub fn get_event(&self) -> XcpEvent {
+        self.event
+    }
+
+    // Uniform random number in -1.0..1.0
+    #[allow(clippy::cast_precision_loss)]
+    fn random(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        (self.seed >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+
+    /// Calculate the signals for the current time and trigger the event, nothing is done if the generator is not active
+    pub fn step(&mut self) {
+        let params = *self.params.read_lock();
+        if !params.active {
+            return;
+        }
+        let phase = (self.start.elapsed().as_secs_f64() * params.frequency).fract();
+        self.values.sine = params.offset + params.ampl * (phase * std::f64::consts::TAU).sin();
+        self.values.square = params.offset + if phase < params.duty { params.ampl } else { -params.ampl };
+        self.values.noise = params.offset + params.noise * self.random();
+        self.values.counter = if self.values.counter >= params.counter_max { 0 } else { self.values.counter + 1 };
+        self.values.cycles = self.values.cycles.wrapping_add(1);
+        // @@@@ UNSAFE - the signals are registered relative to values
+        unsafe { self.event.trigger_ext((&raw const self.values).cast::<u8>()) }
+    }
+
+    /// Run the generator in a thread with the given cycle time, as long as the XCP server is running
+    pub fn spawn(mut self, cycle_time: Duration) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            let _span = tracing::info_span!(target: "daq", "demo_signals").entered();
+            while Xcp::get().check_server() {
+                self.step();
+                std::thread::sleep(cycle_time);
+            }
+        })
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test module
+
+#[cfg(test)]
+mod demo_signals_tests {
+
+    use super::*;
+    use crate::xcp::xcp_test;
+
+    #[test]
+    fn test_demo_signals() {
+        let xcp = xcp_test::test_setup();
+        let mut generator = DemoSignalGenerator::new("test_demo_signals").unwrap();
+        assert!(DemoSignalGenerator::new("test_demo_signals").is_err());
+        for _ in 0..300 {
+            generator.step();
+            assert!(generator.values.sine.abs() <= 1.0);
+            assert!(generator.values.square.abs() == 1.0);
+            assert!(generator.values.noise.abs() <= 0.1);
+        }
+        assert_eq!(generator.values.cycles, 300);
+        assert_eq!(generator.values.counter, 300 % 256);
+        xcp.finalize_registry().unwrap();
+    }
+}
@@ -15,16 +15,54 @@
 
 // Submodule xcp
 mod xcp;
+pub use xcp::CalBinSignature;
 pub use xcp::CalCell;
+pub use xcp::CalChangeSource;
+#[cfg(feature = "lock_profiling")]
+pub use xcp::CalLockStats;
+pub use xcp::CalPageFormat;
+pub use xcp::CalPageInit;
 pub use xcp::CalSeg;
+pub use xcp::CalStorage;
+pub use xcp::CalStorageBinFile;
+pub use xcp::CalStorageJsonDir;
+pub use xcp::DaqBuffer;
 pub use xcp::DaqEvent;
+pub use xcp::DaqQuantized;
+pub use xcp::XCP_INTERNAL_EVENT_NAME;
 pub use xcp::Xcp;
+pub use xcp::XcpA2lNaming;
+pub use xcp::XcpClientError;
+pub use xcp::XcpClockEpoch;
+pub use xcp::XcpDeadlineStats;
+pub use xcp::XcpDuplicatePolicy;
 pub use xcp::XcpEvent;
+pub use xcp::XcpEventIdAllocation;
+pub use xcp::XcpLogSubsystem;
+pub use xcp::XcpMemoryUsage;
+pub use xcp::XcpPlugin;
+pub use xcp::XcpReplay;
 pub use xcp::XcpTransportLayer;
+#[cfg(feature = "cal_sqlite")]
+pub use xcp::{CalChange, CalStorageSqlite};
+#[cfg(feature = "cal_shm")]
+pub use xcp::{CalShmReader, CalShmWriter};
+#[cfg(feature = "daq_shm")]
+pub use xcp::{DAQ_SHM_SLOT_SIZE, DaqOverflowPolicy, DaqShmAggregator, DaqShmProducer};
+#[cfg(feature = "async")]
+pub use xcp::{XcpAsync, XcpEventQueue};
 
 // Public submodule metrics
 pub mod metrics;
 
+// Public submodule demo_signals
+#[cfg(feature = "demo_signals")]
+pub mod demo_signals;
+
+// Public submodule tokio_metrics
+#[cfg(feature = "tokio_metrics")]
+pub mod tokio_metrics;
+
 // Re-export the standalone registry crate under the old module path.
 pub use registry::McValueTypeTrait;
 pub use xcp_registry as registry;
@@ -33,6 +71,8 @@ pub use xcp_registry as registry;
 pub use registry::McRegisterType;
 // Re-export the McRegisterEnum derive (for integer enums used in registered structs).
 pub use registry::McRegisterEnum;
+// Re-export the MeasureSafe marker trait required by the trigger and capture APIs.
+pub use registry::MeasureSafe;
 
 // Used by macros
 #[doc(hidden)]
@@ -46,6 +86,13 @@ pub mod _private {
     pub use linkme::distributed_slice;
 }
 
+// Internal re-exports used by the C API crate xcp_lite_capi, only with feature capi. Not part of the public API.
+#[cfg(feature = "capi")]
+#[doc(hidden)]
+pub mod _capi {
+    pub use crate::xcp::xcplib::{XcpCreateCalSeg, XcpLockCalSeg, XcpUnlockCalSeg};
+}
+
 // EPK calibration segment definitions, must match libxcplite definitions
 //pub(crate) const EPK_SEG_NAME: &str = "epk";
 pub(crate) const EPK_SEG_SIZE: usize = 31;
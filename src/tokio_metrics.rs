@@ -0,0 +1,135 @@
+//----------------------------------------------------------------------------------------------
+// Module tokio_metrics
+// Measurement of tokio runtime metrics (feature tokio_metrics)
+// The stable runtime metrics of tokio are sampled cyclically into measurements of an event, to correlate application signals with executor behavior
+// Per worker local queue depth and poll counts require the tokio_unstable cfg and are not included
+
+use std::time::{Duration, Instant};
+
+use crate::registry::{McRegisterTarget, McRegisterType};
+use crate::{Xcp, XcpClientError, XcpEvent};
+
+// Sampled runtime metrics, measured relative to the metrics event
+#[repr(C)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, McRegisterType)]
+struct TokioMetricsValues {
+    #[measurement(comment = "Busy ratio of all workers", unit = "%")]
+    busy: f64,
+    #[measurement(comment = "Busy ratio of each worker", unit = "%")]
+    worker_busy: [f64; 16], // First 16 workers
+    #[measurement(comment = "Number of worker threads")]
+    workers: u32,
+    #[measurement(comment = "Number of alive tasks")]
+    alive_tasks: u32,
+    #[measurement(comment = "Tasks in the global queue")]
+    global_queue_depth: u32,
+    #[measurement(comment = "Worker parks per second", unit = "1/s")]
+    park_rate: u32,
+}
+
+/// Cyclic measurement of the metrics of a tokio runtime as "<name>.busy", "<name>.worker_busy", "<name>.workers", "<name>.alive_tasks",
+/// "<name>.global_queue_depth" and "<name>.park_rate" on the event "<name>"
+pub struct TokioMetrics {
+    event: XcpEvent,
+    handle: tokio::runtime::Handle,
+    values: TokioMetricsValues,
+    last: Instant,
+    last_busy: Vec<Duration>, // Total busy duration of each worker at the last sample
+    last_parks: u64,          // Total parks of all workers at the last sample
+}
+
+impl TokioMetrics {
+    /// Create the metrics event and register the measurements for the runtime of handle
+    /// Must be called before the registry is finalized
+    pub fn new(name: &'static str, handle: tokio::runtime::Handle) -> Result<TokioMetrics, XcpClientError> {
+        let event = Xcp::get().try_create_event_ext(name, false)?;
+        let values = TokioMetricsValues::default();
+        values.mc_register(McRegisterTarget::Event(event.get_id()), Some(name));
+        let mut metrics = TokioMetrics {
+            event,
+            handle,
+            values,
+            last: Instant::now(),
+            last_busy: Vec::new(),
+            last_parks: 0,
+        };
+        metrics.sample();
+        Ok(metrics)
+    }
+
+    /// Get the event of the runtime metrics
+    pub fn get_event(&self) -> XcpEvent {
+        self.event
+    }
+
+    // Sample the runtime metrics, ratios and rates are calculated since the last sample
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn sample(&mut self) {
+        let m = self.handle.metrics();
+        let workers = m.num_workers();
+        let elapsed = self.last.elapsed().as_secs_f64().max(1e-6);
+        self.last = Instant::now();
+        self.last_busy.resize(workers, Duration::ZERO);
+        let mut busy_total = 0.0;
+        let mut parks = 0;
+        for worker in 0..workers {
+            let busy = m.worker_total_busy_duration(worker);
+            let ratio = (busy.saturating_sub(self.last_busy[worker]).as_secs_f64() * 100.0 / elapsed).min(100.0);
+            self.last_busy[worker] = busy;
+            if let Some(b) = self.values.worker_busy.get_mut(worker) {
+                *b = ratio;
+            }
+            busy_total += ratio;
+            parks += m.worker_park_count(worker);
+        }
+        self.values.busy = if workers > 0 { busy_total / workers as f64 } else { 0.0 };
+        self.values.workers = u32::try_from(workers).unwrap_or(u32::MAX);
+        self.values.alive_tasks = u32::try_from(m.num_alive_tasks()).unwrap_or(u32::MAX);
+        self.values.global_queue_depth = u32::try_from(m.global_queue_depth()).unwrap_or(u32::MAX);
+        self.values.park_rate = u32::try_from(((parks.saturating_sub(self.last_parks)) as f64 / elapsed) as u64).unwrap_or(u32::MAX);
+        self.last_parks = parks;
+    }
+
+    /// Sample the runtime metrics and trigger the event
+    pub fn trigger(&mut self) {
+        self.sample();
+        // @@@@ UNSAFE - the measurements are registered relative to values
+        unsafe { self.event.trigger_ext((&raw const self.values).cast::<u8>()) }
+    }
+
+    /// Sample and trigger in a thread with the given cycle time, as long as the XCP server is running
+    /// A thread outside the runtime is used, the samples are not delayed by a busy runtime
+    pub fn spawn(mut self, cycle_time: Duration) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            let _span = tracing::info_span!(target: "daq", "tokio_metrics").entered();
+            while Xcp::get().check_server() {
+                std::thread::sleep(cycle_time);
+                self.trigger();
+            }
+        })
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test module
+
+#[cfg(test)]
+mod tokio_metrics_tests {
+
+    use super::*;
+    use crate::xcp::xcp_test;
+
+    #[test]
+    fn test_tokio_metrics() {
+        let xcp = xcp_test::test_setup();
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(2).build().unwrap();
+        let mut metrics = TokioMetrics::new("test_tokio_metrics", runtime.handle().clone()).unwrap();
+        assert_eq!(metrics.values.workers, 2);
+        let task = runtime.spawn(std::future::pending::<()>());
+        metrics.trigger();
+        assert_eq!(metrics.values.alive_tasks, 1);
+        assert!(metrics.values.busy <= 100.0);
+        task.abort();
+        xcp.finalize_registry().unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//-----------------------------------------------------------------------------
+// Module a2l_naming
+// Naming strategy of the generated A2L file and device description stub
+// The A2L name is reported to the XCP client tool with GET_ID IDT_ASAM_NAME, the file content with GET_ID IDT_ASAM_UPLOAD
+// CANape stores an A2L uploaded from an unknown device as <name>_autodetect.a2l, the Autodetect strategy uses the same name on
+// the server side, so the A2L on disk and the uploaded A2L are identical files
+// The device description stub contains the transport layer parameters, CANape (or any other tool) can set up the device from it
+// and load the A2L by upload on connect
+
+#[allow(unused_imports)]
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
+use std::io::Write;
+
+use crate::registry::Registry;
+
+//-----------------------------------------------------------------------------
+// XcpA2lNaming
+
+/// Naming strategy of the generated A2L file, set with Xcp::set_a2l_naming
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum XcpA2lNaming {
+    /// <app_name>.a2l
+    #[default]
+    AppName,
+    /// <app_name>_autodetect.a2l, the name CANape uses for A2L files uploaded from the device
+    /// A device description stub <app_name>_autodetect.mdi is written next to the A2L file
+    Autodetect,
+    /// Custom A2L name without extension
+    Custom(String),
+}
+
+impl XcpA2lNaming {
+    /// A2L name without extension for an application name
+    pub fn a2l_name(&self, app_name: &str) -> String {
+        match self {
+            XcpA2lNaming::AppName => app_name.to_string(),
+            XcpA2lNaming::Autodetect => format!("{}_autodetect", app_name),
+            XcpA2lNaming::Custom(name) => name.clone(),
+        }
+    }
+
+    /// Check if a device description stub is generated
+    pub fn has_device_description(&self) -> bool {
+        *self == XcpA2lNaming::Autodetect
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Device description stub
+
+// Write the device description stub for the A2L file <a2l_name>.a2l to a writer
+// Format is a simple ini file
+pub(crate) fn write_device_description<W: Write>(writer: &mut W, reg: &Registry, a2l_name: &str) -> std::io::Result<()> {
+    writeln!(writer, "; Device description generated by xcp-lite")?;
+    writeln!(writer, "[DEVICE]")?;
+    writeln!(writer, "Name={}", reg.application.get_name())?;
+    writeln!(writer, "Driver=XCP")?;
+    if let Some(tl) = &reg.xcp_tl_params {
+        writeln!(writer, "Protocol={}", tl.protocol_name)?;
+        if let Some(addr) = tl.addr {
+            writeln!(writer, "Address={}", addr)?;
+        }
+        if let Some(port) = tl.port {
+            writeln!(writer, "Port={}", port)?;
+        }
+        if let Some(baud_rate) = tl.baud_rate {
+            writeln!(writer, "BaudRate={}", baud_rate)?;
+        }
+    }
+    writeln!(writer, "[DATABASE]")?;
+    writeln!(writer, "File={}.a2l", a2l_name)?;
+    writeln!(writer, "Source=Upload")?; // GET_ID IDT_ASAM_UPLOAD on connect
+    if reg.application.has_epk() {
+        writeln!(writer, "Epk={}", reg.application.get_version())?;
+        writeln!(writer, "EpkAddress=0x{:08X}", reg.application.version.epk_addr)?;
+    }
+    Ok(())
+}
+
+// Write the device description stub to <a2l_name>.mdi
+pub(crate) fn write_device_description_file(reg: &Registry, a2l_name: &str) -> std::io::Result<std::path::PathBuf> {
+    let path = std::path::PathBuf::from(format!("{}.mdi", a2l_name));
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(&path)?);
+    write_device_description(&mut writer, reg, a2l_name)?;
+    writer.flush()?;
+    xcp_info!(Registry, "Device description written to {}", path.display());
+    Ok(path)
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod a2l_naming_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_a2l_naming() {
+        assert_eq!(XcpA2lNaming::AppName.a2l_name("demo"), "demo");
+        assert_eq!(XcpA2lNaming::Autodetect.a2l_name("demo"), "demo_autodetect");
+        assert_eq!(XcpA2lNaming::Custom("device".to_string()).a2l_name("demo"), "device");
+
+        let mut reg = Registry::new();
+        reg.application.set_info("demo", "", 0);
+        reg.application.set_version("EPK_1.0", 0x80000000);
+        reg.set_xcp_eth_params("UDP", std::net::Ipv4Addr::new(127, 0, 0, 1), 5555);
+        let mut buf = Vec::new();
+        write_device_description(&mut buf, &reg, "demo_autodetect").unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.contains("Name=demo\n"));
+        assert!(text.contains("Protocol=UDP\nAddress=127.0.0.1\nPort=5555\n"));
+        assert!(text.contains("File=demo_autodetect.a2l\n"));
+        assert!(text.contains("EpkAddress=0x80000000\n"));
+    }
+}
@@ -5,16 +5,18 @@
 //-----------------------------------------------------------------------------
 
 #[allow(unused_imports)]
-use log::{debug, error, info, trace, warn};
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
 
 #[cfg(feature = "linkme")]
 use linkme::distributed_slice;
 #[cfg(feature = "linkme")]
 use std::sync::Once;
 #[cfg(feature = "linkme")]
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 
 use crate::registry;
+use crate::xcp::XcpClientError;
+use crate::xcp::cal_storage::{CalChangeSource, CalPageFormat};
 use crate::xcp::xcplib;
 use registry::{McRegisterTarget, McRegisterType};
 use std::{marker::PhantomData, ops::Deref, ops::DerefMut};
@@ -33,6 +35,39 @@ where
 // Implement CalPageTrait for all types that may be a calibration page
 impl<T> CalPageTrait for T where T: Sized + Send + Sync + Copy + Clone + 'static + serde::Serialize + serde::de::DeserializeOwned {}
 
+//----------------------------------------------------------------------------------------------
+// CalPageInit
+
+/// Initialization of the working page of a calibration segment on creation
+/// The reference page is always the default page, COPY_CAL_PAGE from the reference page to the working page by the XCP client tool
+/// re-initializes the working page with the default page, independent of this policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CalPageInit {
+    /// Copy of the default page
+    #[default]
+    Default,
+    /// All numbers zero and all bools false, other values (chars, enum variants) are taken from the default page
+    Zero,
+    /// Page frozen in the calibration storage set with Xcp::set_cal_storage, the default page if there is none
+    Storage,
+}
+
+// Zero all numbers and bools of a page, through its serde representation, so the page is always a valid T
+fn zero_page<T: CalPageTrait>(page: &T) -> Result<T, serde_json::Error> {
+    fn zero(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Number(n) => *n = 0.into(),
+            serde_json::Value::Bool(b) => *b = false,
+            serde_json::Value::Array(a) => a.iter_mut().for_each(zero),
+            serde_json::Value::Object(o) => o.values_mut().for_each(zero),
+            serde_json::Value::Null | serde_json::Value::String(_) => {}
+        }
+    }
+    let mut value = serde_json::to_value(page)?;
+    zero(&mut value);
+    serde_json::from_value(value)
+}
+
 //----------------------------------------------------------------------------------------------
 // CalSeg
 
@@ -68,12 +103,60 @@ where
     ///
     /// Flattening for legacy tools that do not support typedefs is a separate, export-time
     /// transform on the registry (not a registration mode).
+    /// Not registered, if the memory limit is exceeded, the error is reported on the error channel.
     pub fn register(&self) -> &Self {
+        if self.is_detached() {
+            return self;
+        }
+        if let Err(e) = super::Xcp::get().check_memory(std::mem::size_of::<registry::McInstance>() + std::mem::size_of::<registry::McTypeDef>()) {
+            super::Xcp::get().report_error(e);
+            return self;
+        }
         self.default_page.mc_register(McRegisterTarget::CalSeg(self.get_name()), Some(self.get_name()));
         self
     }
 }
 
+// Impl register for foreign types, described by manual field descriptors
+impl<T> CalSeg<T>
+where
+    T: CalPageTrait,
+{
+    /// Register the calibration segment of a type which can not implement McRegisterType, e.g. a type from a third-party crate.
+    /// The fields are described by field descriptors with name, offset, type and metadata, they become the typedef type_name,
+    /// the instance name is the calibration segment name. Offsets and sizes are checked against the size of T.
+    ///
+    /// # Example
+    /// ```ignore
+    /// calseg.register_fields("PidParams", vec![
+    ///     McFieldDesc::new("kp", std::mem::offset_of!(PidParams, kp), McValueType::Float32Ieee).set_min(0.0).set_max(100.0),
+    /// ])?;
+    /// ```
+    pub fn register_fields(&self, type_name: &'static str, fields: Vec<registry::McFieldDesc>) -> Result<&Self, XcpClientError> {
+        if self.is_detached() {
+            return Err(XcpClientError::CalSeg(format!("{} not registered, detached calibration segment", type_name)));
+        }
+        super::Xcp::get().check_memory(
+            std::mem::size_of::<registry::McInstance>() + std::mem::size_of::<registry::McTypeDef>() + fields.len() * std::mem::size_of::<registry::McTypeDefField>(),
+        )?;
+        let mut reg = registry::get_lock();
+        let Some(reg) = reg.as_mut() else {
+            return Err(XcpClientError::Registry(format!("{} not registered, registry already closed", self.get_name())));
+        };
+        reg.add_typedef_from_fields(type_name, std::mem::size_of::<T>(), fields, registry::McObjectType::Characteristic)
+            .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+        reg.instance_list
+            .add_instance(
+                self.get_name(),
+                registry::McDimType::new(registry::McValueType::new_typedef(type_name), 1, 1),
+                registry::McSupportData::new(registry::McObjectType::Characteristic),
+                McRegisterTarget::CalSeg(self.get_name()).address(0),
+            )
+            .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+        Ok(self)
+    }
+}
+
 //----------------------------------------------------------------------------------------------
 // CalSeg
 
@@ -93,36 +176,76 @@ where
     /// * `default_page` - Default calibration page
     /// # Returns
     /// A CalSeg instance
-    /// # Panics
-    /// If the name is not unique
-    /// If the maximum number of calibration segments is reached, CANape supports a maximum of 255 calibration segments
+    /// If the name is not unique or the maximum number of calibration segments is reached (CANape supports a maximum of 255 calibration segments),
+    /// the error is reported on the error channel and a detached CalSeg is returned, which reads the default page and discards writes
     pub fn new(instance_name: &'static str, default_page: &'static T) -> CalSeg<T> {
-        // Create a calibration segment in the xcplib C library
-        unsafe {
-            let c_name = std::ffi::CString::new(instance_name).unwrap();
-            let c_default_page = default_page as *const T as *const std::os::raw::c_void;
-            let index = xcplib::XcpCreateCalSeg(
-                c_name.as_ptr(),
-                c_default_page,
-                u16::try_from(std::mem::size_of::<T>()).expect("CalSeg size exceeds u16::MAX"),
-            );
-
-            if index == u16::MAX {
-                panic!("xcplib_create_calseg failed for instance_name={}", instance_name);
-            }
-            CalSeg::<T> {
-                index,
+        CalSeg::try_new(instance_name, default_page).unwrap_or_else(|e| {
+            super::Xcp::get().report_error(e);
+            CalSeg {
+                index: XCP_UNDEFINED_CALSEG,
                 default_page,
                 _not_sync_marker: PhantomData,
             }
+        })
+    }
+
+    /// Create a calibration segment, without panic
+    /// Returns an error, if the name is not unique, the page is larger than 64KByte or the maximum number of calibration segments is reached
+    /// The working page is initialized with the policy set by Xcp::set_cal_page_init, default is a copy of the default page
+    pub fn try_new(instance_name: &'static str, default_page: &'static T) -> Result<CalSeg<T>, XcpClientError> {
+        CalSeg::try_new_with_init(instance_name, default_page, super::Xcp::get().get_cal_page_init())
+    }
+
+    /// Create a calibration segment with an initialization policy for the working page, see CalPageInit
+    /// If the initialization fails, the error is reported and the working page keeps the default values
+    pub fn try_new_with_init(instance_name: &'static str, default_page: &'static T, init: CalPageInit) -> Result<CalSeg<T>, XcpClientError> {
+        let size = u16::try_from(std::mem::size_of::<T>()).map_err(|_| XcpClientError::CalSeg(format!("{} exceeds 64KByte", instance_name)))?;
+        // Create a calibration segment in the xcplib C library
+        let c_name = super::c_string(instance_name);
+        let c_default_page = default_page as *const T as *const std::os::raw::c_void;
+        // @@@@ UNSAFE - C library call, default_page is a 'static page of size bytes
+        let index = unsafe { xcplib::XcpCreateCalSeg(c_name.as_ptr(), c_default_page, size) };
+        if index == XCP_UNDEFINED_CALSEG {
+            return Err(XcpClientError::CalSeg(format!("xcplib_create_calseg failed for instance_name={}", instance_name)));
         }
+        let calseg = CalSeg::<T> {
+            index,
+            default_page,
+            _not_sync_marker: PhantomData,
+        };
+        if let Err(e) = calseg.init_page(init) {
+            super::Xcp::get().report_error(e);
+        }
+        Ok(calseg)
+    }
+
+    // Initialize the working page of a new calibration segment
+    fn init_page(&self, init: CalPageInit) -> Result<(), XcpClientError> {
+        match init {
+            CalPageInit::Default => {}
+            CalPageInit::Zero => {
+                let page = zero_page(self.default_page).map_err(|e| XcpClientError::CalSeg(format!("{}: {}", self.get_name(), e)))?;
+                *self.write_lock() = page;
+                xcp_debug!(Cal, "Working page of {} initialized with zero", self.get_name());
+            }
+            CalPageInit::Storage => {
+                self.load_frozen()?;
+            }
+        }
+        Ok(())
     }
 
-    /// Get the calibration segment name
+    /// Get the calibration segment name, empty for a detached calibration segment
     pub fn get_name(&self) -> &'static str {
+        if self.is_detached() {
+            return "";
+        }
         unsafe {
             let c_str = xcplib::XcpGetCalSegName(self.index);
-            std::ffi::CStr::from_ptr(c_str).to_str().unwrap()
+            if c_str.is_null() {
+                return "";
+            }
+            std::ffi::CStr::from_ptr(c_str).to_str().unwrap_or("")
         }
     }
 
@@ -131,6 +254,11 @@ where
         self.index as usize
     }
 
+    /// Check if the calibration segment is detached, because CalSeg::new failed to create it
+    pub fn is_detached(&self) -> bool {
+        self.index == XCP_UNDEFINED_CALSEG
+    }
+
     /// Construct a `CalSeg` from a link-time registered descriptor (used by the [`cal_seg!`] macro).
     ///
     /// On the first call this triggers deterministic creation of *all* calibration segments whose
@@ -147,11 +275,19 @@ where
             "calibration segment '{}' was not registered - is XCP initialized (Xcp::init) before creating calibration segments?",
             descriptor.name
         );
-        CalSeg {
+        let calseg = CalSeg {
             index,
             default_page,
             _not_sync_marker: PhantomData,
+        };
+        // The working page is initialized when the first CalSeg of the segment is constructed
+        if !descriptor.initialized.swap(true, Ordering::AcqRel) {
+            let init = super::Xcp::get().get_cal_page_init();
+            if let Err(e) = calseg.init_page(init) {
+                super::Xcp::get().report_error(e);
+            }
         }
+        calseg
     }
 }
 
@@ -159,7 +295,6 @@ where
 // Link-time calibration segment registration (cal_seg! macro)
 
 /// Sentinel for an unresolved / invalid calibration segment index (matches XCP_UNDEFINED_CALSEG).
-#[cfg(feature = "linkme")]
 const XCP_UNDEFINED_CALSEG: u16 = 0xFFFF;
 
 /// Descriptor for a calibration segment, collected into [`CAL_SEG_REGISTRY`] at link time by the
@@ -173,6 +308,7 @@ pub struct CalSegDescriptor {
     default_page: *const core::ffi::c_void,
     size: u16,
     index: AtomicU16,
+    initialized: AtomicBool,
 }
 
 #[cfg(feature = "linkme")]
@@ -185,6 +321,7 @@ impl CalSegDescriptor {
             default_page,
             size,
             index: AtomicU16::new(XCP_UNDEFINED_CALSEG),
+            initialized: AtomicBool::new(false),
         }
     }
 }
@@ -215,12 +352,15 @@ fn ensure_calsegs_created() {
         let mut descriptors: Vec<&'static CalSegDescriptor> = CAL_SEG_REGISTRY.iter().collect();
         descriptors.sort_by(|a, b| a.name.cmp(b.name));
         for d in descriptors {
-            let c_name = std::ffi::CString::new(d.name).expect("calibration segment name must not contain NUL");
+            let c_name = super::c_string(d.name);
             // @@@@ UNSAFE - C library call; default_page is a 'static page of d.size bytes
             let index = unsafe { xcplib::XcpCreateCalSeg(c_name.as_ptr(), d.default_page, d.size) };
-            assert!(index != XCP_UNDEFINED_CALSEG, "XcpCreateCalSeg failed for calibration segment '{}'", d.name);
+            if index == XCP_UNDEFINED_CALSEG {
+                super::Xcp::get().report_error(XcpClientError::CalSeg(format!("XcpCreateCalSeg failed for calibration segment '{}'", d.name)));
+                continue;
+            }
             d.index.store(index, Ordering::Release);
-            debug!("Registered calibration segment '{}' with index {}", d.name, index);
+            xcp_debug!(Cal, "Registered calibration segment '{}' with index {}", d.name, index);
         }
     });
 }
@@ -298,14 +438,14 @@ where
     /// Requires the calibration page type to implement serde::Serialize + serde::de::DeserializeOwned
     pub fn load<P: AsRef<std::path::Path>>(&self, filename: P) -> Result<(), std::io::Error> {
         let path = filename.as_ref();
-        info!("Load {} from file {} ", self.get_name(), path.display());
+        xcp_info!(Cal, "Load {} from file {} ", self.get_name(), path.display());
         if let Ok(file) = std::fs::File::open(path) {
             let reader = std::io::BufReader::new(file);
             let page = serde_json::from_reader::<_, T>(reader)?;
             *self.write_lock() = page;
             Ok(())
         } else {
-            warn!("File not found: {}", path.display());
+            xcp_warn!(Cal, "File not found: {}", path.display());
             Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("File not found: {}", path.display())))
         }
     }
@@ -314,7 +454,7 @@ where
     /// Requires the calibration page type to implement serde::Serialize + serde::de::DeserializeOwned
     pub fn save<P: AsRef<std::path::Path>>(&self, filename: P) -> Result<(), std::io::Error> {
         let path = filename.as_ref();
-        info!("Save {} to file {}", self.get_name(), path.display());
+        xcp_info!(Cal, "Save {} to file {}", self.get_name(), path.display());
         let file = std::fs::File::create(path)?;
         let mut writer = std::io::BufWriter::new(file);
         let page = self.read_lock();
@@ -324,12 +464,80 @@ where
     }
 }
 
+//----------------------------------------------------------------------------------------------
+// Freeze/load with the calibration storage of Xcp
+
+impl<T> CalSeg<T>
+where
+    T: CalPageTrait,
+{
+    /// Store the working page in the calibration storage set with Xcp::set_cal_storage
+    pub fn freeze(&self) -> Result<(), XcpClientError> {
+        self.freeze_from(CalChangeSource::Local)
+    }
+
+    /// Store the working page in the calibration storage, with the origin of the change for storages with change history
+    pub fn freeze_from(&self, source: CalChangeSource) -> Result<(), XcpClientError> {
+        if self.is_detached() {
+            return Err(XcpClientError::CalSeg("Detached calibration segment not frozen".to_string()));
+        }
+        let storage = super::Xcp::get()
+            .get_cal_storage()
+            .ok_or_else(|| XcpClientError::CalSeg(format!("{} not frozen, no calibration storage", self.get_name())))?;
+        let data = match storage.format() {
+            CalPageFormat::Binary => {
+                let page = self.read_lock();
+                // @@@@ UNSAFE - T is Copy, the page is read as its memory image of size_of::<T>() bytes
+                unsafe { std::slice::from_raw_parts(&*page as *const T as *const u8, std::mem::size_of::<T>()).to_vec() }
+            }
+            CalPageFormat::Json => serde_json::to_vec(&*self.read_lock()).map_err(|e| XcpClientError::CalSeg(format!("{}: {}", self.get_name(), e)))?,
+        };
+        xcp_info!(Cal, "Freeze {}, {} bytes", self.get_name(), data.len());
+        storage.store_from(self.get_name(), &data, source)
+    }
+
+    /// Initialize the working page from the calibration storage set with Xcp::set_cal_storage
+    /// Returns false, if there is no calibration storage or no page stored for this segment
+    pub fn load_frozen(&self) -> Result<bool, XcpClientError> {
+        if self.is_detached() {
+            return Ok(false);
+        }
+        let Some(storage) = super::Xcp::get().get_cal_storage() else {
+            return Ok(false);
+        };
+        let Some(data) = storage.load(self.get_name())? else {
+            xcp_debug!(Cal, "No frozen page for {}", self.get_name());
+            return Ok(false);
+        };
+        let page: T = match storage.format() {
+            CalPageFormat::Binary => {
+                if data.len() != std::mem::size_of::<T>() {
+                    return Err(XcpClientError::CalSeg(format!(
+                        "{}: frozen page size {} does not match {}",
+                        self.get_name(),
+                        data.len(),
+                        std::mem::size_of::<T>()
+                    )));
+                }
+                // @@@@ UNSAFE - the memory image was stored by freeze for the same page type, the size is checked
+                unsafe { std::ptr::read_unaligned(data.as_ptr() as *const T) }
+            }
+            CalPageFormat::Json => serde_json::from_slice(&data).map_err(|e| XcpClientError::CalSeg(format!("{}: {}", self.get_name(), e)))?,
+        };
+        xcp_info!(Cal, "Load frozen page of {}", self.get_name());
+        *self.write_lock() = page;
+        Ok(true)
+    }
+}
+
 //----------------------------------------------------------------------------------------------
 // Read lock guard for CalSeg
 
 pub struct ReadLockGuard<'a, T: CalPageTrait> {
     page: &'a T,
     index: xcplib::tXcpCalSegIndex,
+    #[cfg(feature = "lock_profiling")]
+    acquired: std::time::Instant,
 }
 
 impl<T> CalSeg<T>
@@ -338,21 +546,42 @@ where
 {
     /// Read lock guard that provides consistent read only access to a calibration page
     /// Consistent read access to the calibration segment while the lock guard is held
+    /// A detached calibration segment reads the default page
     pub fn read_lock(&self) -> ReadLockGuard<'_, T> {
+        #[cfg(feature = "lock_profiling")]
+        let start = std::time::Instant::now();
+        if self.is_detached() {
+            return ReadLockGuard {
+                page: self.default_page,
+                index: self.index,
+                #[cfg(feature = "lock_profiling")]
+                acquired: start,
+            };
+        }
         // Lock the calibration segment in the xcplib C library
         unsafe {
             let ptr: *const T = xcplib::XcpLockCalSeg(self.index) as *const T;
-            ReadLockGuard { page: &*ptr, index: self.index }
+            ReadLockGuard {
+                page: &*ptr,
+                index: self.index,
+                #[cfg(feature = "lock_profiling")]
+                acquired: super::cal_profile::lock_acquired(self.index, false, start),
+            }
         }
     }
 }
 
 impl<T: CalPageTrait> Drop for ReadLockGuard<'_, T> {
     fn drop(&mut self) {
+        if self.index == XCP_UNDEFINED_CALSEG {
+            return;
+        }
         // Unlock the calibration segment in the xcplib C library
         unsafe {
             xcplib::XcpUnlockCalSeg(self.index);
         }
+        #[cfg(feature = "lock_profiling")]
+        super::cal_profile::lock_released(self.index, self.acquired);
     }
 }
 
@@ -371,8 +600,16 @@ impl<T: CalPageTrait> Deref for ReadLockGuard<'_, T> {
 /// Makes the changes visible in this CalSeg after the guard is dropped, all other clones of the CalSeg will see the changes on their next sync
 /// This should be used for testing only, mutable parameters are not supported yet
 pub struct WriteLockGuard<'a, T: CalPageTrait> {
-    page: &'a mut T,
+    page: WritePage<'a, T>,
     index: xcplib::tXcpCalSegIndex,
+    #[cfg(feature = "lock_profiling")]
+    acquired: std::time::Instant,
+}
+
+// The working page of a locked calibration segment or a scratch copy of the default page of a detached calibration segment
+enum WritePage<'a, T> {
+    Locked(&'a mut T),
+    Detached(Box<T>),
 }
 
 impl<T> CalSeg<T>
@@ -380,36 +617,79 @@ where
     T: CalPageTrait,
 {
     /// Consistent write access to the calibration segments working page while the lock guard is held
+    /// The writes to a detached calibration segment are discarded when the lock guard is dropped
     pub fn write_lock(&self) -> WriteLockGuard<'_, T> {
+        #[cfg(feature = "lock_profiling")]
+        let start = std::time::Instant::now();
+        if self.is_detached() {
+            return WriteLockGuard {
+                page: WritePage::Detached(Box::new(*self.default_page)),
+                index: self.index,
+                #[cfg(feature = "lock_profiling")]
+                acquired: start,
+            };
+        }
         unsafe {
             let ptr: *mut T = xcplib::XcpLockCalSeg(self.index) as *mut T;
             WriteLockGuard {
-                page: &mut *ptr,
+                page: WritePage::Locked(&mut *ptr),
                 index: self.index,
+                #[cfg(feature = "lock_profiling")]
+                acquired: super::cal_profile::lock_acquired(self.index, true, start),
             }
         }
     }
+
+    /// Get the lock statistics of this calibration segment (all clones), see CalLockStats
+    #[cfg(feature = "lock_profiling")]
+    pub fn get_lock_stats(&self) -> super::CalLockStats {
+        super::cal_profile::get_lock_stats(self.index)
+    }
+
+    /// Reset the lock statistics of this calibration segment
+    #[cfg(feature = "lock_profiling")]
+    pub fn reset_lock_stats(&self) {
+        super::cal_profile::reset_lock_stats(self.index);
+    }
+
+    /// Register the lock statistics of this calibration segment as measurements "<name>.lock.<counter>" of the event "<name>.lock",
+    /// which is triggered on each unlock, the counters are also readable by polling
+    #[cfg(feature = "lock_profiling")]
+    pub fn register_lock_metrics(&self) -> Result<(), XcpClientError> {
+        super::cal_profile::register_lock_metrics(self.index, self.get_name())
+    }
 }
 
 impl<T: CalPageTrait> Deref for WriteLockGuard<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        self.page
+        match &self.page {
+            WritePage::Locked(page) => page,
+            WritePage::Detached(page) => page,
+        }
     }
 }
 
 impl<T: CalPageTrait> DerefMut for WriteLockGuard<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.page
+        match &mut self.page {
+            WritePage::Locked(page) => page,
+            WritePage::Detached(page) => page,
+        }
     }
 }
 
 impl<T: CalPageTrait> Drop for WriteLockGuard<'_, T> {
     fn drop(&mut self) {
+        if let WritePage::Detached(_) = self.page {
+            return;
+        }
         unsafe {
             xcplib::XcpUnlockCalSeg(self.index);
         }
+        #[cfg(feature = "lock_profiling")]
+        super::cal_profile::lock_released(self.index, self.acquired);
     }
 }
 
@@ -439,9 +719,8 @@ where
     /// * `default_page` - Default calibration page
     /// # Returns
     /// A CalCell instance
-    /// # Panics
-    /// If the instance name is not unique
-    /// If the maximum number of calibration segments is reached, CANape supports a maximum of 255 calibration segments
+    /// If the instance name is not unique or the maximum number of calibration segments is reached,
+    /// the error is reported on the error channel and the CalCell holds a detached CalSeg, see CalSeg::new
     ///
     pub fn new(instance_name: &'static str, default_page: &'static T) -> CalCell<T> {
         CalCell {
@@ -472,6 +751,9 @@ mod cal_tests {
 
     use std::thread;
 
+    #[allow(unused_imports)]
+    use log::{debug, error, info, trace, warn};
+
     use super::*;
     use crate::xcp::*;
     use registry::McRegisterType;
@@ -809,6 +1091,37 @@ mod cal_tests {
     //     let _ = std::fs::remove_file("test2.json");
     // }
 
+    //-----------------------------------------------------------------------------
+    // Test lock profiling
+    #[cfg(feature = "lock_profiling")]
+    #[test]
+    fn test_lock_profiling() {
+        const CAL_PAGE_TEST1: CalPageTest1 = CalPageTest1 {
+            byte1: 0,
+            byte2: 0,
+            byte3: 0,
+            byte4: 0,
+        };
+        xcp_test::test_setup();
+        let cal_seg = CalSeg::new("test_lock_profiling", &CAL_PAGE_TEST1);
+        cal_seg.register_lock_metrics().unwrap();
+        cal_seg.reset_lock_stats();
+
+        // Nested read lock while a guard is held counts as contention
+        let p1 = cal_seg.read_lock();
+        let p2 = cal_seg.read_lock();
+        assert_eq!(p1.byte1, p2.byte1);
+        drop(p2);
+        drop(p1);
+        cal_seg.write_lock().byte1 = 1;
+
+        let stats = cal_seg.get_lock_stats();
+        assert_eq!((stats.read_locks, stats.write_locks, stats.contentions), (2, 1, 1));
+        assert!(stats.hold_ns_max <= stats.hold_ns_total);
+        cal_seg.reset_lock_stats();
+        assert_eq!(cal_seg.get_lock_stats(), CalLockStats::default());
+    }
+
     //-----------------------------------------------------------------------------
     // Test cal page write and CalCell
     #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, McRegisterType)]
@@ -870,4 +1183,47 @@ mod cal_tests {
         assert!(value.read_lock().test5);
         assert_eq!(value.read_lock().test6, 6.0);
     }
+    //-----------------------------------------------------------------------------
+    // Test working page initialization policy
+    #[test]
+    fn test_calpage_init() {
+        let page = zero_page(&StaticCalPage { test5: true, ..STATIC_CAL_PAGE }).unwrap();
+        assert_eq!((page.test1, page.test2, page.test3, page.test4, page.test5, page.test6), (0, 0, 0, 0.0, false, 0.0));
+        assert_eq!((page.test7, page.test8), ([0, 0], [[0, 0], [0, 0]]));
+
+        xcp_test::test_setup();
+        let cal_seg = CalSeg::try_new_with_init("test_calpage_init_zero", &STATIC_CAL_PAGE, CalPageInit::Zero).unwrap();
+        assert_eq!(cal_seg.read_lock().test2, 0);
+        assert_eq!(cal_seg.default_page.test2, -2);
+        let cal_seg = CalSeg::try_new_with_init("test_calpage_init_default", &STATIC_CAL_PAGE, CalPageInit::Default).unwrap();
+        assert_eq!(cal_seg.read_lock().test2, -2);
+
+        // A page frozen in the calibration storage is loaded on creation
+        let dir = std::env::temp_dir().join("xcp_lite_test_calpage_init");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("test_calpage_init_storage.json"),
+            serde_json::to_vec(&StaticCalPage { test2: 7, ..STATIC_CAL_PAGE }).unwrap(),
+        )
+        .unwrap();
+        Xcp::get().set_cal_storage(CalStorageJsonDir::new(&dir));
+        let cal_seg = CalSeg::try_new_with_init("test_calpage_init_storage", &STATIC_CAL_PAGE, CalPageInit::Storage).unwrap();
+        assert_eq!(cal_seg.read_lock().test2, 7);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    //-----------------------------------------------------------------------------
+    // Test the detached calibration segment of a duplicate name
+    #[test]
+    fn test_calseg_detached() {
+        xcp_test::test_setup();
+        let cal_seg = CalSeg::new("test_calseg_detached", &STATIC_CAL_PAGE);
+        assert!(!cal_seg.is_detached());
+        let detached = CalSeg::new("test_calseg_detached", &STATIC_CAL_PAGE);
+        assert!(detached.is_detached());
+        assert_eq!(detached.get_name(), "");
+        detached.write_lock().test2 = 7;
+        assert_eq!(detached.read_lock().test2, -2);
+        assert!(detached.freeze().is_err());
+        assert_eq!(cal_seg.get_name(), "test_calseg_detached");
+    }
 }
@@ -0,0 +1,174 @@
+//-----------------------------------------------------------------------------
+// Module cal_profile
+// Lock profiling of calibration segments (feature lock_profiling)
+// Counts read and write locks and contention (a lock acquired while another guard of the same segment is held), measures lock acquire and hold times
+// The lock of xcplib (XcpLockCalSeg) is wait-free, acquiring a page does not wait for the XCP client tool or other threads,
+// the acquire time shows the cost of this path, the hold time how long the application keeps a page locked and delays the visibility of calibration changes
+
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
+use std::time::Instant;
+
+use super::{Xcp, XcpClientError, XcpEvent};
+use crate::registry::{self, McAddress, McDimType, McObjectType, McSupportData, McValueType};
+
+// Maximum number of profiled calibration segments, CANape supports a maximum of 255 calibration segments
+const MAX_CALSEG_COUNT: usize = 256;
+
+struct CalLockCounters {
+    read_locks: AtomicU64,
+    write_locks: AtomicU64,
+    contentions: AtomicU64,
+    active: AtomicU32, // Guards currently held
+    acquire_ns_max: AtomicU64,
+    hold_ns_total: AtomicU64,
+    hold_ns_max: AtomicU64,
+}
+
+impl CalLockCounters {
+    const fn new() -> CalLockCounters {
+        CalLockCounters {
+            read_locks: AtomicU64::new(0),
+            write_locks: AtomicU64::new(0),
+            contentions: AtomicU64::new(0),
+            active: AtomicU32::new(0),
+            acquire_ns_max: AtomicU64::new(0),
+            hold_ns_total: AtomicU64::new(0),
+            hold_ns_max: AtomicU64::new(0),
+        }
+    }
+}
+
+static CAL_LOCK_COUNTERS: [CalLockCounters; MAX_CALSEG_COUNT] = [const { CalLockCounters::new() }; MAX_CALSEG_COUNT];
+
+// Event id of the lock metrics of a calibration segment, triggered on each unlock, see register_lock_metrics
+static CAL_LOCK_EVENTS: [AtomicU16; MAX_CALSEG_COUNT] = [const { AtomicU16::new(XcpEvent::XCP_UNDEFINED_EVENT_ID) }; MAX_CALSEG_COUNT];
+
+/// Lock statistics of a calibration segment, see CalSeg::get_lock_stats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CalLockStats {
+    pub read_locks: u64,     // Number of read locks
+    pub write_locks: u64,    // Number of write locks
+    pub contentions: u64,    // Locks acquired while another guard of the segment was held
+    pub acquire_ns_max: u64, // Maximum time to acquire the lock in ns
+    pub hold_ns_total: u64,  // Total time the lock was held in ns
+    pub hold_ns_max: u64,    // Maximum time the lock was held in ns
+}
+
+impl CalLockStats {
+    /// Average lock hold time in ns
+    pub fn hold_ns_avg(&self) -> u64 {
+        self.hold_ns_total.checked_div(self.read_locks + self.write_locks).unwrap_or(0)
+    }
+}
+
+impl std::fmt::Display for CalLockStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "read_locks={} write_locks={} contentions={} acquire_max={}ns hold_avg={}ns hold_max={}ns",
+            self.read_locks,
+            self.write_locks,
+            self.contentions,
+            self.acquire_ns_max,
+            self.hold_ns_avg(),
+            self.hold_ns_max
+        )
+    }
+}
+
+// Elapsed time since start in ns
+fn elapsed_ns(start: Instant) -> u64 {
+    u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX)
+}
+
+// Lock of calibration segment index acquired, start is the time before XcpLockCalSeg was called
+// Returns the time the lock was acquired, for lock_released
+pub(crate) fn lock_acquired(index: u16, write: bool, start: Instant) -> Instant {
+    let acquired = Instant::now();
+    if let Some(c) = CAL_LOCK_COUNTERS.get(index as usize) {
+        if write {
+            c.write_locks.fetch_add(1, Ordering::Relaxed);
+        } else {
+            c.read_locks.fetch_add(1, Ordering::Relaxed);
+        }
+        if c.active.fetch_add(1, Ordering::Relaxed) > 0 {
+            c.contentions.fetch_add(1, Ordering::Relaxed);
+        }
+        c.acquire_ns_max.fetch_max(elapsed_ns(start), Ordering::Relaxed);
+    }
+    acquired
+}
+
+// Lock of calibration segment index released, acquired is the time returned by lock_acquired
+pub(crate) fn lock_released(index: u16, acquired: Instant) {
+    if let Some(c) = CAL_LOCK_COUNTERS.get(index as usize) {
+        let hold_ns = elapsed_ns(acquired);
+        c.active.fetch_sub(1, Ordering::Relaxed);
+        c.hold_ns_total.fetch_add(hold_ns, Ordering::Relaxed);
+        c.hold_ns_max.fetch_max(hold_ns, Ordering::Relaxed);
+        let event_id = CAL_LOCK_EVENTS[index as usize].load(Ordering::Relaxed);
+        if event_id != XcpEvent::XCP_UNDEFINED_EVENT_ID {
+            // @@@@ UNSAFE - the lock metrics measurements are registered relative to the static counters of this segment
+            unsafe { XcpEvent::new(event_id, 0).trigger_ext(std::ptr::from_ref(c).cast::<u8>()) }
+        }
+    }
+}
+
+// Register the lock counters of calibration segment index as measurements "<name>.lock.<counter>" of the event "<name>.lock"
+// The event is triggered on each unlock of the segment
+pub(crate) fn register_lock_metrics(index: u16, name: &str) -> Result<(), XcpClientError> {
+    let Some(event_id) = CAL_LOCK_EVENTS.get(index as usize) else {
+        return Err(XcpClientError::CalSeg(format!("{} index {} exceeds the lock profiling range", name, index)));
+    };
+    let event = Xcp::get().try_create_event_ext(Box::leak(format!("{}.lock", name).into_boxed_str()), false)?;
+    let fields = [
+        ("read_locks", std::mem::offset_of!(CalLockCounters, read_locks), ""),
+        ("write_locks", std::mem::offset_of!(CalLockCounters, write_locks), ""),
+        ("contentions", std::mem::offset_of!(CalLockCounters, contentions), ""),
+        ("acquire_ns_max", std::mem::offset_of!(CalLockCounters, acquire_ns_max), "ns"),
+        ("hold_ns_total", std::mem::offset_of!(CalLockCounters, hold_ns_total), "ns"),
+        ("hold_ns_max", std::mem::offset_of!(CalLockCounters, hold_ns_max), "ns"),
+    ];
+    {
+        let mut reg = registry::get_lock();
+        let Some(reg) = reg.as_mut() else {
+            return Err(XcpClientError::Registry(format!("{} lock metrics not registered, registry is finalized", name)));
+        };
+        for (field, offset, unit) in fields {
+            reg.instance_list
+                .add_instance(
+                    format!("{}.lock.{}", name, field),
+                    McDimType::new(McValueType::Ulonglong, 1, 1),
+                    McSupportData::new(McObjectType::Measurement).set_unit(unit),
+                    McAddress::new_event_dyn(0, event.get_id(), i32::try_from(offset).unwrap_or_default()),
+                )
+                .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+        }
+    }
+    event_id.store(event.get_id(), Ordering::Relaxed);
+    Ok(())
+}
+
+// Get the lock statistics of calibration segment index
+pub(crate) fn get_lock_stats(index: u16) -> CalLockStats {
+    CAL_LOCK_COUNTERS.get(index as usize).map_or_else(CalLockStats::default, |c| CalLockStats {
+        read_locks: c.read_locks.load(Ordering::Relaxed),
+        write_locks: c.write_locks.load(Ordering::Relaxed),
+        contentions: c.contentions.load(Ordering::Relaxed),
+        acquire_ns_max: c.acquire_ns_max.load(Ordering::Relaxed),
+        hold_ns_total: c.hold_ns_total.load(Ordering::Relaxed),
+        hold_ns_max: c.hold_ns_max.load(Ordering::Relaxed),
+    })
+}
+
+// Reset the lock statistics of calibration segment index, guards currently held are still counted as active
+pub(crate) fn reset_lock_stats(index: u16) {
+    if let Some(c) = CAL_LOCK_COUNTERS.get(index as usize) {
+        c.read_locks.store(0, Ordering::Relaxed);
+        c.write_locks.store(0, Ordering::Relaxed);
+        c.contentions.store(0, Ordering::Relaxed);
+        c.acquire_ns_max.store(0, Ordering::Relaxed);
+        c.hold_ns_total.store(0, Ordering::Relaxed);
+        c.hold_ns_max.store(0, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,385 @@
+//-----------------------------------------------------------------------------
+// Module cal_serde
+// Registration of calibration parameters of types without McRegisterType, with a serde serializer
+// Serialize implementations generated by serde derive pass a reference to each field of a struct, tuple or array to the serializer,
+// the offset of a field is the address of this reference relative to the address of the default page
+// Basic type fields are registered as characteristics "<calseg>.<field>", nested structs with "." and tuple elements with "._<index>",
+// arrays of basic types as one characteristic with dimension
+// Custom Serialize implementations which serialize temporary values are detected by offsets outside the page and rejected,
+// enums, strings, Option and heap types (Vec, HashMap) are not supported
+
+use serde::Serialize;
+use serde::ser::{self, SerializeStruct, SerializeTuple, SerializeTupleStruct};
+
+use super::cal::{CalPageTrait, CalSeg};
+use super::{Xcp, XcpClientError};
+use crate::registry::{self, McAddress, McDimType, McObjectType, McSupportData, McValueType};
+
+impl ser::Error for XcpClientError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        XcpClientError::CalSeg(msg.to_string())
+    }
+}
+
+// A basic type field or array of basic types found by the serializer
+#[derive(Debug, Clone, PartialEq)]
+struct CalSerdeField {
+    name: String,
+    value_type: McValueType,
+    x_dim: u16,
+    offset: usize,
+}
+
+// Fields collected from a value at base with size bytes
+struct CalSerdeCollector {
+    base: usize,
+    size: usize,
+    fields: Vec<CalSerdeField>,
+}
+
+impl CalSerdeCollector {
+    // Collect the fields of value, a reference into the page
+    fn collect<T: Serialize + ?Sized>(&mut self, name: String, value: &T) -> Result<(), XcpClientError> {
+        let addr = std::ptr::from_ref(value).cast::<u8>() as usize;
+        if addr < self.base || addr >= self.base + self.size {
+            return Err(XcpClientError::CalSeg(format!("{} is not a field of the page, Serialize must reference the fields", name)));
+        }
+        let offset = addr - self.base;
+        value.serialize(CalSerdeSerializer { collector: self, name, offset })
+    }
+
+    fn push(&mut self, name: String, value_type: McValueType, offset: usize) -> Result<(), XcpClientError> {
+        if offset + value_type.get_size() > self.size {
+            return Err(XcpClientError::CalSeg(format!("{} exceeds the page", name)));
+        }
+        self.fields.push(CalSerdeField {
+            name,
+            value_type,
+            x_dim: 1,
+            offset,
+        });
+        Ok(())
+    }
+
+    // Merge the elements of a tuple or array collected from index start into one array field, if they are contiguous scalars of the same type
+    fn merge_array(&mut self, name: &str, start: usize) {
+        let elements = &self.fields[start..];
+        let Some(first) = elements.first() else {
+            return;
+        };
+        let size = first.value_type.get_size();
+        let contiguous = elements
+            .iter()
+            .enumerate()
+            .all(|(i, e)| e.x_dim == 1 && e.value_type == first.value_type && e.offset == first.offset + i * size);
+        if let (true, Ok(x_dim)) = (contiguous && elements.len() > 1, u16::try_from(elements.len())) {
+            let field = CalSerdeField {
+                name: name.to_string(),
+                value_type: first.value_type,
+                x_dim,
+                offset: first.offset,
+            };
+            self.fields.truncate(start);
+            self.fields.push(field);
+        }
+    }
+}
+
+// Serializer for one value at offset
+struct CalSerdeSerializer<'a> {
+    collector: &'a mut CalSerdeCollector,
+    name: String,
+    offset: usize,
+}
+
+impl CalSerdeSerializer<'_> {
+    fn basic(self, value_type: McValueType) -> Result<(), XcpClientError> {
+        self.collector.push(self.name, value_type, self.offset)
+    }
+
+    fn unsupported(self, what: &str) -> Result<(), XcpClientError> {
+        Err(XcpClientError::CalSeg(format!("{}: {} is not supported", self.name, what)))
+    }
+}
+
+// Serializer state of a struct, tuple or array
+struct CalSerdeCompound<'a> {
+    collector: &'a mut CalSerdeCollector,
+    name: String,
+    start: usize, // Index of the first field of this compound in the collector
+    index: usize, // Next tuple element
+}
+
+impl CalSerdeCompound<'_> {
+    fn field_name(&self, key: &str) -> String {
+        if self.name.is_empty() { key.to_string() } else { format!("{}.{}", self.name, key) }
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XcpClientError> {
+        let name = self.field_name(&format!("_{}", self.index));
+        self.index += 1;
+        self.collector.collect(name, value)
+    }
+}
+
+impl SerializeStruct for CalSerdeCompound<'_> {
+    type Ok = ();
+    type Error = XcpClientError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), XcpClientError> {
+        let name = self.field_name(key);
+        self.collector.collect(name, value)
+    }
+    fn end(self) -> Result<(), XcpClientError> {
+        Ok(())
+    }
+}
+
+impl SerializeTuple for CalSerdeCompound<'_> {
+    type Ok = ();
+    type Error = XcpClientError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XcpClientError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), XcpClientError> {
+        self.collector.merge_array(&self.name, self.start);
+        Ok(())
+    }
+}
+
+impl SerializeTupleStruct for CalSerdeCompound<'_> {
+    type Ok = ();
+    type Error = XcpClientError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XcpClientError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), XcpClientError> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for CalSerdeSerializer<'a> {
+    type Ok = ();
+    type Error = XcpClientError;
+    type SerializeSeq = ser::Impossible<(), XcpClientError>;
+    type SerializeTuple = CalSerdeCompound<'a>;
+    type SerializeTupleStruct = CalSerdeCompound<'a>;
+    type SerializeTupleVariant = ser::Impossible<(), XcpClientError>;
+    type SerializeMap = ser::Impossible<(), XcpClientError>;
+    type SerializeStruct = CalSerdeCompound<'a>;
+    type SerializeStructVariant = ser::Impossible<(), XcpClientError>;
+
+    fn serialize_bool(self, _v: bool) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Bool)
+    }
+    fn serialize_i8(self, _v: i8) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Sbyte)
+    }
+    fn serialize_i16(self, _v: i16) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Sword)
+    }
+    fn serialize_i32(self, _v: i32) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Slong)
+    }
+    fn serialize_i64(self, _v: i64) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Slonglong)
+    }
+    fn serialize_u8(self, _v: u8) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Ubyte)
+    }
+    fn serialize_u16(self, _v: u16) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Uword)
+    }
+    fn serialize_u32(self, _v: u32) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Ulong)
+    }
+    fn serialize_u64(self, _v: u64) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Ulonglong)
+    }
+    fn serialize_f32(self, _v: f32) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Float32Ieee)
+    }
+    fn serialize_f64(self, _v: f64) -> Result<(), XcpClientError> {
+        self.basic(McValueType::Float64Ieee)
+    }
+    fn serialize_char(self, _v: char) -> Result<(), XcpClientError> {
+        self.unsupported("char")
+    }
+    fn serialize_str(self, _v: &str) -> Result<(), XcpClientError> {
+        self.unsupported("string")
+    }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), XcpClientError> {
+        self.unsupported("byte slice")
+    }
+    fn serialize_none(self) -> Result<(), XcpClientError> {
+        self.unsupported("Option")
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<(), XcpClientError> {
+        self.unsupported("Option")
+    }
+    fn serialize_unit(self) -> Result<(), XcpClientError> {
+        Ok(())
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), XcpClientError> {
+        Ok(())
+    }
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, _variant: &'static str) -> Result<(), XcpClientError> {
+        self.unsupported("enum")
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<(), XcpClientError> {
+        self.collector.collect(self.name, value)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T) -> Result<(), XcpClientError> {
+        self.unsupported("enum")
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, XcpClientError> {
+        Err(XcpClientError::CalSeg(format!("{}: sequence is not supported", self.name)))
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, XcpClientError> {
+        let start = self.collector.fields.len();
+        Ok(CalSerdeCompound {
+            collector: self.collector,
+            name: self.name,
+            start,
+            index: 0,
+        })
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct, XcpClientError> {
+        self.serialize_tuple(len)
+    }
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant, XcpClientError> {
+        Err(XcpClientError::CalSeg(format!("{}: enum is not supported", self.name)))
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, XcpClientError> {
+        Err(XcpClientError::CalSeg(format!("{}: map is not supported", self.name)))
+    }
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct, XcpClientError> {
+        self.serialize_tuple(len)
+    }
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, XcpClientError> {
+        Err(XcpClientError::CalSeg(format!("{}: enum is not supported", self.name)))
+    }
+}
+
+// Collect the basic type fields of a page
+fn collect_fields<T: Serialize>(page: &T) -> Result<Vec<CalSerdeField>, XcpClientError> {
+    let mut collector = CalSerdeCollector {
+        base: std::ptr::from_ref(page).cast::<u8>() as usize,
+        size: std::mem::size_of::<T>(),
+        fields: Vec::new(),
+    };
+    collector.collect(String::new(), page)?;
+    if collector.fields.is_empty() {
+        return Err(XcpClientError::CalSeg("page has no calibration parameters".to_string()));
+    }
+    Ok(collector.fields)
+}
+
+impl<T: CalPageTrait> CalSeg<T> {
+    /// Create a calibration segment for a type without McRegisterType and register its parameters from its serde Serialize implementation
+    /// Fields are registered as "<instance_name>.<field>", nested structs and tuple elements with "." and "._<index>", arrays of basic types with dimension
+    /// Returns an error for enums, strings, Option and heap types and for Serialize implementations which do not reference the fields of the page
+    pub fn from_serde(instance_name: &'static str, default_page: &'static T) -> Result<CalSeg<T>, XcpClientError> {
+        let fields = collect_fields(default_page).map_err(|e| XcpClientError::CalSeg(format!("{}: {}", instance_name, e)))?;
+        // Address offsets of the fields, checked before the calibration segment is created
+        let offsets = fields
+            .iter()
+            .map(|field| i32::try_from(field.offset).map_err(|_| XcpClientError::CalSeg(format!("{}.{}: offset {} out of range", instance_name, field.name, field.offset))))
+            .collect::<Result<Vec<i32>, XcpClientError>>()?;
+        Xcp::get().check_memory(fields.len() * std::mem::size_of::<registry::McInstance>())?;
+        let calseg = CalSeg::try_new(instance_name, default_page)?;
+        let mut reg = registry::get_lock();
+        let Some(reg) = reg.as_mut() else {
+            return Err(XcpClientError::Registry(format!("{} not registered, registry is finalized", instance_name)));
+        };
+        for (field, offset) in fields.into_iter().zip(offsets) {
+            reg.instance_list
+                .add_instance(
+                    format!("{}.{}", instance_name, field.name),
+                    McDimType::new(field.value_type, field.x_dim, 1),
+                    McSupportData::new(McObjectType::Characteristic),
+                    McAddress::new_calseg_rel(instance_name, offset),
+                )
+                .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+        }
+        Ok(calseg)
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod cal_serde_tests {
+
+    use super::*;
+    use crate::xcp::xcp_test;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+    struct Limits {
+        min: f32,
+        max: f32,
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+    struct Gain(f64);
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+    struct ForeignConfig {
+        enabled: bool,
+        gain: Gain,
+        limits: Limits,
+        curve: [u16; 4],
+        pair: (u8, i32),
+    }
+
+    static FOREIGN_CONFIG: ForeignConfig = ForeignConfig {
+        enabled: true,
+        gain: Gain(1.5),
+        limits: Limits { min: -1.0, max: 1.0 },
+        curve: [0, 10, 20, 30],
+        pair: (1, -1),
+    };
+
+    #[test]
+    fn test_cal_serde_fields() {
+        let fields = collect_fields(&FOREIGN_CONFIG).unwrap();
+        let find = |name: &str| fields.iter().find(|f| f.name == name).unwrap_or_else(|| panic!("{} not found", name));
+        assert_eq!(fields.len(), 7);
+        assert_eq!(find("enabled").offset, std::mem::offset_of!(ForeignConfig, enabled));
+        assert_eq!(find("gain").value_type, McValueType::Float64Ieee);
+        assert_eq!(find("limits.max").offset, std::mem::offset_of!(ForeignConfig, limits) + std::mem::offset_of!(Limits, max));
+        let curve = find("curve");
+        assert_eq!(
+            (curve.value_type, curve.x_dim, curve.offset),
+            (McValueType::Uword, 4, std::mem::offset_of!(ForeignConfig, curve))
+        );
+        assert_eq!(find("pair._1").value_type, McValueType::Slong);
+
+        // Serialize implementations with temporary values and unsupported types
+        #[derive(serde::Serialize)]
+        struct Temporary {
+            #[serde(serialize_with = "serialize_temporary")]
+            value: u32,
+        }
+        fn serialize_temporary<S: ser::Serializer>(value: &u32, s: S) -> Result<S::Ok, S::Error> {
+            (value + 1).serialize(s)
+        }
+        assert!(collect_fields(&Temporary { value: 1 }).is_err());
+        assert!(collect_fields(&Some(1u8)).is_err());
+    }
+
+    #[test]
+    fn test_cal_serde_calseg() {
+        let xcp = xcp_test::test_setup();
+        let calseg = CalSeg::from_serde("test_cal_serde", &FOREIGN_CONFIG).unwrap();
+        assert!(CalSeg::from_serde("test_cal_serde", &FOREIGN_CONFIG).is_err());
+        assert_eq!(calseg.read_lock().curve[3], 30);
+        xcp.finalize_registry().unwrap();
+        let reg = registry::get();
+        assert!(
+            reg.instance_list
+                .find_instance_regex("test_cal_serde\\.limits\\.min", McObjectType::Characteristic, None)
+                .is_some()
+        );
+    }
+}
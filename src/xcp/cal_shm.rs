@@ -0,0 +1,298 @@
+//-----------------------------------------------------------------------------
+// Module cal_shm
+// Calibration segment shared memory
+// Publishes the working page of a calibration segment to a named shared memory region, so other processes of the application
+// can read one consistent calibration page
+// The XCP server process owns the calibration segment and is the only writer, other processes map the region read only
+// Consistency is guaranteed by a sequence counter (version), odd while the page is written, and a heartbeat counter
+// The shared page is a polled copy, not the working page itself: calibration changes become visible to other processes
+// only when the XCP server process calls CalShmWriter::publish, which should be done cyclically
+// The page is copied as plain bytes, so the page type must be MeasureSafe without padding bytes
+
+//-----------------------------------------------------------------------------
+
+#[allow(unused_imports)]
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
+use memmap2::{Mmap, MmapMut};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering, fence};
+
+use super::cal::{CalPageTrait, CalSeg};
+use crate::registry::MeasureSafe;
+
+//-----------------------------------------------------------------------------
+// Shared memory layout
+
+const CAL_SHM_MAGIC: u32 = 0x4D485343; // "CSHM"
+
+// Header at the start of the shared memory region, followed by the calibration page
+#[repr(C)]
+struct CalShmHeader {
+    magic: u32,
+    size: u32,          // Size of the calibration page in bytes
+    version: AtomicU32, // Sequence counter, odd while the writer modifies the page
+    _reserved: u32,
+    heartbeat: AtomicU64, // Incremented on each publish
+}
+
+const CAL_SHM_HEADER_SIZE: usize = std::mem::size_of::<CalShmHeader>();
+
+/// Path of the shared memory file for a calibration segment name
+/// /dev/shm on Linux, the temp directory on other platforms
+pub fn cal_shm_path(name: &str) -> std::path::PathBuf {
+    let dir = if cfg!(target_os = "linux") {
+        std::path::PathBuf::from("/dev/shm")
+    } else {
+        std::env::temp_dir()
+    };
+    dir.join(format!("xcp_calseg_{}", name))
+}
+
+//-----------------------------------------------------------------------------
+// CalShmWriter
+
+/// Publishes a copy of the working page of a CalSeg to shared memory
+/// Owned by the XCP server process, the shared memory file is removed on drop
+/// The copy is updated only by publish, which must be called cyclically to make calibration changes visible
+pub struct CalShmWriter<T>
+where
+    T: CalPageTrait + MeasureSafe,
+{
+    calseg: CalSeg<T>,
+    mmap: MmapMut,
+    path: std::path::PathBuf,
+}
+
+impl<T> CalShmWriter<T>
+where
+    T: CalPageTrait + MeasureSafe,
+{
+    /// Create the shared memory region for a calibration segment and publish the current working page
+    /// An existing region is reused without truncation, readers which still map it from a previous writer stay valid
+    pub fn new(calseg: &CalSeg<T>) -> Result<CalShmWriter<T>, std::io::Error> {
+        const { assert!(T::NO_PADDING, "MeasureSafe: the calibration page type has padding bytes, add explicit padding fields") };
+        let size = std::mem::size_of::<T>();
+        let path = cal_shm_path(calseg.get_name());
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        if file.metadata()?.len() != (CAL_SHM_HEADER_SIZE + size) as u64 {
+            file.set_len((CAL_SHM_HEADER_SIZE + size) as u64)?;
+        }
+        // @@@@ UNSAFE - Memory mapped file, other processes must not write to it
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        let mut writer = CalShmWriter {
+            calseg: calseg.clone(),
+            mmap,
+            path,
+        };
+        {
+            let size = u32::try_from(size).map_err(|_| std::io::Error::other("calibration page too large"))?;
+            let header = writer.header_mut();
+            // The version of a region of the same layout continues, a reader in the middle of a copy must not see an equal version after a restart
+            if header.magic != CAL_SHM_MAGIC || header.size != size || header.version.load(Ordering::Relaxed) & 1 != 0 {
+                header.magic = CAL_SHM_MAGIC;
+                header.size = size;
+                header.version.store(0, Ordering::Relaxed);
+                header.heartbeat.store(0, Ordering::Relaxed);
+            }
+        }
+        writer.write_page();
+        xcp_info!(Cal, "CalSeg {} shared in {}", calseg.get_name(), writer.path.display());
+        Ok(writer)
+    }
+
+    fn header(&self) -> &CalShmHeader {
+        // @@@@ UNSAFE - The mapping is page aligned and at least CAL_SHM_HEADER_SIZE bytes
+        unsafe { &*(self.mmap.as_ptr() as *const CalShmHeader) }
+    }
+
+    fn header_mut(&mut self) -> &mut CalShmHeader {
+        // @@@@ UNSAFE - The mapping is page aligned and at least CAL_SHM_HEADER_SIZE bytes
+        unsafe { &mut *(self.mmap.as_mut_ptr() as *mut CalShmHeader) }
+    }
+
+    // Copy the working page into shared memory, with odd version while writing
+    fn write_page(&mut self) {
+        let size = std::mem::size_of::<T>();
+        let page = self.calseg.read_lock();
+        // @@@@ UNSAFE - T is MeasureSafe without padding, all bytes of the page are initialized
+        let bytes = unsafe { std::slice::from_raw_parts(&*page as *const T as *const u8, size) };
+        self.header().version.fetch_add(1, Ordering::AcqRel);
+        fence(Ordering::Release);
+        self.mmap[CAL_SHM_HEADER_SIZE..CAL_SHM_HEADER_SIZE + size].copy_from_slice(bytes);
+        fence(Ordering::Release);
+        self.header().version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Publish the working page if it has changed and increment the heartbeat
+    /// Should be called cyclically by the XCP server process, returns true if the page was updated
+    pub fn publish(&mut self) -> bool {
+        let size = std::mem::size_of::<T>();
+        let changed = {
+            let page = self.calseg.read_lock();
+            // @@@@ UNSAFE - T is MeasureSafe without padding, all bytes of the page are initialized
+            let bytes = unsafe { std::slice::from_raw_parts(&*page as *const T as *const u8, size) };
+            bytes != &self.mmap[CAL_SHM_HEADER_SIZE..CAL_SHM_HEADER_SIZE + size]
+        };
+        if changed {
+            self.write_page();
+            xcp_debug!(Cal, "CalSeg {} published, version {}", self.calseg.get_name(), self.get_version());
+        }
+        self.header().heartbeat.fetch_add(1, Ordering::Release);
+        changed
+    }
+
+    /// Number of page updates
+    pub fn get_version(&self) -> u32 {
+        self.header().version.load(Ordering::Acquire) / 2
+    }
+
+    /// Path of the shared memory file
+    pub fn get_path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+impl<T> Drop for CalShmWriter<T>
+where
+    T: CalPageTrait + MeasureSafe,
+{
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+//-----------------------------------------------------------------------------
+// CalShmReader
+
+/// Read only access to a calibration page published by another process
+pub struct CalShmReader<T>
+where
+    T: CalPageTrait + MeasureSafe,
+{
+    mmap: Mmap,
+    _marker: PhantomData<T>,
+}
+
+impl<T> CalShmReader<T>
+where
+    T: CalPageTrait + MeasureSafe,
+{
+    /// Open the shared memory region of a calibration segment by name
+    /// Fails, if the region does not exist or the size does not match the calibration page type
+    pub fn open(name: &str) -> Result<CalShmReader<T>, std::io::Error> {
+        let path = cal_shm_path(name);
+        let file = std::fs::File::open(&path)?;
+        // @@@@ UNSAFE - Memory mapped file, modified concurrently by the writer, access only via the version protocol
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() < CAL_SHM_HEADER_SIZE + std::mem::size_of::<T>() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} too small", path.display())));
+        }
+        let reader = CalShmReader { mmap, _marker: PhantomData };
+        let header = reader.header();
+        if header.magic != CAL_SHM_MAGIC || header.size as usize != std::mem::size_of::<T>() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} does not contain a calibration page of this type", path.display()),
+            ));
+        }
+        Ok(reader)
+    }
+
+    fn header(&self) -> &CalShmHeader {
+        // @@@@ UNSAFE - The mapping is page aligned and at least CAL_SHM_HEADER_SIZE bytes
+        unsafe { &*(self.mmap.as_ptr() as *const CalShmHeader) }
+    }
+
+    /// Read a consistent copy of the calibration page, as last published by the writer
+    pub fn read(&self) -> T {
+        let size = std::mem::size_of::<T>();
+        let mut buf = vec![0u8; size];
+        loop {
+            let v1 = self.header().version.load(Ordering::Acquire);
+            if v1 & 1 == 0 {
+                // @@@@ UNSAFE - Volatile copy of bytes modified by another process
+                unsafe {
+                    std::ptr::copy_nonoverlapping(self.mmap.as_ptr().add(CAL_SHM_HEADER_SIZE), buf.as_mut_ptr(), size);
+                }
+                fence(Ordering::Acquire);
+                if self.header().version.load(Ordering::Acquire) == v1 {
+                    break;
+                }
+            }
+            std::hint::spin_loop();
+        }
+        // @@@@ UNSAFE - The bytes are a consistent copy of a T written by the writer
+        unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const T) }
+    }
+
+    /// Number of page updates, changes when the calibration page has been modified
+    pub fn get_version(&self) -> u32 {
+        self.header().version.load(Ordering::Acquire) / 2
+    }
+
+    /// Heartbeat counter of the writer, changes as long as the XCP server process publishes
+    pub fn get_heartbeat(&self) -> u64 {
+        self.header().heartbeat.load(Ordering::Acquire)
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod cal_shm_tests {
+
+    use super::*;
+    use crate::xcp::*;
+    use registry::McRegisterType;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, McRegisterType)]
+    struct CalPageShm {
+        test_u8: u8,
+        _pad: [u8; 3],
+        test_u32: u32,
+        test_f64: f64,
+    }
+
+    const CAL_PAGE_SHM: CalPageShm = CalPageShm {
+        test_u8: 1,
+        _pad: [0; 3],
+        test_u32: 2,
+        test_f64: 3.0,
+    };
+
+    #[test]
+    fn test_calibration_segment_shm() {
+        let _xcp = xcp_test::test_setup();
+
+        let cal_seg = CalSeg::new("CalPageShm", &CAL_PAGE_SHM);
+        cal_seg.register();
+        let mut writer = CalShmWriter::new(&cal_seg).unwrap();
+
+        // Reader with a different page size is rejected
+        assert!(CalShmReader::<u8>::open("CalPageShm").is_err());
+
+        let reader = CalShmReader::<CalPageShm>::open("CalPageShm").unwrap();
+        assert_eq!(reader.read().test_u32, 2);
+        assert_eq!(reader.get_version(), 1);
+
+        // Unchanged page, only the heartbeat is incremented
+        assert!(!writer.publish());
+        assert_eq!(reader.get_heartbeat(), 1);
+        assert_eq!(reader.get_version(), 1);
+
+        // Changed page
+        cal_seg.write_lock().test_f64 = 4.0;
+        assert!(writer.publish());
+        assert_eq!(reader.get_version(), 2);
+        let page = reader.read();
+        assert_eq!(page.test_u8, 1);
+        assert_eq!(page.test_f64, 4.0);
+
+        // Shared memory file removed on drop
+        let path = writer.get_path().to_path_buf();
+        drop(writer);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,276 @@
+//-----------------------------------------------------------------------------
+// Module cal_sqlite
+// Calibration storage in a SQLite database
+// Pages are stored as individual parameter values (flattened JSON fields of the page, named like the A2L instances without segment prefix),
+// every stored change of a parameter is recorded with timestamp, source and old/new value, to answer "who changed this parameter when"
+
+#[allow(unused_imports)]
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use parking_lot::Mutex;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde_json::{Map, Value};
+
+use super::XcpClientError;
+use super::cal_storage::{CalChangeSource, CalPageFormat, CalStorage};
+
+//-----------------------------------------------------------------------------
+// Page flattening
+
+// Flatten a JSON page into parameter name and JSON value text, nested structs are separated by '.', arrays are one parameter
+fn flatten(prefix: &str, value: &Value, values: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let name = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&name, value, values);
+            }
+        }
+        _ => {
+            values.insert(prefix.to_string(), value.to_string());
+        }
+    }
+}
+
+// Rebuild the JSON page from flattened parameters
+fn unflatten(values: &[(String, String)]) -> Result<Value, XcpClientError> {
+    let mut root = Value::Object(Map::new());
+    for (parameter, value) in values {
+        let value: Value = serde_json::from_str(value).map_err(|e| XcpClientError::CalSeg(format!("{}: {}", parameter, e)))?;
+        if parameter.is_empty() {
+            root = value; // The page is a single value
+            continue;
+        }
+        let mut node = &mut root;
+        for key in parameter.split('.') {
+            node = node
+                .as_object_mut()
+                .ok_or_else(|| XcpClientError::CalSeg(format!("{}: conflicting parameter names", parameter)))?
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+        }
+        *node = value;
+    }
+    Ok(root)
+}
+
+fn db_error(e: rusqlite::Error) -> XcpClientError {
+    XcpClientError::CalSeg(format!("SQLite: {}", e))
+}
+
+//-----------------------------------------------------------------------------
+// CalChange
+
+/// A recorded change of a calibration parameter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalChange {
+    /// Time of the change in ns since the UNIX epoch
+    pub timestamp: u64,
+    pub segment: String,
+    pub parameter: String,
+    pub source: CalChangeSource,
+    /// Value before the change as JSON text, None if the parameter did not exist
+    pub old_value: Option<String>,
+    /// Value after the change as JSON text, None if the parameter was removed
+    pub new_value: Option<String>,
+}
+
+impl CalChangeSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            CalChangeSource::Xcp => "XCP",
+            CalChangeSource::Local => "local",
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+// CalStorageSqlite
+
+/// Calibration storage with change history in a SQLite database
+/// Table cal_value holds the current value of each parameter, table cal_history every change
+pub struct CalStorageSqlite {
+    conn: Mutex<Connection>,
+}
+
+impl CalStorageSqlite {
+    /// Open or create the database at path
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CalStorageSqlite, XcpClientError> {
+        let conn = Connection::open(path.as_ref()).map_err(db_error)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS cal_value (
+                segment TEXT NOT NULL,
+                parameter TEXT NOT NULL,
+                value TEXT NOT NULL,
+                PRIMARY KEY (segment, parameter)
+            );
+            CREATE TABLE IF NOT EXISTS cal_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp INTEGER NOT NULL,
+                segment TEXT NOT NULL,
+                parameter TEXT NOT NULL,
+                source TEXT NOT NULL,
+                old_value TEXT,
+                new_value TEXT
+            );",
+        )
+        .map_err(db_error)?;
+        xcp_info!(Cal, "Calibration storage {}", path.as_ref().display());
+        Ok(CalStorageSqlite { conn: Mutex::new(conn) })
+    }
+
+    /// Current value of a parameter as JSON text
+    pub fn get_value(&self, segment: &str, parameter: &str) -> Result<Option<String>, XcpClientError> {
+        self.conn
+            .lock()
+            .query_row("SELECT value FROM cal_value WHERE segment = ?1 AND parameter = ?2", params![segment, parameter], |row| {
+                row.get(0)
+            })
+            .optional()
+            .map_err(db_error)
+    }
+
+    /// Change history of a parameter, oldest change first
+    /// The parameter name may be given with or without segment prefix (segment.parameter)
+    pub fn get_history(&self, parameter: &str) -> Result<Vec<CalChange>, XcpClientError> {
+        let conn = self.conn.lock();
+        let mut stmt = conn
+            .prepare(
+                "SELECT timestamp, segment, parameter, source, old_value, new_value FROM cal_history
+                WHERE parameter = ?1 OR segment || '.' || parameter = ?1 ORDER BY id",
+            )
+            .map_err(db_error)?;
+        let rows = stmt
+            .query_map(params![parameter], |row| {
+                let source: String = row.get(3)?;
+                Ok(CalChange {
+                    timestamp: u64::try_from(row.get::<_, i64>(0)?).unwrap_or_default(),
+                    segment: row.get(1)?,
+                    parameter: row.get(2)?,
+                    source: if source == "XCP" { CalChangeSource::Xcp } else { CalChangeSource::Local },
+                    old_value: row.get(4)?,
+                    new_value: row.get(5)?,
+                })
+            })
+            .map_err(db_error)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+    }
+}
+
+impl CalStorage for CalStorageSqlite {
+    fn format(&self) -> CalPageFormat {
+        CalPageFormat::Json
+    }
+
+    fn load(&self, segment: &str) -> Result<Option<Vec<u8>>, XcpClientError> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT parameter, value FROM cal_value WHERE segment = ?1").map_err(db_error)?;
+        let values = stmt
+            .query_map(params![segment], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(db_error)?
+            .collect::<Result<Vec<(String, String)>, _>>()
+            .map_err(db_error)?;
+        if values.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(unflatten(&values)?.to_string().into_bytes()))
+    }
+
+    fn store(&self, segment: &str, data: &[u8]) -> Result<(), XcpClientError> {
+        self.store_from(segment, data, CalChangeSource::Local)
+    }
+
+    fn store_from(&self, segment: &str, data: &[u8], source: CalChangeSource) -> Result<(), XcpClientError> {
+        let page: Value = serde_json::from_slice(data).map_err(|e| XcpClientError::CalSeg(format!("{}: {}", segment, e)))?;
+        let mut new_values = BTreeMap::new();
+        flatten("", &page, &mut new_values);
+        let timestamp = i64::try_from(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos()).unwrap_or(i64::MAX);
+
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction().map_err(db_error)?;
+        let old_values: BTreeMap<String, String> = {
+            let mut stmt = tx.prepare("SELECT parameter, value FROM cal_value WHERE segment = ?1").map_err(db_error)?;
+            stmt.query_map(params![segment], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(db_error)?
+                .collect::<Result<_, _>>()
+                .map_err(db_error)?
+        };
+
+        let mut changes = 0;
+        for (parameter, value) in &new_values {
+            let old_value = old_values.get(parameter);
+            if old_value == Some(value) {
+                continue;
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO cal_value (segment, parameter, value) VALUES (?1, ?2, ?3)",
+                params![segment, parameter, value],
+            )
+            .map_err(db_error)?;
+            tx.execute(
+                "INSERT INTO cal_history (timestamp, segment, parameter, source, old_value, new_value) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![timestamp, segment, parameter, source.as_str(), old_value, value],
+            )
+            .map_err(db_error)?;
+            changes += 1;
+        }
+        // Parameters which do not exist in the page anymore
+        for (parameter, old_value) in old_values.iter().filter(|(p, _)| !new_values.contains_key(*p)) {
+            tx.execute("DELETE FROM cal_value WHERE segment = ?1 AND parameter = ?2", params![segment, parameter])
+                .map_err(db_error)?;
+            tx.execute(
+                "INSERT INTO cal_history (timestamp, segment, parameter, source, old_value, new_value) VALUES (?1, ?2, ?3, ?4, ?5, NULL)",
+                params![timestamp, segment, parameter, source.as_str(), old_value],
+            )
+            .map_err(db_error)?;
+            changes += 1;
+        }
+        tx.commit().map_err(db_error)?;
+        xcp_debug!(Cal, "Stored {}, {} parameter changes from {}", segment, changes, source.as_str());
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod cal_sqlite_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_cal_storage_sqlite() {
+        let path = std::env::temp_dir().join("xcp_lite_test_cal_storage.db");
+        let _ = std::fs::remove_file(&path);
+        let storage = CalStorageSqlite::open(&path).unwrap();
+        assert!(storage.load("seg").unwrap().is_none());
+
+        storage.store("seg", br#"{"a":1,"b":{"c":[1,2]},"d":true}"#).unwrap();
+        storage.store_from("seg", br#"{"a":2,"b":{"c":[1,3]},"d":true}"#, CalChangeSource::Xcp).unwrap();
+        let page: Value = serde_json::from_slice(&storage.load("seg").unwrap().unwrap()).unwrap();
+        assert_eq!(page, serde_json::json!({"a":2,"b":{"c":[1,3]},"d":true}));
+        assert_eq!(storage.get_value("seg", "b.c").unwrap().as_deref(), Some("[1,3]"));
+
+        let history = storage.get_history("seg.a").unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            (history[0].source, history[0].old_value.as_deref(), history[0].new_value.as_deref()),
+            (CalChangeSource::Local, None, Some("1"))
+        );
+        assert_eq!(
+            (history[1].source, history[1].old_value.as_deref(), history[1].new_value.as_deref()),
+            (CalChangeSource::Xcp, Some("1"), Some("2"))
+        );
+        assert_eq!(storage.get_history("d").unwrap().len(), 1);
+
+        // Removed parameter
+        storage.store("seg", br#"{"a":2,"d":true}"#).unwrap();
+        assert_eq!(storage.get_history("b.c").unwrap().last().unwrap().new_value, None);
+        drop(storage);
+        let _ = std::fs::remove_file(&path);
+    }
+}
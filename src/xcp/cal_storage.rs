@@ -0,0 +1,443 @@
+//-----------------------------------------------------------------------------
+// Module cal_storage
+// Pluggable persistence of calibration pages
+// CalSeg::freeze stores the working page of a calibration segment, CalSeg::load_frozen initializes the working page from the storage
+// The storage is selected with Xcp::set_cal_storage, applications may implement CalStorage to keep parameters in their own configuration database
+// With Xcp::set_cal_page_init(CalPageInit::Storage), all calibration segments are initialized from the storage on creation, so pages frozen by the
+// application or the XCP tool survive restarts without application code
+
+#[allow(unused_imports)]
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use parking_lot::Mutex;
+
+use super::XcpClientError;
+use crate::registry::crc32;
+
+//-----------------------------------------------------------------------------
+// CalStorage
+
+/// Representation of calibration pages in a storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalPageFormat {
+    /// Raw memory image of the page, valid for the same build of the application only
+    Binary,
+    /// JSON serialization of the page (serde), survives layout changes of the page type
+    Json,
+}
+
+/// Origin of a calibration page change, recorded by storages with change history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalChangeSource {
+    /// Calibrated by the XCP tool
+    Xcp,
+    /// Modified by the application
+    Local,
+}
+
+/// Persistence backend for calibration pages, keyed by calibration segment name
+pub trait CalStorage: Send + Sync {
+    /// Representation of the pages in this storage
+    fn format(&self) -> CalPageFormat;
+
+    /// Load the page of a calibration segment, Ok(None) if nothing is stored
+    fn load(&self, segment: &str) -> Result<Option<Vec<u8>>, XcpClientError>;
+
+    /// Store the page of a calibration segment, replaces a previously stored page
+    fn store(&self, segment: &str, data: &[u8]) -> Result<(), XcpClientError>;
+
+    /// Store the page of a calibration segment with the origin of the change
+    /// Storages without change history ignore the source
+    fn store_from(&self, segment: &str, data: &[u8], source: CalChangeSource) -> Result<(), XcpClientError> {
+        let _ = source;
+        self.store(segment, data)
+    }
+}
+
+//-----------------------------------------------------------------------------
+// CalStorageJsonDir
+
+/// One JSON file <segment>.json per calibration segment in a directory, the format of CalSeg::save and CalSeg::load
+#[derive(Debug)]
+pub struct CalStorageJsonDir {
+    dir: PathBuf,
+}
+
+impl CalStorageJsonDir {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> CalStorageJsonDir {
+        CalStorageJsonDir { dir: dir.into() }
+    }
+
+    fn get_path(&self, segment: &str) -> PathBuf {
+        self.dir.join(segment).with_extension("json")
+    }
+}
+
+impl CalStorage for CalStorageJsonDir {
+    fn format(&self) -> CalPageFormat {
+        CalPageFormat::Json
+    }
+
+    fn load(&self, segment: &str) -> Result<Option<Vec<u8>>, XcpClientError> {
+        match std::fs::read(self.get_path(segment)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store(&self, segment: &str, data: &[u8]) -> Result<(), XcpClientError> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.get_path(segment), data)?;
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------------------------------
+// CalBinSignature
+
+/// Signature of calibration BIN files, e.g. Ed25519 with the keys and crypto library of the application, xcp_lite does not include one
+/// The signature covers the whole file content in front of the signature
+pub trait CalBinSignature: Send + Sync + std::fmt::Debug {
+    /// Sign the file content, a verify only implementation returns an error
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, XcpClientError>;
+
+    /// Verify the signature of the file content, an error rejects the file
+    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<(), XcpClientError>;
+}
+
+//-----------------------------------------------------------------------------
+// CalStorageBinFile
+
+/// All calibration segments in one binary file, in the own format of xcp_lite (XCPCAL02), not the BIN persistence file of xcplib
+/// The file starts with a magic, a flags byte, the EPK of the application and the number of pages, pages stored by another build are ignored
+/// Flags bit 0 marks a big endian producer, the integers are little endian otherwise, files with unknown flags are rejected
+/// Each page is a record with name length (u16), name, size (u32), CRC32 (u32) and page data, strings are UTF-8
+/// The file ends with the signature length (u16) and the signature, length 0 if the file is not signed
+/// Files of version 1 (XCPCAL01, without CRC32 and signature) are still accepted, if no signature is required
+#[derive(Debug)]
+pub struct CalStorageBinFile {
+    path: PathBuf,
+    epk: String,
+    signature: Option<Box<dyn CalBinSignature>>,
+    lock: Mutex<()>, // Serializes read-modify-write of the file
+}
+
+impl CalStorageBinFile {
+    const MAGIC: &'static [u8; 8] = b"XCPCAL02";
+    const MAGIC_V1: &'static [u8; 8] = b"XCPCAL01";
+    const FLAG_BIG_ENDIAN: u8 = 0x01; // Byte order of all integers after the flags
+
+    /// Binary file at path, pages are valid for the application version epk only
+    pub fn new<P: Into<PathBuf>>(path: P, epk: &str) -> CalStorageBinFile {
+        CalStorageBinFile {
+            path: path.into(),
+            epk: epk.to_string(),
+            signature: None,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Sign the file on store and require a valid signature on load, unsigned or tampered files are rejected
+    pub fn with_signature(mut self, signature: Box<dyn CalBinSignature>) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    // Read all records, empty if the file does not exist or belongs to another EPK
+    // Fails if a length field exceeds the file, a CRC32 or the signature is wrong
+    fn read_records(&self) -> Result<Vec<(String, Vec<u8>)>, XcpClientError> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let path = self.path.display();
+        let corrupted = |e: XcpClientError| XcpClientError::CalSeg(format!("{} rejected, {}", path, e));
+        let mut reader = BinReader::new(&data);
+        let v1 = match reader.bytes(8).map_err(corrupted)? {
+            m if m == Self::MAGIC => false,
+            m if m == Self::MAGIC_V1 => true,
+            _ => return Err(XcpClientError::CalSeg(format!("{} is not a calibration storage file", path))),
+        };
+        if v1 && self.signature.is_some() {
+            return Err(XcpClientError::CalSeg(format!("{} rejected, version 1 file without signature", path)));
+        }
+        if !v1 {
+            let flags = reader.bytes(1).map_err(corrupted)?[0];
+            if flags & !Self::FLAG_BIG_ENDIAN != 0 {
+                return Err(XcpClientError::CalSeg(format!("{} rejected, unknown flags 0x{:02X}", path, flags)));
+            }
+            reader.big_endian = flags & Self::FLAG_BIG_ENDIAN != 0;
+        }
+        let epk = reader.string().map_err(corrupted)?;
+        let count = if v1 { None } else { Some(reader.u32().map_err(corrupted)?) };
+        let mut records = Vec::new();
+        while count.map_or(!reader.is_empty(), |count| records.len() < count as usize) {
+            let name = reader.string().map_err(corrupted)?;
+            let size = reader.u32().map_err(corrupted)?;
+            let crc = if v1 { None } else { Some(reader.u32().map_err(corrupted)?) };
+            let page = reader.bytes(size as usize).map_err(corrupted)?;
+            if crc.is_some_and(|crc| crc != crc32(page)) {
+                return Err(XcpClientError::CalSeg(format!("{} rejected, CRC32 error in segment {}, the file is corrupted", path, name)));
+            }
+            records.push((name, page.to_vec()));
+        }
+        if !v1 {
+            let signed_len = data.len() - reader.remaining();
+            let len = reader.u16().map_err(corrupted)?;
+            let signature = reader.bytes(len as usize).map_err(corrupted)?;
+            if !reader.is_empty() {
+                return Err(XcpClientError::CalSeg(format!("{} rejected, unexpected data after the signature", path)));
+            }
+            match &self.signature {
+                Some(_) if signature.is_empty() => return Err(XcpClientError::CalSeg(format!("{} rejected, file is not signed", path))),
+                Some(s) => s
+                    .verify(&data[..signed_len], signature)
+                    .map_err(|e| XcpClientError::CalSeg(format!("{} rejected, invalid signature, {}", path, e)))?,
+                None if !signature.is_empty() => xcp_warn!(Cal, "{} is signed, signature not verified", path),
+                None => {}
+            }
+        }
+        if epk != self.epk {
+            xcp_warn!(Cal, "{} ignored, EPK {} does not match {}", path, epk, self.epk);
+            return Ok(Vec::new());
+        }
+        Ok(records)
+    }
+
+    fn write_records(&self, records: &[(String, Vec<u8>)]) -> Result<(), XcpClientError> {
+        let mut data: Vec<u8> = Vec::new();
+        data.write_all(Self::MAGIC)?;
+        data.write_all(&[0])?; // Flags, always written little endian
+        write_string(&mut data, &self.epk)?;
+        let count = u32::try_from(records.len()).map_err(|_| XcpClientError::CalSeg("too many calibration segments".to_string()))?;
+        data.write_all(&count.to_le_bytes())?;
+        for (name, page) in records {
+            write_string(&mut data, name)?;
+            let size = u32::try_from(page.len()).map_err(|_| XcpClientError::CalSeg(format!("{} too large", name)))?;
+            data.write_all(&size.to_le_bytes())?;
+            data.write_all(&crc32(page).to_le_bytes())?;
+            data.write_all(page)?;
+        }
+        let signature = match &self.signature {
+            Some(s) => s.sign(&data)?,
+            None => Vec::new(),
+        };
+        let len = u16::try_from(signature.len()).map_err(|_| XcpClientError::CalSeg("signature too long".to_string()))?;
+        data.write_all(&len.to_le_bytes())?;
+        data.write_all(&signature)?;
+        // Write a temporary file and rename it, the previous file stays valid if writing fails
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, &data)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+// Reader of the file content with the byte order of the producer
+// The file is read byte wise, there are no alignment requirements, each length is checked against the remaining data before it is used,
+// a corrupted length field can not cause a huge allocation
+struct BinReader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> BinReader<'a> {
+    fn new(data: &'a [u8]) -> BinReader<'a> {
+        BinReader { data, big_endian: false }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], XcpClientError> {
+        if len > self.data.len() {
+            return Err(XcpClientError::CalSeg(format!(
+                "length {} exceeds the remaining {} bytes, the file is corrupted",
+                len,
+                self.data.len()
+            )));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, XcpClientError> {
+        let b: [u8; 2] = self.bytes(2)?.try_into().expect("2 bytes");
+        Ok(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+
+    fn u32(&mut self) -> Result<u32, XcpClientError> {
+        let b: [u8; 4] = self.bytes(4)?.try_into().expect("4 bytes");
+        Ok(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    // String with u16 length, UTF-8 encoded
+    fn string(&mut self) -> Result<String, XcpClientError> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| XcpClientError::CalSeg("calibration storage file contains invalid UTF-8".to_string()))
+    }
+}
+
+fn write_string(writer: &mut Vec<u8>, s: &str) -> Result<(), XcpClientError> {
+    let len = u16::try_from(s.len()).map_err(|_| XcpClientError::CalSeg(format!("{} too long", s)))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+impl CalStorage for CalStorageBinFile {
+    fn format(&self) -> CalPageFormat {
+        CalPageFormat::Binary
+    }
+
+    fn load(&self, segment: &str) -> Result<Option<Vec<u8>>, XcpClientError> {
+        let _lock = self.lock.lock();
+        Ok(self.read_records()?.into_iter().find(|(name, _)| name == segment).map(|(_, page)| page))
+    }
+
+    fn store(&self, segment: &str, data: &[u8]) -> Result<(), XcpClientError> {
+        let _lock = self.lock.lock();
+        let mut records = self.read_records()?;
+        match records.iter_mut().find(|(name, _)| name == segment) {
+            Some((_, page)) => *page = data.to_vec(),
+            None => records.push((segment.to_string(), data.to_vec())),
+        }
+        self.write_records(&records)
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod cal_storage_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_cal_storage_bin_file() {
+        let path = std::env::temp_dir().join("xcp_lite_test_cal_storage.bin");
+        let _ = std::fs::remove_file(&path);
+        let storage = CalStorageBinFile::new(&path, "EPK_1");
+        assert!(storage.load("seg1").unwrap().is_none());
+        storage.store("seg1", &[1, 2, 3]).unwrap();
+        storage.store("seg2", &[4]).unwrap();
+        storage.store("seg1", &[5, 6]).unwrap();
+        assert_eq!(storage.load("seg1").unwrap(), Some(vec![5, 6]));
+        assert_eq!(storage.load("seg2").unwrap(), Some(vec![4]));
+
+        // Pages of another build are ignored
+        assert!(CalStorageBinFile::new(&path, "EPK_2").load("seg1").unwrap().is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // Test signature, the sum of the bytes, an application would use Ed25519
+    #[derive(Debug)]
+    struct TestSignature;
+
+    impl CalBinSignature for TestSignature {
+        fn sign(&self, data: &[u8]) -> Result<Vec<u8>, XcpClientError> {
+            Ok(data.iter().fold(0u32, |s, b| s.wrapping_add(u32::from(*b))).to_le_bytes().to_vec())
+        }
+        fn verify(&self, data: &[u8], signature: &[u8]) -> Result<(), XcpClientError> {
+            if self.sign(data)? == signature {
+                Ok(())
+            } else {
+                Err(XcpClientError::CalSeg("signature mismatch".to_string()))
+            }
+        }
+    }
+
+    #[test]
+    fn test_cal_storage_bin_file_crc_and_signature() {
+        let path = std::env::temp_dir().join("xcp_lite_test_cal_storage_signed.bin");
+        let _ = std::fs::remove_file(&path);
+
+        // Unsigned file is rejected if a signature is required
+        CalStorageBinFile::new(&path, "EPK_1").store("seg1", &[1, 2, 3]).unwrap();
+        assert!(CalStorageBinFile::new(&path, "EPK_1").with_signature(Box::new(TestSignature)).load("seg1").is_err());
+
+        // Signed file
+        let storage = CalStorageBinFile::new(&path, "EPK_1").with_signature(Box::new(TestSignature));
+        storage.store("seg1", &[1, 2, 3]).unwrap();
+        assert_eq!(storage.load("seg1").unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(CalStorageBinFile::new(&path, "EPK_1").load("seg1").unwrap(), Some(vec![1, 2, 3]));
+
+        // Corrupted page
+        let mut data = std::fs::read(&path).unwrap();
+        let n = data.len();
+        data[n - 7] ^= 0xFF; // Last page byte in front of the signature
+        std::fs::write(&path, &data).unwrap();
+        assert!(CalStorageBinFile::new(&path, "EPK_1").load("seg1").is_err());
+
+        // Tampered page with correct CRC32, the signature does not match
+        data[n - 7] ^= 0xFF;
+        data[n - 8] = 0xFF;
+        let crc = crc32(&data[n - 9..n - 6]);
+        data[n - 13..n - 9].copy_from_slice(&crc.to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        assert!(CalStorageBinFile::new(&path, "EPK_1").load("seg1").is_ok());
+        assert!(storage.load("seg1").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_cal_storage_bin_file_byte_order_and_length() {
+        let path = std::env::temp_dir().join("xcp_lite_test_cal_storage_be.bin");
+
+        // File of a big endian producer
+        let mut data = b"XCPCAL02".to_vec();
+        data.push(CalStorageBinFile::FLAG_BIG_ENDIAN);
+        data.extend_from_slice(&5u16.to_be_bytes());
+        data.extend_from_slice(b"EPK_1");
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&4u16.to_be_bytes());
+        data.extend_from_slice(b"seg1");
+        data.extend_from_slice(&3u32.to_be_bytes());
+        data.extend_from_slice(&crc32(&[1, 2, 3]).to_be_bytes());
+        data.extend_from_slice(&[1, 2, 3]);
+        data.extend_from_slice(&0u16.to_be_bytes());
+        std::fs::write(&path, &data).unwrap();
+        let storage = CalStorageBinFile::new(&path, "EPK_1");
+        assert_eq!(storage.load("seg1").unwrap(), Some(vec![1, 2, 3]));
+
+        // Unknown flags
+        let mut bad = data.clone();
+        bad[8] = 0x80;
+        std::fs::write(&path, &bad).unwrap();
+        assert!(storage.load("seg1").is_err());
+
+        // Corrupted page size, rejected without allocating
+        let mut bad = data.clone();
+        let size_offset = 8 + 1 + 2 + 5 + 4 + 2 + 4;
+        bad[size_offset..size_offset + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        std::fs::write(&path, &bad).unwrap();
+        assert!(storage.load("seg1").is_err());
+
+        // Truncated file
+        std::fs::write(&path, &data[..data.len() - 4]).unwrap();
+        assert!(storage.load("seg1").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_cal_storage_json_dir() {
+        let dir = std::env::temp_dir().join("xcp_lite_test_cal_storage");
+        let storage = CalStorageJsonDir::new(&dir);
+        storage.store("seg1", br#"{"a":1}"#).unwrap();
+        assert_eq!(storage.load("seg1").unwrap(), Some(br#"{"a":1}"#.to_vec()));
+        assert!(storage.load("seg2").unwrap().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -3,7 +3,7 @@
 // Data acquisition
 
 #[allow(unused_imports)]
-use log::{debug, error, info, trace, warn};
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
 
 use crate::registry::*;
 use crate::xcp::*;
@@ -63,13 +63,19 @@ impl<const N: usize> DaqEvent<N> {
         N
     }
 
+    /// Get the used part of the capture buffer
+    #[cfg(feature = "daq_shm")]
+    pub(crate) fn get_captured(&self) -> &[u8] {
+        &self.buffer[..self.buffer_len]
+    }
+
     /// Allocate space in the events capture buffer
     /// # Panics
     /// On event buffer memory overflow
     /// # Returns
     /// Offset in the event buffer
     pub fn allocate(&mut self, size: usize) -> i16 {
-        trace!("Allocate DAQ buffer, size={}, len={}", size, self.buffer_len);
+        xcp_trace!(Daq, "Allocate DAQ buffer, size={}, len={}", size, self.buffer_len);
         let offset = self.buffer_len;
         assert!(offset + size <= self.buffer.len(), "DAQ buffer overflow");
         self.buffer_len += size;
@@ -83,15 +89,36 @@ impl<const N: usize> DaqEvent<N> {
     }
 
     /// Trigger for stack or capture buffer measurement with relative addressing on base address &self.buffer
+    /// In value change mode of the event, the trigger is skipped if the captured values did not change, see Xcp::set_event_on_change
     pub fn trigger(&self) {
         let base: *const u8 = &self.buffer as *const u8;
         // @@@@ UNSAFE - C library call which will dereference the raw pointer base
         unsafe {
-            self.event.trigger_ext(base);
+            self.event.trigger_ext_captured(base, Some(&self.buffer[..self.buffer_len]));
         }
     }
 
+    /// Copy a plain-old-data value to the capture buffer
+    pub fn capture_value<T: MeasureSafe>(&mut self, value: &T, offset: i16) {
+        const { assert!(T::NO_PADDING, "MeasureSafe: the measured type has padding bytes, add explicit padding fields") };
+        // @@@@ UNSAFE - T is MeasureSafe, all size_of::<T>() bytes of value are initialized
+        let data = unsafe { std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>()) };
+        self.capture(data, offset);
+    }
+
     /// Trigger for event relative addressing on base address base_ptr
+    /// T must be plain old data, the XCP server reads the memory of the measurement variables as bytes
+    pub fn trigger_ext_checked<T: MeasureSafe>(&self, base: *const T) {
+        const { assert!(T::NO_PADDING, "MeasureSafe: the measured type has padding bytes, add explicit padding fields") };
+        // @@@@ UNSAFE - C library call which will dereference the raw pointer base
+        unsafe {
+            self.event.trigger_ext(base as *const u8);
+        }
+    }
+
+    /// Trigger for event relative addressing on base address base_ptr
+    /// T is not checked to be plain old data, pointers and padding bytes are measured as they are
+    #[deprecated(note = "use trigger_ext_checked, which requires T: MeasureSafe")]
     pub fn trigger_ext<T>(&self, base: *const T) {
         // @@@@ UNSAFE - C library call which will dereference the raw pointer base
         unsafe {
@@ -101,21 +128,34 @@ impl<const N: usize> DaqEvent<N> {
 
     /// Associate a variable to this DaqEvent, register in rel addr mode, allocate space in the capture buffer and register it
     #[allow(clippy::too_many_arguments)]
+    #[track_caller]
     pub fn add_capture(&mut self, name: &'static str, size: usize, value_type: McValueType, x_dim: u16, y_dim: u16, mc_support_data: McSupportData) -> i16 {
         let event_offset: i16 = self.allocate(size); // Address offset (signed) relative to event memory context (XCP_ADDR_EXT_DYN)
-        trace!("Allocate DAQ buffer for {}, TLS OFFSET = {} {:?} and register measurement", name, event_offset, &value_type);
+        xcp_trace!(
+            Daq,
+            "Allocate DAQ buffer for {}, TLS OFFSET = {} {:?} and register measurement",
+            name,
+            event_offset,
+            &value_type
+        );
         let event = self.get_xcp_event();
+        if let Err(e) = Xcp::get().check_memory(std::mem::size_of::<McInstance>()) {
+            Xcp::get().report_error(e);
+            return event_offset;
+        }
         if let Some(reg) = registry::get_lock().as_mut() {
-            if let Err(e) = reg.instance_list.add_instance(
+            if let Err(e) = duplicates::add_measurement(
+                reg,
                 name,
                 McDimType::new(value_type, x_dim, y_dim),
                 mc_support_data,
                 McAddress::new_event_dyn(0, event.get_id(), event_offset as i32),
+                std::panic::Location::caller(),
             ) {
-                error!("add_instance failed: {}", e);
+                Xcp::get().report_error(e);
             }
         } else {
-            warn!("Could not register {}, registry already closed", name);
+            xcp_warn!(Daq, "Could not register {}, registry already closed", name);
         }
 
         event_offset
@@ -124,24 +164,183 @@ impl<const N: usize> DaqEvent<N> {
     /// Associate a variable on stack to this DaqEvent and register it in rel addr mode
     #[allow(clippy::too_many_arguments)]
     #[allow(clippy::cast_possible_wrap)]
+    #[track_caller]
     pub fn add_stack(&self, name: &'static str, ptr: *const u8, value_type: McValueType, x_dim: u16, y_dim: u16, mc_support_data: McSupportData) {
         let p = ptr as usize; // variable address
         let b = &self.buffer as *const _ as usize; // base address
         let o: i64 = p as i64 - b as i64; // variable - base address
         let event_offset: i16 = o.try_into().expect("memory offset out of range");
+        if let Err(e) = Xcp::get().check_memory(std::mem::size_of::<McInstance>()) {
+            Xcp::get().report_error(e);
+            return;
+        }
         if let Some(reg) = registry::get_lock().as_mut() {
-            if let Err(e) = reg.instance_list.add_instance(
+            if let Err(e) = duplicates::add_measurement(
+                reg,
                 name,
                 McDimType::new(value_type, x_dim, y_dim),
                 mc_support_data,
                 McAddress::new_event_dyn(0, self.event.get_id(), event_offset as i32),
+                std::panic::Location::caller(),
             ) {
-                error!("add_instance failed: {}", e);
+                Xcp::get().report_error(e);
             }
         } else {
-            warn!("Could not register {}, registry already closed", name);
+            xcp_warn!(Daq, "Could not register {}, registry already closed", name);
         }
     }
+
+    /// Associate a f64 signal to this DaqEvent, which is transmitted as quantized integer T to save bandwidth
+    /// The conversion physical = raw * factor + offset is declared in the registry, the limits are the physical range of T
+    /// # Panics
+    /// If factor is 0 or not finite
+    #[track_caller]
+    pub fn add_capture_quantized<T: DaqQuantized>(&mut self, name: &'static str, factor: f64, offset: f64, mc_support_data: McSupportData) -> i16 {
+        assert!(factor.is_finite() && factor != 0.0, "quantized DAQ factor must be finite and not 0");
+        let (min, max) = (T::RAW_MIN * factor + offset, T::RAW_MAX * factor + offset);
+        let mc_support_data = mc_support_data
+            .set_factor(Some(factor))
+            .set_offset(Some(offset))
+            .set_min(Some(min.min(max)))
+            .set_max(Some(min.max(max)));
+        self.add_capture(name, std::mem::size_of::<T>(), T::default().get_type(), 1, 1, mc_support_data)
+    }
+
+    /// Quantize a f64 value to T and copy it to the capture buffer, values outside the range of T are saturated
+    pub fn capture_quantized<T: DaqQuantized>(&mut self, value: f64, factor: f64, offset: f64, byte_offset: i16) {
+        self.capture(&T::quantize(value, factor, offset).to_le_bytes(), byte_offset);
+    }
+}
+
+//----------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------
+// Quantized DAQ
+
+/// Integer transport type of a quantized f64 signal, see DaqEvent::add_capture_quantized and daq_capture_quantized
+pub trait DaqQuantized: McValueTypeTrait + Copy + Default {
+    /// Range of the raw value
+    const RAW_MIN: f64;
+    const RAW_MAX: f64;
+
+    /// Raw value of a physical value, (value - offset) / factor rounded and saturated to the range of the type, NaN is 0
+    fn quantize(value: f64, factor: f64, offset: f64) -> Self;
+
+    /// Little endian bytes of the raw value
+    fn to_le_bytes(self) -> [u8; 2];
+}
+
+macro_rules! impl_daq_quantized {
+    ( $t:ty ) => {
+        impl DaqQuantized for $t {
+            const RAW_MIN: f64 = <$t>::MIN as f64;
+            const RAW_MAX: f64 = <$t>::MAX as f64;
+
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            fn quantize(value: f64, factor: f64, offset: f64) -> Self {
+                ((value - offset) / factor).round() as $t // Float to int casts saturate
+            }
+
+            fn to_le_bytes(self) -> [u8; 2] {
+                <$t>::to_le_bytes(self)
+            }
+        }
+    };
+}
+
+impl_daq_quantized!(i16);
+impl_daq_quantized!(u16);
+
+//----------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------
+// DaqBuffer
+
+/// DaqBuffer is a heap allocated array of basic type with a stable address, registered as measurement array of an event
+/// The length is fixed on creation, there is no way to grow the buffer, so the address given to XCP never dangles (as with Vec::as_ptr after a push)
+/// The event is triggered with the buffer as base address, the event should not be used for other measurements
+#[derive(Debug)]
+pub struct DaqBuffer<T> {
+    event: XcpEvent,
+    data: Box<[T]>,
+}
+
+impl<T> DaqBuffer<T>
+where
+    T: McValueTypeTrait + MeasureSafe + Copy + Default,
+{
+    /// Create a buffer of len default values and register it as measurement array name of event
+    /// # Panics
+    /// If len is 0 or exceeds u16::MAX
+    #[track_caller]
+    pub fn new(name: &'static str, event: XcpEvent, len: usize) -> DaqBuffer<T> {
+        DaqBuffer::from_vec(name, event, vec![T::default(); len])
+    }
+
+    /// Move the data of a Vec into a buffer with fixed length and register it as measurement array name of event
+    /// # Panics
+    /// If the Vec is empty or its length exceeds u16::MAX
+    #[track_caller]
+    pub fn from_vec(name: &'static str, event: XcpEvent, data: Vec<T>) -> DaqBuffer<T> {
+        let data = data.into_boxed_slice(); // Shrinks to fit, the last reallocation
+        let x_dim: u16 = data.len().try_into().expect("DaqBuffer length out of range");
+        assert!(x_dim > 0, "DaqBuffer must not be empty");
+        let buffer = DaqBuffer { event, data };
+        buffer.register(name, x_dim, std::panic::Location::caller());
+        buffer
+    }
+
+    // Register the buffer as measurement array at offset 0 relative to the event base address
+    fn register(&self, name: &'static str, x_dim: u16, location: &'static std::panic::Location<'static>) {
+        if let Err(e) = Xcp::get().check_memory(std::mem::size_of::<McInstance>()) {
+            Xcp::get().report_error(e);
+            return;
+        }
+        let y_dim = 1;
+        if let Some(reg) = registry::get_lock().as_mut() {
+            if let Err(e) = duplicates::add_measurement(
+                reg,
+                name,
+                McDimType::new(T::default().get_type(), x_dim, y_dim),
+                McSupportData::new(McObjectType::Measurement),
+                McAddress::new_event_dyn(0, self.event.get_id(), 0),
+                location,
+            ) {
+                Xcp::get().report_error(e);
+            }
+        } else {
+            xcp_warn!(Daq, "Could not register {}, registry already closed", name);
+        }
+    }
+}
+
+impl<T> DaqBuffer<T> {
+    /// Get the XcpEvent
+    pub fn get_xcp_event(&self) -> XcpEvent {
+        self.event
+    }
+
+    /// Trigger the event with the buffer as base address
+    pub fn trigger(&self) {
+        // @@@@ UNSAFE - C library call which will dereference the raw pointer base, the buffer is never reallocated and lives as long as self
+        unsafe {
+            self.event.trigger_ext(self.data.as_ptr() as *const u8);
+        }
+    }
+
+    /// Move the data out of the buffer, the event must not be triggered with this data anymore
+    pub fn into_vec(self) -> Vec<T> {
+        self.data.into_vec()
+    }
+}
+
+impl<T> std::ops::Deref for DaqBuffer<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        &self.data
+    }
+}
+
+impl<T> std::ops::DerefMut for DaqBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.data
+    }
 }
 
 //----------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------
@@ -251,6 +450,27 @@ macro_rules! daq_capture {
     }};
 }
 
+/// Capture the value of a f64 variable as quantized i16 or u16 into the capture buffer of the given daq event, to halve the DAQ bandwidth
+/// Register the given variable metadata and the conversion physical = raw * factor + offset once
+#[allow(unused_macros)]
+#[macro_export]
+macro_rules! daq_capture_quantized {
+    // name, event, transport type, comment, unit, factor, offset
+    ( $id:ident, $daq_event:expr, $t:ty, $comment:expr, $unit:expr, $factor:expr, $offset:expr ) => {{
+        static DAQ_OFFSET__: std::sync::atomic::AtomicI16 = std::sync::atomic::AtomicI16::new(-32768);
+        let byte_offset;
+        match DAQ_OFFSET__.compare_exchange(-32768, 0, std::sync::atomic::Ordering::Relaxed, std::sync::atomic::Ordering::Relaxed) {
+            Ok(_) => {
+                let mc_support_data = McSupportData::new(McObjectType::Measurement).set_comment($comment).set_unit($unit);
+                byte_offset = $daq_event.add_capture_quantized::<$t>(stringify!($id), $factor, $offset, mc_support_data);
+                DAQ_OFFSET__.store(byte_offset, std::sync::atomic::Ordering::Relaxed);
+            }
+            Err(offset) => byte_offset = offset,
+        };
+        $daq_event.capture_quantized::<$t>($id, $factor, $offset, byte_offset);
+    }};
+}
+
 // Capture the value of a variable with struct copy type into the the capture buffer of the given daq event
 // Register the given variable metadata once
 #[allow(unused_macros)]
@@ -275,7 +495,7 @@ macro_rules! daq_capture_struct {
             }
             Err(offset) => byte_offset = offset,
         };
-        $daq_event.capture(unsafe { std::mem::transmute(*$id) }, byte_offset);
+        $daq_event.capture_value(&*$id, byte_offset);
     }};
 }
 
@@ -582,6 +802,9 @@ macro_rules! daq_register_instance {
 #[cfg(test)]
 mod daq_tests {
 
+    #[allow(unused_imports)]
+    use log::{debug, error, info, trace, warn};
+
     use super::*;
     // use crate::registry::*;
     // use crate::xcp;
@@ -661,6 +884,135 @@ mod daq_tests {
         xcp.finalize_registry().unwrap(); // Generate A2L and test
     }
 
+    //-----------------------------------------------------------------------------
+    // Test quantized capture
+    #[test]
+    fn test_daq_capture_quantized() {
+        assert_eq!(i16::quantize(1.26, 0.01, 0.0), 126);
+        assert_eq!(i16::quantize(-1.0, 0.01, 0.0), -100);
+        assert_eq!(i16::quantize(1000.0, 0.01, 0.0), i16::MAX);
+        assert_eq!(u16::quantize(-1.0, 0.01, 0.0), 0);
+        assert_eq!(u16::quantize(20.5, 0.5, 10.0), 21);
+        assert_eq!(u16::quantize(f64::NAN, 0.5, 10.0), 0);
+
+        let _xcp = xcp_test::test_setup();
+        let mut event = daq_create_event!("TestEventQuantized", 8);
+        let voltage: f64 = 12.34;
+        daq_capture_quantized!(voltage, event, i16, "voltage", "V", 0.001, 0.0);
+        assert_eq!(&event.buffer[0..2], &12340i16.to_le_bytes());
+        event.trigger();
+    }
+
+    //-----------------------------------------------------------------------------
+    // Test plain old data struct capture and trigger
+    #[derive(McRegisterType, Debug, Clone, Copy)]
+    #[repr(C)]
+    struct PodStruct {
+        a: u32,
+        b: [u16; 2],
+        c: f64,
+    }
+
+    #[test]
+    fn test_daq_measure_safe() {
+        let xcp = xcp_test::test_setup();
+
+        let mut event = daq_create_event!("TestEvent1", 16);
+        let value = PodStruct { a: 1, b: [2, 3], c: 4.0 };
+        event.capture_value(&value, 0);
+        assert_eq!(&event.buffer[0..8], &[1, 0, 0, 0, 2, 0, 3, 0]);
+        event.trigger_ext_checked(&value as *const PodStruct);
+        xcp.finalize_registry().unwrap(); // Generate A2L and test
+    }
+
+    //-----------------------------------------------------------------------------
+    // Test Rust layout struct, fields may be reordered by the compiler, no padding
+    #[derive(McRegisterType, Debug, Clone, Copy)]
+    struct RustLayoutStruct {
+        a: u8,
+        b: u32,
+        c: u8,
+        d: u16,
+    }
+
+    #[test]
+    fn test_daq_rust_layout() {
+        let xcp = xcp_test::test_setup();
+
+        let mut event = daq_create_event!("TestEvent1", 8);
+        let value = RustLayoutStruct { a: 1, b: 2, c: 3, d: 4 };
+        event.capture_value(&value, 0);
+        assert_eq!(event.buffer[std::mem::offset_of!(RustLayoutStruct, a)], 1);
+        assert_eq!(event.buffer[std::mem::offset_of!(RustLayoutStruct, b)], 2);
+        assert_eq!(event.buffer[std::mem::offset_of!(RustLayoutStruct, c)], 3);
+        assert_eq!(event.buffer[std::mem::offset_of!(RustLayoutStruct, d)], 4);
+
+        // The typedef has the real field offsets
+        value.mc_register(McRegisterTarget::Event(event.get_event_id()), Some("value"));
+        {
+            let reg = registry::get_lock();
+            let typedef = reg.as_ref().unwrap().typedef_list.find_typedef("RustLayoutStruct").unwrap();
+            assert_eq!(typedef.find_field("b").unwrap().get_offset() as usize, std::mem::offset_of!(RustLayoutStruct, b));
+            assert_eq!(typedef.find_field("d").unwrap().get_offset() as usize, std::mem::offset_of!(RustLayoutStruct, d));
+        }
+        xcp.finalize_registry().unwrap(); // Generate A2L and test
+    }
+
+    //-----------------------------------------------------------------------------
+    // Test nested struct register and trigger
+    #[derive(McRegisterType, Debug, Clone, Copy)]
+    struct NestedStruct {
+        counter: u64,
+        pod: PodStruct,
+        points: [RustLayoutStruct; 2],
+    }
+
+    #[test]
+    fn test_daq_register_struct() {
+        let xcp = xcp_test::test_setup();
+
+        let event = xcp.create_event("TestEventStruct");
+        event.register_struct::<NestedStruct>("nested").unwrap();
+        assert!(event.register_struct::<NestedStruct>("nested").is_err());
+        {
+            let reg = registry::get_lock();
+            let reg = reg.as_ref().unwrap();
+            let typedef = reg.typedef_list.find_typedef("NestedStruct").unwrap();
+            assert_eq!(typedef.find_field("pod").unwrap().get_offset() as usize, std::mem::offset_of!(NestedStruct, pod));
+            assert_eq!(typedef.find_field("points").unwrap().get_dim_type().get_dim(), [2, 1]);
+            assert!(reg.typedef_list.find_typedef("PodStruct").is_some());
+            assert!(reg.typedef_list.find_typedef("RustLayoutStruct").is_some());
+            assert!(reg.instance_list.get_instance("nested", McObjectType::Measurement, Some(event.get_id())).is_some());
+        }
+        let value = Box::new(NestedStruct {
+            counter: 1,
+            pod: PodStruct { a: 1, b: [2, 3], c: 4.0 },
+            points: [RustLayoutStruct { a: 1, b: 2, c: 3, d: 4 }; 2],
+        });
+        event.trigger_struct(value.as_ref());
+        xcp.finalize_registry().unwrap(); // Generate A2L and test
+        assert!(event.register_struct::<NestedStruct>("nested2").is_err());
+    }
+
+    //-----------------------------------------------------------------------------
+    // Test heap buffer register
+    #[test]
+    fn test_daq_buffer() {
+        let xcp = xcp_test::test_setup();
+
+        let event = xcp.create_event("TestEvent1");
+        let mut buffer: DaqBuffer<f32> = DaqBuffer::from_vec("buffer", event, vec![0.0; 8]);
+        let ptr = buffer.as_ptr();
+        for i in 0..3u8 {
+            buffer.iter_mut().for_each(|v| *v += f32::from(i));
+            buffer.trigger();
+        }
+        assert_eq!(buffer.as_ptr(), ptr);
+        assert_eq!(buffer[7], 3.0);
+        xcp.finalize_registry().unwrap(); // Generate A2L and test
+        assert_eq!(buffer.into_vec().len(), 8);
+    }
+
     //-----------------------------------------------------------------------------
     // Test A2L file generation for local variables
     #[test]
@@ -0,0 +1,538 @@
+//-----------------------------------------------------------------------------
+// Module daq_shm
+// Multi process DAQ event aggregation
+// Worker processes (e.g. of a pre-fork server architecture) push DAQ event capture buffers into a named shared memory queue,
+// the XCP server process drains the queue and triggers the DAQ events on behalf of the workers
+// The queue is a bounded multi producer queue with a sequence number per slot, it uses only atomics in the shared memory and
+// is safe across fork, as long as each process uses its own mapping
+// When the queue is full, the overflow policy of the event decides between dropping the new event, dropping the oldest event or
+// blocking the producer for a bounded time
+// The overflow policy is a feature of this shared memory queue only, the DAQ queue of xcplib used by events triggered in the XCP server
+// process is not configurable from Rust, it drops the new event on overflow
+// Coalescing of bursty events is done by the aggregator and is also limited to events of the shared memory queue
+
+//-----------------------------------------------------------------------------
+
+#[allow(unused_imports)]
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
+use memmap2::MmapMut;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{DaqEvent, XcpClientError, XcpEvent};
+use crate::registry::MeasureSafe;
+
+//-----------------------------------------------------------------------------
+// Shared memory layout
+
+const DAQ_SHM_MAGIC: u32 = 0x51485344; // "DSHQ"
+
+/// Max size of the capture buffer of a DAQ event pushed by a worker process
+pub const DAQ_SHM_SLOT_SIZE: usize = 1024;
+
+// Header at the start of the shared memory region, followed by the slots
+#[repr(C)]
+struct DaqShmHeader {
+    magic: u32,
+    slot_count: u32,
+    enqueue_pos: AtomicU64,
+    dequeue_pos: AtomicU64,
+    overflow_count: AtomicU64, // Number of events lost because the queue was full
+}
+
+// Queue slot
+#[repr(C)]
+struct DaqShmSlot {
+    sequence: AtomicU64, // == position when free, == position + 1 when filled
+    event_id: u16,
+    len: u16,
+    _reserved: u32,
+    data: [u8; DAQ_SHM_SLOT_SIZE],
+}
+
+const DAQ_SHM_HEADER_SIZE: usize = std::mem::size_of::<DaqShmHeader>();
+const DAQ_SHM_SLOT_STRIDE: usize = std::mem::size_of::<DaqShmSlot>();
+
+// Attempts of DaqOverflowPolicy::DropOldest to make room for the new event
+const DAQ_SHM_DROP_OLDEST_RETRIES: usize = 4;
+
+/// Path of the shared memory file for a DAQ queue name
+/// /dev/shm on Linux, the temp directory on other platforms
+pub fn daq_shm_path(name: &str) -> std::path::PathBuf {
+    let dir = if cfg!(target_os = "linux") {
+        std::path::PathBuf::from("/dev/shm")
+    } else {
+        std::env::temp_dir()
+    };
+    dir.join(format!("xcp_daq_{}", name))
+}
+
+// Shared memory queue mapping, used by both sides
+struct DaqShmQueue {
+    mmap: MmapMut,
+}
+
+impl DaqShmQueue {
+    fn header(&self) -> &DaqShmHeader {
+        // @@@@ UNSAFE - The mapping is page aligned and at least DAQ_SHM_HEADER_SIZE bytes
+        unsafe { &*(self.mmap.as_ptr() as *const DaqShmHeader) }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn slot(&self, pos: u64) -> *mut DaqShmSlot {
+        let index = (pos % u64::from(self.header().slot_count)) as usize;
+        // @@@@ UNSAFE - index < slot_count, the mapping contains slot_count slots
+        unsafe { self.mmap.as_ptr().add(DAQ_SHM_HEADER_SIZE + index * DAQ_SHM_SLOT_STRIDE) as *mut DaqShmSlot }
+    }
+
+    // Push an event, returns false if the queue is full
+    #[allow(clippy::cast_possible_truncation)]
+    fn try_push(&self, event_id: u16, data: &[u8]) -> bool {
+        let header = self.header();
+        let mut pos = header.enqueue_pos.load(Ordering::Relaxed);
+        loop {
+            let slot = self.slot(pos);
+            // @@@@ UNSAFE - Atomic access to the slot sequence number in shared memory
+            let seq = unsafe { (*slot).sequence.load(Ordering::Acquire) };
+            if seq == pos {
+                match header.enqueue_pos.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        // @@@@ UNSAFE - The slot is owned by this producer until the sequence number is released
+                        unsafe {
+                            (*slot).event_id = event_id;
+                            (*slot).len = data.len() as u16;
+                            std::ptr::copy_nonoverlapping(data.as_ptr(), (*slot).data.as_mut_ptr(), data.len());
+                            (*slot).sequence.store(pos + 1, Ordering::Release);
+                        }
+                        return true;
+                    }
+                    Err(p) => pos = p,
+                }
+            } else if seq < pos + 1 {
+                // Slot not yet consumed, queue full
+                return false;
+            } else {
+                pos = header.enqueue_pos.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Push an event, apply the overflow policy if the queue is full
+    // Returns false if the event was lost or the data does not fit into a slot
+    fn push(&self, event_id: u16, data: &[u8], policy: DaqOverflowPolicy) -> bool {
+        if data.len() > DAQ_SHM_SLOT_SIZE {
+            xcp_error!(Daq, "DAQ capture buffer too large for shared memory queue");
+            return false;
+        }
+        if self.try_push(event_id, data) {
+            return true;
+        }
+        let header = self.header();
+        match policy {
+            DaqOverflowPolicy::DropNewest => {}
+            DaqOverflowPolicy::DropOldest => {
+                // Other producers may refill the slot, retry a few times before the new event is dropped
+                for _ in 0..DAQ_SHM_DROP_OLDEST_RETRIES {
+                    if self.pop_with(|_, _| ()).is_some() {
+                        header.overflow_count.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if self.try_push(event_id, data) {
+                        return true;
+                    }
+                }
+            }
+            DaqOverflowPolicy::Block(timeout) => {
+                let deadline = std::time::Instant::now() + timeout;
+                while std::time::Instant::now() < deadline {
+                    std::thread::yield_now();
+                    if self.try_push(event_id, data) {
+                        return true;
+                    }
+                }
+            }
+        }
+        header.overflow_count.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    // Pop the oldest event and pass its event id and data to f
+    // Producers dropping the oldest event also consume, the dequeue position is claimed atomically
+    fn pop_with<R>(&self, f: impl FnOnce(u16, &[u8]) -> R) -> Option<R> {
+        let header = self.header();
+        let mut pos = header.dequeue_pos.load(Ordering::Relaxed);
+        loop {
+            let slot = self.slot(pos);
+            // @@@@ UNSAFE - Atomic access to the slot sequence number in shared memory
+            let seq = unsafe { (*slot).sequence.load(Ordering::Acquire) };
+            if seq == pos + 1 {
+                match header.dequeue_pos.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        // @@@@ UNSAFE - The slot is filled and owned by this consumer until the sequence number is released
+                        let r = unsafe {
+                            let len = ((*slot).len as usize).min(DAQ_SHM_SLOT_SIZE);
+                            let r = f((*slot).event_id, &(&(*slot).data)[..len]);
+                            (*slot).sequence.store(pos + u64::from(header.slot_count), Ordering::Release);
+                            r
+                        };
+                        return Some(r);
+                    }
+                    Err(p) => pos = p,
+                }
+            } else if seq < pos + 1 {
+                // Slot not yet filled, queue empty
+                return None;
+            } else {
+                pos = header.dequeue_pos.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Pop an event into buf, returns the event id and the length
+    fn pop(&self, buf: &mut [u8; DAQ_SHM_SLOT_SIZE]) -> Option<(u16, usize)> {
+        self.pop_with(|event_id, data| {
+            buf[..data.len()].copy_from_slice(data);
+            (event_id, data.len())
+        })
+    }
+}
+
+//-----------------------------------------------------------------------------
+// DaqShmProducer
+
+/// Behavior of a producer when the shared memory queue is full
+/// Lost events are counted in the overflow count of the queue and reported by the aggregator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DaqOverflowPolicy {
+    /// Drop the new event, the producer never waits (default)
+    #[default]
+    DropNewest,
+    /// Drop the oldest event in the queue to make room for the new event, the queue keeps the most recent events
+    DropOldest,
+    /// Wait until the aggregator has drained a slot, at most for the given timeout, then drop the new event
+    Block(std::time::Duration),
+}
+
+/// Worker process side of a multi process DAQ queue
+/// Open the queue by name in each worker process, after fork
+pub struct DaqShmProducer {
+    queue: DaqShmQueue,
+    default_policy: DaqOverflowPolicy,
+    policies: Vec<Option<DaqOverflowPolicy>>, // Overflow policy per event id, None = default_policy
+}
+
+impl DaqShmProducer {
+    /// Open the shared memory DAQ queue created by the XCP server process
+    pub fn open(name: &str) -> Result<DaqShmProducer, std::io::Error> {
+        let path = daq_shm_path(name);
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(&path)?;
+        // @@@@ UNSAFE - Memory mapped file, shared with other processes, accessed only via atomics and owned slots
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        if mmap.len() < DAQ_SHM_HEADER_SIZE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} too small", path.display())));
+        }
+        let queue = DaqShmQueue { mmap };
+        let header = queue.header();
+        if header.magic != DAQ_SHM_MAGIC || queue.mmap.len() < DAQ_SHM_HEADER_SIZE + header.slot_count as usize * DAQ_SHM_SLOT_STRIDE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} is not a DAQ queue", path.display())));
+        }
+        Ok(DaqShmProducer {
+            queue,
+            default_policy: DaqOverflowPolicy::default(),
+            policies: Vec::new(),
+        })
+    }
+
+    /// Set the overflow policy of all events without an individual policy
+    pub fn set_default_overflow_policy(&mut self, policy: DaqOverflowPolicy) {
+        self.default_policy = policy;
+    }
+
+    /// Set the overflow policy of an event in the shared memory queue, the DAQ queue of xcplib is not affected
+    pub fn set_overflow_policy(&mut self, event: XcpEvent, policy: DaqOverflowPolicy) {
+        let index = event.get_id() as usize;
+        if self.policies.len() <= index {
+            self.policies.resize(index + 1, None);
+        }
+        self.policies[index] = Some(policy);
+    }
+
+    /// Get the overflow policy of an event
+    pub fn get_overflow_policy(&self, event_id: u16) -> DaqOverflowPolicy {
+        self.policies.get(event_id as usize).copied().flatten().unwrap_or(self.default_policy)
+    }
+
+    /// Push the capture buffer of a DAQ event to the XCP server process
+    /// Returns false if the event was lost because of the overflow policy or the data exceeds DAQ_SHM_SLOT_SIZE
+    pub fn trigger<const N: usize>(&self, daq_event: &DaqEvent<N>) -> bool {
+        self.queue
+            .push(daq_event.get_event_id(), daq_event.get_captured(), self.get_overflow_policy(daq_event.get_event_id()))
+    }
+
+    /// Push a value of type T for an event with relative addressing on the base address of the value
+    /// Returns false if the event was lost because of the overflow policy or the data exceeds DAQ_SHM_SLOT_SIZE
+    pub fn trigger_ext<T: Copy + MeasureSafe>(&self, event: XcpEvent, data: &T) -> bool {
+        const { assert!(T::NO_PADDING, "MeasureSafe: the measured type has padding bytes, add explicit padding fields") };
+        // @@@@ UNSAFE - T is MeasureSafe, the value is read as plain bytes
+        let bytes = unsafe { std::slice::from_raw_parts(data as *const T as *const u8, std::mem::size_of::<T>()) };
+        self.queue.push(event.get_id(), bytes, self.get_overflow_policy(event.get_id()))
+    }
+
+    /// Push raw capture data for an event id
+    /// Returns false if the event was lost because of the overflow policy or the data exceeds DAQ_SHM_SLOT_SIZE
+    pub fn push(&self, event_id: u16, data: &[u8]) -> bool {
+        self.queue.push(event_id, data, self.get_overflow_policy(event_id))
+    }
+}
+
+//-----------------------------------------------------------------------------
+// DaqShmAggregator
+
+/// XCP server process side of a multi process DAQ queue
+/// The DAQ events and their measurement variables must be created and registered in the XCP server process before the workers are forked
+/// DAQ timestamps are taken when the aggregator triggers the event, not when the worker pushed it
+pub struct DaqShmAggregator {
+    queue: DaqShmQueue,
+    path: std::path::PathBuf,
+    buf: Box<[u8; DAQ_SHM_SLOT_SIZE]>,
+    reported_overflow_count: u64,                                // Overflows already reported on the error channel
+    coalesce: std::collections::HashMap<u16, DaqShmCoalesceBuf>, // Latest sample of coalesced events, per event id
+    coalesced_count: u64,                                        // Samples superseded by a newer sample of the same event
+}
+
+// Latest sample of a coalesced event in one poll
+struct DaqShmCoalesceBuf {
+    len: Option<usize>, // None = no sample pending
+    buf: Box<[u8; DAQ_SHM_SLOT_SIZE]>,
+}
+
+impl DaqShmAggregator {
+    /// Create a shared memory DAQ queue with slot_count slots
+    pub fn new(name: &str, slot_count: u32) -> Result<DaqShmAggregator, std::io::Error> {
+        if slot_count == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "slot_count must not be 0"));
+        }
+        let path = daq_shm_path(name);
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+        file.set_len((DAQ_SHM_HEADER_SIZE + slot_count as usize * DAQ_SHM_SLOT_STRIDE) as u64)?;
+        // @@@@ UNSAFE - Memory mapped file, shared with other processes, accessed only via atomics and owned slots
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+        // Initialize header and slot sequence numbers, the file is zero initialized
+        // @@@@ UNSAFE - The mapping is page aligned and large enough for the header and all slots
+        unsafe {
+            let header = &mut *(mmap.as_mut_ptr() as *mut DaqShmHeader);
+            header.slot_count = slot_count;
+            header.enqueue_pos.store(0, Ordering::Relaxed);
+            header.dequeue_pos.store(0, Ordering::Relaxed);
+            header.overflow_count.store(0, Ordering::Relaxed);
+            for i in 0..slot_count as usize {
+                let slot = &*(mmap.as_ptr().add(DAQ_SHM_HEADER_SIZE + i * DAQ_SHM_SLOT_STRIDE) as *const DaqShmSlot);
+                slot.sequence.store(i as u64, Ordering::Relaxed);
+            }
+            std::sync::atomic::fence(Ordering::Release);
+            header.magic = DAQ_SHM_MAGIC;
+        }
+        mmap.flush()?;
+        xcp_info!(Daq, "DAQ shared memory queue {} with {} slots", path.display(), slot_count);
+        Ok(DaqShmAggregator {
+            queue: DaqShmQueue { mmap },
+            path,
+            buf: Box::new([0; DAQ_SHM_SLOT_SIZE]),
+            reported_overflow_count: 0,
+            coalesce: std::collections::HashMap::new(),
+            coalesced_count: 0,
+        })
+    }
+
+    /// Coalesce the triggers of an event within one poll, only the latest sample of the event is triggered
+    /// Protects the XCP transport from producers bursting far above the poll rate, the superseded samples are counted, not reported as error
+    pub fn set_coalescing(&mut self, event: XcpEvent, enable: bool) {
+        if enable {
+            self.coalesce.entry(event.get_id()).or_insert_with(|| DaqShmCoalesceBuf {
+                len: None,
+                buf: Box::new([0; DAQ_SHM_SLOT_SIZE]),
+            });
+        } else {
+            self.coalesce.remove(&event.get_id());
+        }
+    }
+
+    /// Number of samples of coalesced events superseded by a newer sample
+    pub fn get_coalesced_count(&self) -> u64 {
+        self.coalesced_count
+    }
+
+    /// Drain the queue and trigger the DAQ events, returns the number of events triggered
+    /// Coalesced events are triggered once with their latest sample, after the queue is drained
+    /// Events lost since the last poll are reported on the error channel
+    pub fn poll(&mut self) -> usize {
+        let mut count = 0;
+        while let Some((event_id, len)) = self.queue.pop(&mut self.buf) {
+            if let Some(c) = self.coalesce.get_mut(&event_id) {
+                if c.len.is_some() {
+                    self.coalesced_count += 1;
+                }
+                c.buf[..len].copy_from_slice(&self.buf[..len]);
+                c.len = Some(len);
+                continue;
+            }
+            let event = XcpEvent::new(event_id, 0);
+            // @@@@ UNSAFE - C library call, the buffer has the layout of the workers capture buffer
+            unsafe {
+                event.trigger_ext(self.buf.as_ptr());
+            }
+            count += 1;
+        }
+        for (event_id, c) in &mut self.coalesce {
+            if c.len.take().is_some() {
+                let event = XcpEvent::new(*event_id, 0);
+                // @@@@ UNSAFE - C library call, the buffer has the layout of the workers capture buffer
+                unsafe {
+                    event.trigger_ext(c.buf.as_ptr());
+                }
+                count += 1;
+            }
+        }
+        let overflow_count = self.get_overflow_count();
+        if overflow_count > self.reported_overflow_count {
+            super::Xcp::get().report_error(XcpClientError::QueueOverflow(overflow_count - self.reported_overflow_count));
+            self.reported_overflow_count = overflow_count;
+        }
+        count
+    }
+
+    /// Poll the queue cyclically in a thread, as long as the XCP server is running
+    pub fn spawn(mut self, cycle_time: std::time::Duration) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            let _span = tracing::info_span!(target: "daq", "daq_shm_aggregator").entered();
+            while super::Xcp::get().check_server() {
+                self.poll();
+                std::thread::sleep(cycle_time);
+            }
+            xcp_debug!(Daq, "DAQ shared memory aggregator stopped, {} events lost", self.get_overflow_count());
+        })
+    }
+
+    /// Number of events lost because the queue was full
+    pub fn get_overflow_count(&self) -> u64 {
+        self.queue.header().overflow_count.load(Ordering::Relaxed)
+    }
+
+    /// Path of the shared memory file
+    pub fn get_path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+impl Drop for DaqShmAggregator {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod daq_shm_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_daq_shm_queue() {
+        let aggregator = DaqShmAggregator::new("test_daq_shm_queue", 4).unwrap();
+        let producer = DaqShmProducer::open("test_daq_shm_queue").unwrap();
+        let mut buf = [0u8; DAQ_SHM_SLOT_SIZE];
+
+        // Empty queue
+        assert!(aggregator.queue.pop(&mut buf).is_none());
+
+        // Fill the queue until overflow
+        for i in 0..4u8 {
+            assert!(producer.push(u16::from(i), &[i, i + 1]));
+        }
+        assert!(!producer.push(4, &[0]));
+        assert_eq!(aggregator.get_overflow_count(), 1);
+
+        // Drain in order
+        for i in 0..4u8 {
+            let (event_id, len) = aggregator.queue.pop(&mut buf).unwrap();
+            assert_eq!(event_id, u16::from(i));
+            assert_eq!(len, 2);
+            assert_eq!(&buf[..2], &[i, i + 1]);
+        }
+        assert!(aggregator.queue.pop(&mut buf).is_none());
+
+        // Wrap around
+        assert!(producer.push(7, &[7; 16]));
+        assert_eq!(aggregator.queue.pop(&mut buf), Some((7, 16)));
+
+        let path = aggregator.get_path().to_path_buf();
+        drop(producer);
+        drop(aggregator);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_daq_shm_overflow_policy() {
+        let aggregator = DaqShmAggregator::new("test_daq_shm_overflow_policy", 2).unwrap();
+        let mut producer = DaqShmProducer::open("test_daq_shm_overflow_policy").unwrap();
+        let mut buf = [0u8; DAQ_SHM_SLOT_SIZE];
+        producer.set_overflow_policy(XcpEvent::new(1, 0), DaqOverflowPolicy::DropOldest);
+        producer.set_overflow_policy(XcpEvent::new(2, 0), DaqOverflowPolicy::Block(std::time::Duration::from_millis(10)));
+        assert_eq!(producer.get_overflow_policy(0), DaqOverflowPolicy::DropNewest);
+        assert_eq!(producer.get_overflow_policy(1), DaqOverflowPolicy::DropOldest);
+
+        // Drop oldest, the queue keeps the most recent events
+        for i in 0..4u8 {
+            assert!(producer.push(1, &[i]));
+        }
+        assert_eq!(aggregator.get_overflow_count(), 2);
+        assert_eq!(aggregator.queue.pop(&mut buf), Some((1, 1)));
+        assert_eq!(buf[0], 2);
+        assert_eq!(aggregator.queue.pop(&mut buf), Some((1, 1)));
+        assert_eq!(buf[0], 3);
+
+        // Block, the aggregator does not drain, the event is dropped after the timeout
+        assert!(producer.push(2, &[0]));
+        assert!(producer.push(2, &[1]));
+        let start = std::time::Instant::now();
+        assert!(!producer.push(2, &[2]));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(10));
+        assert_eq!(aggregator.get_overflow_count(), 3);
+
+        // Default policy drop newest
+        producer.set_default_overflow_policy(DaqOverflowPolicy::DropNewest);
+        assert!(!producer.push(0, &[0]));
+        assert_eq!(aggregator.get_overflow_count(), 4);
+        assert_eq!(aggregator.queue.pop(&mut buf), Some((2, 1)));
+        assert_eq!(buf[0], 0);
+    }
+
+    #[test]
+    fn test_daq_shm_coalescing() {
+        let _xcp = crate::xcp::xcp_test::test_setup();
+        let mut aggregator = DaqShmAggregator::new("test_daq_shm_coalescing", 16).unwrap();
+        let producer = DaqShmProducer::open("test_daq_shm_coalescing").unwrap();
+        aggregator.set_coalescing(XcpEvent::new(1, 0), true);
+
+        // Burst of event 1 is triggered once, event 0 is not coalesced
+        for i in 0..8u8 {
+            assert!(producer.push(1, &[i]));
+        }
+        assert!(producer.push(0, &[0]));
+        assert!(producer.push(0, &[1]));
+        assert_eq!(aggregator.poll(), 3);
+        assert_eq!(aggregator.get_coalesced_count(), 7);
+        assert_eq!(aggregator.coalesce[&1].buf[0], 7);
+
+        // Nothing pending after the poll
+        assert_eq!(aggregator.poll(), 0);
+
+        // Coalescing disabled
+        aggregator.set_coalescing(XcpEvent::new(1, 0), false);
+        assert!(producer.push(1, &[0]));
+        assert!(producer.push(1, &[1]));
+        assert_eq!(aggregator.poll(), 2);
+        assert_eq!(aggregator.get_coalesced_count(), 7);
+    }
+}
@@ -0,0 +1,130 @@
+//-----------------------------------------------------------------------------
+// Module duplicates
+// Diagnostics and policy for duplicate event and measurement names
+// The source location of the first registration is recorded, a duplicate is reported with this location and a hint how to create multiple instances
+// With XcpDuplicatePolicy::AutoSuffix, a duplicate is registered with the first free name suffix _2, _3, ... instead
+// The source location is also stored in the registry instance and written as A2L ANNOTATION
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::panic::Location;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[allow(unused_imports)]
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
+use super::XcpClientError;
+use crate::registry::{McAddress, McDimType, McSupportData, Registry};
+
+/// Handling of duplicate event names and duplicate measurement names of the same event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum XcpDuplicatePolicy {
+    /// A duplicate is not registered, the error contains the source location of the first registration
+    #[default]
+    Error,
+    /// A duplicate is registered with the name suffix _2, _3, ..., a warning with the new name is logged
+    AutoSuffix,
+}
+
+static AUTO_SUFFIX: AtomicBool = AtomicBool::new(false);
+
+// Source location of the first registration of a measurement, by event id and name
+static MEASUREMENT_LOCATIONS: LazyLock<Mutex<HashMap<(u16, &'static str), &'static Location<'static>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub(crate) fn set_policy(policy: XcpDuplicatePolicy) {
+    AUTO_SUFFIX.store(policy == XcpDuplicatePolicy::AutoSuffix, Ordering::Relaxed);
+}
+
+pub(crate) fn get_policy() -> XcpDuplicatePolicy {
+    if AUTO_SUFFIX.load(Ordering::Relaxed) {
+        XcpDuplicatePolicy::AutoSuffix
+    } else {
+        XcpDuplicatePolicy::Error
+    }
+}
+
+// Name with the first free suffix _2, _3, ..., exists checks a candidate name
+fn suffixed_name(name: &str, exists: impl Fn(&str) -> bool) -> &'static str {
+    let mut n = 2;
+    loop {
+        let candidate = format!("{}_{}", name, n);
+        if !exists(&candidate) {
+            return Box::leak(candidate.into_boxed_str());
+        }
+        n += 1;
+    }
+}
+
+// Resolve a duplicate event name
+// Returns the name to create the event with, or the error with the first registration at first
+pub(crate) fn resolve_event_name(name: &'static str, first: &'static Location<'static>, exists: impl Fn(&str) -> bool) -> Result<&'static str, XcpClientError> {
+    match get_policy() {
+        XcpDuplicatePolicy::AutoSuffix => {
+            let new_name = suffixed_name(name, exists);
+            xcp_warn!(Daq, "Event {} already exists (first created at {}), created as {}", name, first, new_name);
+            Ok(new_name)
+        }
+        XcpDuplicatePolicy::Error => Err(XcpClientError::Event(format!(
+            "event {} already exists, first created at {}. Use create_event_ext(name, true), daq_create_event_tli! or daq_create_event_instance! for multiple instances of an event, or XcpDuplicatePolicy::AutoSuffix",
+            name, first
+        ))),
+    }
+}
+
+// Register a measurement instance of an event, a duplicate name of the same event is handled by the policy
+// Returns the registered name
+pub(crate) fn add_measurement(
+    reg: &mut Registry,
+    name: &'static str,
+    dim_type: McDimType,
+    mc_support_data: McSupportData,
+    address: McAddress,
+    location: &'static Location<'static>,
+) -> Result<&'static str, XcpClientError> {
+    let event_id = address.get_event_id();
+    let exists = |n: &str| reg.instance_list.into_iter().any(|i| i.get_name() == n && i.event_id() == event_id);
+    let key = (event_id.unwrap_or(0xFFFF), name);
+    let mut locations = MEASUREMENT_LOCATIONS.lock();
+    let name = if exists(name) {
+        let first = locations.get(&key).map_or_else(|| "unknown location".to_string(), ToString::to_string);
+        match get_policy() {
+            XcpDuplicatePolicy::AutoSuffix => {
+                let new_name = suffixed_name(name, exists);
+                xcp_warn!(
+                    Daq,
+                    "Measurement {} already exists in event {} (first registered at {}), registered as {}",
+                    name,
+                    key.0,
+                    first,
+                    new_name
+                );
+                new_name
+            }
+            XcpDuplicatePolicy::Error => {
+                return Err(XcpClientError::Registry(format!(
+                    "measurement {} already exists in event {}, first registered at {}, duplicate at {}. Use daq_register_tli! or daq_register_instance! with an event instance for each thread or task instance, or XcpDuplicatePolicy::AutoSuffix",
+                    name, key.0, first, location
+                )));
+            }
+        }
+    } else {
+        name
+    };
+    reg.instance_list
+        .add_instance(name, dim_type, mc_support_data, address)
+        .map_err(|e| XcpClientError::Registry(format!("{} not registered: {}", name, e)))?;
+    // The registration site is the source location of the measurement in the A2L
+    reg.instance_list
+        .set_source(name, event_id, location.file(), location.line())
+        .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+    locations.insert((key.0, name), location);
+    Ok(name)
+}
+
+// Reset the policy and the recorded locations, the registry is reinitialized by the test setup
+#[cfg(test)]
+pub(crate) fn test_reset() {
+    set_policy(XcpDuplicatePolicy::Error);
+    MEASUREMENT_LOCATIONS.lock().clear();
+}
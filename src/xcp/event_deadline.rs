@@ -0,0 +1,248 @@
+//-----------------------------------------------------------------------------
+// Module event_deadline
+// Deadline monitoring of periodic events
+// An event with an expected cycle time is checked on each trigger, the time since the previous trigger is compared to the cycle time:
+// a trigger later than cycle time plus jitter budget is late, if one or more whole cycles passed without trigger, these cycles are missed
+// The counters may be measured with their own event, which is triggered after each check, see register_deadline_metrics
+// Violations of events with notify are printed to the XCP client tool by a monitor thread, the trigger path only sets a flag,
+// the event name is looked up when the deadline is set
+
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, Ordering};
+use std::sync::{LazyLock, Once};
+use std::time::{Duration, Instant};
+
+use super::{Xcp, XcpClientError, XcpEvent};
+use crate::registry::{self, McAddress, McDimType, McObjectType, McSupportData, McValueType};
+
+const MAX_EVENTS: usize = XcpEvent::XCP_MAX_EVENTS as usize;
+
+struct EventDeadlineCounters {
+    cycle_time_ns: AtomicU32, // Expected cycle time, 0 = not monitored
+    jitter_ns: AtomicU32,     // Jitter budget
+    notify: AtomicBool,       // Print a message to the XCP client tool on a violation
+    last_ns: AtomicU64,       // Time of the previous trigger, 0 = no previous trigger
+    triggers: AtomicU64,
+    late: AtomicU64,
+    missed: AtomicU64,
+    jitter_ns_max: AtomicU64, // Maximum deviation from the cycle time
+    violation: AtomicBool,    // A violation is pending to be notified by the monitor thread
+    violation_interval_ns: AtomicU64,
+    violation_missed: AtomicU64,
+}
+
+impl EventDeadlineCounters {
+    const fn new() -> EventDeadlineCounters {
+        EventDeadlineCounters {
+            cycle_time_ns: AtomicU32::new(0),
+            jitter_ns: AtomicU32::new(0),
+            notify: AtomicBool::new(false),
+            last_ns: AtomicU64::new(0),
+            triggers: AtomicU64::new(0),
+            late: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+            jitter_ns_max: AtomicU64::new(0),
+            violation: AtomicBool::new(false),
+            violation_interval_ns: AtomicU64::new(0),
+            violation_missed: AtomicU64::new(0),
+        }
+    }
+}
+
+static EVENT_DEADLINE_COUNTERS: [EventDeadlineCounters; MAX_EVENTS] = [const { EventDeadlineCounters::new() }; MAX_EVENTS];
+
+// Event id of the deadline metrics of an event, triggered after each check, see register_deadline_metrics
+static EVENT_DEADLINE_EVENTS: [AtomicU16; MAX_EVENTS] = [const { AtomicU16::new(XcpEvent::XCP_UNDEFINED_EVENT_ID) }; MAX_EVENTS];
+
+// Event names of the monitored events, for the notifications of the monitor thread
+static EVENT_DEADLINE_NAMES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+// Cycle time of the monitor thread, which prints pending violations
+const NOTIFY_CYCLE: Duration = Duration::from_millis(100);
+static NOTIFY_THREAD: Once = Once::new();
+
+// Time base of the trigger timestamps, the XCP clock resolution depends on the xcplib configuration
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Deadline statistics of an event, see Xcp::get_event_deadline_stats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XcpDeadlineStats {
+    pub triggers: u64,      // Number of monitored triggers
+    pub late: u64,          // Triggers later than cycle time plus jitter budget, without a missed cycle
+    pub missed: u64,        // Cycles without trigger
+    pub jitter_ns_max: u64, // Maximum deviation of the trigger interval from the cycle time in ns
+}
+
+impl XcpDeadlineStats {
+    /// Check if there was no late trigger and no missed cycle
+    pub fn is_ok(&self) -> bool {
+        self.late == 0 && self.missed == 0
+    }
+}
+
+impl std::fmt::Display for XcpDeadlineStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "triggers={} late={} missed={} jitter_max={}ns",
+            self.triggers, self.late, self.missed, self.jitter_ns_max
+        )
+    }
+}
+
+// Elapsed time since START in ns, never 0
+fn now_ns() -> u64 {
+    u64::try_from(START.elapsed().as_nanos()).unwrap_or(u64::MAX).max(1)
+}
+
+// Set the expected cycle time and jitter budget of event id, a cycle time of 0 stops the monitoring
+pub(crate) fn set_deadline(id: u16, cycle_time: Duration, jitter: Duration, notify: bool) -> Result<(), XcpClientError> {
+    let Some(c) = EVENT_DEADLINE_COUNTERS.get(id as usize) else {
+        return Err(XcpClientError::Event(format!("invalid event id {} for deadline monitoring", id)));
+    };
+    let (Ok(cycle_time_ns), Ok(jitter_ns)) = (u32::try_from(cycle_time.as_nanos()), u32::try_from(jitter.as_nanos())) else {
+        return Err(XcpClientError::Event(format!("deadline of event id {} exceeds u32 ns", id)));
+    };
+    LazyLock::force(&START);
+    if notify {
+        {
+            let mut names = EVENT_DEADLINE_NAMES.lock();
+            if names.len() <= id as usize {
+                names.resize(id as usize + 1, "");
+            }
+            names[id as usize] = XcpEvent::new(id, 0).get_name();
+        }
+        NOTIFY_THREAD.call_once(|| {
+            std::thread::spawn(notify_thread);
+        });
+    }
+    c.jitter_ns.store(jitter_ns, Ordering::Relaxed);
+    c.notify.store(notify, Ordering::Relaxed);
+    c.last_ns.store(0, Ordering::Relaxed);
+    c.cycle_time_ns.store(cycle_time_ns, Ordering::Relaxed);
+    Ok(())
+}
+
+// Get the expected cycle time of event id in ns, 0 if not monitored
+pub(crate) fn get_cycle_time_ns(id: u16) -> u32 {
+    EVENT_DEADLINE_COUNTERS.get(id as usize).map_or(0, |c| c.cycle_time_ns.load(Ordering::Relaxed))
+}
+
+// Check a trigger of event id against its deadline
+// Called on each trigger, before decimation
+pub(crate) fn check(id: u16) {
+    let Some(c) = EVENT_DEADLINE_COUNTERS.get(id as usize) else {
+        return;
+    };
+    let cycle_time_ns = u64::from(c.cycle_time_ns.load(Ordering::Relaxed));
+    if cycle_time_ns == 0 {
+        return;
+    }
+    let now = now_ns();
+    let last = c.last_ns.swap(now, Ordering::Relaxed);
+    c.triggers.fetch_add(1, Ordering::Relaxed);
+    if last == 0 || now < last {
+        return;
+    }
+    let interval = now - last;
+    c.jitter_ns_max.fetch_max(interval.abs_diff(cycle_time_ns), Ordering::Relaxed);
+    if interval > cycle_time_ns + u64::from(c.jitter_ns.load(Ordering::Relaxed)) {
+        // Rounded number of cycles since the previous trigger, more than one means cycles without trigger
+        let missed = (interval + cycle_time_ns / 2) / cycle_time_ns - 1;
+        if missed > 0 {
+            c.missed.fetch_add(missed, Ordering::Relaxed);
+        } else {
+            c.late.fetch_add(1, Ordering::Relaxed);
+        }
+        if c.notify.load(Ordering::Relaxed) {
+            c.violation_interval_ns.store(interval, Ordering::Relaxed);
+            c.violation_missed.store(missed, Ordering::Relaxed);
+            c.violation.store(true, Ordering::Release);
+        }
+    }
+    let event_id = EVENT_DEADLINE_EVENTS[id as usize].load(Ordering::Relaxed);
+    if event_id != XcpEvent::XCP_UNDEFINED_EVENT_ID {
+        // @@@@ UNSAFE - the deadline metrics measurements are registered relative to the static counters of this event
+        unsafe { XcpEvent::new(event_id, 0).trigger_ext(std::ptr::from_ref(c).cast::<u8>()) }
+    }
+}
+
+// Print the pending violations of the events with notify to the XCP client tool console, the last violation of each event per cycle
+fn notify_thread() {
+    let _span = tracing::info_span!(target: "daq", "xcp_deadline").entered();
+    loop {
+        std::thread::sleep(NOTIFY_CYCLE);
+        let names = EVENT_DEADLINE_NAMES.lock();
+        for (id, name) in names.iter().enumerate() {
+            let c = &EVENT_DEADLINE_COUNTERS[id];
+            if c.violation.swap(false, Ordering::Acquire) {
+                Xcp::get().print(&format!(
+                    "Event {} deadline violation: interval {}us, cycle time {}us, {} cycles missed",
+                    name,
+                    c.violation_interval_ns.load(Ordering::Relaxed) / 1000,
+                    c.cycle_time_ns.load(Ordering::Relaxed) / 1000,
+                    c.violation_missed.load(Ordering::Relaxed)
+                ));
+            }
+        }
+    }
+}
+
+// Check if a violation of event id is pending to be notified
+#[cfg(test)]
+pub(crate) fn is_violation_pending(id: u16) -> bool {
+    EVENT_DEADLINE_COUNTERS[id as usize].violation.load(Ordering::Acquire)
+}
+
+// Register the deadline counters of event id as measurements "<name>.deadline.<counter>" of the event "<name>.deadline"
+pub(crate) fn register_deadline_metrics(id: u16, name: &str) -> Result<(), XcpClientError> {
+    let Some(metrics_event_id) = EVENT_DEADLINE_EVENTS.get(id as usize) else {
+        return Err(XcpClientError::Event(format!("invalid event id {} for deadline monitoring", id)));
+    };
+    let event = Xcp::get().try_create_event_ext(Box::leak(format!("{}.deadline", name).into_boxed_str()), false)?;
+    let fields = [
+        ("triggers", std::mem::offset_of!(EventDeadlineCounters, triggers), ""),
+        ("late", std::mem::offset_of!(EventDeadlineCounters, late), ""),
+        ("missed", std::mem::offset_of!(EventDeadlineCounters, missed), ""),
+        ("jitter_ns_max", std::mem::offset_of!(EventDeadlineCounters, jitter_ns_max), "ns"),
+    ];
+    {
+        let mut reg = registry::get_lock();
+        let Some(reg) = reg.as_mut() else {
+            return Err(XcpClientError::Registry(format!("{} deadline metrics not registered, registry is finalized", name)));
+        };
+        for (field, offset, unit) in fields {
+            reg.instance_list
+                .add_instance(
+                    format!("{}.deadline.{}", name, field),
+                    McDimType::new(McValueType::Ulonglong, 1, 1),
+                    McSupportData::new(McObjectType::Measurement).set_unit(unit),
+                    McAddress::new_event_dyn(0, event.get_id(), i32::try_from(offset).unwrap_or_default()),
+                )
+                .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+        }
+    }
+    metrics_event_id.store(event.get_id(), Ordering::Relaxed);
+    Ok(())
+}
+
+// Get the deadline statistics of event id
+pub(crate) fn get_deadline_stats(id: u16) -> XcpDeadlineStats {
+    EVENT_DEADLINE_COUNTERS.get(id as usize).map_or_else(XcpDeadlineStats::default, |c| XcpDeadlineStats {
+        triggers: c.triggers.load(Ordering::Relaxed),
+        late: c.late.load(Ordering::Relaxed),
+        missed: c.missed.load(Ordering::Relaxed),
+        jitter_ns_max: c.jitter_ns_max.load(Ordering::Relaxed),
+    })
+}
+
+// Reset the deadline statistics of event id, the next trigger starts a new interval
+pub(crate) fn reset_deadline_stats(id: u16) {
+    if let Some(c) = EVENT_DEADLINE_COUNTERS.get(id as usize) {
+        c.last_ns.store(0, Ordering::Relaxed);
+        c.triggers.store(0, Ordering::Relaxed);
+        c.late.store(0, Ordering::Relaxed);
+        c.missed.store(0, Ordering::Relaxed);
+        c.jitter_ns_max.store(0, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,115 @@
+//-----------------------------------------------------------------------------
+// Module event_on_change
+// Value change (on change) DAQ mode of events with a capture buffer, for slow changing signals
+// A trigger is only measured if the captured bytes changed since the last measured trigger, or if the maximum interval (heartbeat) elapsed
+// The comparison uses a FNV-1a hash of the captured bytes, on an equal hash the bytes are compared with a copy of the last measured capture,
+// it works per event, as xcplib measures all DAQ lists of an event on a trigger
+// The copy is accessed with try_lock, the trigger never blocks, if the copy is busy, the trigger is measured
+
+use parking_lot::Mutex;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::{XcpClientError, XcpEvent};
+
+const MAX_EVENTS: usize = XcpEvent::XCP_MAX_EVENTS as usize;
+
+struct EventOnChangeState {
+    max_interval_ns: AtomicU32, // Heartbeat interval, 0 = on change mode off
+    last_hash: AtomicU64,       // Hash of the last measured capture, 0 = no previous measurement
+    last_ns: AtomicU64,         // Time of the last measured trigger
+    last_bytes: Mutex<Vec<u8>>, // Copy of the last measured capture
+}
+
+impl EventOnChangeState {
+    const fn new() -> EventOnChangeState {
+        EventOnChangeState {
+            max_interval_ns: AtomicU32::new(0),
+            last_hash: AtomicU64::new(0),
+            last_ns: AtomicU64::new(0),
+            last_bytes: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+static EVENT_ON_CHANGE_STATE: [EventOnChangeState; MAX_EVENTS] = [const { EventOnChangeState::new() }; MAX_EVENTS];
+
+// Time base of the heartbeat
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+// Elapsed time since START in ns
+fn now_ns() -> u64 {
+    u64::try_from(START.elapsed().as_nanos()).unwrap_or(u64::MAX)
+}
+
+// FNV-1a hash of the captured bytes, never 0
+fn hash(data: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in data {
+        h ^= u64::from(*b);
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h.max(1)
+}
+
+// Set the on change mode of an event, None turns it off
+pub(super) fn set_on_change(id: u16, max_interval: Option<Duration>) -> Result<(), XcpClientError> {
+    let Some(state) = EVENT_ON_CHANGE_STATE.get(id as usize) else {
+        return Err(XcpClientError::Event(format!("invalid event id {}", id)));
+    };
+    let max_interval_ns = match max_interval {
+        None => 0,
+        Some(d) if d.is_zero() => return Err(XcpClientError::Event(format!("event id {} on change max interval must not be zero", id))),
+        Some(d) => u32::try_from(d.as_nanos()).map_err(|_| XcpClientError::Event(format!("event id {} on change max interval {:?} exceeds u32 ns", id, d)))?,
+    };
+    state.max_interval_ns.store(max_interval_ns, Ordering::Relaxed);
+    state.last_hash.store(0, Ordering::Relaxed);
+    Ok(())
+}
+
+// Get the heartbeat interval of an event in ns, 0 if on change mode is off
+pub(super) fn get_max_interval_ns(id: u16) -> u32 {
+    EVENT_ON_CHANGE_STATE.get(id as usize).map_or(0, |s| s.max_interval_ns.load(Ordering::Relaxed))
+}
+
+// Forget the last measured captures, the next trigger of each event is measured
+pub(super) fn reset() {
+    for state in &EVENT_ON_CHANGE_STATE {
+        state.last_hash.store(0, Ordering::Relaxed);
+    }
+}
+
+// Check if a trigger with the captured bytes has to be measured, always true if on change mode is off
+pub(super) fn is_due(id: u16, captured: &[u8]) -> bool {
+    let state = &EVENT_ON_CHANGE_STATE[id as usize];
+    let max_interval_ns = state.max_interval_ns.load(Ordering::Relaxed);
+    if max_interval_ns == 0 {
+        return true;
+    }
+    let h = hash(captured);
+    let now = now_ns();
+    if state.last_hash.load(Ordering::Relaxed) == h
+        && now.saturating_sub(state.last_ns.load(Ordering::Relaxed)) < u64::from(max_interval_ns)
+        && state.last_bytes.try_lock().is_some_and(|last| last.as_slice() == captured)
+    {
+        return false;
+    }
+    // Keep a copy of the measured capture, without a copy the hash is invalidated and the next trigger is measured
+    match state.last_bytes.try_lock() {
+        Some(mut last) => {
+            last.clear();
+            last.extend_from_slice(captured);
+            state.last_hash.store(h, Ordering::Relaxed);
+        }
+        None => state.last_hash.store(0, Ordering::Relaxed),
+    }
+    state.last_ns.store(now, Ordering::Relaxed);
+    true
+}
+
+// Store the hash of data as hash of the last measured capture, without its bytes, to simulate a hash collision
+#[cfg(test)]
+pub(super) fn set_last_hash_of(id: u16, data: &[u8]) {
+    EVENT_ON_CHANGE_STATE[id as usize].last_hash.store(hash(data), Ordering::Relaxed);
+}
@@ -0,0 +1,540 @@
+//-----------------------------------------------------------------------------
+// Module headless
+// Autonomous logging without a connected XCP client, xcp-lite as a standalone data logger
+// The measurement configurations marked as auto start (see Xcp::add_measurement_config) are recorded to a local CSV file,
+// each trigger of an event writes one line with the time (in s) and the values of the signals of this event, the other columns are empty
+// The recording has the format of XcpReplay, it may be replayed or imported by a measurement tool
+// When a client connects, it takes over: the logger stops and the file is closed, logging may be restarted by the application
+// Structures and arrays are logged as separate columns of their fields and elements
+// MDF4 is not supported, the transport and the DAQ lists of xcplib are not used, values are read directly at the trigger base pointer
+// The trigger copies the logged memory span of its event into a lock-free ring of samples, a writer thread formats and writes the lines,
+// on a full ring the sample is dropped and counted
+
+#[allow(unused_imports)]
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
+use std::cell::UnsafeCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU16, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use super::{Xcp, XcpClientError, XcpEvent};
+use crate::registry::{self, McDimType, McValueType, Registry};
+
+const MAX_EVENTS: usize = XcpEvent::XCP_MAX_EVENTS as usize;
+
+static LOGGER_ACTIVE: AtomicBool = AtomicBool::new(false);
+static LOGGER: Mutex<Option<std::thread::JoinHandle<std::io::Result<u64>>>> = Mutex::new(None);
+
+// Logged memory span of each event relative to its trigger base pointer, length 0 = event not logged
+static EVENT_SPAN_OFFSET: [AtomicI32; MAX_EVENTS] = [const { AtomicI32::new(0) }; MAX_EVENTS];
+static EVENT_SPAN_LEN: [AtomicU16; MAX_EVENTS] = [const { AtomicU16::new(0) }; MAX_EVENTS];
+
+// Logging session, samples of a previous session left in the ring are discarded
+static SESSION: AtomicU32 = AtomicU32::new(0);
+
+// Time base of the sample timestamps
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+// Cycle time of the writer thread
+const WRITE_CYCLE: Duration = Duration::from_millis(10);
+
+// A logged signal, a value of basic type at an offset to the base pointer of its event
+#[derive(Debug, Clone)]
+struct HeadlessSignal {
+    name: String,
+    event_id: u16,
+    offset: i32,
+    value_type: McValueType,
+}
+
+impl HeadlessSignal {
+    // Format the value at the start of bytes as text, empty if bytes is too short
+    fn format(&self, bytes: &[u8]) -> String {
+        fn ne<const N: usize>(bytes: &[u8]) -> Option<[u8; N]> {
+            bytes.get(..N)?.try_into().ok()
+        }
+        let value = match self.value_type {
+            McValueType::Bool => ne::<1>(bytes).map(|b| u8::from(b[0] != 0).to_string()),
+            McValueType::Ubyte => ne(bytes).map(|b| u8::from_ne_bytes(b).to_string()),
+            McValueType::Sbyte => ne(bytes).map(|b| i8::from_ne_bytes(b).to_string()),
+            McValueType::Uword => ne(bytes).map(|b| u16::from_ne_bytes(b).to_string()),
+            McValueType::Sword => ne(bytes).map(|b| i16::from_ne_bytes(b).to_string()),
+            McValueType::Ulong => ne(bytes).map(|b| u32::from_ne_bytes(b).to_string()),
+            McValueType::Slong => ne(bytes).map(|b| i32::from_ne_bytes(b).to_string()),
+            McValueType::Ulonglong => ne(bytes).map(|b| u64::from_ne_bytes(b).to_string()),
+            McValueType::Slonglong => ne(bytes).map(|b| i64::from_ne_bytes(b).to_string()),
+            McValueType::Float32Ieee => ne(bytes).map(|b| f32::from_ne_bytes(b).to_string()),
+            McValueType::Float64Ieee => ne(bytes).map(|b| f64::from_ne_bytes(b).to_string()),
+            _ => None,
+        };
+        value.unwrap_or_default()
+    }
+}
+
+// Expand a measurement object to signals of basic type, structures and arrays to their fields and elements
+fn expand_signals(registry: &Registry, name: &str, dim_type: &McDimType, event_id: u16, offset: i32, signals: &mut Vec<HeadlessSignal>) {
+    let [x_dim, y_dim] = dim_type.get_dim();
+    let count = usize::from(x_dim) * usize::from(y_dim);
+    let value_type = dim_type.value_type;
+    let element_size = match value_type {
+        McValueType::TypeDef(typedef_name) => match registry.typedef_list.find_typedef(typedef_name.as_str()) {
+            Some(typedef) => typedef.size,
+            None => {
+                xcp_warn!(Daq, "Headless logging: typedef {} of {} not found", typedef_name, name);
+                return;
+            }
+        },
+        McValueType::Blob(_) | McValueType::Unknown => {
+            xcp_warn!(Daq, "Headless logging: {} has no basic type, not logged", name);
+            return;
+        }
+        _ => value_type.get_size(),
+    };
+    for i in 0..count {
+        let element_name = if count > 1 { format!("{}[{}]", name, i) } else { name.to_string() };
+        let element_offset = offset + i32::try_from(i * element_size).unwrap_or_default();
+        if let McValueType::TypeDef(typedef_name) = value_type {
+            let Some(typedef) = registry.typedef_list.find_typedef(typedef_name.as_str()) else {
+                return;
+            };
+            for field in &typedef.fields {
+                expand_signals(
+                    registry,
+                    &format!("{}.{}", element_name, field.get_name()),
+                    field.get_dim_type(),
+                    event_id,
+                    element_offset + i32::from(field.get_offset()),
+                    signals,
+                );
+            }
+        } else {
+            signals.push(HeadlessSignal {
+                name: element_name,
+                event_id,
+                offset: element_offset,
+                value_type,
+            });
+        }
+    }
+}
+
+// Signals of the auto start measurement configurations, in the order of the configurations, each signal once
+fn auto_start_signals(registry: &Registry) -> Vec<HeadlessSignal> {
+    let mut signals: Vec<HeadlessSignal> = Vec::new();
+    for config in registry.measurement_config_list.auto_start_configs() {
+        for name in &config.measurements {
+            let mut found = false;
+            for instance in registry.instance_list.into_iter().filter(|i| i.is_measurement_object() && i.name == *name) {
+                found = true;
+                let address = instance.get_address();
+                let Some(event_id) = address.get_event_id().filter(|_| address.is_event_relative()) else {
+                    xcp_warn!(Daq, "Headless logging: {} is not event relative, not logged", name);
+                    continue;
+                };
+                let mut instance_signals = Vec::new();
+                expand_signals(
+                    registry,
+                    &instance.get_unique_name(registry),
+                    instance.get_dim_type(),
+                    event_id,
+                    address.get_addr_offset(),
+                    &mut instance_signals,
+                );
+                for signal in instance_signals {
+                    if !signals.iter().any(|s| s.name == signal.name) {
+                        signals.push(signal);
+                    }
+                }
+            }
+            if !found {
+                xcp_warn!(Daq, "Headless logging: measurement configuration {}: measurement {} not found", config.name, name);
+            }
+        }
+    }
+    signals
+}
+
+//-----------------------------------------------------------------------------
+// Sample ring
+// Bounded multi producer, single consumer queue with a sequence number per slot, the triggers are the producers, the writer thread
+// is the consumer, a slot is written by the producer which reserved its position and released with its sequence number
+
+const RING_SIZE: usize = 1024; // Number of samples
+const SAMPLE_SIZE: usize = 256; // Maximum logged memory span of an event in bytes
+
+#[derive(Clone, Copy)]
+struct HeadlessSample {
+    session: u32,
+    event_id: u16,
+    len: u16,
+    time_ns: u64,
+    data: [u8; SAMPLE_SIZE],
+}
+
+struct HeadlessSlot {
+    seq: AtomicU64, // Position of the slot, when it is free to write, position + 1, when the sample is ready to read
+    sample: UnsafeCell<HeadlessSample>,
+}
+
+struct HeadlessRing {
+    slots: Box<[HeadlessSlot]>,
+    tail: AtomicU64,    // Next position to write
+    head: AtomicU64,    // Next position to read, only modified by the writer thread
+    dropped: AtomicU64, // Samples dropped on a full ring
+}
+
+// @@@@ UNSAFE - A sample is only accessed by the thread which owns its slot according to the sequence number
+unsafe impl Sync for HeadlessRing {}
+
+static RING: LazyLock<HeadlessRing> = LazyLock::new(|| HeadlessRing {
+    slots: (0..RING_SIZE as u64)
+        .map(|pos| HeadlessSlot {
+            seq: AtomicU64::new(pos),
+            sample: UnsafeCell::new(HeadlessSample {
+                session: 0,
+                event_id: 0,
+                len: 0,
+                time_ns: 0,
+                data: [0; SAMPLE_SIZE],
+            }),
+        })
+        .collect(),
+    tail: AtomicU64::new(0),
+    head: AtomicU64::new(0),
+    dropped: AtomicU64::new(0),
+});
+
+impl HeadlessRing {
+    // Copy len bytes at src into the next free slot, returns false if the ring is full
+    // SAFETY: src must be valid for reads of len bytes, len <= SAMPLE_SIZE
+    unsafe fn push(&self, session: u32, event_id: u16, time_ns: u64, src: *const u8, len: u16) -> bool {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        let slot = loop {
+            let slot = &self.slots[(pos % RING_SIZE as u64) as usize];
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq == pos {
+                match self.tail.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => break slot,
+                    Err(p) => pos = p,
+                }
+            } else if seq < pos {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return false;
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        };
+        // @@@@ UNSAFE - The slot is reserved for this producer until its sequence number is released, src is valid for len bytes
+        unsafe {
+            let sample = &mut *slot.sample.get();
+            sample.session = session;
+            sample.event_id = event_id;
+            sample.len = len;
+            sample.time_ns = time_ns;
+            std::ptr::copy_nonoverlapping(src, sample.data.as_mut_ptr(), len as usize);
+        }
+        slot.seq.store(pos + 1, Ordering::Release);
+        true
+    }
+
+    // Take the next sample, None if the ring is empty
+    // Must only be called by the writer thread
+    fn pop(&self) -> Option<HeadlessSample> {
+        let pos = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[(pos % RING_SIZE as u64) as usize];
+        if slot.seq.load(Ordering::Acquire) != pos + 1 {
+            return None;
+        }
+        // @@@@ UNSAFE - The sample is released by its producer and owned by the consumer until the slot is freed
+        let sample = unsafe { *slot.sample.get() };
+        slot.seq.store(pos + RING_SIZE as u64, Ordering::Release);
+        self.head.store(pos + 1, Ordering::Relaxed);
+        Some(sample)
+    }
+}
+
+// Elapsed time since EPOCH in ns
+fn now_ns() -> u64 {
+    u64::try_from(EPOCH.elapsed().as_nanos()).unwrap_or(u64::MAX)
+}
+
+//-----------------------------------------------------------------------------
+// Writer thread
+
+// The recording of the headless logger, owned by the writer thread
+struct HeadlessLogger {
+    signals: Vec<HeadlessSignal>,
+    spans: HashMap<u16, i32>, // Offset of the logged memory span per event
+    writer: Box<dyn Write + Send>,
+    session: u32,
+    start_ns: u64,
+    samples: u64,
+}
+
+impl HeadlessLogger {
+    fn new(signals: Vec<HeadlessSignal>, spans: HashMap<u16, i32>, mut writer: Box<dyn Write + Send>, session: u32) -> std::io::Result<HeadlessLogger> {
+        let mut header = String::from("time");
+        for signal in &signals {
+            header.push(',');
+            header.push_str(&signal.name);
+        }
+        writeln!(writer, "{}", header)?;
+        Ok(HeadlessLogger {
+            signals,
+            spans,
+            writer,
+            session,
+            start_ns: now_ns(),
+            samples: 0,
+        })
+    }
+
+    // Write a line with the signals of the event of the sample
+    fn log(&mut self, sample: &HeadlessSample) -> std::io::Result<()> {
+        if sample.session != self.session {
+            return Ok(());
+        }
+        let Some(&span_offset) = self.spans.get(&sample.event_id) else {
+            return Ok(());
+        };
+        let data = &sample.data[..sample.len as usize];
+        let mut line = format!("{:.9}", sample.time_ns.saturating_sub(self.start_ns) as f64 / 1e9);
+        for signal in &self.signals {
+            line.push(',');
+            if signal.event_id == sample.event_id {
+                if let Some(bytes) = usize::try_from(signal.offset - span_offset).ok().and_then(|index| data.get(index..)) {
+                    line.push_str(&signal.format(bytes));
+                }
+            }
+        }
+        self.samples += 1;
+        writeln!(self.writer, "{}", line)
+    }
+
+    // Write the samples of the ring until logging stops, then write the remaining samples and flush
+    fn run(mut self) -> std::io::Result<u64> {
+        let _span = tracing::info_span!(target: "daq", "xcp_headless").entered();
+        let dropped = RING.dropped.load(Ordering::Relaxed);
+        let result = loop {
+            let active = LOGGER_ACTIVE.load(Ordering::Acquire);
+            let mut result = Ok(());
+            while let Some(sample) = RING.pop() {
+                result = self.log(&sample);
+                if result.is_err() {
+                    break;
+                }
+            }
+            if let Err(e) = result {
+                xcp_error!(Daq, "Headless logging stopped, write failed, {}", e);
+                LOGGER_ACTIVE.store(false, Ordering::Release);
+                break Err(e);
+            }
+            if !active {
+                break self.writer.flush();
+            }
+            std::thread::sleep(WRITE_CYCLE);
+        };
+        let dropped = RING.dropped.load(Ordering::Relaxed) - dropped;
+        if dropped > 0 {
+            xcp_warn!(Daq, "Headless logging: {} samples dropped, ring full", dropped);
+        }
+        result.map(|_| self.samples)
+    }
+}
+
+// Start logging to writer
+// Signals of events with a logged memory span larger than SAMPLE_SIZE are not logged
+fn start(mut signals: Vec<HeadlessSignal>, writer: Box<dyn Write + Send>) -> Result<usize, XcpClientError> {
+    let mut logger = LOGGER.lock();
+    if logger.is_some() {
+        return Err(XcpClientError::Event("headless logging already started".to_string()));
+    }
+
+    // Logged memory span of each event
+    let mut spans: HashMap<u16, (i32, i32)> = HashMap::new();
+    for signal in &signals {
+        let end = signal.offset + i32::try_from(signal.value_type.get_size()).unwrap_or_default();
+        let span = spans.entry(signal.event_id).or_insert((signal.offset, end));
+        *span = (span.0.min(signal.offset), span.1.max(end));
+    }
+    spans.retain(|event_id, (offset, end)| {
+        let ok = usize::try_from(*end - *offset).is_ok_and(|len| len <= SAMPLE_SIZE);
+        if !ok {
+            xcp_warn!(Daq, "Headless logging: signals of event id {} span more than {} bytes, not logged", event_id, SAMPLE_SIZE);
+        }
+        ok
+    });
+    signals.retain(|s| spans.contains_key(&s.event_id));
+    let count = signals.len();
+
+    let session = SESSION.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+    let recording = HeadlessLogger::new(signals, spans.iter().map(|(&id, &(offset, _))| (id, offset)).collect(), writer, session)?;
+    LazyLock::force(&RING);
+    LazyLock::force(&EPOCH);
+    for (&id, &(offset, end)) in &spans {
+        EVENT_SPAN_OFFSET[id as usize].store(offset, Ordering::Relaxed);
+        EVENT_SPAN_LEN[id as usize].store(u16::try_from(end - offset).unwrap_or_default(), Ordering::Relaxed);
+    }
+    SESSION.store(session, Ordering::Relaxed);
+    LOGGER_ACTIVE.store(true, Ordering::Release);
+    *logger = Some(std::thread::spawn(move || recording.run()));
+    Ok(count)
+}
+
+// Stop logging, write the remaining samples, flush and close the recording, returns the number of samples written
+fn stop() -> Result<u64, XcpClientError> {
+    LOGGER_ACTIVE.store(false, Ordering::Release);
+    let handle = {
+        let mut logger = LOGGER.lock();
+        let Some(handle) = logger.take() else {
+            return Ok(0);
+        };
+        EVENT_SPAN_LEN.iter().for_each(|len| len.store(0, Ordering::Relaxed));
+        handle
+    };
+    let samples = handle.join().map_err(|_| XcpClientError::Event("headless logging thread panicked".to_string()))??;
+    Ok(samples)
+}
+
+// Log a trigger of event_id, if headless logging is active
+// Lock-free, the logged memory span of the event is copied into the sample ring
+// SAFETY: base must be the trigger base pointer of event_id
+pub(crate) unsafe fn log_event(event_id: u16, base: *const u8) {
+    if !LOGGER_ACTIVE.load(Ordering::Acquire) {
+        return;
+    }
+    let Some(len) = EVENT_SPAN_LEN.get(event_id as usize).map(|len| len.load(Ordering::Relaxed)).filter(|len| *len > 0) else {
+        return;
+    };
+    let offset = EVENT_SPAN_OFFSET[event_id as usize].load(Ordering::Relaxed);
+    // @@@@ UNSAFE - Same contract as log_event, the span covers the memory of the registered signals of the event
+    unsafe { RING.push(SESSION.load(Ordering::Relaxed), event_id, now_ns(), base.wrapping_offset(offset as isize), len) };
+}
+
+// A client connected, it takes over and headless logging stops
+pub(crate) fn take_over() {
+    if LOGGER_ACTIVE.load(Ordering::Acquire) {
+        match stop() {
+            Ok(samples) => xcp_info!(Daq, "Headless logging stopped by client connect, {} samples", samples),
+            Err(e) => xcp_error!(Daq, "Headless logging stopped by client connect, {}", e),
+        }
+    }
+}
+
+impl Xcp {
+    /// Start headless logging of the auto start measurement configurations to a CSV file at path
+    /// Finalizes the registry and writes the A2L file, all measurements must be registered before
+    /// Logging runs until stop_headless_logging or until an XCP client connects and takes over
+    /// The triggers copy the logged memory of their event into a lock-free ring of 1024 samples, a thread writes the file
+    /// Samples are dropped on a full ring, an event with signals spanning more than 256 bytes is not logged
+    /// Returns the number of logged signals
+    pub fn start_headless_logging<P: AsRef<std::path::Path>>(&self, path: P) -> Result<usize, XcpClientError> {
+        self.finalize_registry()?;
+        let signals = auto_start_signals(registry::get());
+        if signals.is_empty() {
+            return Err(XcpClientError::Registry("no auto start measurement configuration with signals".to_string()));
+        }
+        let file = std::fs::File::create(path.as_ref())?;
+        let count = start(signals, Box::new(std::io::BufWriter::new(file)))?;
+        xcp_info!(Daq, "Headless logging of {} signals to {}", count, path.as_ref().display());
+        Ok(count)
+    }
+
+    /// Stop headless logging and close the CSV file, returns the number of samples written
+    pub fn stop_headless_logging(&self) -> Result<u64, XcpClientError> {
+        stop()
+    }
+
+    /// Check if headless logging is active
+    pub fn is_headless_logging(&self) -> bool {
+        LOGGER_ACTIVE.load(Ordering::Acquire)
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod headless_tests {
+    use super::*;
+    use crate::registry::{McAddress, McObjectType, McSupportData, McTypeDef};
+    use crate::xcp::xcp_test;
+
+    // A writer with a shared buffer
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_headless_logging() {
+        let xcp = xcp_test::test_setup();
+        let event1 = xcp.create_event("headless_event1");
+        let event2 = xcp.create_event("headless_event2");
+        {
+            let mut reg = registry::get_lock();
+            let reg = reg.as_mut().unwrap();
+            let mut typedef = McTypeDef::new("HeadlessPoint", 4);
+            typedef
+                .add_field("x", McDimType::new(McValueType::Sword, 1, 1), McSupportData::new(McObjectType::Measurement), 0)
+                .unwrap();
+            typedef
+                .add_field("y", McDimType::new(McValueType::Uword, 1, 1), McSupportData::new(McObjectType::Measurement), 2)
+                .unwrap();
+            reg.typedef_list.push(typedef);
+            let add = |reg: &mut Registry, name: &'static str, dim_type: McDimType, event_id: u16, offset: i32| {
+                reg.instance_list
+                    .add_instance(name, dim_type, McSupportData::new(McObjectType::Measurement), McAddress::new_event_dyn(0, event_id, offset))
+                    .unwrap();
+            };
+            add(reg, "headless_counter", McDimType::new(McValueType::Ulong, 1, 1), event1.get_id(), 0);
+            add(reg, "headless_array", McDimType::new(McValueType::Ubyte, 2, 1), event1.get_id(), 4);
+            add(reg, "headless_point", McDimType::new(McValueType::new_typedef("HeadlessPoint"), 1, 1), event2.get_id(), 0);
+            add(reg, "headless_other", McDimType::new(McValueType::Float64Ieee, 1, 1), event2.get_id(), 8);
+        }
+        xcp.add_measurement_config("headless_config", "", &["headless_counter", "headless_array", "headless_point"], true)
+            .unwrap();
+        xcp.finalize_registry().unwrap();
+
+        let signals = auto_start_signals(registry::get());
+        let names: Vec<&str> = signals.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names.len(), 5);
+        assert!(names[0].starts_with("headless_counter"));
+        assert!(names[1].starts_with("headless_array") && names[1].ends_with("[0]"));
+        assert!(names[4].starts_with("headless_point") && names[4].ends_with(".y"));
+
+        let buffer = SharedBuffer::default();
+        assert_eq!(start(signals, Box::new(buffer.clone())).unwrap(), 5);
+        assert!(xcp.is_headless_logging());
+        let sample1: [u8; 6] = [0x01, 0x02, 0x00, 0x00, 7, 8];
+        let sample2: [i16; 2] = [-5, 6];
+        unsafe {
+            event1.trigger_ext(sample1.as_ptr());
+            event2.trigger_ext(sample2.as_ptr().cast::<u8>());
+        }
+        take_over();
+        assert!(!xcp.is_headless_logging());
+        unsafe { event1.trigger_ext(sample1.as_ptr()) };
+        assert_eq!(stop().unwrap(), 0);
+
+        let text = String::from_utf8(buffer.0.lock().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("time,"));
+        assert!(lines[1].ends_with(",513,7,8,,"));
+        assert!(lines[2].ends_with(",,,,-5,6"));
+    }
+}
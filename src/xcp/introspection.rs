@@ -0,0 +1,180 @@
+//-----------------------------------------------------------------------------
+// Module introspection
+// Self-measurement of the XCP server with the internal event xcp_internal
+// The health signals are counted on the Rust side of the server: event triggers, DAQ events given to xcplib after decimation,
+// client connects, internal faults and memory usage, sampled cyclically by a thread and measured like application signals
+// The DAQ queue fill level, transmitted bytes, command counts and the CPU time of the transmit thread are internal to xcplib,
+// which has no API to read them
+// The per event counters in the trigger path are only incremented after the self-measurement has been started, a server without
+// introspection pays one relaxed load per trigger
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::{Xcp, XcpClientError, XcpEvent};
+use crate::registry::{self, McAddress, McDimType, McObjectType, McSupportData, McValueType};
+
+/// Name of the internal event of the self-measurement
+pub const XCP_INTERNAL_EVENT_NAME: &str = "xcp_internal";
+
+const MAX_EVENTS: usize = XcpEvent::XCP_MAX_EVENTS as usize;
+
+// Enables the counters in the trigger path, set when the internal event is registered
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Triggers and DAQ events per event id, counted per event to avoid a shared counter in the trigger path
+static EVENT_TRIGGERS: [AtomicU64; MAX_EVENTS] = [const { AtomicU64::new(0) }; MAX_EVENTS];
+static EVENT_DAQ: [AtomicU64; MAX_EVENTS] = [const { AtomicU64::new(0) }; MAX_EVENTS];
+
+static CONNECTS: AtomicU32 = AtomicU32::new(0);
+static ERRORS: AtomicU32 = AtomicU32::new(0);
+
+// Health signals measured with the internal event
+#[repr(C)]
+#[derive(Debug, Default)]
+struct XcpInternalSignals {
+    triggers: u64,       // Event triggers
+    daq_events: u64,     // Event triggers given to xcplib, after decimation
+    trigger_rate: u32,   // Event triggers per second
+    daq_rate: u32,       // DAQ events per second
+    connects: u32,       // Client connects
+    errors: u32,         // Internal faults reported on the error channel
+    registry_size: u32,  // Memory used by the registry in bytes
+    daq_queue_size: u32, // Size of the DAQ queue in bytes
+}
+
+// Enable the counters in the trigger path
+pub(crate) fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// Event id triggered, count after the undefined event check
+#[inline]
+pub(crate) fn count_trigger(id: u16) {
+    if ENABLED.load(Ordering::Relaxed) {
+        EVENT_TRIGGERS[id as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Event id given to xcplib, count after decimation
+#[inline]
+pub(crate) fn count_daq(id: u16) {
+    if ENABLED.load(Ordering::Relaxed) {
+        EVENT_DAQ[id as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// DAQ events of event id
+#[cfg(test)]
+pub(crate) fn get_daq_count(id: u16) -> u64 {
+    EVENT_DAQ[id as usize].load(Ordering::Relaxed)
+}
+
+pub(crate) fn count_connect() {
+    CONNECTS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn count_error() {
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+// Create the internal event and register the health signals as measurements "xcp_internal.<signal>"
+fn register() -> Result<XcpEvent, XcpClientError> {
+    let event = Xcp::get().try_create_event_ext(XCP_INTERNAL_EVENT_NAME, false)?;
+    let fields = [
+        ("triggers", std::mem::offset_of!(XcpInternalSignals, triggers), McValueType::Ulonglong, ""),
+        ("daq_events", std::mem::offset_of!(XcpInternalSignals, daq_events), McValueType::Ulonglong, ""),
+        ("trigger_rate", std::mem::offset_of!(XcpInternalSignals, trigger_rate), McValueType::Ulong, "1/s"),
+        ("daq_rate", std::mem::offset_of!(XcpInternalSignals, daq_rate), McValueType::Ulong, "1/s"),
+        ("connects", std::mem::offset_of!(XcpInternalSignals, connects), McValueType::Ulong, ""),
+        ("errors", std::mem::offset_of!(XcpInternalSignals, errors), McValueType::Ulong, ""),
+        ("registry_size", std::mem::offset_of!(XcpInternalSignals, registry_size), McValueType::Ulong, "bytes"),
+        ("daq_queue_size", std::mem::offset_of!(XcpInternalSignals, daq_queue_size), McValueType::Ulong, "bytes"),
+    ];
+    let mut reg = registry::get_lock();
+    let Some(reg) = reg.as_mut() else {
+        return Err(XcpClientError::Registry(format!("{} not registered, registry is finalized", XCP_INTERNAL_EVENT_NAME)));
+    };
+    enable();
+    for (field, offset, value_type, unit) in fields {
+        reg.instance_list
+            .add_instance(
+                format!("{}.{}", XCP_INTERNAL_EVENT_NAME, field),
+                McDimType::new(value_type, 1, 1),
+                McSupportData::new(McObjectType::Measurement).set_unit(unit),
+                McAddress::new_event_dyn(0, event.get_id(), i32::try_from(offset).unwrap_or_default()),
+            )
+            .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+    }
+    Ok(event)
+}
+
+// Sample the health signals, rates are calculated from the previous sample taken at last
+fn sample(signals: &mut XcpInternalSignals, last: Instant) {
+    let triggers: u64 = EVENT_TRIGGERS.iter().map(|c| c.load(Ordering::Relaxed)).sum();
+    let daq_events: u64 = EVENT_DAQ.iter().map(|c| c.load(Ordering::Relaxed)).sum();
+    let elapsed_us = u64::try_from(last.elapsed().as_micros()).unwrap_or(u64::MAX).max(1);
+    let rate = |n: u64, prev: u64| u32::try_from(n.saturating_sub(prev).saturating_mul(1_000_000) / elapsed_us).unwrap_or(u32::MAX);
+    signals.trigger_rate = rate(triggers, signals.triggers);
+    signals.daq_rate = rate(daq_events, signals.daq_events);
+    signals.triggers = triggers;
+    signals.daq_events = daq_events;
+    signals.connects = CONNECTS.load(Ordering::Relaxed);
+    signals.errors = ERRORS.load(Ordering::Relaxed);
+    let memory = Xcp::get().get_memory_usage();
+    signals.registry_size = u32::try_from(memory.registry).unwrap_or(u32::MAX);
+    signals.daq_queue_size = u32::try_from(memory.daq_queue).unwrap_or(u32::MAX);
+}
+
+// Register the internal event and sample and trigger it cyclically in a thread, as long as the XCP server is running
+pub(crate) fn spawn(cycle_time: Duration) -> Result<std::thread::JoinHandle<()>, XcpClientError> {
+    let event = register()?;
+    Ok(std::thread::spawn(move || {
+        let _span = tracing::info_span!(target: "daq", "xcp_internal").entered();
+        let mut signals = XcpInternalSignals::default();
+        let mut last = Instant::now();
+        while Xcp::get().check_server() {
+            std::thread::sleep(cycle_time);
+            sample(&mut signals, last);
+            last = Instant::now();
+            // @@@@ UNSAFE - the measurements of the internal event are registered relative to signals
+            unsafe { event.trigger_ext((&raw const signals).cast::<u8>()) }
+        }
+    }))
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod introspection_tests {
+
+    use super::*;
+    use crate::xcp::xcp_test;
+
+    #[test]
+    fn test_introspection() {
+        let xcp = xcp_test::test_setup();
+        let event = xcp.try_create_event_ext("test_introspection", false).unwrap();
+        let internal_event = register().unwrap();
+        assert!(register().is_err());
+
+        let mut signals = XcpInternalSignals::default();
+        let last = Instant::now();
+        sample(&mut signals, last);
+        let value = 0u8;
+        for _ in 0..10 {
+            // @@@@ UNSAFE - no measurements registered for this event
+            unsafe { event.trigger_ext(&raw const value) };
+        }
+        sample(&mut signals, last);
+        assert!(signals.triggers >= 10);
+        assert!(signals.daq_events >= 10);
+        assert!(signals.trigger_rate > 0);
+        assert!(signals.registry_size > 0);
+
+        // @@@@ UNSAFE - the measurements of the internal event are registered relative to signals
+        unsafe { internal_event.trigger_ext((&raw const signals).cast::<u8>()) };
+        xcp.finalize_registry().unwrap();
+    }
+}
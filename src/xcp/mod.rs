@@ -8,7 +8,7 @@ use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use std::sync::{
     Arc,
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicUsize, Ordering},
 };
 
 use crate::registry::{self, McAddress, McEvent};
@@ -18,16 +18,91 @@ use crate::registry::{self, McAddress, McEvent};
 
 // Submodule daq
 pub mod daq;
+pub use daq::DaqBuffer;
 pub use daq::DaqEvent;
+pub use daq::DaqQuantized;
+
+// Submodule daq_shm
+#[cfg(feature = "daq_shm")]
+mod daq_shm;
+#[cfg(feature = "daq_shm")]
+pub use daq_shm::{DAQ_SHM_SLOT_SIZE, DaqOverflowPolicy, DaqShmAggregator, DaqShmProducer};
 
 // Submodule cal
 mod cal;
 pub use cal::CalCell;
+pub use cal::CalPageInit;
 pub use cal::CalSeg;
 // Re-exports used by the cal_seg! macro (not part of the public API)
 #[cfg(feature = "linkme")]
 pub use cal::{CAL_SEG_REGISTRY, CalSegDescriptor};
 
+// Submodule cal_serde
+mod cal_serde;
+
+// Submodule cal_storage
+mod cal_storage;
+pub use cal_storage::{CalBinSignature, CalChangeSource, CalPageFormat, CalStorage, CalStorageBinFile, CalStorageJsonDir};
+
+// Submodule cal_sqlite
+#[cfg(feature = "cal_sqlite")]
+mod cal_sqlite;
+#[cfg(feature = "cal_sqlite")]
+pub use cal_sqlite::{CalChange, CalStorageSqlite};
+
+// Submodule xcp_async
+#[cfg(feature = "async")]
+mod xcp_async;
+#[cfg(feature = "async")]
+pub use xcp_async::{XcpAsync, XcpEventQueue};
+
+// Submodule cal_shm
+#[cfg(feature = "cal_shm")]
+mod cal_shm;
+#[cfg(feature = "cal_shm")]
+pub use cal_shm::{CalShmReader, CalShmWriter};
+
+// Submodule cal_profile
+#[cfg(feature = "lock_profiling")]
+mod cal_profile;
+#[cfg(feature = "lock_profiling")]
+pub use cal_profile::CalLockStats;
+
+// Submodule duplicates
+mod duplicates;
+pub use duplicates::XcpDuplicatePolicy;
+
+// Submodule event_deadline
+mod event_deadline;
+pub use event_deadline::XcpDeadlineStats;
+
+// Submodule event_on_change
+mod event_on_change;
+
+// Submodule introspection
+mod introspection;
+pub use introspection::XCP_INTERNAL_EVENT_NAME;
+
+// Submodule replay
+mod replay;
+pub use replay::XcpReplay;
+
+// Submodule headless
+mod headless;
+
+// Submodule plugin
+mod plugin;
+pub use plugin::XcpPlugin;
+
+// Submodule a2l_naming
+mod a2l_naming;
+pub use a2l_naming::XcpA2lNaming;
+
+// Submodule xcp_log
+mod xcp_log;
+pub use xcp_log::XcpLogSubsystem;
+use xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
 // Submodule xcplib ffi c bindings
 #[allow(unused)]
 pub mod xcplib;
@@ -60,10 +135,36 @@ pub enum XcpClientError {
     #[error("xcplib error: `{0}` ")]
     XcpLib(&'static str),
 
+    #[error("invalid log filter: `{0}` ")]
+    LogFilter(String),
+
+    #[error("transport error: `{0}` ")]
+    Transport(String),
+
+    #[error("DAQ queue overflow, {0} events lost")]
+    QueueOverflow(u64),
+
+    #[error("event error: `{0}` ")]
+    Event(String),
+
+    #[error("calibration segment error: `{0}` ")]
+    CalSeg(String),
+
+    #[error("registry error: `{0}` ")]
+    Registry(String),
+
+    #[error("memory limit of {0} bytes exceeded")]
+    MemoryLimit(usize),
+
     #[error("unknown error")]
     Unknown,
 }
 
+// Convert to a C string, interior null characters are removed
+pub(crate) fn c_string(s: &str) -> std::ffi::CString {
+    std::ffi::CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
 //----------------------------------------------------------------------------------------------
 // XcpEvent
 
@@ -73,6 +174,14 @@ pub enum XcpClientError {
 // The remapping cell is initialized when the registry is finalized and the A2L is written
 // static XCP_EVENT_MAP: OnceCell<[u16; XcpEvent::XCP_MAX_EVENTS]> = OnceCell::new();
 
+// Event prescaler and trigger counter per event id, an event is measured every prescaler-th trigger, the counter resets at the prescaler
+// Set at runtime without DAQ restart to reduce the DAQ rate of an event, see Xcp::set_event_prescaler
+static EVENT_PRESCALER: [AtomicU16; XcpEvent::XCP_MAX_EVENTS as usize] = [const { AtomicU16::new(1) }; XcpEvent::XCP_MAX_EVENTS as usize];
+static EVENT_TRIGGER_COUNTER: [AtomicU16; XcpEvent::XCP_MAX_EVENTS as usize] = [const { AtomicU16::new(0) }; XcpEvent::XCP_MAX_EVENTS as usize];
+
+// Default measurement rate of events as cycle time in ns, 0 is the target cycle time, see Xcp::set_event_default_rate
+static EVENT_DEFAULT_CYCLE_TIME: [AtomicU32; XcpEvent::XCP_MAX_EVENTS as usize] = [const { AtomicU32::new(0) }; XcpEvent::XCP_MAX_EVENTS as usize];
+
 /// Represents a measurement event  
 /// Holds the raw u16 event number used in the XCP protocol and in A2L IF_DATA to identify an event
 /// May have an index > 0 to express multiple events with the same name are instantiated in different thread local instances
@@ -97,14 +206,17 @@ impl XcpEvent {
     };
 
     /// Create a new XCP event
+    /// Returns XCP_UNDEFINED_EVENT, if id exceeds the maximum number of events
     pub fn new(id: u16, index: u16) -> XcpEvent {
-        assert!(id < XcpEvent::XCP_MAX_EVENTS, "Maximum number of events exceeded");
+        if id >= XcpEvent::XCP_MAX_EVENTS {
+            return XcpEvent::XCP_UNDEFINED_EVENT;
+        }
         XcpEvent { id, index }
     }
 
-    /// Get the event name
+    /// Get the event name, empty if the event does not exist
     pub fn get_name(self) -> &'static str {
-        XCP.event_list.lock().get_name(self).unwrap()
+        XCP.event_list.lock().get_name(self).unwrap_or("")
     }
 
     /// Get the event id as u16
@@ -127,9 +239,97 @@ impl XcpEvent {
     /// The provenance of the pointer (len, lifetime) is is guaranteed , it refers to self
     /// The buffer must match its registry description, to avoid corrupt data given to the XCP tool
     pub unsafe fn trigger_ext(self, base: *const u8) {
+        // @@@@ UNSAFE - Same contract as trigger_ext
+        unsafe { self.trigger_ext_captured(base, None) }
+    }
+
+    // Trigger with the captured bytes of a capture buffer, in on change mode, the trigger is skipped if they did not change
+    // Without captured bytes, the trigger is always measured
+    pub(crate) unsafe fn trigger_ext_captured(self, base: *const u8, captured: Option<&[u8]>) {
+        if self.id == XcpEvent::XCP_UNDEFINED_EVENT_ID {
+            return;
+        }
+        introspection::count_trigger(self.id);
+        // Deadline monitoring
+        event_deadline::check(self.id);
+        // Decimation, the trigger counter runs from 0 to prescaler-1, the trigger is measured at 0
+        let prescaler = EVENT_PRESCALER[self.id as usize].load(Ordering::Relaxed);
+        if prescaler > 1 {
+            let count = match EVENT_TRIGGER_COUNTER[self.id as usize]
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| Some(if c.saturating_add(1) >= prescaler { 0 } else { c + 1 }))
+            {
+                Ok(c) | Err(c) => c,
+            };
+            if count != 0 {
+                return;
+            }
+        }
+        // Value change mode
+        if captured.is_some_and(|captured| !event_on_change::is_due(self.id, captured)) {
+            return;
+        }
+        introspection::count_daq(self.id);
+        // Headless logging without a connected client
+        // @@@@ UNSAFE - Same contract as trigger_ext
+        unsafe { headless::log_event(self.id, base) };
         // @@@@ UNSAFE - C library call and transferring a pointer and its valid memory range to XCPlite FFI
         unsafe { xcplib::XcpEventExt(self.get_id(), base.wrapping_sub(McAddress::XCP_ADDR_EXT_DYN_OFFSET_OFFSET as usize)) }
     }
+
+    /// Register a measurement object of a type T which can not implement McRegisterType, e.g. a type from a third-party crate
+    /// The fields are described by field descriptors with name, offset, type and metadata, they become the typedef type_name
+    /// The instance instance_name is relative to the base pointer given to trigger_ext, offsets and sizes are checked against the size of T
+    pub fn register_fields<T>(self, instance_name: &'static str, type_name: &'static str, fields: Vec<registry::McFieldDesc>) -> Result<(), XcpClientError> {
+        XCP.check_memory(
+            std::mem::size_of::<registry::McInstance>() + std::mem::size_of::<registry::McTypeDef>() + fields.len() * std::mem::size_of::<registry::McTypeDefField>(),
+        )?;
+        let mut reg = registry::get_lock();
+        let Some(reg) = reg.as_mut() else {
+            return Err(XcpClientError::Registry(format!("{} not registered, registry already closed", instance_name)));
+        };
+        reg.add_typedef_from_fields(type_name, std::mem::size_of::<T>(), fields, registry::McObjectType::Measurement)
+            .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+        reg.instance_list
+            .add_instance(
+                instance_name,
+                registry::McDimType::new(registry::McValueType::new_typedef(type_name), 1, 1),
+                registry::McSupportData::new(registry::McObjectType::Measurement),
+                McAddress::new_event_dyn(0, self.id, 0),
+            )
+            .map_err(|e| XcpClientError::Registry(e.to_string()))
+    }
+
+    /// Register a struct type T of #[derive(McRegisterType)] and an instance instance_name of it, measured with trigger_struct
+    /// Nested structs become typedefs, arrays of structs become typedef arrays, the field offsets are those of the Rust layout
+    /// The instance is relative to the struct given to trigger_struct, it may be located anywhere, e.g. on heap or in a static
+    pub fn register_struct<T: registry::McRegisterType>(self, instance_name: &'static str) -> Result<(), XcpClientError> {
+        XCP.check_memory(std::mem::size_of::<registry::McInstance>())?;
+        {
+            let reg = registry::get_lock();
+            let Some(reg) = reg.as_ref() else {
+                return Err(XcpClientError::Registry(format!("{} not registered, registry already closed", instance_name)));
+            };
+            if reg.instance_list.get_instance(instance_name, registry::McObjectType::Measurement, Some(self.id)).is_some() {
+                return Err(XcpClientError::Registry(format!("{} not registered, duplicate instance", instance_name)));
+            }
+        }
+        T::register(&registry::McRegisterContext {
+            target: registry::McRegisterTarget::Event(self.id),
+            instance_name: Some(instance_name),
+            name_prefix: String::new(),
+            addr_offset: 0,
+            level: 0,
+        });
+        Ok(())
+    }
+
+    /// Trigger the event with a struct instance registered with register_struct
+    /// T must be plain old data without padding bytes, the XCP server reads the memory of the struct as bytes
+    pub fn trigger_struct<T: registry::McRegisterType + registry::MeasureSafe>(self, instance: &T) {
+        const { assert!(T::NO_PADDING, "MeasureSafe: the measured type has padding bytes, add explicit padding fields") };
+        // @@@@ UNSAFE - the measurements of the struct are registered relative to instance and are within size_of::<T>()
+        unsafe { self.trigger_ext((instance as *const T).cast::<u8>()) }
+    }
 }
 
 impl PartialEq for XcpEvent {
@@ -144,28 +344,78 @@ impl Default for XcpEvent {
     }
 }
 
+//----------------------------------------------------------------------------------------------
+// XcpEventIdAllocation
+
+/// Allocation of event id numbers for events created without explicit id
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum XcpEventIdAllocation {
+    /// Lowest free id, ids of removed events are reused immediately
+    #[default]
+    Recycle,
+    /// Increasing ids, ids of removed events are reused only after the id space is exhausted
+    Monotonic,
+}
+
 //----------------------------------------------------------------------------------------------
 // EventList
 
 struct XcpEventInfo {
     name: &'static str,
     event: XcpEvent,
+    location: &'static std::panic::Location<'static>, // Source location of the creation, for duplicate diagnostics
 }
 
-struct EventList(Vec<XcpEventInfo>);
+struct EventList {
+    events: Vec<XcpEventInfo>,
+    allocation: XcpEventIdAllocation,
+    reserved: Vec<std::ops::Range<u16>>, // Id ranges excluded from automatic allocation
+    next_id: u16,                        // Next id of monotonic allocation
+}
 
 impl EventList {
     fn new() -> EventList {
-        EventList(Vec::new())
+        EventList {
+            events: Vec::new(),
+            allocation: XcpEventIdAllocation::default(),
+            reserved: Vec::new(),
+            next_id: 0,
+        }
     }
 
     #[allow(dead_code)]
     fn clear(&mut self) {
-        self.0.clear();
+        *self = EventList::new();
+    }
+
+    // Allocate an event id, which is not used and not reserved
+    fn allocate_id(&mut self) -> Option<u16> {
+        let free = |id: &u16| !self.events.iter().any(|e| e.event.id == *id) && !self.reserved.iter().any(|r| r.contains(id));
+        let id = match self.allocation {
+            XcpEventIdAllocation::Recycle => (0..XcpEvent::XCP_MAX_EVENTS).find(free),
+            XcpEventIdAllocation::Monotonic => (self.next_id..XcpEvent::XCP_MAX_EVENTS).find(free).or_else(|| (0..self.next_id).find(free)),
+        }?;
+        self.next_id = id + 1;
+        Some(id)
+    }
+
+    // Exclude an id range from automatic allocation
+    fn reserve(&mut self, ids: std::ops::Range<u16>) -> Result<(), XcpClientError> {
+        if ids.is_empty() || ids.end > XcpEvent::XCP_MAX_EVENTS {
+            return Err(XcpClientError::Event(format!("invalid event id range {:?}, maximum is {}", ids, XcpEvent::XCP_MAX_EVENTS)));
+        }
+        if let Some(e) = self.events.iter().find(|e| ids.contains(&e.event.id)) {
+            return Err(XcpClientError::Event(format!(
+                "event id range {:?} not reserved, id {} is used by event {}",
+                ids, e.event.id, e.name
+            )));
+        }
+        self.reserved.push(ids);
+        Ok(())
     }
 
     fn get_name(&self, event: XcpEvent) -> Option<&'static str> {
-        for e in &self.0 {
+        for e in &self.events {
             if e.event == event {
                 return Some(e.name);
             }
@@ -174,57 +424,85 @@ impl EventList {
     }
 
     fn sort_by_name_and_index(&mut self) {
-        self.0.sort_by(|a, b| if a.name == b.name { a.event.index.cmp(&b.event.index) } else { a.name.cmp(b.name) });
+        self.events
+            .sort_by(|a, b| if a.name == b.name { a.event.index.cmp(&b.event.index) } else { a.name.cmp(b.name) });
     }
 
     // Register all events in the list and create the event id transformation map
-    fn register(&mut self) {
+    fn register(&mut self) -> Result<(), XcpClientError> {
         // Sort the event list by name and then instance index
         // This gives a deterministic order of instance event numbers, not deterministic for normal events
         self.sort_by_name_and_index();
 
         // Register all events
-        {
-            let mut l = registry::get_lock();
-            let r = l.as_mut().unwrap();
-            self.0.iter().for_each(|e| {
-                let _ = r.event_list.add_event(McEvent::new(e.name, e.event.index, e.event.id, 0));
-                // @@@@ TODO Error handling needed
-            });
+        let mut l = registry::get_lock();
+        let Some(r) = l.as_mut() else {
+            return Err(XcpClientError::Registry("events not registered, registry already closed".to_string()));
+        };
+        for e in &self.events {
+            let mut event = McEvent::new(e.name, e.event.index, e.event.id, event_deadline::get_cycle_time_ns(e.event.id));
+            event.on_change_max_interval_ns = event_on_change::get_max_interval_ns(e.event.id);
+            event.default_cycle_time_ns = EVENT_DEFAULT_CYCLE_TIME[e.event.id as usize].load(Ordering::Relaxed);
+            if let Err(err) = r.event_list.add_event(event) {
+                xcp_error!(Registry, "Event {} not registered: {}", e.name, err);
+            }
         }
+        Ok(())
     }
 
-    fn create_event_ext(&mut self, name: &'static str, indexed: bool) -> XcpEvent {
-        // Allocate a new, sequential event id number
-        let id: u16 = u16::try_from(self.0.len()).expect("Event id exceeds u16::MAX");
-        if id >= XcpEvent::XCP_MAX_EVENTS {
-            log::error!("Maximum number of events exceeded");
-            return XcpEvent::XCP_UNDEFINED_EVENT;
-        }
+    // Create an event with an explicit id or an allocated id
+    fn create_event_ext(&mut self, name: &'static str, indexed: bool, id: Option<u16>, location: &'static std::panic::Location<'static>) -> Result<XcpEvent, XcpClientError> {
+        let id = match id {
+            // Explicit id, may be in a reserved range, collisions are errors
+            Some(id) => {
+                if id >= XcpEvent::XCP_MAX_EVENTS {
+                    return Err(XcpClientError::Event(format!(
+                        "event {} not created, id {} exceeds the maximum {}",
+                        name,
+                        id,
+                        XcpEvent::XCP_MAX_EVENTS - 1
+                    )));
+                }
+                if let Some(e) = self.events.iter().find(|e| e.event.id == id) {
+                    return Err(XcpClientError::Event(format!("event {} not created, id {} is already used by event {}", name, id, e.name)));
+                }
+                id
+            }
+            None => self
+                .allocate_id()
+                .ok_or_else(|| XcpClientError::Event(format!("event {} not created, maximum number of events exceeded", name)))?,
+        };
 
-        // In instance mode, check for other events in instance mode with duplicate name and create new instance index
+        // In instance mode, check for other events in instance mode with duplicate name and create the lowest free instance index
         // otherwise check for unique event name
         let index: u16 = if indexed {
-            (self.0.iter().filter(|e| e.name == name && e.event.get_index() > 0).count() + 1).try_into().unwrap()
+            (1..=XcpEvent::XCP_MAX_EVENT_INSTS + 1)
+                .find(|index| !self.events.iter().any(|e| e.name == name && e.event.index == *index))
+                .unwrap_or(u16::MAX)
         } else {
-            if self.0.iter().filter(|e| e.name == name).count() > 0 {
-                log::error!("Event name {} already exists", name);
-                return XcpEvent::XCP_UNDEFINED_EVENT;
+            if let Some(first) = self.events.iter().find(|e| e.name == name) {
+                let new_name = duplicates::resolve_event_name(name, first.location, |n| self.events.iter().any(|e| e.name == n))?;
+                return self.create_event_ext(new_name, false, Some(id), location);
             }
             0
         };
         if index > XcpEvent::XCP_MAX_EVENT_INSTS {
-            log::error!("Maximum number of event thread local instances exceeded");
-            return XcpEvent::XCP_UNDEFINED_EVENT;
+            return Err(XcpClientError::Event(format!("event {} not created, maximum number of event instances exceeded", name)));
         }
 
         // Create XcpEvent
         let event = XcpEvent::new(id, index);
-        log::debug!("Create event {} id={}, index={}", name, event.get_id(), event.get_index());
+        xcp_debug!(Daq, "Create event {} id={}, index={}", name, event.get_id(), event.get_index());
 
         // Add XcpEventInfo to event list
-        self.0.push(XcpEventInfo { name, event });
-        event
+        self.events.push(XcpEventInfo { name, event, location });
+        Ok(event)
+    }
+
+    // Remove an event from the list, its id and instance index are free for reuse
+    fn remove(&mut self, event: XcpEvent) -> Option<&'static str> {
+        let index = self.events.iter().position(|e| e.event.id == event.id)?;
+        Some(self.events.remove(index).name)
     }
 }
 
@@ -250,6 +528,56 @@ impl XcpTransportLayer {
     }
 }
 
+//------------------------------------------------------------------------------------------
+// XcpClockEpoch
+
+/// Name of the internal calibration segment, which publishes the DAQ clock epoch of the server to the XCP client
+pub const XCP_CLOCK_EPOCH_NAME: &str = "xcp_clock_epoch";
+
+/// Mapping of the XCP DAQ clock to UTC wall clock time
+/// Recorded when the XCP server is started, the DAQ clock epoch is arbitrary (OPTION_CLOCK_EPOCH_ARB in xcplib_rust_cfg.h)
+/// The epoch of the first server start is published in the calibration segment XCP_CLOCK_EPOCH_NAME, a client reads it with SHORT_UPLOAD
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, registry::McRegisterType)]
+pub struct XcpClockEpoch {
+    /// UTC time in ns since 1.1.1970
+    pub utc_ns: u64,
+    /// DAQ clock value in ns at the same time
+    pub clock_ns: u64,
+}
+
+impl XcpClockEpoch {
+    /// Convert a DAQ clock value (timestamp in ns) to UTC in ns since 1.1.1970
+    pub fn clock_to_utc(&self, clock_ns: u64) -> u64 {
+        self.utc_ns.wrapping_add(clock_ns.wrapping_sub(self.clock_ns))
+    }
+
+    /// Convert a DAQ clock value (timestamp in ns) to SystemTime
+    pub fn clock_to_system_time(&self, clock_ns: u64) -> std::time::SystemTime {
+        std::time::UNIX_EPOCH + std::time::Duration::from_nanos(self.clock_to_utc(clock_ns))
+    }
+}
+
+//------------------------------------------------------------------------------------------
+// XcpMemoryUsage
+
+/// Memory used by the XCP server in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct XcpMemoryUsage {
+    /// DAQ transmit queue, allocated when the server is started
+    pub daq_queue: usize,
+    /// Estimated size of the registry (events, calibration segments, measurements, typedefs)
+    pub registry: usize,
+    /// Memory limit, 0 if unlimited
+    pub limit: usize,
+}
+
+impl XcpMemoryUsage {
+    /// Total memory used
+    pub fn total(&self) -> usize {
+        self.daq_queue + self.registry
+    }
+}
+
 //------------------------------------------------------------------------------------------
 // Xcp singleton
 
@@ -257,7 +585,17 @@ impl XcpTransportLayer {
 /// The Xcp singleton is obtained with Xcp::get()
 pub struct Xcp {
     registry_finalized: AtomicBool,
+    server_running: AtomicBool,
     event_list: Arc<Mutex<EventList>>,
+    clock_epoch: Mutex<Option<XcpClockEpoch>>,
+    error_sender: Mutex<Option<std::sync::mpsc::Sender<XcpClientError>>>,
+    memory_limit: AtomicUsize,
+    daq_queue_size: AtomicUsize,
+    a2l_naming: Mutex<XcpA2lNaming>,
+    cal_storage: Mutex<Option<Arc<dyn CalStorage>>>,
+    cal_page_init: Mutex<CalPageInit>,
+    plugins: Mutex<plugin::XcpPluginList>,
+    a2l_revision: AtomicU32,
 }
 
 lazy_static! {
@@ -269,32 +607,55 @@ impl Xcp {
         // Create the Xcp singleton
         Xcp {
             registry_finalized: AtomicBool::new(false),
+            server_running: AtomicBool::new(false),
             event_list: Arc::new(Mutex::new(EventList::new())),
+            clock_epoch: Mutex::new(None),
+            error_sender: Mutex::new(None),
+            memory_limit: AtomicUsize::new(0),
+            daq_queue_size: AtomicUsize::new(0),
+            a2l_naming: Mutex::new(XcpA2lNaming::AppName),
+            cal_storage: Mutex::new(None),
+            cal_page_init: Mutex::new(CalPageInit::Default),
+            plugins: Mutex::new(Vec::new()),
+            a2l_revision: AtomicU32::new(0),
         }
     }
 
     // Initialization of the Xcp singleton
     pub fn init(app_name: &str, app_revision: &str, log_level: u8) -> &'static Xcp {
+        // All subsystems start with the same log level
+        for subsystem in XcpLogSubsystem::ALL {
+            subsystem.set_level(log_level);
+        }
+
+        // The EPK version string must fit into the EPK segment, including the terminating null
+        let app_revision = if app_revision.len() >= crate::EPK_SEG_SIZE {
+            let mut len = crate::EPK_SEG_SIZE - 1;
+            while !app_revision.is_char_boundary(len) {
+                len -= 1;
+            }
+            XCP.report_error(XcpClientError::Registry(format!("app_revision truncated to {} bytes", len)));
+            &app_revision[..len]
+        } else {
+            app_revision
+        };
+
         // Initialize the XCP library
         // @@@@ UNSAFE - C library calls
         unsafe {
             xcplib::XcpSetLogLevel(log_level);
-            assert!(app_revision.len() < crate::EPK_SEG_SIZE);
-            let epk = std::ffi::CString::new(app_revision).unwrap();
-            assert!(app_revision.len() < crate::EPK_SEG_SIZE);
-            let name = std::ffi::CString::new(app_name).unwrap();
+            let epk = c_string(app_revision);
+            let name = c_string(app_name);
             xcplib::XcpInit(name.as_ptr(), epk.as_ptr(), 1); // @@@@ TODO XCP_MODE_LOCAL
             xcplib::ApplXcpRegisterConnectCallback(Some(cb_connect));
         }
 
         // Initialize the registry
         registry::init();
-        registry::get_lock().as_mut().unwrap().application.set_info(app_name.to_string(), "xcp-lite", 0);
-        registry::get_lock()
-            .as_mut()
-            .unwrap()
-            .application
-            .set_version(app_revision.to_string(), crate::EPK_SEG_ADDR);
+        if let Some(reg) = registry::get_lock().as_mut() {
+            reg.application.set_info(app_name.to_string(), "xcp-lite", 0);
+            reg.application.set_version(app_revision.to_string(), crate::EPK_SEG_ADDR);
+        }
 
         &XCP
     }
@@ -308,17 +669,359 @@ impl Xcp {
 
     /// Set registry mode (flat or with typedefs, prefix names with app name)
     pub fn set_registry_mode(&self, flatten_typedefs: bool, prefix_names: bool) -> &'static Xcp {
-        registry::get_lock().as_mut().unwrap().set_flatten_typedefs_mode(flatten_typedefs);
-        registry::get_lock().as_mut().unwrap().set_prefix_names_mode(prefix_names);
+        if let Some(reg) = registry::get_lock().as_mut() {
+            reg.set_flatten_typedefs_mode(flatten_typedefs);
+            reg.set_prefix_names_mode(prefix_names);
+        } else {
+            xcp_error!(Registry, "Registry mode not set, registry already closed");
+        }
+        &XCP
+    }
+
+    /// Set a system constant, written to the A2L MOD_PAR as SYSTEM_CONSTANT, next to the EPK and its address
+    /// Must be set before the registry is finalized
+    pub fn set_system_constant(&self, name: &str, value: &str) -> &'static Xcp {
+        if let Some(reg) = registry::get_lock().as_mut() {
+            reg.application.set_system_constant(name.to_string(), value.to_string());
+        } else {
+            xcp_error!(Registry, "System constant {} not set, registry already closed", name);
+        }
+        &XCP
+    }
+
+    /// Set the naming strategy of the A2L file, default is <app_name>.a2l
+    /// Must be set before the registry is finalized
+    pub fn set_a2l_naming(&self, naming: XcpA2lNaming) -> &'static Xcp {
+        if self.registry_finalized.load(Ordering::Relaxed) {
+            xcp_error!(Registry, "A2L naming not set, registry already finalized");
+        } else {
+            *self.a2l_naming.lock() = naming;
+        }
+        &XCP
+    }
+
+    /// Get the A2L name without extension, as reported to the XCP client tool by GET_ID IDT_ASAM_NAME
+    /// After the A2L has been rewritten for loaded plugins, the name has the suffix _<revision> (see Xcp::get_a2l_revision)
+    pub fn get_a2l_name(&self) -> String {
+        let app_name = if registry::is_closed() {
+            registry::get().application.get_name()
+        } else {
+            registry::get_lock().as_ref().map_or("", |reg| reg.application.get_name())
+        };
+        plugin::a2l_revision_name(&self.a2l_naming.lock().a2l_name(app_name), self.a2l_revision.load(Ordering::Relaxed))
+    }
+
+    /// Set log levels per subsystem, e.g. "daq=trace,cmd=info" or "warn,daq=debug"
+    /// Subsystems are cmd, daq, transport, registry and cal, levels are off, error, warn, info, debug, trace or 0..5
+    /// A level without subsystem applies to all subsystems not listed, the others keep their current level
+    /// The XCPlite C library has one log level for command processing and transport layer, it is set to the higher of both
+    pub fn set_log_filter(&self, filter: &str) -> Result<&'static Xcp, XcpClientError> {
+        let levels = xcp_log::parse_log_filter(filter).map_err(XcpClientError::LogFilter)?;
+        xcp_log::set_log_levels(&levels);
+        let c_log_level = XcpLogSubsystem::Cmd.get_level().max(XcpLogSubsystem::Transport.get_level());
+        // @@@@ UNSAFE - C library call
+        unsafe {
+            xcplib::XcpSetLogLevel(c_log_level);
+        }
+        Ok(&XCP)
+    }
+
+    //------------------------------------------------------------------------------------------
+    // Calibration storage
+
+    /// Set the persistence backend for calibration pages, used by CalSeg::freeze and CalSeg::load_frozen
+    /// e.g. CalStorageBinFile, CalStorageJsonDir or an application specific implementation of CalStorage
+    pub fn set_cal_storage<S: CalStorage + 'static>(&self, storage: S) -> &'static Xcp {
+        *self.cal_storage.lock() = Some(Arc::new(storage));
+        &XCP
+    }
+
+    // Get the persistence backend for calibration pages
+    pub(crate) fn get_cal_storage(&self) -> Option<Arc<dyn CalStorage>> {
+        self.cal_storage.lock().clone()
+    }
+
+    /// Set the initialization policy of the working page for calibration segments created with CalSeg::new, CalSeg::try_new and cal_seg!
+    /// With CalPageInit::Storage, pages frozen by the application or the XCP tool are loaded on the next start without application code
+    pub fn set_cal_page_init(&self, init: CalPageInit) -> &'static Xcp {
+        *self.cal_page_init.lock() = init;
+        &XCP
+    }
+
+    // Get the initialization policy of the working page
+    pub(crate) fn get_cal_page_init(&self) -> CalPageInit {
+        *self.cal_page_init.lock()
+    }
+
+    //------------------------------------------------------------------------------------------
+    // Calibration patch
+
+    /// Apply a binary calibration patch (registry::McCalPatch) from the application to the working pages, e.g. a patch file
+    /// Over XCP, a client transfers a patch with DOWNLOAD inside an atomic calibration action (USER_CMD modify begin/end), see apply_cal_patch of tools/xcp_client
+    /// All records are checked before anything is written, the affected segments stay locked until all records are applied
+    /// Returns the number of records
+    pub fn apply_cal_patch(&self, patch: &[u8]) -> Result<usize, XcpClientError> {
+        let patch = registry::McCalPatch::from_bytes(patch).map_err(XcpClientError::CalSeg)?;
+
+        // @@@@ UNSAFE - C library call
+        let count = unsafe { xcplib::XcpGetCalSegCount() };
+        for r in patch.get_records() {
+            // @@@@ UNSAFE - C library call, the index is checked
+            let size = if r.segment < count {
+                unsafe { xcplib::XcpGetCalSegSize(r.segment) as usize }
+            } else {
+                0
+            };
+            if r.offset as usize + r.data.len() > size {
+                return Err(XcpClientError::CalSeg(format!(
+                    "Calibration patch segment {} offset {} size {} out of range",
+                    r.segment,
+                    r.offset,
+                    r.data.len()
+                )));
+            }
+        }
+
+        // Lock all affected segments, in ascending order
+        let mut segments: Vec<u16> = patch.get_records().iter().map(|r| r.segment).collect();
+        segments.sort_unstable();
+        segments.dedup();
+        // @@@@ UNSAFE - C library call
+        let pages: Vec<(u16, *mut u8)> = segments.iter().map(|s| (*s, unsafe { xcplib::XcpLockCalSeg(*s) as *mut u8 })).collect();
+        let locked = pages.iter().all(|(_, p)| !p.is_null());
+        if locked {
+            for r in patch.get_records() {
+                if let Some((_, page)) = pages.iter().find(|(s, _)| *s == r.segment) {
+                    // @@@@ UNSAFE - Write to the locked working page, the range is checked against the segment size
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(r.data.as_ptr(), page.add(r.offset as usize), r.data.len());
+                    }
+                }
+            }
+        }
+        for (s, _) in &pages {
+            // @@@@ UNSAFE - C library call
+            unsafe {
+                xcplib::XcpUnlockCalSeg(*s);
+            }
+        }
+        if !locked {
+            return Err(XcpClientError::CalSeg("Calibration patch rejected, a calibration segment could not be locked".to_string()));
+        }
+        xcp_info!(Cal, "Calibration patch with {} records applied", patch.len());
+        Ok(patch.len())
+    }
+
+    //------------------------------------------------------------------------------------------
+    // Memory budget
+
+    /// Limit the memory used by the XCP server (DAQ transmit queue and registry) in bytes, 0 means unlimited
+    /// Starting the server, creating events and registering measurements or calibration segments fail with
+    /// XcpClientError::MemoryLimit, if they would exceed the limit
+    pub fn set_memory_limit(&self, bytes: usize) -> &'static Xcp {
+        self.memory_limit.store(bytes, Ordering::Relaxed);
+        let usage = self.get_memory_usage();
+        if bytes > 0 && usage.total() > bytes {
+            xcp_warn!(Registry, "Memory limit {} bytes already exceeded, {} bytes used", bytes, usage.total());
+        }
+        &XCP
+    }
+
+    /// Get the memory usage of the XCP server
+    pub fn get_memory_usage(&self) -> XcpMemoryUsage {
+        let registry = if registry::is_closed() {
+            registry::get().get_memory_usage()
+        } else {
+            registry::get_lock().as_ref().map_or(0, registry::Registry::get_memory_usage)
+        };
+        XcpMemoryUsage {
+            daq_queue: self.daq_queue_size.load(Ordering::Relaxed),
+            registry,
+            limit: self.memory_limit.load(Ordering::Relaxed),
+        }
+    }
+
+    // Check if additional memory of size bytes is within the memory limit
+    // Must not be called while holding the registry lock
+    pub(crate) fn check_memory(&self, size: usize) -> Result<(), XcpClientError> {
+        let limit = self.memory_limit.load(Ordering::Relaxed);
+        if limit > 0 && self.get_memory_usage().total() + size > limit {
+            return Err(XcpClientError::MemoryLimit(limit));
+        }
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------------------------
+    // Event prescaler
+
+    /// Set the prescaler of an event at runtime, without restarting DAQ
+    /// The event is measured only every prescaler-th trigger, 1 measures every trigger
+    /// Used by a measurement tool to back off a too fast event when DAQ data is lost
+    pub fn set_event_prescaler(&self, event: XcpEvent, prescaler: u16) -> Result<(), XcpClientError> {
+        if event.get_id() >= XcpEvent::XCP_MAX_EVENTS || prescaler == 0 {
+            return Err(XcpClientError::Event(format!("invalid prescaler {} for event id {}", prescaler, event.get_id())));
+        }
+        let old = EVENT_PRESCALER[event.get_id() as usize].swap(prescaler, Ordering::Relaxed);
+        EVENT_TRIGGER_COUNTER[event.get_id() as usize].store(0, Ordering::Relaxed);
+        if old != prescaler {
+            xcp_info!(Daq, "Event {} prescaler {} -> {}", event.get_id(), old, prescaler);
+        }
+        Ok(())
+    }
+
+    /// Get the prescaler of an event, 1 if the event is not decimated
+    pub fn get_event_prescaler(&self, event: XcpEvent) -> u16 {
+        EVENT_PRESCALER.get(event.get_id() as usize).map_or(1, |p| p.load(Ordering::Relaxed))
+    }
+
+    //------------------------------------------------------------------------------------------
+    // Event value change mode
+
+    /// Measure an event with a capture buffer (DaqEvent) only when the captured values changed, to save bandwidth for slow changing signals
+    /// A trigger with unchanged values is measured anyway after max_interval (heartbeat), None measures every trigger again
+    /// Triggers with an external base address (trigger_ext) are always measured
+    /// The mode is annotated in the A2L file, if set before the registry is finalized
+    pub fn set_event_on_change(&self, event: XcpEvent, max_interval: Option<std::time::Duration>) -> Result<(), XcpClientError> {
+        event_on_change::set_on_change(event.get_id(), max_interval)?;
+        xcp_info!(Daq, "Event {} on change, max interval {:?}", event.get_id(), max_interval);
+        Ok(())
+    }
+
+    /// Get the heartbeat interval of an event in value change mode, None if every trigger is measured
+    pub fn get_event_on_change(&self, event: XcpEvent) -> Option<std::time::Duration> {
+        match event_on_change::get_max_interval_ns(event.get_id()) {
+            0 => None,
+            ns => Some(std::time::Duration::from_nanos(u64::from(ns))),
+        }
+    }
+
+    /// Set the default measurement rate of an event, the rate a tool should measure the variables of this event with
+    /// Annotated in the A2L file and in the description of the measurement preset of the event, see set_measurement_presets
+    /// Has to be set before the registry is finalized, a cycle time of 0 defaults to the target cycle time of the event
+    pub fn set_event_default_rate(&self, event: XcpEvent, cycle_time: std::time::Duration) -> Result<(), XcpClientError> {
+        let Some(c) = EVENT_DEFAULT_CYCLE_TIME.get(event.get_id() as usize) else {
+            return Err(XcpClientError::Event(format!("invalid event id {}", event.get_id())));
+        };
+        let Ok(cycle_time_ns) = u32::try_from(cycle_time.as_nanos()) else {
+            return Err(XcpClientError::Event(format!("default rate of event {} exceeds {} ns", event.get_id(), u32::MAX)));
+        };
+        c.store(cycle_time_ns, Ordering::Relaxed);
+        xcp_info!(Daq, "Event {} default rate {:?}", event.get_id(), cycle_time);
+        Ok(())
+    }
+
+    /// Write a measurement preset for each event into the A2L file, a GROUP preset_<event> with all measurements of the event
+    /// Must be set before the registry is finalized
+    pub fn set_measurement_presets(&self, enable: bool) -> &'static Xcp {
+        if let Some(reg) = registry::get_lock().as_mut() {
+            reg.set_measurement_presets_mode(enable);
+        } else {
+            xcp_error!(Registry, "Measurement presets not set, registry already closed");
+        }
         &XCP
     }
 
+    /// Add a predefined measurement configuration, a named set of measurement signals of any events, written into the A2L file as GROUP
+    /// next to the measurement presets, a tool offers it as ready-made measurement configuration
+    /// With auto_start, the configuration is annotated to be started by headless loggers on connect
+    /// Must be added before the registry is finalized
+    pub fn add_measurement_config(&self, name: &'static str, comment: &'static str, measurements: &[&str], auto_start: bool) -> Result<(), XcpClientError> {
+        let mut config = registry::McMeasurementConfig::new(name, comment, measurements);
+        config.set_auto_start(auto_start);
+        let mut reg = registry::get_lock();
+        let Some(reg) = reg.as_mut() else {
+            return Err(XcpClientError::Registry(format!("measurement configuration {} not added, registry already closed", name)));
+        };
+        reg.add_measurement_config(config).map_err(|e| XcpClientError::Registry(e.to_string()))?;
+        xcp_info!(Registry, "Measurement configuration {} with {} signals", name, measurements.len());
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------------------------
+    // Event deadline monitoring
+
+    /// Declare the expected cycle time of a periodic event, each trigger is checked against the deadline
+    /// A trigger later than cycle_time plus jitter is counted as late, whole cycles without trigger as missed
+    /// With notify, violations are printed to the XCP client tool console (SERV_TEXT) by a monitor thread, at most one per event and 100ms
+    /// The cycle time is also the target cycle time of the event in the A2L file, if set before the registry is finalized
+    /// A cycle time of 0 stops the monitoring
+    pub fn set_event_deadline(&self, event: XcpEvent, cycle_time: std::time::Duration, jitter: std::time::Duration, notify: bool) -> Result<(), XcpClientError> {
+        event_deadline::set_deadline(event.get_id(), cycle_time, jitter, notify)?;
+        xcp_info!(Daq, "Event {} deadline {:?} jitter {:?}", event.get_id(), cycle_time, jitter);
+        Ok(())
+    }
+
+    /// Get the deadline statistics of an event
+    pub fn get_event_deadline_stats(&self, event: XcpEvent) -> XcpDeadlineStats {
+        event_deadline::get_deadline_stats(event.get_id())
+    }
+
+    /// Reset the deadline statistics of an event
+    pub fn reset_event_deadline_stats(&self, event: XcpEvent) {
+        event_deadline::reset_deadline_stats(event.get_id());
+    }
+
+    /// Register the deadline statistics of an event as measurements "<event>.deadline.<counter>" of a new event "<event>.deadline"
+    /// The new event is triggered after each check of the monitored event
+    pub fn register_event_deadline_metrics(&self, event: XcpEvent) -> Result<(), XcpClientError> {
+        let name = event.get_name();
+        if name.is_empty() {
+            return Err(XcpClientError::Event(format!("event id {} does not exist", event.get_id())));
+        }
+        event_deadline::register_deadline_metrics(event.get_id(), name)
+    }
+
+    //------------------------------------------------------------------------------------------
+    // Self-measurement
+
+    /// Measure the health signals of the XCP server with the internal event xcp_internal, sampled every cycle_time by a thread
+    /// Signals xcp_internal.<signal>: triggers, daq_events (after decimation), trigger_rate, daq_rate, connects, errors, registry_size, daq_queue_size
+    /// Must be called before the registry is finalized, the thread stops when the server stops
+    /// The trigger and DAQ event counters in the trigger path are enabled from this call on
+    pub fn start_introspection(&self, cycle_time: std::time::Duration) -> Result<std::thread::JoinHandle<()>, XcpClientError> {
+        if !self.server_running.load(Ordering::Relaxed) {
+            return Err(XcpClientError::Transport("introspection not started, server is not running".to_string()));
+        }
+        introspection::spawn(cycle_time)
+    }
+
+    //------------------------------------------------------------------------------------------
+    // Error channel
+
+    /// Get the receiver of the error channel
+    /// Internal faults of the XCP server (transport errors, DAQ queue overflows, registry and A2L errors) are sent to this channel,
+    /// the application decides how to react
+    /// Returns None, if the receiver has already been taken
+    pub fn take_error_receiver(&self) -> Option<std::sync::mpsc::Receiver<XcpClientError>> {
+        let mut error_sender = self.error_sender.lock();
+        if error_sender.is_some() {
+            return None;
+        }
+        let (tx, rx) = std::sync::mpsc::channel();
+        *error_sender = Some(tx);
+        Some(rx)
+    }
+
+    // Log an internal fault and send it to the error channel, if the application has taken the receiver
+    pub(crate) fn report_error(&self, error: XcpClientError) {
+        introspection::count_error();
+        match &error {
+            XcpClientError::QueueOverflow(_) | XcpClientError::Event(_) | XcpClientError::MemoryLimit(_) => xcp_error!(Daq, "{}", error),
+            XcpClientError::CalSeg(_) => xcp_error!(Cal, "{}", error),
+            XcpClientError::Registry(_) => xcp_error!(Registry, "{}", error),
+            _ => xcp_error!(Transport, "{}", error),
+        }
+        if let Some(tx) = self.error_sender.lock().as_ref() {
+            let _ = tx.send(error);
+        }
+    }
+
     /// Print a formatted text message to the XCP client tool console
     #[allow(clippy::unused_self)]
     pub fn print(&self, msg: &str) {
         // @@@@ UNSAFE - C library call
         unsafe {
-            let msg = std::ffi::CString::new(msg).unwrap();
+            let msg = c_string(msg);
             xcplib::XcpPrint(msg.as_ptr());
         }
     }
@@ -332,9 +1035,13 @@ impl Xcp {
         A: Into<std::net::Ipv4Addr>,
     {
         {
+            let _span = tracing::info_span!(target: "transport", "start_server").entered();
             let ipv4_addr: std::net::Ipv4Addr = addr.into();
             let _ = &XCP;
 
+            // The DAQ transmit queue is allocated by xcplib
+            self.check_memory(queue_size as usize)?;
+
             // Initialize the XCP server and ETH transport layer in xcplib
             unsafe {
                 // @@@@ UNSAFE - C library call
@@ -342,6 +1049,7 @@ impl Xcp {
                     return Err(XcpClientError::XcpLib("Error: XcpEthServerInit() failed"));
                 }
             }
+            self.daq_queue_size.store(queue_size as usize, Ordering::Relaxed);
 
             // Register transport layer parameters and actual ip addr of the server to create XCP IF_DATA make the A2L plug&play
             // If bound to any, get the actual ip address
@@ -357,22 +1065,34 @@ impl Xcp {
             if let Some(reg) = reg.as_mut() {
                 reg.set_xcp_eth_params(tl.protocol_name(), addr.into(), port); // Transport layer parameters
             }
+
+            self.server_running.store(true, Ordering::Relaxed);
+
+            // Record the DAQ clock epoch
+            let epoch = self.record_clock_epoch();
+            xcp_info!(Transport, "XCP DAQ clock epoch: clock={}ns utc={}ns", epoch.clock_ns, epoch.utc_ns);
+            Self::publish_clock_epoch(epoch);
+
             Ok(&XCP)
         }
     }
 
     /// Check if the XCP server is ok and running
-    #[allow(clippy::unused_self)]
+    /// A server which stopped without stop_server is reported on the error channel
     pub fn check_server(&self) -> bool {
-        unsafe {
+        let ok = unsafe {
             // @@@@ UNSAFE - C library call
             xcplib::XcpEthServerStatus()
+        };
+        if !ok && self.server_running.swap(false, Ordering::Relaxed) {
+            self.report_error(XcpClientError::Transport("XCP server stopped unexpectedly".to_string()));
         }
+        ok
     }
 
     /// Stop the XCP server
-    #[allow(clippy::unused_self)]
     pub fn stop_server(&self) {
+        self.server_running.store(false, Ordering::Relaxed);
         // @@@@ UNSAFE - C library calls
         unsafe {
             xcplib::XcpSendTerminateSessionEvent(); // Send terminate session event, if the XCP client is still connected
@@ -397,7 +1117,7 @@ impl Xcp {
     pub fn get_calseg_index(&self, name: &str) -> Option<usize> {
         unsafe {
             // @@@@ UNSAFE - C library call
-            let c_name = std::ffi::CString::new(name).unwrap();
+            let c_name = c_string(name);
             let index = xcplib::XcpFindCalSeg(c_name.as_ptr());
             if index == u16::MAX {
                 return None;
@@ -427,22 +1147,99 @@ impl Xcp {
     /// Create XCP event  
     /// index==0 single instance  
     /// index>0 multi instance (instance number is attached to name)  
+    /// If the event name already exists or the maximum number of events is exceeded, see try_create_event_ext,
+    /// the error is logged and reported on the error channel and XCP_UNDEFINED_EVENT is returned, its triggers are ignored
+    /// Use try_create_event_ext to handle the error
+    #[track_caller]
     pub fn create_event_ext(&self, name: &'static str, indexed: bool) -> XcpEvent {
-        let event = self.event_list.lock().create_event_ext(name, indexed);
-        if event == XcpEvent::XCP_UNDEFINED_EVENT {
-            panic!("Event name already exists or maximum number of events exceeded");
-        }
-        event
+        let location = std::panic::Location::caller();
+        self.try_create_event_ext(name, indexed).unwrap_or_else(|e| {
+            xcp_error!(Daq, "Event {} created at {} is undefined, its triggers are ignored: {}", name, location, e);
+            self.report_error(e);
+            XcpEvent::XCP_UNDEFINED_EVENT
+        })
     }
 
     /// Create XCP event  
     /// Single instance  
+    /// If the event name already exists or the maximum number of events is exceeded, the error is reported on the error channel, see create_event_ext
+    #[track_caller]
     pub fn create_event(&self, name: &'static str) -> XcpEvent {
-        let event = self.event_list.lock().create_event_ext(name, false);
-        if event == XcpEvent::XCP_UNDEFINED_EVENT {
-            panic!("Event name already exists or maximum number of events exceeded");
+        self.create_event_ext(name, false)
+    }
+
+    /// Create XCP event, without panic
+    /// Returns an error, if the event name already exists, the maximum number of events or the memory limit is exceeded
+    /// The error of a duplicate name contains the source location of the first creation, see set_duplicate_policy
+    #[track_caller]
+    pub fn try_create_event_ext(&self, name: &'static str, indexed: bool) -> Result<XcpEvent, XcpClientError> {
+        let location = std::panic::Location::caller();
+        self.check_memory(std::mem::size_of::<McEvent>())?;
+        self.event_list.lock().create_event_ext(name, indexed, None, location)
+    }
+
+    /// Create XCP event with an explicit id number, for A2L files with fixed event ids
+    /// The id may be in a range reserved with reserve_event_ids
+    /// Returns an error, if the id is already used by another event, see try_create_event_ext for other errors
+    #[track_caller]
+    pub fn try_create_event_with_id(&self, name: &'static str, id: u16) -> Result<XcpEvent, XcpClientError> {
+        let location = std::panic::Location::caller();
+        self.check_memory(std::mem::size_of::<McEvent>())?;
+        self.event_list.lock().create_event_ext(name, false, Some(id), location)
+    }
+
+    /// Exclude a range of event ids from automatic allocation, to keep them for events with explicit ids
+    /// Returns an error, if an existing event uses an id in the range
+    pub fn reserve_event_ids(&self, ids: std::ops::Range<u16>) -> Result<(), XcpClientError> {
+        self.event_list.lock().reserve(ids)
+    }
+
+    /// Set the allocation of event ids for events created without explicit id, default is XcpEventIdAllocation::Recycle
+    pub fn set_event_id_allocation(&self, allocation: XcpEventIdAllocation) {
+        self.event_list.lock().allocation = allocation;
+    }
+
+    /// Remove an event and all its measurements from the registry, returns the number of removed measurements
+    /// The event id is reused by the next created event, the event must not be triggered after removal
+    /// Only possible before the registry is finalized, when the XCP client tool connects, the A2L file is written and DAQ lists refer to the events,
+    /// xcplib has no API to stop them, so removal is refused with an error after finalization
+    pub fn remove_event(&self, event: XcpEvent) -> Result<usize, XcpClientError> {
+        // Lock order as in finalize_registry, event list before registry
+        let mut event_list = self.event_list.lock();
+        if self.registry_finalized.load(Ordering::Relaxed) {
+            return Err(XcpClientError::Event(format!(
+                "event id {} not removed, registry is finalized and DAQ may refer to it",
+                event.get_id()
+            )));
         }
-        event
+        let mut reg = registry::get_lock();
+        let Some(reg) = reg.as_mut() else {
+            return Err(XcpClientError::Registry(format!("event id {} not removed, registry already closed", event.get_id())));
+        };
+        let Some(name) = event_list.remove(event) else {
+            return Err(XcpClientError::Event(format!("event id {} not removed, event does not exist", event.get_id())));
+        };
+        let id = event.get_id();
+        EVENT_PRESCALER[id as usize].store(1, Ordering::Relaxed);
+        EVENT_TRIGGER_COUNTER[id as usize].store(0, Ordering::Relaxed);
+        event_deadline::set_deadline(id, std::time::Duration::ZERO, std::time::Duration::ZERO, false)?;
+        event_deadline::reset_deadline_stats(id);
+        event_on_change::set_on_change(id, None)?;
+        EVENT_DEFAULT_CYCLE_TIME[id as usize].store(0, Ordering::Relaxed);
+        let count = reg.remove_event_instances(id);
+        xcp_info!(Daq, "Removed event {} id={}, index={} and {} measurements", name, id, event.get_index(), count);
+        Ok(count)
+    }
+
+    /// Set the handling of duplicate event names and duplicate measurement names of an event
+    /// Default is XcpDuplicatePolicy::Error, with AutoSuffix duplicates are registered as name_2, name_3, ...
+    pub fn set_duplicate_policy(&self, policy: XcpDuplicatePolicy) {
+        duplicates::set_policy(policy);
+    }
+
+    /// Get the handling of duplicate names
+    pub fn get_duplicate_policy(&self) -> XcpDuplicatePolicy {
+        duplicates::get_policy()
     }
 
     //------------------------------------------------------------------------------------------
@@ -458,7 +1255,10 @@ impl Xcp {
         if self.registry_finalized.load(Ordering::Relaxed) {
             return Ok(false);
         }
-        assert!(!registry::is_closed());
+        if registry::is_closed() {
+            return Err(XcpClientError::Registry("registry already closed".to_string()));
+        }
+        let _span = tracing::info_span!(target: "registry", "finalize_registry").entered();
 
         // Register all calibration segments
         // @@@@ TODO: Check if calibration segment index is not equal calibration segment number !!
@@ -468,19 +1268,26 @@ impl Xcp {
             if number == u8::MAX {
                 continue;
             }
-            let name = unsafe { std::ffi::CStr::from_ptr(xcplib::XcpGetCalSegName(i)).to_str().unwrap() };
+            let Ok(name) = (unsafe { std::ffi::CStr::from_ptr(xcplib::XcpGetCalSegName(i)).to_str() }) else {
+                self.report_error(XcpClientError::Registry(format!("CalSeg {} not registered, name is not UTF-8", i)));
+                continue;
+            };
             let size = unsafe { xcplib::XcpGetCalSegSize(i) };
-            log::info!("Register CalSeg {}, size={}", name, size);
-            let _ = registry::get_lock().as_mut().unwrap().cal_seg_list.add_cal_seg(name, Some(number), size as u32);
+            xcp_info!(Registry, "Register CalSeg {}, size={}", name, size);
+            if let Some(reg) = registry::get_lock().as_mut() {
+                let _ = reg.cal_seg_list.add_cal_seg(name, Some(number), size as u32);
+            }
         }
 
         // Register all events
-        self.event_list.lock().register();
+        self.event_list.lock().register()?;
 
         // Sort typedef, measurement, axis and calibration list to get a deterministic order
         // Event and CalSeg lists stay in the order they were added
-        registry::get_lock().as_mut().unwrap().typedef_list.sort_by_name();
-        registry::get_lock().as_mut().unwrap().instance_list.sort_by_name_and_event();
+        if let Some(reg) = registry::get_lock().as_mut() {
+            reg.typedef_list.sort_by_name();
+            reg.instance_list.sort_by_name_and_event();
+        }
 
         // Close the registry and move to immutable state
         registry::close();
@@ -488,9 +1295,9 @@ impl Xcp {
         // Write A2L file from registry
         // Build filename
         let app_name = registry::get().application.get_name();
-        assert!(app_name.len() != 0, "App name not set");
+        let a2l_name = self.get_a2l_name();
         let mut path = std::path::PathBuf::new();
-        path.set_file_name(app_name);
+        path.set_file_name(&a2l_name);
         path.set_extension("a2l");
 
         // Write A2L file to disk, with typedefs or flatten and mangle
@@ -501,10 +1308,16 @@ impl Xcp {
         let check = false;
         registry::get().write_a2l(&path, "xcp-lite", app_name, "", app_name, "XCPLITE__CASDD", check)?;
 
+        // Write the device description stub, the A2L is usable without it
+        if self.a2l_naming.lock().has_device_description()
+            && let Err(e) = a2l_naming::write_device_description_file(registry::get(), &a2l_name)
+        {
+            self.report_error(XcpClientError::Registry(format!("device description not written, {}", e)));
+        }
+
         // Notify xcplib of the A2L file
         unsafe {
-            let reg = registry::get();
-            let name = std::ffi::CString::new(reg.application.get_name()).unwrap();
+            let name = c_string(&a2l_name);
             // @@@@ UNSAFE - C library call
             xcplib::XcpSetA2lName(name.as_ptr());
         }
@@ -527,6 +1340,48 @@ impl Xcp {
             xcplib::ApplXcpGetClock64()
         }
     }
+
+    // Sample the DAQ clock and the UTC wall clock as close as possible
+    // The UTC time is taken in the middle of two DAQ clock reads
+    fn record_clock_epoch(&self) -> XcpClockEpoch {
+        let t1 = self.get_clock();
+        let utc = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        let t2 = self.get_clock();
+        let epoch = XcpClockEpoch {
+            utc_ns: u64::try_from(utc.as_nanos()).unwrap_or(u64::MAX),
+            clock_ns: t1 + (t2 - t1) / 2,
+        };
+        *self.clock_epoch.lock() = Some(epoch);
+        epoch
+    }
+
+    // Publish the DAQ clock epoch in the calibration segment XCP_CLOCK_EPOCH_NAME, once for the first server start
+    // The DAQ clock continues over a server restart, the first epoch stays valid
+    fn publish_clock_epoch(epoch: XcpClockEpoch) {
+        static PUBLISHED: std::sync::Once = std::sync::Once::new();
+        PUBLISHED.call_once(|| {
+            let page: &'static XcpClockEpoch = Box::leak(Box::new(epoch));
+            match CalSeg::try_new_with_init(XCP_CLOCK_EPOCH_NAME, page, CalPageInit::Default) {
+                Ok(calseg) => {
+                    calseg.register();
+                }
+                Err(e) => Xcp::get().report_error(e),
+            }
+        });
+    }
+
+    /// Get the mapping of the DAQ clock to UTC, recorded when the server was started
+    /// None if the server has not been started
+    pub fn get_clock_epoch(&self) -> Option<XcpClockEpoch> {
+        *self.clock_epoch.lock()
+    }
+
+    /// Convert a DAQ clock value or DAQ timestamp in ns to UTC in ns since 1.1.1970
+    /// The epoch is recorded when the server is started, if not started yet, it is recorded now
+    pub fn clock_to_utc(&self, clock_ns: u64) -> u64 {
+        let epoch = self.get_clock_epoch().unwrap_or_else(|| self.record_clock_epoch());
+        epoch.clock_to_utc(clock_ns)
+    }
 }
 
 //--------------------------------------------------------------------------------------------------------------------------------------------------
@@ -536,11 +1391,21 @@ impl Xcp {
 #[unsafe(no_mangle)]
 extern "C" fn cb_connect(_mode: u8) -> bool {
     {
-        log::trace!("cb_connect: generate and write Al2 file");
+        let _span = tracing::debug_span!(target: "cmd", "cb_connect").entered();
+        xcp_trace!(Cmd, "cb_connect: generate and write Al2 file");
         if let Err(e) = XCP.finalize_registry() {
-            log::error!("connect refused, A2L file write failed, {}", e);
+            XCP.report_error(XcpClientError::Registry(format!("connect refused, A2L file write failed, {}", e)));
             return false;
         }
+        introspection::count_connect();
+        // The client takes over from the headless logger
+        headless::take_over();
+        // A new client session starts without event decimation
+        for prescaler in &EVENT_PRESCALER {
+            prescaler.store(1, Ordering::Relaxed);
+        }
+        // and with a complete first sample of each value change event
+        event_on_change::reset();
         true
     }
 }
@@ -584,7 +1449,277 @@ pub mod xcp_test {
         }
         let xcp = Xcp::init("Test", "EPK_V1.1.0", TEST_XCP_LOG_LEVEL);
         xcp.event_list.lock().clear();
+        duplicates::test_reset();
 
         xcp
     }
 }
+
+#[cfg(test)]
+mod xcp_error_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_error_channel() {
+        let xcp = xcp_test::test_setup();
+        let rx = xcp.take_error_receiver().unwrap();
+        assert!(xcp.take_error_receiver().is_none());
+
+        // Duplicate event name is an error, not a panic
+        assert!(xcp.try_create_event_ext("test_error_channel", false).is_ok());
+        assert!(matches!(xcp.try_create_event_ext("test_error_channel", false), Err(XcpClientError::Event(_))));
+        assert!(xcp.create_event("test_error_channel") == XcpEvent::XCP_UNDEFINED_EVENT);
+        assert!(matches!(rx.try_recv(), Ok(XcpClientError::Event(_))));
+
+        // Internal faults are sent to the error channel
+        xcp.report_error(XcpClientError::QueueOverflow(3));
+        assert!(matches!(rx.try_recv(), Ok(XcpClientError::QueueOverflow(3))));
+    }
+
+    #[test]
+    fn test_remove_event() {
+        use crate::registry::{McDimType, McObjectType, McSupportData, McValueType};
+        let xcp = xcp_test::test_setup();
+        let event1 = xcp.try_create_event_ext("test_remove_event_1", false).unwrap();
+        let event2 = xcp.try_create_event_ext("test_remove_event_2", false).unwrap();
+        for (name, event) in [("a", event1), ("b", event1), ("c", event2)] {
+            registry::get_lock()
+                .as_mut()
+                .unwrap()
+                .instance_list
+                .add_instance(
+                    name,
+                    McDimType::new(McValueType::Ulong, 1, 1),
+                    McSupportData::new(McObjectType::Measurement),
+                    McAddress::new_event_dyn(0, event.get_id(), 0),
+                )
+                .unwrap();
+        }
+
+        // Remove a single instance and an event with its instances, the event id is reused
+        registry::get_lock().as_mut().unwrap().remove_instance("b", Some(event1.get_id())).unwrap();
+        assert!(registry::get_lock().as_mut().unwrap().remove_instance("b", Some(event1.get_id())).is_err());
+        assert_eq!(xcp.remove_event(event1).unwrap(), 1);
+        assert!(xcp.remove_event(event1).is_err());
+        let event3 = xcp.try_create_event_ext("test_remove_event_1", false).unwrap();
+        assert_eq!(event3.get_id(), event1.get_id());
+        assert_eq!(registry::get_lock().as_ref().unwrap().instance_list.len(), 1);
+
+        // Refused after finalization
+        xcp.finalize_registry().unwrap();
+        assert!(xcp.remove_event(event2).is_err());
+    }
+
+    #[test]
+    fn test_event_id_allocation() {
+        let xcp = xcp_test::test_setup();
+
+        // Reserved range and explicit ids with collision detection
+        xcp.reserve_event_ids(0..2).unwrap();
+        assert!(xcp.reserve_event_ids(1..3).is_ok());
+        let e1 = xcp.try_create_event_ext("test_event_id_1", false).unwrap();
+        assert_eq!(e1.get_id(), 3);
+        assert!(xcp.reserve_event_ids(3..4).is_err());
+        assert_eq!(xcp.try_create_event_with_id("test_event_id_fixed", 1).unwrap().get_id(), 1);
+        assert!(matches!(xcp.try_create_event_with_id("test_event_id_other", 1), Err(XcpClientError::Event(msg)) if msg.contains("test_event_id_fixed")));
+        assert!(xcp.try_create_event_with_id("test_event_id_other", XcpEvent::XCP_MAX_EVENTS).is_err());
+
+        // Recycle reuses the removed id, monotonic continues
+        let e2 = xcp.try_create_event_ext("test_event_id_2", false).unwrap();
+        assert_eq!(e2.get_id(), 4);
+        xcp.remove_event(e1).unwrap();
+        assert_eq!(xcp.try_create_event_ext("test_event_id_3", false).unwrap().get_id(), 3);
+        xcp.remove_event(e2).unwrap();
+        xcp.set_event_id_allocation(XcpEventIdAllocation::Monotonic);
+        assert_eq!(xcp.try_create_event_ext("test_event_id_4", false).unwrap().get_id(), 4);
+        assert_eq!(xcp.try_create_event_ext("test_event_id_5", false).unwrap().get_id(), 5);
+    }
+
+    #[test]
+    fn test_duplicate_policy() {
+        use crate::registry::{McObjectType, McSupportData, McValueType};
+        let xcp = xcp_test::test_setup();
+        let rx = xcp.take_error_receiver().unwrap();
+
+        // Duplicate event reports the location of the first creation
+        let line = line!() + 1;
+        let event = xcp.try_create_event_ext("test_duplicate_policy", false).unwrap();
+        let Err(XcpClientError::Event(msg)) = xcp.try_create_event_ext("test_duplicate_policy", false) else {
+            panic!("duplicate event not detected");
+        };
+        assert!(msg.contains(&format!("{}:{}", file!(), line)), "{}", msg);
+
+        // Duplicate measurement reports the location of the first registration
+        let daq_event = DaqEvent::<0>::new_from(&event);
+        let value: u32 = 0;
+        daq_event.add_stack(
+            "value",
+            (&raw const value).cast::<u8>(),
+            McValueType::Ulong,
+            1,
+            1,
+            McSupportData::new(McObjectType::Measurement),
+        );
+        daq_event.add_stack(
+            "value",
+            (&raw const value).cast::<u8>(),
+            McValueType::Ulong,
+            1,
+            1,
+            McSupportData::new(McObjectType::Measurement),
+        );
+        assert!(matches!(rx.try_recv(), Ok(XcpClientError::Registry(msg)) if msg.contains(file!())));
+
+        // Auto suffix
+        xcp.set_duplicate_policy(XcpDuplicatePolicy::AutoSuffix);
+        let event2 = xcp.try_create_event_ext("test_duplicate_policy", false).unwrap();
+        assert_eq!(event2.get_name(), "test_duplicate_policy_2");
+        daq_event.add_stack(
+            "value",
+            (&raw const value).cast::<u8>(),
+            McValueType::Ulong,
+            1,
+            1,
+            McSupportData::new(McObjectType::Measurement),
+        );
+        assert!(rx.try_recv().is_err());
+        let reg = registry::get_lock();
+        assert!(reg.as_ref().unwrap().instance_list.into_iter().any(|i| i.get_name() == "value_2"));
+        drop(reg);
+        xcp.set_duplicate_policy(XcpDuplicatePolicy::Error);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let xcp = xcp_test::test_setup();
+        assert!(xcp.try_create_event_ext("test_memory_limit_1", false).is_ok());
+
+        // Limit is exceeded by the next event
+        let usage = xcp.get_memory_usage();
+        xcp.set_memory_limit(usage.total() + 1);
+        assert!(matches!(xcp.try_create_event_ext("test_memory_limit_2", false), Err(XcpClientError::MemoryLimit(_))));
+
+        xcp.set_memory_limit(0);
+        assert!(xcp.try_create_event_ext("test_memory_limit_2", false).is_ok());
+    }
+
+    #[test]
+    fn test_event_prescaler() {
+        let xcp = xcp_test::test_setup();
+        let event = xcp.try_create_event_ext("test_event_prescaler", false).unwrap();
+        assert_eq!(xcp.get_event_prescaler(event), 1);
+        assert!(xcp.set_event_prescaler(event, 0).is_err());
+        assert!(xcp.set_event_prescaler(XcpEvent::XCP_UNDEFINED_EVENT, 2).is_err());
+
+        // Every prescaler-th trigger is forwarded to xcplib, starting with the first one, counted by the introspection counters
+        introspection::enable();
+        let value = 0u8;
+        let forwarded = |prescaler: u16, triggers: usize| {
+            xcp.set_event_prescaler(event, prescaler).unwrap();
+            let count = introspection::get_daq_count(event.get_id());
+            for _ in 0..triggers {
+                // @@@@ UNSAFE - no measurements registered for this event
+                unsafe { event.trigger_ext(&raw const value) };
+            }
+            introspection::get_daq_count(event.get_id()) - count
+        };
+        assert_eq!(forwarded(4, 8), 2);
+        assert_eq!(xcp.get_event_prescaler(event), 4);
+        assert_eq!(forwarded(3, 8), 3);
+        assert!(EVENT_TRIGGER_COUNTER[event.get_id() as usize].load(Ordering::Relaxed) < 3);
+        assert_eq!(forwarded(1, 8), 8);
+    }
+
+    #[test]
+    fn test_event_deadline() {
+        let xcp = xcp_test::test_setup();
+        let event = xcp.try_create_event_ext("test_event_deadline", false).unwrap();
+        let value = 0u8;
+        let trigger = || {
+            // @@@@ UNSAFE - no measurements registered for this event
+            unsafe { event.trigger_ext(&raw const value) };
+        };
+
+        // Not monitored
+        trigger();
+        assert_eq!(xcp.get_event_deadline_stats(event), XcpDeadlineStats::default());
+
+        // Within the deadline
+        xcp.set_event_deadline(event, Duration::from_secs(1), Duration::ZERO, false).unwrap();
+        trigger();
+        trigger();
+        let stats = xcp.get_event_deadline_stats(event);
+        assert_eq!(stats.triggers, 2);
+        assert!(stats.is_ok());
+
+        // Late trigger
+        xcp.reset_event_deadline_stats(event);
+        xcp.set_event_deadline(event, Duration::from_millis(100), Duration::ZERO, false).unwrap();
+        trigger();
+        std::thread::sleep(Duration::from_millis(110));
+        trigger();
+        assert_eq!(xcp.get_event_deadline_stats(event).late, 1);
+
+        // Missed cycles
+        xcp.reset_event_deadline_stats(event);
+        xcp.set_event_deadline(event, Duration::from_millis(1), Duration::ZERO, false).unwrap();
+        trigger();
+        std::thread::sleep(Duration::from_millis(20));
+        trigger();
+        let stats = xcp.get_event_deadline_stats(event);
+        assert!(stats.missed >= 10);
+        assert!(stats.jitter_ns_max >= 19_000_000);
+        assert!(!stats.is_ok());
+
+        // Notification, the trigger only flags the violation, the monitor thread prints it
+        xcp.reset_event_deadline_stats(event);
+        xcp.set_event_deadline(event, Duration::from_millis(1), Duration::ZERO, true).unwrap();
+        trigger();
+        std::thread::sleep(Duration::from_millis(5));
+        trigger();
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(!event_deadline::is_violation_pending(event.get_id()));
+        assert!(xcp.get_event_deadline_stats(event).missed > 0);
+
+        xcp.set_event_deadline(event, Duration::ZERO, Duration::ZERO, false).unwrap();
+    }
+
+    #[test]
+    fn test_event_on_change() {
+        let xcp = xcp_test::test_setup();
+        let event = xcp.try_create_event_ext("test_event_on_change", false).unwrap();
+        assert_eq!(xcp.get_event_on_change(event), None);
+        assert!(xcp.set_event_on_change(event, Some(Duration::ZERO)).is_err());
+        assert!(event_on_change::is_due(event.get_id(), &[1]));
+        assert!(event_on_change::is_due(event.get_id(), &[1]));
+
+        // Only changes are measured
+        xcp.set_event_on_change(event, Some(Duration::from_millis(50))).unwrap();
+        assert_eq!(xcp.get_event_on_change(event), Some(Duration::from_millis(50)));
+        assert!(event_on_change::is_due(event.get_id(), &[1]));
+        assert!(!event_on_change::is_due(event.get_id(), &[1]));
+        assert!(event_on_change::is_due(event.get_id(), &[2]));
+        assert!(!event_on_change::is_due(event.get_id(), &[2]));
+
+        // Equal hash with different bytes is measured
+        event_on_change::set_last_hash_of(event.get_id(), &[3]);
+        assert!(event_on_change::is_due(event.get_id(), &[3]));
+        assert!(!event_on_change::is_due(event.get_id(), &[3]));
+
+        // Heartbeat
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(event_on_change::is_due(event.get_id(), &[2]));
+
+        xcp.set_event_on_change(event, None).unwrap();
+    }
+
+    #[test]
+    fn test_event_default_rate() {
+        let xcp = xcp_test::test_setup();
+        let event = xcp.try_create_event_ext("test_event_default_rate", false).unwrap();
+        xcp.set_event_default_rate(event, std::time::Duration::from_millis(10)).unwrap();
+        assert_eq!(EVENT_DEFAULT_CYCLE_TIME[event.get_id() as usize].load(Ordering::Relaxed), 10_000_000);
+        assert!(xcp.set_event_default_rate(event, std::time::Duration::from_secs(10)).is_err());
+    }
+}
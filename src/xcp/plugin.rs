@@ -0,0 +1,348 @@
+//-----------------------------------------------------------------------------
+// Module plugin
+// Instrumentation of dynamically loaded libraries (plugins) with the already running XCP server
+// A plugin registers its events, measurements and calibration segments in its own registry, which is written as an additional MODULE
+// of the A2L file, next to the MODULE of the application (see Registry::write_a2l_modules_to_writer)
+// Measurement addresses are event relative to the load address of the plugin, the A2L stays valid wherever the library is mapped
+// Dropping the XcpPlugin handle (before unloading the library) removes its MODULE from the A2L
+// After the plugin has been published with update_a2l, measurements may be added and removed at runtime, each change rewrites the A2L
+// Each rewrite increments the A2L revision, the name reported by GET_ID IDT_ASAM_NAME changes to <a2l_name>_<revision>, so a connected
+// XCP client tool detects the changed topology and uploads the new A2L
+// Note: xcplib has no API to delete events or calibration segments, they stay allocated in the server after unload
+
+#[allow(unused_imports)]
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::Mutex;
+
+use super::cal::{CalPageTrait, CalSeg};
+use super::{Xcp, XcpClientError, XcpEvent, c_string, xcplib};
+use crate::registry::{self, McAddress, McDimType, McEvent, McObjectType, McRegisterContext, McRegisterTarget, McRegisterType, McSupportData, McValueTypeTrait, Registry};
+
+//-----------------------------------------------------------------------------
+// Address translation
+
+// Event relative address offset of addr to the plugin load address base
+// None if addr is out of the range of the event relative addressing mode (XCP_ADDR_EXT_DYN)
+fn dyn_addr_offset(base: usize, addr: usize) -> Option<i32> {
+    let offset = i64::try_from(addr).ok()? - i64::try_from(base).ok()?;
+    let min = -i64::from(McAddress::XCP_ADDR_EXT_DYN_OFFSET_OFFSET);
+    let max = i64::from(McAddress::XCP_ADDR_EXT_DYN_OFFSET_MASK) - i64::from(McAddress::XCP_ADDR_EXT_DYN_OFFSET_OFFSET);
+    if offset < min || offset > max {
+        return None;
+    }
+    i32::try_from(offset).ok()
+}
+
+//-----------------------------------------------------------------------------
+// A2L revision
+
+// A2L name reported by GET_ID IDT_ASAM_NAME for a revision of the A2L file, revision 0 is the A2L written on finalization
+pub(crate) fn a2l_revision_name(a2l_name: &str, revision: u32) -> String {
+    if revision == 0 { a2l_name.to_string() } else { format!("{}_{}", a2l_name, revision) }
+}
+
+//-----------------------------------------------------------------------------
+// XcpPlugin
+
+// Name and registry of the loaded plugins
+pub(crate) type XcpPluginList = Vec<(&'static str, Arc<Mutex<Registry>>)>;
+
+/// Handle of a dynamically loaded library, which registers its own events, measurements and calibration segments
+/// Created with Xcp::load_plugin, all names are prefixed with "<plugin name>."
+/// Drop the handle and all CalSegs of the plugin before the library is unloaded
+/// Components which are not in a separate library use a null base address and register_struct
+#[derive(Debug)]
+pub struct XcpPlugin {
+    name: &'static str,
+    base_addr: usize,
+    registry: Arc<Mutex<Registry>>,
+    published: AtomicBool,
+}
+
+impl XcpPlugin {
+    /// Get the plugin name, which is also the A2L MODULE name
+    pub fn get_name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Get the load address of the plugin, the base of the measurement addresses
+    pub fn get_base_addr(&self) -> usize {
+        self.base_addr
+    }
+
+    // Leaked "<plugin>.<name>"
+    fn prefixed(&self, name: &str) -> &'static str {
+        Box::leak(format!("{}.{}", self.name, name).into_boxed_str())
+    }
+
+    /// Event relative address offset of a variable in the plugin memory
+    /// Fails, if the variable is too far away from the plugin load address
+    pub fn get_addr_offset<T>(&self, ptr: *const T) -> Result<i32, XcpClientError> {
+        dyn_addr_offset(self.base_addr, ptr as usize)
+            .ok_or_else(|| XcpClientError::Registry(format!("address 0x{:X} out of range of plugin {} at 0x{:X}", ptr as usize, self.name, self.base_addr)))
+    }
+
+    // Rewrite the A2L file after a change, if the plugin has already been published with update_a2l
+    fn changed(&self) -> Result<(), XcpClientError> {
+        if self.published.load(Ordering::Relaxed) {
+            Xcp::get().write_plugin_a2l()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Create an event of the plugin
+    /// An event of a previously unloaded instance of the plugin with the same name is reused
+    pub fn create_event(&self, name: &str) -> Result<XcpEvent, XcpClientError> {
+        let name = format!("{}.{}", self.name, name);
+        let existing = Xcp::get()
+            .event_list
+            .lock()
+            .events
+            .iter()
+            .find(|e| e.name == name && e.event.get_index() == 0)
+            .map(|e| e.event);
+        let event = match existing {
+            Some(event) => event,
+            None => Xcp::get().try_create_event_ext(Box::leak(name.into_boxed_str()), false)?,
+        };
+        self.registry
+            .lock()
+            .event_list
+            .add_event(McEvent::new(event.get_name(), 0, event.get_id(), 0))
+            .map_err(|e| XcpClientError::Event(e.to_string()))?;
+        self.changed()?;
+        Ok(event)
+    }
+
+    /// Register a static variable of the plugin as measurement of event
+    /// The address is relative to the plugin load address, measured by XcpPlugin::trigger
+    pub fn add_measurement<T: McValueTypeTrait>(&self, name: &str, event: XcpEvent, value: &'static T) -> Result<(), XcpClientError> {
+        let addr_offset = self.get_addr_offset(value as *const T)?;
+        self.registry
+            .lock()
+            .instance_list
+            .add_instance(
+                self.prefixed(name),
+                McDimType::new(value.get_type(), 1, 1),
+                McSupportData::new(McObjectType::Measurement),
+                McAddress::new_event_dyn(0, event.get_id(), addr_offset),
+            )
+            .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+        self.changed()
+    }
+
+    /// Register a struct type T of #[derive(McRegisterType)] and an instance instance_name of it as measurement of event
+    /// The instance is relative to the struct given to XcpEvent::trigger_struct, it may be located anywhere, e.g. on heap
+    pub fn register_struct<T: McRegisterType>(&self, event: XcpEvent, instance_name: &str) -> Result<(), XcpClientError> {
+        let name = self.prefixed(instance_name);
+        let mut reg = self.registry.lock();
+        if reg.instance_list.get_instance(name, McObjectType::Measurement, Some(event.get_id())).is_some() {
+            return Err(XcpClientError::Registry(format!("{} not registered, duplicate instance", name)));
+        }
+        let registered = registry::register_into(&mut reg, || {
+            T::register(&McRegisterContext {
+                target: McRegisterTarget::Event(event.get_id()),
+                instance_name: Some(name),
+                name_prefix: String::new(),
+                addr_offset: 0,
+                level: 0,
+            });
+        });
+        drop(reg);
+        if !registered {
+            return Err(XcpClientError::Registry(format!("{} not registered, application registry is not finalized", name)));
+        }
+        self.changed()
+    }
+
+    /// Remove a measurement of event added with add_measurement or register_struct
+    /// A DAQ measurement of the XCP client tool may still refer to it, the memory must stay valid until the tool has uploaded the new A2L
+    pub fn remove_measurement(&self, name: &str, event: XcpEvent) -> Result<(), XcpClientError> {
+        let name = format!("{}.{}", self.name, name);
+        if self.registry.lock().instance_list.remove(&name, Some(event.get_id())).is_none() {
+            return Err(XcpClientError::Registry(format!(
+                "{} not removed, measurement of event id {} does not exist",
+                name,
+                event.get_id()
+            )));
+        }
+        xcp_info!(Registry, "Plugin measurement {} removed", name);
+        self.changed()
+    }
+
+    /// Trigger an event of the plugin, with the plugin load address as base for the measurements added with add_measurement
+    pub fn trigger(&self, event: XcpEvent) {
+        // @@@@ UNSAFE - the measurement addresses of the plugin are offsets of static variables in the plugin memory mapped at base_addr
+        unsafe { event.trigger_ext(self.base_addr as *const u8) }
+    }
+
+    /// Create a calibration segment of the plugin and register its page type
+    /// Fails, if a calibration segment with this name already exists, xcplib can not create it again after the plugin has been unloaded
+    pub fn create_calseg<T>(&self, name: &str, default_page: &'static T) -> Result<CalSeg<T>, XcpClientError>
+    where
+        T: CalPageTrait + McRegisterType,
+    {
+        let name = self.prefixed(name);
+        let c_name = c_string(name);
+        // @@@@ UNSAFE - C library call
+        if unsafe { xcplib::XcpFindCalSeg(c_name.as_ptr()) } != u16::MAX {
+            return Err(XcpClientError::CalSeg(format!(
+                "{} already exists, calibration segments of unloaded plugins can not be created again",
+                name
+            )));
+        }
+        let calseg = CalSeg::try_new(name, default_page)?;
+        let index = u16::try_from(calseg.get_index()).unwrap_or(u16::MAX);
+        // @@@@ UNSAFE - C library calls
+        let (number, size) = unsafe { (xcplib::XcpGetCalSegNumber(index), xcplib::XcpGetCalSegSize(index)) };
+        let (addr_ext, addr) = McAddress::get_calseg_ext_addr_base(index);
+
+        let mut reg = self.registry.lock();
+        reg.cal_seg_list
+            .add_a2l_cal_seg(name, index, (number != u8::MAX).then_some(number), addr_ext, addr, u32::from(size))
+            .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+        if !registry::register_into(&mut reg, || default_page.mc_register(McRegisterTarget::CalSeg(name), Some(name))) {
+            return Err(XcpClientError::Registry(format!("{} not registered, application registry is not finalized", name)));
+        }
+        drop(reg);
+        self.changed()?;
+        Ok(calseg)
+    }
+
+    /// Write the A2L file with the current content of all loaded plugins and publish the plugin
+    /// Call once after all events, measurements and calibration segments of the plugin are created,
+    /// later changes of the plugin rewrite the A2L automatically
+    /// The A2L name reported by GET_ID changes with each rewrite, the XCP client tool has to upload the A2L again to see the changes
+    pub fn update_a2l(&self) -> Result<(), XcpClientError> {
+        self.published.store(true, Ordering::Relaxed);
+        Xcp::get().write_plugin_a2l()
+    }
+}
+
+impl Drop for XcpPlugin {
+    fn drop(&mut self) {
+        let xcp = Xcp::get();
+        xcp.plugins.lock().retain(|(name, _)| *name != self.name);
+        xcp_info!(Registry, "Plugin {} unloaded", self.name);
+        if let Err(e) = xcp.write_plugin_a2l() {
+            xcp.report_error(e);
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Xcp plugin API
+
+impl Xcp {
+    /// Load a plugin, a dynamically loaded library with its own events, measurements and calibration segments
+    /// base_addr is the load address of the library (e.g. from dladdr), measurement addresses of the plugin are relative to it
+    /// Finalizes the registry of the application, if not already done, application objects must be created before the first plugin is loaded
+    /// Fails, if a plugin with the same name is already loaded
+    pub fn load_plugin(&self, name: &str, base_addr: *const u8) -> Result<XcpPlugin, XcpClientError> {
+        self.finalize_registry()?;
+        if name.is_empty() || name == registry::get().application.get_name() {
+            return Err(XcpClientError::Registry(format!("invalid plugin name '{}'", name)));
+        }
+        let mut plugins = self.plugins.lock();
+        if plugins.iter().any(|(n, _)| *n == name) {
+            return Err(XcpClientError::Registry(format!("plugin {} already loaded", name)));
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let registry = Arc::new(Mutex::new(Registry::new()));
+        plugins.push((name, registry.clone()));
+        xcp_info!(Registry, "Plugin {} loaded at 0x{:X}", name, base_addr as usize);
+        Ok(XcpPlugin {
+            name,
+            base_addr: base_addr as usize,
+            registry,
+            published: AtomicBool::new(false),
+        })
+    }
+
+    /// Get the names of the loaded plugins
+    pub fn get_plugin_names(&self) -> Vec<&'static str> {
+        self.plugins.lock().iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Get the revision of the A2L file, incremented each time the A2L is rewritten after a change of the loaded plugins
+    /// 0 is the A2L written when the registry is finalized
+    pub fn get_a2l_revision(&self) -> u32 {
+        self.a2l_revision.load(Ordering::Relaxed)
+    }
+
+    // Rewrite the A2L file with one MODULE for the application and one for each loaded plugin
+    // The A2L revision is incremented, the previous revision of the file is removed
+    fn write_plugin_a2l(&self) -> Result<(), XcpClientError> {
+        if !self.registry_finalized.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let app_name = registry::get().application.get_name();
+        let plugins = self.plugins.lock();
+        let revision = self.a2l_revision.fetch_add(1, Ordering::Relaxed) + 1;
+        let a2l_name = self.get_a2l_name();
+        let mut path = std::path::PathBuf::new();
+        path.set_file_name(&a2l_name);
+        path.set_extension("a2l");
+
+        let mut guards: Vec<_> = plugins.iter().map(|(name, reg)| (*name, reg.lock())).collect();
+        for (_, reg) in &mut guards {
+            reg.typedef_list.sort_by_name();
+            reg.instance_list.sort_by_name_and_event();
+        }
+        let mut modules: Vec<(&str, &Registry)> = vec![(app_name, registry::get())];
+        modules.extend(guards.iter().map(|(name, reg)| (*name, &**reg)));
+
+        xcp_info!(Registry, "Write A2L file {} with {} plugins", path.display(), plugins.len());
+        let mut writer = std::io::LineWriter::new(std::fs::File::create(&path)?);
+        Registry::write_a2l_modules_to_writer(&mut writer, "xcp-lite", app_name, "", "XCPLITE__CASDD", &modules)?;
+
+        // Notify xcplib of the changed A2L name, reported to the XCP client tool by GET_ID IDT_ASAM_NAME
+        unsafe {
+            let name = c_string(&a2l_name);
+            // @@@@ UNSAFE - C library call
+            xcplib::XcpSetA2lName(name.as_ptr());
+        }
+        self.print(&format!("A2L changed, revision {} {}.a2l", revision, a2l_name));
+
+        // The initial A2L file is kept, it is the one referenced by the device description
+        if revision > 1 {
+            let previous = path
+                .with_file_name(a2l_revision_name(&self.a2l_naming.lock().a2l_name(app_name), revision - 1))
+                .with_extension("a2l");
+            if let Err(e) = std::fs::remove_file(&previous) {
+                xcp_warn!(Registry, "Previous A2L file {} not removed, {}", previous.display(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod plugin_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_dyn_addr_offset() {
+        assert_eq!(dyn_addr_offset(0x7F00_0000_0000, 0x7F00_0000_1234), Some(0x1234));
+        assert_eq!(dyn_addr_offset(0x7F00_0001_0000, 0x7F00_0000_0000), Some(-0x10000));
+        assert_eq!(dyn_addr_offset(0x7F00_0001_0000, 0x7F00_0000_FFFF), None);
+        assert_eq!(dyn_addr_offset(0x7F00_0000_0000, 0x7F00_003E_FFFF), Some(0x3EFFFF));
+        assert_eq!(dyn_addr_offset(0x7F00_0000_0000, 0x7F00_003F_0000), None);
+    }
+
+    #[test]
+    fn test_a2l_revision_name() {
+        assert_eq!(a2l_revision_name("demo", 0), "demo");
+        assert_eq!(a2l_revision_name("demo", 1), "demo_1");
+        assert_eq!(a2l_revision_name("demo_autodetect", 12), "demo_autodetect_12");
+    }
+}
@@ -0,0 +1,201 @@
+//-----------------------------------------------------------------------------
+// Module replay
+// Replay of a recorded measurement as DAQ traffic, for tool development and demonstration without a target
+// A CSV recording has a header line with the time column (in s) and the signal names, and one sample of all signals per line,
+// separated by ',' or ';'. Each signal is registered as a f64 measurement of the replay event, each sample triggers the event
+// with the recorded timing. A static calibration image is served by the calibration segments with a CalStorage, see Xcp::set_cal_storage
+// MDF4 recordings are not supported, export them to CSV with the measurement tool
+
+#[allow(unused_imports)]
+use super::xcp_log::{xcp_debug, xcp_error, xcp_info, xcp_trace, xcp_warn};
+
+use std::time::{Duration, Instant};
+
+use super::{Xcp, XcpClientError, XcpEvent};
+use crate::registry::{self, McAddress, McDimType, McObjectType, McSupportData, McValueType};
+
+// Maximum size of a sample, measurement offsets must fit u16
+const MAX_SAMPLE_SIZE: usize = 0x10000;
+
+/// A recorded measurement, replayed as DAQ traffic of one event
+#[derive(Debug)]
+pub struct XcpReplay {
+    signals: Vec<String>,
+    times_ns: Vec<u64>, // Time of each sample, relative to the first sample
+    values: Vec<f64>,   // Samples, signals.len() values each
+    event: XcpEvent,
+}
+
+impl XcpReplay {
+    /// Load a CSV recording
+    pub fn load_csv<P: AsRef<std::path::Path>>(path: P) -> Result<XcpReplay, XcpClientError> {
+        let text = std::fs::read_to_string(path.as_ref())?;
+        XcpReplay::parse_csv(&text).map_err(|e| XcpClientError::Event(format!("{}: {}", path.as_ref().display(), e)))
+    }
+
+    /// Parse a CSV recording
+    /// The first column is the time in s, empty lines are ignored, empty values are replaced by the previous value of the signal
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn parse_csv(text: &str) -> Result<XcpReplay, String> {
+        let mut lines = text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+        let Some((_, header)) = lines.next() else {
+            return Err("empty recording".to_string());
+        };
+        let separator = if header.contains(';') { ';' } else { ',' };
+        let signals: Vec<String> = header.split(separator).skip(1).map(|s| s.trim().trim_matches('"').to_string()).collect();
+        if signals.is_empty() || signals.iter().any(String::is_empty) {
+            return Err("header must contain the time column and signal names".to_string());
+        }
+        if signals.len() * std::mem::size_of::<f64>() > MAX_SAMPLE_SIZE {
+            return Err(format!("too many signals, {}", signals.len()));
+        }
+
+        let mut times_ns = Vec::new();
+        let mut values: Vec<f64> = Vec::new();
+        let mut t0: Option<f64> = None;
+        for (n, line) in lines {
+            let mut columns = line.split(separator).map(str::trim);
+            let time: f64 = columns.next().unwrap_or_default().parse().map_err(|_| format!("line {}: invalid time", n + 1))?;
+            let t0 = *t0.get_or_insert(time);
+            let t = ((time - t0) * 1e9) as u64;
+            if time < t0 || times_ns.last().is_some_and(|last| t < *last) {
+                return Err(format!("line {}: time is not monotonic", n + 1));
+            }
+            times_ns.push(t);
+            let previous = values.len().checked_sub(signals.len());
+            for i in 0..signals.len() {
+                let value = match columns.next() {
+                    Some(v) if !v.is_empty() => v.parse().map_err(|_| format!("line {}: invalid value of {}", n + 1, signals[i]))?,
+                    _ => previous.map_or(0.0, |p| values[p + i]),
+                };
+                values.push(value);
+            }
+        }
+        if times_ns.is_empty() {
+            return Err("no samples".to_string());
+        }
+        Ok(XcpReplay {
+            signals,
+            times_ns,
+            values,
+            event: XcpEvent::XCP_UNDEFINED_EVENT,
+        })
+    }
+
+    /// Names of the recorded signals
+    pub fn get_signal_names(&self) -> &[String] {
+        &self.signals
+    }
+
+    /// Number of samples
+    pub fn get_sample_count(&self) -> usize {
+        self.times_ns.len()
+    }
+
+    /// Duration of the recording
+    pub fn get_duration(&self) -> Duration {
+        Duration::from_nanos(self.times_ns.last().copied().unwrap_or(0))
+    }
+
+    /// Create the replay event and register the signals as f64 measurements "<event_name>.<signal>"
+    pub fn register(&mut self, event_name: &'static str) -> Result<XcpEvent, XcpClientError> {
+        let event = Xcp::get().try_create_event_ext(event_name, false)?;
+        {
+            let mut reg = registry::get_lock();
+            let Some(reg) = reg.as_mut() else {
+                return Err(XcpClientError::Registry(format!("{} signals not registered, registry is finalized", event_name)));
+            };
+            for (i, name) in self.signals.iter().enumerate() {
+                reg.instance_list
+                    .add_instance(
+                        format!("{}.{}", event_name, name),
+                        McDimType::new(McValueType::Float64Ieee, 1, 1),
+                        McSupportData::new(McObjectType::Measurement).set_comment("Replayed signal"),
+                        McAddress::new_event_dyn(0, event.get_id(), i32::try_from(i * std::mem::size_of::<f64>()).unwrap_or_default()),
+                    )
+                    .map_err(|e| XcpClientError::Registry(e.to_string()))?;
+            }
+        }
+        self.event = event;
+        Ok(event)
+    }
+
+    // Trigger the replay event with sample n
+    fn trigger(&self, n: usize) {
+        let sample = &self.values[n * self.signals.len()..(n + 1) * self.signals.len()];
+        // @@@@ UNSAFE - the measurements of the replay event are registered relative to the sample
+        unsafe { self.event.trigger_ext(sample.as_ptr().cast::<u8>()) }
+    }
+
+    /// Replay the recording in a thread with the recorded timing, as long as the XCP server is running
+    /// speed scales the replay rate (2.0 replays twice as fast), repeat restarts the recording at its end
+    pub fn spawn(self, speed: f64, repeat: bool) -> Result<std::thread::JoinHandle<()>, XcpClientError> {
+        if self.event == XcpEvent::XCP_UNDEFINED_EVENT {
+            return Err(XcpClientError::Event("replay event not registered".to_string()));
+        }
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(XcpClientError::Event(format!("invalid replay speed {}", speed)));
+        }
+        Ok(std::thread::spawn(move || {
+            let _span = tracing::info_span!(target: "daq", "replay").entered();
+            xcp_info!(Daq, "Replay of {} samples, duration {:?}", self.get_sample_count(), self.get_duration());
+            loop {
+                let start = Instant::now();
+                for (n, t) in self.times_ns.iter().enumerate() {
+                    let due = Duration::from_nanos(*t).div_f64(speed);
+                    if let Some(wait) = due.checked_sub(start.elapsed()) {
+                        std::thread::sleep(wait);
+                    }
+                    if !Xcp::get().check_server() {
+                        return;
+                    }
+                    self.trigger(n);
+                }
+                if !repeat {
+                    break;
+                }
+            }
+            xcp_debug!(Daq, "Replay finished");
+        }))
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod replay_tests {
+
+    use super::*;
+    use crate::xcp::xcp_test;
+
+    const CSV: &str = "time;speed;\"rpm\"\n0.0;1.5;800\n0.01;2.5;\n\n0.02;3.5;900\n";
+
+    #[test]
+    fn test_replay_parse_csv() {
+        let replay = XcpReplay::parse_csv(CSV).unwrap();
+        assert_eq!(replay.get_signal_names(), ["speed", "rpm"]);
+        assert_eq!(replay.get_sample_count(), 3);
+        assert_eq!(replay.get_duration(), Duration::from_millis(20));
+        assert_eq!(replay.values, [1.5, 800.0, 2.5, 800.0, 3.5, 900.0]);
+
+        assert!(XcpReplay::parse_csv("").is_err());
+        assert!(XcpReplay::parse_csv("time\n0.0\n").is_err());
+        assert!(XcpReplay::parse_csv("time,a\n0.0,x\n").is_err());
+        assert!(XcpReplay::parse_csv("time,a\n1.0,1\n0.5,2\n").is_err());
+    }
+
+    #[test]
+    fn test_replay_register() {
+        let xcp = xcp_test::test_setup();
+        assert!(XcpReplay::parse_csv(CSV).unwrap().spawn(1.0, false).is_err());
+        let mut replay = XcpReplay::parse_csv(CSV).unwrap();
+        replay.register("test_replay").unwrap();
+        for n in 0..replay.get_sample_count() {
+            replay.trigger(n);
+        }
+        xcp.finalize_registry().unwrap();
+        let reg = registry::get();
+        assert!(reg.instance_list.find_instance_regex("test_replay\\.rpm", McObjectType::Measurement, None).is_some());
+    }
+}
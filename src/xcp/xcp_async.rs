@@ -0,0 +1,175 @@
+//----------------------------------------------------------------------------------------------
+// Module xcp_async
+// Async API of the XCP server for tokio applications (feature async)
+// XcpAsync starts and stops the XCP server without blocking the runtime and supervises it in a task, XcpEventQueue triggers DAQ events
+// of values sent from tasks in a task, CalSeg::changed awaits calibration changes of the XCP tool
+// The XCP on Ethernet transport of xcplib keeps its own receive and transmit threads, it is not part of the xcplib API available to Rust
+
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use super::cal::{CalPageTrait, CalSeg};
+use super::{Xcp, XcpClientError, XcpEvent, XcpTransportLayer};
+use crate::registry::{McRegisterTarget, McRegisterType, MeasureSafe};
+
+//----------------------------------------------------------------------------------------------
+// XcpAsync
+
+/// Handle of the XCP server started from async code, see XcpAsync::start_server
+pub struct XcpAsync {
+    supervisor: JoinHandle<()>,
+    running: watch::Receiver<bool>,
+}
+
+impl XcpAsync {
+    /// Start the XCP server on a blocking thread of the runtime and supervise it in a task, with Xcp::check_server every check_interval
+    /// Xcp::init must have been called
+    pub async fn start_server<A>(tl: XcpTransportLayer, addr: A, port: u16, queue_size: u32, check_interval: Duration) -> Result<XcpAsync, XcpClientError>
+    where
+        A: Into<std::net::Ipv4Addr>,
+    {
+        let addr: std::net::Ipv4Addr = addr.into();
+        tokio::task::spawn_blocking(move || Xcp::get().start_server(tl, addr, port, queue_size).map(|_| ()))
+            .await
+            .map_err(|e| XcpClientError::Transport(format!("server start task failed: {}", e)))??;
+
+        let (tx, running) = watch::channel(true);
+        let supervisor = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(check_interval);
+            loop {
+                interval.tick().await;
+                if !Xcp::get().check_server() {
+                    let _ = tx.send(false);
+                    break;
+                }
+            }
+        });
+        Ok(XcpAsync { supervisor, running })
+    }
+
+    /// Check if the XCP server is running
+    pub fn is_running(&self) -> bool {
+        *self.running.borrow()
+    }
+
+    /// Wait until the XCP server stopped, a server which stopped unexpectedly is reported on the error channel
+    pub async fn stopped(&mut self) {
+        let _ = self.running.wait_for(|running| !running).await;
+    }
+
+    /// Stop the supervision and the XCP server
+    pub async fn stop(self) {
+        self.supervisor.abort();
+        let _ = tokio::task::spawn_blocking(|| Xcp::get().stop_server()).await;
+    }
+}
+
+//----------------------------------------------------------------------------------------------
+// XcpEventQueue
+
+/// Queue of measurement values of type T from tasks, a task triggers the event "<name>" for each value, in the order of the queue
+/// The measurements of T are registered relative to the event, the DAQ timestamp is the time the value is dequeued
+#[derive(Clone)]
+pub struct XcpEventQueue<T> {
+    event: XcpEvent,
+    tx: mpsc::Sender<T>,
+}
+
+impl<T> XcpEventQueue<T>
+where
+    T: MeasureSafe + McRegisterType + Default + Copy + Send + 'static,
+{
+    /// Create the event "<name>" and a queue with capacity values, register the measurements of T and spawn the trigger task
+    /// The trigger task ends when all clones of the queue are dropped
+    /// Must be called before the registry is finalized
+    pub fn new(name: &'static str, capacity: usize) -> Result<(XcpEventQueue<T>, JoinHandle<()>), XcpClientError> {
+        const { assert!(T::NO_PADDING, "MeasureSafe: the measured type has padding bytes, add explicit padding fields") };
+        let event = Xcp::get().try_create_event_ext(name, false)?;
+        T::default().mc_register(McRegisterTarget::Event(event.get_id()), Some(name));
+        let (tx, mut rx) = mpsc::channel::<T>(capacity.max(1));
+        let task = tokio::spawn(async move {
+            while let Some(value) = rx.recv().await {
+                // @@@@ UNSAFE - the measurements are registered relative to value
+                unsafe { event.trigger_ext((&raw const value).cast::<u8>()) }
+            }
+        });
+        Ok((XcpEventQueue { event, tx }, task))
+    }
+
+    /// Get the event of the queue
+    pub fn get_event(&self) -> XcpEvent {
+        self.event
+    }
+
+    /// Queue a value, waits while the queue is full
+    pub async fn trigger(&self, value: T) -> Result<(), XcpClientError> {
+        self.tx
+            .send(value)
+            .await
+            .map_err(|_| XcpClientError::Event(format!("trigger task of event {} terminated", self.event.get_id())))
+    }
+
+    /// Queue a value without waiting, fails with QueueOverflow if the queue is full
+    pub fn try_trigger(&self, value: T) -> Result<(), XcpClientError> {
+        self.tx.try_send(value).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => XcpClientError::QueueOverflow(1),
+            mpsc::error::TrySendError::Closed(_) => XcpClientError::Event(format!("trigger task of event {} terminated", self.event.get_id())),
+        })
+    }
+}
+
+//----------------------------------------------------------------------------------------------
+// CalSeg
+
+impl<T> CalSeg<T>
+where
+    T: CalPageTrait,
+{
+    /// Wait until the active page of the calibration segment changes, by calibration of the XCP tool or by page switching
+    /// The page is compared every poll_interval, returns the new page
+    pub async fn changed(&self, poll_interval: Duration) -> T {
+        let snapshot = |page: &T| serde_json::to_value(page).ok();
+        let last = snapshot(&self.read_lock());
+        loop {
+            tokio::time::sleep(poll_interval).await;
+            let page = *self.read_lock();
+            if snapshot(&page) != last {
+                return page;
+            }
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test module
+
+#[cfg(test)]
+mod xcp_async_tests {
+
+    use super::*;
+    use crate::xcp::xcp_test;
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, Default, McRegisterType)]
+    struct TestValues {
+        counter: u32,
+        value: f32,
+    }
+
+    #[test]
+    fn test_event_queue() {
+        let xcp = xcp_test::test_setup();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            let (queue, task) = XcpEventQueue::<TestValues>::new("test_event_queue", 2).unwrap();
+            queue.trigger(TestValues { counter: 1, value: 1.0 }).await.unwrap();
+            queue.try_trigger(TestValues { counter: 2, value: 2.0 }).unwrap();
+            assert!(matches!(queue.try_trigger(TestValues::default()), Err(XcpClientError::QueueOverflow(1))));
+            drop(queue);
+            task.await.unwrap();
+        });
+        xcp.finalize_registry().unwrap();
+    }
+}
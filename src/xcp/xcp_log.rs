@@ -0,0 +1,175 @@
+//-----------------------------------------------------------------------------
+// Module xcp_log
+// Logging with individual log levels per subsystem (cmd, daq, transport, registry, cal)
+// Log messages are tracing events with the subsystem name as target
+// Without a tracing subscriber, they are forwarded to the log crate, loggers like env_logger can filter by the same target names
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+//-----------------------------------------------------------------------------
+// XcpLogSubsystem
+
+/// Subsystems with individual log levels
+/// Log levels are 0=off, 1=error, 2=warn, 3=info, 4=debug, 5=trace, as in Xcp::init
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XcpLogSubsystem {
+    Cmd,       // XCP command processing
+    Daq,       // DAQ events and measurement
+    Transport, // XCP on Ethernet server and transport layer
+    Registry,  // Registry and A2L generation
+    Cal,       // Calibration segments
+}
+
+const SUBSYSTEM_COUNT: usize = 5;
+const LOG_LEVEL_TRACE: u8 = 5;
+
+// Current log level of each subsystem, not restricted until Xcp::init or Xcp::set_log_filter
+static LOG_LEVELS: [AtomicU8; SUBSYSTEM_COUNT] = [const { AtomicU8::new(LOG_LEVEL_TRACE) }; SUBSYSTEM_COUNT];
+
+impl XcpLogSubsystem {
+    pub const ALL: [XcpLogSubsystem; SUBSYSTEM_COUNT] = [
+        XcpLogSubsystem::Cmd,
+        XcpLogSubsystem::Daq,
+        XcpLogSubsystem::Transport,
+        XcpLogSubsystem::Registry,
+        XcpLogSubsystem::Cal,
+    ];
+
+    /// Name of the subsystem, used as log target and in log filters
+    pub const fn target(self) -> &'static str {
+        match self {
+            XcpLogSubsystem::Cmd => "cmd",
+            XcpLogSubsystem::Daq => "daq",
+            XcpLogSubsystem::Transport => "transport",
+            XcpLogSubsystem::Registry => "registry",
+            XcpLogSubsystem::Cal => "cal",
+        }
+    }
+
+    fn from_target(target: &str) -> Option<XcpLogSubsystem> {
+        XcpLogSubsystem::ALL.into_iter().find(|s| s.target() == target)
+    }
+
+    /// Get the log level
+    pub fn get_level(self) -> u8 {
+        LOG_LEVELS[self as usize].load(Ordering::Relaxed)
+    }
+
+    /// Set the log level
+    pub fn set_level(self, level: u8) {
+        LOG_LEVELS[self as usize].store(level.min(LOG_LEVEL_TRACE), Ordering::Relaxed);
+    }
+
+    /// Check if messages of a level are enabled
+    #[inline]
+    pub fn enabled(self, level: tracing::Level) -> bool {
+        let level = match level {
+            tracing::Level::ERROR => 1,
+            tracing::Level::WARN => 2,
+            tracing::Level::INFO => 3,
+            tracing::Level::DEBUG => 4,
+            tracing::Level::TRACE => 5,
+        };
+        level <= self.get_level()
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Log filter
+
+// Parse a log level name or number
+fn parse_level(s: &str) -> Option<u8> {
+    match s.to_ascii_lowercase().as_str() {
+        "off" | "0" => Some(0),
+        "error" | "1" => Some(1),
+        "warn" | "2" => Some(2),
+        "info" | "3" => Some(3),
+        "debug" | "4" => Some(4),
+        "trace" | "5" => Some(5),
+        _ => None,
+    }
+}
+
+// Parse a log filter like "daq=trace,cmd=info" or "warn,daq=debug"
+// A level without subsystem applies to all subsystems not listed, other subsystems keep their current level
+// Returns the resulting log levels of all subsystems
+pub(crate) fn parse_log_filter(filter: &str) -> Result<[u8; SUBSYSTEM_COUNT], String> {
+    let mut default_level: Option<u8> = None;
+    let mut levels: [Option<u8>; SUBSYSTEM_COUNT] = [None; SUBSYSTEM_COUNT];
+    for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        match directive.split_once('=') {
+            Some((target, level)) => {
+                let subsystem = XcpLogSubsystem::from_target(target.trim()).ok_or_else(|| format!("unknown subsystem {}", target))?;
+                levels[subsystem as usize] = Some(parse_level(level.trim()).ok_or_else(|| format!("unknown log level {}", level))?);
+            }
+            None => {
+                default_level = Some(parse_level(directive).ok_or_else(|| format!("unknown log level {}", directive))?);
+            }
+        }
+    }
+    let mut result = [0; SUBSYSTEM_COUNT];
+    for subsystem in XcpLogSubsystem::ALL {
+        result[subsystem as usize] = levels[subsystem as usize].or(default_level).unwrap_or_else(|| subsystem.get_level());
+    }
+    Ok(result)
+}
+
+// Set the log levels of all subsystems
+pub(crate) fn set_log_levels(levels: &[u8; SUBSYSTEM_COUNT]) {
+    for subsystem in XcpLogSubsystem::ALL {
+        subsystem.set_level(levels[subsystem as usize]);
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Log macros
+// xcp_info!(Daq, "Create event {}", name);
+
+macro_rules! xcp_event {
+    ($subsystem:ident, $level:ident, $($arg:tt)+) => {
+        if $crate::xcp::XcpLogSubsystem::$subsystem.enabled(::tracing::Level::$level) {
+            ::tracing::event!(target: $crate::xcp::XcpLogSubsystem::$subsystem.target(), ::tracing::Level::$level, $($arg)+);
+        }
+    };
+}
+
+macro_rules! xcp_error {
+    ($subsystem:ident, $($arg:tt)+) => { $crate::xcp::xcp_log::xcp_event!($subsystem, ERROR, $($arg)+) };
+}
+macro_rules! xcp_warn {
+    ($subsystem:ident, $($arg:tt)+) => { $crate::xcp::xcp_log::xcp_event!($subsystem, WARN, $($arg)+) };
+}
+macro_rules! xcp_info {
+    ($subsystem:ident, $($arg:tt)+) => { $crate::xcp::xcp_log::xcp_event!($subsystem, INFO, $($arg)+) };
+}
+macro_rules! xcp_debug {
+    ($subsystem:ident, $($arg:tt)+) => { $crate::xcp::xcp_log::xcp_event!($subsystem, DEBUG, $($arg)+) };
+}
+macro_rules! xcp_trace {
+    ($subsystem:ident, $($arg:tt)+) => { $crate::xcp::xcp_log::xcp_event!($subsystem, TRACE, $($arg)+) };
+}
+
+#[allow(unused_imports)]
+pub(crate) use {xcp_debug, xcp_error, xcp_event, xcp_info, xcp_trace, xcp_warn};
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod xcp_log_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_log_filter() {
+        let levels = parse_log_filter("warn, daq=trace,cmd=info").unwrap();
+        assert_eq!(levels[XcpLogSubsystem::Daq as usize], 5);
+        assert_eq!(levels[XcpLogSubsystem::Cmd as usize], 3);
+        assert_eq!(levels[XcpLogSubsystem::Transport as usize], 2);
+        assert_eq!(levels[XcpLogSubsystem::Cal as usize], 2);
+
+        assert_eq!(parse_log_filter("registry=0").unwrap()[XcpLogSubsystem::Registry as usize], 0);
+        assert!(parse_log_filter("foo=info").is_err());
+        assert!(parse_log_filter("daq=verbose").is_err());
+    }
+}
@@ -1,8 +1,8 @@
 //-----------------------------------------------------------------------------
 // Library crate xcp_test_client
 // Slim XCP-on-ETH client used only as the integration-test client for the
-// xcp_lite workspace (see ../../../tests). The full standalone tool (with the
-// ELF/DWARF reader and CLI) lives in the xcplib submodule: xcplib/tools/xcpclient.
+// xcp_lite workspace (see ../../../tests). The full client (with the recorders,
+// the ELF reader and the CLI) is the workspace crate tools/xcp_client.
 
 // This crate is a library
 #![crate_type = "lib"]
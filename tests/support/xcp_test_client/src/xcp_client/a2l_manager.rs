@@ -0,0 +1,158 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module a2l_manager
+// Upload of the A2L file of a XCP server and loading it into the registry of the client
+// All A2L paths of the client (XcpClient, XcpMultiClient, Python bindings, test executor) use A2lManager
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use xcp_registry::A2lLoadOptions;
+
+use super::XcpClient;
+
+/// Name of the A2L file on the client side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum A2lFileName {
+    /// Fixed name without extension
+    Fixed(String),
+    /// Name reported by the server with GET_ID IDT_ASAM_NAME, e.g. <app_name> or <app_name>_autodetect
+    AsamName,
+}
+
+/// Upload and load of A2L files
+/// Default is: upload into ./<asam_name>.a2l and load into the registry of the client, typedefs and instances are kept
+#[derive(Debug, Clone)]
+pub struct A2lManager {
+    dir: PathBuf,
+    name: A2lFileName,
+    load_into_registry: bool,
+    load_options: A2lLoadOptions,
+}
+
+impl Default for A2lManager {
+    fn default() -> Self {
+        A2lManager {
+            dir: PathBuf::from("."),
+            name: A2lFileName::AsamName,
+            load_into_registry: true,
+            load_options: A2lLoadOptions {
+                strict: true,
+                check: true,
+                ..A2lLoadOptions::default()
+            },
+        }
+    }
+}
+
+impl A2lManager {
+    pub fn new() -> A2lManager {
+        A2lManager::default()
+    }
+
+    /// A2L file path, with or without extension .a2l
+    pub fn with_path<P: AsRef<Path>>(path: P) -> A2lManager {
+        let path = path.as_ref();
+        let mut m = A2lManager::new();
+        m.dir(path.parent().unwrap_or(Path::new("")));
+        m.name(A2lFileName::Fixed(path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned())));
+        m
+    }
+
+    /// Directory of the A2L file
+    pub fn dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Naming strategy of the A2L file
+    pub fn name(&mut self, name: A2lFileName) -> &mut Self {
+        self.name = name;
+        self
+    }
+
+    /// Load the A2L file into the registry of the client after upload
+    pub fn load_into_registry(&mut self, load: bool) -> &mut Self {
+        self.load_into_registry = load;
+        self
+    }
+
+    /// Options for loading the A2L file into the registry
+    pub fn load_options(&mut self, options: A2lLoadOptions) -> &mut Self {
+        self.load_options = options;
+        self
+    }
+
+    /// Path of the A2L file, the server is asked for the name with naming strategy AsamName
+    pub async fn get_path(&self, client: &mut XcpClient) -> Result<PathBuf, Box<dyn Error>> {
+        let name = match &self.name {
+            A2lFileName::Fixed(name) => name.clone(),
+            A2lFileName::AsamName => client.get_a2l_name().await?,
+        };
+        Ok(self.dir.join(name).with_extension("a2l"))
+    }
+
+    /// Upload the A2L file with GET_ID IDT_ASAM_UPLOAD and load it into the registry of the client, if enabled
+    /// Returns the path of the A2L file
+    pub async fn upload(&self, client: &mut XcpClient) -> Result<PathBuf, Box<dyn Error>> {
+        let path = self.get_path(client).await?;
+        client.upload_a2l_file(&path).await?;
+        if self.load_into_registry {
+            self.load_file(client, &path)?;
+        }
+        Ok(path)
+    }
+
+    /// Load an existing A2L file into the registry of the client
+    /// Returns the path of the A2L file
+    pub async fn load(&self, client: &mut XcpClient) -> Result<PathBuf, Box<dyn Error>> {
+        let path = self.get_path(client).await?;
+        self.load_file(client, &path)?;
+        Ok(path)
+    }
+
+    /// Load an A2L file into a registry
+    pub fn load_into<P: AsRef<Path>>(&self, path: &P, reg: &mut xcp_registry::Registry) -> Result<(), Box<dyn Error>> {
+        reg.load_a2l(&path, &self.load_options)?;
+        info!(
+            " A2L file contains {} instances, {} events and {} calibration segments",
+            reg.instance_list.len(),
+            reg.event_list.len(),
+            reg.cal_seg_list.len()
+        );
+        Ok(())
+    }
+
+    /// Create a client for the XCP server described by an existing A2L file, the A2L file is loaded into the registry of the client
+    /// The destination address and protocol are the connection defaults from the IF_DATA XCP_ON_UDP_IP or XCP_ON_TCP_IP, like MC tools bootstrap from the A2L
+    /// Returns an error, if the A2L file has no Ethernet transport layer parameters
+    pub fn new_client<P: AsRef<Path>>(&self, path: &P, bind_addr: SocketAddr) -> Result<XcpClient, Box<dyn Error>> {
+        let mut reg = xcp_registry::Registry::new();
+        self.load_into(path, &mut reg)?;
+        let Some((tcp, dest_addr)) = get_a2l_connection(&reg) else {
+            return Err(format!("{}: no IF_DATA XCP_ON_UDP_IP or XCP_ON_TCP_IP, destination address required", path.as_ref().display()).into());
+        };
+        info!("Connection defaults from A2L: {} {}", if tcp { "TCP" } else { "UDP" }, dest_addr);
+        let mut client = XcpClient::new(tcp, dest_addr, bind_addr);
+        client.set_registry(reg);
+        Ok(client)
+    }
+
+    fn load_file(&self, client: &mut XcpClient, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut reg = xcp_registry::Registry::new();
+        self.load_into(&path, &mut reg)?;
+        client.set_registry(reg);
+        Ok(())
+    }
+}
+
+/// Connection defaults (tcp, destination address) from the Ethernet transport layer parameters of a registry loaded from A2L
+/// The unspecified address 0.0.0.0, a server listening on all interfaces, is replaced by localhost
+pub fn get_a2l_connection(reg: &xcp_registry::Registry) -> Option<(bool, SocketAddr)> {
+    let (protocol, addr, port) = reg.get_xcp_eth_params()?;
+    let addr = if addr.is_unspecified() { Ipv4Addr::LOCALHOST } else { addr };
+    Some((protocol == "TCP", SocketAddr::from((addr, port))))
+}
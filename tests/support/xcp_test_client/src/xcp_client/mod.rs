@@ -13,9 +13,9 @@ use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::Cursor;
-use std::io::Write;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use tokio::net::{TcpStream, UdpSocket};
 use tokio::select;
@@ -24,6 +24,12 @@ use tokio::time::{Duration, timeout};
 
 pub mod xcp;
 use xcp::*;
+pub mod commands;
+use commands::*;
+mod capabilities;
+pub use capabilities::*;
+mod a2l_manager;
+pub use a2l_manager::*;
 use xcp_registry::*;
 
 //--------------------------------------------------------------------------------------------------------------------------------------------------
@@ -31,6 +37,14 @@ use xcp_registry::*;
 
 pub const CMD_TIMEOUT: Duration = Duration::from_secs(3);
 
+/// Name of the calibration segment, in which an xcp_lite server publishes the mapping of its DAQ clock to UTC
+pub const XCP_CLOCK_EPOCH_NAME: &str = "xcp_clock_epoch";
+
+// Max number of ODT entries per ODT and of ODTs per DAQ list
+// The PIDs 0xFC..0xFF are reserved for CTO packets
+const MAX_ODT_ENTRY_COUNT: usize = 0x7B;
+const MAX_ODT_COUNT: usize = 0x7C;
+
 //--------------------------------------------------------------------------------------------------------------------------------------------------
 //--------------------------------------------------------------------------------------------------------------------------------------------------
 // CalibrationObject
@@ -112,12 +126,106 @@ impl XcpCalibrationObjectHandle {
     }
 }
 
+// XCPlite event relative (dyn) addressing mode address extensions, the upper address bits are the event id
+fn is_dyn_addr_ext(ext: u8) -> bool {
+    (McAddress::XCP_ADDR_EXT_DYN..McAddress::XCP_ADDR_EXT_DYN + 16).contains(&ext)
+}
+
+// Check the minimum length of a positive command response before it is indexed
+fn check_response_len(data: &[u8], len: usize, command_code: u8) -> Result<(), Box<dyn Error>> {
+    if data.len() < len {
+        error!("Response to command 0x{:02X} has {} bytes, expected at least {}", command_code, data.len(), len);
+        return Err(Box::new(XcpError::new(ERROR_RESPONSE_LENGTH, command_code)) as Box<dyn Error>);
+    }
+    Ok(())
+}
+
+// Distribute the (measurement object index, size) entries of a DAQ list to ODTs, a new ODT is started when a DTO is full
+// The first ODT carries first_header_size bytes of DAQ header and timestamp, the following ODTs header_size bytes
+// Returns the index of the first entry which does not fit into an empty DTO
+fn split_odt_entries(entries: &[(usize, u16)], max_dto_size: u16, first_header_size: u16, header_size: u16) -> Result<Vec<Vec<usize>>, usize> {
+    let mut odts: Vec<Vec<usize>> = vec![Vec::new()];
+    let mut odt_size: u32 = 0;
+    for &(i, size) in entries {
+        let max_odt_size = u32::from(max_dto_size.saturating_sub(if odts.len() == 1 { first_header_size } else { header_size }));
+        let odt = odts.last_mut().unwrap();
+        if !odt.is_empty() && (odt_size + u32::from(size) > max_odt_size || odt.len() >= MAX_ODT_ENTRY_COUNT) {
+            odts.push(Vec::new());
+            odt_size = 0;
+        }
+        let max_odt_size = u32::from(max_dto_size.saturating_sub(if odts.len() == 1 { first_header_size } else { header_size }));
+        if odt_size + u32::from(size) > max_odt_size {
+            return Err(i);
+        }
+        odts.last_mut().unwrap().push(i);
+        odt_size += u32::from(size);
+    }
+    Ok(odts)
+}
+
+// Integer write values as f64 for the limit check
+// Magnitudes up to 2^53 convert exactly, above that the nearest f64 is used with a relative error of at most 2^-53,
+// so only a value closer than that to an A2L limit may be misjudged, the downloaded raw value is always exact
+#[allow(clippy::cast_precision_loss)]
+fn u64_to_limit_f64(value: u64) -> f64 {
+    value as f64
+}
+#[allow(clippy::cast_precision_loss)]
+fn i64_to_limit_f64(value: i64) -> f64 {
+    value as f64
+}
+
+// Encode a physical value as little endian raw value bytes, None for blobs or unsupported sizes
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn f64_to_value(a2l_type: A2lType, value: f64) -> Option<Vec<u8>> {
+    let v: u64 = match a2l_type.encoding {
+        A2lTypeEncoding::Float if a2l_type.size == 4 => (value as f32).to_bits() as u64,
+        A2lTypeEncoding::Float if a2l_type.size == 8 => value.to_bits(),
+        A2lTypeEncoding::Signed => value as i64 as u64,
+        A2lTypeEncoding::Unsigned => value as u64,
+        _ => return None,
+    };
+    if a2l_type.size == 0 || a2l_type.size > 8 {
+        return None;
+    }
+    Some(v.to_le_bytes()[0..a2l_type.size].to_vec())
+}
+
+/// Convert little endian raw value bytes to f64
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap, clippy::cast_precision_loss)]
+pub fn value_to_f64(a2l_type: A2lType, bytes: &[u8]) -> f64 {
+    let size = a2l_type.size.min(bytes.len()).min(8);
+    let mut v = 0u64;
+    for i in (0..size).rev() {
+        v <<= 8;
+        v |= bytes[i] as u64;
+    }
+    match a2l_type.encoding {
+        A2lTypeEncoding::Float => match size {
+            4 => f32::from_bits(v as u32) as f64,
+            8 => f64::from_bits(v),
+            _ => 0.0,
+        },
+        A2lTypeEncoding::Signed => {
+            // Sign extend
+            let shift = 64 - 8 * size as u32;
+            if shift >= 64 { 0.0 } else { (((v << shift) as i64) >> shift) as f64 }
+        }
+        A2lTypeEncoding::Unsigned => v as f64,
+        A2lTypeEncoding::Blob => 0.0,
+    }
+}
+
+/// Calibration object with name, address, type, limits and its cached value
+/// Arrays (CURVE, MAP, VAL_BLK) have dim > 1, the type and the limits apply to each element
+/// Typedef instances and blobs have type encoding Blob and are not writeable as values
 #[derive(Debug)]
 pub struct XcpClientCalibrationObject {
     name: String,
     a2l_addr: A2lAddr,
     get_type: A2lType,
     a2l_limits: A2lLimits,
+    dim: [u16; 2],
     value: Vec<u8>,
 }
 
@@ -128,6 +236,7 @@ impl XcpClientCalibrationObject {
             a2l_addr,
             get_type,
             a2l_limits,
+            dim: [1, 1],
             value: Vec::new(),
         }
     }
@@ -136,6 +245,71 @@ impl XcpClientCalibrationObject {
         &self.name
     }
 
+    /// Array dimensions [x_dim, y_dim], [1, 1] for scalars
+    pub fn get_dim(&self) -> [u16; 2] {
+        self.dim
+    }
+
+    /// Number of elements
+    pub fn get_element_count(&self) -> usize {
+        self.dim[0] as usize * self.dim[1] as usize
+    }
+
+    /// Size of the object in bytes
+    pub fn get_size(&self) -> usize {
+        self.get_type.size * self.get_element_count()
+    }
+
+    /// Check if the object is a single value of basic type
+    pub fn is_scalar(&self) -> bool {
+        !matches!(self.get_type.encoding, A2lTypeEncoding::Blob) && self.get_element_count() == 1
+    }
+
+    // Check a write of value to element index
+    fn check_write(&self, index: usize, value: f64) -> Result<(), Box<dyn Error>> {
+        if matches!(self.get_type.encoding, A2lTypeEncoding::Blob) {
+            error!("{}: {} values can not be written as numbers", self.name, self.get_type);
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+        }
+        if index >= self.get_element_count() {
+            error!("{}: element index {} out of range, {} elements", self.name, index, self.get_element_count());
+            return Err(Box::new(XcpError::new(ERROR_INDEX_RANGE, 0)) as Box<dyn Error>);
+        }
+        if value > self.a2l_limits.upper || value < self.a2l_limits.lower {
+            error!("{}: value {} out of limits [{}, {}]", self.name, value, self.a2l_limits.lower, self.a2l_limits.upper);
+            return Err(Box::new(XcpError::new(ERROR_LIMIT, 0)) as Box<dyn Error>);
+        }
+        Ok(())
+    }
+
+    // Check a scalar write
+    fn check_scalar_write(&self, value: f64) -> Result<(), Box<dyn Error>> {
+        if !self.is_scalar() && !matches!(self.get_type.encoding, A2lTypeEncoding::Blob) {
+            error!("{}: not a scalar, {} elements, use set_element_f64", self.name, self.get_element_count());
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+        }
+        self.check_write(0, value)
+    }
+
+    /// Physical value of element index from the cached value
+    pub fn get_element_f64(&self, index: usize) -> Option<f64> {
+        if matches!(self.get_type.encoding, A2lTypeEncoding::Blob) || index >= self.get_element_count() {
+            return None;
+        }
+        let size = self.get_type.size;
+        self.value.get(index * size..(index + 1) * size).map(|bytes| value_to_f64(self.get_type, bytes))
+    }
+
+    // Hierarchical name of element index, as used by Registry::resolve_instance
+    fn get_element_name(&self, index: usize) -> String {
+        let [x_dim, y_dim] = self.dim;
+        if y_dim > 1 {
+            format!("{}._{}_{}", self.name, index / x_dim as usize, index % x_dim as usize)
+        } else {
+            format!("{}._{}", self.name, index)
+        }
+    }
+
     pub fn get_a2l_type(&self) -> A2lType {
         self.get_type
     }
@@ -204,6 +378,9 @@ pub struct XcpClientMeasurementObject {
     pub daq: u16,
     pub odt: u8,
     pub offset: u16,
+    measured: bool,                 // Assigned to a running DAQ list
+    conversion: Option<(f64, f64)>, // Linear conversion (factor, offset) from the registry
+    dim: [u16; 2],                  // Array dimensions [x_dim, y_dim], [1, 1] for scalars
 }
 
 impl XcpClientMeasurementObject {
@@ -215,6 +392,9 @@ impl XcpClientMeasurementObject {
             daq: 0,
             odt: 0,
             offset: 0,
+            measured: false,
+            conversion: None,
+            dim: [1, 1],
         }
     }
 
@@ -227,6 +407,22 @@ impl XcpClientMeasurementObject {
     pub fn get_a2l_type(&self) -> A2lType {
         self.a2l_type
     }
+    /// Linear conversion (factor, offset), physical value = raw value * factor + offset, None for identity
+    pub fn get_conversion(&self) -> Option<(f64, f64)> {
+        self.conversion
+    }
+    /// Array dimensions [x_dim, y_dim], [1, 1] for scalars
+    pub fn get_dim(&self) -> [u16; 2] {
+        self.dim
+    }
+    /// Number of elements
+    pub fn get_element_count(&self) -> usize {
+        self.dim[0] as usize * self.dim[1] as usize
+    }
+    /// Size of the measurement object in bytes, the size of an ODT entry
+    pub fn get_size(&self) -> usize {
+        self.a2l_type.size * self.get_element_count()
+    }
 }
 
 //--------------------------------------------------------------------------------------------------------------------------------------------------
@@ -256,12 +452,58 @@ pub trait XcpTextDecoder {
 
 /// DAQ information
 /// Describes a single ODT entry
+/// Arrays (VAL_BLK) have dim > 1, a2l_type is the type of an element, typedef instances and blobs have type encoding Blob
 #[derive(Debug)]
 pub struct OdtEntry {
     pub name: String,
     pub a2l_type: A2lType,
     pub a2l_addr: A2lAddr,
-    pub offset: u16, // offset from data start, not including daq header and timestamp
+    pub odt: u8,                        // ODT of the DAQ list, signals exceeding one DTO are split over several ODTs
+    pub offset: u16,                    // offset from data start of the ODT, not including daq header and timestamp
+    pub conversion: Option<(f64, f64)>, // Linear conversion (factor, offset), e.g. of a quantized signal
+    pub dim: [u16; 2],                  // Array dimensions [x_dim, y_dim], [1, 1] for scalars
+}
+
+impl OdtEntry {
+    /// Physical value of a raw value
+    pub fn to_physical(&self, raw: f64) -> f64 {
+        self.conversion.map_or(raw, |(factor, offset)| raw * factor + offset)
+    }
+
+    /// Number of elements
+    pub fn get_element_count(&self) -> usize {
+        self.dim[0] as usize * self.dim[1] as usize
+    }
+
+    /// Size of the ODT entry in bytes
+    pub fn get_size(&self) -> usize {
+        self.a2l_type.size * self.get_element_count()
+    }
+
+    /// Check if the entry is an array of basic type
+    pub fn is_array(&self) -> bool {
+        !matches!(self.a2l_type.encoding, A2lTypeEncoding::Blob) && self.get_element_count() > 1
+    }
+
+    /// Name of element index, name._ix or name._iy_ix as used by Registry::resolve_instance, the name for scalars
+    pub fn get_element_name(&self, index: usize) -> String {
+        let [x_dim, y_dim] = self.dim;
+        if y_dim > 1 {
+            format!("{}._{}_{}", self.name, index / x_dim as usize, index % x_dim as usize)
+        } else if x_dim > 1 {
+            format!("{}._{}", self.name, index)
+        } else {
+            self.name.clone()
+        }
+    }
+
+    /// Raw values of the elements from the data of the ODT entry, empty for blobs and typedef instances or if data is too short
+    pub fn decode_elements(&self, data: &[u8]) -> Vec<f64> {
+        if matches!(self.a2l_type.encoding, A2lTypeEncoding::Blob) || data.len() < self.get_size() {
+            return Vec::new();
+        }
+        data[..self.get_size()].chunks(self.a2l_type.size).map(|bytes| value_to_f64(self.a2l_type, bytes)).collect()
+    }
 }
 
 pub trait XcpDaqDecoder {
@@ -276,9 +518,17 @@ pub trait XcpDaqDecoder {
     /// Measurement stop
     fn stop(&mut self) {}
 
+    /// A spare DAQ list has been configured (session added) or stopped (session removed, empty odt_entries) while DAQ is running
+    /// Spare DAQ lists are passed to start with empty ODT entries
+    fn update_daq_list(&mut self, _daq: u16, _odt_entries: Vec<OdtEntry>) {}
+
     /// Set measurement timestamp resolution in ns per raw timestamp tick and DAQ header size (2 (ODTB/DAQB or 4 (ODTB,_,DAQW))
     fn set_daq_properties(&mut self, timestamp_resolution: u64, daq_header_size: u8);
 
+    /// Set the size of the DAQ timestamp in the first ODT of a DAQ list (0 = no timestamps, 1, 2 or 4)
+    /// Called after set_daq_properties, decoders assuming 4 byte timestamps may ignore it
+    fn set_daq_timestamp_size(&mut self, _timestamp_size: u8) {}
+
     /// Get the event count
     fn get_event_count(&self) -> usize {
         0
@@ -290,6 +540,82 @@ pub trait XcpDaqDecoder {
     }
 }
 
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpSegmentInfo, XcpPageProperties
+
+/// Calibration segment standard information from GET_SEGMENT_INFO
+#[derive(Debug, Clone, Copy)]
+pub struct XcpSegmentInfo {
+    pub max_pages: u8,
+    pub addr_ext: u8,
+    pub max_mapping: u8,
+    pub compression: u8,
+    pub encryption: u8,
+}
+
+/// Calibration page properties from GET_PAGE_INFO
+#[derive(Debug, Clone, Copy)]
+pub struct XcpPageProperties {
+    pub properties: u8, // PAGE_PROPERTIES, 2 bits each for ECU access, XCP read access and XCP write access
+    pub init_segment: u8,
+}
+
+impl XcpPageProperties {
+    /// Page is writable by XCP, with or without ECU access
+    pub fn is_xcp_writable(&self) -> bool {
+        self.properties & 0x30 != 0
+    }
+
+    /// Page is readable by XCP, with or without ECU access
+    pub fn is_xcp_readable(&self) -> bool {
+        self.properties & 0x0C != 0
+    }
+
+    /// Page is accessed by the ECU, with or without XCP access
+    pub fn is_ecu_accessible(&self) -> bool {
+        self.properties & 0x03 != 0
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpEventInfo
+
+/// Event channel information from GET_DAQ_EVENT_INFO
+#[derive(Debug, Clone)]
+pub struct XcpEventInfo {
+    pub name: String,
+    pub properties: u8, // DAQ_EVENT_PROPERTIES
+    pub max_daq_list: u8,
+    pub time_cycle: u8,
+    pub time_unit: u8,
+    pub priority: u8,
+}
+
+impl XcpEventInfo {
+    const DAQ: u8 = 0x04;
+    const STIM: u8 = 0x08;
+
+    /// Variables of this event can be measured, servers which report neither DAQ nor STIM are assumed to support DAQ
+    pub fn is_daq(&self) -> bool {
+        self.properties & XcpEventInfo::DAQ != 0 || self.properties & XcpEventInfo::STIM == 0
+    }
+
+    /// Variables of this event can be stimulated
+    pub fn is_stim(&self) -> bool {
+        self.properties & XcpEventInfo::STIM != 0
+    }
+
+    /// Data consistency of the event, bit 6 DAQ, bit 7 EVENT, none of both ODT
+    pub fn get_consistency(&self) -> McEventConsistency {
+        match self.properties >> 6 {
+            0 => McEventConsistency::Odt,
+            1 => McEventConsistency::Daq,
+            2 => McEventConsistency::Event,
+            _ => McEventConsistency::None,
+        }
+    }
+}
+
 //--------------------------------------------------------------------------------------------------------------------------------------------------
 // Type to control the receive task sent over the receive task control channel
 
@@ -354,15 +680,20 @@ pub struct XcpClient {
     pub protocol_version: u16,
     pub transport_layer_version: u16,
     pub comm_mode_optional: u8,
+    pub max_bs: u8,     // Master block mode, maximum number of packets of a block
+    pub min_st: u8,     // Master block mode, minimum separation time between the packets of a block in 100 us
+    pub queue_size: u8, // Interleaved mode, maximum number of pending commands
     pub driver_version: u8,
     pub max_segments: u8,
     pub freeze_supported: bool,
     pub max_events: u16,
+    pub capabilities: XcpCapabilities,
 
     pub registry: Option<xcp_registry::Registry>,
 
     timestamp_resolution_ns: u64,
     daq_header_size: u8,
+    daq_start: Option<(std::time::SystemTime, u64)>, // UTC wall clock and DAQ clock in ns at measurement start
 
     bind_addr: SocketAddr,
     dest_addr: SocketAddr,
@@ -373,6 +704,7 @@ pub struct XcpClient {
     tx_task_control: Option<mpsc::Sender<XcpTaskControl>>,
     task_control: XcpTaskControl,
     daq_decoder: Option<Arc<Mutex<dyn XcpDaqDecoder>>>,
+    daq_lost: Arc<AtomicU64>, // DAQ packets lost, from the transport layer counter
     ctr: u16,
 
     calibration_object_list: Vec<XcpClientCalibrationObject>,
@@ -395,10 +727,14 @@ impl XcpClient {
             tx_task_control: None,
             task_control: XcpTaskControl::new(),
             daq_decoder: None,
+            daq_lost: Arc::new(AtomicU64::new(0)),
             ctr: 0,
             resources: 0,
             comm_mode_basic: 0,
             comm_mode_optional: 0,
+            max_bs: 0,
+            min_st: 0,
+            queue_size: 0,
             driver_version: 0,
             max_cto_size: 0,
             max_dto_size: 0,
@@ -407,8 +743,10 @@ impl XcpClient {
             freeze_supported: false,
             protocol_version: 0,
             transport_layer_version: 0,
+            capabilities: XcpCapabilities::new(),
             timestamp_resolution_ns: 1,
             daq_header_size: 4,
+            daq_start: None,
             registry: None,
             calibration_object_list: Vec::new(),
             measurement_object_list: Vec::new(),
@@ -479,6 +817,7 @@ impl XcpClient {
         mut rx_daq_decoder: Receiver<XcpTaskControl>,
         decode_serv_text: impl XcpTextDecoder,
         decode_daq: Arc<Mutex<impl XcpDaqDecoder>>,
+        daq_lost: Arc<AtomicU64>,
     ) -> Result<(), Box<dyn Error>> {
         let mut ctr_last: u16 = 0;
         let mut ctr_first: bool = true;
@@ -545,6 +884,7 @@ impl XcpClient {
                                     ctr_first = false;
                                 } else if ctr != ctr_last.wrapping_add(1) {
                                     ctr_lost += ctr.wrapping_sub(ctr_last) as u32;
+                                    daq_lost.fetch_add(ctr.wrapping_sub(ctr_last.wrapping_add(1)) as u64, Ordering::Relaxed);
 
                                 }
                                 ctr_last = ctr;
@@ -619,13 +959,33 @@ impl XcpClient {
     // Send a XCP command and wait for the response
     // @@@@ Must be &mut self because of the mpsc::Receiver
     async fn send_command(&mut self, cmd_bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
-        //
-        // Send command
+        self.send_command_only(cmd_bytes).await?;
+        self.receive_response(cmd_bytes[4]).await
+    }
+
+    // Send a typed XCP command and wait for the response, the command is logged with its parameter names
+    async fn send_typed<C: XcpTypedCommand>(&mut self, cmd: &C) -> Result<Vec<u8>, Box<dyn Error>> {
+        let res = self.send_command(&cmd.to_packet()).await;
+        match &res {
+            Ok(data) => trace!("xcp_command: {} -> {:?}", cmd, data),
+            Err(e) => debug!("xcp_command: {} -> {}", cmd, e),
+        }
+        res
+    }
+
+    // Send a XCP command without waiting for the response, for block transfers and interleaved mode
+    async fn send_command_only(&mut self, cmd_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
         let socket = self.socket.as_ref().unwrap();
         socket.send_to(cmd_bytes, self.dest_addr).await?;
+        match format_command(&cmd_bytes[4..]) {
+            Some(cmd) => debug!("xcp_command: sent {}", cmd),
+            None => debug!("xcp_command: sent command = {:?}", cmd_bytes),
+        }
+        Ok(())
+    }
 
-        debug!("xcp_command: sent command = {:?}", cmd_bytes);
-
+    // Wait for the next response of the command with command code command_code
+    async fn receive_response(&mut self, command_code: u8) -> Result<Vec<u8>, Box<dyn Error>> {
         // Wait for response channel with timeout
         let res = timeout(CMD_TIMEOUT, self.rx_cmd_resp.as_mut().unwrap().recv()).await; // rx channel
         match res {
@@ -640,7 +1000,7 @@ impl XcpClient {
                             }
                             0xFE => {
                                 // XCP negative response, return error code with XcpError
-                                Err(Box::new(XcpError::new(data[1], cmd_bytes[4])) as Box<dyn Error>)
+                                Err(Box::new(XcpError::new(data[1], command_code)) as Box<dyn Error>)
                             }
                             _ => {
                                 panic!("xcp_command: bug in receive_task");
@@ -650,13 +1010,13 @@ impl XcpClient {
                     None => {
                         // Empty response, channel has been closed because receive task terminated
                         info!("xcp_command: receive_task terminated");
-                        Err(Box::new(XcpError::new(ERROR_TASK_TERMINATED, cmd_bytes[4])) as Box<dyn Error>)
+                        Err(Box::new(XcpError::new(ERROR_TASK_TERMINATED, command_code)) as Box<dyn Error>)
                     }
                 }
             }
             Err(_) => {
                 // Timeout, return with XcpError
-                Err(Box::new(XcpError::new(ERROR_CMD_TIMEOUT, cmd_bytes[4])) as Box<dyn Error>)
+                Err(Box::new(XcpError::new(ERROR_CMD_TIMEOUT, command_code)) as Box<dyn Error>)
             }
         }
     }
@@ -701,16 +1061,19 @@ impl XcpClient {
             let (tx_daq, rx_daq) = mpsc::channel(3);
             self.tx_task_control = Some(tx_daq); // tx XCP DAQ control channel
             let daq_decoder_clone = Arc::clone(&daq_decoder);
+            let daq_lost = Arc::clone(&self.daq_lost);
             self.receive_task = Some(tokio::spawn(async move {
-                let _res = XcpClient::receive_task(socket, tx_resp, rx_daq, text_decoder, daq_decoder_clone).await;
+                let _res = XcpClient::receive_task(socket, tx_resp, rx_daq, text_decoder, daq_decoder_clone, daq_lost).await;
             }));
             tokio::time::sleep(Duration::from_millis(100)).await; // wait for the receive task to start
         }
 
         // Connect
         debug!("XCP CONNECT");
-        let data = self.send_command(XcpCommandBuilder::new(CC_CONNECT).add_u8(connect_mode).build()).await?;
-        assert!(data.len() >= 8);
+        self.capabilities = XcpCapabilities::new();
+        self.daq_lost.store(0, Ordering::Relaxed);
+        let data = self.send_typed(&Connect { mode: connect_mode }).await?;
+        check_response_len(&data, 8, CC_CONNECT)?;
         let resources = data[1];
         let comm_mode_basic = data[2];
         let max_cto_size: u8 = data[3];
@@ -729,30 +1092,50 @@ impl XcpClient {
         );
 
         // Get version info
-        let data = self.send_command(XcpCommandBuilder::new(CC_GET_VERSION).add_u8(0).build()).await?;
-        self.protocol_version = (data[2] as u16) << 8 | data[3] as u16;
-        self.transport_layer_version = (data[4] as u16) << 8 | data[5] as u16;
-        debug!(
-            "XCP GET_VERSION -> protocol_version=0x{:04X} transport_layer_version=0x{:04X}",
-            self.protocol_version, self.transport_layer_version
-        );
+        // Optional, if not supported, the major versions from CONNECT are used
+        match self
+            .send_command(XcpCommandBuilder::new(CC_GET_VERSION).add_u8(0).build())
+            .await
+            .and_then(|data| check_response_len(&data, 6, CC_GET_VERSION).map(|_| data))
+        {
+            Ok(data) => {
+                self.protocol_version = (data[2] as u16) << 8 | data[3] as u16;
+                self.transport_layer_version = (data[4] as u16) << 8 | data[5] as u16;
+                debug!(
+                    "XCP GET_VERSION -> protocol_version=0x{:04X} transport_layer_version=0x{:04X}",
+                    self.protocol_version, self.transport_layer_version
+                );
+            }
+            Err(e) => {
+                self.protocol_version <<= 8;
+                self.transport_layer_version <<= 8;
+                self.capabilities.get_version = false;
+                self.capabilities.degrade(format!("GET_VERSION failed ({}), using major versions from CONNECT", e));
+            }
+        }
 
         // Get comm mode info
-        if self.comm_mode_basic & 0x80 != 0 {
+        if self.comm_mode_basic & CMB_OPTIONAL != 0 {
             let data = self.send_command(XcpCommandBuilder::new(CC_GET_COMM_MODE_INFO).add_u8(0).build()).await?;
-            self.comm_mode_optional = data[2]; // Master block mode and interleaved mode not supported yet
+            check_response_len(&data, 8, CC_GET_COMM_MODE_INFO)?;
+            self.comm_mode_optional = data[2];
+            self.max_bs = data[4];
+            self.min_st = data[5];
+            self.queue_size = data[6];
             self.driver_version = data[7];
             debug!(
-                "XCP GET_COMM_MODE_INFO -> comm_mode_optional=0x{:02X} driver_version=0x{:02X}",
-                self.comm_mode_optional, self.driver_version
+                "XCP GET_COMM_MODE_INFO -> comm_mode_optional=0x{:02X} max_bs={} min_st={} queue_size={} driver_version=0x{:02X}",
+                self.comm_mode_optional, self.max_bs, self.min_st, self.queue_size, self.driver_version
             );
         }
 
         // Get calibration page count and freeze support
-        let res = self.send_command(XcpCommandBuilder::new(CC_GET_PAGE_PROCESSOR_INFO).add_u8(0).build()).await;
+        let res = self
+            .send_command(XcpCommandBuilder::new(CC_GET_PAGE_PROCESSOR_INFO).add_u8(0).build())
+            .await
+            .and_then(|data| check_response_len(&data, 3, CC_GET_PAGE_PROCESSOR_INFO).map(|_| data));
         match res {
             Ok(data) => {
-                assert!(data.len() >= 3);
                 self.max_segments = data[1];
                 self.freeze_supported = (data[2] & 0x01) != 0;
             }
@@ -784,11 +1167,22 @@ impl XcpClient {
         assert!(self.is_connected());
 
         // Initialize DAQ clock
-        self.time_correlation_properties().await?; // Set 64 bit response format for GET_DAQ_CLOCK
+        // Set 64 bit response format for GET_DAQ_CLOCK, fall back to the legacy 32 bit response format
+        if let Err(e) = self.time_correlation_properties().await {
+            self.capabilities.time_correlation = false;
+            self.capabilities.degrade(format!("TIME_CORRELATION_PROPERTIES failed ({}), GET_DAQ_CLOCK is 32 bit", e));
+        }
         self.timestamp_resolution_ns = self.get_daq_resolution_info().await?;
 
         // Set the DAQ decoder
-        daq_decoder.lock().set_daq_properties(self.timestamp_resolution_ns, self.daq_header_size);
+        {
+            let mut d = daq_decoder.lock();
+            d.set_daq_properties(self.timestamp_resolution_ns, self.daq_header_size);
+            d.set_daq_timestamp_size(self.capabilities.daq_timestamp_size);
+        }
+        if self.capabilities.is_degraded() {
+            info!("XCP server connected with {} degraded capabilities", self.capabilities.get_degraded().len());
+        }
 
         // Keep the the DAQ decoder for measurement start
         self.daq_decoder = Some(daq_decoder);
@@ -830,59 +1224,89 @@ impl XcpClient {
 
     //------------------------------------------------------------------------
     // Get server identification
-    // Returns (size, name) where name is only set if the server returned the name in the response, otherwise the caller must do an upload to get the data
-    pub async fn get_id(&mut self, id_type: u8) -> Result<(u32, Option<String>), Box<dyn Error>> {
-        assert!(id_type == IDT_VECTOR_ELF_UPLOAD || id_type == IDT_ASAM_UPLOAD || id_type == IDT_ASAM_NAME || id_type == IDT_ASCII || id_type == IDT_ASAM_EPK); // others not supported yet
-
-        let data = self.send_command(XcpCommandBuilder::new(CC_GET_ID).add_u8(id_type).build()).await?;
-        assert_eq!(data[0], 0xFF);
-        let mode = data[1]; // 0 = data by upload, 1 = data in response
-
-        // Decode size
-        let mut size = 0u32;
-        for i in (4..8).rev() {
-            size = (size << 8) | (data[i] as u32);
+    // GET_ID responds with mode 1 and the data in the response, or with mode 0 and the MTA set to the data for UPLOAD
+    // Size 0 means the identification type is not available on the server
+
+    // Send GET_ID, returns the data in the response in mode 1, None in mode 0, and the size of the data
+    async fn get_id_response(&mut self, id_type: u8) -> Result<(u32, Option<Vec<u8>>), Box<dyn Error>> {
+        let data = self.send_typed(&GetId { id_type }).await?;
+        if data.len() < 8 {
+            error!("GET_ID type={} -> response too short", id_type);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>);
         }
-        debug!("GET_ID mode={} -> size = {}", id_type, size);
-
-        // Data ready for upload
+        let mode = data[1] & 0x01; // 0 = data by upload, 1 = data in response
+        let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        debug!("GET_ID type={} -> mode={} size={}", id_type, mode, size);
         if mode == 0 {
-            // Upload the result immediately, if size fits in one upload command
-            if size < self.max_cto_size as u32 {
-                let data = self.upload(size as u8).await?;
-                let name = String::from_utf8(data[1..=(size as usize)].to_vec());
-                match name {
-                    Ok(name) => {
-                        debug!("  -> text result = {}", name);
-                        Ok((0, Some(name)))
-                    }
-                    Err(_) => {
-                        error!("GET_ID mode={} -> invalid string {:?}", id_type, data);
-                        Err(Box::new(XcpError::new(CRC_CMD_SYNTAX, CC_GET_ID)) as Box<dyn Error>)
-                    }
-                }
-            } else {
-                // Return size for later upload
-                Ok((size, None))
+            return Ok((size, None));
+        }
+        match data.get(8..8 + size as usize) {
+            Some(bytes) => Ok((size, Some(bytes.to_vec()))),
+            None => {
+                error!("GET_ID type={} -> response shorter than size {}", id_type, size);
+                Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>)
             }
         }
-        // Data in response
-        else {
-            // Decode string
-            let name = String::from_utf8(data[8..(size as usize + 8)].to_vec());
-            match name {
-                Ok(name) => {
-                    debug!("  -> text result = {}", name);
-                    Ok((0, Some(name)))
-                }
-                Err(_) => {
-                    error!("GET_ID mode={} -> invalid string {:?}", id_type, data);
-                    Err(Box::new(XcpError::new(CRC_CMD_SYNTAX, CC_GET_ID)) as Box<dyn Error>)
-                }
+    }
+
+    // Get server identification
+    // Returns (size, name) where name is only set if the server returned the name in the response or it fits into one upload,
+    // otherwise the caller must do an upload to get the data
+    pub async fn get_id(&mut self, id_type: u8) -> Result<(u32, Option<String>), Box<dyn Error>> {
+        let (size, data) = self.get_id_response(id_type).await?;
+        let data = match data {
+            Some(data) => data,
+            // Upload the result immediately, if size fits in one upload command
+            None if size > 0 && size < u32::from(self.max_cto_size) => {
+                let data = self.upload(u8::try_from(size)?).await?;
+                data[1..=(size as usize)].to_vec()
+            }
+            // Return size for later upload
+            None => return Ok((size, None)),
+        };
+        match String::from_utf8(data) {
+            Ok(name) => {
+                debug!("  -> text result = {}", name);
+                Ok((0, Some(name)))
+            }
+            Err(e) => {
+                error!("GET_ID type={} -> invalid string {:?}", id_type, e.as_bytes());
+                Err(Box::new(XcpError::new(CRC_CMD_SYNTAX, CC_GET_ID)) as Box<dyn Error>)
             }
         }
     }
 
+    /// Get the identification data of id_type (one of IDT_xxx), in the response or by upload, empty if not available
+    pub async fn get_id_data(&mut self, id_type: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (size, data) = self.get_id_response(id_type).await?;
+        if let Some(data) = data {
+            return Ok(data);
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        let mut remaining = size;
+        while remaining > 0 {
+            let n = if remaining >= u32::from(self.max_cto_size) {
+                self.max_cto_size - 1
+            } else {
+                u8::try_from(remaining)?
+            };
+            remaining -= u32::from(n);
+            let res = self.upload(n).await?;
+            trace!("xcp_client.upload: {} bytes = {:?}", res.len(), res);
+            data.extend_from_slice(&res[1..=n as usize]);
+        }
+        Ok(data)
+    }
+
+    /// Get the identification text of id_type (one of IDT_xxx), None if not available
+    pub async fn get_id_text(&mut self, id_type: u8) -> Result<Option<String>, Box<dyn Error>> {
+        let data = self.get_id_data(id_type).await?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8(data)?))
+    }
+
     //------------------------------------------------------------------------
     // Execute a XCP command with no other parameters
     pub async fn command(&mut self, command_code: u8) -> Result<Vec<u8>, Box<dyn Error>> {
@@ -894,29 +1318,27 @@ impl XcpClient {
 
     pub async fn get_ecu_page(&mut self, segment: u8) -> Result<u8, Box<dyn Error>> {
         let mode = CAL_PAGE_MODE_ECU;
-        let data = self.send_command(XcpCommandBuilder::new(CC_GET_CAL_PAGE).add_u8(mode).add_u8(segment).build()).await?;
+        let data = self.send_typed(&GetCalPage { mode, segment }).await?;
         let page = if data[3] != 0 { 1 } else { 0 };
         Ok(page)
     }
 
     pub async fn get_xcp_page(&mut self, segment: u8) -> Result<u8, Box<dyn Error>> {
         let mode = CAL_PAGE_MODE_XCP;
-        let data = self.send_command(XcpCommandBuilder::new(CC_GET_CAL_PAGE).add_u8(mode).add_u8(segment).build()).await?;
+        let data = self.send_typed(&GetCalPage { mode, segment }).await?;
         let page = if data[3] != 0 { 1 } else { 0 };
         Ok(page)
     }
 
     pub async fn set_ecu_page(&mut self, page: u8) -> Result<(), Box<dyn Error>> {
         let mode = CAL_PAGE_MODE_ECU | 0x80; // All segments
-        self.send_command(XcpCommandBuilder::new(CC_SET_CAL_PAGE).add_u8(mode).add_u8(0).add_u8(page).build())
-            .await?;
+        self.send_typed(&SetCalPage { mode, segment: 0, page }).await?;
         Ok(())
     }
 
     pub async fn set_xcp_page(&mut self, page: u8) -> Result<(), Box<dyn Error>> {
         let mode = CAL_PAGE_MODE_XCP | 0x80; // All segments
-        self.send_command(XcpCommandBuilder::new(CC_SET_CAL_PAGE).add_u8(mode).add_u8(0).add_u8(page).build())
-            .await?;
+        self.send_typed(&SetCalPage { mode, segment: 0, page }).await?;
         Ok(())
     }
 
@@ -925,8 +1347,7 @@ impl XcpClient {
 
     pub async fn set_mta(&mut self, addr_ext: u8, addr: u32) -> Result<(), Box<dyn Error>> {
         trace!("set_mta addr={}:{:08X}", addr_ext, addr);
-        self.send_command(XcpCommandBuilder::new(CC_SET_MTA).add_u8(0).add_u8(0).add_u8(addr_ext).add_u32(addr).build())
-            .await?;
+        self.send_typed(&SetMta { addr_ext, addr }).await?;
         Ok(())
     }
 
@@ -947,22 +1368,20 @@ impl XcpClient {
     }
     pub async fn short_upload(&mut self, addr: u32, ext: u8, size: u8) -> Result<Vec<u8>, Box<dyn Error>> {
         trace!("short_upload addr={}:{:08X},{}", ext, addr, size);
-        let data = self
-            .send_command(XcpCommandBuilder::new(CC_SHORT_UPLOAD).add_u8(size).add_u8(0).add_u8(ext).add_u32(addr).build())
-            .await?;
+        let data = self.send_typed(&ShortUpload { size, addr_ext: ext, addr }).await?;
         Ok(data)
     }
 
     pub async fn upload(&mut self, size: u8) -> Result<Vec<u8>, Box<dyn Error>> {
         trace!("upload size={}", size);
-        let data = self.send_command(XcpCommandBuilder::new(CC_UPLOAD).add_u8(size).build()).await?;
+        let data = self.send_typed(&Upload { size }).await?;
         Ok(data)
     }
 
     pub async fn download(&mut self, data_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
         let n = data_bytes.len();
         trace!("download len={}, data={:?}", n, data_bytes);
-        if n >= (self.max_cto_size - 2) as usize {
+        if n > (self.max_cto_size - 2) as usize {
             return Err(Box::new(XcpError::new(CRC_CMD_SYNTAX, CC_DOWNLOAD)) as Box<dyn Error>);
         }
         self.send_command(XcpCommandBuilder::new(CC_DOWNLOAD).add_u8(n as u8).add_u8_slice(data_bytes).build())
@@ -981,40 +1400,9 @@ impl XcpClient {
         Ok(())
     }
 
-    //------------------------------------------------------------------------
-    // XCP memory access services, upload and download of larger data blocks
-
-    // Upload a memory block of block_size bytes from the XCP server
-    pub async fn upload_memory_block(&mut self, block_size: u32) -> Result<Vec<u8>, Box<dyn Error>> {
-        trace!("upload_memory_block block_size={}", block_size);
-
-        let mut size = block_size;
-        let mut result = Vec::new();
-        while size > 0 {
-            let n = if size >= self.max_cto_size as u32 { self.max_cto_size - 1 } else { size as u8 };
-            size -= n as u32;
-            let data = self.upload(n).await?;
-            result.extend_from_slice(&data[1..=n as usize]);
-        }
-        Ok(result)
-    }
-
-    // Download a memory block of data_bytes to the XCP server
-    pub async fn download_memory_block(&mut self, data_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
-        let mut block_size = data_bytes.len();
-        trace!("download_memory_block block_size={}", block_size);
-        let mut pos = 0;
-        while block_size > 0 {
-            let n = if block_size >= self.max_cto_size as usize - 1 {
-                self.max_cto_size as usize - 2
-            } else {
-                block_size
-            };
-            self.download(&data_bytes[pos..(pos + n)]).await?;
-            block_size -= n;
-            pos += n;
-        }
-        Ok(())
+    /// Get the number of lost DAQ packets since connect, detected from gaps in the transport layer counter
+    pub fn get_daq_lost(&self) -> u64 {
+        self.daq_lost.load(Ordering::Relaxed)
     }
 
     //------------------------------------------------------------------------
@@ -1024,40 +1412,34 @@ impl XcpClient {
     pub async fn get_segment_info(&mut self, segment_number: u8) -> Result<(u8, u32, u16, String), Box<dyn Error>> {
         //addr
         let data = self
-            .send_command(
-                XcpCommandBuilder::new(CC_GET_SEGMENT_INFO)
-                    .add_u8(0) // get basic address info
-                    .add_u8(segment_number)
-                    .add_u8(0) // get addr
-                    .add_u8(0)
-                    .build(),
-            )
+            .send_typed(&GetSegmentInfo {
+                mode: 0,
+                segment: segment_number,
+                info: 0,
+                mapping_index: 0,
+            })
             .await?;
 
         let addr = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
         // Length
         let data = self
-            .send_command(
-                XcpCommandBuilder::new(CC_GET_SEGMENT_INFO)
-                    .add_u8(0) // get basic address info
-                    .add_u8(segment_number)
-                    .add_u8(1) // get length
-                    .add_u8(0)
-                    .build(),
-            )
+            .send_typed(&GetSegmentInfo {
+                mode: 0,
+                segment: segment_number,
+                info: 1,
+                mapping_index: 0,
+            })
             .await?;
         let length = u32::from_le_bytes([data[4], data[5], data[6], data[7]]).try_into().unwrap();
 
         // Name
         let data = self
-            .send_command(
-                XcpCommandBuilder::new(CC_GET_SEGMENT_INFO)
-                    .add_u8(0) // get standard info
-                    .add_u8(segment_number)
-                    .add_u8(2) // get name
-                    .add_u8(0)
-                    .build(),
-            )
+            .send_typed(&GetSegmentInfo {
+                mode: 0,
+                segment: segment_number,
+                info: 2,
+                mapping_index: 0,
+            })
             .await?;
         let name_length: u8 = u32::from_le_bytes([data[4], data[5], data[6], data[7]]).try_into().unwrap();
         let data = self.upload(name_length).await?;
@@ -1078,33 +1460,144 @@ impl XcpClient {
     /// Get page info
     pub async fn get_page_info(&mut self, segment_number: u8, page_number: u8) -> Result<Vec<u8>, Box<dyn Error>> {
         let data = self
-            .send_command(
-                XcpCommandBuilder::new(CC_GET_PAGE_INFO)
-                    .add_u8(0) // Reserved
-                    .add_u8(segment_number)
-                    .add_u8(page_number)
-                    .build(),
-            )
+            .send_typed(&GetPageInfo {
+                segment: segment_number,
+                page: page_number,
+            })
             .await?;
         Ok(data)
     }
 
+    /// Get segment standard info (mode 1), number of pages, address extension, number of address mappings, compression and encryption method
+    pub async fn get_segment_standard_info(&mut self, segment_number: u8) -> Result<XcpSegmentInfo, Box<dyn Error>> {
+        let data = self
+            .send_typed(&GetSegmentInfo {
+                mode: 1,
+                segment: segment_number,
+                info: 0,
+                mapping_index: 0,
+            })
+            .await?;
+        if data.len() < 6 {
+            error!("GET_SEGMENT_INFO: standard info response of segment {} too short", segment_number);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_SEGMENT_INFO)) as Box<dyn Error>);
+        }
+        Ok(XcpSegmentInfo {
+            max_pages: data[1],
+            addr_ext: data[2],
+            max_mapping: data[3],
+            compression: data[4],
+            encryption: data[5],
+        })
+    }
+
+    /// Get segment mapping info (mode 2) of a mapping, info 0 is the source address, 1 the destination address, 2 the length
+    pub async fn get_segment_mapping_info(&mut self, segment_number: u8, mapping_index: u8, info: u8) -> Result<u32, Box<dyn Error>> {
+        let data = self
+            .send_typed(&GetSegmentInfo {
+                mode: 2,
+                segment: segment_number,
+                info,
+                mapping_index,
+            })
+            .await?;
+        if data.len() < 8 {
+            error!("GET_SEGMENT_INFO: mapping info response of segment {} too short", segment_number);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_SEGMENT_INFO)) as Box<dyn Error>);
+        }
+        Ok(u32::from_le_bytes([data[4], data[5], data[6], data[7]]))
+    }
+
+    /// Get the page properties and the init segment of a page
+    pub async fn get_page_properties(&mut self, segment_number: u8, page_number: u8) -> Result<XcpPageProperties, Box<dyn Error>> {
+        let data = self.get_page_info(segment_number, page_number).await?;
+        if data.len() < 3 {
+            error!("GET_PAGE_INFO: response of segment {} page {} too short", segment_number, page_number);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_PAGE_INFO)) as Box<dyn Error>);
+        }
+        Ok(XcpPageProperties {
+            properties: data[1],
+            init_segment: data[2],
+        })
+    }
+
+    /// Enable or disable the freeze mode of a segment, segments in freeze mode are frozen with store_cal
+    pub async fn set_freeze_mode(&mut self, segment_number: u8, enable: bool) -> Result<(), Box<dyn Error>> {
+        if !self.freeze_supported {
+            warn!("SET_SEGMENT_MODE: freeze mode not supported by the server");
+        }
+        let mode = if enable { SEGMENT_MODE_FREEZE } else { 0 };
+        self.send_typed(&SetSegmentMode { mode, segment: segment_number }).await?;
+        Ok(())
+    }
+
+    /// Check if the freeze mode of a segment is enabled
+    pub async fn get_freeze_mode(&mut self, segment_number: u8) -> Result<bool, Box<dyn Error>> {
+        let data = self.send_typed(&GetSegmentMode { segment: segment_number }).await?;
+        if data.len() < 3 {
+            error!("GET_SEGMENT_MODE: response of segment {} too short", segment_number);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_SEGMENT_MODE)) as Box<dyn Error>);
+        }
+        Ok((data[2] & SEGMENT_MODE_FREEZE) != 0)
+    }
+
+    /// Freeze all segments in freeze mode with SET_REQUEST STORE_CAL_REQ, the server persists their working page as reference page
+    pub async fn store_cal(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&SetRequest {
+            mode: SET_REQUEST_STORE_CAL_REQ,
+            session_configuration_id: 0,
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Copy a calibration page, page 0 is the working page, page 1 the reference page
+    /// Copying the reference page to the working page reinitializes the working page, copying the working page to the reference page freezes it
+    pub async fn copy_cal_page(&mut self, src_segment: u8, src_page: u8, dst_segment: u8, dst_page: u8) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&CopyCalPage {
+            src_segment,
+            src_page,
+            dst_segment,
+            dst_page,
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Re-initialize the working page of a segment, COPY_CAL_PAGE from the reference page (default page) to the working page
+    pub async fn init_working_page(&mut self, segment_number: u8) -> Result<(), Box<dyn Error>> {
+        self.copy_cal_page(segment_number, 1, segment_number, 0).await
+    }
+
+    /// Check if the server granted the CAL/PAG resource on CONNECT
+    /// Without CAL/PAG, the server rejects downloads with CRC_ACCESS_DENIED
+    pub fn is_cal_available(&self) -> bool {
+        self.resources & RM_CAL_PAG != 0
+    }
+
     //------------------------------------------------------------------------
     // XCP DAQ services
 
     /// Get DAQ clock timestamp resolution in ns
     pub async fn get_daq_processor_info(&mut self) -> Result<(), Box<dyn Error>> {
-        let data = self.send_command(XcpCommandBuilder::new(CC_GET_DAQ_PROCESSOR_INFO).build()).await?;
+        let data = self.send_typed(&GetDaqProcessorInfo {}).await?;
         let mut c = Cursor::new(&data[1..]);
 
         let daq_properties = ReadBytesExt::read_u8(&mut c)?;
-        assert!((daq_properties & 0x10) == 0x10, "DAQ timestamps must be available");
+        if (daq_properties & 0x10) == 0 {
+            self.capabilities.daq_timestamps = false;
+            self.capabilities.daq_timestamp_size = 0;
+            self.capabilities.degrade("DAQ timestamps not available, measurement without timestamps".to_string());
+        }
         let max_daq = ReadBytesExt::read_u16::<LittleEndian>(&mut c)?;
         self.max_events = ReadBytesExt::read_u16::<LittleEndian>(&mut c)?;
         let min_daq = ReadBytesExt::read_u8(&mut c)?;
         let daq_key_byte = ReadBytesExt::read_u8(&mut c)?;
         self.daq_header_size = (daq_key_byte >> 6) + 1;
-        assert!(self.daq_header_size == 4 || self.daq_header_size == 2, "DAQ header type must be ODT_FIL_DAQW or ODT_DAQB");
+        if self.daq_header_size == 1 {
+            self.capabilities.daq = false;
+            self.capabilities.degrade("DAQ header type absolute ODT number is not supported, DAQ disabled".to_string());
+        }
 
         debug!(
             "GET_DAQ_PROPERTIES daq_properties = 0x{:0X}, max_daq = {}, max_event = {}, min_daq = {}, daq_key_byte = 0x{:0X} (header_size={})",
@@ -1114,94 +1607,96 @@ impl XcpClient {
     }
 
     pub async fn get_daq_event_info(&mut self, event_id: u16) -> Result<String, Box<dyn Error>> {
-        let data = self.send_command(XcpCommandBuilder::new(CC_GET_DAQ_EVENT_INFO).add_u8(0).add_u16(event_id).build()).await?;
-        let event_name_len = data[3];
+        Ok(self.get_event_info(event_id).await?.name)
+    }
+
+    /// Get name, direction, consistency, cycle time and priority of an event with GET_DAQ_EVENT_INFO
+    pub async fn get_event_info(&mut self, event_id: u16) -> Result<XcpEventInfo, Box<dyn Error>> {
+        let data = self.send_typed(&GetDaqEventInfo { event: event_id }).await?;
+        let (properties, max_daq_list, event_name_len, time_cycle, time_unit, priority) = (data[1], data[2], data[3], data[4], data[5], data[6]);
         let data = self.upload(event_name_len).await?;
         let res = String::from_utf8(data[1..=(event_name_len as usize)].to_vec());
         match res {
-            Ok(event_name) => {
-                return Ok(event_name);
-            }
+            Ok(name) => Ok(XcpEventInfo {
+                name,
+                properties,
+                max_daq_list,
+                time_cycle,
+                time_unit,
+                priority,
+            }),
             Err(_) => Err(Box::new(XcpError::new(CRC_CMD_SYNTAX, CC_GET_DAQ_EVENT_INFO)) as Box<dyn Error>),
         }
     }
 
     async fn free_daq(&mut self) -> Result<(), Box<dyn Error>> {
-        self.send_command(XcpCommandBuilder::new(CC_FREE_DAQ).build()).await?;
+        self.send_typed(&FreeDaq {}).await?;
         Ok(())
     }
 
     async fn alloc_daq(&mut self, count: u16) -> Result<(), Box<dyn Error>> {
-        self.send_command(XcpCommandBuilder::new(CC_ALLOC_DAQ).add_u8(0).add_u16(count).build()).await?;
+        self.send_typed(&AllocDaq { daq_count: count }).await?;
         Ok(())
     }
 
     async fn alloc_odt(&mut self, daq: u16, odt: u8) -> Result<(), Box<dyn Error>> {
-        self.send_command(XcpCommandBuilder::new(CC_ALLOC_ODT).add_u8(0).add_u16(daq).add_u8(odt).build()).await?;
+        self.send_typed(&AllocOdt { daq, odt_count: odt }).await?;
         Ok(())
     }
 
     async fn alloc_odt_entries(&mut self, daq: u16, odt: u8, count: u8) -> Result<(), Box<dyn Error>> {
-        self.send_command(XcpCommandBuilder::new(CC_ALLOC_ODT_ENTRY).add_u8(0).add_u16(daq).add_u8(odt).add_u8(count).build())
-            .await?;
+        self.send_typed(&AllocOdtEntry { daq, odt, entry_count: count }).await?;
         Ok(())
     }
 
     async fn set_daq_ptr(&mut self, daq: u16, odt: u8, idx: u8) -> Result<(), Box<dyn Error>> {
-        self.send_command(XcpCommandBuilder::new(CC_SET_DAQ_PTR).add_u8(0).add_u16(daq).add_u8(odt).add_u8(idx).build())
-            .await?;
+        self.send_typed(&SetDaqPtr { daq, odt, entry: idx }).await?;
         Ok(())
     }
 
     async fn write_daq(&mut self, ext: u8, addr: u32, len: u8) -> Result<(), Box<dyn Error>> {
-        self.send_command(
-            XcpCommandBuilder::new(CC_WRITE_DAQ)
-                .add_u8(0) // bit offset
-                .add_u8(len)
-                .add_u8(ext)
-                .add_u32(addr)
-                .build(),
-        )
+        self.send_typed(&WriteDaq {
+            bit_offset: 0,
+            size: len,
+            addr_ext: ext,
+            addr,
+        })
         .await?;
         Ok(())
     }
 
     async fn set_daq_list_mode(&mut self, daq: u16, eventchannel: u16) -> Result<(), Box<dyn Error>> {
         const XCP_DAQ_MODE_TIMESTAMP: u8 = 0x10; // Timestamp always on, no other mode supported by XCPlite
-        let mode: u8 = XCP_DAQ_MODE_TIMESTAMP;
+        let mode: u8 = if self.capabilities.daq_timestamps { XCP_DAQ_MODE_TIMESTAMP } else { 0x00 };
         let priority = 0x00; // Always use priority 0, no DAQ list flush for specific events, priorization supported by XCPlite
-        self.send_command(
-            XcpCommandBuilder::new(CC_SET_DAQ_LIST_MODE)
-                .add_u8(mode)
-                .add_u16(daq)
-                .add_u16(eventchannel)
-                .add_u8(1) // prescaler
-                .add_u8(priority)
-                .build(),
-        )
+        self.send_typed(&SetDaqListMode {
+            mode,
+            daq,
+            event: eventchannel,
+            prescaler: 1,
+            priority,
+        })
         .await?;
         Ok(())
     }
 
     // Select DAQ list
     async fn select_daq_list(&mut self, daq: u16) -> Result<(), Box<dyn Error>> {
-        self.send_command(XcpCommandBuilder::new(CC_START_STOP_DAQ_LIST).add_u8(2).add_u16(daq).build()).await?;
+        self.send_typed(&StartStopDaqList { mode: 2, daq }).await?;
         Ok(())
     }
 
     // Prepare, start selected, stop all
     async fn prepare_selected_daq_lists(&mut self) -> Result<(), Box<dyn Error>> {
-        self.send_command(XcpCommandBuilder::new(CC_START_STOP_SYNCH).add_u8(3 /* prepare selected */).build())
-            .await?;
+        self.send_typed(&StartStopSynch { mode: 3, /* prepare selected */ }).await?;
         Ok(())
     }
     async fn start_selected_daq_lists(&mut self) -> Result<(), Box<dyn Error>> {
-        self.send_command(XcpCommandBuilder::new(CC_START_STOP_SYNCH).add_u8(1 /* start selected */).build())
-            .await?;
+        self.send_typed(&StartStopSynch { mode: 1, /* start selected */ }).await?;
         Ok(())
     }
     async fn stop_all_daq_lists(&mut self) -> Result<(), Box<dyn Error>> {
-        self.send_command(XcpCommandBuilder::new(CC_START_STOP_SYNCH).add_u8(0).build()).await?;
+        self.send_typed(&StartStopSynch { mode: 0 }).await?;
         Ok(())
     }
 
@@ -1214,14 +1709,11 @@ impl XcpClient {
         let properties: u8 = 0;
         let cluster_id: u16 = 0;
         let _data = self
-            .send_command(
-                XcpCommandBuilder::new(CC_TIME_CORRELATION_PROPERTIES)
-                    .add_u8(request)
-                    .add_u8(properties)
-                    .add_u8(0)
-                    .add_u16(cluster_id)
-                    .build(),
-            )
+            .send_typed(&TimeCorrelationProperties {
+                set_properties: request,
+                get_properties_request: properties,
+                cluster_id,
+            })
             .await?;
         debug!("TIME_CORRELATION_PROPERIES set response format to SERVER_CONFIG_RESPONSE_FMT_ADVANCED");
         Ok(())
@@ -1229,7 +1721,7 @@ impl XcpClient {
 
     /// Get DAQ clock timestamp resolution in ns
     pub async fn get_daq_resolution_info(&mut self) -> Result<u64, Box<dyn Error>> {
-        let data = self.send_command(XcpCommandBuilder::new(CC_GET_DAQ_RESOLUTION_INFO).build()).await?;
+        let data = self.send_typed(&GetDaqResolutionInfo {}).await?;
         let mut c = Cursor::new(&data[1..]);
 
         let granularity_daq = ReadBytesExt::read_u8(&mut c)?;
@@ -1239,9 +1731,30 @@ impl XcpClient {
         let timestamp_mode = ReadBytesExt::read_u8(&mut c)?;
         let timestamp_ticks = ReadBytesExt::read_u16::<LittleEndian>(&mut c)?;
 
-        assert!(granularity_daq == 0x01, "support only 1 byte DAQ granularity");
-        assert!(timestamp_mode & 0x07 == 0x04, "support only 32 bit DAQ timestamps");
-        assert!(timestamp_mode & 0x08 == 0x08, "support only fixed DAQ timestamps");
+        if granularity_daq != 0x01 {
+            self.capabilities.degrade(format!(
+                "DAQ granularity is {}, only ODT entries with a multiple of this size are possible",
+                granularity_daq
+            ));
+        }
+        self.capabilities.daq_granularity = granularity_daq;
+        self.capabilities.daq_max_odt_entry_size = max_size_daq;
+        if self.capabilities.daq_timestamps {
+            let timestamp_size = timestamp_mode & 0x07;
+            if timestamp_size != 1 && timestamp_size != 2 && timestamp_size != 4 {
+                self.capabilities.daq_timestamps = false;
+                self.capabilities.daq_timestamp_size = 0;
+                self.capabilities
+                    .degrade(format!("DAQ timestamp size {} not supported, measurement without timestamps", timestamp_size));
+            } else {
+                if timestamp_size != 4 {
+                    self.capabilities
+                        .degrade(format!("DAQ timestamp size is {} byte, timestamps wrap around more frequently", timestamp_size));
+                }
+                self.capabilities.daq_timestamp_size = timestamp_size;
+            }
+            self.capabilities.daq_timestamp_fixed = timestamp_mode & 0x08 == 0x08;
+        }
 
         // Calculate timestamp resolution in ns per tick
         let mut timestamp_unit = timestamp_mode >> 4; // 1ns=0, 10ns=1, 100ns=2, 1us=3, 10us=4, 100us=5, 1ms=6, 10ms=7, 100ms=8, 1s=9
@@ -1261,7 +1774,7 @@ impl XcpClient {
 
     // Get DAQ clock raw value in ticks of timestamp_resolution ns
     async fn get_daq_clock_raw(&mut self) -> Result<u64, Box<dyn Error>> {
-        let data = self.send_command(XcpCommandBuilder::new(CC_GET_DAQ_CLOCK).build()).await?;
+        let data = self.send_typed(&GetDaqClock {}).await?;
         let mut c = Cursor::new(&data[2..]);
 
         // Trigger info and payload format
@@ -1272,7 +1785,11 @@ impl XcpClient {
         let payload_fmt = ReadBytesExt::read_u8(&mut c)?;
 
         // Timestamp
-        let timestamp64 = if payload_fmt == 1 {
+        let timestamp64 = if !self.capabilities.time_correlation {
+            // Legacy response format, 32 bit slave clock at offset 4
+            let mut c = Cursor::new(&data[4..]);
+            ReadBytesExt::read_u32::<LittleEndian>(&mut c)? as u64
+        } else if payload_fmt == 1 {
             // 32 bit slave clock
             ReadBytesExt::read_u32::<LittleEndian>(&mut c)? as u64
         } else if payload_fmt == 2 {
@@ -1295,11 +1812,12 @@ impl XcpClient {
 
     //-------------------------------------------------------------------------------------------------
     // ELF upload
+    // Use verify_elf_signature to check an ELF file against the target
 
     pub async fn upload_elf_file<P: AsRef<std::path::Path>>(&mut self, elf_path: &P) -> Result<(), Box<dyn Error>> {
-        // Send XCP GET_ID IDT_VECTOR_ELF_UPLOAD command to set MTA
-        let (file_size, _) = self.get_id(IDT_VECTOR_ELF_UPLOAD).await?;
-        if file_size == 0 {
+        // Send XCP GET_ID IDT_VECTOR_ELF_UPLOAD command and upload the file
+        let data = self.get_id_data(IDT_VECTOR_ELF_UPLOAD).await?;
+        if data.is_empty() {
             error!("ELF file not available, GET_ID returned size 0");
             return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>);
         }
@@ -1309,31 +1827,22 @@ impl XcpClient {
             warn!("ELF file {} already exists, overwriting", elf_path.as_ref().display());
         }
 
-        // Upload the ELF file
         info!("Upload ELF to {}", elf_path.as_ref().display());
-        let file = std::fs::File::create(elf_path)?;
-        let mut writer = std::io::BufWriter::new(file);
-        let mut size = file_size;
-        while size > 0 {
-            let n = if size >= self.max_cto_size as u32 { self.max_cto_size - 1 } else { size as u8 };
-            size -= n as u32;
-            let data = self.upload(n).await?;
-            trace!("xcp_client.upload: {} bytes = {:?}", data.len(), data);
-            writer.write_all(&data[1..=n as usize])?;
-        }
-        writer.flush()?;
-        debug!("ELF upload completed, {} bytes loaded", file_size);
+        std::fs::write(elf_path, &data)?;
+        debug!("ELF upload completed, {} bytes loaded", data.len());
 
         Ok(())
     }
 
     //-------------------------------------------------------------------------------------------------
     // A2L upload
+    // Use A2lManager to upload and load the A2L into the registry
 
+    // Upload the A2L file with GET_ID IDT_ASAM_UPLOAD into a2l_path
     pub async fn upload_a2l_file<P: AsRef<std::path::Path>>(&mut self, a2l_path: &P) -> Result<(), Box<dyn Error>> {
-        // Send XCP GET_ID 4 command to set MTA
-        let (file_size, _) = self.get_id(IDT_ASAM_UPLOAD).await?;
-        if file_size == 0 {
+        // Send XCP GET_ID 4 command and upload the file
+        let data = self.get_id_data(IDT_ASAM_UPLOAD).await?;
+        if data.is_empty() {
             error!("A2L file not available, GET_ID 4 returned size 0");
             return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>);
         }
@@ -1343,53 +1852,32 @@ impl XcpClient {
             warn!("A2L file {} already exists, overwriting", a2l_path.as_ref().display());
         }
 
-        // Upload the A2L file
-        info!("Upload A2L to {}.a2l", a2l_path.as_ref().display());
-        let file = std::fs::File::create(a2l_path)?;
-        let mut writer = std::io::BufWriter::new(file);
-        let mut size = file_size;
-        while size > 0 {
-            let n = if size >= self.max_cto_size as u32 { self.max_cto_size - 1 } else { size as u8 };
-            size -= n as u32;
-            let data = self.upload(n).await?;
-            trace!("xcp_client.upload: {} bytes = {:?}", data.len(), data);
-            writer.write_all(&data[1..=n as usize])?;
-        }
-        writer.flush()?;
-        debug!("A2L upload completed, {} bytes loaded", file_size);
+        info!("Upload A2L to {}", a2l_path.as_ref().display());
+        std::fs::write(a2l_path, &data)?;
+        debug!("A2L upload completed, {} bytes loaded", data.len());
 
         Ok(())
     }
 
-    // Get the A2L via XCP upload and GET_ID4 (IDT_ASAM_UPLOAD) and load it into the registry
-    pub async fn upload_a2l_into_registry<P: AsRef<std::path::Path>>(&mut self, a2l_path: &P, reg: &mut xcp_registry::Registry) -> Result<(), Box<dyn Error>> {
-        // Upload the A2L file
-        self.upload_a2l_file(&a2l_path).await?;
+    // Get the A2L name of the server with GET_ID IDT_ASAM_NAME, without extension
+    pub async fn get_a2l_name(&mut self) -> Result<String, Box<dyn Error>> {
+        match self.get_id(IDT_ASAM_NAME).await? {
+            (_, Some(name)) if !name.is_empty() => Ok(name),
+            _ => {
+                error!("A2L name not available, GET_ID IDT_ASAM_NAME returned no name");
+                Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>)
+            }
+        }
+    }
 
-        // Load the A2L file into the registry
-        // @@@@ TODO xcp_client does not support arrays, instances and typedefs yet, flatten the registry and mangle the names
-        reg.load_a2l(&a2l_path, true, true, true, true)?;
-        info!(
-            " A2L file contains {} instances, {} events and {} calibration segments",
-            reg.instance_list.len(),
-            reg.event_list.len(),
-            reg.cal_seg_list.len()
-        );
-        Ok(())
+    /// Get the A2L file name with path on the server with GET_ID IDT_ASAM_PATH, None if not available
+    pub async fn get_a2l_path(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        self.get_id_text(IDT_ASAM_PATH).await
     }
 
-    // Get the A2L via XCP upload and GET_ID4 (IDT_ASAM_UPLOAD) and load it into the registry
-    pub fn load_a2l_file_into_registry<P: AsRef<std::path::Path>>(&mut self, a2l_path: &P, reg: &mut xcp_registry::Registry) -> Result<(), Box<dyn Error>> {
-        // Load the A2L file into the registry
-        // @@@@ TODO xcp_client does not support arrays, instances and typedefs yet, flatten the registry and mangle the names
-        reg.load_a2l(&a2l_path, true, true, true, true)?;
-        info!(
-            " A2L file contains {} instances, {} events and {} calibration segments",
-            reg.instance_list.len(),
-            reg.event_list.len(),
-            reg.cal_seg_list.len()
-        );
-        Ok(())
+    /// Get the URL of the A2L file with GET_ID IDT_ASAM_URL, None if not available
+    pub async fn get_a2l_url(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        self.get_id_text(IDT_ASAM_URL).await
     }
 
     pub fn get_epk(&self) -> Option<&str> {
@@ -1403,10 +1891,20 @@ impl XcpClient {
         info!("Reading event and segment information from connected XCP server:");
 
         // Get event information
+        // STIM only events are marked, they are not measurable and have no measurement groups in the A2L
         for i in 0..self.max_events {
-            let name = self.get_daq_event_info(i).await?;
-            info!(" Event {}: {}", i, name);
-            reg.event_list.add_event(McEvent::new(name, 0, i, 0)).unwrap();
+            let event_info = self.get_event_info(i).await?;
+            let direction = match (event_info.is_daq(), event_info.is_stim()) {
+                (true, true) => "DAQ_STIM",
+                (false, true) => "STIM",
+                _ => "DAQ",
+            };
+            info!(" Event {}: {} {} consistency={}", i, event_info.name, direction, event_info.get_consistency());
+            let mut event = McEvent::new(event_info.name.clone(), 0, i, 0);
+            event.stim = event_info.is_daq() && event_info.is_stim();
+            event.stim_only = !event_info.is_daq();
+            event.consistency = event_info.get_consistency();
+            reg.event_list.add_event(event).unwrap();
         }
 
         // Get segment information
@@ -1434,14 +1932,19 @@ impl XcpClient {
         self.registry.as_ref().unwrap()
     }
 
+    // Names of typedef members are included, e.g. "cal_seg.test_ints.test_u8"
     pub fn find_characteristics(&self, expr: &str) -> Vec<String> {
         let registry = self.registry.as_ref().unwrap();
-        registry.instance_list.find_instances_regex(expr, xcp_registry::McObjectType::Characteristic, None)
+        registry
+            .get_flattened_instance_list()
+            .find_instances_regex(expr, xcp_registry::McObjectType::Characteristic, None)
     }
 
     pub fn find_measurements(&self, expr: &str) -> Vec<String> {
         let registry = self.registry.as_ref().unwrap();
-        registry.instance_list.find_instances_regex(expr, xcp_registry::McObjectType::Measurement, None)
+        registry
+            .get_flattened_instance_list()
+            .find_instances_regex(expr, xcp_registry::McObjectType::Measurement, None)
     }
 
     //------------------------------------------------------------------------
@@ -1456,7 +1959,8 @@ impl XcpClient {
     /// name may be a regular expression matching exactly one characteristic
     pub async fn create_calibration_object(&mut self, name: &str) -> Result<XcpCalibrationObjectHandle, Box<dyn Error>> {
         let registry = self.registry.as_ref().unwrap();
-        match registry.instance_list.get_instance(name, xcp_registry::McObjectType::Characteristic, None) {
+        // Typedef members and array elements are resolved by name, e.g. "cal_seg.test_ints.test_u8" or "cal_seg.curve._3"
+        match registry.resolve_instance(name, xcp_registry::McObjectType::Characteristic, None) {
             None => {
                 error!("Characteristic {} not found", name);
                 Err(Box::new(XcpError::new(ERROR_NOT_FOUND, 0)) as Box<dyn Error>)
@@ -1464,20 +1968,30 @@ impl XcpClient {
             Some(instance) => {
                 let (ext, addr) = instance.get_address().get_a2l_addr(registry);
                 let a2l_addr: A2lAddr = A2lAddr { ext, addr, event: None };
+                // Typedef instances and blobs are a single blob element, the size of a blob is unknown
+                let (size, dim) = match instance.value_type() {
+                    McValueType::TypeDef(typedef_name) => (registry.typedef_list.find_typedef(typedef_name).map_or(0, |t| t.size), [1, 1]),
+                    McValueType::Blob(_) => (0, [1, 1]),
+                    _ => (instance.value_size(), instance.get_dim_type().get_dim()),
+                };
                 let a2l_type: A2lType = A2lType {
-                    size: instance.value_size(),
+                    size,
                     encoding: instance.value_type().into(),
                 };
                 let a2l_limits: A2lLimits = A2lLimits {
-                    lower: instance.get_min().unwrap(),
-                    upper: instance.get_max().unwrap(),
+                    lower: instance.get_min().unwrap_or(f64::MIN),
+                    upper: instance.get_max().unwrap_or(f64::MAX),
                 };
-                let mut o = XcpClientCalibrationObject::new(instance.get_name(), a2l_addr, a2l_type, a2l_limits);
-                let size = o.get_type.size;
-                assert!(size < 256, "xcp_client currently supports only <256 byte values");
-                if self.is_connected() {
-                    let resp = self.short_upload(o.a2l_addr.addr, o.a2l_addr.ext, size as u8).await?;
-                    o.value = resp[1..=o.get_type.size].to_vec();
+                let mut o = XcpClientCalibrationObject::new(name, a2l_addr, a2l_type, a2l_limits);
+                o.dim = dim;
+                let size = o.get_size();
+                if self.is_connected() && size > 0 {
+                    let Ok(size) = u8::try_from(size) else {
+                        error!("{}: size {} not supported, xcp_client currently supports only <256 byte values", name, size);
+                        return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+                    };
+                    let resp = self.short_upload(o.a2l_addr.addr, o.a2l_addr.ext, size).await?;
+                    o.value = resp[1..=size as usize].to_vec();
                     trace!("upload {}: addr = {:?} type = {:?} limit={:?} value={:?}\n", name, a2l_addr, a2l_type, a2l_limits, o.value);
                 }
                 self.calibration_object_list.push(o);
@@ -1486,79 +2000,80 @@ impl XcpClient {
         }
     }
 
+    /// Write a scalar calibration object, the value must be within the A2L limits
+    /// Arrays, typedef instances and blobs are refused with ERROR_TYPE_MISMATCH
     pub async fn set_value_u64(&mut self, handle: XcpCalibrationObjectHandle, value: u64) -> Result<(), Box<dyn Error>> {
         let obj = &self.calibration_object_list[handle.0];
-        if (value as f64) > obj.a2l_limits.upper || (value as f64) < obj.a2l_limits.lower {
-            return Err(Box::new(XcpError::new(ERROR_LIMIT, 0)) as Box<dyn Error>);
-        }
+        obj.check_scalar_write(u64_to_limit_f64(value))?;
         let size: usize = obj.get_type.size;
-        let slice = &value.to_le_bytes()[0..size];
-        self.short_download(obj.a2l_addr.addr, obj.a2l_addr.ext, slice).await?;
-        self.calibration_object_list[handle.0].set_value(slice);
-        Ok(())
+        self.download_value(handle, &value.to_le_bytes()[0..size]).await
     }
     pub async fn set_value_i64(&mut self, handle: XcpCalibrationObjectHandle, value: i64) -> Result<(), Box<dyn Error>> {
         let obj = &self.calibration_object_list[handle.0];
-        if (value as f64) > obj.a2l_limits.upper || (value as f64) < obj.a2l_limits.lower {
-            return Err(Box::new(XcpError::new(ERROR_LIMIT, 0)) as Box<dyn Error>);
-        }
+        obj.check_scalar_write(i64_to_limit_f64(value))?;
         let size: usize = obj.get_type.size;
-        let slice = &value.to_le_bytes()[0..size];
-        self.short_download(obj.a2l_addr.addr, obj.a2l_addr.ext, slice).await?;
-        self.calibration_object_list[handle.0].set_value(slice);
-        Ok(())
+        self.download_value(handle, &value.to_le_bytes()[0..size]).await
     }
     pub async fn set_value_f64(&mut self, handle: XcpCalibrationObjectHandle, value: f64) -> Result<(), Box<dyn Error>> {
         let obj = &self.calibration_object_list[handle.0];
-        if value > obj.a2l_limits.upper || value < obj.a2l_limits.lower {
-            return Err(Box::new(XcpError::new(ERROR_LIMIT, 0)) as Box<dyn Error>);
-        }
-        let value_size: usize = obj.get_type.size;
-        let value_type: A2lType = obj.get_type;
-        let value: u64 = match value_type {
-            A2lType {
-                size: 4,
-                encoding: A2lTypeEncoding::Float,
-            } => {
-                let v = value as f32;
-                v.to_bits() as u64
-            }
-            A2lType {
-                size: 8,
-                encoding: A2lTypeEncoding::Float,
-            } => value.to_bits(),
-            A2lType {
-                size,
-                encoding: A2lTypeEncoding::Signed,
-            } => {
-                let v = value as i64;
-                v as u64
-            }
-            A2lType {
-                size,
-                encoding: A2lTypeEncoding::Unsigned,
-            } => {
-                let v = value as u64;
-                v
-            }
-            _ => {
-                error!("set_value_f64: unsupported type {:?}", value_type);
-                return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
-            }
+        obj.check_scalar_write(value)?;
+        let Some(bytes) = f64_to_value(obj.get_type, value) else {
+            error!("set_value_f64: unsupported type {:?}", obj.get_type);
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+        };
+        self.download_value(handle, &bytes).await
+    }
+
+    /// Write element index (row major, index = iy * x_dim + ix) of an array calibration object, the value must be within the A2L limits
+    /// The change is recorded in the calibration history with the element name (name._ix or name._iy_ix)
+    pub async fn set_element_f64(&mut self, handle: XcpCalibrationObjectHandle, index: usize, value: f64) -> Result<(), Box<dyn Error>> {
+        let obj = &self.calibration_object_list[handle.0];
+        obj.check_write(index, value)?;
+        let Some(bytes) = f64_to_value(obj.get_type, value) else {
+            error!("set_element_f64: unsupported type {:?}", obj.get_type);
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+        };
+        let a2l_addr = A2lAddr {
+            addr: obj.a2l_addr.addr + u32::try_from(index * obj.get_type.size)?,
+            ..obj.a2l_addr
         };
-        let slice = &value.to_le_bytes()[0..value_size];
-        self.short_download(obj.a2l_addr.addr, obj.a2l_addr.ext, slice).await?;
-        self.calibration_object_list[handle.0].set_value(slice);
+        self.short_download(a2l_addr.addr, a2l_addr.ext, &bytes).await?;
+        self.update_cached_values(a2l_addr, &bytes);
+        Ok(())
+    }
+
+    // Download a new value to the XCP server and update the cached value
+    async fn download_value(&mut self, handle: XcpCalibrationObjectHandle, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        let a2l_addr = self.calibration_object_list[handle.0].a2l_addr;
+        self.short_download(a2l_addr.addr, a2l_addr.ext, bytes).await?;
+        self.calibration_object_list[handle.0].set_value(bytes);
         Ok(())
     }
 
+    // Update the cached values of all calibration objects containing the written memory range
+    fn update_cached_values(&mut self, a2l_addr: A2lAddr, bytes: &[u8]) {
+        for o in &mut self.calibration_object_list {
+            if o.a2l_addr.ext != a2l_addr.ext || a2l_addr.addr < o.a2l_addr.addr {
+                continue;
+            }
+            let offset = (a2l_addr.addr - o.a2l_addr.addr) as usize;
+            if let Some(value) = o.value.get_mut(offset..offset + bytes.len()) {
+                value.copy_from_slice(bytes);
+            }
+        }
+    }
+
     pub async fn read_value_u64(&mut self, index: XcpCalibrationObjectHandle) -> Result<u64, Box<dyn Error>> {
         let obj = &self.calibration_object_list[index.0];
         let a2l_addr = obj.a2l_addr;
         let get_type = obj.get_type;
         let size = obj.get_type.size;
-        assert!(size < 256, "xcp_client currently supports only <256 byte values");
-        let resp = self.short_upload(a2l_addr.addr, a2l_addr.ext, size as u8).await?;
+        let Ok(size) = u8::try_from(size) else {
+            error!("read_value_u64: value size {} exceeds the SHORT_UPLOAD limit of 255 bytes", size);
+            return Err(Box::new(XcpError::new(ERROR_LIMIT, CC_SHORT_UPLOAD)) as Box<dyn Error>);
+        };
+        let resp = self.short_upload(a2l_addr.addr, a2l_addr.ext, size).await?;
+        check_response_len(&resp, 1 + get_type.size, CC_SHORT_UPLOAD)?;
         let value = resp[1..=get_type.size].to_vec();
         self.calibration_object_list[index.0].value = value;
         Ok(self.get_value_u64(index))
@@ -1573,6 +2088,11 @@ impl XcpClient {
         let obj = &self.calibration_object_list[index.0];
         obj.get_value_i64()
     }
+    /// Physical value of element index of an array calibration object from the cached value
+    pub fn get_element_f64(&self, handle: XcpCalibrationObjectHandle, index: usize) -> Option<f64> {
+        self.calibration_object_list[handle.0].get_element_f64(index)
+    }
+
     pub fn get_value_f64(&mut self, index: XcpCalibrationObjectHandle) -> f64 {
         let obj = &self.calibration_object_list[index.0];
         let v = obj.get_value_u64();
@@ -1592,15 +2112,10 @@ impl XcpClient {
         }
     }
 
-    //------------------------------------------------------------------------
-    // XcpMeasurementObject, XcpMeasurementObjectHandle (index pointer to XcpCMeasurementObject),
-    //
-
-    /// Create a measurement object by name from the registry
-    /// name may be a regular expression matching exactly one measurement
     pub fn create_measurement_object(&mut self, name: &str) -> Option<XcpMeasurementObjectHandle> {
         let registry = self.registry.as_ref().unwrap();
-        match registry.instance_list.get_instance(name, xcp_registry::McObjectType::Measurement, None) {
+        // Typedef members and array elements are resolved by name, e.g. "struct.field" or "array._3"
+        match registry.resolve_instance(name, xcp_registry::McObjectType::Measurement, None) {
             None => {
                 debug!("Measurement {} not found", name);
                 None
@@ -1612,12 +2127,33 @@ impl XcpClient {
                     return None;
                 }
                 let event = instance.event_id().unwrap();
+                if registry.event_list.find_event_id(event).is_some_and(|e| !e.is_daq()) {
+                    error!("Measurement object {}: event {} is a STIM only event, its variables can not be measured", name, event);
+                    return None;
+                }
                 let a2l_addr: A2lAddr = A2lAddr { ext, addr, event: Some(event) };
+                // Arrays of basic types are measured as a whole, with the element type and the dimensions
+                // Typedef instances are a blob of the typedef size, the x dimension of a blob is its size in bytes
+                let dim = instance.get_dim_type().get_dim();
+                let size = match instance.value_type() {
+                    McValueType::TypeDef(typedef_name) => registry.typedef_list.find_typedef(typedef_name).map_or(0, |t| t.size),
+                    McValueType::Blob(_) => 1,
+                    _ => instance.value_size(),
+                };
+                if size == 0 {
+                    error!("Measurement object {}: size of {:?} unknown", name, instance.value_type());
+                    return None;
+                }
                 let a2l_type: A2lType = A2lType {
-                    size: instance.value_size(),
+                    size,
                     encoding: instance.value_type().into(),
                 };
-                let o = XcpClientMeasurementObject::new(name, a2l_addr, a2l_type);
+                let mut o = XcpClientMeasurementObject::new(name, a2l_addr, a2l_type);
+                o.dim = dim;
+                let mc_support_data = instance.get_mc_support_data();
+                if mc_support_data.factor.is_some() || mc_support_data.offset.is_some() {
+                    o.conversion = Some((mc_support_data.factor.unwrap_or(1.0), mc_support_data.offset.unwrap_or(0.0)));
+                }
                 debug!("Create measurement object {}: addr = {:08X} type = {:?}", name, a2l_addr.addr, a2l_type);
                 debug!("-> {:?} ", o);
                 self.measurement_object_list.push(o);
@@ -1630,6 +2166,34 @@ impl XcpClient {
         &self.measurement_object_list[handle.0]
     }
 
+    /// Names of the measurement configurations predefined in the A2L, which should be started automatically
+    pub fn get_auto_start_configs(&self) -> Vec<String> {
+        self.registry
+            .as_ref()
+            .map(|registry| registry.measurement_config_list.auto_start_configs().map(|c| c.name.to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Create the measurement objects of a measurement configuration predefined in the A2L, start with start_measurement
+    /// Signals not found are skipped, returns the number of measurement objects created
+    pub fn create_measurement_config(&mut self, name: &str) -> Result<usize, Box<dyn Error>> {
+        let Some(config) = self.registry.as_ref().and_then(|registry| registry.measurement_config_list.find_config(name)) else {
+            error!("Measurement configuration {} not found", name);
+            return Err(Box::new(XcpError::new(ERROR_NOT_FOUND, 0)) as Box<dyn Error>);
+        };
+        let measurements = config.measurements.clone();
+        let mut count = 0;
+        for m in &measurements {
+            if self.create_measurement_object(m).is_some() {
+                count += 1;
+            } else {
+                warn!("Measurement configuration {}: measurement {} not found", name, m);
+            }
+        }
+        info!("Measurement configuration {}: {} of {} signals", name, count, measurements.len());
+        Ok(count)
+    }
+
     //------------------------------------------------------------------------
     // DAQ init, start, stop
     //
@@ -1639,9 +2203,47 @@ impl XcpClient {
         self.timestamp_resolution_ns
     }
 
+    /// Get the UTC wall clock time of the last measurement start
+    pub fn get_measurement_start_utc(&self) -> Option<std::time::SystemTime> {
+        self.daq_start.map(|(utc, _)| utc)
+    }
+
+    /// Convert a DAQ timestamp in ns to UTC wall clock time, based on the last measurement start
+    pub fn daq_timestamp_to_utc(&self, timestamp_ns: u64) -> Option<std::time::SystemTime> {
+        let (utc, start_ns) = self.daq_start?;
+        if timestamp_ns >= start_ns {
+            utc.checked_add(Duration::from_nanos(timestamp_ns - start_ns))
+        } else {
+            utc.checked_sub(Duration::from_nanos(start_ns - timestamp_ns))
+        }
+    }
+
+    // Read the DAQ clock epoch (UTC in ns, DAQ clock in ns) from the calibration segment XCP_CLOCK_EPOCH_NAME of an xcp_lite server
+    // None, if the registry does not contain it or the upload fails
+    async fn read_server_clock_epoch(&mut self) -> Option<(u64, u64)> {
+        let mut values = [0u64; 2];
+        for (i, field) in ["utc_ns", "clock_ns"].iter().enumerate() {
+            let registry = self.registry.as_ref()?;
+            let name = format!("{}.{}", XCP_CLOCK_EPOCH_NAME, field);
+            let instance = registry.resolve_instance(&name, xcp_registry::McObjectType::Characteristic, None)?;
+            let (ext, addr) = instance.get_address().get_a2l_addr(registry);
+            let resp = self.short_upload(addr, ext, 8).await.ok()?;
+            values[i] = u64::from_le_bytes(resp.get(1..9)?.try_into().ok()?);
+        }
+        debug!("Server DAQ clock epoch: utc={}ns clock={}ns", values[0], values[1]);
+        Some((values[0], values[1]))
+    }
+
     /// Start DAQ
     pub async fn start_measurement(&mut self) -> Result<(), Box<dyn Error>> {
         debug!("Start measurement");
+        if !self.capabilities.daq {
+            error!("DAQ measurement not supported by this XCP server");
+            return Err(Box::new(XcpError::new(CRC_CMD_UNKNOWN, CC_START_STOP_SYNCH)) as Box<dyn Error>);
+        }
+
+        // Size of the DAQ header and timestamp in the first ODT
+        let dto_header_size = self.daq_header_size as u16 + self.capabilities.daq_timestamp_size as u16;
 
         // Init
         let signal_count = self.measurement_object_list.len();
@@ -1672,6 +2274,36 @@ impl XcpClient {
         }
         event_list.sort_by(|a, b| a.0.cmp(&b.0));
 
+        // Distribute the signals of each DAQ list (event) to ODTs, a new ODT is started when a DTO is full
+        // The first ODT of a DAQ list carries the timestamp
+        let mut daq_odts: Vec<Vec<Vec<usize>>> = Vec::with_capacity(event_list.len()); // Measurement object indices per ODT per DAQ list
+        for (event, _) in &event_list {
+            let mut entries: Vec<(usize, u16)> = Vec::new();
+            for (i, m) in self.measurement_object_list.iter().enumerate() {
+                if m.a2l_addr.event != Some(*event) {
+                    continue;
+                }
+                let Some(size) = u8::try_from(m.get_size()).ok().filter(|size| self.capabilities.check_odt_entry_size(usize::from(*size))) else {
+                    error!("{}: size {} not supported by DAQ granularity or max ODT entry size", m.name, m.get_size());
+                    return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+                };
+                entries.push((i, u16::from(size)));
+            }
+            let odts = match split_odt_entries(&entries, self.max_dto_size, dto_header_size, u16::from(self.daq_header_size)) {
+                Ok(odts) => odts,
+                Err(i) => {
+                    let m = &self.measurement_object_list[i];
+                    error!("{}: size {} exceeds the max DTO size {}", m.name, m.get_size(), self.max_dto_size);
+                    return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+                }
+            };
+            if odts.len() > MAX_ODT_COUNT {
+                error!("Event {}: {} ODTs exceed the max ODT count {} of a DAQ list", event, odts.len(), MAX_ODT_COUNT);
+                return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+            }
+            daq_odts.push(odts);
+        }
+
         // Alloc a DAQ list for each event
         assert!(event_count <= 1024, "event_count > 1024");
         let daq_count: u16 = event_count;
@@ -1679,41 +2311,36 @@ impl XcpClient {
         self.alloc_daq(daq_count).await?;
         debug!("alloc_daq count={}", daq_count);
 
-        // Alloc one ODT for each DAQ list (event)
-        // @@@@ TODO Restriction: Only one ODT per DAQ list supported yet
+        // Alloc the ODTs for each DAQ list
         for daq in 0..daq_count {
-            self.alloc_odt(daq, 1).await?;
-            debug!("Alloc daq={}, odt_count={}", daq, 1);
+            let odt_count = u8::try_from(daq_odts[daq as usize].len()).expect("ODT count checked");
+            self.alloc_odt(daq, odt_count).await?;
+            debug!("Alloc daq={}, odt_count={}", daq, odt_count);
         }
 
-        // Alloc ODT entries (signal count) for each ODT/DAQ list
+        // Alloc ODT entries (signal count) for each ODT of each DAQ list
         for daq in 0..daq_count {
-            let odt_entry_count = event_list[daq as usize].1;
-            assert!(odt_entry_count < 0x7C, "odt_entry_count >= 0x7C");
-            self.alloc_odt_entries(daq, 0, odt_entry_count as u8).await?;
-            debug!("Alloc odt_entries: daq={}, odt={}, odt_entry_count={}", daq, 0, odt_entry_count);
+            for (odt, entries) in (0u8..).zip(&daq_odts[daq as usize]) {
+                let entry_count = u8::try_from(entries.len()).expect("ODT entry count checked");
+                self.alloc_odt_entries(daq, odt, entry_count).await?;
+                debug!("Alloc odt_entries: daq={}, odt={}, odt_entry_count={}", daq, odt, entries.len());
+            }
         }
 
         // Create all ODT entries for each daq/event list and store information for the DAQ decoder
-        for daq in 0..daq_count {
-            //
-            let event = event_list[daq as usize].0;
-            let odt = 0; // Only one odt per daq list supported yet
-            let odt_entry_count = self.measurement_object_list.len();
-
-            // Create ODT entries for this daq list
+        for (daq, odts) in (0u16..).zip(&daq_odts) {
             let mut odt_entries = Vec::new();
-            let mut odt_size: u16 = 0;
-            self.set_daq_ptr(daq, odt, 0).await?;
-            for odt_entry in 0..odt_entry_count {
-                let m = &mut self.measurement_object_list[odt_entry];
-                let a2l_addr = m.a2l_addr;
-                if a2l_addr.event == Some(event) {
-                    // Only add signals for the daq list event
-                    let a2l_type: A2lType = m.a2l_type;
+            for (odt, entries) in (0u8..).zip(odts) {
+                let header_size = if odt == 0 { dto_header_size } else { u16::from(self.daq_header_size) };
+                let mut odt_size: u16 = 0;
+                self.set_daq_ptr(daq, odt, 0).await?;
+                for &i in entries {
+                    let m = &mut self.measurement_object_list[i];
+                    let (a2l_addr, a2l_type, dim, size) = (m.a2l_addr, m.a2l_type, m.dim, m.get_size());
                     m.daq = daq;
                     m.odt = odt;
-                    m.offset = odt_size + 6;
+                    m.offset = odt_size + header_size;
+                    m.measured = true;
 
                     debug!(
                         "WRITE_DAQ {} daq={}, odt={},  type={:?}, size={}, ext={}, addr=0x{:08X}, offset={}",
@@ -1721,29 +2348,26 @@ impl XcpClient {
                         daq,
                         odt,
                         a2l_type.encoding,
-                        a2l_type.size,
+                        size,
                         a2l_addr.ext,
                         a2l_addr.addr,
-                        odt_size + 6
+                        odt_size + header_size
                     );
 
                     odt_entries.push(OdtEntry {
                         name: m.name.clone(),
                         a2l_type,
                         a2l_addr,
+                        odt,
                         offset: odt_size,
+                        conversion: m.conversion,
+                        dim,
                     });
-
-                    let size = a2l_type.size;
-                    assert!(size < 256, "xcp_client currently supports only <256 byte values");
-                    self.write_daq(a2l_addr.ext, a2l_addr.addr, size as u8).await?;
-
-                    odt_size += a2l_type.size as u16;
-                    if odt_size > self.max_dto_size - 6 {
-                        return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
-                    }
+                    let size = u8::try_from(size).expect("size checked");
+                    self.write_daq(a2l_addr.ext, a2l_addr.addr, size).await?;
+                    odt_size += u16::from(size);
                 }
-            } // odt_entries
+            } // odts
 
             daq_odt_entries.push(odt_entries);
         }
@@ -1762,7 +2386,15 @@ impl XcpClient {
         self.prepare_selected_daq_lists().await?;
 
         // Reset the DAQ decoder and set measurement start time
+        // Record the UTC wall clock time of the measurement start to map DAQ timestamps to absolute time
+        // The DAQ clock epoch published by the server is used, the local wall clock only if the server does not publish it
         let daq_clock = self.get_daq_clock_raw().await?;
+        let daq_clock_ns = daq_clock * self.timestamp_resolution_ns;
+        let utc = match self.read_server_clock_epoch().await {
+            Some((utc_ns, clock_ns)) => std::time::UNIX_EPOCH + Duration::from_nanos(utc_ns.wrapping_add(daq_clock_ns.wrapping_sub(clock_ns))),
+            None => std::time::SystemTime::now(),
+        };
+        self.daq_start = Some((utc, daq_clock_ns));
         self.daq_decoder.as_ref().unwrap().lock().start(daq_odt_entries, daq_clock);
 
         // Send running=true throught the DAQ control channel to the receive task
@@ -1819,3 +2451,33 @@ impl XcpClient {
     //---------------------------------------------------------------------------------
 
 }
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod xcp_client_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_split_odt_entries() {
+        // max_dto_size 12, 8 bytes DAQ header and timestamp in the first ODT, 4 bytes DAQ header in the following ODTs
+        let entries = [(0, 4), (1, 4), (2, 8), (3, 4)];
+        assert_eq!(split_odt_entries(&entries, 12, 8, 4), Ok(vec![vec![0], vec![1], vec![2], vec![3]]));
+        assert_eq!(split_odt_entries(&entries, 16, 8, 4), Ok(vec![vec![0, 1], vec![2, 3]]));
+        assert_eq!(split_odt_entries(&[], 16, 8, 4), Ok(vec![vec![]]));
+
+        // An entry which does not fit into an empty DTO
+        assert_eq!(split_odt_entries(&[(0, 4), (7, 9)], 12, 4, 4), Err(7));
+
+        // A max DTO size smaller than the header does not underflow
+        assert_eq!(split_odt_entries(&[(5, 1)], 6, 8, 4), Err(5));
+
+        // The ODT entry count limit starts a new ODT
+        let entries: Vec<(usize, u16)> = (0..MAX_ODT_ENTRY_COUNT + 1).map(|i| (i, 1)).collect();
+        let odts = split_odt_entries(&entries, u16::MAX, 8, 4).unwrap();
+        assert_eq!(odts.len(), 2);
+        assert_eq!(odts[1], vec![MAX_ODT_ENTRY_COUNT]);
+    }
+}
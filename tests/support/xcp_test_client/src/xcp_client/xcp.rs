@@ -41,11 +41,17 @@ pub const CC_SHORT_DOWNLOAD: u8 = 0xED;
 pub const CC_SYNC: u8 = 0xFC;
 pub const CC_GET_COMM_MODE_INFO: u8 = 0xFB;
 pub const CC_GET_ID: u8 = 0xFA;
+pub const CC_GET_STATUS: u8 = 0xFD;
+pub const CC_GET_SEED: u8 = 0xF8;
+pub const CC_UNLOCK: u8 = 0xF7;
+pub const CC_SET_REQUEST: u8 = 0xF9;
 pub const CC_SET_MTA: u8 = 0xF6;
 pub const CC_UPLOAD: u8 = 0xF5;
 pub const CC_SHORT_UPLOAD: u8 = 0xF4;
+pub const CC_BUILD_CHECKSUM: u8 = 0xF3;
 pub const CC_USER: u8 = 0xF1;
 pub const CC_DOWNLOAD: u8 = 0xF0;
+pub const CC_DOWNLOAD_NEXT: u8 = 0xEF;
 pub const CC_NOP: u8 = 0xC1;
 pub const CC_SET_CAL_PAGE: u8 = 0xEB;
 pub const CC_GET_CAL_PAGE: u8 = 0xEA;
@@ -75,6 +81,18 @@ pub const CC_ALLOC_ODT_ENTRY: u8 = 0xD3;
 pub const CC_TIME_CORRELATION_PROPERTIES: u8 = 0xC6;
 pub const CC_GET_VERSION: u8 = 0xC0;
 
+// Communication modes, COMM_MODE_BASIC of CONNECT and COMM_MODE_OPTIONAL of GET_COMM_MODE_INFO
+pub const CMB_SLAVE_BLOCK_MODE: u8 = 0x40;
+pub const CMB_OPTIONAL: u8 = 0x80;
+pub const CMO_MASTER_BLOCK_MODE: u8 = 0x01;
+pub const CMO_INTERLEAVED_MODE: u8 = 0x02;
+
+// Resources, for GET_SEED, UNLOCK and the protection status of GET_STATUS
+pub const RM_CAL_PAG: u8 = 0x01;
+pub const RM_DAQ: u8 = 0x04;
+pub const RM_STIM: u8 = 0x08;
+pub const RM_PGM: u8 = 0x10;
+
 //--------------------------------------------------------------------------------------------------------------------------------------------------
 // XCP protocol definitions
 
@@ -93,9 +111,14 @@ pub const IDT_VECTOR_ELF_UPLOAD: u8 = 0xA3;
 pub const CAL_PAGE_MODE_ECU: u8 = 0x01;
 pub const CAL_PAGE_MODE_XCP: u8 = 0x02;
 
+// XCP segment mode and SET_REQUEST mode
+pub const SEGMENT_MODE_FREEZE: u8 = 0x01;
+pub const SET_REQUEST_STORE_CAL_REQ: u8 = 0x01;
+
 //--------------------------------------------------------------------------------------------------------------------------------------------------
 // XCP error type
 
+pub const ERROR_RESPONSE_LENGTH: u8 = 0xEF;
 pub const ERROR_CMD_TIMEOUT: u8 = 0xF0;
 pub const ERROR_TL_HEADER: u8 = 0xF1;
 pub const ERROR_A2L: u8 = 0xF2;
@@ -107,6 +130,11 @@ pub const ERROR_TYPE_MISMATCH: u8 = 0xF7;
 pub const ERROR_REGISTRY_EXISTS: u8 = 0xF8;
 pub const ERROR_GENERIC: u8 = 0xF9;
 pub const ERROR_NOT_FOUND: u8 = 0xFA;
+pub const ERROR_SIGNATURE_MISMATCH: u8 = 0xFB;
+pub const ERROR_INDEX_RANGE: u8 = 0xFC;
+pub const ERROR_NOT_CONFIRMED: u8 = 0xFD;
+pub const ERROR_CAL_PATCH: u8 = 0xFE;
+pub const ERROR_DAQ_LOSS: u8 = 0xFF;
 
 #[derive(Default)]
 pub struct XcpError {
@@ -121,12 +149,18 @@ impl XcpError {
     pub fn get_error_code(&self) -> u8 {
         self.code
     }
+    pub fn get_command(&self) -> u8 {
+        self.cmd
+    }
 }
 
 impl std::fmt::Display for XcpError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let cmd: XcpCommand = From::from(self.cmd);
         match self.code {
+            ERROR_RESPONSE_LENGTH => {
+                write!(f, "{cmd:?}: Command response too short")
+            }
             ERROR_CMD_TIMEOUT => {
                 write!(f, "{cmd:?}: Command response timeout")
             }
@@ -151,9 +185,27 @@ impl std::fmt::Display for XcpError {
             ERROR_LIMIT => {
                 write!(f, "Calibration value limit exceeded")
             }
+            ERROR_TYPE_MISMATCH => {
+                write!(f, "Value type does not match the calibration object")
+            }
+            ERROR_INDEX_RANGE => {
+                write!(f, "Calibration object element index out of range")
+            }
+            ERROR_NOT_CONFIRMED => {
+                write!(f, "{cmd:?}: Raw memory access not confirmed")
+            }
+            ERROR_CAL_PATCH => {
+                write!(f, "Calibration patch does not match the calibration segments")
+            }
+            ERROR_DAQ_LOSS => {
+                write!(f, "DAQ packet loss rate exceeds the threshold")
+            }
             ERROR_NOT_FOUND => {
                 write!(f, "Measurement or calibration variable not found")
             }
+            ERROR_SIGNATURE_MISMATCH => {
+                write!(f, "ELF file does not match the target software")
+            }
             ERROR_ODT_SIZE => {
                 write!(f, "ODT max size exceeded")
             }
@@ -248,15 +300,22 @@ impl std::error::Error for XcpError {}
 pub enum XcpCommand {
     Connect = CC_CONNECT as isize,
     Disconnect = CC_DISCONNECT as isize,
+    GetStatus = CC_GET_STATUS as isize,
+    GetSeed = CC_GET_SEED as isize,
+    Unlock = CC_UNLOCK as isize,
+    GetVersion = CC_GET_VERSION as isize,
     SetMta = CC_SET_MTA as isize,
     ShortUpload = CC_SHORT_UPLOAD as isize,
+    BuildChecksum = CC_BUILD_CHECKSUM as isize,
     Upload = CC_UPLOAD as isize,
     ShortDownload = CC_SHORT_DOWNLOAD as isize,
     Download = CC_DOWNLOAD as isize,
+    DownloadNext = CC_DOWNLOAD_NEXT as isize,
     User = CC_USER as isize,
     Sync = CC_SYNC as isize,
     Nop = CC_NOP as isize,
     GetId = CC_GET_ID as isize,
+    SetRequest = CC_SET_REQUEST as isize,
     SetCalPage = CC_SET_CAL_PAGE as isize,
     GetCalPage = CC_GET_CAL_PAGE as isize,
     GetPageProcessorInfo = CC_GET_PAGE_PROCESSOR_INFO as isize,
@@ -283,6 +342,7 @@ pub enum XcpCommand {
     AllocOdt = CC_ALLOC_ODT as isize,
     AllocOdtEntry = CC_ALLOC_ODT_ENTRY as isize,
     TimeCorrelationProperties = CC_TIME_CORRELATION_PROPERTIES as isize,
+    Unknown = 0, // Errors of the client, which are not related to a command
 }
 
 impl From<u8> for XcpCommand {
@@ -290,15 +350,22 @@ impl From<u8> for XcpCommand {
         match code {
             CC_CONNECT => XcpCommand::Connect,
             CC_DISCONNECT => XcpCommand::Disconnect,
+            CC_GET_STATUS => XcpCommand::GetStatus,
+            CC_GET_SEED => XcpCommand::GetSeed,
+            CC_UNLOCK => XcpCommand::Unlock,
+            CC_GET_VERSION => XcpCommand::GetVersion,
             CC_SET_MTA => XcpCommand::SetMta,
             CC_SHORT_DOWNLOAD => XcpCommand::ShortDownload,
             CC_DOWNLOAD => XcpCommand::Download,
+            CC_DOWNLOAD_NEXT => XcpCommand::DownloadNext,
             CC_SHORT_UPLOAD => XcpCommand::ShortUpload,
+            CC_BUILD_CHECKSUM => XcpCommand::BuildChecksum,
             CC_UPLOAD => XcpCommand::Upload,
             CC_USER => XcpCommand::User,
             CC_SYNC => XcpCommand::Sync,
             CC_NOP => XcpCommand::Nop,
             CC_GET_ID => XcpCommand::GetId,
+            CC_SET_REQUEST => XcpCommand::SetRequest,
             CC_SET_CAL_PAGE => XcpCommand::SetCalPage,
             CC_GET_CAL_PAGE => XcpCommand::GetCalPage,
             CC_GET_PAGE_PROCESSOR_INFO => XcpCommand::GetPageProcessorInfo,
@@ -325,10 +392,7 @@ impl From<u8> for XcpCommand {
             CC_ALLOC_ODT => XcpCommand::AllocOdt,
             CC_ALLOC_ODT_ENTRY => XcpCommand::AllocOdtEntry,
             CC_TIME_CORRELATION_PROPERTIES => XcpCommand::TimeCorrelationProperties,
-            _ => {
-                error!("Unknown command code: 0x{:02X}", code);
-                panic!("Unknown command code: 0x{:02X}", code);
-            }
+            _ => XcpCommand::Unknown,
         }
     }
 }
@@ -355,6 +419,14 @@ impl XcpCommandBuilder {
         self
     }
 
+    /// Append reserved zero bytes until the command is aligned to align bytes
+    pub fn align(&mut self, align: usize) -> &mut Self {
+        while !self.data.len().is_multiple_of(align) {
+            self.data.put_u8(0);
+        }
+        self
+    }
+
     pub fn add_u8_slice(&mut self, value: &[u8]) -> &mut Self {
         self.data.put_slice(value);
         self
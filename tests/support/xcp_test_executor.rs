@@ -149,10 +149,12 @@ impl XcpDaqDecoder for DaqDecoder {
         }
 
         assert!((daq as usize) < self.task_count);
-        assert!(odt == 0);
         if daq > self.daq_max {
             self.daq_max = daq;
         }
+        if odt > self.odt_max {
+            self.odt_max = odt;
+        }
 
         // Decode raw timestamp as u64
         // Check declining and stuck timestamps
@@ -173,8 +175,7 @@ impl XcpDaqDecoder for DaqDecoder {
             self.daq_timestamp[daq as usize] = t;
         }
 
-        // Hardcoded decoding of data (only one ODT)
-        assert!(odt == 0);
+        // Hardcoded decoding of the test signals in the first ODT, the following ODTs of a DAQ list are only counted
         if odt == 0 && data.len() >= 8 {
             // Check counter_max (+0) and counter (+4)
             let counter_max = (data[0] as u32) | ((data[1] as u32) << 8) | ((data[2] as u32) << 16) | ((data[3] as u32) << 24);
@@ -575,7 +576,7 @@ async fn test_calibration(xcp_client: &mut XcpClient, _task_cycle_us: u64) -> bo
 
         // Get address of calibration variable cal_seg.cal_test
         let registry = xcp_client.get_registry();
-        let instance_cal_test = registry.instance_list.get_instance("cal_seg.cal_test", McObjectType::Characteristic, None).unwrap();
+        let instance_cal_test = registry.resolve_instance("cal_seg.cal_test", McObjectType::Characteristic, None).unwrap();
         let addr_cal_test = instance_cal_test.get_address().get_a2l_addr(registry);
         debug!("Address of cal_seg.cal_test = {}:0x{:X}\n", addr_cal_test.0, addr_cal_test.1);
 
@@ -687,9 +688,7 @@ pub async fn test_setup(test_name: &str, task_count: usize, load_a2l: bool, uplo
             }
 
             // Upload A2L file from XCP server into test.a2l file and into a new registry
-            let mut reg = Registry::new();
-            xcp_client.upload_a2l_into_registry(&a2l_path, &mut reg).await.unwrap();
-            xcp_client.set_registry(reg);
+            A2lManager::with_path(&a2l_path).upload(&mut xcp_client).await.unwrap();
             info!("A2L file uploaded from XCP server into registry from {:?}", a2l_path);
 
             // Compare the uploaded A2L file with the backup test.a2l.bak file
@@ -711,15 +710,11 @@ pub async fn test_setup(test_name: &str, task_count: usize, load_a2l: bool, uplo
         else {
             // Send XCP GET_ID GET_ID XCP_IDT_ASAM_NAME to obtain the A2L filename
             info!("XCP GET_ID XCP_IDT_ASAM_NAME");
-            let res = xcp_client.get_id(xcp::IDT_ASAM_NAME).await;
-            let a2l_name = match res {
-                Ok((_, Some(id))) => id,
+            let a2l_name = match xcp_client.get_a2l_name().await {
+                Ok(name) => name,
                 Err(e) => {
                     panic!("GET_ID failed, Error: {}", e);
                 }
-                _ => {
-                    panic!("Empty string");
-                }
             };
             info!("A2l file name from GET_ID IDT_ASAM_NAME = {}", a2l_name);
 
@@ -0,0 +1,14 @@
+//-----------------------------------------------------------------------------
+// Library crate xcp_client
+// XCP-on-ETH and XCP-on-CAN client for xcp_lite servers, with the measurement
+// recorders, calibration file I/O, ELF tooling and the command line tool
+// (src/main.rs). The integration tests of the workspace use the slim copy of the
+// protocol core in tests/support/xcp_test_client.
+
+// This crate is a library
+#![crate_type = "lib"]
+// The library crate is named "xcp_client"
+#![crate_name = "xcp_client"]
+
+mod xcp_client;
+pub use xcp_client::*;
@@ -0,0 +1,375 @@
+//-----------------------------------------------------------------------------
+// xcp_client
+// Command line tool for xcp_lite servers
+
+// Run the tool
+// cargo run -p xcp_client -- --dest-addr 127.0.0.1:5555 --upload-a2l
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use parking_lot::Mutex;
+
+use xcp_client::*;
+
+//-----------------------------------------------------------------------------
+// Command line arguments
+
+const DEFAULT_LOG_LEVEL: u8 = 2; // Warn
+const DEFAULT_DEST_ADDR: &str = "127.0.0.1:5555";
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0:0";
+const DEFAULT_WATCH_RATE: f64 = 2.0; // Hz
+const DEFAULT_TIME_MS: u64 = 5000;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Log level (Off=0, Error=1, Warn=2, Info=3, Debug=4, Trace=5)
+    #[arg(short, long, default_value_t = DEFAULT_LOG_LEVEL)]
+    log_level: u8,
+
+    /// Disable logging and print only the results, one key=value line each, the last line is exit_code=N
+    #[arg(short, long, default_value_t = false)]
+    quiet: bool,
+
+    /// XCP server address
+    #[arg(short, long, default_value = DEFAULT_DEST_ADDR)]
+    dest_addr: SocketAddr,
+
+    /// Bind address
+    #[arg(short, long, default_value = DEFAULT_BIND_ADDR)]
+    bind_addr: SocketAddr,
+
+    /// Use TCP as transport layer, default is UDP
+    #[arg(short, long, default_value_t = false)]
+    tcp: bool,
+
+    /// Upload the A2L file from the server and load it into the registry
+    #[arg(short, long, default_value_t = false)]
+    upload_a2l: bool,
+
+    /// Load this A2L file into the registry
+    #[arg(short, long)]
+    a2l: Option<PathBuf>,
+
+    /// List the measurement and calibration objects matching this regular expression
+    #[arg(long)]
+    list: Option<String>,
+
+    /// Write the physical value of a scalar calibration object, repeatable
+    #[arg(long, num_args = 2, value_names = ["NAME", "VALUE"], action = clap::ArgAction::Append)]
+    cal: Vec<String>,
+
+    /// Apply a calibration batch file (NAME VALUE, NAME=VALUE or CSV) in one atomic calibration action and verify it
+    #[arg(long)]
+    cal_file: Option<PathBuf>,
+
+    /// List the calibration changes of this session
+    #[arg(long, default_value_t = false)]
+    history: bool,
+
+    /// Write the net calibration changes of this session to a DCM file
+    #[arg(long)]
+    dcm: Option<PathBuf>,
+
+    /// Watch the measurement and calibration objects matching this regular expression in a refreshing table
+    #[arg(long)]
+    watch: Option<String>,
+
+    /// Watch table polls per second
+    #[arg(long, default_value_t = DEFAULT_WATCH_RATE)]
+    rate: f64,
+
+    /// Measure the measurement objects matching this regular expression with DAQ and print the samples
+    #[arg(long)]
+    mea: Option<String>,
+
+    /// Record the measurement into this ASAM MDF 4.1 file
+    #[arg(long)]
+    mdf: Option<PathBuf>,
+
+    /// Record the measurement into CSV files, one file <stem>_<event>.csv per event
+    #[arg(long)]
+    csv: Option<PathBuf>,
+
+    /// With --csv, record all events into one file resampled with this period in ms
+    #[arg(long)]
+    csv_merge_ms: Option<u64>,
+
+    /// Fail the run with exit code 5, if the DAQ packet loss of the measurement exceeds this rate in parts per million
+    #[arg(long)]
+    max_loss_ppm: Option<f64>,
+
+    /// Print the EPK and segments of a calibration BIN file, does not connect to the server
+    #[arg(long, value_name = "BIN")]
+    dump_bin: Option<PathBuf>,
+
+    /// With --dump-bin, print a hex dump of each segment
+    #[arg(long, default_value_t = false)]
+    verbose: bool,
+
+    /// With --dump-bin, print JSON
+    #[arg(long, default_value_t = false)]
+    json: bool,
+
+    /// Convert a calibration BIN file to Intel HEX with the segment addresses of the --a2l file, does not connect to the server
+    #[arg(long, num_args = 2, value_names = ["BIN", "HEX"])]
+    bin2hex: Option<Vec<PathBuf>>,
+
+    /// Convert an Intel HEX file to a calibration BIN file with the segment addresses of the --a2l file, does not connect to the server
+    #[arg(long, num_args = 2, value_names = ["HEX", "BIN"])]
+    hex2bin: Option<Vec<PathBuf>>,
+
+    /// Duration of the watch or measurement in ms
+    #[arg(long, default_value_t = DEFAULT_TIME_MS)]
+    time_ms: u64,
+
+    /// Read raw memory ext:addr:len and print it as hex dump, requires --yes-i-know
+    #[arg(long, value_name = "EXT:ADDR:LEN")]
+    read_mem: Option<XcpMemoryRange>,
+
+    /// Write raw memory at ext:addr with hex bytes, requires --yes-i-know
+    #[arg(long, num_args = 2, value_names = ["EXT:ADDR", "HEXBYTES"])]
+    write_mem: Option<Vec<String>>,
+
+    /// Confirm raw memory access without type and limit checks
+    #[arg(long, default_value_t = false)]
+    yes_i_know: bool,
+}
+
+impl Args {
+    fn log_level_filter(&self) -> log::LevelFilter {
+        if self.quiet {
+            return log::LevelFilter::Off;
+        }
+        match self.log_level {
+            0 => log::LevelFilter::Off,
+            1 => log::LevelFilter::Error,
+            2 => log::LevelFilter::Warn,
+            3 => log::LevelFilter::Info,
+            4 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Decoders
+
+// SERV_TEXT messages are printed with the default implementation
+struct ServTextPrinter;
+impl XcpTextDecoder for ServTextPrinter {}
+
+// DAQ decoder forwarding to all decoders selected on the command line
+#[derive(Default)]
+struct DaqDecoders {
+    decoders: Vec<Box<dyn XcpDaqDecoder + Send>>,
+}
+
+impl XcpDaqDecoder for DaqDecoders {
+    fn decode(&mut self, lost: u32, data: &[u8]) {
+        self.decoders.iter_mut().for_each(|d| d.decode(lost, data));
+    }
+    fn start(&mut self, odt_entries: Vec<Vec<OdtEntry>>, timestamp_raw64: u64) {
+        for d in &mut self.decoders {
+            d.start(odt_entries.clone(), timestamp_raw64);
+        }
+    }
+    fn stop(&mut self) {
+        self.decoders.iter_mut().for_each(|d| d.stop());
+    }
+    fn update_daq_list(&mut self, daq: u16, odt_entries: Vec<OdtEntry>) {
+        for d in &mut self.decoders {
+            d.update_daq_list(daq, odt_entries.clone());
+        }
+    }
+    fn set_daq_properties(&mut self, timestamp_resolution: u64, daq_header_size: u8) {
+        self.decoders.iter_mut().for_each(|d| d.set_daq_properties(timestamp_resolution, daq_header_size));
+    }
+    fn set_daq_timestamp_size(&mut self, daq_timestamp_size: u8) {
+        self.decoders.iter_mut().for_each(|d| d.set_daq_timestamp_size(daq_timestamp_size));
+    }
+}
+
+//-----------------------------------------------------------------------------
+// File conversions, without server
+
+// Registry of the A2L file given with --a2l, for the calibration segment addresses
+fn load_registry(args: &Args) -> Result<xcp_registry::Registry, Box<dyn Error>> {
+    let Some(a2l) = &args.a2l else {
+        return Err("--bin2hex and --hex2bin require the A2L file with --a2l".into());
+    };
+    let mut reg = xcp_registry::Registry::new();
+    A2lManager::new().load_into(a2l, &mut reg)?;
+    Ok(reg)
+}
+
+// I/O errors of files are not connect failures, they are converted to a message with the path
+fn file_error<P: AsRef<std::path::Path>, E: Into<Box<dyn Error>>>(path: P) -> impl Fn(E) -> Box<dyn Error> {
+    move |e| {
+        let e = e.into();
+        if e.is::<std::io::Error>() {
+            format!("{}: {}", path.as_ref().display(), e).into()
+        } else {
+            e
+        }
+    }
+}
+
+// Returns true, if a file option was given
+fn run_files(args: &Args) -> Result<bool, Box<dyn Error>> {
+    if let Some(path) = &args.dump_bin {
+        let (bin, _) = XcpBinFile::read_file(path).map_err(file_error(path))?;
+        if args.json {
+            println!("{}", bin.to_json());
+        } else {
+            print!("{}", bin.dump(args.verbose));
+        }
+    }
+    if let Some(paths) = &args.bin2hex {
+        let reg = load_registry(args)?;
+        let (bin, _) = XcpBinFile::read_file(&paths[0]).map_err(file_error(&paths[0]))?;
+        let hex = bin.to_intel_hex(&reg, &IntelHexOptions::default())?;
+        std::fs::write(&paths[1], hex).map_err(file_error(&paths[1]))?;
+        info!("{} written", paths[1].display());
+    }
+    if let Some(paths) = &args.hex2bin {
+        let reg = load_registry(args)?;
+        let hex = std::fs::read_to_string(&paths[0]).map_err(file_error(&paths[0]))?;
+        let bin = XcpBinFile::from_intel_hex(&hex, &reg)?;
+        bin.write_file(&paths[1]).map_err(file_error(&paths[1]))?;
+        info!("{} written", paths[1].display());
+    }
+    Ok(args.dump_bin.is_some() || args.bin2hex.is_some() || args.hex2bin.is_some())
+}
+
+//-----------------------------------------------------------------------------
+
+async fn run(args: &Args, summary: &mut XcpRunSummary) -> Result<(), Box<dyn Error>> {
+    if run_files(args)? {
+        return Ok(());
+    }
+
+    let mut xcp_client = XcpClient::new(args.tcp, args.dest_addr, args.bind_addr);
+    let daq_decoders = Arc::new(Mutex::new(DaqDecoders::default()));
+    xcp_client.connect(0, Arc::clone(&daq_decoders), ServTextPrinter).await?;
+
+    if args.upload_a2l {
+        let path = A2lManager::new().upload(&mut xcp_client).await?;
+        info!("A2L file uploaded to {}", path.display());
+    } else if let Some(a2l) = &args.a2l {
+        A2lManager::with_path(a2l).load(&mut xcp_client).await?;
+    }
+    let need_a2l = args.list.is_some() || !args.cal.is_empty() || args.cal_file.is_some() || args.watch.is_some() || args.mea.is_some();
+    if need_a2l && xcp_client.registry.is_none() {
+        return Err("--list, --cal, --cal-file, --watch and --mea require the A2L, use --upload-a2l or --a2l".into());
+    }
+
+    if let Some(regex) = &args.list {
+        for name in xcp_client.find_measurements(regex) {
+            println!("measurement    {}", name);
+        }
+        for name in xcp_client.find_characteristics(regex) {
+            println!("characteristic {}", name);
+        }
+    }
+
+    for pair in args.cal.chunks(2) {
+        let (name, value) = (&pair[0], &pair[1]);
+        let value: f64 = value.parse().map_err(|e| format!("--cal {} {}: {}", name, value, e))?;
+        let handle = xcp_client.create_calibration_object(name).await?;
+        xcp_client.set_value_f64(handle, value).await?;
+    }
+    if let Some(cal_file) = &args.cal_file {
+        let batch = XcpCalBatch::read_file(cal_file)?;
+        let report = xcp_client.apply_cal_batch(&batch).await?;
+        if !summary.is_quiet() {
+            println!("{}", report);
+        }
+        summary.add_cal_batch_report(&report);
+    }
+    if args.history {
+        xcp_client.print_calibration_history();
+    }
+    if let Some(dcm) = &args.dcm {
+        let count = xcp_client.write_dcm_patch(dcm)?;
+        info!("{} calibration changes written to {}", count, dcm.display());
+    }
+
+    if let Some(range) = args.read_mem {
+        let data = xcp_client.read_memory(range, args.yes_i_know).await?;
+        print!("{}", dump_hex_data(range.addr, &data));
+    }
+    if let Some(write_mem) = &args.write_mem {
+        let (ext, addr) = parse_memory_address(&write_mem[0])?;
+        let data = parse_hex_bytes(&write_mem[1])?;
+        xcp_client.write_memory(ext, addr, &data, args.yes_i_know).await?;
+    }
+
+    if let Some(regex) = &args.watch {
+        xcp_client.watch(regex, args.rate, Duration::from_millis(args.time_ms)).await?;
+    }
+
+    if let Some(regex) = &args.mea {
+        let names = xcp_client.find_measurements(regex);
+        if names.is_empty() {
+            return Err(format!("--mea {}: no measurement object found", regex).into());
+        }
+        for name in &names {
+            if xcp_client.create_measurement_object(name).is_none() {
+                warn!("Measurement object {} can not be measured", name);
+            }
+        }
+        if !args.quiet {
+            daq_decoders.lock().decoders.push(Box::new(XcpDaqPrinter::new(XcpArrayDisplay::default())));
+        }
+        if let Some(mdf) = &args.mdf {
+            let recorder = XcpMdfRecorder::new(mdf).set_registry(xcp_client.get_registry());
+            daq_decoders.lock().decoders.push(Box::new(recorder));
+            summary.add("mdf_file", mdf.display());
+        }
+        if let Some(csv) = &args.csv {
+            let mode = match args.csv_merge_ms {
+                Some(ms) => XcpCsvMode::Merged(Duration::from_millis(ms)),
+                None => XcpCsvMode::PerEvent,
+            };
+            let recorder = XcpCsvRecorder::new(csv, mode).set_registry(xcp_client.get_registry());
+            daq_decoders.lock().decoders.push(Box::new(recorder));
+            summary.add("csv_file", csv.display());
+        }
+        xcp_client.start_measurement().await?;
+        tokio::time::sleep(Duration::from_millis(args.time_ms)).await;
+        xcp_client.stop_measurement().await?;
+        if let Some(max_loss_ppm) = args.max_loss_ppm {
+            summary.add_daq_loss(&xcp_client.get_daq_loss_statistics(), max_loss_ppm);
+        }
+    }
+
+    xcp_client.disconnect().await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> std::process::ExitCode {
+    let args = Args::parse();
+    env_logger::Builder::new()
+        .target(env_logger::Target::Stdout)
+        .filter_level(args.log_level_filter())
+        .format_timestamp(None)
+        .format_module_path(false)
+        .format_target(false)
+        .init();
+
+    let mut summary = XcpRunSummary::new(args.quiet);
+    if let Err(e) = run(&args, &mut summary).await {
+        summary.fail_with_error(e.as_ref());
+    }
+    summary.finish()
+}
@@ -0,0 +1,259 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module bin_check
+// Consistency check of a calibration BIN file against an A2L (registry)
+// The EPK, the segment names and the segment sizes must match, a size difference is explained with the calibration object layout of the segment
+// from the registry, the fields beyond the smaller size are listed
+// A BIN file of CalStorageBinFile does not store segment indices or event ids, the record order is the order of the first store, so these
+// can not be checked
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use xcp_registry::{McCalibrationSegment, McDimType, McValueType, Registry};
+
+use super::XcpBinFile;
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpBinDifference
+
+/// A difference between a calibration BIN file and the A2L
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XcpBinDifference {
+    /// The BIN file belongs to another build
+    Epk { bin: String, a2l: String },
+    /// Segment in the BIN file, not in the A2L
+    SegmentNotInA2l(String),
+    /// Segment in the A2L, not in the BIN file, the segment keeps its default values
+    SegmentNotInBin(String),
+    /// Segment size changed, fields are the calibration objects of the A2L layout beyond the smaller size
+    SegmentSize {
+        name: String,
+        bin_size: usize,
+        a2l_size: usize,
+        fields: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for XcpBinDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            XcpBinDifference::Epk { bin, a2l } => write!(f, "EPK '{}' does not match A2L EPK '{}', the BIN file belongs to another build", bin, a2l),
+            XcpBinDifference::SegmentNotInA2l(name) => write!(f, "segment '{}' not found in A2L, removed or renamed", name),
+            XcpBinDifference::SegmentNotInBin(name) => write!(f, "segment '{}' not in BIN file, new segment keeps its default values", name),
+            XcpBinDifference::SegmentSize { name, bin_size, a2l_size, fields } => {
+                if a2l_size > bin_size {
+                    write!(f, "segment '{}' grew by {} bytes", name, a2l_size - bin_size)?;
+                    if !fields.is_empty() {
+                        write!(f, " - fields added or moved: {}", fields.join(", "))?;
+                    }
+                    Ok(())
+                } else {
+                    write!(f, "segment '{}' shrunk by {} bytes - fields removed or resized", name, bin_size - a2l_size)
+                }
+            }
+        }
+    }
+}
+
+/// Result of a consistency check of a BIN file against an A2L
+#[derive(Debug, Default)]
+pub struct XcpBinCheckReport {
+    pub differences: Vec<XcpBinDifference>,
+}
+
+impl XcpBinCheckReport {
+    /// Check if the BIN file matches the A2L
+    pub fn is_ok(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl std::fmt::Display for XcpBinCheckReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for d in &self.differences {
+            writeln!(f, "{}", d)?;
+        }
+        write!(f, "{} differences", self.differences.len())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Segment layout
+
+// Add a calibration object and the fields of its typedef with offset and size, arrays of typedefs are not split into fields
+fn add_fields(registry: &Registry, name: String, dim_type: &McDimType, offset: usize, fields: &mut Vec<(String, usize, usize)>) {
+    let typedef = match &dim_type.value_type {
+        McValueType::TypeDef(typedef_name) => registry.typedef_list.find_typedef(typedef_name.as_str()),
+        _ => None,
+    };
+    match typedef {
+        Some(typedef) if dim_type.get_dim() == [1, 1] => {
+            for field in &typedef.fields {
+                add_fields(
+                    registry,
+                    format!("{}.{}", name, field.get_name()),
+                    field.get_dim_type(),
+                    offset + field.get_offset() as usize,
+                    fields,
+                );
+            }
+        }
+        Some(typedef) => {
+            let [x_dim, y_dim] = dim_type.get_dim();
+            fields.push((name, offset, typedef.size * x_dim as usize * y_dim as usize));
+        }
+        None => fields.push((name, offset, dim_type.get_size())),
+    }
+}
+
+// Calibration objects in a segment with offset and size, sorted by offset
+fn get_segment_fields(registry: &Registry, segment: &McCalibrationSegment) -> Vec<(String, usize, usize)> {
+    let mut fields = Vec::new();
+    for instance in &registry.instance_list {
+        if !instance.is_calibration_object() {
+            continue;
+        }
+        let address = instance.get_address();
+        let offset = if address.is_segment_relative() {
+            match address.get_calseg_name() {
+                Some(name) if name == segment.name => usize::try_from(address.get_addr_offset()).ok(),
+                _ => None,
+            }
+        } else if address.get_addr_mode().is_a2l() {
+            let (_, addr) = address.get_raw_a2l_addr();
+            addr.checked_sub(segment.addr).filter(|o| *o < segment.size).map(|o| o as usize)
+        } else {
+            None
+        };
+        if let Some(offset) = offset {
+            add_fields(registry, instance.get_name().to_string(), instance.get_dim_type(), offset, &mut fields);
+        }
+    }
+    fields.sort_by_key(|(_, offset, _)| *offset);
+    fields
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Consistency check
+
+impl XcpBinFile {
+    /// Check the BIN file against the A2L registry
+    /// The EPK must match, each segment must exist in both with the same size, a size difference lists the fields beyond the smaller size
+    pub fn check_consistency(&self, registry: &Registry) -> XcpBinCheckReport {
+        let mut report = XcpBinCheckReport::default();
+        let epk = registry.application.get_version();
+        if !epk.is_empty() && epk != self.epk {
+            report.differences.push(XcpBinDifference::Epk {
+                bin: self.epk.clone(),
+                a2l: epk.to_string(),
+            });
+        }
+        for (name, page) in &self.segments {
+            match registry.cal_seg_list.into_iter().find(|s| s.get_name() == name.as_str()) {
+                None => report.differences.push(XcpBinDifference::SegmentNotInA2l(name.clone())),
+                Some(s) if s.size as usize != page.len() => {
+                    let (bin_size, a2l_size) = (page.len(), s.size as usize);
+                    let fields = get_segment_fields(registry, s)
+                        .into_iter()
+                        .filter(|(_, offset, size)| offset + size > bin_size.min(a2l_size))
+                        .map(|(name, _, _)| name)
+                        .collect();
+                    report.differences.push(XcpBinDifference::SegmentSize {
+                        name: name.clone(),
+                        bin_size,
+                        a2l_size,
+                        fields,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        for s in &registry.cal_seg_list {
+            if self.get_segment(s.get_name()).is_none() {
+                report.differences.push(XcpBinDifference::SegmentNotInBin(s.get_name().to_string()));
+            }
+        }
+        for d in &report.differences {
+            warn!("BIN check: {}", d);
+        }
+        report
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod bin_check_tests {
+
+    use super::*;
+    use xcp_registry::{McAddress, McObjectType, McSupportData};
+
+    // Segment seg with a: u8 at 0, t: {x: u16 at 0, y: u32 at 4} at 4, b: u16 at 12
+    fn registry() -> Registry {
+        let mut reg = Registry::new();
+        reg.application.set_version("EPK_1.0", 0x8000_0000);
+        reg.cal_seg_list.add_cal_seg("seg", None, 14).unwrap();
+        let cal = McSupportData::new(McObjectType::Characteristic);
+        reg.add_typedef("T", 8).unwrap();
+        reg.add_typedef_field("T", "x", McDimType::new(McValueType::Uword, 1, 1), cal.clone(), 0).unwrap();
+        reg.add_typedef_field("T", "y", McDimType::new(McValueType::Ulong, 1, 1), cal.clone(), 4).unwrap();
+        reg.instance_list
+            .add_instance("a", McDimType::new(McValueType::Ubyte, 1, 1), cal.clone(), McAddress::new_calseg_rel("seg", 0))
+            .unwrap();
+        reg.instance_list
+            .add_instance("t", McDimType::new(McValueType::new_typedef("T"), 1, 1), cal.clone(), McAddress::new_calseg_rel("seg", 4))
+            .unwrap();
+        reg.instance_list
+            .add_instance("b", McDimType::new(McValueType::Uword, 1, 1), cal, McAddress::new_calseg_rel("seg", 12))
+            .unwrap();
+        reg
+    }
+
+    fn bin_file(epk: &str, segments: &[(&str, usize)]) -> XcpBinFile {
+        XcpBinFile {
+            epk: epk.to_string(),
+            segments: segments.iter().map(|(name, size)| (name.to_string(), vec![0; *size])).collect(),
+            signature: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_bin_check_ok() {
+        let report = bin_file("EPK_1.0", &[("seg", 14)]).check_consistency(&registry());
+        assert!(report.is_ok());
+        assert_eq!(report.to_string(), "0 differences");
+    }
+
+    #[test]
+    fn test_bin_check_differences() {
+        let report = bin_file("EPK_0.9", &[("old", 4)]).check_consistency(&registry());
+        assert_eq!(
+            report.differences,
+            vec![
+                XcpBinDifference::Epk {
+                    bin: "EPK_0.9".to_string(),
+                    a2l: "EPK_1.0".to_string()
+                },
+                XcpBinDifference::SegmentNotInA2l("old".to_string()),
+                XcpBinDifference::SegmentNotInBin("seg".to_string()),
+            ]
+        );
+        assert!(report.to_string().ends_with("3 differences"));
+    }
+
+    #[test]
+    fn test_bin_check_segment_size() {
+        // The fields beyond the smaller size, typedef instances are split into their fields
+        let report = bin_file("EPK_1.0", &[("seg", 8)]).check_consistency(&registry());
+        let [XcpBinDifference::SegmentSize { bin_size, a2l_size, fields, .. }] = report.differences.as_slice() else {
+            panic!("{}", report);
+        };
+        assert_eq!((*bin_size, *a2l_size), (8, 14));
+        assert_eq!(fields, &["t.y".to_string(), "b".to_string()]);
+        assert_eq!(report.differences[0].to_string(), "segment 'seg' grew by 6 bytes - fields added or moved: t.y, b");
+
+        let report = bin_file("EPK_1.0", &[("seg", 16)]).check_consistency(&registry());
+        assert_eq!(report.differences[0].to_string(), "segment 'seg' shrunk by 2 bytes - fields removed or resized");
+    }
+}
@@ -0,0 +1,504 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module bin_file
+// Calibration BIN files of xcp_lite (CalStorageBinFile, magic XCPCAL02 or XCPCAL01): read, write, dump, validate against the A2L and convert from and to
+// Intel HEX with the calibration segment addresses of the A2L, see hexfile
+// XCPCAL01/02 is the own format of xcp_lite, it is not the BIN persistence file of xcplib and the tools of xcplib do not read it
+// Files of little and big endian producers are read, files are always written little endian
+// The --dump-bin, --bin2hex and --hex2bin options of the command line tool work on files only, without connecting to a server
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+use xcp_registry::crc32;
+
+use super::{IntelHexOptions, MemoryImage};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpBinFile
+
+const BIN_MAGIC: &[u8; 8] = b"XCPCAL02";
+const BIN_MAGIC_V1: &[u8; 8] = b"XCPCAL01"; // Without CRC32 and signature
+
+/// Calibration BIN file, the EPK of the application and the page of each calibration segment
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct XcpBinFile {
+    pub epk: String,
+    pub segments: Vec<(String, Vec<u8>)>,
+    pub signature: Vec<u8>, // Signature of the file as read, empty if not signed
+}
+
+/// Signature status of a BIN file read without verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XcpBinSignature {
+    Unsigned,    // The file has no signature
+    NotVerified, // The file is signed, the signature was not checked, use read_file_verified
+}
+
+const BIN_FLAG_BIG_ENDIAN: u8 = 0x01; // Integers of the producer are big endian
+
+// Reader of the BIN file content, byte wise without alignment requirements, in the byte order of the producer
+// Each length is checked against the remaining data before it is used, a corrupted length field can not cause a huge allocation
+struct BinReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> BinReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let remaining = self.data.len() - self.pos;
+        if len > remaining {
+            return Err(format!(
+                "length {} at offset {} exceeds the remaining {} bytes, the file is truncated or corrupted",
+                len, self.pos, remaining
+            ));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        let b = [b[0], b[1]];
+        Ok(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    // String with u16 length, UTF-8 encoded
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        let pos = self.pos;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| format!("invalid UTF-8 at offset {}", pos))
+    }
+}
+
+// Strings are written UTF-8 with u16 length, all integers little endian
+fn write_string(data: &mut Vec<u8>, s: &str) -> Result<(), String> {
+    let len = u16::try_from(s.len()).map_err(|_| format!("{} too long", s))?;
+    data.extend_from_slice(&len.to_le_bytes());
+    data.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+impl XcpBinFile {
+    // Parse and check the CRC32 of each segment, returns the file and the length of the signed content
+    fn parse(data: &[u8]) -> Result<(XcpBinFile, usize), String> {
+        let mut reader = BinReader { data, pos: 0, big_endian: false };
+        let v1 = match reader.bytes(8)? {
+            m if m == BIN_MAGIC => false,
+            m if m == BIN_MAGIC_V1 => true,
+            _ => return Err("not a calibration BIN file, magic XCPCAL02 or XCPCAL01 expected".to_string()),
+        };
+        if !v1 {
+            let flags = reader.bytes(1)?[0];
+            if flags & !BIN_FLAG_BIG_ENDIAN != 0 {
+                return Err(format!("unknown flags 0x{:02X}, the file was written by a newer version", flags));
+            }
+            reader.big_endian = flags & BIN_FLAG_BIG_ENDIAN != 0;
+        }
+        let epk = reader.string()?;
+        let count = if v1 { None } else { Some(reader.u32()? as usize) };
+        let mut segments = Vec::new();
+        while count.map_or(reader.pos < data.len(), |count| segments.len() < count) {
+            let name = reader.string()?;
+            let size = reader.u32()? as usize;
+            let crc = if v1 { None } else { Some(reader.u32()?) };
+            let page = reader.bytes(size).map_err(|e| format!("segment {}: {}", name, e))?;
+            if let Some(crc) = crc.filter(|crc| *crc != crc32(page)) {
+                return Err(format!(
+                    "segment {}: CRC32 error, expected {:08X}, found {:08X}, the file is corrupted",
+                    name,
+                    crc,
+                    crc32(page)
+                ));
+            }
+            segments.push((name, page.to_vec()));
+        }
+        let signed_len = reader.pos;
+        let signature = if v1 {
+            Vec::new()
+        } else {
+            let len = reader.u16()? as usize;
+            reader.bytes(len)?.to_vec()
+        };
+        if reader.pos != data.len() {
+            return Err(format!("unexpected data at offset {}", reader.pos));
+        }
+        Ok((XcpBinFile { epk, segments, signature }, signed_len))
+    }
+
+    /// Parse the content of a BIN file, the CRC32 of each segment is checked, a signature is not verified
+    pub fn from_bytes(data: &[u8]) -> Result<XcpBinFile, String> {
+        XcpBinFile::parse(data).map(|(bin, _)| bin)
+    }
+
+    /// Parse the content of a signed BIN file and verify the signature with verify(content, signature), e.g. Ed25519 with the public key
+    /// Unsigned files and files with an invalid signature are rejected
+    pub fn from_bytes_verified<F: Fn(&[u8], &[u8]) -> bool>(data: &[u8], verify: F) -> Result<XcpBinFile, String> {
+        let (bin, signed_len) = XcpBinFile::parse(data)?;
+        if bin.signature.is_empty() {
+            return Err("file is not signed".to_string());
+        }
+        if !verify(&data[..signed_len], &bin.signature) {
+            return Err("invalid signature, the file has been modified or was signed with another key".to_string());
+        }
+        Ok(bin)
+    }
+
+    /// Content of a BIN file, not signed
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        self.to_bytes_signed(|_| Vec::new())
+    }
+
+    /// Content of a BIN file, signed with sign(content), e.g. Ed25519 with the private key
+    pub fn to_bytes_signed<F: Fn(&[u8]) -> Vec<u8>>(&self, sign: F) -> Result<Vec<u8>, String> {
+        let mut data = BIN_MAGIC.to_vec();
+        data.push(0); // Flags, little endian
+        write_string(&mut data, &self.epk)?;
+        let count = u32::try_from(self.segments.len()).map_err(|_| "too many segments".to_string())?;
+        data.extend_from_slice(&count.to_le_bytes());
+        for (name, page) in &self.segments {
+            write_string(&mut data, name)?;
+            let size = u32::try_from(page.len()).map_err(|_| format!("segment {} too large", name))?;
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&crc32(page).to_le_bytes());
+            data.extend_from_slice(page);
+        }
+        let signature = sign(&data);
+        let len = u16::try_from(signature.len()).map_err(|_| "signature too long".to_string())?;
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(&signature);
+        Ok(data)
+    }
+
+    /// Read a BIN file without signature verification, the CRC32 of each segment is checked
+    /// Returns the signature status, a signed file is not verified, see read_file_verified
+    pub fn read_file<P: AsRef<Path>>(path: &P) -> Result<(XcpBinFile, XcpBinSignature), Box<dyn Error>> {
+        let data = std::fs::read(path)?;
+        let bin = XcpBinFile::from_bytes(&data).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        let status = if bin.signature.is_empty() {
+            XcpBinSignature::Unsigned
+        } else {
+            warn!("{}: signature not verified", path.as_ref().display());
+            XcpBinSignature::NotVerified
+        };
+        Ok((bin, status))
+    }
+
+    /// Read a signed BIN file and verify the signature, see from_bytes_verified
+    pub fn read_file_verified<P: AsRef<Path>, F: Fn(&[u8], &[u8]) -> bool>(path: &P, verify: F) -> Result<XcpBinFile, Box<dyn Error>> {
+        let data = std::fs::read(path)?;
+        let bin = XcpBinFile::from_bytes_verified(&data, verify).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        Ok(bin)
+    }
+
+    /// Write a BIN file, not signed
+    pub fn write_file<P: AsRef<Path>>(&self, path: &P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Get the page of a calibration segment
+    pub fn get_segment(&self, name: &str) -> Option<&[u8]> {
+        self.segments.iter().find(|(n, _)| n == name).map(|(_, page)| page.as_slice())
+    }
+
+    /// Human readable dump, with verbose a hex dump of each page
+    pub fn dump(&self, verbose: bool) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "EPK: {}", self.epk);
+        if !self.signature.is_empty() {
+            let _ = writeln!(s, "Signature: {} bytes, not verified", self.signature.len());
+        }
+        for (name, page) in &self.segments {
+            let _ = writeln!(s, "Segment {}: {} bytes", name, page.len());
+            if verbose {
+                for (i, line) in page.chunks(16).enumerate() {
+                    let _ = write!(s, "  {:08X}:", i * 16);
+                    for b in line {
+                        let _ = write!(s, " {:02X}", b);
+                    }
+                    s.push('\n');
+                }
+            }
+        }
+        s
+    }
+
+    /// JSON representation with the pages as hex strings
+    pub fn to_json(&self) -> String {
+        let segments: Vec<String> = self
+            .segments
+            .iter()
+            .map(|(name, page)| {
+                let data: String = page.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("    {{ \"name\": {}, \"size\": {}, \"data\": \"{}\" }}", json_string(name), page.len(), data)
+            })
+            .collect();
+        format!("{{\n  \"epk\": {},\n  \"segments\": [\n{}\n  ]\n}}\n", json_string(&self.epk), segments.join(",\n"))
+    }
+
+    /// Check the BIN file against the A2L registry, returns a message for each difference found, see check_consistency
+    pub fn validate(&self, registry: &xcp_registry::Registry) -> Vec<String> {
+        self.check_consistency(registry).differences.iter().map(ToString::to_string).collect()
+    }
+
+    /// Memory image with the A2L addresses of the calibration segments
+    pub fn to_memory_image(&self, registry: &xcp_registry::Registry) -> Result<MemoryImage, String> {
+        let mut image = MemoryImage::new();
+        for (name, page) in &self.segments {
+            let s = registry
+                .cal_seg_list
+                .into_iter()
+                .find(|s| s.get_name() == name.as_str())
+                .ok_or_else(|| format!("segment {} not found in A2L", name))?;
+            image.insert(s.addr, page).map_err(|e| format!("segment {}: {}", name, e))?;
+        }
+        Ok(image)
+    }
+
+    /// Convert to Intel HEX with the A2L addresses of the calibration segments
+    pub fn to_intel_hex(&self, registry: &xcp_registry::Registry, options: &IntelHexOptions) -> Result<String, String> {
+        self.to_memory_image(registry)?.to_intel_hex(options)
+    }
+
+    /// Convert to one Intel HEX per calibration segment, returns (segment name, Intel HEX)
+    /// With options.fill, only the alignment of each segment is filled
+    pub fn to_intel_hex_per_segment(&self, registry: &xcp_registry::Registry, options: &IntelHexOptions) -> Result<Vec<(String, String)>, String> {
+        self.segments
+            .iter()
+            .map(|(name, page)| {
+                let bin = XcpBinFile {
+                    epk: self.epk.clone(),
+                    segments: vec![(name.clone(), page.clone())],
+                    signature: Vec::new(),
+                };
+                Ok((name.clone(), bin.to_intel_hex(registry, options)?))
+            })
+            .collect()
+    }
+
+    /// Convert from a memory image with the A2L addresses and the EPK of the A2L
+    /// Each calibration segment of the A2L must be completely covered or not at all, data outside of the segments is an error
+    pub fn from_memory_image(image: &MemoryImage, registry: &xcp_registry::Registry) -> Result<XcpBinFile, String> {
+        let mut segments = Vec::new();
+        let mut used = 0;
+        for s in &registry.cal_seg_list {
+            let Some(page) = image.slice(s.addr, s.size) else {
+                match image.count_defined(s.addr, s.size) {
+                    0 => warn!("hex2bin: segment {} not in HEX file", s.get_name()),
+                    n => return Err(format!("segment {} incomplete, {} of {} bytes", s.get_name(), n, s.size)),
+                }
+                continue;
+            };
+            used += page.len();
+            segments.push((s.get_name().to_string(), page.to_vec()));
+        }
+        if used != image.len() {
+            return Err(format!("{} bytes outside of the A2L calibration segments", image.len() - used));
+        }
+        Ok(XcpBinFile {
+            epk: registry.application.get_version().to_string(),
+            segments,
+            signature: Vec::new(),
+        })
+    }
+
+    /// Convert from Intel HEX with the A2L addresses and the EPK of the A2L, see from_memory_image
+    pub fn from_intel_hex(hex: &str, registry: &xcp_registry::Registry) -> Result<XcpBinFile, String> {
+        XcpBinFile::from_memory_image(&MemoryImage::from_intel_hex(hex)?, registry)
+    }
+}
+
+// JSON string literal
+fn json_string(s: &str) -> String {
+    let mut j = String::with_capacity(s.len() + 2);
+    j.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => j.push_str("\\\""),
+            '\\' => j.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(j, "\\u{:04X}", c as u32);
+            }
+            c => j.push(c),
+        }
+    }
+    j.push('"');
+    j
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod bin_file_tests {
+
+    use super::*;
+
+    fn registry() -> xcp_registry::Registry {
+        let mut reg = xcp_registry::Registry::new();
+        reg.application.set_version("EPK_1.0", 0x8000_0000);
+        reg.cal_seg_list.add_cal_seg_by_addr("seg1", Some(1), 0, 0x8000_1000, 4).unwrap();
+        reg.cal_seg_list.add_cal_seg_by_addr("seg2", Some(2), 0, 0x8000_1010, 2).unwrap();
+        reg
+    }
+
+    fn bin_file() -> XcpBinFile {
+        XcpBinFile {
+            epk: "EPK_1.0".to_string(),
+            segments: vec![("seg1".to_string(), vec![1, 2, 3, 4]), ("seg2".to_string(), vec![5, 6])],
+            signature: Vec::new(),
+        }
+    }
+
+    // BIN file content of a big endian producer, without signature
+    fn big_endian_bytes(page: &[u8]) -> Vec<u8> {
+        let mut data = BIN_MAGIC.to_vec();
+        data.push(BIN_FLAG_BIG_ENDIAN);
+        data.extend_from_slice(&3u16.to_be_bytes());
+        data.extend_from_slice(b"EPK");
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&4u16.to_be_bytes());
+        data.extend_from_slice(b"seg1");
+        data.extend_from_slice(&u32::try_from(page.len()).unwrap().to_be_bytes());
+        data.extend_from_slice(&crc32(page).to_be_bytes());
+        data.extend_from_slice(page);
+        data.extend_from_slice(&0u16.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_bin_round_trip() {
+        let bin = bin_file();
+        let data = bin.to_bytes().unwrap();
+        assert_eq!(&data[..9], b"XCPCAL02\0");
+        assert_eq!(XcpBinFile::from_bytes(&data).unwrap(), bin);
+
+        // Signed
+        let data = bin.to_bytes_signed(|content| crc32(content).to_le_bytes().to_vec()).unwrap();
+        let verify = |content: &[u8], signature: &[u8]| crc32(content).to_le_bytes() == signature;
+        assert_eq!(XcpBinFile::from_bytes_verified(&data, verify).unwrap().segments, bin.segments);
+        assert_eq!(XcpBinFile::from_bytes(&data).unwrap().signature.len(), 4);
+        let mut modified = data.clone();
+        *modified.last_mut().unwrap() ^= 1;
+        assert!(XcpBinFile::from_bytes_verified(&modified, verify).unwrap_err().contains("invalid signature"));
+        assert!(XcpBinFile::from_bytes_verified(&bin.to_bytes().unwrap(), verify).unwrap_err().contains("not signed"));
+    }
+
+    #[test]
+    fn test_bin_endianness() {
+        let bin = XcpBinFile::from_bytes(&big_endian_bytes(&[1, 2, 3, 4])).unwrap();
+        assert_eq!(bin.epk, "EPK");
+        assert_eq!(bin.get_segment("seg1"), Some(&[1u8, 2, 3, 4][..]));
+
+        // Files are always written little endian
+        assert_eq!(bin.to_bytes().unwrap()[8], 0);
+
+        // Version 1 without flags, segment count, CRC32 and signature
+        let mut data = BIN_MAGIC_V1.to_vec();
+        data.extend_from_slice(&[3, 0]);
+        data.extend_from_slice(b"EPK");
+        data.extend_from_slice(&[4, 0]);
+        data.extend_from_slice(b"seg1");
+        data.extend_from_slice(&[2, 0, 0, 0, 5, 6]);
+        let bin = XcpBinFile::from_bytes(&data).unwrap();
+        assert_eq!(bin.get_segment("seg1"), Some(&[5u8, 6][..]));
+    }
+
+    #[test]
+    fn test_bin_corrupted() {
+        let data = big_endian_bytes(&[1, 2, 3, 4]);
+        assert!(XcpBinFile::from_bytes(b"XCPCAL03").unwrap_err().contains("magic"));
+
+        let mut flags = data.clone();
+        flags[8] = 0x80;
+        assert!(XcpBinFile::from_bytes(&flags).unwrap_err().contains("unknown flags"));
+
+        // A huge segment size is rejected without allocation
+        let mut size = data.clone();
+        size[24..28].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(XcpBinFile::from_bytes(&size).unwrap_err().contains("exceeds the remaining"));
+
+        let mut crc = data.clone();
+        crc[34] ^= 1;
+        assert!(XcpBinFile::from_bytes(&crc).unwrap_err().contains("CRC32 error"));
+
+        assert!(XcpBinFile::from_bytes(&data[..data.len() - 1]).unwrap_err().contains("truncated"));
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(XcpBinFile::from_bytes(&trailing).unwrap_err().contains("unexpected data"));
+
+        let mut utf8 = data;
+        utf8[11] = 0xFF;
+        assert!(XcpBinFile::from_bytes(&utf8).unwrap_err().contains("UTF-8"));
+    }
+
+    #[test]
+    fn test_dump_and_json() {
+        let mut bin = bin_file();
+        bin.epk = "EPK \"1.0\"".to_string();
+        let dump = bin.dump(true);
+        assert!(dump.starts_with("EPK: EPK \"1.0\"\n"));
+        assert!(dump.contains("Segment seg1: 4 bytes\n  00000000: 01 02 03 04\n"));
+        assert!(!bin.dump(false).contains("00000000"));
+        let json = bin.to_json();
+        assert!(json.contains("\"epk\": \"EPK \\\"1.0\\\"\""));
+        assert!(json.contains("{ \"name\": \"seg2\", \"size\": 2, \"data\": \"0506\" }"));
+    }
+
+    #[test]
+    fn test_bin2hex2bin() {
+        let reg = registry();
+        let bin = bin_file();
+        let hex = bin.to_intel_hex(&reg, &IntelHexOptions::default()).unwrap();
+        assert_eq!(XcpBinFile::from_intel_hex(&hex, &reg).unwrap(), bin);
+
+        // A segment missing in the HEX file is skipped, incomplete segments and data outside of the segments are errors
+        let mut image = MemoryImage::new();
+        image.insert(0x8000_1010, &[5, 6]).unwrap();
+        assert_eq!(XcpBinFile::from_memory_image(&image, &reg).unwrap().segments, vec![("seg2".to_string(), vec![5, 6])]);
+        image.insert(0x8000_1000, &[1, 2]).unwrap();
+        assert!(XcpBinFile::from_memory_image(&image, &reg).unwrap_err().contains("incomplete"));
+        let mut image = MemoryImage::new();
+        image.insert(0x8000_1010, &[5, 6, 7]).unwrap();
+        assert!(XcpBinFile::from_memory_image(&image, &reg).unwrap_err().contains("outside"));
+
+        let mut unknown = bin_file();
+        unknown.segments[0].0 = "seg3".to_string();
+        assert!(unknown.to_intel_hex(&reg, &IntelHexOptions::default()).unwrap_err().contains("not found"));
+    }
+
+    #[test]
+    fn test_intel_hex_per_segment() {
+        let reg = registry();
+        let bin = bin_file();
+        let options = IntelHexOptions {
+            record_len: 16,
+            fill: Some(0xFF),
+            align: 8,
+        };
+
+        // One block filled from the aligned start of seg1 to the aligned end of seg2
+        let image = MemoryImage::from_intel_hex(&bin.to_intel_hex(&reg, &options).unwrap()).unwrap();
+        assert_eq!(image.segments().count(), 1);
+        assert_eq!(image.len(), 0x18);
+        assert_eq!(image.slice(0x8000_1004, 4), Some(&[0xFF; 4][..]));
+
+        // One file per segment, only the alignment of each segment is filled
+        let hex = bin.to_intel_hex_per_segment(&reg, &options).unwrap();
+        assert_eq!(hex.len(), 2);
+        assert_eq!(hex[1].0, "seg2");
+        let image = MemoryImage::from_intel_hex(&hex[1].1).unwrap();
+        assert_eq!(image.segments().collect::<Vec<_>>(), vec![(0x8000_1010, &[5, 6, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF][..])]);
+    }
+}
@@ -0,0 +1,225 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module block_transfer
+// Upload and download of larger memory blocks, with the block transfer modes negotiated with CONNECT and GET_COMM_MODE_INFO
+// Slave block mode: an UPLOAD of up to 255 bytes is answered with a sequence of responses
+// Master block mode: a DOWNLOAD is followed by DOWNLOAD_NEXT packets of the same block, the server responds once per block
+// Interleaved mode: up to QUEUE_SIZE commands are sent, before their responses are received
+// Without these modes, each UPLOAD and DOWNLOAD of one CTO waits for its response
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+
+use tokio::time::{Duration, timeout};
+
+use super::XcpClient;
+use super::commands::{Upload, XcpTypedCommand};
+use super::xcp::*;
+
+// Maximum number of bytes of an UPLOAD or of a master block DOWNLOAD
+const MAX_BLOCK_SIZE: usize = 255;
+
+impl XcpClient {
+    /// Enable or disable the block transfer modes for upload_memory_block and download_memory_block, default is enabled
+    /// The modes are only used, if the server supports them
+    pub fn set_block_transfer(&mut self, enabled: bool) {
+        self.block_transfer = enabled;
+    }
+
+    // Slave block mode supported
+    fn slave_block_mode(&self) -> bool {
+        self.block_transfer && self.comm_mode_basic & CMB_SLAVE_BLOCK_MODE != 0
+    }
+
+    // Size of a master block, None if master block mode is not supported
+    fn master_block_size(&self) -> Option<usize> {
+        if self.block_transfer && self.comm_mode_optional & CMO_MASTER_BLOCK_MODE != 0 && self.max_bs > 1 {
+            Some((self.max_bs as usize * (self.max_cto_size as usize - 2)).min(MAX_BLOCK_SIZE))
+        } else {
+            None
+        }
+    }
+
+    // Number of commands which may be pending, 1 if interleaved mode is not supported
+    fn interleaved_queue_size(&self) -> usize {
+        if self.block_transfer && self.comm_mode_optional & CMO_INTERLEAVED_MODE != 0 {
+            self.queue_size.max(1) as usize
+        } else {
+            1
+        }
+    }
+
+    // Send commands with up to queue size commands pending, returns the responses in the order of the commands
+    // On error, the responses of the pending commands are received and discarded, to keep commands and responses in sequence
+    async fn send_interleaved(&mut self, commands: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let queue_size = self.interleaved_queue_size();
+        let mut responses = Vec::with_capacity(commands.len());
+        let mut result: Result<(), Box<dyn Error>> = Ok(());
+        let mut sent = 0;
+        let mut received = 0;
+        while received < sent || (result.is_ok() && sent < commands.len()) {
+            while result.is_ok() && sent < commands.len() && sent - received < queue_size {
+                match self.send_command_only(&commands[sent]).await {
+                    Ok(()) => sent += 1,
+                    Err(e) => result = Err(e),
+                }
+            }
+            if received < sent {
+                match self.receive_response(commands[received][4]).await {
+                    Ok(data) => responses.push(data),
+                    Err(e) => {
+                        if result.is_ok() {
+                            result = Err(e);
+                        }
+                    }
+                }
+                received += 1;
+            }
+        }
+        result.map(|()| responses)
+    }
+
+    /// Upload a memory block of block_size bytes from the XCP server, starting at the MTA
+    pub async fn upload_memory_block(&mut self, block_size: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        trace!("upload_memory_block block_size={}", block_size);
+        let size = block_size as usize;
+        let max_data = self.max_cto_size as usize - 1; // Data bytes of an UPLOAD response
+        let mut result = Vec::with_capacity(size);
+
+        if self.slave_block_mode() {
+            while result.len() < size {
+                let n = (size - result.len()).min(MAX_BLOCK_SIZE);
+                let mut data = self.upload(u8::try_from(n)?).await?;
+                let mut received = 0;
+                loop {
+                    let len = (n - received).min(data.len() - 1);
+                    result.extend_from_slice(&data[1..=len]);
+                    received += len;
+                    if received >= n {
+                        break;
+                    }
+                    data = self.receive_response(CC_UPLOAD).await?;
+                }
+            }
+        } else {
+            let mut commands = Vec::with_capacity(size.div_ceil(max_data));
+            let mut sizes = Vec::with_capacity(commands.capacity());
+            let mut remaining = size;
+            while remaining > 0 {
+                let n = remaining.min(max_data);
+                commands.push(Upload { size: u8::try_from(n)? }.to_packet());
+                sizes.push(n);
+                remaining -= n;
+            }
+            let responses = self.send_interleaved(&commands).await?;
+            for (data, n) in responses.iter().zip(sizes) {
+                result.extend_from_slice(&data[1..=n]);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Download a memory block of data_bytes to the XCP server, starting at the MTA
+    pub async fn download_memory_block(&mut self, data_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        trace!("download_memory_block block_size={}", data_bytes.len());
+        let max_data = self.max_cto_size as usize - 2; // Data bytes of a DOWNLOAD or DOWNLOAD_NEXT
+
+        if let Some(block_size) = self.master_block_size() {
+            for block in data_bytes.chunks(block_size) {
+                self.download_block(block, max_data).await?;
+            }
+        } else {
+            let mut commands = Vec::with_capacity(data_bytes.len().div_ceil(max_data));
+            for packet in data_bytes.chunks(max_data) {
+                commands.push(
+                    XcpCommandBuilder::new(CC_DOWNLOAD)
+                        .add_u8(u8::try_from(packet.len())?)
+                        .add_u8_slice(packet)
+                        .build()
+                        .to_vec(),
+                );
+            }
+            self.send_interleaved(&commands).await?;
+        }
+        Ok(())
+    }
+
+    // Master block mode, DOWNLOAD with the first packet and DOWNLOAD_NEXT with the remaining packets of a block, the server responds once
+    async fn download_block(&mut self, block: &[u8], max_data: usize) -> Result<(), Box<dyn Error>> {
+        let separation_time = Duration::from_micros(u64::from(self.min_st) * 100);
+        let mut remaining = block.len();
+        for (i, packet) in block.chunks(max_data).enumerate() {
+            let command_code = if i == 0 { CC_DOWNLOAD } else { CC_DOWNLOAD_NEXT };
+            if i > 0 && !separation_time.is_zero() {
+                tokio::time::sleep(separation_time).await;
+            }
+            self.send_command_only(XcpCommandBuilder::new(command_code).add_u8(u8::try_from(remaining)?).add_u8_slice(packet).build())
+                .await?;
+            remaining -= packet.len();
+        }
+        if let Err(e) = self.receive_response(CC_DOWNLOAD).await {
+            // The server may have rejected the remaining packets of the block too, discard their responses until there is no response for 10ms
+            while let Ok(Some(_)) = timeout(Duration::from_millis(10), self.rx_cmd_resp.as_mut().unwrap().recv()).await {}
+            error!("DOWNLOAD: master block of {} bytes failed", block.len());
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod block_transfer_tests {
+
+    use super::*;
+
+    fn client(comm_mode_basic: u8, comm_mode_optional: u8) -> XcpClient {
+        let mut client = XcpClient::new(false, "127.0.0.1:5555".parse().unwrap(), "0.0.0.0:0".parse().unwrap());
+        client.comm_mode_basic = comm_mode_basic;
+        client.comm_mode_optional = comm_mode_optional;
+        client.max_cto_size = 8;
+        client.max_bs = 4;
+        client.queue_size = 3;
+        client
+    }
+
+    #[test]
+    fn test_block_transfer_modes() {
+        let mut c = client(CMB_SLAVE_BLOCK_MODE, CMO_MASTER_BLOCK_MODE | CMO_INTERLEAVED_MODE);
+        assert!(c.slave_block_mode());
+        assert_eq!(c.master_block_size(), Some(4 * 6)); // MAX_BS packets with MAX_CTO - 2 data bytes
+        assert_eq!(c.interleaved_queue_size(), 3);
+
+        // Disabled by the client
+        c.set_block_transfer(false);
+        assert!(!c.slave_block_mode());
+        assert_eq!(c.master_block_size(), None);
+        assert_eq!(c.interleaved_queue_size(), 1);
+
+        // Not supported by the server
+        let c = client(0, 0);
+        assert!(!c.slave_block_mode());
+        assert_eq!(c.master_block_size(), None);
+        assert_eq!(c.interleaved_queue_size(), 1);
+    }
+
+    #[test]
+    fn test_master_block_size() {
+        // A block is limited by the 8 bit size parameter of DOWNLOAD
+        let mut c = client(0, CMO_MASTER_BLOCK_MODE);
+        c.max_cto_size = 255;
+        assert_eq!(c.master_block_size(), Some(MAX_BLOCK_SIZE));
+
+        // MAX_BS 1 is not a block
+        c.max_bs = 1;
+        assert_eq!(c.master_block_size(), None);
+
+        // Interleaved mode with QUEUE_SIZE 0 sends one command at a time
+        let mut c = client(0, CMO_INTERLEAVED_MODE);
+        c.queue_size = 0;
+        assert_eq!(c.interleaved_queue_size(), 1);
+    }
+}
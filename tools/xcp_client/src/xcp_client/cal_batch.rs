@@ -0,0 +1,229 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module cal_batch
+// Calibration batch files, many name/value pairs applied in one connected session
+// All names, values and limits are checked before anything is written, the values are written with block download in one atomic calibration
+// action (USER_CMD modify begin/end) and read back for a verification report
+// The --cal-file option of the command line tool reads a batch with XcpCalBatch::read_file() and applies it with apply_cal_batch()
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+
+use super::xcp::*;
+use super::{A2lAddr, A2lType, XcpCalibrationHistoryEntry, XcpClient, f64_to_value, value_to_f64};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpCalBatch
+
+/// A name/value pair of a calibration batch file, arrays have one value per element in row major order
+#[derive(Debug, Clone, PartialEq)]
+pub struct XcpCalBatchEntry {
+    pub line: usize,
+    pub name: String,
+    pub values: Vec<f64>,
+}
+
+/// Calibration batch file
+/// Text format with one `NAME VALUE...` or `NAME=VALUE` per line, or CSV format with `NAME,VALUE,...` and an optional header line starting with `name`
+/// Empty lines and lines starting with # are ignored
+#[derive(Debug, Default)]
+pub struct XcpCalBatch {
+    entries: Vec<XcpCalBatchEntry>,
+}
+
+impl std::str::FromStr for XcpCalBatch {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = if line.contains(',') {
+                line.split(',').map(str::trim).collect::<Vec<_>>()
+            } else {
+                line.split(|c: char| c == '=' || c.is_ascii_whitespace()).filter(|f| !f.is_empty()).collect()
+            };
+            let name = fields.remove(0);
+            if entries.is_empty() && name.eq_ignore_ascii_case("name") {
+                continue; // CSV header
+            }
+            if name.is_empty() || fields.is_empty() {
+                return Err(format!("line {}: expected name and value, found '{}'", i + 1, line));
+            }
+            let values = fields
+                .iter()
+                .map(|f| f.parse::<f64>().map_err(|_| format!("line {}: invalid value '{}' for {}", i + 1, f, name)))
+                .collect::<Result<Vec<f64>, String>>()?;
+            entries.push(XcpCalBatchEntry {
+                line: i + 1,
+                name: name.to_string(),
+                values,
+            });
+        }
+        Ok(XcpCalBatch { entries })
+    }
+}
+
+impl XcpCalBatch {
+    /// Read a calibration batch file in text or CSV format
+    pub fn read_file<P: AsRef<std::path::Path>>(path: &P) -> Result<XcpCalBatch, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let batch = text.parse::<XcpCalBatch>().map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        Ok(batch)
+    }
+
+    pub fn get_entries(&self) -> &[XcpCalBatchEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpCalBatchReport
+
+/// Verification result of a calibration batch entry, the requested and the read back values
+#[derive(Debug, Clone)]
+pub struct XcpCalBatchResult {
+    pub name: String,
+    pub requested: Vec<f64>,
+    pub read_back: Vec<f64>,
+    pub ok: bool, // Read back bytes are equal to the written bytes
+}
+
+/// Verification report of an applied calibration batch
+#[derive(Debug, Default)]
+pub struct XcpCalBatchReport {
+    pub results: Vec<XcpCalBatchResult>,
+}
+
+impl XcpCalBatchReport {
+    /// Check if all values have been verified
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|r| r.ok)
+    }
+
+    /// Number of values which could not be verified
+    pub fn get_mismatch_count(&self) -> usize {
+        self.results.iter().filter(|r| !r.ok).count()
+    }
+}
+
+impl std::fmt::Display for XcpCalBatchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for r in &self.results {
+            writeln!(f, "{:<48} {:?} -> {:?} {}", r.name, r.requested, r.read_back, if r.ok { "ok" } else { "MISMATCH" })?;
+        }
+        write!(f, "{} values written, {} mismatches", self.results.len(), self.get_mismatch_count())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Apply a calibration batch
+
+// A checked write of a batch entry
+struct CalBatchWrite {
+    name: String,
+    requested: Vec<f64>,
+    a2l_addr: A2lAddr,
+    a2l_type: A2lType,
+    before: Vec<u8>,
+    after: Vec<u8>,
+}
+
+impl XcpClient {
+    /// Apply a calibration batch in one atomic calibration action and verify the written values
+    /// Fails before anything is written, if a name is not found, the value count does not match the element count or a value exceeds the A2L limits
+    /// The changes are recorded in the calibration history, the report lists the read back values
+    pub async fn apply_cal_batch(&mut self, batch: &XcpCalBatch) -> Result<XcpCalBatchReport, Box<dyn Error>> {
+        // Check all entries
+        let mut writes: Vec<CalBatchWrite> = Vec::with_capacity(batch.len());
+        for e in batch.get_entries() {
+            let handle = self.create_calibration_object(&e.name).await.inspect_err(|_| {
+                error!("apply_cal_batch: line {}: {} not found", e.line, e.name);
+            })?;
+            let obj = self.get_calibration_object(handle);
+            if e.values.len() != obj.get_element_count() {
+                error!(
+                    "apply_cal_batch: line {}: {} has {} elements, {} values given",
+                    e.line,
+                    e.name,
+                    obj.get_element_count(),
+                    e.values.len()
+                );
+                return Err(Box::new(XcpError::new(ERROR_INDEX_RANGE, 0)) as Box<dyn Error>);
+            }
+            let mut after = Vec::with_capacity(obj.get_size());
+            for (i, value) in e.values.iter().enumerate() {
+                obj.check_write(i, *value)?;
+                let Some(bytes) = f64_to_value(obj.get_type, *value) else {
+                    error!("apply_cal_batch: line {}: unsupported type {:?}", e.line, obj.get_type);
+                    return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+                };
+                after.extend_from_slice(&bytes);
+            }
+            writes.push(CalBatchWrite {
+                name: e.name.clone(),
+                requested: e.values.clone(),
+                a2l_addr: obj.a2l_addr,
+                a2l_type: obj.get_type,
+                before: obj.value.clone(),
+                after,
+            });
+        }
+
+        // Write all values with block download, the calibration action is always ended, also if a write fails
+        self.modify_begin().await?;
+        let res = self.write_cal_batch(&writes).await;
+        let end = self.modify_end().await;
+        res?;
+        end?;
+
+        // Read back and verify
+        let mut report = XcpCalBatchReport::default();
+        for w in writes {
+            let size = w.after.len();
+            let resp = self.short_upload(w.a2l_addr.addr, w.a2l_addr.ext, u8::try_from(size)?).await?;
+            let value = &resp[1..=size];
+            let ok = value == w.after.as_slice();
+            if !ok {
+                warn!("apply_cal_batch: {} read back does not match", w.name);
+            }
+            report.results.push(XcpCalBatchResult {
+                read_back: value.chunks(w.a2l_type.size).map(|b| value_to_f64(w.a2l_type, b)).collect(),
+                name: w.name.clone(),
+                requested: w.requested,
+                ok,
+            });
+            self.cal_history.push(XcpCalibrationHistoryEntry {
+                name: w.name,
+                a2l_addr: w.a2l_addr,
+                a2l_type: w.a2l_type,
+                before: w.before,
+                after: w.after,
+            });
+        }
+        info!("Calibration batch with {} values applied, {} mismatches", report.results.len(), report.get_mismatch_count());
+        Ok(report)
+    }
+
+    // Write the values of a calibration batch
+    async fn write_cal_batch(&mut self, writes: &[CalBatchWrite]) -> Result<(), Box<dyn Error>> {
+        for w in writes {
+            debug!("apply_cal_batch: {} {}:0x{:08X} {} bytes", w.name, w.a2l_addr.ext, w.a2l_addr.addr, w.after.len());
+            self.set_mta(w.a2l_addr.ext, w.a2l_addr.addr).await?;
+            self.download_memory_block(&w.after).await?;
+            self.update_cached_values(w.a2l_addr, &w.after);
+        }
+        Ok(())
+    }
+}
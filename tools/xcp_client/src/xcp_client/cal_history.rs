@@ -0,0 +1,224 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module cal_history
+// Calibration history of a client session
+// Records every value written by the XcpClient set_value_xxx methods with before and after bytes, provides an undo/redo cursor
+// and exports the net changes of the session as a DCM (KONSERVIERUNG_FORMAT 2.0) patch file
+// The --history option of the command line tool lists the history with print_calibration_history(), --dcm writes the patch file
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::io::Write;
+
+use super::{A2lAddr, A2lType, A2lTypeEncoding};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpCalibrationHistoryEntry
+
+/// A single calibration write with the raw value bytes before and after
+#[derive(Debug, Clone)]
+pub struct XcpCalibrationHistoryEntry {
+    pub name: String,
+    pub a2l_addr: A2lAddr,
+    pub a2l_type: A2lType,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+impl XcpCalibrationHistoryEntry {
+    /// Physical value before the change
+    pub fn get_before_f64(&self) -> f64 {
+        value_to_f64(self.a2l_type, &self.before)
+    }
+
+    /// Physical value after the change
+    pub fn get_after_f64(&self) -> f64 {
+        value_to_f64(self.a2l_type, &self.after)
+    }
+}
+
+impl std::fmt::Display for XcpCalibrationHistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}: {} -> {}", self.name, self.a2l_addr, self.get_before_f64(), self.get_after_f64())
+    }
+}
+
+/// Convert little endian raw value bytes to f64
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap, clippy::cast_precision_loss)]
+pub fn value_to_f64(a2l_type: A2lType, bytes: &[u8]) -> f64 {
+    let size = a2l_type.size.min(bytes.len()).min(8);
+    let mut v = 0u64;
+    for i in (0..size).rev() {
+        v <<= 8;
+        v |= bytes[i] as u64;
+    }
+    match a2l_type.encoding {
+        A2lTypeEncoding::Float => match size {
+            4 => f32::from_bits(v as u32) as f64,
+            8 => f64::from_bits(v),
+            _ => 0.0,
+        },
+        A2lTypeEncoding::Signed => {
+            // Sign extend
+            let shift = 64 - 8 * size as u32;
+            if shift >= 64 { 0.0 } else { (((v << shift) as i64) >> shift) as f64 }
+        }
+        A2lTypeEncoding::Unsigned => v as f64,
+        A2lTypeEncoding::Blob => 0.0,
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpCalibrationHistory
+
+/// Undo stack of calibration writes
+/// Entries at and above the cursor are undone and may be redone, a new write discards them
+#[derive(Debug, Default)]
+pub struct XcpCalibrationHistory {
+    entries: Vec<XcpCalibrationHistoryEntry>,
+    cursor: usize,
+}
+
+impl XcpCalibrationHistory {
+    pub fn new() -> XcpCalibrationHistory {
+        XcpCalibrationHistory { entries: Vec::new(), cursor: 0 }
+    }
+
+    /// Record a calibration write, discards the redo part of the history
+    pub fn push(&mut self, entry: XcpCalibrationHistoryEntry) {
+        self.entries.truncate(self.cursor);
+        self.entries.push(entry);
+        self.cursor = self.entries.len();
+    }
+
+    /// Number of active (not undone) entries
+    pub fn len(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cursor == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.cursor = 0;
+    }
+
+    /// Active (not undone) entries in chronological order
+    pub fn get_entries(&self) -> &[XcpCalibrationHistoryEntry] {
+        &self.entries[..self.cursor]
+    }
+
+    /// Entry to undo, the entry before the cursor
+    pub fn get_undo(&self) -> Option<&XcpCalibrationHistoryEntry> {
+        self.cursor.checked_sub(1).map(|i| &self.entries[i])
+    }
+
+    /// Entry to redo, the entry at the cursor
+    pub fn get_redo(&self) -> Option<&XcpCalibrationHistoryEntry> {
+        self.entries.get(self.cursor)
+    }
+
+    /// Move the cursor back after the entry to undo has been restored
+    pub fn undo(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    /// Move the cursor forward after the entry to redo has been restored
+    pub fn redo(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.entries.len());
+    }
+
+    /// Net changes of the session
+    /// One entry per object with the first before and the last after value, objects with no net change are omitted
+    pub fn get_net_changes(&self) -> Vec<XcpCalibrationHistoryEntry> {
+        let mut changes: Vec<XcpCalibrationHistoryEntry> = Vec::new();
+        for e in self.get_entries() {
+            if let Some(c) = changes.iter_mut().find(|c| c.name == e.name) {
+                c.after = e.after.clone();
+            } else {
+                changes.push(e.clone());
+            }
+        }
+        changes.retain(|c| c.before != c.after);
+        changes
+    }
+
+    /// Print the history listing
+    pub fn print(&self) {
+        println!("Calibration history ({} changes, {} undone):", self.cursor, self.entries.len() - self.cursor);
+        for (i, e) in self.entries.iter().enumerate() {
+            println!(" {}{:3}: {}", if i < self.cursor { ' ' } else { '-' }, i, e);
+        }
+    }
+
+    /// Write the net changes of the session as DCM patch file
+    pub fn write_dcm<P: AsRef<std::path::Path>>(&self, dcm_path: &P) -> Result<usize, std::io::Error> {
+        let changes = self.get_net_changes();
+        let file = std::fs::File::create(dcm_path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        writeln!(writer, "KONSERVIERUNG_FORMAT 2.0")?;
+        writeln!(writer)?;
+        for c in &changes {
+            if let A2lTypeEncoding::Blob = c.a2l_type.encoding {
+                warn!("write_dcm: {} skipped, blob values are not supported", c.name);
+                continue;
+            }
+            writeln!(writer, "FESTWERT {}", c.name)?;
+            writeln!(writer, "   WERT {}", c.get_after_f64())?;
+            writeln!(writer, "END")?;
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        info!("DCM patch file {} written, {} changes", dcm_path.as_ref().display(), changes.len());
+        Ok(changes.len())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod cal_history_tests {
+
+    use super::*;
+
+    fn entry(before: u8, after: u8) -> XcpCalibrationHistoryEntry {
+        XcpCalibrationHistoryEntry {
+            name: "p".to_string(),
+            a2l_addr: A2lAddr { ext: 0, addr: 0, event: None },
+            a2l_type: A2lType {
+                size: 1,
+                encoding: A2lTypeEncoding::Unsigned,
+            },
+            before: vec![before],
+            after: vec![after],
+        }
+    }
+
+    #[test]
+    fn test_cal_history_cursor() {
+        let mut history = XcpCalibrationHistory::new();
+        history.push(entry(0, 1));
+        history.push(entry(1, 2));
+
+        // The cursor does not move before the entry is restored
+        assert_eq!(history.get_undo().unwrap().before, vec![1]);
+        assert_eq!(history.len(), 2);
+        history.undo();
+        assert_eq!(history.get_undo().unwrap().before, vec![0]);
+        assert_eq!(history.get_redo().unwrap().after, vec![2]);
+        history.undo();
+        assert!(history.get_undo().is_none());
+        history.undo();
+        assert_eq!(history.len(), 0);
+
+        history.redo();
+        assert_eq!(history.len(), 1);
+        history.push(entry(1, 3));
+        assert!(history.get_redo().is_none());
+        assert_eq!(history.get_net_changes().len(), 1);
+        assert_eq!(history.get_net_changes()[0].after, vec![3]);
+    }
+}
@@ -0,0 +1,98 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module cal_patch
+// Exchange of calibration changes as binary patch (xcp_registry::McCalPatch)
+// A patch is created from the net changes of the calibration history and applied in one atomic calibration action (USER_CMD modify begin/end),
+// the server makes all changes of the patch visible to the application at once
+// The atomic calibration action is the transport of a patch, the records are transferred with the standard DOWNLOAD command
+// and verified with BUILD_CHECKSUM, Xcp::apply_cal_patch applies patches on the server side from the application, e.g. from a file
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+
+use xcp_registry::{McCalPatch, Registry};
+
+use super::xcp::*;
+use super::{A2lAddr, XcpClient};
+
+// Find the calibration segment index and offset of an A2L address
+fn find_cal_seg_offset(registry: &Registry, ext: u8, addr: u32, len: usize) -> Option<(u16, u32)> {
+    registry
+        .cal_seg_list
+        .into_iter()
+        .find(|s| s.addr_ext == ext && addr >= s.addr && (addr - s.addr) as usize + len <= s.size as usize)
+        .map(|s| (s.index, addr - s.addr))
+}
+
+impl XcpClient {
+    /// Create a calibration patch from the net changes of the calibration history
+    /// Fails with ERROR_CAL_PATCH, if a change is not located in a calibration segment of the registry
+    pub fn create_cal_patch(&self) -> Result<McCalPatch, Box<dyn Error>> {
+        let mut patch = McCalPatch::new();
+        for c in self.get_calibration_history().get_net_changes() {
+            let Some((segment, offset)) = find_cal_seg_offset(self.get_registry(), c.a2l_addr.ext, c.a2l_addr.addr, c.after.len()) else {
+                error!("create_cal_patch: {} at {} is not in a calibration segment", c.name, c.a2l_addr);
+                return Err(Box::new(XcpError::new(ERROR_CAL_PATCH, 0)) as Box<dyn Error>);
+            };
+            patch.add(segment, offset, &c.after).map_err(|e| {
+                error!("create_cal_patch: {}", e);
+                Box::new(XcpError::new(ERROR_CAL_PATCH, 0)) as Box<dyn Error>
+            })?;
+        }
+        info!("Calibration patch with {} records created", patch.len());
+        Ok(patch)
+    }
+
+    /// Apply a calibration patch in one atomic calibration action
+    /// All records are checked against the calibration segments of the registry before anything is written
+    /// The records are written with DOWNLOAD and verified on the server with BUILD_CHECKSUM, fails with ERROR_CAL_PATCH on a mismatch
+    pub async fn apply_cal_patch(&mut self, patch: &McCalPatch) -> Result<(), Box<dyn Error>> {
+        let mut writes: Vec<(u8, u32, &[u8])> = Vec::with_capacity(patch.len());
+        for r in patch.get_records() {
+            let segment = self.get_registry().cal_seg_list.into_iter().find(|s| s.index == r.segment);
+            match segment {
+                Some(s) if r.offset as usize + r.data.len() <= s.size as usize => writes.push((s.addr_ext, s.addr + r.offset, &r.data)),
+                _ => {
+                    error!("apply_cal_patch: segment {} offset {} size {} does not match", r.segment, r.offset, r.data.len());
+                    return Err(Box::new(XcpError::new(ERROR_CAL_PATCH, 0)) as Box<dyn Error>);
+                }
+            }
+        }
+
+        // The calibration action is always ended, also if a write fails, the first error is returned
+        self.modify_begin().await?;
+        let res = self.write_cal_patch(&writes).await;
+        let end = self.modify_end().await;
+        res?;
+        end?;
+
+        // Verify the records on the server
+        for (ext, addr, data) in writes {
+            if !self.verify_checksum(addr, ext, data).await? {
+                error!("apply_cal_patch: {}:0x{:08X} {} bytes, checksum mismatch", ext, addr, data.len());
+                return Err(Box::new(XcpError::new(ERROR_CAL_PATCH, 0)) as Box<dyn Error>);
+            }
+        }
+        info!("Calibration patch with {} records applied", patch.len());
+        Ok(())
+    }
+
+    // Write the records of a calibration patch
+    async fn write_cal_patch(&mut self, writes: &[(u8, u32, &[u8])]) -> Result<(), Box<dyn Error>> {
+        for (ext, addr, data) in writes {
+            debug!("apply_cal_patch: {}:0x{:08X} {} bytes", ext, addr, data.len());
+            self.set_mta(*ext, *addr).await?;
+            self.download_memory_block(data).await?;
+            self.update_cached_values(
+                A2lAddr {
+                    ext: *ext,
+                    addr: *addr,
+                    event: None,
+                },
+                data,
+            );
+        }
+        Ok(())
+    }
+}
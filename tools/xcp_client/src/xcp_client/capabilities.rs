@@ -0,0 +1,79 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module capabilities
+// Protocol capabilities of the connected XCP server, negotiated during connect
+// Unsupported features are disabled instead of failing, each degradation is recorded with a reason
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// Capabilities of the connected XCP server
+#[derive(Debug, Clone)]
+pub struct XcpCapabilities {
+    pub get_version: bool,          // GET_VERSION supported
+    pub time_correlation: bool,     // TIME_CORRELATION_PROPERTIES supported, GET_DAQ_CLOCK has 64 bit response format
+    pub daq: bool,                  // DAQ measurement possible with this client
+    pub daq_timestamps: bool,       // DAQ timestamps available
+    pub daq_timestamp_fixed: bool,  // DAQ timestamps can not be switched off
+    pub daq_timestamp_size: u8,     // Size of the DAQ timestamp in the first ODT, 0 if no timestamps
+    pub daq_granularity: u8,        // ODT entry size granularity
+    pub daq_max_odt_entry_size: u8, // Max size of an ODT entry
+    degraded: Vec<String>,
+}
+
+impl Default for XcpCapabilities {
+    fn default() -> Self {
+        XcpCapabilities {
+            get_version: true,
+            time_correlation: true,
+            daq: true,
+            daq_timestamps: true,
+            daq_timestamp_fixed: true,
+            daq_timestamp_size: 4,
+            daq_granularity: 1,
+            daq_max_odt_entry_size: 0xFF,
+            degraded: Vec::new(),
+        }
+    }
+}
+
+impl XcpCapabilities {
+    pub fn new() -> XcpCapabilities {
+        XcpCapabilities::default()
+    }
+
+    /// Record a degraded feature
+    pub fn degrade(&mut self, reason: String) {
+        warn!("XCP server capability: {}", reason);
+        self.degraded.push(reason);
+    }
+
+    /// All features degraded during capability negotiation
+    pub fn get_degraded(&self) -> &[String] {
+        &self.degraded
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        !self.degraded.is_empty()
+    }
+
+    /// Check if a DAQ ODT entry of this size is supported
+    pub fn check_odt_entry_size(&self, size: usize) -> bool {
+        size <= self.daq_max_odt_entry_size as usize && size.is_multiple_of(self.daq_granularity.max(1) as usize)
+    }
+
+    /// Print the capability report
+    pub fn print(&self) {
+        println!("XCP server capabilities:");
+        println!(" GET_VERSION:          {}", self.get_version);
+        println!(" Time correlation:     {}", self.time_correlation);
+        println!(" DAQ:                  {}", self.daq);
+        println!(
+            " DAQ timestamps:       {} (size={}, fixed={})",
+            self.daq_timestamps, self.daq_timestamp_size, self.daq_timestamp_fixed
+        );
+        println!(" DAQ granularity:      {} (max ODT entry size={})", self.daq_granularity, self.daq_max_odt_entry_size);
+        for d in &self.degraded {
+            println!(" Degraded: {}", d);
+        }
+    }
+}
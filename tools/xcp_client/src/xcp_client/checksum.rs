@@ -0,0 +1,119 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module checksum
+// Memory checksums with BUILD_CHECKSUM, to verify uploads, downloads and the integrity of calibration segments
+// The server selects the checksum type (XCPlite: CRC16 CCITT or CRC32), the client calculates the same checksum type over its own data
+// Additive checksums of words use the little endian byte order of the XCPlite targets
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+
+use super::XcpClient;
+use super::commands::BuildChecksum;
+use super::xcp::*;
+use xcp_registry::{crc16, crc16_ccitt, crc32};
+
+/// Checksum type of BUILD_CHECKSUM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XcpChecksumType {
+    Add11,
+    Add12,
+    Add14,
+    Add22,
+    Add24,
+    Add44,
+    Crc16,
+    Crc16Ccitt,
+    Crc32,
+    UserDefined,
+}
+
+impl XcpChecksumType {
+    /// Checksum type from the XCP code in the BUILD_CHECKSUM response
+    pub fn from_xcp(checksum_type: u8) -> Option<XcpChecksumType> {
+        match checksum_type {
+            0x01 => Some(XcpChecksumType::Add11),
+            0x02 => Some(XcpChecksumType::Add12),
+            0x03 => Some(XcpChecksumType::Add14),
+            0x04 => Some(XcpChecksumType::Add22),
+            0x05 => Some(XcpChecksumType::Add24),
+            0x06 => Some(XcpChecksumType::Add44),
+            0x07 => Some(XcpChecksumType::Crc16),
+            0x08 => Some(XcpChecksumType::Crc16Ccitt),
+            0x09 => Some(XcpChecksumType::Crc32),
+            0xFF => Some(XcpChecksumType::UserDefined),
+            _ => None,
+        }
+    }
+
+    /// Calculate the checksum of data, None for user defined checksums or if data is not a multiple of the word size
+    pub fn calculate(self, data: &[u8]) -> Option<u32> {
+        let sum = |size: usize| -> Option<u32> {
+            if !data.len().is_multiple_of(size) {
+                return None;
+            }
+            Some(data.chunks(size).fold(0u32, |sum, w| {
+                let mut b = [0u8; 4];
+                b[..size].copy_from_slice(w);
+                sum.wrapping_add(u32::from_le_bytes(b))
+            }))
+        };
+        match self {
+            XcpChecksumType::Add11 => sum(1).map(|s| s & 0xFF),
+            XcpChecksumType::Add12 => sum(1).map(|s| s & 0xFFFF),
+            XcpChecksumType::Add14 => sum(1),
+            XcpChecksumType::Add22 => sum(2).map(|s| s & 0xFFFF),
+            XcpChecksumType::Add24 => sum(2),
+            XcpChecksumType::Add44 => sum(4),
+            XcpChecksumType::Crc16 => Some(u32::from(crc16(data))),
+            XcpChecksumType::Crc16Ccitt => Some(u32::from(crc16_ccitt(data))),
+            XcpChecksumType::Crc32 => Some(crc32(data)),
+            XcpChecksumType::UserDefined => None,
+        }
+    }
+}
+
+/// Result of BUILD_CHECKSUM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XcpChecksum {
+    pub checksum_type: XcpChecksumType,
+    pub value: u32,
+}
+
+impl XcpClient {
+    /// Build the checksum of size bytes at ext:addr on the server with SET_MTA and BUILD_CHECKSUM
+    pub async fn build_checksum(&mut self, addr: u32, ext: u8, size: u32) -> Result<XcpChecksum, Box<dyn Error>> {
+        self.set_mta(ext, addr).await?;
+        let data = self.send_typed(&BuildChecksum { size }).await?;
+        if data.len() < 8 {
+            error!("BUILD_CHECKSUM: response too short");
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_BUILD_CHECKSUM)) as Box<dyn Error>);
+        }
+        let Some(checksum_type) = XcpChecksumType::from_xcp(data[1]) else {
+            error!("BUILD_CHECKSUM: unknown checksum type 0x{:02X}", data[1]);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_BUILD_CHECKSUM)) as Box<dyn Error>);
+        };
+        let value = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        debug!("BUILD_CHECKSUM {}:0x{:08X} size={}: {:?} 0x{:08X}", ext, addr, size, checksum_type, value);
+        Ok(XcpChecksum { checksum_type, value })
+    }
+
+    /// Verify that the memory at ext:addr on the server has the content data, with BUILD_CHECKSUM
+    /// Fails with ERROR_TYPE_MISMATCH, if the checksum type of the server can not be calculated by the client
+    pub async fn verify_checksum(&mut self, addr: u32, ext: u8, data: &[u8]) -> Result<bool, Box<dyn Error>> {
+        let size = u32::try_from(data.len())?;
+        let checksum = self.build_checksum(addr, ext, size).await?;
+        let Some(expected) = checksum.checksum_type.calculate(data) else {
+            error!("BUILD_CHECKSUM: checksum type {:?} not supported for {} bytes", checksum.checksum_type, size);
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, CC_BUILD_CHECKSUM)) as Box<dyn Error>);
+        };
+        if checksum.value != expected {
+            warn!(
+                "BUILD_CHECKSUM {}:0x{:08X} size={}: {:?} 0x{:08X}, expected 0x{:08X}",
+                ext, addr, size, checksum.checksum_type, checksum.value, expected
+            );
+        }
+        Ok(checksum.value == expected)
+    }
+}
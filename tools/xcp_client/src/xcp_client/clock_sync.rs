@@ -0,0 +1,172 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module clock_sync
+// Estimation of target clock offset and drift against the client clock
+// Based on GET_DAQ_CLOCK round trips, the target time is assumed to be sampled in the middle of the round trip
+// Offset and drift are estimated by a least squares fit over all round trips
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+/// A single GET_DAQ_CLOCK round trip, all times in ns
+#[derive(Debug, Clone, Copy)]
+pub struct XcpClockSample {
+    pub client_time_ns: i64, // middle of the round trip
+    pub target_time_ns: i64,
+    pub round_trip_ns: i64,
+}
+
+/// Clock offset and drift estimator for one target
+/// client_time = target_time + offset + drift * (target_time - target_time_0)
+#[derive(Debug, Default)]
+pub struct XcpClockSync {
+    samples: Vec<XcpClockSample>,
+    offset_ns: f64,
+    drift: f64,
+    target_time_0: i64,
+    error_bound_ns: i64,
+}
+
+impl XcpClockSync {
+    // Round trips longer than this factor times the shortest round trip are ignored as outliers
+    const MAX_ROUND_TRIP_FACTOR: i64 = 4;
+
+    pub fn new() -> XcpClockSync {
+        XcpClockSync::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = XcpClockSync::default();
+    }
+
+    /// Add a round trip, client time before and after the GET_DAQ_CLOCK command and the target time in the response
+    pub fn add_round_trip(&mut self, client_time_before_ns: i64, client_time_after_ns: i64, target_time_ns: i64) {
+        let round_trip_ns = client_time_after_ns - client_time_before_ns;
+        self.samples.push(XcpClockSample {
+            client_time_ns: client_time_before_ns + round_trip_ns / 2,
+            target_time_ns,
+            round_trip_ns,
+        });
+        self.estimate();
+    }
+
+    /// Estimated offset in ns at the first round trip
+    pub fn get_offset_ns(&self) -> f64 {
+        self.offset_ns
+    }
+
+    /// Estimated drift of the target clock in ppm (positive, if the target clock is slow)
+    pub fn get_drift_ppm(&self) -> f64 {
+        self.drift * 1E6
+    }
+
+    /// Bound of the alignment error in ns
+    /// Half of the shortest round trip plus the largest residual of the fit
+    pub fn get_error_bound_ns(&self) -> i64 {
+        self.error_bound_ns
+    }
+
+    /// Convert a target time in ns to client time in ns
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    pub fn to_client_time(&self, target_time_ns: i64) -> i64 {
+        let dt = (target_time_ns - self.target_time_0) as f64;
+        target_time_ns + (self.offset_ns + self.drift * dt).round() as i64
+    }
+
+    // Least squares fit of offset and drift over all valid round trips
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn estimate(&mut self) {
+        let Some(min_rtt) = self.samples.iter().map(|s| s.round_trip_ns).min() else {
+            return;
+        };
+        let valid: Vec<&XcpClockSample> = self.samples.iter().filter(|s| s.round_trip_ns <= min_rtt.max(1) * Self::MAX_ROUND_TRIP_FACTOR).collect();
+        self.target_time_0 = valid[0].target_time_ns;
+
+        // x = target time since first sample, y = client time - target time
+        let n = valid.len() as f64;
+        let (mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0);
+        for s in &valid {
+            let x = (s.target_time_ns - self.target_time_0) as f64;
+            let y = (s.client_time_ns - s.target_time_ns) as f64;
+            sx += x;
+            sy += y;
+            sxx += x * x;
+            sxy += x * y;
+        }
+        let d = n * sxx - sx * sx;
+        if valid.len() < 2 || d.abs() < f64::EPSILON {
+            self.drift = 0.0;
+            self.offset_ns = sy / n;
+        } else {
+            self.drift = (n * sxy - sx * sy) / d;
+            self.offset_ns = (sy - self.drift * sx) / n;
+        }
+
+        // Error bound
+        let max_residual = valid.iter().map(|s| (self.to_client_time(s.target_time_ns) - s.client_time_ns).abs()).max().unwrap_or(0);
+        self.error_bound_ns = min_rtt / 2 + max_residual;
+        trace!(
+            "Clock sync: {} samples, offset = {:.0} ns, drift = {:.3} ppm, error bound = {} ns",
+            valid.len(),
+            self.offset_ns,
+            self.get_drift_ppm(),
+            self.error_bound_ns
+        );
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod clock_sync_tests {
+
+    use super::*;
+
+    // Client time of a target time, with 1 ms offset and the target clock 100 ppm slow
+    fn client_time(target_time_ns: i64) -> i64 {
+        1_000_000 + target_time_ns + target_time_ns / 10_000
+    }
+
+    #[test]
+    fn test_clock_sync_offset_and_drift() {
+        let mut sync = XcpClockSync::new();
+        assert!(sync.is_empty());
+        sync.add_round_trip(client_time(0) - 500, client_time(0) + 500, 0);
+        assert_eq!(sync.get_offset_ns(), 1_000_000.0);
+        assert_eq!(sync.get_drift_ppm(), 0.0);
+        assert_eq!(sync.get_error_bound_ns(), 500);
+
+        for i in 1..10 {
+            let target_time_ns = i * 1_000_000_000;
+            sync.add_round_trip(client_time(target_time_ns) - 500, client_time(target_time_ns) + 500, target_time_ns);
+        }
+        assert_eq!(sync.len(), 10);
+        assert!((sync.get_drift_ppm() - 100.0).abs() < 1E-6);
+        assert!((sync.get_offset_ns() - 1_000_000.0).abs() < 1.0);
+        assert!(sync.get_error_bound_ns() <= 501);
+        assert!((sync.to_client_time(20_000_000_000) - client_time(20_000_000_000)).abs() <= 1);
+
+        sync.clear();
+        assert!(sync.is_empty());
+    }
+
+    #[test]
+    fn test_clock_sync_outlier() {
+        // A round trip longer than 4 times the shortest one is ignored
+        let mut sync = XcpClockSync::new();
+        sync.add_round_trip(client_time(0) - 500, client_time(0) + 500, 0);
+        sync.add_round_trip(client_time(1_000_000_000) - 500, client_time(1_000_000_000) + 500, 1_000_000_000);
+        sync.add_round_trip(client_time(2_000_000_000) - 500, client_time(2_000_000_000) + 9_500, 2_000_000_000);
+        assert_eq!(sync.len(), 3);
+        assert!((sync.get_drift_ppm() - 100.0).abs() < 1E-6);
+        assert_eq!(sync.get_error_bound_ns(), 500);
+    }
+}
@@ -0,0 +1,295 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module commands
+// Typed XCP commands with a declarative definition of their parameter layout
+// Each command is a struct with named fields, encoded to and decoded from the command packet by code generated by the xcp_commands! macro
+// u16 and u32 parameters are aligned to their size as required by XCP, additional reserved bytes before a parameter are given in brackets
+// Commands with variable length data (DOWNLOAD, UNLOCK, ..) and vendor specific USER commands are built with XcpCommandBuilder
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::fmt;
+
+use super::xcp::*;
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Parameter types
+
+/// Parameter type of a typed XCP command, encoded little endian
+pub trait XcpCommandParam: Copy {
+    /// Size in bytes, the parameter is aligned to its size
+    const SIZE: usize;
+
+    /// Append the parameter to a command
+    fn encode(self, builder: &mut XcpCommandBuilder);
+
+    /// Read the parameter at offset of a command packet
+    fn decode(packet: &[u8], offset: usize) -> Option<Self>;
+
+    /// Format the parameter value for logging
+    fn format(self) -> String;
+}
+
+impl XcpCommandParam for u8 {
+    const SIZE: usize = 1;
+    fn encode(self, builder: &mut XcpCommandBuilder) {
+        builder.add_u8(self);
+    }
+    fn decode(packet: &[u8], offset: usize) -> Option<Self> {
+        packet.get(offset).copied()
+    }
+    fn format(self) -> String {
+        self.to_string()
+    }
+}
+
+impl XcpCommandParam for u16 {
+    const SIZE: usize = 2;
+    fn encode(self, builder: &mut XcpCommandBuilder) {
+        builder.align(2).add_u16(self);
+    }
+    fn decode(packet: &[u8], offset: usize) -> Option<Self> {
+        Some(u16::from_le_bytes(packet.get(offset..offset + 2)?.try_into().ok()?))
+    }
+    fn format(self) -> String {
+        self.to_string()
+    }
+}
+
+impl XcpCommandParam for u32 {
+    const SIZE: usize = 4;
+    fn encode(self, builder: &mut XcpCommandBuilder) {
+        builder.align(4).add_u32(self);
+    }
+    fn decode(packet: &[u8], offset: usize) -> Option<Self> {
+        Some(u32::from_le_bytes(packet.get(offset..offset + 4)?.try_into().ok()?))
+    }
+    fn format(self) -> String {
+        format!("0x{:X}", self)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Typed commands
+
+/// Typed XCP command with a fixed parameter layout
+pub trait XcpTypedCommand: fmt::Display {
+    /// Command code
+    const CODE: u8;
+
+    /// Command name as in the XCP specification, e.g. "SET_DAQ_LIST_MODE"
+    const NAME: &'static str;
+
+    /// Append the parameters to a command
+    fn encode(&self, builder: &mut XcpCommandBuilder);
+
+    /// Decode a command packet without transport layer header, starting with the command code
+    /// None if the command code does not match or the packet is too short
+    fn decode(packet: &[u8]) -> Option<Self>
+    where
+        Self: Sized;
+
+    /// Parameter names and formatted values
+    fn params(&self) -> Vec<(&'static str, String)>;
+
+    /// Command packet with transport layer header
+    fn to_packet(&self) -> Vec<u8> {
+        let mut builder = XcpCommandBuilder::new(Self::CODE);
+        self.encode(&mut builder);
+        builder.build().to_vec()
+    }
+}
+
+// Offset of a parameter in a command packet, after the previous parameter at offset, reserved bytes and alignment
+fn param_offset(offset: usize, reserved: usize, size: usize) -> usize {
+    (offset + reserved).next_multiple_of(size)
+}
+
+// Declarative command definitions
+// Name(command code) { [reserved bytes] parameter: type, ... }
+macro_rules! xcp_commands {
+    ($( $(#[$meta:meta])* $name:ident($code:ident) { $( $([$reserved:literal])? $param:ident : $ty:ty ),* $(,)? } )*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+            pub struct $name {
+                $( pub $param: $ty, )*
+            }
+
+            impl XcpTypedCommand for $name {
+                const CODE: u8 = $code;
+                const NAME: &'static str = stringify!($code).split_at(3).1;
+
+                fn encode(&self, builder: &mut XcpCommandBuilder) {
+                    $(
+                        $( builder.add_u8_slice(&[0; $reserved]); )?
+                        <$ty as XcpCommandParam>::encode(self.$param, builder);
+                    )*
+                }
+
+                #[allow(unused_mut, unused_variables, unused_assignments)]
+                fn decode(packet: &[u8]) -> Option<Self> {
+                    if *packet.first()? != $code {
+                        return None;
+                    }
+                    let mut offset = 1;
+                    $(
+                        offset = param_offset(offset, 0 $( + $reserved )?, <$ty as XcpCommandParam>::SIZE);
+                        let $param = <$ty as XcpCommandParam>::decode(packet, offset)?;
+                        offset += <$ty as XcpCommandParam>::SIZE;
+                    )*
+                    Some($name { $( $param, )* })
+                }
+
+                fn params(&self) -> Vec<(&'static str, String)> {
+                    vec![ $( (stringify!($param), <$ty as XcpCommandParam>::format(self.$param)), )* ]
+                }
+            }
+
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    write!(f, "{}", Self::NAME)?;
+                    for (name, value) in self.params() {
+                        write!(f, " {}={}", name, value)?;
+                    }
+                    Ok(())
+                }
+            }
+        )*
+
+        /// Format a command packet without transport layer header with the command name and parameter names, for logging
+        /// None for commands which are not defined as typed commands
+        pub fn format_command(packet: &[u8]) -> Option<String> {
+            match *packet.first()? {
+                $( $code => $name::decode(packet).map(|cmd| cmd.to_string()), )*
+                _ => None,
+            }
+        }
+    };
+}
+
+xcp_commands! {
+    /// CONNECT
+    Connect(CC_CONNECT) { mode: u8 }
+    /// GET_STATUS
+    GetStatus(CC_GET_STATUS) {}
+    /// GET_ID, id_type is one of IDT_xxx
+    GetId(CC_GET_ID) { id_type: u8 }
+    /// GET_SEED, mode 0 is the first part of the seed, resource is one of RM_xxx
+    GetSeed(CC_GET_SEED) { mode: u8, resource: u8 }
+    /// SET_REQUEST
+    SetRequest(CC_SET_REQUEST) { mode: u8, session_configuration_id: u16 }
+    /// SET_MTA
+    SetMta(CC_SET_MTA) { [2] addr_ext: u8, addr: u32 }
+    /// UPLOAD
+    Upload(CC_UPLOAD) { size: u8 }
+    /// SHORT_UPLOAD
+    ShortUpload(CC_SHORT_UPLOAD) { size: u8, [1] addr_ext: u8, addr: u32 }
+    /// BUILD_CHECKSUM of size bytes at the MTA
+    BuildChecksum(CC_BUILD_CHECKSUM) { size: u32 }
+    /// SET_CAL_PAGE, mode is CAL_PAGE_MODE_xxx, with bit 7 for all segments
+    SetCalPage(CC_SET_CAL_PAGE) { mode: u8, segment: u8, page: u8 }
+    /// GET_CAL_PAGE
+    GetCalPage(CC_GET_CAL_PAGE) { mode: u8, segment: u8 }
+    /// GET_SEGMENT_INFO, mode 0 is basic address info, 1 standard info, 2 mapping info
+    GetSegmentInfo(CC_GET_SEGMENT_INFO) { mode: u8, segment: u8, info: u8, mapping_index: u8 }
+    /// GET_PAGE_INFO
+    GetPageInfo(CC_GET_PAGE_INFO) { [1] segment: u8, page: u8 }
+    /// SET_SEGMENT_MODE, mode bit 0 is the freeze mode
+    SetSegmentMode(CC_SET_SEGMENT_MODE) { mode: u8, segment: u8 }
+    /// GET_SEGMENT_MODE
+    GetSegmentMode(CC_GET_SEGMENT_MODE) { [1] segment: u8 }
+    /// COPY_CAL_PAGE
+    CopyCalPage(CC_COPY_CAL_PAGE) { src_segment: u8, src_page: u8, dst_segment: u8, dst_page: u8 }
+    /// SET_DAQ_PTR
+    SetDaqPtr(CC_SET_DAQ_PTR) { daq: u16, odt: u8, entry: u8 }
+    /// WRITE_DAQ to the ODT entry at the DAQ pointer
+    WriteDaq(CC_WRITE_DAQ) { bit_offset: u8, size: u8, addr_ext: u8, addr: u32 }
+    /// SET_DAQ_LIST_MODE, mode is a combination of XCP_DAQ_MODE_xxx
+    SetDaqListMode(CC_SET_DAQ_LIST_MODE) { mode: u8, daq: u16, event: u16, prescaler: u8, priority: u8 }
+    /// START_STOP_DAQ_LIST, mode 0 = stop, 1 = start, 2 = select
+    StartStopDaqList(CC_START_STOP_DAQ_LIST) { mode: u8, daq: u16 }
+    /// START_STOP_SYNCH, mode 0 = stop all, 1 = start selected, 2 = stop selected, 3 = prepare selected
+    StartStopSynch(CC_START_STOP_SYNCH) { mode: u8 }
+    /// GET_DAQ_CLOCK
+    GetDaqClock(CC_GET_DAQ_CLOCK) {}
+    /// GET_DAQ_PROCESSOR_INFO
+    GetDaqProcessorInfo(CC_GET_DAQ_PROCESSOR_INFO) {}
+    /// GET_DAQ_RESOLUTION_INFO
+    GetDaqResolutionInfo(CC_GET_DAQ_RESOLUTION_INFO) {}
+    /// GET_DAQ_EVENT_INFO
+    GetDaqEventInfo(CC_GET_DAQ_EVENT_INFO) { event: u16 }
+    /// FREE_DAQ
+    FreeDaq(CC_FREE_DAQ) {}
+    /// ALLOC_DAQ
+    AllocDaq(CC_ALLOC_DAQ) { daq_count: u16 }
+    /// ALLOC_ODT
+    AllocOdt(CC_ALLOC_ODT) { daq: u16, odt_count: u8 }
+    /// ALLOC_ODT_ENTRY
+    AllocOdtEntry(CC_ALLOC_ODT_ENTRY) { daq: u16, odt: u8, entry_count: u8 }
+    /// TIME_CORRELATION_PROPERTIES
+    TimeCorrelationProperties(CC_TIME_CORRELATION_PROPERTIES) { set_properties: u8, get_properties_request: u8, cluster_id: u16 }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod commands_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_command_layout() {
+        // Transport layer header with the length of the command packet, u16 and u32 parameters are aligned, reserved bytes are zero
+        let cmd = SetMta { addr_ext: 1, addr: 0x8000_1234 };
+        assert_eq!(cmd.to_packet(), [8, 0, 0, 0, CC_SET_MTA, 0, 0, 1, 0x34, 0x12, 0x00, 0x80]);
+        let cmd = SetDaqListMode {
+            mode: 0x10,
+            daq: 2,
+            event: 3,
+            prescaler: 1,
+            priority: 0,
+        };
+        assert_eq!(cmd.to_packet(), [8, 0, 0, 0, CC_SET_DAQ_LIST_MODE, 0x10, 2, 0, 3, 0, 1, 0]);
+        let cmd = WriteDaq {
+            bit_offset: 0xFF,
+            size: 4,
+            addr_ext: 0,
+            addr: 0x100,
+        };
+        assert_eq!(cmd.to_packet(), [8, 0, 0, 0, CC_WRITE_DAQ, 0xFF, 4, 0, 0, 1, 0, 0]);
+        assert_eq!(GetStatus {}.to_packet(), [1, 0, 0, 0, CC_GET_STATUS]);
+    }
+
+    #[test]
+    fn test_command_decode() {
+        let cmd = ShortUpload {
+            size: 8,
+            addr_ext: 1,
+            addr: 0x1000,
+        };
+        let packet = cmd.to_packet();
+        assert_eq!(ShortUpload::decode(&packet[4..]), Some(cmd));
+        assert_eq!(ShortUpload::decode(&packet[4..packet.len() - 1]), None);
+        assert_eq!(Upload::decode(&packet[4..]), None);
+
+        let cmd = TimeCorrelationProperties {
+            set_properties: 1,
+            get_properties_request: 2,
+            cluster_id: 0x1234,
+        };
+        assert_eq!(TimeCorrelationProperties::decode(&cmd.to_packet()[4..]), Some(cmd));
+    }
+
+    #[test]
+    fn test_format_command() {
+        assert_eq!(SetMta::NAME, "SET_MTA");
+        let cmd = SetMta { addr_ext: 1, addr: 0x8000_1234 };
+        assert_eq!(cmd.to_string(), "SET_MTA addr_ext=1 addr=0x80001234");
+        assert_eq!(format_command(&cmd.to_packet()[4..]), Some(cmd.to_string()));
+        assert_eq!(format_command(&[CC_GET_DAQ_CLOCK]), Some("GET_DAQ_CLOCK".to_string()));
+        assert_eq!(format_command(&[CC_DOWNLOAD, 1, 0]), None);
+        assert_eq!(format_command(&[]), None);
+    }
+}
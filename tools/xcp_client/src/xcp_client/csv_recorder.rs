@@ -0,0 +1,401 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module csv_recorder
+// Recording of DAQ data into CSV tables with one column per signal, for quick scripted analysis without MDF tooling
+// Each DAQ list (event) is a table with one row per event cycle (timestamp_ns,<signal>,...), written into a separate file per event,
+// or all events are resampled with a fixed period into one merged file, the last value of each signal is repeated until it changes
+// Timestamps are client time in ns since the measurement start, like the samples of XcpMergedRecording
+// The --csv option of the command line tool records the --mea measurement with XcpCsvRecorder as DAQ decoder
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use xcp_registry::Registry;
+
+use super::{A2lTypeEncoding, OdtEntry, XcpDaqDecoder, XcpMergedRecording, XcpTargetDaqDecoder};
+
+/// Output of the CSV recorder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XcpCsvMode {
+    /// A file <stem>_<event>.csv for each event, with one row per event cycle
+    PerEvent,
+    /// One merged file with the signals of all events, resampled with the given period
+    Merged(Duration),
+}
+
+// Table of a DAQ list
+struct CsvTable {
+    columns: Vec<String>, // Signal names
+    last_odt: u8,         // The row is complete with this ODT
+    timestamp_ns: i64,
+    row: Vec<Option<f64>>,
+    writer: Option<BufWriter<File>>, // Per event mode
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpCsvRecorder
+
+/// DAQ decoder recording into CSV files with one column per signal
+/// Values are raw values, or physical values with the linear conversion of the registry, see set_physical
+pub struct XcpCsvRecorder {
+    decoder: XcpTargetDaqDecoder,
+    recording: Arc<Mutex<XcpMergedRecording>>,
+    path: PathBuf,
+    mode: XcpCsvMode,
+    events: HashMap<u16, String>, // Event names from the registry
+    daq_header_size: u8,
+    tables: Vec<Option<CsvTable>>,     // Table of each DAQ list
+    rows: Vec<(i64, usize, Vec<f64>)>, // Merged mode: rows (timestamp, DAQ list, values) until measurement stop
+    files: Vec<PathBuf>,
+    failed: bool, // A file error stopped the recording
+}
+
+impl XcpCsvRecorder {
+    /// Recorder writing to path, in per event mode the event name is appended to the file stem
+    pub fn new<P: AsRef<Path>>(path: P, mode: XcpCsvMode) -> XcpCsvRecorder {
+        let recording = Arc::new(Mutex::new(XcpMergedRecording::new()));
+        let target = recording.lock().add_target("csv");
+        XcpCsvRecorder {
+            decoder: XcpTargetDaqDecoder::new(target, Arc::clone(&recording)),
+            recording,
+            path: path.as_ref().to_path_buf(),
+            mode,
+            events: HashMap::new(),
+            daq_header_size: 4,
+            tables: Vec::new(),
+            rows: Vec::new(),
+            files: Vec::new(),
+            failed: false,
+        }
+    }
+
+    /// Take the event names for the per event file names from registry, default is event_<id>
+    pub fn set_registry(mut self, registry: &Registry) -> Self {
+        for event in &registry.event_list {
+            self.events.insert(event.get_id(), event.get_unique_name(registry).to_string());
+        }
+        self
+    }
+
+    /// Record physical values, converted with the linear conversion from the registry
+    pub fn set_physical(mut self, physical: bool) -> Self {
+        self.decoder.set_physical(physical);
+        self
+    }
+
+    /// Files written, available after measurement start (per event) or stop (merged)
+    pub fn get_files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Check if a file error stopped the recording
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    // Create the tables of the DAQ lists, in per event mode the files with the header line
+    fn create(&mut self, odt_entries: &[Vec<OdtEntry>]) -> std::io::Result<()> {
+        self.tables.clear();
+        self.rows.clear();
+        self.files.clear();
+        for (daq, entries) in odt_entries.iter().enumerate() {
+            if entries.is_empty() {
+                self.tables.push(None); // Spare DAQ list
+                continue;
+            }
+            // Arrays have a column for each element, blobs and typedef instances are not recorded
+            let columns: Vec<String> = entries
+                .iter()
+                .filter(|e| !matches!(e.a2l_type.encoding, A2lTypeEncoding::Blob))
+                .flat_map(|e| (0..e.get_element_count()).map(|i| e.get_element_name(i)))
+                .collect();
+            let writer = if self.mode == XcpCsvMode::PerEvent {
+                let event = entries[0].a2l_addr.event;
+                let name = match event.and_then(|id| self.events.get(&id)) {
+                    Some(name) => name.clone(),
+                    None => event.map_or(format!("daq_{}", daq), |id| format!("event_{}", id)),
+                };
+                let stem = self.path.file_stem().and_then(|s| s.to_str()).unwrap_or("daq");
+                let path = self.path.with_file_name(format!("{}_{}.csv", stem, name));
+                let mut writer = BufWriter::new(File::create(&path)?);
+                writeln!(writer, "timestamp_ns,{}", columns.join(","))?;
+                debug!("CSV recorder: {} with {} columns", path.display(), columns.len());
+                self.files.push(path);
+                Some(writer)
+            } else {
+                None
+            };
+            self.tables.push(Some(CsvTable {
+                row: vec![None; columns.len()],
+                columns,
+                last_odt: entries.iter().map(|e| e.odt).max().unwrap_or(0),
+                timestamp_ns: 0,
+                writer,
+            }));
+        }
+        Ok(())
+    }
+
+    // Complete the row of a DAQ list, write it in per event mode, keep it in merged mode
+    fn write_row(&mut self, daq: usize) -> std::io::Result<()> {
+        let Some(Some(table)) = self.tables.get_mut(daq) else {
+            return Ok(());
+        };
+        if table.row.iter().all(Option::is_none) {
+            return Ok(());
+        }
+        if let Some(writer) = table.writer.as_mut() {
+            let mut line = table.timestamp_ns.to_string();
+            for value in &table.row {
+                line.push(',');
+                if let Some(value) = value {
+                    line.push_str(&value.to_string());
+                }
+            }
+            line.push('\n');
+            writer.write_all(line.as_bytes())?;
+        } else {
+            self.rows.push((table.timestamp_ns, daq, table.row.iter().map(|v| v.unwrap_or(f64::NAN)).collect()));
+        }
+        table.row.fill(None);
+        Ok(())
+    }
+
+    // Write the merged file, resampled with period, the last value of each signal is repeated, empty before its first value
+    fn write_merged(&mut self, period: Duration) -> std::io::Result<()> {
+        self.rows.sort_by_key(|(t, _, _)| *t);
+        let (Some((first, _, _)), Some((last, _, _))) = (self.rows.first(), self.rows.last()) else {
+            return Ok(());
+        };
+        let (first, last) = (*first, *last);
+
+        // Columns of all DAQ lists, the column offset of each DAQ list
+        let mut header = String::from("timestamp_ns");
+        let mut offsets = Vec::with_capacity(self.tables.len());
+        let mut count = 0;
+        for table in &self.tables {
+            offsets.push(count);
+            if let Some(table) = table {
+                for column in &table.columns {
+                    header.push(',');
+                    header.push_str(column);
+                }
+                count += table.columns.len();
+            }
+        }
+
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        writeln!(writer, "{}", header)?;
+        let period_ns = i64::try_from(period.as_nanos()).unwrap_or(i64::MAX).max(1);
+        let mut values: Vec<Option<f64>> = vec![None; count];
+        let mut next = self.rows.iter().peekable();
+        let mut t = first;
+        let mut lines = 0;
+        while t <= last {
+            while let Some((_, daq, row)) = next.next_if(|(ts, _, _)| *ts <= t) {
+                for (i, v) in row.iter().enumerate() {
+                    if !v.is_nan() {
+                        values[offsets[*daq] + i] = Some(*v);
+                    }
+                }
+            }
+            let mut line = t.to_string();
+            for value in &values {
+                line.push(',');
+                if let Some(value) = value {
+                    line.push_str(&value.to_string());
+                }
+            }
+            line.push('\n');
+            writer.write_all(line.as_bytes())?;
+            lines += 1;
+            t = t.saturating_add(period_ns);
+            if t == i64::MAX {
+                break;
+            }
+        }
+        writer.flush()?;
+        debug!("CSV recorder: {} rows resampled to {} rows with period {:?}", self.rows.len(), lines, period);
+        self.files.push(self.path.clone());
+        Ok(())
+    }
+
+    // Write the pending rows and close the files
+    fn close(&mut self) -> std::io::Result<()> {
+        for daq in 0..self.tables.len() {
+            self.write_row(daq)?;
+        }
+        for table in self.tables.iter_mut().flatten() {
+            if let Some(mut writer) = table.writer.take() {
+                writer.flush()?;
+            }
+        }
+        if let XcpCsvMode::Merged(period) = self.mode {
+            self.write_merged(period)?;
+            self.rows.clear();
+        }
+        info!("CSV recorder: {} files written", self.files.len());
+        Ok(())
+    }
+
+    // Put the decoded samples of a DAQ packet into the row of its DAQ list
+    fn decode_odt(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let odt = data.first().copied().unwrap_or(0);
+        let daq = match self.daq_header_size {
+            4 if data.len() >= 4 => data[2] as usize | (data[3] as usize) << 8,
+            3 if data.len() >= 3 => data[1] as usize | (data[2] as usize) << 8,
+            _ if data.len() >= 2 => data[1] as usize,
+            _ => return Ok(()),
+        };
+        let samples = self.recording.lock().take_samples();
+        let Some(Some(table)) = self.tables.get_mut(daq) else {
+            return Ok(());
+        };
+        for s in samples {
+            if let Some(i) = table.columns.iter().position(|c| *c == s.name) {
+                table.row[i] = Some(s.value);
+                table.timestamp_ns = s.timestamp_ns;
+            }
+        }
+        if odt == table.last_odt {
+            self.write_row(daq)?;
+        }
+        Ok(())
+    }
+}
+
+impl XcpDaqDecoder for XcpCsvRecorder {
+    fn start(&mut self, odt_entries: Vec<Vec<OdtEntry>>, timestamp_raw64: u64) {
+        self.failed = false;
+        if let Err(e) = self.create(&odt_entries) {
+            error!("CSV recorder: could not create {}: {}", self.path.display(), e);
+            self.failed = true;
+        }
+        self.decoder.start(odt_entries, timestamp_raw64);
+    }
+
+    fn stop(&mut self) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.close() {
+            error!("CSV recorder: {}", e);
+            self.failed = true;
+        }
+    }
+
+    fn update_daq_list(&mut self, daq: u16, odt_entries: Vec<OdtEntry>) {
+        // The columns are written on measurement start
+        if !odt_entries.is_empty() {
+            warn!("CSV recorder: DAQ list {} configured while running is not recorded", daq);
+        }
+    }
+
+    fn set_daq_properties(&mut self, timestamp_resolution: u64, daq_header_size: u8) {
+        self.daq_header_size = daq_header_size;
+        self.decoder.set_daq_properties(timestamp_resolution, daq_header_size);
+    }
+
+    fn set_daq_timestamp_size(&mut self, timestamp_size: u8) {
+        self.decoder.set_daq_timestamp_size(timestamp_size);
+    }
+
+    fn decode(&mut self, lost: u32, data: &[u8]) {
+        self.decoder.decode(lost, data);
+        if self.failed {
+            self.recording.lock().take_samples();
+            return;
+        }
+        if let Err(e) = self.decode_odt(data) {
+            error!("CSV recorder: recording stopped, {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod csv_recorder_tests {
+
+    use super::*;
+    use crate::xcp_client::{A2lAddr, A2lType};
+
+    fn odt_entry(name: &str, event: u16, odt: u8, size: usize) -> OdtEntry {
+        OdtEntry {
+            name: name.to_string(),
+            a2l_type: A2lType {
+                size,
+                encoding: A2lTypeEncoding::Unsigned,
+            },
+            a2l_addr: A2lAddr {
+                ext: 0,
+                addr: 0,
+                event: Some(event),
+            },
+            odt,
+            offset: 0,
+            conversion: None,
+            dim: [1, 1],
+        }
+    }
+
+    // Record DAQ list 0 (event 7, signals a and b in 2 ODTs) at 1 and 3 ms and DAQ list 1 (event 8, signal c) at 2 ms
+    fn record(recorder: &mut XcpCsvRecorder) {
+        recorder.set_daq_properties(1000, 4);
+        recorder.set_daq_timestamp_size(4);
+        recorder.start(vec![vec![odt_entry("a", 7, 0, 4), odt_entry("b", 7, 1, 2)], vec![odt_entry("c", 8, 0, 1)]], 0);
+        recorder.decode(0, &[0, 0, 0, 0, 0xE8, 0x03, 0, 0, 1, 0, 0, 0]);
+        recorder.decode(0, &[1, 0, 0, 0, 2, 0]);
+        recorder.decode(0, &[0, 0, 1, 0, 0xD0, 0x07, 0, 0, 3]);
+        recorder.decode(0, &[0, 0, 0, 0, 0xB8, 0x0B, 0, 0, 4, 0, 0, 0]);
+        recorder.decode(0, &[1, 0, 0, 0, 5, 0]);
+        recorder.stop();
+        assert!(!recorder.is_failed());
+    }
+
+    // Lines of a CSV file with the timestamps relative to the first row
+    fn read_lines(path: &Path) -> Vec<String> {
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let t0: i64 = lines[1].split(',').next().unwrap().parse().unwrap();
+        for line in lines.iter_mut().skip(1) {
+            let (t, values) = line.split_once(',').unwrap();
+            *line = format!("{},{}", t.parse::<i64>().unwrap() - t0, values);
+        }
+        lines
+    }
+
+    #[test]
+    fn test_csv_recorder_per_event() {
+        let path = std::env::temp_dir().join(format!("xcp_client_csv_{}.csv", std::process::id()));
+        let mut recorder = XcpCsvRecorder::new(&path, XcpCsvMode::PerEvent);
+        record(&mut recorder);
+        let files = recorder.get_files().to_vec();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].to_string_lossy().ends_with("_event_7.csv"));
+        assert_eq!(read_lines(&files[0]), vec!["timestamp_ns,a,b", "0,1,2", "2000000,4,5"]);
+        assert_eq!(read_lines(&files[1]), vec!["timestamp_ns,c", "0,3"]);
+    }
+
+    #[test]
+    fn test_csv_recorder_merged() {
+        let path = std::env::temp_dir().join(format!("xcp_client_csv_merged_{}.csv", std::process::id()));
+        let mut recorder = XcpCsvRecorder::new(&path, XcpCsvMode::Merged(Duration::from_micros(500)));
+        record(&mut recorder);
+        assert_eq!(recorder.get_files(), std::slice::from_ref(&path));
+        assert_eq!(
+            read_lines(&path),
+            vec!["timestamp_ns,a,b,c", "0,1,2,", "500000,1,2,", "1000000,1,2,3", "1500000,1,2,3", "2000000,4,5,3"]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module daq_loss
+// DAQ packet loss statistics per event and loss rate threshold assertion for automated tests
+// Lost packets are detected from gaps in the transport layer counter, the counter is common to all DAQ lists, so a gap is attributed to the
+// event of the next received DAQ packet, which is exact for a single event and an approximation for multiple events
+// The --max-loss-ppm option of the command line tool records the statistics of the --mea measurement with XcpRunSummary::add_daq_loss()
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use super::xcp::*;
+use super::{XcpClient, XcpExitCode, XcpRunSummary};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpDaqLossStatistics
+
+/// Received and lost DAQ packets of an event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XcpDaqEventLoss {
+    pub event: u16,
+    pub packets: u64,
+    pub lost: u64,
+}
+
+impl XcpDaqEventLoss {
+    /// Lost packets in parts per million of all packets
+    pub fn get_loss_ppm(&self) -> f64 {
+        loss_ppm(self.packets, self.lost)
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn loss_ppm(packets: u64, lost: u64) -> f64 {
+    if packets + lost == 0 {
+        0.0
+    } else {
+        lost as f64 * 1_000_000.0 / (packets + lost) as f64
+    }
+}
+
+/// DAQ packet loss statistics of the current or last measurement, by DAQ list
+#[derive(Debug, Clone, Default)]
+pub struct XcpDaqLossStatistics {
+    daq_header_size: u8,
+    daq_events: Vec<Option<u16>>, // Event of each DAQ list, None for unused spare DAQ lists
+    packets: Vec<u64>,
+    lost: Vec<u64>,
+    pending: u64, // Lost packets not yet attributed to a DAQ list
+}
+
+impl XcpDaqLossStatistics {
+    // Reset at measurement start
+    pub(super) fn start(&mut self, daq_header_size: u8, daq_events: Vec<Option<u16>>) {
+        self.daq_header_size = daq_header_size;
+        self.packets = vec![0; daq_events.len()];
+        self.lost = vec![0; daq_events.len()];
+        self.daq_events = daq_events;
+        self.pending = 0;
+    }
+
+    // Set the event of a spare DAQ list started while DAQ is running
+    pub(super) fn set_daq_event(&mut self, daq: u16, event: Option<u16>) {
+        if let Some(e) = self.daq_events.get_mut(daq as usize) {
+            *e = event;
+        }
+    }
+
+    // Gap in the transport layer counter
+    pub(super) fn add_lost(&mut self, lost: u64) {
+        self.pending += lost;
+    }
+
+    // Received DAQ packet, without transport layer header
+    pub(super) fn record(&mut self, data: &[u8]) {
+        let daq = match self.daq_header_size {
+            2 if data.len() >= 2 => data[1] as usize,
+            4 if data.len() >= 4 => data[2] as usize | ((data[3] as usize) << 8),
+            _ => return,
+        };
+        if daq < self.packets.len() {
+            self.packets[daq] += 1;
+            self.lost[daq] += self.pending;
+            self.pending = 0;
+        }
+    }
+
+    /// Total received DAQ packets
+    pub fn get_packet_count(&self) -> u64 {
+        self.packets.iter().sum()
+    }
+
+    /// Total lost DAQ packets
+    pub fn get_lost_count(&self) -> u64 {
+        self.lost.iter().sum::<u64>() + self.pending
+    }
+
+    /// Lost packets in parts per million of all packets
+    pub fn get_loss_ppm(&self) -> f64 {
+        loss_ppm(self.get_packet_count(), self.get_lost_count())
+    }
+
+    /// Received and lost packets of each event, sorted by event id
+    pub fn get_event_loss(&self) -> Vec<XcpDaqEventLoss> {
+        let mut events: BTreeMap<u16, XcpDaqEventLoss> = BTreeMap::new();
+        for (daq, event) in self.daq_events.iter().enumerate() {
+            if let Some(event) = *event {
+                let e = events.entry(event).or_insert(XcpDaqEventLoss { event, ..Default::default() });
+                e.packets += self.packets[daq];
+                e.lost += self.lost[daq];
+            }
+        }
+        events.into_values().collect()
+    }
+}
+
+impl std::fmt::Display for XcpDaqLossStatistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for e in self.get_event_loss() {
+            writeln!(f, "event {:>5}: {:>10} packets, {:>8} lost, {:>10.1} ppm", e.event, e.packets, e.lost, e.get_loss_ppm())?;
+        }
+        write!(
+            f,
+            "total      : {:>10} packets, {:>8} lost, {:>10.1} ppm",
+            self.get_packet_count(),
+            self.get_lost_count(),
+            self.get_loss_ppm()
+        )
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Loss rate threshold
+
+impl XcpClient {
+    /// Get the DAQ packet loss statistics of the current or last measurement
+    pub fn get_daq_loss_statistics(&self) -> XcpDaqLossStatistics {
+        self.daq_loss.lock().clone()
+    }
+
+    /// Check the DAQ packet loss rate of the current or last measurement against a threshold in parts per million
+    /// Fails with ERROR_DAQ_LOSS and logs the loss of each event, if the threshold is exceeded
+    pub fn check_daq_loss(&self, max_loss_ppm: f64) -> Result<XcpDaqLossStatistics, Box<dyn Error>> {
+        let stats = self.get_daq_loss_statistics();
+        if stats.get_loss_ppm() > max_loss_ppm {
+            error!("DAQ loss {:.1} ppm exceeds {:.1} ppm\n{}", stats.get_loss_ppm(), max_loss_ppm, stats);
+            return Err(Box::new(XcpError::new(ERROR_DAQ_LOSS, 0)) as Box<dyn Error>);
+        }
+        info!("DAQ loss {:.1} ppm\n{}", stats.get_loss_ppm(), stats);
+        Ok(stats)
+    }
+}
+
+impl XcpRunSummary {
+    /// Record the DAQ loss statistics, fails with DaqLoss if the loss rate exceeds max_loss_ppm
+    pub fn add_daq_loss(&mut self, stats: &XcpDaqLossStatistics, max_loss_ppm: f64) {
+        self.add("daq_packets", stats.get_packet_count());
+        self.add("daq_lost", stats.get_lost_count());
+        self.add("daq_loss_ppm", format!("{:.1}", stats.get_loss_ppm()));
+        for e in stats.get_event_loss() {
+            self.add(&format!("daq_loss_ppm_event_{}", e.event), format!("{:.1}", e.get_loss_ppm()));
+        }
+        if stats.get_loss_ppm() > max_loss_ppm {
+            self.fail(XcpExitCode::DaqLoss, &format!("{:.1} ppm > {:.1} ppm", stats.get_loss_ppm(), max_loss_ppm));
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod daq_loss_tests {
+
+    use super::*;
+
+    // DAQ packet with a 4 byte DAQ header (odt, reserved, daq)
+    fn packet(daq: u16) -> [u8; 4] {
+        let [daq_lo, daq_hi] = daq.to_le_bytes();
+        [0, 0, daq_lo, daq_hi]
+    }
+
+    // DAQ list 0 and 2 on event 1, DAQ list 1 on event 7, DAQ list 3 unused
+    fn statistics() -> XcpDaqLossStatistics {
+        let mut stats = XcpDaqLossStatistics::default();
+        stats.start(4, vec![Some(1), Some(7), Some(1), None]);
+        for _ in 0..998 {
+            stats.record(&packet(1));
+        }
+        stats.add_lost(2);
+        stats.record(&packet(0)); // The gap is attributed to the next packet
+        stats.record(&packet(2));
+        stats.record(&packet(9)); // Unknown DAQ list
+        stats
+    }
+
+    #[test]
+    fn test_daq_loss_statistics() {
+        let stats = statistics();
+        assert_eq!(stats.get_packet_count(), 1000);
+        assert_eq!(stats.get_lost_count(), 2);
+        assert!((stats.get_loss_ppm() - 2.0 * 1_000_000.0 / 1002.0).abs() < 1E-9);
+        assert_eq!(
+            stats.get_event_loss(),
+            vec![XcpDaqEventLoss { event: 1, packets: 2, lost: 2 }, XcpDaqEventLoss { event: 7, packets: 998, lost: 0 },]
+        );
+        assert!(stats.to_string().lines().nth(1).unwrap().starts_with("event     7:        998 packets,        0 lost"));
+
+        // Loss at the end of the measurement is counted, but not attributed to an event
+        let mut stats = stats;
+        stats.add_lost(3);
+        assert_eq!(stats.get_lost_count(), 5);
+        assert_eq!(stats.get_event_loss().iter().map(|e| e.lost).sum::<u64>(), 2);
+
+        // 2 byte DAQ header (odt, daq) and spare DAQ lists started while running
+        let mut stats = XcpDaqLossStatistics::default();
+        stats.start(2, vec![Some(1), None]);
+        stats.set_daq_event(1, Some(3));
+        stats.record(&[0, 1]);
+        assert_eq!(stats.get_event_loss()[1], XcpDaqEventLoss { event: 3, packets: 1, lost: 0 });
+        assert_eq!(XcpDaqLossStatistics::default().get_loss_ppm(), 0.0);
+    }
+
+    #[test]
+    fn test_daq_loss_threshold() {
+        let stats = statistics();
+        let mut summary = XcpRunSummary::new(true);
+        summary.add_daq_loss(&stats, 2000.0);
+        assert_eq!(summary.get_exit_code(), XcpExitCode::Success);
+        assert!(summary.format().contains("daq_lost=2\ndaq_loss_ppm=1996.0\ndaq_loss_ppm_event_1=500000.0\n"));
+
+        summary.add_daq_loss(&stats, 1.0);
+        assert_eq!(summary.get_exit_code(), XcpExitCode::DaqLoss);
+        assert!(summary.format().ends_with("failure=daq_loss 1996.0 ppm > 1.0 ppm\nexit_code=5\n"));
+    }
+}
@@ -0,0 +1,243 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module daq_printer
+// DAQ decoder printing the decoded samples to the console, one line per ODT entry
+// Arrays (VAL_BLK) are shown with the display mode XcpArrayDisplay: the first n elements, min/max/mean, or the first n elements with a full dump
+// of each sample to a file, blobs and typedef instances are shown with their size
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use super::{OdtEntry, XcpDaqDecoder};
+
+/// Display mode for arrays
+#[derive(Debug, Clone, PartialEq)]
+pub enum XcpArrayDisplay {
+    /// The first n elements
+    First(usize),
+    /// Minimum, maximum and mean of the elements
+    MinMax,
+    /// The first n elements, all elements of each sample are written to a CSV file (timestamp, name, elements)
+    Dump(usize, PathBuf),
+}
+
+impl Default for XcpArrayDisplay {
+    fn default() -> Self {
+        XcpArrayDisplay::First(8)
+    }
+}
+
+impl XcpArrayDisplay {
+    /// Format the values of an array
+    pub fn format(&self, values: &[f64]) -> String {
+        match self {
+            XcpArrayDisplay::First(n) | XcpArrayDisplay::Dump(n, _) => {
+                let mut s = String::from("[");
+                for (i, v) in values.iter().take(*n).enumerate() {
+                    if i > 0 {
+                        s.push_str(", ");
+                    }
+                    let _ = write!(s, "{}", v);
+                }
+                if values.len() > *n {
+                    let _ = write!(s, ", .. ({} elements)", values.len());
+                }
+                s.push(']');
+                s
+            }
+            XcpArrayDisplay::MinMax => {
+                if values.is_empty() {
+                    return "[]".to_string();
+                }
+                let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                #[allow(clippy::cast_precision_loss)]
+                let mean = values.iter().sum::<f64>() / values.len() as f64;
+                format!("[{} elements, min={}, max={}, mean={}]", values.len(), min, max, mean)
+            }
+        }
+    }
+}
+
+/// DAQ decoder printing the decoded values
+pub struct XcpDaqPrinter {
+    odt_entries: Vec<Vec<OdtEntry>>,
+    daq_header_size: u8,
+    daq_timestamp_size: u8,
+    timestamp_resolution_ns: u64,
+    timestamp_ns: u64,
+    array_display: XcpArrayDisplay,
+    physical: bool,
+    dump: Option<BufWriter<File>>,
+    event_count: usize,
+    byte_count: usize,
+}
+
+impl XcpDaqPrinter {
+    pub fn new(array_display: XcpArrayDisplay) -> XcpDaqPrinter {
+        XcpDaqPrinter {
+            odt_entries: Vec::new(),
+            daq_header_size: 4,
+            daq_timestamp_size: 4,
+            timestamp_resolution_ns: 1,
+            timestamp_ns: 0,
+            array_display,
+            physical: false,
+            dump: None,
+            event_count: 0,
+            byte_count: 0,
+        }
+    }
+
+    /// Print physical values, converted with the linear conversion from the registry, default are raw values
+    pub fn set_physical(&mut self, physical: bool) {
+        self.physical = physical;
+    }
+}
+
+// Values of an ODT entry from the data of the ODT, None if the data is too short, empty for blobs and typedef instances
+fn entry_values(entry: &OdtEntry, data: &[u8], physical: bool) -> Option<Vec<f64>> {
+    let data = data.get(entry.offset as usize..entry.offset as usize + entry.get_size())?;
+    let mut values = entry.decode_elements(data);
+    if physical {
+        values.iter_mut().for_each(|v| *v = entry.to_physical(*v));
+    }
+    Some(values)
+}
+
+impl XcpDaqDecoder for XcpDaqPrinter {
+    fn start(&mut self, odt_entries: Vec<Vec<OdtEntry>>, _timestamp_raw64: u64) {
+        self.odt_entries = odt_entries;
+        self.event_count = 0;
+        self.byte_count = 0;
+        if let XcpArrayDisplay::Dump(_, path) = &self.array_display {
+            match File::create(path) {
+                Ok(file) => self.dump = Some(BufWriter::new(file)),
+                Err(e) => error!("DAQ printer: could not create dump file {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut dump) = self.dump.take()
+            && let Err(e) = dump.flush()
+        {
+            error!("DAQ printer: {}", e);
+        }
+    }
+
+    fn update_daq_list(&mut self, daq: u16, odt_entries: Vec<OdtEntry>) {
+        if let Some(e) = self.odt_entries.get_mut(daq as usize) {
+            *e = odt_entries;
+        }
+    }
+
+    fn set_daq_properties(&mut self, timestamp_resolution: u64, daq_header_size: u8) {
+        self.timestamp_resolution_ns = timestamp_resolution;
+        self.daq_header_size = daq_header_size;
+    }
+
+    fn set_daq_timestamp_size(&mut self, timestamp_size: u8) {
+        self.daq_timestamp_size = timestamp_size;
+    }
+
+    fn decode(&mut self, lost: u32, buf: &[u8]) {
+        if lost > 0 {
+            warn!("{} DAQ packets lost", lost);
+        }
+        self.byte_count += buf.len();
+        let header_size = self.daq_header_size as usize;
+        let odt = buf.first().copied().unwrap_or(0);
+        let timestamp_size = if odt == 0 { self.daq_timestamp_size as usize } else { 0 };
+        if buf.len() < header_size + timestamp_size {
+            return;
+        }
+        let daq = match header_size {
+            4 => buf[2] as usize | (buf[3] as usize) << 8,
+            3 => buf[1] as usize | (buf[2] as usize) << 8,
+            _ => buf[1] as usize,
+        };
+        if odt == 0 {
+            self.event_count += 1;
+            let timestamp_raw = buf[header_size..header_size + timestamp_size].iter().rev().fold(0u64, |t, b| (t << 8) | u64::from(*b));
+            self.timestamp_ns = timestamp_raw * self.timestamp_resolution_ns;
+        }
+        let Some(entries) = self.odt_entries.get(daq) else {
+            warn!("Unexpected DAQ packet daq={} odt={}", daq, odt);
+            return;
+        };
+        let data = &buf[header_size + timestamp_size..];
+        for e in entries.iter().filter(|e| e.odt == odt) {
+            let Some(values) = entry_values(e, data, self.physical) else {
+                warn!("DAQ packet too short for {}", e.name);
+                continue;
+            };
+            let value = if values.is_empty() {
+                format!("{} bytes", e.get_size())
+            } else if !e.is_array() {
+                values[0].to_string()
+            } else {
+                if let Some(dump) = &mut self.dump {
+                    let line = values.iter().map(f64::to_string).collect::<Vec<_>>().join(",");
+                    if let Err(err) = writeln!(dump, "{},{},{}", self.timestamp_ns, e.name, line) {
+                        error!("DAQ printer: dump stopped, {}", err);
+                        self.dump = None;
+                    }
+                }
+                self.array_display.format(&values)
+            };
+            println!("{} daq={} odt={} {} = {}", self.timestamp_ns, daq, odt, e.name, value);
+        }
+    }
+
+    fn get_event_count(&self) -> usize {
+        self.event_count
+    }
+
+    fn get_byte_count(&self) -> usize {
+        self.byte_count
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod daq_printer_tests {
+
+    use super::*;
+    use crate::xcp_client::{A2lAddr, A2lType, A2lTypeEncoding};
+
+    #[test]
+    fn test_array_display() {
+        let values = [1.0, 2.0, 3.0, 6.0];
+        assert_eq!(XcpArrayDisplay::First(8).format(&values), "[1, 2, 3, 6]");
+        assert_eq!(XcpArrayDisplay::First(2).format(&values), "[1, 2, .. (4 elements)]");
+        assert_eq!(XcpArrayDisplay::MinMax.format(&values), "[4 elements, min=1, max=6, mean=3]");
+        assert_eq!(XcpArrayDisplay::MinMax.format(&[]), "[]");
+    }
+
+    #[test]
+    fn test_entry_values() {
+        let entry = OdtEntry {
+            name: "a".to_string(),
+            a2l_type: A2lType {
+                size: 2,
+                encoding: A2lTypeEncoding::Signed,
+            },
+            a2l_addr: A2lAddr { ext: 0, addr: 0, event: None },
+            odt: 0,
+            offset: 1,
+            conversion: Some((0.5, 1.0)),
+            dim: [3, 1],
+        };
+        let data = [0xAA, 0x01, 0x00, 0xFF, 0xFF, 0x04, 0x00];
+        assert_eq!(entry_values(&entry, &data, false), Some(vec![1.0, -1.0, 4.0]));
+        assert_eq!(entry_values(&entry, &data, true), Some(vec![1.5, 0.5, 3.0]));
+        assert_eq!(entry_values(&entry, &data[..6], false), None);
+    }
+}
@@ -0,0 +1,349 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module elf_debug_file
+// Locate the separate debug file of a stripped ELF file, by build-id (.note.gnu.build-id) or debuglink (.gnu_debuglink), the same way as gdb does
+// Search order: <debug dir>/.build-id/xx/yyyy.debug, then <dir>/<debuglink>, <dir>/.debug/<debuglink> and <debug dir>/<dir>/<debuglink>
+// The client has no HTTP client, debuginfod servers are not queried, debuginfod_urls() returns the URLs to download the debug file from
+// The ELF/DWARF reader for A2L creation belongs to the standalone tool in xcplib/tools/xcpclient
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use xcp_registry::crc32;
+
+/// Default global debug directory
+pub const ELF_DEBUG_DIR: &str = "/usr/lib/debug";
+
+const SHT_NOBITS: u32 = 8; // Section without data in the file, e.g. the debug sections of a stripped file
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// References of an ELF file to its separate debug file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ElfDebugLink {
+    pub build_id: Option<Vec<u8>>,        // Content of the GNU build-id note
+    pub debuglink: Option<(String, u32)>, // File name and CRC32 of the debug file from .gnu_debuglink
+    pub has_debug_info: bool,             // The file itself contains .debug_info (or compressed .zdebug_info) with data
+}
+
+impl ElfDebugLink {
+    /// Build-id as lower case hex string, as used in build-id directories and debuginfod URLs
+    pub fn build_id_hex(&self) -> Option<String> {
+        self.build_id.as_ref().map(|id| id.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+// Byte order and word size aware reader of the ELF content
+pub(super) struct ElfReader<'a> {
+    pub(super) data: &'a [u8],
+    pub(super) is_64: bool,
+    pub(super) big_endian: bool,
+}
+
+impl<'a> ElfReader<'a> {
+    pub(super) fn bytes(&self, offset: u64, len: u64) -> Result<&'a [u8], String> {
+        usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(len).ok())
+            .and_then(|(offset, len)| self.data.get(offset..offset.checked_add(len)?))
+            .ok_or_else(|| format!("ELF file truncated, {} bytes at offset {} expected", len, offset))
+    }
+
+    pub(super) fn u16(&self, offset: u64) -> Result<u16, String> {
+        let b = self.bytes(offset, 2)?;
+        let b = [b[0], b[1]];
+        Ok(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+
+    pub(super) fn u32(&self, offset: u64) -> Result<u32, String> {
+        let b = self.bytes(offset, 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    // Address or offset, 4 or 8 bytes
+    pub(super) fn word(&self, offset: u64) -> Result<u64, String> {
+        if self.is_64 {
+            let b = self.bytes(offset, 8)?;
+            let b = [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]];
+            Ok(if self.big_endian { u64::from_be_bytes(b) } else { u64::from_le_bytes(b) })
+        } else {
+            self.u32(offset).map(u64::from)
+        }
+    }
+}
+
+// Section name, type, linked section and content
+pub(super) struct ElfSection<'a> {
+    pub(super) name: &'a [u8],
+    pub(super) sh_type: u32,
+    pub(super) sh_link: u32,
+    pub(super) data: &'a [u8],
+}
+
+// All sections of an ELF file
+pub(super) fn read_sections(data: &[u8]) -> Result<(ElfReader<'_>, Vec<ElfSection<'_>>), String> {
+    if !data.starts_with(b"\x7FELF") || data.len() < 0x34 {
+        return Err("not an ELF file".to_string());
+    }
+    let reader = ElfReader {
+        data,
+        is_64: data[4] == 2,
+        big_endian: data[5] == 2,
+    };
+    let (shoff, shentsize, shnum, shstrndx) = if reader.is_64 {
+        (reader.word(0x28)?, reader.u16(0x3A)?, reader.u16(0x3C)?, reader.u16(0x3E)?)
+    } else {
+        (reader.word(0x20)?, reader.u16(0x2E)?, reader.u16(0x30)?, reader.u16(0x32)?)
+    };
+
+    // Section headers: name, type, offset, size, link
+    let mut headers = Vec::with_capacity(usize::from(shnum));
+    for i in 0..u64::from(shnum) {
+        let sh = shoff + i * u64::from(shentsize);
+        let (offset, size, link) = if reader.is_64 {
+            (reader.word(sh + 0x18)?, reader.word(sh + 0x20)?, reader.u32(sh + 0x28)?)
+        } else {
+            (reader.word(sh + 0x10)?, reader.word(sh + 0x14)?, reader.u32(sh + 0x18)?)
+        };
+        headers.push((reader.u32(sh)?, reader.u32(sh + 4)?, offset, size, link));
+    }
+    let strtab = match headers.get(usize::from(shstrndx)) {
+        Some((_, _, offset, size, _)) => reader.bytes(*offset, *size)?,
+        None => return Err("ELF file has no section name table".to_string()),
+    };
+
+    let mut sections = Vec::with_capacity(headers.len());
+    for (name, sh_type, offset, size, sh_link) in headers {
+        let name = strtab.get(name as usize..).unwrap_or_default();
+        let name = &name[..name.iter().position(|c| *c == 0).unwrap_or(name.len())];
+        let data = if sh_type == SHT_NOBITS { &[][..] } else { reader.bytes(offset, size)? };
+        sections.push(ElfSection { name, sh_type, sh_link, data });
+    }
+    Ok((reader, sections))
+}
+
+/// Read the build-id and debuglink of an ELF file
+pub fn get_elf_debug_link(data: &[u8]) -> Result<ElfDebugLink, String> {
+    let (reader, sections) = read_sections(data)?;
+    let mut link = ElfDebugLink::default();
+    for section in &sections {
+        match section.name {
+            b".debug_info" | b".zdebug_info" => link.has_debug_info |= section.sh_type != SHT_NOBITS && !section.data.is_empty(),
+            b".note.gnu.build-id" => {
+                // Note: namesz, descsz, type, name "GNU\0", desc, name and desc padded to 4 bytes
+                let note = ElfReader { data: section.data, ..reader };
+                let (namesz, descsz, note_type) = (note.u32(0)?, note.u32(4)?, note.u32(8)?);
+                if note_type == NT_GNU_BUILD_ID && note.bytes(12, u64::from(namesz))? == b"GNU\0" {
+                    link.build_id = Some(note.bytes(12 + u64::from(namesz).next_multiple_of(4), u64::from(descsz))?.to_vec());
+                }
+            }
+            b".gnu_debuglink" => {
+                // File name, 0 terminated and padded to 4 bytes, CRC32 of the debug file
+                let len = section.data.iter().position(|c| *c == 0).ok_or("invalid .gnu_debuglink section")?;
+                let name = String::from_utf8(section.data[..len].to_vec()).map_err(|_| "invalid .gnu_debuglink file name")?;
+                let crc = ElfReader { data: section.data, ..reader }.u32((len as u64 + 1).next_multiple_of(4))?;
+                link.debuglink = Some((name, crc));
+            }
+            _ => {}
+        }
+    }
+    Ok(link)
+}
+
+/// Find the separate debug file of an ELF file in its directory and in the global debug directories (e.g. ELF_DEBUG_DIR)
+/// A debug file found by debuglink must match the CRC32 of the debuglink
+/// Returns the path of the ELF file itself, if it contains debug information, None if no debug file was found
+pub fn find_elf_debug_file<P: AsRef<Path>>(elf_path: &P, debug_dirs: &[PathBuf]) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let elf_path = elf_path.as_ref();
+    let link = get_elf_debug_link(&std::fs::read(elf_path)?).map_err(|e| format!("{}: {}", elf_path.display(), e))?;
+    if link.has_debug_info {
+        return Ok(Some(elf_path.to_path_buf()));
+    }
+
+    // Build-id directories
+    if let Some(id) = link.build_id_hex().filter(|id| id.len() > 2) {
+        for dir in debug_dirs {
+            let path = dir.join(".build-id").join(&id[..2]).join(format!("{}.debug", &id[2..]));
+            if path.is_file() {
+                info!("Debug file of {} found by build-id: {}", elf_path.display(), path.display());
+                return Ok(Some(path));
+            }
+        }
+    }
+
+    // Debuglink
+    if let Some((name, crc)) = &link.debuglink {
+        let dir = elf_path.parent().unwrap_or(Path::new("."));
+        let abs_dir = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let mut candidates = vec![dir.join(name), dir.join(".debug").join(name)];
+        candidates.extend(debug_dirs.iter().map(|d| d.join(abs_dir.strip_prefix("/").unwrap_or(&abs_dir)).join(name)));
+        for path in candidates {
+            if path == elf_path || !path.is_file() {
+                continue;
+            }
+            if crc32(&std::fs::read(&path)?) != *crc {
+                warn!("Debug file {} does not match the debuglink CRC32 of {}, ignored", path.display(), elf_path.display());
+                continue;
+            }
+            info!("Debug file of {} found by debuglink: {}", elf_path.display(), path.display());
+            return Ok(Some(path));
+        }
+    }
+
+    warn!(
+        "No debug file found for {}, build-id {:?}, debuglink {:?}",
+        elf_path.display(),
+        link.build_id_hex(),
+        link.debuglink
+    );
+    Ok(None)
+}
+
+/// URLs to download the debug file of a build-id from debuginfod servers, from the base URLs in DEBUGINFOD_URLS (space separated)
+pub fn debuginfod_urls(link: &ElfDebugLink) -> Vec<String> {
+    let (Some(id), Ok(urls)) = (link.build_id_hex(), std::env::var("DEBUGINFOD_URLS")) else {
+        return Vec::new();
+    };
+    urls.split_whitespace()
+        .map(|base| format!("{}/buildid/{}/debuginfo", base.trim_end_matches('/'), id))
+        .collect()
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+pub(super) mod elf_debug_file_tests {
+
+    use super::*;
+
+    /// Section of a test ELF file: name, type, link and content
+    pub(in super::super) struct TestSection<'a>(pub &'a str, pub u32, pub u32, pub Vec<u8>);
+
+    /// Build an ELF file with the given sections, after the null section and before the section name table
+    pub(in super::super) fn build_elf(is_64: bool, big_endian: bool, sections: &[TestSection]) -> Vec<u8> {
+        let u16_bytes = |v: u16| if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+        let word_bytes = |v: u64| {
+            if is_64 {
+                if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() }
+            } else {
+                u32_bytes(u32::try_from(v).unwrap())
+            }
+        };
+
+        // Section name table
+        let mut shstrtab = vec![0u8];
+        let mut names = Vec::new();
+        for name in sections.iter().map(|s| s.0).chain([".shstrtab"]) {
+            names.push(u32::try_from(shstrtab.len()).unwrap());
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+        }
+
+        // Header, section contents, section headers
+        let header_size = if is_64 { 0x40 } else { 0x34 };
+        let mut data = vec![0u8; header_size];
+        data[..4].copy_from_slice(b"\x7FELF");
+        data[4] = if is_64 { 2 } else { 1 };
+        data[5] = if big_endian { 2 } else { 1 };
+        let mut headers = vec![(0, 0, 0, 0, 0)]; // Null section
+        for (i, (content, sh_type, link)) in sections.iter().map(|s| (&s.3, s.1, s.2)).chain([(&shstrtab, 3, 0)]).enumerate() {
+            headers.push((names[i], sh_type, data.len() as u64, content.len() as u64, link));
+            data.extend_from_slice(content);
+        }
+        let shoff = data.len() as u64;
+        let shentsize: u16 = if is_64 { 0x40 } else { 0x28 };
+        for (name, sh_type, offset, size, link) in &headers {
+            let mut sh = [u32_bytes(*name), u32_bytes(*sh_type)].concat();
+            sh.resize(if is_64 { 0x18 } else { 0x10 }, 0);
+            sh.extend([word_bytes(*offset), word_bytes(*size), u32_bytes(*link)].concat());
+            sh.resize(usize::from(shentsize), 0);
+            data.extend(sh);
+        }
+        let shnum = u16::try_from(headers.len()).unwrap();
+        let fields = if is_64 { [0x28, 0x3A, 0x3C, 0x3E] } else { [0x20, 0x2E, 0x30, 0x32] };
+        let shoff = word_bytes(shoff);
+        data[fields[0]..fields[0] + shoff.len()].copy_from_slice(&shoff);
+        for (field, value) in fields[1..].iter().zip([shentsize, shnum, shnum - 1]) {
+            data[*field..*field + 2].copy_from_slice(&u16_bytes(value));
+        }
+        data
+    }
+
+    // GNU build-id note
+    fn build_id_note(big_endian: bool, id: &[u8]) -> Vec<u8> {
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        [&u32_bytes(4)[..], &u32_bytes(u32::try_from(id.len()).unwrap()), &u32_bytes(NT_GNU_BUILD_ID), b"GNU\0", id].concat()
+    }
+
+    // .gnu_debuglink content
+    fn debuglink(big_endian: bool, name: &str, crc: u32) -> Vec<u8> {
+        let mut data = name.as_bytes().to_vec();
+        data.resize((name.len() + 1).next_multiple_of(4), 0);
+        data.extend(if big_endian { crc.to_be_bytes() } else { crc.to_le_bytes() });
+        data
+    }
+
+    // Stripped ELF file with build-id and debuglink
+    fn stripped_elf(is_64: bool, big_endian: bool, id: &[u8], link: &str, crc: u32) -> Vec<u8> {
+        build_elf(
+            is_64,
+            big_endian,
+            &[
+                TestSection(".note.gnu.build-id", 7, 0, build_id_note(big_endian, id)),
+                TestSection(".gnu_debuglink", 1, 0, debuglink(big_endian, link, crc)),
+                TestSection(".debug_info", SHT_NOBITS, 0, Vec::new()),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_elf_debug_link() {
+        for (is_64, big_endian) in [(true, false), (false, true)] {
+            let link = get_elf_debug_link(&stripped_elf(is_64, big_endian, &[0xAB, 0xCD, 0xEF], "app.debug", 0x1234_5678)).unwrap();
+            assert_eq!(link.build_id_hex().as_deref(), Some("abcdef"));
+            assert_eq!(link.debuglink, Some(("app.debug".to_string(), 0x1234_5678)));
+            assert!(!link.has_debug_info);
+        }
+
+        let elf = build_elf(true, false, &[TestSection(".debug_info", 1, 0, vec![0; 4])]);
+        assert_eq!(
+            get_elf_debug_link(&elf).unwrap(),
+            ElfDebugLink {
+                has_debug_info: true,
+                ..Default::default()
+            }
+        );
+
+        assert!(get_elf_debug_link(b"MZ").is_err());
+        assert!(get_elf_debug_link(&elf[..elf.len() - 0x30]).unwrap_err().contains("truncated"));
+    }
+
+    #[test]
+    fn test_find_elf_debug_file() {
+        let dir = std::env::temp_dir().join(format!("xcp_client_elf_debug_{}", std::process::id()));
+        let debug_dirs = [dir.join("debug")];
+        let debug_dir = &debug_dirs[0];
+        std::fs::create_dir_all(debug_dir.join(".build-id/ab")).unwrap();
+        let debug_data = build_elf(true, false, &[TestSection(".debug_info", 1, 0, vec![0; 4])]);
+        let elf_path = dir.join("app");
+
+        // By debuglink in the directory of the ELF file, the CRC32 must match
+        std::fs::write(&elf_path, stripped_elf(true, false, &[0xAB, 0xCD], "app.debug", crc32(&debug_data) ^ 1)).unwrap();
+        std::fs::write(dir.join("app.debug"), &debug_data).unwrap();
+        assert_eq!(find_elf_debug_file(&elf_path, &debug_dirs).unwrap(), None);
+        std::fs::write(&elf_path, stripped_elf(true, false, &[0xAB, 0xCD], "app.debug", crc32(&debug_data))).unwrap();
+        assert_eq!(find_elf_debug_file(&elf_path, &debug_dirs).unwrap(), Some(dir.join("app.debug")));
+
+        // Build-id first
+        std::fs::write(debug_dir.join(".build-id/ab/cd.debug"), &debug_data).unwrap();
+        assert_eq!(find_elf_debug_file(&elf_path, &debug_dirs).unwrap(), Some(debug_dir.join(".build-id/ab/cd.debug")));
+
+        // A file with debug information is its own debug file
+        assert_eq!(find_elf_debug_file(&dir.join("app.debug"), &debug_dirs).unwrap(), Some(dir.join("app.debug")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
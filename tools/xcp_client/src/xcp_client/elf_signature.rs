@@ -0,0 +1,78 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module elf_signature
+// Check that an ELF file matches the target software running on the connected XCP server
+// ELF files of XCPlite targets contain an XCPLITE__<sig> marker (the A2L project number, e.g. XCPLITE__CASDD) and the EPK string
+// The EPK of the running target is read with GET_ID IDT_ASAM_EPK, an ELF file of the same build contains it
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+
+use super::XcpClient;
+use super::xcp::*;
+
+/// Prefix of the target signature marker in ELF files of XCPlite targets
+pub const TARGET_SIGNATURE_PREFIX: &str = "XCPLITE__";
+
+// Position of the first occurrence of needle in haystack
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Get the target signature from the first XCPLITE__<sig> marker in the content of an ELF file, e.g. "CASDD"
+/// Returns None if there is no marker
+pub fn get_target_signature(elf: &[u8]) -> Option<String> {
+    let start = find_bytes(elf, TARGET_SIGNATURE_PREFIX.as_bytes())? + TARGET_SIGNATURE_PREFIX.len();
+    let len = elf[start..].iter().take_while(|c| c.is_ascii_uppercase() || c.is_ascii_digit()).count();
+    if len == 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&elf[start..start + len]).into_owned())
+}
+
+impl XcpClient {
+    /// Verify that an ELF file matches the target software running on the connected XCP server
+    /// The ELF file must contain an XCPLITE__<sig> marker and the EPK of the target (GET_ID IDT_ASAM_EPK)
+    /// On mismatch, ERROR_SIGNATURE_MISMATCH is returned, with force the mismatch is only logged
+    /// Returns the target signature of the ELF file
+    pub async fn verify_elf_signature<P: AsRef<std::path::Path>>(&mut self, elf_path: &P, force: bool) -> Result<Option<String>, Box<dyn Error>> {
+        let elf = std::fs::read(elf_path)?;
+        let signature = get_target_signature(&elf);
+
+        let mismatch = if !elf.starts_with(b"\x7FELF") {
+            Some("not an ELF file".to_string())
+        } else if signature.is_none() {
+            Some(format!("no {} marker found", TARGET_SIGNATURE_PREFIX))
+        } else {
+            match self.get_id(IDT_ASAM_EPK).await?.1.as_deref().map(|epk| epk.trim_end_matches('\0')) {
+                Some(epk) if !epk.is_empty() => {
+                    if find_bytes(&elf, epk.as_bytes()).is_none() {
+                        Some(format!("EPK '{}' of the target not found", epk))
+                    } else {
+                        None
+                    }
+                }
+                _ => Some("target has no EPK".to_string()),
+            }
+        };
+
+        match mismatch {
+            None => {
+                info!("ELF file {} matches the target, signature {:?}", elf_path.as_ref().display(), signature);
+                Ok(signature)
+            }
+            Some(reason) if force => {
+                warn!("ELF file {} does not match the target: {}, ignored", elf_path.as_ref().display(), reason);
+                Ok(signature)
+            }
+            Some(reason) => {
+                error!("ELF file {} does not match the target: {}", elf_path.as_ref().display(), reason);
+                Err(Box::new(XcpError::new(ERROR_SIGNATURE_MISMATCH, CC_GET_ID)) as Box<dyn Error>)
+            }
+        }
+    }
+}
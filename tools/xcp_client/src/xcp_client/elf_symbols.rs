@@ -0,0 +1,178 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module elf_symbols
+// Symbol table of an ELF file and the address update of an A2L file from a new build of the application, like the update command of a2ltool
+// Only objects with a SYMBOL_LINK are updated, manual edits of the A2L file are preserved, see xcp_registry::update_a2l_addresses_str
+// The --update-addresses option of the command line client belongs to the standalone tool in xcplib/tools/xcpclient
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use super::elf_debug_file::{ELF_DEBUG_DIR, ElfReader, debuginfod_urls, find_elf_debug_file, get_elf_debug_link, read_sections};
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+const SHN_UNDEF: u16 = 0;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+
+/// Defined symbols (name -> address) of the symbol tables .symtab and .dynsym of an ELF file
+/// Section and file symbols are ignored, if a name is defined more than once, .symtab and the first definition win
+pub fn get_elf_symbols(data: &[u8]) -> Result<HashMap<String, u64>, String> {
+    let (reader, sections) = read_sections(data)?;
+    let mut symbols = HashMap::new();
+    for sh_type in [SHT_SYMTAB, SHT_DYNSYM] {
+        for section in sections.iter().filter(|s| s.sh_type == sh_type) {
+            let strtab = sections.get(section.sh_link as usize).ok_or("invalid symbol table string table link")?.data;
+            let table = ElfReader { data: section.data, ..reader };
+            let entsize = if reader.is_64 { 24 } else { 16 };
+            for offset in (0..section.data.len() as u64 / entsize).map(|i| i * entsize) {
+                // Elf64_Sym: name, info, other, shndx, value, size - Elf32_Sym: name, value, size, info, other, shndx
+                let (name, value, info, shndx) = if reader.is_64 {
+                    (table.u32(offset)?, table.word(offset + 8)?, table.bytes(offset + 4, 1)?[0], table.u16(offset + 6)?)
+                } else {
+                    (table.u32(offset)?, table.word(offset + 4)?, table.bytes(offset + 12, 1)?[0], table.u16(offset + 14)?)
+                };
+                if name == 0 || shndx == SHN_UNDEF || matches!(info & 0x0F, STT_SECTION | STT_FILE) {
+                    continue;
+                }
+                let name = strtab.get(name as usize..).unwrap_or_default();
+                let name = &name[..name.iter().position(|c| *c == 0).unwrap_or(name.len())];
+                if let Ok(name) = std::str::from_utf8(name) {
+                    symbols.entry(name.to_string()).or_insert(value);
+                }
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+/// Update the addresses of the objects with a SYMBOL_LINK in the A2L file a2l_path from the symbols of the ELF file elf_path
+/// The symbols of a stripped ELF file are read from its separate debug file, searched in ELF_DEBUG_DIR, see find_elf_debug_file
+/// The result is written to out_path, which may be a2l_path, everything except the addresses is kept verbatim
+/// Returns the report of changed addresses and symbols not found
+pub fn update_a2l_addresses_from_elf<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(a2l_path: &P, elf_path: &Q, out_path: &R) -> Result<Vec<String>, Box<dyn Error>> {
+    let elf_path = elf_path.as_ref();
+    let data = std::fs::read(elf_path)?;
+    let mut symbols = get_elf_symbols(&data).map_err(|e| format!("{}: {}", elf_path.display(), e))?;
+
+    // The symbol table of a stripped file is in the debug file, its symbols win over the remaining .dynsym symbols
+    if let Some(debug_path) = find_elf_debug_file(&elf_path, &[PathBuf::from(ELF_DEBUG_DIR)])?
+        && debug_path != elf_path
+    {
+        let debug_symbols = get_elf_symbols(&std::fs::read(&debug_path)?).map_err(|e| format!("{}: {}", debug_path.display(), e))?;
+        symbols.extend(debug_symbols);
+    }
+    if symbols.is_empty() {
+        let urls = debuginfod_urls(&get_elf_debug_link(&data)?);
+        if urls.is_empty() {
+            return Err(format!("{}: no symbol table and no debug file found, stripped file?", elf_path.display()).into());
+        }
+        return Err(format!(
+            "{}: no symbol table and no debug file found, download the debug file from {}",
+            elf_path.display(),
+            urls.join(" or ")
+        )
+        .into());
+    }
+    let a2l = std::fs::read_to_string(a2l_path)?;
+    let (updated, report) = xcp_registry::update_a2l_addresses_str(&a2l, &symbols).map_err(|e| format!("{}: {}", a2l_path.as_ref().display(), e))?;
+    for line in &report {
+        info!("{}", line);
+    }
+    std::fs::write(out_path, updated)?;
+    Ok(report)
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod elf_symbols_tests {
+
+    use super::super::elf_debug_file::elf_debug_file_tests::{TestSection, build_elf};
+    use super::*;
+
+    const STT_OBJECT: u8 = 1;
+
+    // Symbol table (name, value, info, shndx) and its string table
+    fn symbol_table(is_64: bool, big_endian: bool, symbols: &[(&str, u64, u8, u16)]) -> (Vec<u8>, Vec<u8>) {
+        let u16_bytes = |v: u16| if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+        let u64_bytes = |v: u64| if big_endian { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+        let mut table = vec![0u8; if is_64 { 24 } else { 16 }]; // Null symbol
+        let mut strtab = vec![0u8];
+        for (name, value, info, shndx) in symbols {
+            let name_offset = u32_bytes(u32::try_from(strtab.len()).unwrap());
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            if is_64 {
+                table.extend([name_offset, vec![*info, 0], u16_bytes(*shndx), u64_bytes(*value), u64_bytes(0)].concat());
+            } else {
+                let value = u32_bytes(u32::try_from(*value).unwrap());
+                table.extend([name_offset, value, u32_bytes(0), vec![*info, 0], u16_bytes(*shndx)].concat());
+            }
+        }
+        (table, strtab)
+    }
+
+    // ELF file with .symtab (section 1, string table 2) and .dynsym (section 3, string table 4)
+    fn elf_with_symbols(is_64: bool, big_endian: bool, symtab: &[(&str, u64, u8, u16)], dynsym: &[(&str, u64, u8, u16)]) -> Vec<u8> {
+        let (symtab, strtab) = symbol_table(is_64, big_endian, symtab);
+        let (dynsym, dynstr) = symbol_table(is_64, big_endian, dynsym);
+        build_elf(
+            is_64,
+            big_endian,
+            &[
+                TestSection(".symtab", SHT_SYMTAB, 2, symtab),
+                TestSection(".strtab", 3, 0, strtab),
+                TestSection(".dynsym", SHT_DYNSYM, 4, dynsym),
+                TestSection(".dynstr", 3, 0, dynstr),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_get_elf_symbols() {
+        for (is_64, big_endian) in [(true, false), (false, true)] {
+            let elf = elf_with_symbols(
+                is_64,
+                big_endian,
+                &[
+                    ("counter", 0x1000, STT_OBJECT, 1),
+                    ("main.c", 0, STT_FILE, 0xFFF1),
+                    (".data", 0x1000, STT_SECTION, 1),
+                    ("extern_var", 0, STT_OBJECT, SHN_UNDEF),
+                    ("counter", 0x9000, STT_OBJECT, 1),
+                ],
+                &[("counter", 0x2000, STT_OBJECT, 1), ("params", 0x3000, STT_OBJECT, 1)],
+            );
+            let symbols = get_elf_symbols(&elf).unwrap();
+            assert_eq!(symbols, HashMap::from([("counter".to_string(), 0x1000), ("params".to_string(), 0x3000)]));
+        }
+    }
+
+    #[test]
+    fn test_update_a2l_addresses_from_elf() {
+        let dir = std::env::temp_dir().join(format!("xcp_client_elf_symbols_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (elf_path, a2l_path, out_path) = (dir.join("app"), dir.join("app.a2l"), dir.join("app_new.a2l"));
+        let a2l = "/begin MEASUREMENT counter \"\" UWORD NO_COMPU_METHOD 0 0 0 65535 ECU_ADDRESS 0x1000 SYMBOL_LINK \"counter\" 0 /end MEASUREMENT\n";
+        std::fs::write(&a2l_path, a2l).unwrap();
+
+        std::fs::write(&elf_path, elf_with_symbols(true, false, &[("counter", 0x1100, STT_OBJECT, 1)], &[])).unwrap();
+        let report = update_a2l_addresses_from_elf(&a2l_path, &elf_path, &out_path).unwrap();
+        assert_eq!(report.len(), 1);
+        assert!(std::fs::read_to_string(&out_path).unwrap().contains("ECU_ADDRESS 0x1100 SYMBOL_LINK"));
+        assert_eq!(std::fs::read_to_string(&a2l_path).unwrap(), a2l);
+
+        // Stripped file without debug file
+        std::fs::write(&elf_path, build_elf(true, false, &[])).unwrap();
+        let e = update_a2l_addresses_from_elf(&a2l_path, &elf_path, &out_path).unwrap_err();
+        assert!(e.to_string().contains("no symbol table"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module epk_check
+// Check that the A2L matches the target software running on the connected XCP server
+// The EPK of the running target is read with GET_ID IDT_ASAM_EPK and compared to the EPK in the A2L MOD_PAR (EPK, ADDR_EPK)
+// A mismatch is returned as XcpEpkMismatch error, which is mapped to XcpExitCode::EpkMismatch
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+
+use super::XcpClient;
+use super::xcp::*;
+
+/// EPK of the target does not match the EPK of the A2L, the A2L belongs to another build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XcpEpkMismatch {
+    /// EPK of the target, None if the target has no EPK
+    pub target: Option<String>,
+    /// EPK of the A2L MOD_PAR
+    pub a2l: String,
+    /// EPK address of the A2L MOD_PAR (ADDR_EPK)
+    pub a2l_addr: u32,
+}
+
+impl std::fmt::Display for XcpEpkMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.target {
+            Some(target) => write!(f, "target EPK '{}' does not match A2L EPK '{}' at 0x{:08X}", target, self.a2l, self.a2l_addr),
+            None => write!(f, "target has no EPK, A2L EPK is '{}' at 0x{:08X}", self.a2l, self.a2l_addr),
+        }
+    }
+}
+
+impl Error for XcpEpkMismatch {}
+
+// Compare the EPK of the target with the EPK of the A2L, trailing zero bytes of the target EPK are ignored
+fn check_epk(target: Option<&str>, a2l: &str, a2l_addr: u32) -> Result<(), XcpEpkMismatch> {
+    match target.map(|epk| epk.trim_end_matches('\0')) {
+        Some(epk) if epk == a2l => Ok(()),
+        target => Err(XcpEpkMismatch {
+            target: target.filter(|epk| !epk.is_empty()).map(str::to_string),
+            a2l: a2l.to_string(),
+            a2l_addr,
+        }),
+    }
+}
+
+impl XcpClient {
+    /// Verify that the EPK of the target (GET_ID IDT_ASAM_EPK) matches the EPK of the A2L MOD_PAR in the registry
+    /// An A2L without EPK can not be checked, this is only logged
+    /// On mismatch, a XcpEpkMismatch error is returned
+    pub async fn verify_epk(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(registry) = self.registry.as_ref() else {
+            error!("EPK check: no A2L loaded");
+            return Err(Box::new(XcpError::new(ERROR_A2L, 0)) as Box<dyn Error>);
+        };
+        if !registry.application.has_epk() {
+            warn!("EPK check: A2L has no EPK, not checked");
+            return Ok(());
+        }
+        let a2l = registry.application.get_version().to_string();
+        let a2l_addr = registry.application.version.epk_addr;
+
+        let (_, target) = self.get_id(IDT_ASAM_EPK).await?;
+        match check_epk(target.as_deref(), &a2l, a2l_addr) {
+            Ok(()) => {
+                info!("EPK check: target matches A2L EPK '{}'", a2l);
+                Ok(())
+            }
+            Err(e) => {
+                error!("EPK check: {}", e);
+                Err(Box::new(e) as Box<dyn Error>)
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module exit_code
+// Process exit codes per failure category and machine readable run results for shell based CI jobs
+// Exit codes:
+//   0 success
+//   1 other failure
+//   2 connect failed (transport error or no response to CONNECT)
+//   3 EPK mismatch, the ELF file or A2L does not match the target software
+//   4 calibration verification failed, a read back value differs from the written value
+//   5 DAQ loss above threshold
+// In quiet mode, the tool disables logging and prints only the results, one key=value line each, the last line is exit_code=N
+// The --quiet option of the command line tool creates XcpRunSummary::new(quiet), main returns finish()
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+use std::fmt::Write;
+
+use super::XcpCalBatchReport;
+use super::XcpEpkMismatch;
+use super::xcp::*;
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpExitCode
+
+/// Process exit code of a client run, one per failure category
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum XcpExitCode {
+    Success = 0,
+    Failure = 1,
+    ConnectFailed = 2,
+    EpkMismatch = 3,
+    CalVerifyFailed = 4,
+    DaqLoss = 5,
+}
+
+impl XcpExitCode {
+    /// Failure category of an error returned by the XCP client
+    pub fn from_error(e: &(dyn Error + 'static)) -> XcpExitCode {
+        if e.is::<std::io::Error>() {
+            return XcpExitCode::ConnectFailed;
+        }
+        if e.is::<XcpEpkMismatch>() {
+            return XcpExitCode::EpkMismatch;
+        }
+        match e.downcast_ref::<XcpError>() {
+            Some(e) if e.get_command() == CC_CONNECT => XcpExitCode::ConnectFailed,
+            Some(e) if e.get_error_code() == ERROR_SIGNATURE_MISMATCH => XcpExitCode::EpkMismatch,
+            Some(e) if e.get_error_code() == ERROR_DAQ_LOSS => XcpExitCode::DaqLoss,
+            _ => XcpExitCode::Failure,
+        }
+    }
+
+    pub fn get_code(self) -> u8 {
+        self as u8
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            XcpExitCode::Success => "success",
+            XcpExitCode::Failure => "failure",
+            XcpExitCode::ConnectFailed => "connect_failed",
+            XcpExitCode::EpkMismatch => "epk_mismatch",
+            XcpExitCode::CalVerifyFailed => "cal_verify_failed",
+            XcpExitCode::DaqLoss => "daq_loss",
+        }
+    }
+}
+
+impl From<XcpExitCode> for std::process::ExitCode {
+    fn from(code: XcpExitCode) -> Self {
+        std::process::ExitCode::from(code.get_code())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpRunSummary
+
+/// Results of a client run and the resulting exit code
+/// The first failure determines the exit code, later failures are recorded as results
+#[derive(Debug)]
+pub struct XcpRunSummary {
+    quiet: bool,
+    results: Vec<(String, String)>,
+    exit_code: XcpExitCode,
+}
+
+impl XcpRunSummary {
+    pub fn new(quiet: bool) -> XcpRunSummary {
+        XcpRunSummary {
+            quiet,
+            results: Vec::new(),
+            exit_code: XcpExitCode::Success,
+        }
+    }
+
+    /// Quiet mode, only the results are printed
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Add a result, key should not contain whitespace or '='
+    pub fn add<T: std::fmt::Display>(&mut self, key: &str, value: T) {
+        self.results.push((key.to_string(), value.to_string()));
+    }
+
+    /// Record a failure, the first failure determines the exit code
+    pub fn fail(&mut self, code: XcpExitCode, reason: &str) {
+        if !self.quiet {
+            error!("{}: {}", code.get_name(), reason);
+        }
+        self.add("failure", format!("{} {}", code.get_name(), reason));
+        if self.exit_code == XcpExitCode::Success {
+            self.exit_code = code;
+        }
+    }
+
+    /// Record a failure from an error returned by the XCP client
+    pub fn fail_with_error(&mut self, e: &(dyn Error + 'static)) {
+        self.fail(XcpExitCode::from_error(e), &e.to_string());
+    }
+
+    /// Record the verification report of a calibration batch, fails with CalVerifyFailed on mismatches
+    pub fn add_cal_batch_report(&mut self, report: &XcpCalBatchReport) {
+        self.add("cal_values", report.results.len());
+        self.add("cal_mismatches", report.get_mismatch_count());
+        for r in report.results.iter().filter(|r| !r.ok) {
+            self.fail(XcpExitCode::CalVerifyFailed, &r.name);
+        }
+    }
+
+    pub fn get_exit_code(&self) -> XcpExitCode {
+        self.exit_code
+    }
+
+    /// Machine readable results, one key=value line each, the last line is exit_code=N
+    pub fn format(&self) -> String {
+        let mut s = String::new();
+        for (key, value) in &self.results {
+            let _ = writeln!(s, "{}={}", key, value);
+        }
+        let _ = writeln!(s, "exit_code={}", self.exit_code.get_code());
+        s
+    }
+
+    /// Print the results to stdout and return the exit code for main
+    pub fn finish(&self) -> std::process::ExitCode {
+        print!("{}", self.format());
+        self.exit_code.into()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod exit_code_tests {
+
+    use super::*;
+    use crate::xcp_client::XcpCalBatchResult;
+
+    #[test]
+    fn test_from_error() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(XcpExitCode::from_error(&io_error), XcpExitCode::ConnectFailed);
+        let epk_mismatch = XcpEpkMismatch {
+            target: None,
+            a2l: "EPK".to_string(),
+            a2l_addr: 0x80000000,
+        };
+        assert_eq!(XcpExitCode::from_error(&epk_mismatch), XcpExitCode::EpkMismatch);
+        assert_eq!(XcpExitCode::from_error(&XcpError::new(ERROR_CMD_TIMEOUT, CC_CONNECT)), XcpExitCode::ConnectFailed);
+        assert_eq!(XcpExitCode::from_error(&XcpError::new(ERROR_SIGNATURE_MISMATCH, 0)), XcpExitCode::EpkMismatch);
+        assert_eq!(XcpExitCode::from_error(&XcpError::new(ERROR_DAQ_LOSS, 0)), XcpExitCode::DaqLoss);
+        assert_eq!(XcpExitCode::from_error(&XcpError::new(ERROR_CMD_TIMEOUT, CC_UPLOAD)), XcpExitCode::Failure);
+        let other: Box<dyn Error> = "other".into();
+        assert_eq!(XcpExitCode::from_error(other.as_ref()), XcpExitCode::Failure);
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let mut summary = XcpRunSummary::new(true);
+        assert!(summary.is_quiet());
+        summary.add("a2l", "test.a2l");
+        assert_eq!(summary.format(), "a2l=test.a2l\nexit_code=0\n");
+
+        // The first failure determines the exit code
+        summary.fail(XcpExitCode::DaqLoss, "loss");
+        summary.fail(XcpExitCode::Failure, "other");
+        assert_eq!(summary.get_exit_code(), XcpExitCode::DaqLoss);
+        assert_eq!(summary.format(), "a2l=test.a2l\nfailure=daq_loss loss\nfailure=failure other\nexit_code=5\n");
+    }
+
+    #[test]
+    fn test_cal_batch_report() {
+        let result = |name: &str, ok: bool| XcpCalBatchResult {
+            name: name.to_string(),
+            requested: vec![1.0],
+            read_back: vec![if ok { 1.0 } else { 0.0 }],
+            ok,
+        };
+
+        let mut summary = XcpRunSummary::new(true);
+        summary.add_cal_batch_report(&XcpCalBatchReport { results: vec![result("a", true)] });
+        assert_eq!(summary.get_exit_code(), XcpExitCode::Success);
+        assert_eq!(summary.format(), "cal_values=1\ncal_mismatches=0\nexit_code=0\n");
+
+        let mut summary = XcpRunSummary::new(true);
+        summary.add_cal_batch_report(&XcpCalBatchReport {
+            results: vec![result("a", true), result("b", false)],
+        });
+        assert_eq!(summary.get_exit_code(), XcpExitCode::CalVerifyFailed);
+        assert_eq!(summary.format(), "cal_values=2\ncal_mismatches=1\nfailure=cal_verify_failed b\nexit_code=4\n");
+    }
+}
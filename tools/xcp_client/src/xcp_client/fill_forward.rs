@@ -0,0 +1,46 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module fill_forward
+// Fill forward of value change (on change) events for recording
+// An event in on change mode sends a sample only when a value changed or after the max interval, the A2L file annotates such events with a
+// comment /* on change, max interval = N ns */, which the A2L reader ignores, so the resampling period is given explicitly
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::collections::BTreeMap;
+
+use super::XcpSample;
+
+/// Resample decoded samples to a fixed period, the last value of each signal is repeated until it changes
+/// Signals appear from their first sample on, the result is sorted by timestamp, target and name
+pub fn fill_forward(samples: &[XcpSample], period_ns: i64) -> Vec<XcpSample> {
+    let mut sorted: Vec<&XcpSample> = samples.iter().collect();
+    sorted.sort_by_key(|s| s.timestamp_ns);
+    let (Some(first), Some(last)) = (sorted.first(), sorted.last()) else {
+        return Vec::new();
+    };
+    let period_ns = period_ns.max(1);
+    let end_ns = last.timestamp_ns;
+
+    let mut result = Vec::new();
+    let mut values: BTreeMap<(usize, &str), f64> = BTreeMap::new();
+    let mut next = sorted.iter().peekable();
+    let mut t = first.timestamp_ns;
+    while t <= end_ns {
+        while let Some(s) = next.next_if(|s| s.timestamp_ns <= t) {
+            values.insert((s.target, s.name.as_str()), s.value);
+        }
+        result.extend(values.iter().map(|((target, name), value)| XcpSample {
+            timestamp_ns: t,
+            target: *target,
+            name: (*name).to_string(),
+            value: *value,
+        }));
+        t = t.saturating_add(period_ns);
+        if t == i64::MAX {
+            break;
+        }
+    }
+    debug!("fill_forward: {} samples -> {} samples with period {} ns", samples.len(), result.len(), period_ns);
+    result
+}
@@ -0,0 +1,390 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module hexfile
+// Intel HEX files and the memory image they describe, the single implementation of Intel HEX reading and writing in the client
+// A MemoryImage is a set of non overlapping contiguous memory segments, adjacent data is merged into one segment
+// The hex_reader and bin_reader of xcplib belong to the standalone tool in xcplib/tools/xcpclient
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::ops::Bound;
+use std::path::Path;
+
+use xcp_registry::crc32;
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// MemoryImage
+
+/// Memory content of an Intel HEX file, contiguous segments ordered by address
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MemoryImage {
+    segments: BTreeMap<u32, Vec<u8>>, // Start address -> data, never empty, never overlapping or adjacent
+}
+
+/// Options for writing Intel HEX
+/// Flash tools often expect one contiguous block with the gaps filled with the erased flash value and sector aligned start and end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntelHexOptions {
+    pub record_len: usize, // Maximum number of data bytes per record, 1..=255
+    pub fill: Option<u8>,  // Fill the gaps between the segments with this pattern, e.g. 0xFF (erased flash) or 0x00
+    pub align: u32,        // With fill, align the start down and the end up to a multiple of align bytes, 0 or 1 for no alignment
+}
+
+impl Default for IntelHexOptions {
+    fn default() -> Self {
+        IntelHexOptions {
+            record_len: 16,
+            fill: None,
+            align: 1,
+        }
+    }
+}
+
+// End address (exclusive) of data at addr, may be 1 << 32
+fn end_addr(addr: u32, len: usize) -> u64 {
+    u64::from(addr) + len as u64
+}
+
+impl MemoryImage {
+    pub fn new() -> MemoryImage {
+        MemoryImage::default()
+    }
+
+    /// Number of data bytes
+    pub fn len(&self) -> usize {
+        self.segments.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Contiguous segments (address, data) ordered by address
+    pub fn segments(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.segments.iter().map(|(addr, data)| (*addr, data.as_slice()))
+    }
+
+    /// Gaps (address, length) between the segments
+    pub fn gaps(&self) -> Vec<(u32, u32)> {
+        self.segments()
+            .zip(self.segments().skip(1))
+            .map(|((addr, data), (next, _))| {
+                let end = u32::try_from(end_addr(addr, data.len())).expect("end of a segment followed by another segment");
+                (end, next - end)
+            })
+            .collect()
+    }
+
+    /// Add data at addr, adjacent segments are merged, data overlapping existing data is an error
+    pub fn insert(&mut self, addr: u32, data: &[u8]) -> Result<(), String> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let end = end_addr(addr, data.len());
+        if end > 1 << 32 {
+            return Err(format!("data at 0x{:08X} with {} bytes exceeds the 32 bit address space", addr, data.len()));
+        }
+        let prev = self.segments.range(..=addr).next_back().map(|(a, d)| (*a, end_addr(*a, d.len())));
+        let next = self.segments.range((Bound::Excluded(addr), Bound::Unbounded)).next().map(|(a, _)| *a);
+        if prev.is_some_and(|(_, prev_end)| prev_end > u64::from(addr)) {
+            return Err(format!("address 0x{:08X} defined twice", addr));
+        }
+        if let Some(next) = next.filter(|next| u64::from(*next) < end) {
+            return Err(format!("address 0x{:08X} defined twice", next));
+        }
+
+        // Merge with the previous and the next segment if adjacent
+        let (start, mut merged) = match prev.filter(|(_, prev_end)| *prev_end == u64::from(addr)) {
+            Some((prev_addr, _)) => (prev_addr, self.segments.remove(&prev_addr).expect("previous segment")),
+            None => (addr, Vec::with_capacity(data.len())),
+        };
+        merged.extend_from_slice(data);
+        if let Some(next) = next.filter(|next| u64::from(*next) == end) {
+            merged.extend_from_slice(&self.segments.remove(&next).expect("next segment"));
+        }
+        self.segments.insert(start, merged);
+        Ok(())
+    }
+
+    /// Add all segments of another image, overlapping data is an error
+    pub fn merge(&mut self, other: &MemoryImage) -> Result<(), String> {
+        for (addr, data) in other.segments() {
+            self.insert(addr, data)?;
+        }
+        Ok(())
+    }
+
+    /// Data of the range addr..addr+len, None if the range is not completely defined
+    pub fn slice(&self, addr: u32, len: u32) -> Option<&[u8]> {
+        let (start, data) = self.segments.range(..=addr).next_back()?;
+        let offset = (addr - start) as usize;
+        data.get(offset..offset + len as usize)
+    }
+
+    /// Number of defined bytes in the range addr..addr+len
+    pub fn count_defined(&self, addr: u32, len: u32) -> u32 {
+        let end = end_addr(addr, len as usize);
+        let count: u64 = self
+            .segments()
+            .map(|(a, d)| (u64::from(a), end_addr(a, d.len())))
+            .filter(|(a, e)| *a < end && *e > u64::from(addr))
+            .map(|(a, e)| e.min(end) - a.max(u64::from(addr)))
+            .sum();
+        u32::try_from(count).expect("count within the range")
+    }
+
+    /// Copy with the gaps between the segments filled with pattern, one contiguous segment
+    /// The start is aligned down and the end aligned up to a multiple of align bytes, also filled with pattern
+    pub fn fill_gaps(&self, pattern: u8, align: u32) -> MemoryImage {
+        let (Some((first, _)), Some((last, data))) = (self.segments().next(), self.segments().last()) else {
+            return MemoryImage::new();
+        };
+        let align = u64::from(align.max(1));
+        let start = u64::from(first) / align * align;
+        let end = end_addr(last, data.len()).div_ceil(align).saturating_mul(align).min(1 << 32);
+        let mut block = vec![pattern; usize::try_from(end - start).expect("32 bit address space")];
+        for (addr, data) in self.segments() {
+            let offset = usize::try_from(u64::from(addr) - start).expect("32 bit address space");
+            block[offset..offset + data.len()].copy_from_slice(data);
+        }
+        let start = u32::try_from(start).expect("aligned down");
+        MemoryImage {
+            segments: BTreeMap::from([(start, block)]),
+        }
+    }
+
+    /// CRC32 (IEEE 802.3) of the range addr..addr+len, None if the range is not completely defined
+    pub fn crc32(&self, addr: u32, len: u32) -> Option<u32> {
+        self.slice(addr, len).map(crc32)
+    }
+
+    /// Check the CRC32 of the range addr..addr+len
+    pub fn check_crc32(&self, addr: u32, len: u32, expected: u32) -> Result<(), String> {
+        match self.crc32(addr, len) {
+            None => Err(format!("range 0x{:08X}..0x{:08X} is not completely defined", addr, end_addr(addr, len as usize))),
+            Some(crc) if crc != expected => Err(format!(
+                "CRC32 error in range 0x{:08X}..0x{:08X}, expected {:08X}, found {:08X}",
+                addr,
+                end_addr(addr, len as usize),
+                expected,
+                crc
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    //--------------------------------------------------------------------------------------------------------------------------------------------------
+    // Intel HEX
+
+    /// Parse Intel HEX, extended linear (04) and extended segment (02) addresses are supported
+    /// The checksum of each record is checked, data defined twice is an error
+    pub fn from_intel_hex(hex: &str) -> Result<MemoryImage, String> {
+        let mut image = MemoryImage::new();
+        let mut base: u64 = 0;
+        for (i, line) in hex.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let record = line
+                .strip_prefix(':')
+                .filter(|r| r.len() >= 10 && r.len().is_multiple_of(2) && r.is_ascii())
+                .and_then(|r| (0..r.len()).step_by(2).map(|j| u8::from_str_radix(&r[j..j + 2], 16).ok()).collect::<Option<Vec<u8>>>())
+                .ok_or_else(|| format!("line {}: invalid Intel HEX record", i + 1))?;
+            let len = record[0] as usize;
+            if record.len() != len + 5 {
+                return Err(format!("line {}: record length mismatch", i + 1));
+            }
+            if record.iter().fold(0u8, |c, b| c.wrapping_add(*b)) != 0 {
+                return Err(format!("line {}: checksum error", i + 1));
+            }
+            let addr = u64::from(u16::from_be_bytes([record[1], record[2]]));
+            let data = &record[4..4 + len];
+            match record[3] {
+                0x00 => {
+                    let addr = u32::try_from(base + addr).map_err(|_| format!("line {}: address exceeds the 32 bit address space", i + 1))?;
+                    image.insert(addr, data).map_err(|e| format!("line {}: {}", i + 1, e))?;
+                }
+                0x01 => break,
+                0x02 if len == 2 => base = u64::from(u16::from_be_bytes([data[0], data[1]])) << 4,
+                0x04 if len == 2 => base = u64::from(u16::from_be_bytes([data[0], data[1]])) << 16,
+                0x03 | 0x05 => {} // Start address
+                t => return Err(format!("line {}: unsupported record type {:02X}", i + 1, t)),
+            }
+        }
+        Ok(image)
+    }
+
+    /// Intel HEX with extended linear address (04) records, data records do not cross a 64K boundary
+    /// With options.fill, the gaps are filled, see fill_gaps
+    pub fn to_intel_hex(&self, options: &IntelHexOptions) -> Result<String, String> {
+        if !(1..=255).contains(&options.record_len) {
+            return Err(format!("invalid Intel HEX record length {}, 1..255 expected", options.record_len));
+        }
+        let filled;
+        let image = match options.fill {
+            Some(pattern) => {
+                filled = self.fill_gaps(pattern, options.align);
+                &filled
+            }
+            None => self,
+        };
+        let mut hex = String::new();
+        let mut upper: Option<u16> = None;
+        for (start, data) in image.segments() {
+            let mut offset = 0;
+            while offset < data.len() {
+                let addr = u32::try_from(end_addr(start, offset)).expect("segments do not exceed the address space");
+                let [a3, a2, a1, a0] = addr.to_be_bytes();
+                if upper != Some(u16::from_be_bytes([a3, a2])) {
+                    write_hex_record(&mut hex, 0, 0x04, &[a3, a2]);
+                    upper = Some(u16::from_be_bytes([a3, a2]));
+                }
+                let len = (data.len() - offset).min(options.record_len).min(0x10000 - usize::from(u16::from_be_bytes([a1, a0])));
+                write_hex_record(&mut hex, u16::from_be_bytes([a1, a0]), 0x00, &data[offset..offset + len]);
+                offset += len;
+            }
+        }
+        write_hex_record(&mut hex, 0, 0x01, &[]);
+        Ok(hex)
+    }
+
+    /// Read an Intel HEX file
+    pub fn read_intel_hex_file<P: AsRef<Path>>(path: &P) -> Result<MemoryImage, Box<dyn Error>> {
+        let hex = std::fs::read_to_string(path)?;
+        let image = MemoryImage::from_intel_hex(&hex).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        Ok(image)
+    }
+
+    /// Write an Intel HEX file
+    pub fn write_intel_hex_file<P: AsRef<Path>>(&self, path: &P, options: &IntelHexOptions) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.to_intel_hex(options)?)?;
+        Ok(())
+    }
+}
+
+fn write_hex_record(hex: &mut String, addr: u16, record_type: u8, data: &[u8]) {
+    let len = u8::try_from(data.len()).expect("Intel HEX record too long");
+    let [addr_hi, addr_lo] = addr.to_be_bytes();
+    let mut checksum = len.wrapping_add(addr_hi).wrapping_add(addr_lo).wrapping_add(record_type);
+    let _ = write!(hex, ":{:02X}{:04X}{:02X}", len, addr, record_type);
+    for b in data {
+        let _ = write!(hex, "{:02X}", b);
+        checksum = checksum.wrapping_add(*b);
+    }
+    let _ = writeln!(hex, "{:02X}", checksum.wrapping_neg());
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod hexfile_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_memory_image_insert() {
+        let mut image = MemoryImage::new();
+        image.insert(0x1010, &[3, 4]).unwrap();
+        image.insert(0x1000, &[1, 2]).unwrap();
+        image.insert(0x1002, &[0; 14]).unwrap(); // Merges both segments
+        assert_eq!(image.segments().count(), 1);
+        assert_eq!(image.len(), 18);
+        assert_eq!(image.slice(0x1010, 2), Some(&[3u8, 4][..]));
+        assert!(image.insert(0x1011, &[0]).is_err());
+        assert!(image.insert(0x0FFF, &[0, 0]).is_err());
+        assert!(image.insert(0xFFFF_FFFF, &[0, 0]).is_err());
+
+        let mut other = MemoryImage::new();
+        other.insert(0x2000, &[5]).unwrap();
+        image.merge(&other).unwrap();
+        assert_eq!(image.gaps(), vec![(0x1012, 0x2000 - 0x1012)]);
+        assert_eq!(image.slice(0x1010, 3), None);
+        assert_eq!(image.count_defined(0x1000, 0x1001), 19);
+        assert!(image.merge(&other).is_err());
+    }
+
+    #[test]
+    fn test_memory_image_crc32() {
+        let mut image = MemoryImage::new();
+        image.insert(0x100, b"123456789").unwrap();
+        assert_eq!(image.crc32(0x100, 9), Some(0xCBF4_3926));
+        assert!(image.check_crc32(0x100, 9, 0xCBF4_3926).is_ok());
+        assert!(image.check_crc32(0x100, 9, 0).is_err());
+        assert!(image.check_crc32(0x100, 10, 0xCBF4_3926).is_err());
+    }
+
+    #[test]
+    fn test_intel_hex_round_trip() {
+        let mut image = MemoryImage::new();
+        image.insert(0x8000_FFF0, &(0..32).collect::<Vec<u8>>()).unwrap(); // Crosses a 64K boundary
+        image.insert(0x8002_0000, &[0xAA; 5]).unwrap();
+        let options = IntelHexOptions {
+            record_len: 8,
+            ..Default::default()
+        };
+        let hex = image.to_intel_hex(&options).unwrap();
+        assert!(hex.starts_with(":020000048000"));
+        assert!(hex.contains(":020000048001"));
+        assert!(hex.ends_with(":00000001FF\n"));
+        assert!(hex.lines().all(|l| l.len() <= 11 + 2 * 8));
+        assert_eq!(MemoryImage::from_intel_hex(&hex).unwrap(), image);
+
+        assert!(
+            image
+                .to_intel_hex(&IntelHexOptions {
+                    record_len: 0,
+                    ..Default::default()
+                })
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_fill_gaps() {
+        let mut image = MemoryImage::new();
+        image.insert(0x1003, &[1, 2]).unwrap();
+        image.insert(0x1008, &[3]).unwrap();
+        let filled = image.fill_gaps(0xFF, 4);
+        assert_eq!(
+            filled.segments().collect::<Vec<_>>(),
+            vec![(0x1000, &[0xFF, 0xFF, 0xFF, 1, 2, 0xFF, 0xFF, 0xFF, 3, 0xFF, 0xFF, 0xFF][..])]
+        );
+        assert!(filled.gaps().is_empty());
+        assert_eq!(image.fill_gaps(0x00, 0).segments().next(), Some((0x1003, &[1, 2, 0, 0, 0, 3][..])));
+        assert!(MemoryImage::new().fill_gaps(0xFF, 4).is_empty());
+
+        // The end is aligned up without exceeding the address space
+        let mut top = MemoryImage::new();
+        top.insert(0xFFFF_FFFE, &[1]).unwrap();
+        assert_eq!(top.fill_gaps(0xFF, 0x100).slice(0xFFFF_FF00, 0x100).unwrap()[0xFE], 1);
+
+        // Intel HEX with fill is one contiguous block
+        let options = IntelHexOptions {
+            record_len: 32,
+            fill: Some(0xFF),
+            align: 4,
+        };
+        let hex = image.to_intel_hex(&options).unwrap();
+        assert_eq!(MemoryImage::from_intel_hex(&hex).unwrap(), filled);
+    }
+
+    #[test]
+    fn test_intel_hex_records() {
+        // Extended segment address 0x1000 << 4, start address and end of file
+        let hex = ":020000021000EC\n:03000000010203F7\n:0400000500001000E7\n:00000001FF\n:0100000099";
+        let image = MemoryImage::from_intel_hex(hex).unwrap();
+        assert_eq!(image.slice(0x10000, 3), Some(&[1u8, 2, 3][..]));
+        assert_eq!(image.len(), 3);
+
+        assert!(MemoryImage::from_intel_hex(":0300000001020300").unwrap_err().contains("checksum"));
+        assert!(MemoryImage::from_intel_hex(":0500000001020300F5").unwrap_err().contains("length"));
+        assert!(MemoryImage::from_intel_hex(":00000006FA").unwrap_err().contains("record type"));
+        assert!(MemoryImage::from_intel_hex("0300000001020300").is_err());
+        assert!(MemoryImage::from_intel_hex(":0100000001FE\n:0100000002FD").unwrap_err().contains("defined twice"));
+    }
+}
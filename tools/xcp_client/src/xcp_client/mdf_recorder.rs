@@ -0,0 +1,585 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module mdf_recorder
+// Recording of DAQ data into ASAM MDF 4.1 files (.mf4), for the analysis in measurement tools like CANape or vSignalyzer
+// Each DAQ list (event) is a channel group of one unsorted data group, with the raw 64 bit DAQ timestamp as master channel
+// The channels are the raw ODT entry values, the conversion rules (linear, rational function, value and verbal tables) and units are
+// taken from the registry. The records are written while recording, the file is finalized on measurement stop, an unfinalized file
+// (UnFinMF) has to be finalized with a MDF tool
+// The --mdf option of the command line tool records the --mea measurement with XcpMdfRecorder as DAQ decoder
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use xcp_registry::{McConversion, McSupportData, Registry};
+
+use super::{A2lTypeEncoding, OdtEntry, XcpDaqDecoder};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// MDF4 blocks
+
+const MDF_ID_SIZE: u64 = 64;
+const MDF_HD_POS: u64 = 64; // The HD block always follows the ID block
+const MDF_UNFIN_FLAGS: u16 = 0x0001 | 0x0004; // Cycle counters of the CG blocks and length of the last DT block not updated
+
+// CN types and data types
+const CN_TYPE_FIXED: u8 = 0;
+const CN_TYPE_MASTER: u8 = 2;
+const CN_SYNC_TIME: u8 = 1;
+const CN_DATA_UINT_LE: u8 = 0;
+const CN_DATA_SINT_LE: u8 = 2;
+const CN_DATA_FLOAT_LE: u8 = 4;
+
+// CC types
+const CC_LINEAR: u8 = 1;
+const CC_RATIONAL: u8 = 2;
+const CC_TAB_INTP: u8 = 4;
+const CC_TAB: u8 = 5;
+const CC_VALUE_TO_TEXT: u8 = 7;
+
+// MDF4 file content, blocks are 8 byte aligned, links may be set after the linked block has been added
+struct MdfBuilder {
+    buf: Vec<u8>,
+}
+
+impl MdfBuilder {
+    fn new(unfinished: bool) -> MdfBuilder {
+        let mut buf = Vec::with_capacity(4096);
+        buf.extend_from_slice(if unfinished { b"UnFinMF " } else { b"MDF     " });
+        buf.extend_from_slice(b"4.10    ");
+        buf.extend_from_slice(b"xcp_lite");
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&410u16.to_le_bytes());
+        buf.extend_from_slice(&[0; 30]);
+        buf.extend_from_slice(&(if unfinished { MDF_UNFIN_FLAGS } else { 0 }).to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        assert_eq!(buf.len() as u64, MDF_ID_SIZE);
+        MdfBuilder { buf }
+    }
+
+    // Add a block, returns its file position
+    fn block(&mut self, id: &[u8; 4], links: &[u64], data: &[u8]) -> u64 {
+        let pos = self.buf.len();
+        let len = (24 + 8 * links.len() + data.len()).next_multiple_of(8);
+        self.buf.extend_from_slice(id);
+        self.buf.extend_from_slice(&[0; 4]);
+        self.buf.extend_from_slice(&(len as u64).to_le_bytes());
+        self.buf.extend_from_slice(&(links.len() as u64).to_le_bytes());
+        for link in links {
+            self.buf.extend_from_slice(&link.to_le_bytes());
+        }
+        self.buf.extend_from_slice(data);
+        self.buf.resize(pos + len, 0);
+        pos as u64
+    }
+
+    // Set link index of the block at pos
+    fn set_link(&mut self, pos: u64, index: usize, link: u64) {
+        let p = usize::try_from(pos).expect("block in buffer") + 24 + 8 * index;
+        self.buf[p..p + 8].copy_from_slice(&link.to_le_bytes());
+    }
+
+    // Add a TX block, 0 (NIL) for an empty text
+    fn tx(&mut self, text: &str) -> u64 {
+        if text.is_empty() {
+            return 0;
+        }
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        self.block(b"##TX", &[], &data)
+    }
+
+    // Add a CC block with the values and the links to referenced blocks
+    fn cc(&mut self, cc_type: u8, values: &[f64], refs: &[u64]) -> u64 {
+        let mut links = vec![0u64; 4]; // name, unit, comment, inverse
+        links.extend_from_slice(refs);
+        let mut data = vec![cc_type, 0];
+        data.extend_from_slice(&0u16.to_le_bytes()); // flags
+        data.extend_from_slice(&u16::try_from(refs.len()).unwrap_or(u16::MAX).to_le_bytes());
+        data.extend_from_slice(&u16::try_from(values.len()).unwrap_or(u16::MAX).to_le_bytes());
+        data.extend_from_slice(&0f64.to_le_bytes()); // physical range
+        data.extend_from_slice(&0f64.to_le_bytes());
+        for v in values {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        self.block(b"##CC", &links, &data)
+    }
+
+    // Add a CN block, links cn_next, composition, name, source, conversion, data, unit, comment
+    #[allow(clippy::too_many_arguments)]
+    fn cn(&mut self, cn_type: u8, sync_type: u8, data_type: u8, byte_offset: u32, bit_count: u32, name: u64, cc: u64, unit: u64) -> u64 {
+        let mut data = vec![cn_type, sync_type, data_type, 0];
+        data.extend_from_slice(&byte_offset.to_le_bytes());
+        data.extend_from_slice(&bit_count.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes()); // flags
+        data.extend_from_slice(&0u32.to_le_bytes()); // invalidation bit position
+        data.extend_from_slice(&[0, 0, 0, 0]); // precision, reserved, attachment count
+        data.extend_from_slice(&[0; 6 * 8]); // value range and limits, invalid by flags
+        self.block(b"##CN", &[0, 0, name, 0, cc, 0, unit, 0], &data)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Channel groups
+
+// Channel group of a DAQ list, the record is the raw 64 bit timestamp followed by the ODTs of the DAQ list
+struct MdfGroup {
+    cg_pos: u64,           // File position of the CG block, to update the cycle count
+    odt_offsets: Vec<u16>, // Record offset of each ODT
+    odt_sizes: Vec<u16>,
+    record: Vec<u8>, // Record currently assembled, with record id
+    pending: bool,   // The record has data which is not written yet
+    cycle_count: u64,
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpMdfRecorder
+
+/// DAQ decoder recording into a MDF4 file
+/// One channel group per DAQ list, named by its event, with the raw DAQ timestamps as master channel and the raw signal values
+pub struct XcpMdfRecorder {
+    path: PathBuf,
+    signals: HashMap<String, McSupportData>, // Conversion rules and units from the registry
+    events: HashMap<u16, String>,            // Event names from the registry
+    writer: Option<BufWriter<File>>,
+    dt_pos: u64,
+    dt_size: u64,
+    groups: Vec<Option<MdfGroup>>, // Channel group of each DAQ list
+    record_id_size: usize,
+    timestamp_resolution_ns: u64,
+    daq_header_size: u8,
+    daq_timestamp_size: u8,
+    daq_timestamp: Vec<u64>, // last 64 bit raw timestamp per DAQ list
+    start_instant: std::time::Instant,
+    failed: bool, // A file error stopped the recording
+}
+
+impl XcpMdfRecorder {
+    /// Recorder writing to path on measurement start, an existing file is overwritten
+    pub fn new<P: AsRef<Path>>(path: P) -> XcpMdfRecorder {
+        XcpMdfRecorder {
+            path: path.as_ref().to_path_buf(),
+            signals: HashMap::new(),
+            events: HashMap::new(),
+            writer: None,
+            dt_pos: 0,
+            dt_size: 0,
+            groups: Vec::new(),
+            record_id_size: 1,
+            timestamp_resolution_ns: 1,
+            daq_header_size: 4,
+            daq_timestamp_size: 4,
+            daq_timestamp: Vec::new(),
+            start_instant: std::time::Instant::now(),
+            failed: false,
+        }
+    }
+
+    /// Take the conversion rules and units of the measurements and the event names from registry
+    /// Without registry, only the linear conversions of the ODT entries are recorded and channel groups are named by event id
+    pub fn set_registry(mut self, registry: &Registry) -> Self {
+        for instance in &registry.get_flattened_instance_list() {
+            if instance.is_measurement_object() {
+                self.signals.insert(instance.get_name().to_string(), instance.get_mc_support_data().clone());
+            }
+        }
+        for event in &registry.event_list {
+            self.events.insert(event.get_id(), event.get_unique_name(registry).to_string());
+        }
+        self
+    }
+
+    /// Get the file path
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check if a file error stopped the recording
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Get the number of records written for each DAQ list
+    pub fn get_cycle_counts(&self) -> Vec<u64> {
+        self.groups.iter().map(|g| g.as_ref().map_or(0, |g| g.cycle_count)).collect()
+    }
+
+    // Add the conversion of an ODT entry, a conversion rule from the registry or the linear conversion of the ODT entry
+    #[allow(clippy::cast_precision_loss)] // Raw values of verbal tables
+    fn add_conversion(&self, b: &mut MdfBuilder, entry: &OdtEntry) -> u64 {
+        match self.signals.get(&entry.name).and_then(|s| s.get_conversion()) {
+            // Inverse of the A2L rational function, physical = (f * raw - c) / (b - e * raw), requires a = d = 0
+            Some(McConversion::RatFunc([a, rb, c, d, e, f])) if *a == 0.0 && *d == 0.0 => b.cc(CC_RATIONAL, &[0.0, *f, -c, 0.0, -e, *rb], &[]),
+            Some(McConversion::RatFunc(_)) => {
+                warn!("MDF recorder: rational function of {} is not invertible, recorded without conversion", entry.name);
+                0
+            }
+            Some(McConversion::Table { pairs, interpolate }) => {
+                let values: Vec<f64> = pairs.iter().flat_map(|(raw, phys)| [*raw, *phys]).collect();
+                b.cc(if *interpolate { CC_TAB_INTP } else { CC_TAB }, &values, &[])
+            }
+            Some(McConversion::Verbal(pairs)) => {
+                let values: Vec<f64> = pairs.iter().map(|(raw, _)| *raw as f64).collect();
+                let mut refs: Vec<u64> = pairs.iter().map(|(_, label)| b.tx(label)).collect();
+                refs.push(0); // Default text
+                b.cc(CC_VALUE_TO_TEXT, &values, &refs)
+            }
+            None => match entry.conversion {
+                Some((factor, offset)) => b.cc(CC_LINEAR, &[offset, factor], &[]),
+                None => 0,
+            },
+        }
+    }
+
+    // Create the MDF file with the channel groups of the DAQ lists, ready to append records
+    #[allow(clippy::cast_precision_loss)] // Timestamp conversion
+    fn create(&mut self, odt_entries: &[Vec<OdtEntry>], timestamp_raw64: u64) -> std::io::Result<()> {
+        let mut b = MdfBuilder::new(true);
+        let start_time_ns = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(0));
+
+        // HD and FH
+        let hd = b.block(b"##HD", &[0; 6], &[&start_time_ns.to_le_bytes()[..], &[0; 24]].concat());
+        debug_assert_eq!(hd, MDF_HD_POS);
+        let md = b.block(
+            b"##MD",
+            &[],
+            format!(
+                "<FHcomment><TX>Recorded by xcp_client</TX><tool_id>xcp_client</tool_id><tool_vendor>Vector</tool_vendor><tool_version>{}</tool_version></FHcomment>\0",
+                env!("CARGO_PKG_VERSION")
+            )
+            .as_bytes(),
+        );
+        let fh = b.block(b"##FH", &[0, md], &[&start_time_ns.to_le_bytes()[..], &[0; 8]].concat());
+        b.set_link(hd, 1, fh);
+
+        // Data group with record ids
+        let record_id_size: u8 = if odt_entries.len() <= u8::MAX as usize { 1 } else { 2 };
+        self.record_id_size = record_id_size as usize;
+        let dg = b.block(b"##DG", &[0; 4], &[record_id_size, 0, 0, 0, 0, 0, 0, 0]);
+        b.set_link(hd, 0, dg);
+
+        // Channel group for each DAQ list, the master channel time in s starts at the measurement start
+        let time_unit = b.tx("s");
+        let time_factor = self.timestamp_resolution_ns as f64 * 1e-9;
+        let time_cc = b.cc(CC_LINEAR, &[-(timestamp_raw64 as f64) * time_factor, time_factor], &[]);
+        let mut cg_prev: Option<u64> = None;
+        self.groups = Vec::with_capacity(odt_entries.len());
+        for (daq, entries) in odt_entries.iter().enumerate() {
+            if entries.is_empty() {
+                self.groups.push(None); // Spare DAQ list
+                continue;
+            }
+
+            // ODT layout of the record, after the 8 byte timestamp
+            let odt_count = entries.iter().map(|e| e.odt as usize + 1).max().unwrap_or(0);
+            let mut odt_sizes = vec![0u16; odt_count];
+            for e in entries {
+                let size = odt_sizes[e.odt as usize].max(e.offset + u16::try_from(e.get_size()).unwrap_or(0));
+                odt_sizes[e.odt as usize] = size;
+            }
+            let mut odt_offsets = Vec::with_capacity(odt_count);
+            let mut record_size = 8u16;
+            for size in &odt_sizes {
+                odt_offsets.push(record_size);
+                record_size += size;
+            }
+
+            // Channels, the master channel first, arrays have a channel for each element
+            let name = b.tx("time");
+            let mut cns = vec![b.cn(CN_TYPE_MASTER, CN_SYNC_TIME, CN_DATA_UINT_LE, 0, 64, name, time_cc, time_unit)];
+            for e in entries {
+                let data_type = match e.a2l_type.encoding {
+                    A2lTypeEncoding::Unsigned => CN_DATA_UINT_LE,
+                    A2lTypeEncoding::Signed => CN_DATA_SINT_LE,
+                    A2lTypeEncoding::Float => CN_DATA_FLOAT_LE,
+                    A2lTypeEncoding::Blob => {
+                        warn!("MDF recorder: {} has an unsupported type, not recorded", e.name);
+                        continue;
+                    }
+                };
+                let cc = self.add_conversion(&mut b, e);
+                let unit = b.tx(self.signals.get(&e.name).map_or("", |s| s.get_unit()));
+                let offset = u32::from(odt_offsets[e.odt as usize] + e.offset);
+                for i in 0..e.get_element_count() {
+                    let name = b.tx(&e.get_element_name(i));
+                    let offset = offset + u32::try_from(i * e.a2l_type.size).unwrap_or(0);
+                    cns.push(b.cn(CN_TYPE_FIXED, 0, data_type, offset, u32::try_from(8 * e.a2l_type.size).unwrap_or(0), name, cc, unit));
+                }
+            }
+            for w in cns.windows(2) {
+                b.set_link(w[0], 0, w[1]);
+            }
+
+            // Channel group, named by the event of the DAQ list
+            let event = entries[0].a2l_addr.event;
+            let acq_name = match event.and_then(|id| self.events.get(&id)) {
+                Some(name) => name.clone(),
+                None => event.map_or(format!("daq_{}", daq), |id| format!("event_{}", id)),
+            };
+            let acq_name = b.tx(&acq_name);
+            let record_id = daq as u64 + 1;
+            let mut data = Vec::with_capacity(32);
+            data.extend_from_slice(&record_id.to_le_bytes());
+            data.extend_from_slice(&0u64.to_le_bytes()); // cycle count
+            data.extend_from_slice(&[0; 8]); // flags, path separator, reserved
+            data.extend_from_slice(&u32::from(record_size).to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes()); // invalidation bytes
+            let cg = b.block(b"##CG", &[0, cns[0], acq_name, 0, 0, 0], &data);
+            match cg_prev {
+                Some(prev) => b.set_link(prev, 0, cg),
+                None => b.set_link(dg, 1, cg),
+            }
+            cg_prev = Some(cg);
+
+            let mut record = vec![0u8; self.record_id_size + record_size as usize];
+            record[..self.record_id_size].copy_from_slice(&record_id.to_le_bytes()[..self.record_id_size]);
+            debug!("MDF recorder: channel group daq={}, {} channels, record size {}", daq, cns.len(), record_size);
+            self.groups.push(Some(MdfGroup {
+                cg_pos: cg,
+                odt_offsets,
+                odt_sizes,
+                record,
+                pending: false,
+                cycle_count: 0,
+            }));
+        }
+
+        // DT block header, its length is updated on stop
+        self.dt_pos = b.buf.len() as u64;
+        b.set_link(dg, 2, self.dt_pos);
+        b.buf.extend_from_slice(b"##DT");
+        b.buf.extend_from_slice(&[0; 4]);
+        b.buf.extend_from_slice(&24u64.to_le_bytes());
+        b.buf.extend_from_slice(&0u64.to_le_bytes());
+        self.dt_size = 0;
+
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        writer.write_all(&b.buf)?;
+        self.writer = Some(writer);
+        info!("MDF recorder: recording to {}", self.path.display());
+        Ok(())
+    }
+
+    // Write the record of a DAQ list
+    fn write_record(&mut self, daq: usize) -> std::io::Result<()> {
+        let (Some(writer), Some(Some(group))) = (self.writer.as_mut(), self.groups.get_mut(daq)) else {
+            return Ok(());
+        };
+        if group.pending {
+            writer.write_all(&group.record)?;
+            self.dt_size += group.record.len() as u64;
+            group.cycle_count += 1;
+            group.pending = false;
+        }
+        Ok(())
+    }
+
+    // Write the pending records, update the DT block length and the cycle counts and mark the file as finalized
+    fn finalize(&mut self) -> std::io::Result<()> {
+        for daq in 0..self.groups.len() {
+            self.write_record(daq)?;
+        }
+        let Some(mut writer) = self.writer.take() else {
+            return Ok(());
+        };
+        writer.seek(SeekFrom::Start(self.dt_pos + 8))?;
+        writer.write_all(&(24 + self.dt_size).to_le_bytes())?;
+        for group in self.groups.iter().flatten() {
+            writer.seek(SeekFrom::Start(group.cg_pos + 24 + 6 * 8 + 8))?;
+            writer.write_all(&group.cycle_count.to_le_bytes())?;
+        }
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(&MdfBuilder::new(false).buf)?;
+        writer.flush()?;
+        info!("MDF recorder: {} records written to {}", self.get_cycle_counts().iter().sum::<u64>(), self.path.display());
+        Ok(())
+    }
+
+    // Extend a 8, 16 or 32 bit raw timestamp to 64 bit, using the last timestamp of this DAQ list
+    fn extend_timestamp(&mut self, daq: usize, timestamp_raw: u64) -> u64 {
+        let bits = 8 * self.daq_timestamp_size as u32;
+        let mask = (1u64 << bits) - 1;
+        let t_last = self.daq_timestamp[daq];
+        let mut th = t_last >> bits;
+        if timestamp_raw < (t_last & mask) {
+            th += 1;
+        }
+        let t = (th << bits) | timestamp_raw;
+        self.daq_timestamp[daq] = t;
+        t
+    }
+
+    // Copy the ODT into the record of its DAQ list, the first ODT starts a new record
+    fn decode_odt(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        let header_size = self.daq_header_size as usize;
+        let odt = buf.first().copied().unwrap_or(0) as usize;
+        let timestamp_size = if odt == 0 { self.daq_timestamp_size as usize } else { 0 }; // Only the first ODT of a DAQ list has a timestamp
+        if buf.len() < header_size + timestamp_size {
+            return Ok(());
+        }
+        let daq = match header_size {
+            4 => buf[2] as usize | (buf[3] as usize) << 8,
+            3 => buf[1] as usize | (buf[2] as usize) << 8,
+            _ => buf[1] as usize,
+        };
+        if self.groups.get(daq).and_then(Option::as_ref).is_none_or(|g| odt >= g.odt_sizes.len()) {
+            warn!("MDF recorder: unexpected DAQ packet daq={} odt={}", daq, odt);
+            return Ok(());
+        }
+
+        if odt == 0 {
+            self.write_record(daq)?;
+            let t = if self.daq_timestamp_size == 0 {
+                u64::try_from(self.start_instant.elapsed().as_nanos()).unwrap_or(u64::MAX) / self.timestamp_resolution_ns.max(1) // Receive time
+            } else {
+                let timestamp_raw = buf[header_size..header_size + timestamp_size].iter().rev().fold(0u64, |t, b| (t << 8) | *b as u64);
+                self.extend_timestamp(daq, timestamp_raw)
+            };
+            if let Some(Some(group)) = self.groups.get_mut(daq) {
+                group.record[self.record_id_size..self.record_id_size + 8].copy_from_slice(&t.to_le_bytes());
+            }
+        }
+
+        let record_id_size = self.record_id_size;
+        let Some(Some(group)) = self.groups.get_mut(daq) else {
+            return Ok(());
+        };
+        let data = &buf[header_size + timestamp_size..];
+        let size = (group.odt_sizes[odt] as usize).min(data.len());
+        let offset = record_id_size + group.odt_offsets[odt] as usize;
+        group.record[offset..offset + size].copy_from_slice(&data[..size]);
+        group.pending = true;
+        if odt + 1 == group.odt_sizes.len() {
+            self.write_record(daq)?;
+        }
+        Ok(())
+    }
+}
+
+impl XcpDaqDecoder for XcpMdfRecorder {
+    fn start(&mut self, odt_entries: Vec<Vec<OdtEntry>>, timestamp_raw64: u64) {
+        self.daq_timestamp = vec![if self.daq_timestamp_size == 0 { 0 } else { timestamp_raw64 }; odt_entries.len()];
+        self.start_instant = std::time::Instant::now();
+        self.failed = false;
+        if let Err(e) = self.create(&odt_entries, if self.daq_timestamp_size == 0 { 0 } else { timestamp_raw64 }) {
+            error!("MDF recorder: could not create {}: {}", self.path.display(), e);
+            self.writer = None;
+            self.failed = true;
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Err(e) = self.finalize() {
+            error!("MDF recorder: could not finalize {}: {}", self.path.display(), e);
+            self.failed = true;
+        }
+    }
+
+    fn update_daq_list(&mut self, daq: u16, odt_entries: Vec<OdtEntry>) {
+        // The channel groups are written on measurement start
+        if !odt_entries.is_empty() {
+            warn!("MDF recorder: DAQ list {} configured while running is not recorded", daq);
+        }
+    }
+
+    fn set_daq_properties(&mut self, timestamp_resolution: u64, daq_header_size: u8) {
+        self.timestamp_resolution_ns = timestamp_resolution;
+        self.daq_header_size = daq_header_size;
+    }
+
+    fn set_daq_timestamp_size(&mut self, timestamp_size: u8) {
+        self.daq_timestamp_size = timestamp_size;
+    }
+
+    fn decode(&mut self, lost: u32, data: &[u8]) {
+        if lost > 0 {
+            warn!("MDF recorder: {} DAQ packets lost", lost);
+        }
+        if self.failed || self.writer.is_none() {
+            return;
+        }
+        if let Err(e) = self.decode_odt(data) {
+            error!("MDF recorder: recording stopped, {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod mdf_recorder_tests {
+
+    use super::*;
+    use crate::xcp_client::{A2lAddr, A2lType};
+
+    fn odt_entry(name: &str, odt: u8, size: usize) -> OdtEntry {
+        OdtEntry {
+            name: name.to_string(),
+            a2l_type: A2lType {
+                size,
+                encoding: A2lTypeEncoding::Unsigned,
+            },
+            a2l_addr: A2lAddr { ext: 0, addr: 0, event: Some(7) },
+            odt,
+            offset: 0,
+            conversion: None,
+            dim: [1, 1],
+        }
+    }
+
+    fn find_block(data: &[u8], id: &[u8; 4]) -> usize {
+        data.windows(4).position(|w| w == id).unwrap()
+    }
+
+    fn read_u64(data: &[u8], pos: usize) -> u64 {
+        u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_mdf_recorder() {
+        let path = std::env::temp_dir().join(format!("xcp_client_mdf_{}.mf4", std::process::id()));
+        let mut recorder = XcpMdfRecorder::new(&path);
+        recorder.set_daq_properties(1000, 4);
+        recorder.set_daq_timestamp_size(4);
+
+        // DAQ list 0 with 2 ODTs, spare DAQ list 1
+        recorder.start(vec![vec![odt_entry("a", 0, 4), odt_entry("b", 1, 2)], Vec::new()], 0xFFFF_FF00);
+        recorder.decode(0, &[0, 0, 0, 0, 0xF0, 0xFF, 0xFF, 0xFF, 0x78, 0x56, 0x34, 0x12]);
+        recorder.decode(0, &[1, 0, 0, 0, 0x34, 0x12]);
+        // 32 bit timestamp overflow, the ODT of the last cycle is missing
+        recorder.decode(0, &[0, 0, 0, 0, 0x10, 0, 0, 0, 1, 0, 0, 0]);
+        recorder.decode(0, &[0, 0, 1, 0, 0, 0, 0, 0]); // Spare DAQ list
+        recorder.stop();
+        assert!(!recorder.is_failed());
+        assert_eq!(recorder.get_cycle_counts(), vec![2, 0]);
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&data[0..8], b"MDF     ");
+        assert_eq!(u16::from_le_bytes([data[60], data[61]]), 0); // Finalized
+
+        // Channel group cycle count
+        let cg = find_block(&data, b"##CG");
+        assert_eq!(read_u64(&data, cg + 24 + 6 * 8 + 8), 2);
+
+        // Records: record id, 64 bit timestamp, ODT 0 and ODT 1
+        let dt = find_block(&data, b"##DT");
+        let records = &data[dt + 24..];
+        assert_eq!(read_u64(&data, dt + 8), 24 + records.len() as u64);
+        assert_eq!(records.len(), 2 * (1 + 8 + 4 + 2));
+        assert_eq!(records[0], 1);
+        assert_eq!(read_u64(records, 1), 0xFFFF_FFF0);
+        assert_eq!(&records[9..15], &[0x78, 0x56, 0x34, 0x12, 0x34, 0x12]);
+        assert_eq!(records[15], 1);
+        assert_eq!(read_u64(records, 16), 0x1_0000_0010);
+        assert_eq!(&records[24..30], &[1, 0, 0, 0, 0x34, 0x12]);
+    }
+}
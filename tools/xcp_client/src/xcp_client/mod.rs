@@ -0,0 +1,2698 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module xcp_client
+// XCP client for xcp_lite servers, XCP on UDP, TCP and CAN
+
+ // because of all the unused XCP definitions
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::select;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::time::{Duration, timeout};
+
+pub mod xcp;
+use xcp::*;
+pub mod commands;
+use commands::*;
+mod cal_history;
+pub use cal_history::*;
+mod watch;
+pub use watch::*;
+mod capabilities;
+pub use capabilities::*;
+mod checksum;
+pub use checksum::*;
+mod clock_sync;
+pub use clock_sync::*;
+mod csv_recorder;
+pub use csv_recorder::*;
+mod multi_target;
+pub use multi_target::*;
+mod a2l_manager;
+pub use a2l_manager::*;
+mod elf_signature;
+pub use elf_signature::*;
+mod elf_debug_file;
+pub use elf_debug_file::*;
+mod elf_symbols;
+pub use elf_symbols::*;
+mod epk_check;
+pub use epk_check::*;
+mod raw_memory;
+pub use raw_memory::*;
+mod bin_check;
+pub use bin_check::*;
+mod bin_file;
+pub use bin_file::*;
+mod block_transfer;
+mod cal_batch;
+pub use cal_batch::*;
+mod cal_patch;
+mod daq_loss;
+pub use daq_loss::*;
+mod daq_printer;
+pub use daq_printer::*;
+mod exit_code;
+pub use exit_code::*;
+mod hexfile;
+pub use hexfile::*;
+mod mdf_recorder;
+pub use mdf_recorder::*;
+mod fill_forward;
+pub use fill_forward::*;
+mod ring_recorder;
+pub use ring_recorder::*;
+mod seed_key;
+pub use seed_key::*;
+mod sessions;
+pub use sessions::*;
+mod stack_check;
+pub use stack_check::*;
+mod stim;
+pub use stim::*;
+mod trigger_recorder;
+pub use trigger_recorder::*;
+mod xcp_on_can;
+pub use xcp_on_can::*;
+use xcp_registry::*;
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XCP Parameters
+
+pub const CMD_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Name of the calibration segment, in which an xcp_lite server publishes the mapping of its DAQ clock to UTC
+pub const XCP_CLOCK_EPOCH_NAME: &str = "xcp_clock_epoch";
+
+// Max number of ODT entries per ODT and of ODTs per DAQ list
+// The PIDs 0xFC..0xFF are reserved for CTO packets
+const MAX_ODT_ENTRY_COUNT: usize = 0x7B;
+const MAX_ODT_COUNT: usize = 0x7C;
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// CalibrationObject
+// Describes a calibration object with name, address, type, limits and caches it actual value
+
+// Measurement and calibration object attributes
+
+#[derive(Debug, Clone, Copy)]
+pub struct A2lAddr {
+    pub ext: u8,
+    pub addr: u32,
+    pub event: Option<u16>,
+}
+
+impl std::fmt::Display for A2lAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(event) = self.event {
+            write!(f, "{}:0x{:08X} event {}", self.ext, self.addr, event)
+        } else {
+            write!(f, "{}:0x{:08X}", self.ext, self.addr)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum A2lTypeEncoding {
+    Signed,
+    Unsigned,
+    Float,
+    Blob,
+}
+
+impl From<&McValueType> for A2lTypeEncoding {
+    fn from(value_type: &McValueType) -> A2lTypeEncoding {
+        match value_type {
+            McValueType::Bool | McValueType::Ubyte | McValueType::Uword | McValueType::Ulong | McValueType::Ulonglong => A2lTypeEncoding::Unsigned,
+            McValueType::Sbyte | McValueType::Sword | McValueType::Slong | McValueType::Slonglong => A2lTypeEncoding::Signed,
+            McValueType::Float32Ieee | McValueType::Float64Ieee => A2lTypeEncoding::Float,
+            _ => A2lTypeEncoding::Blob,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct A2lType {
+    pub size: usize,
+    pub encoding: A2lTypeEncoding,
+}
+
+impl std::fmt::Display for A2lType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.encoding {
+            A2lTypeEncoding::Signed => write!(f, "{} byte signed", self.size),
+            A2lTypeEncoding::Unsigned => write!(f, "{} byte unsigned", self.size),
+            A2lTypeEncoding::Float => write!(f, "{} byte float", self.size),
+            A2lTypeEncoding::Blob => write!(f, "{} byte blob", self.size),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct A2lLimits {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct XcpCalibrationObjectHandle(usize);
+
+impl XcpCalibrationObjectHandle {
+    pub fn get_name(self, xcp_client: &mut XcpClient) -> &str {
+        xcp_client.get_calibration_object(self).get_name()
+    }
+    pub fn get_a2l_addr(self, xcp_client: &mut XcpClient) -> A2lAddr {
+        xcp_client.get_calibration_object(self).get_a2l_addr()
+    }
+    pub fn get_a2l_type(self, xcp_client: &mut XcpClient) -> A2lType {
+        xcp_client.get_calibration_object(self).get_a2l_type()
+    }
+}
+
+// XCPlite event relative (dyn) addressing mode address extensions, the upper address bits are the event id
+fn is_dyn_addr_ext(ext: u8) -> bool {
+    (McAddress::XCP_ADDR_EXT_DYN..McAddress::XCP_ADDR_EXT_DYN + 16).contains(&ext)
+}
+
+// Check the minimum length of a positive command response before it is indexed
+fn check_response_len(data: &[u8], len: usize, command_code: u8) -> Result<(), Box<dyn Error>> {
+    if data.len() < len {
+        error!("Response to command 0x{:02X} has {} bytes, expected at least {}", command_code, data.len(), len);
+        return Err(Box::new(XcpError::new(ERROR_RESPONSE_LENGTH, command_code)) as Box<dyn Error>);
+    }
+    Ok(())
+}
+
+// Distribute the (measurement object index, size) entries of a DAQ list to ODTs, a new ODT is started when a DTO is full
+// The first ODT carries first_header_size bytes of DAQ header and timestamp, the following ODTs header_size bytes
+// Returns the index of the first entry which does not fit into an empty DTO
+fn split_odt_entries(entries: &[(usize, u16)], max_dto_size: u16, first_header_size: u16, header_size: u16) -> Result<Vec<Vec<usize>>, usize> {
+    let mut odts: Vec<Vec<usize>> = vec![Vec::new()];
+    let mut odt_size: u32 = 0;
+    for &(i, size) in entries {
+        let max_odt_size = u32::from(max_dto_size.saturating_sub(if odts.len() == 1 { first_header_size } else { header_size }));
+        let odt = odts.last_mut().unwrap();
+        if !odt.is_empty() && (odt_size + u32::from(size) > max_odt_size || odt.len() >= MAX_ODT_ENTRY_COUNT) {
+            odts.push(Vec::new());
+            odt_size = 0;
+        }
+        let max_odt_size = u32::from(max_dto_size.saturating_sub(if odts.len() == 1 { first_header_size } else { header_size }));
+        if odt_size + u32::from(size) > max_odt_size {
+            return Err(i);
+        }
+        odts.last_mut().unwrap().push(i);
+        odt_size += u32::from(size);
+    }
+    Ok(odts)
+}
+
+// Integer write values as f64 for the limit check
+// Magnitudes up to 2^53 convert exactly, above that the nearest f64 is used with a relative error of at most 2^-53,
+// so only a value closer than that to an A2L limit may be misjudged, the downloaded raw value is always exact
+#[allow(clippy::cast_precision_loss)]
+fn u64_to_limit_f64(value: u64) -> f64 {
+    value as f64
+}
+#[allow(clippy::cast_precision_loss)]
+fn i64_to_limit_f64(value: i64) -> f64 {
+    value as f64
+}
+
+// Encode a physical value as little endian raw value bytes, None for blobs or unsupported sizes
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn f64_to_value(a2l_type: A2lType, value: f64) -> Option<Vec<u8>> {
+    let v: u64 = match a2l_type.encoding {
+        A2lTypeEncoding::Float if a2l_type.size == 4 => (value as f32).to_bits() as u64,
+        A2lTypeEncoding::Float if a2l_type.size == 8 => value.to_bits(),
+        A2lTypeEncoding::Signed => value as i64 as u64,
+        A2lTypeEncoding::Unsigned => value as u64,
+        _ => return None,
+    };
+    if a2l_type.size == 0 || a2l_type.size > 8 {
+        return None;
+    }
+    Some(v.to_le_bytes()[0..a2l_type.size].to_vec())
+}
+
+/// Calibration object with name, address, type, limits and its cached value
+/// Arrays (CURVE, MAP, VAL_BLK) have dim > 1, the type and the limits apply to each element
+/// Typedef instances and blobs have type encoding Blob and are not writeable as values
+#[derive(Debug)]
+pub struct XcpClientCalibrationObject {
+    name: String,
+    a2l_addr: A2lAddr,
+    get_type: A2lType,
+    a2l_limits: A2lLimits,
+    dim: [u16; 2],
+    value: Vec<u8>,
+}
+
+impl XcpClientCalibrationObject {
+    pub fn new(name: &str, a2l_addr: A2lAddr, get_type: A2lType, a2l_limits: A2lLimits) -> XcpClientCalibrationObject {
+        XcpClientCalibrationObject {
+            name: name.to_string(),
+            a2l_addr,
+            get_type,
+            a2l_limits,
+            dim: [1, 1],
+            value: Vec::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Array dimensions [x_dim, y_dim], [1, 1] for scalars
+    pub fn get_dim(&self) -> [u16; 2] {
+        self.dim
+    }
+
+    /// Number of elements
+    pub fn get_element_count(&self) -> usize {
+        self.dim[0] as usize * self.dim[1] as usize
+    }
+
+    /// Size of the object in bytes
+    pub fn get_size(&self) -> usize {
+        self.get_type.size * self.get_element_count()
+    }
+
+    /// Check if the object is a single value of basic type
+    pub fn is_scalar(&self) -> bool {
+        !matches!(self.get_type.encoding, A2lTypeEncoding::Blob) && self.get_element_count() == 1
+    }
+
+    // Check a write of value to element index
+    fn check_write(&self, index: usize, value: f64) -> Result<(), Box<dyn Error>> {
+        if matches!(self.get_type.encoding, A2lTypeEncoding::Blob) {
+            error!("{}: {} values can not be written as numbers", self.name, self.get_type);
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+        }
+        if index >= self.get_element_count() {
+            error!("{}: element index {} out of range, {} elements", self.name, index, self.get_element_count());
+            return Err(Box::new(XcpError::new(ERROR_INDEX_RANGE, 0)) as Box<dyn Error>);
+        }
+        if value > self.a2l_limits.upper || value < self.a2l_limits.lower {
+            error!("{}: value {} out of limits [{}, {}]", self.name, value, self.a2l_limits.lower, self.a2l_limits.upper);
+            return Err(Box::new(XcpError::new(ERROR_LIMIT, 0)) as Box<dyn Error>);
+        }
+        Ok(())
+    }
+
+    // Check a scalar write
+    fn check_scalar_write(&self, value: f64) -> Result<(), Box<dyn Error>> {
+        if !self.is_scalar() && !matches!(self.get_type.encoding, A2lTypeEncoding::Blob) {
+            error!("{}: not a scalar, {} elements, use set_element_f64", self.name, self.get_element_count());
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+        }
+        self.check_write(0, value)
+    }
+
+    /// Physical value of element index from the cached value
+    pub fn get_element_f64(&self, index: usize) -> Option<f64> {
+        if matches!(self.get_type.encoding, A2lTypeEncoding::Blob) || index >= self.get_element_count() {
+            return None;
+        }
+        let size = self.get_type.size;
+        self.value.get(index * size..(index + 1) * size).map(|bytes| value_to_f64(self.get_type, bytes))
+    }
+
+    // Hierarchical name of element index, as used by Registry::resolve_instance
+    fn get_element_name(&self, index: usize) -> String {
+        let [x_dim, y_dim] = self.dim;
+        if y_dim > 1 {
+            format!("{}._{}_{}", self.name, index / x_dim as usize, index % x_dim as usize)
+        } else {
+            format!("{}._{}", self.name, index)
+        }
+    }
+
+    pub fn get_a2l_type(&self) -> A2lType {
+        self.get_type
+    }
+
+    pub fn get_a2l_addr(&self) -> A2lAddr {
+        self.a2l_addr
+    }
+
+    pub fn set_value(&mut self, bytes: &[u8]) {
+        self.value = bytes.to_vec();
+    }
+
+    pub fn get_value(&mut self) -> &[u8] {
+        &self.value
+    }
+
+    pub fn get_value_u64(&self) -> u64 {
+        let mut value = 0u64;
+        for i in (0..self.get_type.size).rev() {
+            value <<= 8;
+            value += self.value[i] as u64;
+        }
+        value
+    }
+
+    pub fn get_value_i64(&self) -> i64 {
+        let size: usize = self.get_type.size;
+        let mut value = 0;
+        if self.value[size - 1] & 0x80 != 0 {
+            value = -1;
+        }
+        for i in (0..size).rev() {
+            value <<= 8;
+            assert!(value & 0xFF == 0);
+            value |= self.value[i] as i64;
+        }
+        value
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// MeasurementObject
+// Describes a measurement object with name, address, type and event
+
+#[derive(Debug, Copy, Clone)]
+pub struct XcpMeasurementObjectHandle(pub usize);
+
+impl XcpMeasurementObjectHandle {
+    pub fn get_name(self, xcp_client: &mut XcpClient) -> &str {
+        xcp_client.get_measurement_object(self).get_name()
+    }
+    pub fn get_a2l_addr(self, xcp_client: &mut XcpClient) -> A2lAddr {
+        xcp_client.get_measurement_object(self).get_a2l_addr()
+    }
+    pub fn get_a2l_type(self, xcp_client: &mut XcpClient) -> A2lType {
+        xcp_client.get_measurement_object(self).get_a2l_type()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XcpClientMeasurementObject {
+    name: String,
+    pub a2l_addr: A2lAddr,
+    pub a2l_type: A2lType,
+    pub daq: u16,
+    pub odt: u8,
+    pub offset: u16,
+    measured: bool,                 // Assigned to a running DAQ list
+    conversion: Option<(f64, f64)>, // Linear conversion (factor, offset) from the registry
+    dim: [u16; 2],                  // Array dimensions [x_dim, y_dim], [1, 1] for scalars
+}
+
+impl XcpClientMeasurementObject {
+    pub fn new(name: &str, a2l_addr: A2lAddr, a2l_type: A2lType) -> XcpClientMeasurementObject {
+        XcpClientMeasurementObject {
+            name: name.to_string(),
+            a2l_addr,
+            a2l_type,
+            daq: 0,
+            odt: 0,
+            offset: 0,
+            measured: false,
+            conversion: None,
+            dim: [1, 1],
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub fn get_a2l_addr(&self) -> A2lAddr {
+        self.a2l_addr
+    }
+    pub fn get_a2l_type(&self) -> A2lType {
+        self.a2l_type
+    }
+    /// Linear conversion (factor, offset), physical value = raw value * factor + offset, None for identity
+    pub fn get_conversion(&self) -> Option<(f64, f64)> {
+        self.conversion
+    }
+    /// Array dimensions [x_dim, y_dim], [1, 1] for scalars
+    pub fn get_dim(&self) -> [u16; 2] {
+        self.dim
+    }
+    /// Number of elements
+    pub fn get_element_count(&self) -> usize {
+        self.dim[0] as usize * self.dim[1] as usize
+    }
+    /// Size of the measurement object in bytes, the size of an ODT entry
+    pub fn get_size(&self) -> usize {
+        self.a2l_type.size * self.get_element_count()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Decoder traits for XCP messages
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Text decoder trait for XCP SERV_TEXT messages
+
+pub trait XcpTextDecoder {
+    /// Handle incomming SERV_TEXT data from XCP server
+    fn decode(&self, data: &[u8]) {
+        print!("[SERV_TEXT] ");
+        let mut j = 0;
+        while j < data.len() {
+            if data[j] == 0 {
+                break;
+            }
+            print!("{}", data[j] as char);
+            j += 1;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// DAQ decoder trait for XCP DAQ messages
+
+/// DAQ information
+/// Describes a single ODT entry
+/// Arrays (VAL_BLK) have dim > 1, a2l_type is the type of an element, typedef instances and blobs have type encoding Blob
+#[derive(Debug, Clone)]
+pub struct OdtEntry {
+    pub name: String,
+    pub a2l_type: A2lType,
+    pub a2l_addr: A2lAddr,
+    pub odt: u8,                        // ODT of the DAQ list, signals exceeding one DTO are split over several ODTs
+    pub offset: u16,                    // offset from data start of the ODT, not including daq header and timestamp
+    pub conversion: Option<(f64, f64)>, // Linear conversion (factor, offset), e.g. of a quantized signal
+    pub dim: [u16; 2],                  // Array dimensions [x_dim, y_dim], [1, 1] for scalars
+}
+
+impl OdtEntry {
+    /// Physical value of a raw value
+    pub fn to_physical(&self, raw: f64) -> f64 {
+        self.conversion.map_or(raw, |(factor, offset)| raw * factor + offset)
+    }
+
+    /// Number of elements
+    pub fn get_element_count(&self) -> usize {
+        self.dim[0] as usize * self.dim[1] as usize
+    }
+
+    /// Size of the ODT entry in bytes
+    pub fn get_size(&self) -> usize {
+        self.a2l_type.size * self.get_element_count()
+    }
+
+    /// Check if the entry is an array of basic type
+    pub fn is_array(&self) -> bool {
+        !matches!(self.a2l_type.encoding, A2lTypeEncoding::Blob) && self.get_element_count() > 1
+    }
+
+    /// Name of element index, name._ix or name._iy_ix as used by Registry::resolve_instance, the name for scalars
+    pub fn get_element_name(&self, index: usize) -> String {
+        let [x_dim, y_dim] = self.dim;
+        if y_dim > 1 {
+            format!("{}._{}_{}", self.name, index / x_dim as usize, index % x_dim as usize)
+        } else if x_dim > 1 {
+            format!("{}._{}", self.name, index)
+        } else {
+            self.name.clone()
+        }
+    }
+
+    /// Raw values of the elements from the data of the ODT entry, empty for blobs and typedef instances or if data is too short
+    pub fn decode_elements(&self, data: &[u8]) -> Vec<f64> {
+        if matches!(self.a2l_type.encoding, A2lTypeEncoding::Blob) || data.len() < self.get_size() {
+            return Vec::new();
+        }
+        data[..self.get_size()].chunks(self.a2l_type.size).map(|bytes| value_to_f64(self.a2l_type, bytes)).collect()
+    }
+}
+
+pub trait XcpDaqDecoder {
+    /// Handle incomming DAQ packet from XCP server
+    /// Transport layer header has been stripped
+    fn decode(&mut self, lost: u32, data: &[u8]);
+
+    /// Measurement start
+    /// Decoding information: ODT entry table and 64 bit start timestamp
+    fn start(&mut self, odt_entries: Vec<Vec<OdtEntry>>, timestamp_raw64: u64);
+
+    /// Measurement stop
+    fn stop(&mut self) {}
+
+    /// A spare DAQ list has been configured (session added) or stopped (session removed, empty odt_entries) while DAQ is running
+    /// Spare DAQ lists are passed to start with empty ODT entries
+    fn update_daq_list(&mut self, _daq: u16, _odt_entries: Vec<OdtEntry>) {}
+
+    /// Set measurement timestamp resolution in ns per raw timestamp tick and DAQ header size (2 (ODTB/DAQB or 4 (ODTB,_,DAQW))
+    fn set_daq_properties(&mut self, timestamp_resolution: u64, daq_header_size: u8);
+
+    /// Set the size of the DAQ timestamp in the first ODT of a DAQ list (0 = no timestamps, 1, 2 or 4)
+    /// Called after set_daq_properties, decoders assuming 4 byte timestamps may ignore it
+    fn set_daq_timestamp_size(&mut self, _timestamp_size: u8) {}
+
+    /// Get the event count
+    fn get_event_count(&self) -> usize {
+        0
+    }
+
+    /// Get the byte count
+    fn get_byte_count(&self) -> usize {
+        0
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpSegmentInfo, XcpPageProperties
+
+/// Calibration segment standard information from GET_SEGMENT_INFO
+#[derive(Debug, Clone, Copy)]
+pub struct XcpSegmentInfo {
+    pub max_pages: u8,
+    pub addr_ext: u8,
+    pub max_mapping: u8,
+    pub compression: u8,
+    pub encryption: u8,
+}
+
+/// Calibration page properties from GET_PAGE_INFO
+#[derive(Debug, Clone, Copy)]
+pub struct XcpPageProperties {
+    pub properties: u8, // PAGE_PROPERTIES, 2 bits each for ECU access, XCP read access and XCP write access
+    pub init_segment: u8,
+}
+
+impl XcpPageProperties {
+    /// Page is writable by XCP, with or without ECU access
+    pub fn is_xcp_writable(&self) -> bool {
+        self.properties & 0x30 != 0
+    }
+
+    /// Page is readable by XCP, with or without ECU access
+    pub fn is_xcp_readable(&self) -> bool {
+        self.properties & 0x0C != 0
+    }
+
+    /// Page is accessed by the ECU, with or without XCP access
+    pub fn is_ecu_accessible(&self) -> bool {
+        self.properties & 0x03 != 0
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpEventInfo
+
+/// Event channel information from GET_DAQ_EVENT_INFO
+#[derive(Debug, Clone)]
+pub struct XcpEventInfo {
+    pub name: String,
+    pub properties: u8, // DAQ_EVENT_PROPERTIES
+    pub max_daq_list: u8,
+    pub time_cycle: u8,
+    pub time_unit: u8,
+    pub priority: u8,
+}
+
+impl XcpEventInfo {
+    const DAQ: u8 = 0x04;
+    const STIM: u8 = 0x08;
+
+    /// Variables of this event can be measured, servers which report neither DAQ nor STIM are assumed to support DAQ
+    pub fn is_daq(&self) -> bool {
+        self.properties & XcpEventInfo::DAQ != 0 || self.properties & XcpEventInfo::STIM == 0
+    }
+
+    /// Variables of this event can be stimulated
+    pub fn is_stim(&self) -> bool {
+        self.properties & XcpEventInfo::STIM != 0
+    }
+
+    /// Data consistency of the event, bit 6 DAQ, bit 7 EVENT, none of both ODT
+    pub fn get_consistency(&self) -> McEventConsistency {
+        match self.properties >> 6 {
+            0 => McEventConsistency::Odt,
+            1 => McEventConsistency::Daq,
+            2 => McEventConsistency::Event,
+            _ => McEventConsistency::None,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Type to control the receive task sent over the receive task control channel
+
+#[derive(Debug, Copy, Clone)]
+pub struct XcpTaskControl {
+    running: bool,
+    connected: bool,
+}
+
+impl XcpTaskControl {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> XcpTaskControl {
+        XcpTaskControl { running: false, connected: false }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Socket abstraction for UDP, TCP and CAN
+
+#[derive(Debug)]
+enum XcpSocket {
+    Udp(Arc<UdpSocket>),
+    Tcp(Arc<TcpStream>),
+    Can(Arc<XcpCanSocket>),
+}
+
+impl XcpSocket {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize, std::io::Error> {
+        match self {
+            XcpSocket::Udp(udp_socket) => udp_socket.send_to(buf, addr).await,
+            XcpSocket::Tcp(tcp_stream) => {
+                // But for now, let's revert to the working approach:
+                let mut pos = 0;
+                while pos < buf.len() {
+                    match tcp_stream.try_write(&buf[pos..]) {
+                        Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "write zero bytes")),
+                        Ok(n) => pos += n,
+                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            tcp_stream.writable().await?;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(buf.len())
+            }
+            XcpSocket::Can(can_socket) => can_socket.send(buf).await,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpClient
+
+/// XCP client
+pub struct XcpClient {
+    tcp: bool,
+    can: Option<XcpCanConfig>, // XCP on CAN, instead of UDP or TCP
+    // Information from connect and get_comm_mode_info commands
+    pub resources: u8,
+    pub comm_mode_basic: u8,
+    pub max_cto_size: u8,
+    pub max_dto_size: u16,
+    pub protocol_version: u16,
+    pub transport_layer_version: u16,
+    pub comm_mode_optional: u8,
+    pub max_bs: u8,     // Master block mode, maximum number of packets of a block
+    pub min_st: u8,     // Master block mode, minimum separation time between the packets of a block in 100 us
+    pub queue_size: u8, // Interleaved mode, maximum number of pending commands
+    pub driver_version: u8,
+    block_transfer: bool, // Use slave block, master block and interleaved mode for memory block transfers, if supported by the server
+    pub max_segments: u8,
+    pub freeze_supported: bool,
+    pub max_events: u16,
+    pub capabilities: XcpCapabilities,
+
+    pub registry: Option<xcp_registry::Registry>,
+
+    timestamp_resolution_ns: u64,
+    daq_header_size: u8,
+    daq_start: Option<(std::time::SystemTime, u64)>, // UTC wall clock and DAQ clock in ns at measurement start
+
+    bind_addr: SocketAddr,
+    dest_addr: SocketAddr,
+
+    socket: Option<XcpSocket>,
+    receive_task: Option<tokio::task::JoinHandle<()>>,
+    rx_cmd_resp: Option<mpsc::Receiver<Vec<u8>>>,
+    tx_task_control: Option<mpsc::Sender<XcpTaskControl>>,
+    task_control: XcpTaskControl,
+    daq_decoder: Option<Arc<Mutex<dyn XcpDaqDecoder>>>,
+    daq_lost: Arc<AtomicU64>, // DAQ packets lost, from the transport layer counter
+    daq_loss: Arc<Mutex<XcpDaqLossStatistics>>,
+    ctr: u16,
+
+    calibration_object_list: Vec<XcpClientCalibrationObject>,
+    measurement_object_list: Vec<XcpClientMeasurementObject>,
+    daq_reserve: XcpDaqReserve,
+    sessions: Vec<Option<XcpMeasurementSession>>,
+    cal_history: XcpCalibrationHistory,
+}
+
+impl XcpClient {
+    //------------------------------------------------------------------------
+    // new
+    //
+    #[allow(clippy::type_complexity)]
+    pub fn new(tcp: bool, dest_addr: SocketAddr, bind_addr: SocketAddr) -> XcpClient {
+        XcpClient {
+            tcp,
+            can: None,
+            bind_addr,
+            dest_addr,
+            socket: None,
+            receive_task: None,
+            rx_cmd_resp: None,
+            tx_task_control: None,
+            task_control: XcpTaskControl::new(),
+            daq_decoder: None,
+            daq_lost: Arc::new(AtomicU64::new(0)),
+            daq_loss: Arc::new(Mutex::new(XcpDaqLossStatistics::default())),
+            ctr: 0,
+            resources: 0,
+            comm_mode_basic: 0,
+            comm_mode_optional: 0,
+            max_bs: 0,
+            min_st: 0,
+            queue_size: 0,
+            driver_version: 0,
+            block_transfer: true,
+            max_cto_size: 0,
+            max_dto_size: 0,
+            max_segments: 0,
+            max_events: 0,
+            freeze_supported: false,
+            protocol_version: 0,
+            transport_layer_version: 0,
+            capabilities: XcpCapabilities::new(),
+            timestamp_resolution_ns: 1,
+            daq_header_size: 4,
+            daq_start: None,
+            registry: None,
+            calibration_object_list: Vec::new(),
+            measurement_object_list: Vec::new(),
+            daq_reserve: XcpDaqReserve::new(),
+            sessions: Vec::new(),
+            cal_history: XcpCalibrationHistory::new(),
+        }
+    }
+
+    /// XCP on CAN or CAN FD client, see XcpCanConfig
+    pub fn new_can(config: XcpCanConfig) -> XcpClient {
+        let unspecified = SocketAddr::from(([0, 0, 0, 0], 0));
+        XcpClient {
+            can: Some(config),
+            ..XcpClient::new(false, unspecified, unspecified)
+        }
+    }
+
+    pub fn set_registry(&mut self, registry: xcp_registry::Registry) {
+        self.registry = Some(registry);
+    }
+
+    //------------------------------------------------------------------------
+    // Helper function for socket receive
+    async fn socket_receive(socket: &XcpSocket, buf: &mut [u8]) -> Result<(usize, Option<SocketAddr>), std::io::Error> {
+        match socket {
+            XcpSocket::Udp(udp_socket) => udp_socket.recv_from(buf).await.map(|(size, addr)| (size, Some(addr))),
+            XcpSocket::Tcp(tcp_stream) => {
+                let mut header = [0u8; 4];
+                let mut bytes_read = 0;
+                while bytes_read < 4 {
+                    tcp_stream.readable().await?;
+                    match tcp_stream.try_read(&mut header[bytes_read..]) {
+                        Ok(n) => {
+                            bytes_read += n;
+                            if n == 0 {
+                                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed"));
+                            }
+                        }
+                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                let len = header[0] as usize + ((header[1] as usize) << 8);
+                if len == 0 || len > buf.len() - 4 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid XCP header length: {}", len)));
+                }
+                buf[0..4].copy_from_slice(&header);
+                let mut bytes_read = 0;
+                while bytes_read < len {
+                    tcp_stream.readable().await?;
+                    match tcp_stream.try_read(&mut buf[4 + bytes_read..4 + len]) {
+                        Ok(n) => {
+                            bytes_read += n;
+                            if n == 0 {
+                                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed"));
+                            }
+                        }
+                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                Ok((len + 4, None))
+            }
+            XcpSocket::Can(can_socket) => can_socket.receive(buf).await.map(|size| (size, None)),
+        }
+    }
+
+    //------------------------------------------------------------------------
+    // receiver task
+    // Handle incoming data from XCP server
+    async fn receive_task(
+        socket: XcpSocket,
+        tx_resp: Sender<Vec<u8>>,
+        mut rx_daq_decoder: Receiver<XcpTaskControl>,
+        decode_serv_text: impl XcpTextDecoder,
+        decode_daq: Arc<Mutex<impl XcpDaqDecoder>>,
+        daq_lost: Arc<AtomicU64>,
+        daq_loss: Arc<Mutex<XcpDaqLossStatistics>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut ctr_last: u16 = 0;
+        let mut ctr_first: bool = true;
+        let mut ctr_lost: u32 = 0;
+
+        let mut buf: [u8; 8000] = [0; 8000];
+        let mut task_control: Option<XcpTaskControl> = None;
+
+        loop {
+            select! {
+
+                // Handle the data from rx_daq_decoder
+                res = rx_daq_decoder.recv() => {
+                    match res {
+                        Some(c) => {
+                            debug!("receive_task: task control status changed: connected={} running={}", c.connected, c.running);
+
+                            // Disconnect
+                            if !c.connected { // Handle the data from rx_daq_decoder
+                                debug!("receive_task: stop, disconnect");
+                                return Ok(());
+                            }
+
+                            // Start DAQ
+                            if c.running {
+                                debug!("receive_task: start DAQ");
+                                ctr_first = true;
+                                ctr_last = 0;
+                                ctr_lost = 0;
+
+                            }
+
+                            task_control = Some(c);
+                        }
+                        None => { // The sender has been dropped
+                            debug!("receive_task: stop, channel closed");
+                            return Ok(());
+                        }
+                    }
+                } // rx_daq_decoder.recv
+
+                // Handle the data from socket
+                res = Self::socket_receive(&socket, &mut buf) => {
+                    match res {
+                        Ok((size, _addr)) => {
+                            // Handle the data from recv_from/read
+                            if size == 0 {
+                                warn!("receive_task: stop, socket closed");
+                                return Ok(());
+                            }
+
+                            let mut i: usize = 0;
+                            while i < size {
+                                // Decode the next transport layer message header in the packet
+                                if size < 5 {
+                                    return Err(Box::new(XcpError::new(ERROR_TL_HEADER,0)) as Box<dyn Error>);
+                                }
+                                let len = buf[i] as usize + ((buf[i + 1] as usize) << 8);
+                                if len > size - 4 || len == 0 { // Corrupt packet received, not enough data received or no content
+                                    return Err(Box::new(XcpError::new(ERROR_TL_HEADER,0)) as Box<dyn Error>);
+                                }
+                                let ctr = buf[i + 2] as u16 + ((buf[i + 3] as u16) << 8);
+                                if ctr_first {
+                                    ctr_first = false;
+                                } else if ctr != ctr_last.wrapping_add(1) {
+                                    ctr_lost += ctr.wrapping_sub(ctr_last) as u32;
+                                    daq_lost.fetch_add(ctr.wrapping_sub(ctr_last.wrapping_add(1)) as u64, Ordering::Relaxed);
+                                    daq_loss.lock().add_lost(ctr.wrapping_sub(ctr_last.wrapping_add(1)) as u64);
+
+                                }
+                                ctr_last = ctr;
+                                let pid = buf[i + 4];
+                                trace!("RX: i = {}, len = {}, pid = {}", i, len, pid,);
+                                match pid {
+                                    0xFF => {
+                                        // Command response
+                                        let response = &buf[(i + 4)..(i + 4 + len)];
+                                        trace!("receive_task: XCP response = {:?}", response);
+                                        tx_resp.send(response.to_vec()).await?;
+                                    }
+                                    0xFE => {
+                                        // Command error response
+                                        let response = &buf[(i + 4)..(i + 6)];
+                                        trace!("receive_task: XCP error response = {:?}", response);
+                                        tx_resp.send(response.to_vec()).await?;
+                                    }
+                                    0xFD => {
+                                        // Event
+                                        let event_code = buf[i + 5];
+                                        match event_code {
+                                            0x07 => { info!("receive_task: stop, SESSION_TERMINATDED"); return Err(Box::new(XcpError::new(ERROR_SESSION_TERMINATION,0)) as Box<dyn Error>); },
+                                            _ => warn!("xcp_receive: ignored XCP event = 0x{:0X}", event_code),
+                                        }
+
+                                    }
+                                    0xFC => {
+                                        // Service
+                                        let service_code = buf[i + 5];
+                                        if service_code == 0x01 {
+                                            decode_serv_text.decode(&buf[i + 6..i + len + 4]);
+                                        } else {
+                                            // Unknown PID
+                                            warn!(
+                                                "receive_task: ignored unknown service request code = 0x{:0X}",
+                                                service_code
+                                            );
+                                        }
+                                    }
+                                    _ => {
+                                        // Check that we got a DAQ control
+                                        if let Some(c) = &task_control {
+
+                                            // Handle DAQ data if DAQ running
+                                            if c.running {
+                                                daq_loss.lock().record(&buf[i + 4..i + 4 + len]);
+                                                let mut m = decode_daq.lock(); // @@@@ TODO Unnecessary mutex ?????
+                                                m.decode(ctr_lost, &buf[i + 4..i + 4 + len]);
+                                                ctr_lost = 0;
+                                            } // running
+                                        }
+                                    }
+                                } // match pid
+                                i = i + len + 4;
+                            } // while message in packet
+
+
+                        }
+                        Err(e) => {
+                            // Handle the error from recv_from/read
+                            warn!("receive_task: stop, socket error {}",e);
+                            return Err(Box::new(XcpError::new(ERROR_TL_HEADER,0)) as Box<dyn Error>);
+                        }
+                    }
+                } // socket receive
+            }
+        } // loop
+    }
+
+    //------------------------------------------------------------------------
+    // XCP command service
+    // Send a XCP command and wait for the response
+    // @@@@ Must be &mut self because of the mpsc::Receiver
+    async fn send_command(&mut self, cmd_bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.send_command_only(cmd_bytes).await?;
+        self.receive_response(cmd_bytes[4]).await
+    }
+
+    // Send a typed XCP command and wait for the response, the command is logged with its parameter names
+    async fn send_typed<C: XcpTypedCommand>(&mut self, cmd: &C) -> Result<Vec<u8>, Box<dyn Error>> {
+        let res = self.send_command(&cmd.to_packet()).await;
+        match &res {
+            Ok(data) => trace!("xcp_command: {} -> {:?}", cmd, data),
+            Err(e) => debug!("xcp_command: {} -> {}", cmd, e),
+        }
+        res
+    }
+
+    // Send a XCP command without waiting for the response, for block transfers and interleaved mode
+    async fn send_command_only(&mut self, cmd_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        let socket = self.socket.as_ref().unwrap();
+        socket.send_to(cmd_bytes, self.dest_addr).await?;
+        match format_command(&cmd_bytes[4..]) {
+            Some(cmd) => debug!("xcp_command: sent {}", cmd),
+            None => debug!("xcp_command: sent command = {:?}", cmd_bytes),
+        }
+        Ok(())
+    }
+
+    // Wait for the next response of the command with command code command_code
+    async fn receive_response(&mut self, command_code: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+        // Wait for response channel with timeout
+        let res = timeout(CMD_TIMEOUT, self.rx_cmd_resp.as_mut().unwrap().recv()).await; // rx channel
+        match res {
+            Ok(res) => {
+                match res {
+                    Some(data) => {
+                        trace!("xcp_command: res = {:?}", data);
+                        match data[0] {
+                            0xFF => {
+                                // XCP positive response
+                                Ok(data)
+                            }
+                            0xFE => {
+                                // XCP negative response, return error code with XcpError
+                                Err(Box::new(XcpError::new(data[1], command_code)) as Box<dyn Error>)
+                            }
+                            _ => {
+                                panic!("xcp_command: bug in receive_task");
+                            }
+                        }
+                    }
+                    None => {
+                        // Empty response, channel has been closed because receive task terminated
+                        info!("xcp_command: receive_task terminated");
+                        Err(Box::new(XcpError::new(ERROR_TASK_TERMINATED, command_code)) as Box<dyn Error>)
+                    }
+                }
+            }
+            Err(_) => {
+                // Timeout, return with XcpError
+                Err(Box::new(XcpError::new(ERROR_CMD_TIMEOUT, command_code)) as Box<dyn Error>)
+            }
+        }
+    }
+
+    //------------------------------------------------------------------------
+    // Connect/disconnect to server, create receive task
+
+    pub async fn connect<D, T>(&mut self, connect_mode: u8, daq_decoder: Arc<Mutex<D>>, text_decoder: T) -> Result<(), Box<dyn Error>>
+    where
+        T: XcpTextDecoder + Send + 'static,
+        D: XcpDaqDecoder + Send + 'static,
+    {
+        // Create socket
+        let socket = if let Some(config) = &self.can {
+            XcpSocket::Can(Arc::new(XcpCanSocket::open(config)?))
+        } else if self.tcp {
+            // Create TCP socket and connect
+            let stream = TcpStream::connect(self.dest_addr).await?;
+            debug!("TCP connection established to {:?}", stream.peer_addr()?);
+            debug!("TCP local address: {:?}", stream.local_addr()?);
+            // Give the server a moment to set up the connection
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            XcpSocket::Tcp(Arc::new(stream))
+        } else {
+            // Create UDP socket
+            let udp_socket = UdpSocket::bind(self.bind_addr).await?;
+            XcpSocket::Udp(Arc::new(udp_socket))
+        };
+        self.socket = Some(socket);
+
+        // Spawn a rx task to handle incoming data
+        // Hand over the DAQ decoder and the text decoder
+        // clone the socket
+        // Create channels for command responses and DAQ state control
+        debug!("Start RX task");
+        {
+            let socket = match &self.socket {
+                Some(XcpSocket::Udp(udp_sock)) => XcpSocket::Udp(Arc::clone(udp_sock)),
+                Some(XcpSocket::Tcp(tcp_stream)) => XcpSocket::Tcp(Arc::clone(tcp_stream)),
+                Some(XcpSocket::Can(can_socket)) => XcpSocket::Can(Arc::clone(can_socket)),
+                None => unreachable!(),
+            };
+            let (tx_resp, rx_resp) = mpsc::channel(1);
+            self.rx_cmd_resp = Some(rx_resp); // rx XCP command response channel
+            let (tx_daq, rx_daq) = mpsc::channel(3);
+            self.tx_task_control = Some(tx_daq); // tx XCP DAQ control channel
+            let daq_decoder_clone = Arc::clone(&daq_decoder);
+            let daq_lost = Arc::clone(&self.daq_lost);
+            let daq_loss = Arc::clone(&self.daq_loss);
+            self.receive_task = Some(tokio::spawn(async move {
+                let _res = XcpClient::receive_task(socket, tx_resp, rx_daq, text_decoder, daq_decoder_clone, daq_lost, daq_loss).await;
+            }));
+            tokio::time::sleep(Duration::from_millis(100)).await; // wait for the receive task to start
+        }
+
+        // Connect
+        debug!("XCP CONNECT");
+        self.capabilities = XcpCapabilities::new();
+        self.daq_lost.store(0, Ordering::Relaxed);
+        let data = self.send_typed(&Connect { mode: connect_mode }).await?;
+        check_response_len(&data, 8, CC_CONNECT)?;
+        let resources = data[1];
+        let comm_mode_basic = data[2];
+        let max_cto_size: u8 = data[3];
+        let max_dto_size: u16 = (data[4] as u16) | ((data[5] as u16) << 8);
+        let protocol_version: u8 = data[6];
+        let transport_layer_version: u8 = data[7];
+        self.resources = resources;
+        self.comm_mode_basic = comm_mode_basic;
+        self.max_cto_size = max_cto_size;
+        self.max_dto_size = max_dto_size;
+        self.protocol_version = protocol_version as u16;
+        self.transport_layer_version = transport_layer_version as u16;
+        debug!(
+            "XCP CONNECT -> resources=0x{:02X} comm_mode_basic=0x{:02X} max_cto_size={} max_dto_size={} protocol_version=0x{:02X} transport_layer_version=0x{:02X}",
+            resources, comm_mode_basic, max_cto_size, max_dto_size, protocol_version, transport_layer_version
+        );
+
+        // Get version info
+        // Optional, if not supported, the major versions from CONNECT are used
+        match self
+            .send_command(XcpCommandBuilder::new(CC_GET_VERSION).add_u8(0).build())
+            .await
+            .and_then(|data| check_response_len(&data, 6, CC_GET_VERSION).map(|_| data))
+        {
+            Ok(data) => {
+                self.protocol_version = (data[2] as u16) << 8 | data[3] as u16;
+                self.transport_layer_version = (data[4] as u16) << 8 | data[5] as u16;
+                debug!(
+                    "XCP GET_VERSION -> protocol_version=0x{:04X} transport_layer_version=0x{:04X}",
+                    self.protocol_version, self.transport_layer_version
+                );
+            }
+            Err(e) => {
+                self.protocol_version <<= 8;
+                self.transport_layer_version <<= 8;
+                self.capabilities.get_version = false;
+                self.capabilities.degrade(format!("GET_VERSION failed ({}), using major versions from CONNECT", e));
+            }
+        }
+
+        // Get comm mode info
+        if self.comm_mode_basic & CMB_OPTIONAL != 0 {
+            let data = self.send_command(XcpCommandBuilder::new(CC_GET_COMM_MODE_INFO).add_u8(0).build()).await?;
+            check_response_len(&data, 8, CC_GET_COMM_MODE_INFO)?;
+            self.comm_mode_optional = data[2];
+            self.max_bs = data[4];
+            self.min_st = data[5];
+            self.queue_size = data[6];
+            self.driver_version = data[7];
+            debug!(
+                "XCP GET_COMM_MODE_INFO -> comm_mode_optional=0x{:02X} max_bs={} min_st={} queue_size={} driver_version=0x{:02X}",
+                self.comm_mode_optional, self.max_bs, self.min_st, self.queue_size, self.driver_version
+            );
+        }
+
+        // Get calibration page count and freeze support
+        let res = self
+            .send_command(XcpCommandBuilder::new(CC_GET_PAGE_PROCESSOR_INFO).add_u8(0).build())
+            .await
+            .and_then(|data| check_response_len(&data, 3, CC_GET_PAGE_PROCESSOR_INFO).map(|_| data));
+        match res {
+            Ok(data) => {
+                self.max_segments = data[1];
+                self.freeze_supported = (data[2] & 0x01) != 0;
+            }
+            Err(e) => {
+                if e.is::<XcpError>() {
+                    if e.downcast_ref::<XcpError>().unwrap().get_error_code() != CRC_CMD_UNKNOWN {
+                        warn!("GET_PAGE_PROCESSOR_INFO failed: {}", e);
+                    } else {
+                        info!("GET_PAGE_PROCESSOR_INFO not supported by server");
+                    }
+                }
+
+                self.max_segments = 0;
+                self.freeze_supported = false;
+            }
+        }
+
+        // Get DAQ header size and event count
+        self.get_daq_processor_info().await?;
+
+        // let data = self.send_command(XcpCommandBuilder::new(CC_GET_DAQ_PROCESSOR_INFO).add_u8(0).build()).await?;
+        // self.max_events = (data[4] as u16) | ((data[5] as u16) << 8);
+
+        // Notify the rx task
+        self.task_control.connected = true; // the task will end, when it gets connected = false over the XcpControl channel
+        self.task_control.running = false;
+        self.tx_task_control.as_ref().unwrap().send(self.task_control).await.unwrap();
+
+        assert!(self.is_connected());
+
+        // Initialize DAQ clock
+        // Set 64 bit response format for GET_DAQ_CLOCK, fall back to the legacy 32 bit response format
+        if let Err(e) = self.time_correlation_properties().await {
+            self.capabilities.time_correlation = false;
+            self.capabilities.degrade(format!("TIME_CORRELATION_PROPERTIES failed ({}), GET_DAQ_CLOCK is 32 bit", e));
+        }
+        self.timestamp_resolution_ns = self.get_daq_resolution_info().await?;
+
+        // Set the DAQ decoder
+        {
+            let mut d = daq_decoder.lock();
+            d.set_daq_properties(self.timestamp_resolution_ns, self.daq_header_size);
+            d.set_daq_timestamp_size(self.capabilities.daq_timestamp_size);
+        }
+        if self.capabilities.is_degraded() {
+            info!("XCP server connected with {} degraded capabilities", self.capabilities.get_degraded().len());
+        }
+
+        // Keep the the DAQ decoder for measurement start
+        self.daq_decoder = Some(daq_decoder);
+
+        Ok(())
+    }
+
+    pub fn get_daq_decoder(&mut self) -> Option<Arc<Mutex<dyn XcpDaqDecoder>>> {
+        self.daq_decoder.as_ref().map(|d| d.clone())
+    }
+
+    //------------------------------------------------------------------------
+    pub async fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        // Ignore errors and assume disconnected
+
+        // Disconnect
+        let _ = self.send_command(XcpCommandBuilder::new(CC_DISCONNECT).add_u8(0).build()).await;
+
+        // Stop XCP client task
+        self.task_control.connected = false;
+        self.task_control.running = false;
+        let _ = self.tx_task_control.as_ref().unwrap().send(self.task_control).await;
+
+        // Make sure receive_task has terminated
+        if let Some(receive_task) = self.receive_task.take() {
+            let res = receive_task.await;
+            if let Err(e) = res {
+                error!("{:?}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------
+    pub fn is_connected(&mut self) -> bool {
+        self.task_control.connected
+    }
+
+    //------------------------------------------------------------------------
+    // Get server identification
+    // GET_ID responds with mode 1 and the data in the response, or with mode 0 and the MTA set to the data for UPLOAD
+    // Size 0 means the identification type is not available on the server
+
+    // Send GET_ID, returns the data in the response in mode 1, None in mode 0, and the size of the data
+    async fn get_id_response(&mut self, id_type: u8) -> Result<(u32, Option<Vec<u8>>), Box<dyn Error>> {
+        let data = self.send_typed(&GetId { id_type }).await?;
+        if data.len() < 8 {
+            error!("GET_ID type={} -> response too short", id_type);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>);
+        }
+        let mode = data[1] & 0x01; // 0 = data by upload, 1 = data in response
+        let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        debug!("GET_ID type={} -> mode={} size={}", id_type, mode, size);
+        if mode == 0 {
+            return Ok((size, None));
+        }
+        match data.get(8..8 + size as usize) {
+            Some(bytes) => Ok((size, Some(bytes.to_vec()))),
+            None => {
+                error!("GET_ID type={} -> response shorter than size {}", id_type, size);
+                Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>)
+            }
+        }
+    }
+
+    // Get server identification
+    // Returns (size, name) where name is only set if the server returned the name in the response or it fits into one upload,
+    // otherwise the caller must do an upload to get the data
+    pub async fn get_id(&mut self, id_type: u8) -> Result<(u32, Option<String>), Box<dyn Error>> {
+        let (size, data) = self.get_id_response(id_type).await?;
+        let data = match data {
+            Some(data) => data,
+            // Upload the result immediately, if size fits in one upload command
+            None if size > 0 && size < u32::from(self.max_cto_size) => {
+                let data = self.upload(u8::try_from(size)?).await?;
+                data[1..=(size as usize)].to_vec()
+            }
+            // Return size for later upload
+            None => return Ok((size, None)),
+        };
+        match String::from_utf8(data) {
+            Ok(name) => {
+                debug!("  -> text result = {}", name);
+                Ok((0, Some(name)))
+            }
+            Err(e) => {
+                error!("GET_ID type={} -> invalid string {:?}", id_type, e.as_bytes());
+                Err(Box::new(XcpError::new(CRC_CMD_SYNTAX, CC_GET_ID)) as Box<dyn Error>)
+            }
+        }
+    }
+
+    /// Get the identification data of id_type (one of IDT_xxx), in the response or by upload, empty if not available
+    pub async fn get_id_data(&mut self, id_type: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (size, data) = self.get_id_response(id_type).await?;
+        if let Some(data) = data {
+            return Ok(data);
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        let mut remaining = size;
+        while remaining > 0 {
+            let n = if remaining >= u32::from(self.max_cto_size) {
+                self.max_cto_size - 1
+            } else {
+                u8::try_from(remaining)?
+            };
+            remaining -= u32::from(n);
+            let res = self.upload(n).await?;
+            trace!("xcp_client.upload: {} bytes = {:?}", res.len(), res);
+            data.extend_from_slice(&res[1..=n as usize]);
+        }
+        Ok(data)
+    }
+
+    /// Get the identification text of id_type (one of IDT_xxx), None if not available
+    pub async fn get_id_text(&mut self, id_type: u8) -> Result<Option<String>, Box<dyn Error>> {
+        let data = self.get_id_data(id_type).await?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8(data)?))
+    }
+
+    //------------------------------------------------------------------------
+    // Execute a XCP command with no other parameters
+    pub async fn command(&mut self, command_code: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.send_command(XcpCommandBuilder::new(command_code).build()).await
+    }
+
+    //------------------------------------------------------------------------
+    // calibration segment and page control
+
+    pub async fn get_ecu_page(&mut self, segment: u8) -> Result<u8, Box<dyn Error>> {
+        let mode = CAL_PAGE_MODE_ECU;
+        let data = self.send_typed(&GetCalPage { mode, segment }).await?;
+        let page = if data[3] != 0 { 1 } else { 0 };
+        Ok(page)
+    }
+
+    pub async fn get_xcp_page(&mut self, segment: u8) -> Result<u8, Box<dyn Error>> {
+        let mode = CAL_PAGE_MODE_XCP;
+        let data = self.send_typed(&GetCalPage { mode, segment }).await?;
+        let page = if data[3] != 0 { 1 } else { 0 };
+        Ok(page)
+    }
+
+    pub async fn set_ecu_page(&mut self, page: u8) -> Result<(), Box<dyn Error>> {
+        let mode = CAL_PAGE_MODE_ECU | 0x80; // All segments
+        self.send_typed(&SetCalPage { mode, segment: 0, page }).await?;
+        Ok(())
+    }
+
+    pub async fn set_xcp_page(&mut self, page: u8) -> Result<(), Box<dyn Error>> {
+        let mode = CAL_PAGE_MODE_XCP | 0x80; // All segments
+        self.send_typed(&SetCalPage { mode, segment: 0, page }).await?;
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------
+    // XCP memory access services (calibration and polling of measurement values)
+
+    pub async fn set_mta(&mut self, addr_ext: u8, addr: u32) -> Result<(), Box<dyn Error>> {
+        trace!("set_mta addr={}:{:08X}", addr_ext, addr);
+        self.send_typed(&SetMta { addr_ext, addr }).await?;
+        Ok(())
+    }
+
+    pub async fn short_download(&mut self, addr: u32, ext: u8, data_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        let len: u8 = data_bytes.len().try_into().unwrap();
+        trace!("short_download addr={}:{:08X},{} data={:?}", ext, addr, len, data_bytes);
+        self.send_command(
+            XcpCommandBuilder::new(CC_SHORT_DOWNLOAD)
+                .add_u8(len)
+                .add_u8(0)
+                .add_u8(ext)
+                .add_u32(addr)
+                .add_u8_slice(data_bytes)
+                .build(),
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn short_upload(&mut self, addr: u32, ext: u8, size: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+        trace!("short_upload addr={}:{:08X},{}", ext, addr, size);
+        let data = self.send_typed(&ShortUpload { size, addr_ext: ext, addr }).await?;
+        Ok(data)
+    }
+
+    pub async fn upload(&mut self, size: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+        trace!("upload size={}", size);
+        let data = self.send_typed(&Upload { size }).await?;
+        Ok(data)
+    }
+
+    pub async fn download(&mut self, data_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        let n = data_bytes.len();
+        trace!("download len={}, data={:?}", n, data_bytes);
+        if n > (self.max_cto_size - 2) as usize {
+            return Err(Box::new(XcpError::new(CRC_CMD_SYNTAX, CC_DOWNLOAD)) as Box<dyn Error>);
+        }
+        self.send_command(XcpCommandBuilder::new(CC_DOWNLOAD).add_u8(n as u8).add_u8_slice(data_bytes).build())
+            .await?;
+        Ok(())
+    }
+    pub async fn modify_begin(&mut self) -> Result<(), Box<dyn Error>> {
+        trace!("modify_begin");
+        self.send_command(XcpCommandBuilder::new(CC_USER).add_u8(1).add_u8(0).add_u8(0).build()).await?;
+        Ok(())
+    }
+
+    pub async fn modify_end(&mut self) -> Result<(), Box<dyn Error>> {
+        trace!("modify_end");
+        self.send_command(XcpCommandBuilder::new(CC_USER).add_u8(2).add_u8(0).add_u8(0).build()).await?;
+        Ok(())
+    }
+
+    /// Get the number of lost DAQ packets since connect, detected from gaps in the transport layer counter
+    pub fn get_daq_lost(&self) -> u64 {
+        self.daq_lost.load(Ordering::Relaxed)
+    }
+
+    //------------------------------------------------------------------------
+    // XCP segment info services
+
+    /// Get segment info
+    pub async fn get_segment_info(&mut self, segment_number: u8) -> Result<(u8, u32, u16, String), Box<dyn Error>> {
+        //addr
+        let data = self
+            .send_typed(&GetSegmentInfo {
+                mode: 0,
+                segment: segment_number,
+                info: 0,
+                mapping_index: 0,
+            })
+            .await?;
+
+        let addr = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        // Length
+        let data = self
+            .send_typed(&GetSegmentInfo {
+                mode: 0,
+                segment: segment_number,
+                info: 1,
+                mapping_index: 0,
+            })
+            .await?;
+        let length = u32::from_le_bytes([data[4], data[5], data[6], data[7]]).try_into().unwrap();
+
+        // Name
+        let data = self
+            .send_typed(&GetSegmentInfo {
+                mode: 0,
+                segment: segment_number,
+                info: 2,
+                mapping_index: 0,
+            })
+            .await?;
+        let name_length: u8 = u32::from_le_bytes([data[4], data[5], data[6], data[7]]).try_into().unwrap();
+        let data = self.upload(name_length).await?;
+        let res = String::from_utf8(data[1..=(name_length as usize)].to_vec());
+        let name = match res {
+            Ok(name) => name,
+            Err(_) => {
+                return Err(Box::new(XcpError::new(CRC_CMD_SYNTAX, CC_GET_SEGMENT_INFO)) as Box<dyn Error>);
+            }
+        };
+
+        // Addr extension
+        let addr_ext: u8 = 0; // @@@@ Segment address extension not supported yet
+
+        Ok((addr_ext, addr, length, name))
+    }
+
+    /// Get page info
+    pub async fn get_page_info(&mut self, segment_number: u8, page_number: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+        let data = self
+            .send_typed(&GetPageInfo {
+                segment: segment_number,
+                page: page_number,
+            })
+            .await?;
+        Ok(data)
+    }
+
+    /// Get segment standard info (mode 1), number of pages, address extension, number of address mappings, compression and encryption method
+    pub async fn get_segment_standard_info(&mut self, segment_number: u8) -> Result<XcpSegmentInfo, Box<dyn Error>> {
+        let data = self
+            .send_typed(&GetSegmentInfo {
+                mode: 1,
+                segment: segment_number,
+                info: 0,
+                mapping_index: 0,
+            })
+            .await?;
+        if data.len() < 6 {
+            error!("GET_SEGMENT_INFO: standard info response of segment {} too short", segment_number);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_SEGMENT_INFO)) as Box<dyn Error>);
+        }
+        Ok(XcpSegmentInfo {
+            max_pages: data[1],
+            addr_ext: data[2],
+            max_mapping: data[3],
+            compression: data[4],
+            encryption: data[5],
+        })
+    }
+
+    /// Get segment mapping info (mode 2) of a mapping, info 0 is the source address, 1 the destination address, 2 the length
+    pub async fn get_segment_mapping_info(&mut self, segment_number: u8, mapping_index: u8, info: u8) -> Result<u32, Box<dyn Error>> {
+        let data = self
+            .send_typed(&GetSegmentInfo {
+                mode: 2,
+                segment: segment_number,
+                info,
+                mapping_index,
+            })
+            .await?;
+        if data.len() < 8 {
+            error!("GET_SEGMENT_INFO: mapping info response of segment {} too short", segment_number);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_SEGMENT_INFO)) as Box<dyn Error>);
+        }
+        Ok(u32::from_le_bytes([data[4], data[5], data[6], data[7]]))
+    }
+
+    /// Get the page properties and the init segment of a page
+    pub async fn get_page_properties(&mut self, segment_number: u8, page_number: u8) -> Result<XcpPageProperties, Box<dyn Error>> {
+        let data = self.get_page_info(segment_number, page_number).await?;
+        if data.len() < 3 {
+            error!("GET_PAGE_INFO: response of segment {} page {} too short", segment_number, page_number);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_PAGE_INFO)) as Box<dyn Error>);
+        }
+        Ok(XcpPageProperties {
+            properties: data[1],
+            init_segment: data[2],
+        })
+    }
+
+    /// Enable or disable the freeze mode of a segment, segments in freeze mode are frozen with store_cal
+    pub async fn set_freeze_mode(&mut self, segment_number: u8, enable: bool) -> Result<(), Box<dyn Error>> {
+        if !self.freeze_supported {
+            warn!("SET_SEGMENT_MODE: freeze mode not supported by the server");
+        }
+        let mode = if enable { SEGMENT_MODE_FREEZE } else { 0 };
+        self.send_typed(&SetSegmentMode { mode, segment: segment_number }).await?;
+        Ok(())
+    }
+
+    /// Check if the freeze mode of a segment is enabled
+    pub async fn get_freeze_mode(&mut self, segment_number: u8) -> Result<bool, Box<dyn Error>> {
+        let data = self.send_typed(&GetSegmentMode { segment: segment_number }).await?;
+        if data.len() < 3 {
+            error!("GET_SEGMENT_MODE: response of segment {} too short", segment_number);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_SEGMENT_MODE)) as Box<dyn Error>);
+        }
+        Ok((data[2] & SEGMENT_MODE_FREEZE) != 0)
+    }
+
+    /// Freeze all segments in freeze mode with SET_REQUEST STORE_CAL_REQ, the server persists their working page as reference page
+    pub async fn store_cal(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&SetRequest {
+            mode: SET_REQUEST_STORE_CAL_REQ,
+            session_configuration_id: 0,
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Copy a calibration page, page 0 is the working page, page 1 the reference page
+    /// Copying the reference page to the working page reinitializes the working page, copying the working page to the reference page freezes it
+    pub async fn copy_cal_page(&mut self, src_segment: u8, src_page: u8, dst_segment: u8, dst_page: u8) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&CopyCalPage {
+            src_segment,
+            src_page,
+            dst_segment,
+            dst_page,
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Re-initialize the working page of a segment, COPY_CAL_PAGE from the reference page (default page) to the working page
+    pub async fn init_working_page(&mut self, segment_number: u8) -> Result<(), Box<dyn Error>> {
+        self.copy_cal_page(segment_number, 1, segment_number, 0).await
+    }
+
+    /// Check if the server granted the CAL/PAG resource on CONNECT
+    /// Without CAL/PAG, the server rejects downloads with CRC_ACCESS_DENIED
+    pub fn is_cal_available(&self) -> bool {
+        self.resources & RM_CAL_PAG != 0
+    }
+
+    //------------------------------------------------------------------------
+    // XCP DAQ services
+
+    /// Get DAQ clock timestamp resolution in ns
+    pub async fn get_daq_processor_info(&mut self) -> Result<(), Box<dyn Error>> {
+        let data = self.send_typed(&GetDaqProcessorInfo {}).await?;
+        let mut c = Cursor::new(&data[1..]);
+
+        let daq_properties = ReadBytesExt::read_u8(&mut c)?;
+        if (daq_properties & 0x10) == 0 {
+            self.capabilities.daq_timestamps = false;
+            self.capabilities.daq_timestamp_size = 0;
+            self.capabilities.degrade("DAQ timestamps not available, measurement without timestamps".to_string());
+        }
+        let max_daq = ReadBytesExt::read_u16::<LittleEndian>(&mut c)?;
+        self.max_events = ReadBytesExt::read_u16::<LittleEndian>(&mut c)?;
+        let min_daq = ReadBytesExt::read_u8(&mut c)?;
+        let daq_key_byte = ReadBytesExt::read_u8(&mut c)?;
+        self.daq_header_size = (daq_key_byte >> 6) + 1;
+        if self.daq_header_size == 1 {
+            self.capabilities.daq = false;
+            self.capabilities.degrade("DAQ header type absolute ODT number is not supported, DAQ disabled".to_string());
+        }
+
+        debug!(
+            "GET_DAQ_PROPERTIES daq_properties = 0x{:0X}, max_daq = {}, max_event = {}, min_daq = {}, daq_key_byte = 0x{:0X} (header_size={})",
+            daq_properties, max_daq, self.max_events, min_daq, daq_key_byte, self.daq_header_size
+        );
+        Ok(())
+    }
+
+    pub async fn get_daq_event_info(&mut self, event_id: u16) -> Result<String, Box<dyn Error>> {
+        Ok(self.get_event_info(event_id).await?.name)
+    }
+
+    /// Get name, direction, consistency, cycle time and priority of an event with GET_DAQ_EVENT_INFO
+    pub async fn get_event_info(&mut self, event_id: u16) -> Result<XcpEventInfo, Box<dyn Error>> {
+        let data = self.send_typed(&GetDaqEventInfo { event: event_id }).await?;
+        let (properties, max_daq_list, event_name_len, time_cycle, time_unit, priority) = (data[1], data[2], data[3], data[4], data[5], data[6]);
+        let data = self.upload(event_name_len).await?;
+        let res = String::from_utf8(data[1..=(event_name_len as usize)].to_vec());
+        match res {
+            Ok(name) => Ok(XcpEventInfo {
+                name,
+                properties,
+                max_daq_list,
+                time_cycle,
+                time_unit,
+                priority,
+            }),
+            Err(_) => Err(Box::new(XcpError::new(CRC_CMD_SYNTAX, CC_GET_DAQ_EVENT_INFO)) as Box<dyn Error>),
+        }
+    }
+
+    async fn free_daq(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&FreeDaq {}).await?;
+        Ok(())
+    }
+
+    async fn alloc_daq(&mut self, count: u16) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&AllocDaq { daq_count: count }).await?;
+        Ok(())
+    }
+
+    async fn alloc_odt(&mut self, daq: u16, odt: u8) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&AllocOdt { daq, odt_count: odt }).await?;
+        Ok(())
+    }
+
+    async fn alloc_odt_entries(&mut self, daq: u16, odt: u8, count: u8) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&AllocOdtEntry { daq, odt, entry_count: count }).await?;
+        Ok(())
+    }
+
+    async fn set_daq_ptr(&mut self, daq: u16, odt: u8, idx: u8) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&SetDaqPtr { daq, odt, entry: idx }).await?;
+        Ok(())
+    }
+
+    async fn write_daq(&mut self, ext: u8, addr: u32, len: u8) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&WriteDaq {
+            bit_offset: 0,
+            size: len,
+            addr_ext: ext,
+            addr,
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn set_daq_list_mode(&mut self, daq: u16, eventchannel: u16) -> Result<(), Box<dyn Error>> {
+        const XCP_DAQ_MODE_TIMESTAMP: u8 = 0x10; // Timestamp always on, no other mode supported by XCPlite
+        let mode: u8 = if self.capabilities.daq_timestamps { XCP_DAQ_MODE_TIMESTAMP } else { 0x00 };
+        let priority = 0x00; // Always use priority 0, no DAQ list flush for specific events, priorization supported by XCPlite
+        self.send_typed(&SetDaqListMode {
+            mode,
+            daq,
+            event: eventchannel,
+            prescaler: 1,
+            priority,
+        })
+        .await?;
+        Ok(())
+    }
+
+    // Select DAQ list
+    async fn select_daq_list(&mut self, daq: u16) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&StartStopDaqList { mode: 2, daq }).await?;
+        Ok(())
+    }
+
+    // Prepare, start selected, stop all
+    async fn prepare_selected_daq_lists(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&StartStopSynch { mode: 3, /* prepare selected */ }).await?;
+        Ok(())
+    }
+    async fn start_selected_daq_lists(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&StartStopSynch { mode: 1, /* start selected */ }).await?;
+        Ok(())
+    }
+    async fn stop_all_daq_lists(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&StartStopSynch { mode: 0 }).await?;
+        Ok(())
+    }
+
+    //-------------------------------------------------------------------------------------------------
+    // Clock
+
+    // CC_TIME_CORRELATION_PROPERTIES
+    async fn time_correlation_properties(&mut self) -> Result<(), Box<dyn Error>> {
+        let request: u8 = 2; // set responce format to SERVER_CONFIG_RESPONSE_FMT_ADVANCED
+        let properties: u8 = 0;
+        let cluster_id: u16 = 0;
+        let _data = self
+            .send_typed(&TimeCorrelationProperties {
+                set_properties: request,
+                get_properties_request: properties,
+                cluster_id,
+            })
+            .await?;
+        debug!("TIME_CORRELATION_PROPERIES set response format to SERVER_CONFIG_RESPONSE_FMT_ADVANCED");
+        Ok(())
+    }
+
+    /// Get DAQ clock timestamp resolution in ns
+    pub async fn get_daq_resolution_info(&mut self) -> Result<u64, Box<dyn Error>> {
+        let data = self.send_typed(&GetDaqResolutionInfo {}).await?;
+        let mut c = Cursor::new(&data[1..]);
+
+        let granularity_daq = ReadBytesExt::read_u8(&mut c)?;
+        let max_size_daq = ReadBytesExt::read_u8(&mut c)?;
+        let _granularity_stim = ReadBytesExt::read_u8(&mut c)?;
+        let _max_size_stim = ReadBytesExt::read_u8(&mut c)?;
+        let timestamp_mode = ReadBytesExt::read_u8(&mut c)?;
+        let timestamp_ticks = ReadBytesExt::read_u16::<LittleEndian>(&mut c)?;
+
+        if granularity_daq != 0x01 {
+            self.capabilities.degrade(format!(
+                "DAQ granularity is {}, only ODT entries with a multiple of this size are possible",
+                granularity_daq
+            ));
+        }
+        self.capabilities.daq_granularity = granularity_daq;
+        self.capabilities.daq_max_odt_entry_size = max_size_daq;
+        if self.capabilities.daq_timestamps {
+            let timestamp_size = timestamp_mode & 0x07;
+            if timestamp_size != 1 && timestamp_size != 2 && timestamp_size != 4 {
+                self.capabilities.daq_timestamps = false;
+                self.capabilities.daq_timestamp_size = 0;
+                self.capabilities
+                    .degrade(format!("DAQ timestamp size {} not supported, measurement without timestamps", timestamp_size));
+            } else {
+                if timestamp_size != 4 {
+                    self.capabilities
+                        .degrade(format!("DAQ timestamp size is {} byte, timestamps wrap around more frequently", timestamp_size));
+                }
+                self.capabilities.daq_timestamp_size = timestamp_size;
+            }
+            self.capabilities.daq_timestamp_fixed = timestamp_mode & 0x08 == 0x08;
+        }
+
+        // Calculate timestamp resolution in ns per tick
+        let mut timestamp_unit = timestamp_mode >> 4; // 1ns=0, 10ns=1, 100ns=2, 1us=3, 10us=4, 100us=5, 1ms=6, 10ms=7, 100ms=8, 1s=9
+        let mut timestamp_resolution_ns: u64 = timestamp_ticks as u64;
+        while timestamp_unit > 0 {
+            timestamp_resolution_ns *= 10;
+            timestamp_unit -= 1;
+        }
+        self.timestamp_resolution_ns = timestamp_resolution_ns;
+
+        debug!(
+            "GET_DAQ_RESOLUTION_INFO granularity_daq={} max_size_daq={} timestamp_mode={} timestamp_resolution={}ns",
+            granularity_daq, max_size_daq, timestamp_mode, timestamp_resolution_ns
+        );
+        Ok(timestamp_resolution_ns)
+    }
+
+    // Get DAQ clock raw value in ticks of timestamp_resolution ns
+    async fn get_daq_clock_raw(&mut self) -> Result<u64, Box<dyn Error>> {
+        let data = self.send_typed(&GetDaqClock {}).await?;
+        let mut c = Cursor::new(&data[2..]);
+
+        // Trigger info and payload format
+        // TIME_OF_TS_SAMPLING: (trigger_info >> 3) & 0x03 : 3-reception, 2-transmission, 1-low jitter, 0-during commend processing
+        // TRIGGER_INITIATOR:   (trigger_info >> 0) & 0x07 : not relevant for GET_DAQ_CLOCK
+        // FMT_XCP_SLV: (payload_fmt >> 0) & 0x03 let payload_fmt = data[3];
+        let trigger_info = ReadBytesExt::read_u8(&mut c)?;
+        let payload_fmt = ReadBytesExt::read_u8(&mut c)?;
+
+        // Timestamp
+        let timestamp64 = if !self.capabilities.time_correlation {
+            // Legacy response format, 32 bit slave clock at offset 4
+            let mut c = Cursor::new(&data[4..]);
+            ReadBytesExt::read_u32::<LittleEndian>(&mut c)? as u64
+        } else if payload_fmt == 1 {
+            // 32 bit slave clock
+            ReadBytesExt::read_u32::<LittleEndian>(&mut c)? as u64
+        } else if payload_fmt == 2 {
+            // 64 bit slave clock
+            ReadBytesExt::read_u64::<LittleEndian>(&mut c)?
+        } else {
+            return Err(Box::new(XcpError::new(CRC_OUT_OF_RANGE, CC_GET_DAQ_CLOCK)) as Box<dyn Error>);
+        };
+
+        trace!("GET_DAQ_CLOCK trigger_info=0x{:2X}, payload_fmt=0x{:2X} time={}", trigger_info, payload_fmt, timestamp64);
+        Ok(timestamp64)
+    }
+
+    /// Get DAQ clock in ns
+    pub async fn get_daq_clock(&mut self) -> Result<u64, Box<dyn Error>> {
+        let timestamp64 = self.get_daq_clock_raw().await?;
+        let timestamp_ns = timestamp64 * self.timestamp_resolution_ns;
+        Ok(timestamp_ns)
+    }
+
+    //-------------------------------------------------------------------------------------------------
+    // ELF upload
+    // Use verify_elf_signature to check an ELF file against the target
+
+    pub async fn upload_elf_file<P: AsRef<std::path::Path>>(&mut self, elf_path: &P) -> Result<(), Box<dyn Error>> {
+        // Send XCP GET_ID IDT_VECTOR_ELF_UPLOAD command and upload the file
+        let data = self.get_id_data(IDT_VECTOR_ELF_UPLOAD).await?;
+        if data.is_empty() {
+            error!("ELF file not available, GET_ID returned size 0");
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>);
+        }
+
+        // Check if the ELF file already exists and warn about overwriting
+        if elf_path.as_ref().exists() {
+            warn!("ELF file {} already exists, overwriting", elf_path.as_ref().display());
+        }
+
+        info!("Upload ELF to {}", elf_path.as_ref().display());
+        std::fs::write(elf_path, &data)?;
+        debug!("ELF upload completed, {} bytes loaded", data.len());
+
+        Ok(())
+    }
+
+    //-------------------------------------------------------------------------------------------------
+    // A2L upload
+    // Use A2lManager to upload and load the A2L into the registry
+
+    // Upload the A2L file with GET_ID IDT_ASAM_UPLOAD into a2l_path
+    pub async fn upload_a2l_file<P: AsRef<std::path::Path>>(&mut self, a2l_path: &P) -> Result<(), Box<dyn Error>> {
+        // Send XCP GET_ID 4 command and upload the file
+        let data = self.get_id_data(IDT_ASAM_UPLOAD).await?;
+        if data.is_empty() {
+            error!("A2L file not available, GET_ID 4 returned size 0");
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>);
+        }
+
+        // Check if the A2L file already exists and warn about overwriting
+        if a2l_path.as_ref().exists() {
+            warn!("A2L file {} already exists, overwriting", a2l_path.as_ref().display());
+        }
+
+        info!("Upload A2L to {}", a2l_path.as_ref().display());
+        std::fs::write(a2l_path, &data)?;
+        debug!("A2L upload completed, {} bytes loaded", data.len());
+
+        Ok(())
+    }
+
+    // Get the A2L name of the server with GET_ID IDT_ASAM_NAME, without extension
+    pub async fn get_a2l_name(&mut self) -> Result<String, Box<dyn Error>> {
+        match self.get_id(IDT_ASAM_NAME).await? {
+            (_, Some(name)) if !name.is_empty() => Ok(name),
+            _ => {
+                error!("A2L name not available, GET_ID IDT_ASAM_NAME returned no name");
+                Err(Box::new(XcpError::new(ERROR_GENERIC, CC_GET_ID)) as Box<dyn Error>)
+            }
+        }
+    }
+
+    /// Get the A2L file name with path on the server with GET_ID IDT_ASAM_PATH, None if not available
+    pub async fn get_a2l_path(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        self.get_id_text(IDT_ASAM_PATH).await
+    }
+
+    /// Get the URL of the A2L file with GET_ID IDT_ASAM_URL, None if not available
+    pub async fn get_a2l_url(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        self.get_id_text(IDT_ASAM_URL).await
+    }
+
+    pub fn get_epk(&self) -> Option<&str> {
+        self.registry.as_ref().map(|r| r.application.get_version())
+    }
+
+    //------------------------------------------------------------------------
+    // Get event and segment information from XCP server and add to registry
+
+    pub async fn get_event_segment_info(&mut self, reg: &mut xcp_registry::Registry) -> Result<(), Box<dyn Error>> {
+        info!("Reading event and segment information from connected XCP server:");
+
+        // Get event information
+        // STIM only events are marked, they are not measurable and have no measurement groups in the A2L
+        for i in 0..self.max_events {
+            let event_info = self.get_event_info(i).await?;
+            let direction = match (event_info.is_daq(), event_info.is_stim()) {
+                (true, true) => "DAQ_STIM",
+                (false, true) => "STIM",
+                _ => "DAQ",
+            };
+            info!(" Event {}: {} {} consistency={}", i, event_info.name, direction, event_info.get_consistency());
+            let mut event = McEvent::new(event_info.name.clone(), 0, i, 0);
+            event.stim = event_info.is_daq() && event_info.is_stim();
+            event.stim_only = !event_info.is_daq();
+            event.consistency = event_info.get_consistency();
+            reg.event_list.add_event(event).unwrap();
+        }
+
+        // Get segment information
+        let mut n = 0;
+        for i in 0..self.max_segments {
+            let (addr_ext, addr, length, name) = self.get_segment_info(i).await?;
+            info!(" Segment {}: {} addr={}:0x{:08X} length={} ", i, name, addr_ext, addr, length);
+
+            // Otherwise the EPK segment would be handled like a normal calibration segment with 2 pages
+            // Segment relative addressing is ignored, all addresses are treated as raw A2L addr_ext/addr
+            // Segment relative addressing would be reg.cal_seg_list.add_cal_seg(name, i as u16, length as u32).unwrap();
+            reg.cal_seg_list.add_cal_seg_by_addr(name, Some(n as u8), addr_ext, addr, length as u32).unwrap();
+
+            n += 1;
+        }
+
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------
+    // Registry
+    // Get a list available measurement and calibration object names from registry matching a regular expression
+
+    pub fn get_registry(&self) -> &xcp_registry::Registry {
+        self.registry.as_ref().unwrap()
+    }
+
+    // Names of typedef members are included, e.g. "cal_seg.test_ints.test_u8"
+    pub fn find_characteristics(&self, expr: &str) -> Vec<String> {
+        let registry = self.registry.as_ref().unwrap();
+        registry
+            .get_flattened_instance_list()
+            .find_instances_regex(expr, xcp_registry::McObjectType::Characteristic, None)
+    }
+
+    pub fn find_measurements(&self, expr: &str) -> Vec<String> {
+        let registry = self.registry.as_ref().unwrap();
+        registry
+            .get_flattened_instance_list()
+            .find_instances_regex(expr, xcp_registry::McObjectType::Measurement, None)
+    }
+
+    //------------------------------------------------------------------------
+    // XcpCalibrationObject, XcpCalibrationObjectHandle (index pointer to XcpCalibrationObject),
+    // XcpXcpCalibrationObjectHandle is assumed immutable and the actual value is cached
+
+    pub fn get_calibration_object(&self, handle: XcpCalibrationObjectHandle) -> &XcpClientCalibrationObject {
+        &self.calibration_object_list[handle.0]
+    }
+
+    /// Create a calibration object by name from the registry and upload its current value from the XCP server
+    /// name may be a regular expression matching exactly one characteristic
+    pub async fn create_calibration_object(&mut self, name: &str) -> Result<XcpCalibrationObjectHandle, Box<dyn Error>> {
+        let registry = self.registry.as_ref().unwrap();
+        // Typedef members and array elements are resolved by name, e.g. "cal_seg.test_ints.test_u8" or "cal_seg.curve._3"
+        match registry.resolve_instance(name, xcp_registry::McObjectType::Characteristic, None) {
+            None => {
+                error!("Characteristic {} not found", name);
+                Err(Box::new(XcpError::new(ERROR_NOT_FOUND, 0)) as Box<dyn Error>)
+            }
+            Some(instance) => {
+                let (ext, addr) = instance.get_address().get_a2l_addr(registry);
+                let a2l_addr: A2lAddr = A2lAddr { ext, addr, event: None };
+                // Typedef instances and blobs are a single blob element, the size of a blob is unknown
+                let (size, dim) = match instance.value_type() {
+                    McValueType::TypeDef(typedef_name) => (registry.typedef_list.find_typedef(typedef_name).map_or(0, |t| t.size), [1, 1]),
+                    McValueType::Blob(_) => (0, [1, 1]),
+                    _ => (instance.value_size(), instance.get_dim_type().get_dim()),
+                };
+                let a2l_type: A2lType = A2lType {
+                    size,
+                    encoding: instance.value_type().into(),
+                };
+                let a2l_limits: A2lLimits = A2lLimits {
+                    lower: instance.get_min().unwrap_or(f64::MIN),
+                    upper: instance.get_max().unwrap_or(f64::MAX),
+                };
+                let mut o = XcpClientCalibrationObject::new(name, a2l_addr, a2l_type, a2l_limits);
+                o.dim = dim;
+                let size = o.get_size();
+                if self.is_connected() && size > 0 {
+                    let Ok(size) = u8::try_from(size) else {
+                        error!("{}: size {} not supported, xcp_client currently supports only <256 byte values", name, size);
+                        return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+                    };
+                    let resp = self.short_upload(o.a2l_addr.addr, o.a2l_addr.ext, size).await?;
+                    o.value = resp[1..=size as usize].to_vec();
+                    trace!("upload {}: addr = {:?} type = {:?} limit={:?} value={:?}\n", name, a2l_addr, a2l_type, a2l_limits, o.value);
+                }
+                self.calibration_object_list.push(o);
+                Ok(XcpCalibrationObjectHandle(self.calibration_object_list.len() - 1))
+            }
+        }
+    }
+
+    /// Write a scalar calibration object, the value must be within the A2L limits
+    /// Arrays, typedef instances and blobs are refused with ERROR_TYPE_MISMATCH
+    pub async fn set_value_u64(&mut self, handle: XcpCalibrationObjectHandle, value: u64) -> Result<(), Box<dyn Error>> {
+        let obj = &self.calibration_object_list[handle.0];
+        obj.check_scalar_write(u64_to_limit_f64(value))?;
+        let size: usize = obj.get_type.size;
+        self.download_value(handle, &value.to_le_bytes()[0..size]).await
+    }
+    pub async fn set_value_i64(&mut self, handle: XcpCalibrationObjectHandle, value: i64) -> Result<(), Box<dyn Error>> {
+        let obj = &self.calibration_object_list[handle.0];
+        obj.check_scalar_write(i64_to_limit_f64(value))?;
+        let size: usize = obj.get_type.size;
+        self.download_value(handle, &value.to_le_bytes()[0..size]).await
+    }
+    pub async fn set_value_f64(&mut self, handle: XcpCalibrationObjectHandle, value: f64) -> Result<(), Box<dyn Error>> {
+        let obj = &self.calibration_object_list[handle.0];
+        obj.check_scalar_write(value)?;
+        let Some(bytes) = f64_to_value(obj.get_type, value) else {
+            error!("set_value_f64: unsupported type {:?}", obj.get_type);
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+        };
+        self.download_value(handle, &bytes).await
+    }
+
+    /// Write element index (row major, index = iy * x_dim + ix) of an array calibration object, the value must be within the A2L limits
+    /// The change is recorded in the calibration history with the element name (name._ix or name._iy_ix)
+    pub async fn set_element_f64(&mut self, handle: XcpCalibrationObjectHandle, index: usize, value: f64) -> Result<(), Box<dyn Error>> {
+        let obj = &self.calibration_object_list[handle.0];
+        obj.check_write(index, value)?;
+        let Some(bytes) = f64_to_value(obj.get_type, value) else {
+            error!("set_element_f64: unsupported type {:?}", obj.get_type);
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+        };
+        let offset = index * obj.get_type.size;
+        let entry = XcpCalibrationHistoryEntry {
+            name: obj.get_element_name(index),
+            a2l_addr: A2lAddr {
+                addr: obj.a2l_addr.addr + u32::try_from(offset)?,
+                ..obj.a2l_addr
+            },
+            a2l_type: obj.get_type,
+            before: obj.value.get(offset..offset + bytes.len()).map_or(Vec::new(), <[u8]>::to_vec),
+            after: bytes,
+        };
+        self.short_download(entry.a2l_addr.addr, entry.a2l_addr.ext, &entry.after).await?;
+        self.update_cached_values(entry.a2l_addr, &entry.after);
+        self.cal_history.push(entry);
+        Ok(())
+    }
+
+    // Download a new value to the XCP server, update the cached value and record the change in the calibration history
+    async fn download_value(&mut self, handle: XcpCalibrationObjectHandle, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        let obj = &self.calibration_object_list[handle.0];
+        let entry = XcpCalibrationHistoryEntry {
+            name: obj.name.clone(),
+            a2l_addr: obj.a2l_addr,
+            a2l_type: obj.get_type,
+            before: obj.value.clone(),
+            after: bytes.to_vec(),
+        };
+        self.short_download(entry.a2l_addr.addr, entry.a2l_addr.ext, bytes).await?;
+        self.calibration_object_list[handle.0].set_value(bytes);
+        self.cal_history.push(entry);
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------
+    // Calibration history
+    // All set_value_xxx operations of this session are recorded with before and after value
+
+    pub fn get_calibration_history(&self) -> &XcpCalibrationHistory {
+        &self.cal_history
+    }
+
+    pub fn print_calibration_history(&self) {
+        self.cal_history.print();
+    }
+
+    /// Undo the last calibration change, returns false if there is nothing to undo
+    pub async fn undo(&mut self) -> Result<bool, Box<dyn Error>> {
+        let Some(entry) = self.cal_history.get_undo().cloned() else {
+            return Ok(false);
+        };
+        debug!("Undo {}", entry);
+        self.restore_value(entry.a2l_addr, &entry.before).await?;
+        self.cal_history.undo();
+        Ok(true)
+    }
+
+    /// Redo the last undone calibration change, returns false if there is nothing to redo
+    pub async fn redo(&mut self) -> Result<bool, Box<dyn Error>> {
+        let Some(entry) = self.cal_history.get_redo().cloned() else {
+            return Ok(false);
+        };
+        debug!("Redo {}", entry);
+        self.restore_value(entry.a2l_addr, &entry.after).await?;
+        self.cal_history.redo();
+        Ok(true)
+    }
+
+    // Download a value from the history, without recording it
+    async fn restore_value(&mut self, a2l_addr: A2lAddr, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.short_download(a2l_addr.addr, a2l_addr.ext, bytes).await?;
+        self.update_cached_values(a2l_addr, bytes);
+        Ok(())
+    }
+
+    // Update the cached values of all calibration objects containing the written memory range
+    fn update_cached_values(&mut self, a2l_addr: A2lAddr, bytes: &[u8]) {
+        for o in &mut self.calibration_object_list {
+            if o.a2l_addr.ext != a2l_addr.ext || a2l_addr.addr < o.a2l_addr.addr {
+                continue;
+            }
+            let offset = (a2l_addr.addr - o.a2l_addr.addr) as usize;
+            if let Some(value) = o.value.get_mut(offset..offset + bytes.len()) {
+                value.copy_from_slice(bytes);
+            }
+        }
+    }
+
+    /// Write the net calibration changes of this session to a DCM patch file
+    pub fn write_dcm_patch<P: AsRef<std::path::Path>>(&self, dcm_path: &P) -> Result<usize, Box<dyn Error>> {
+        Ok(self.cal_history.write_dcm(dcm_path)?)
+    }
+
+    pub async fn read_value_u64(&mut self, index: XcpCalibrationObjectHandle) -> Result<u64, Box<dyn Error>> {
+        let obj = &self.calibration_object_list[index.0];
+        let a2l_addr = obj.a2l_addr;
+        let get_type = obj.get_type;
+        let size = obj.get_type.size;
+        let Ok(size) = u8::try_from(size) else {
+            error!("read_value_u64: value size {} exceeds the SHORT_UPLOAD limit of 255 bytes", size);
+            return Err(Box::new(XcpError::new(ERROR_LIMIT, CC_SHORT_UPLOAD)) as Box<dyn Error>);
+        };
+        let resp = self.short_upload(a2l_addr.addr, a2l_addr.ext, size).await?;
+        check_response_len(&resp, 1 + get_type.size, CC_SHORT_UPLOAD)?;
+        let value = resp[1..=get_type.size].to_vec();
+        self.calibration_object_list[index.0].value = value;
+        Ok(self.get_value_u64(index))
+    }
+
+    pub fn get_value_u64(&mut self, index: XcpCalibrationObjectHandle) -> u64 {
+        let obj = &self.calibration_object_list[index.0];
+        obj.get_value_u64()
+    }
+
+    pub fn get_value_i64(&mut self, index: XcpCalibrationObjectHandle) -> i64 {
+        let obj = &self.calibration_object_list[index.0];
+        obj.get_value_i64()
+    }
+    /// Physical value of element index of an array calibration object from the cached value
+    pub fn get_element_f64(&self, handle: XcpCalibrationObjectHandle, index: usize) -> Option<f64> {
+        self.calibration_object_list[handle.0].get_element_f64(index)
+    }
+
+    pub fn get_value_f64(&mut self, index: XcpCalibrationObjectHandle) -> f64 {
+        let obj = &self.calibration_object_list[index.0];
+        let v = obj.get_value_u64();
+        match obj.get_type.size {
+            8 => {
+                // Convert to f64
+                f64::from_bits(v)
+            }
+            4 => {
+                // Convert to f32
+                f32::from_bits(v as u32) as f64
+            }
+            _ => {
+                error!("get_value_f64: size = {}", obj.get_type.size);
+                0.0
+            }
+        }
+    }
+
+    //------------------------------------------------------------------------
+    // Watch
+    // Poll all measurement and calibration objects matching a regular expression and print a refreshing table
+
+    /// Create a watch table for all objects matching a regular expression
+    pub fn create_watch_table(&self, expr: &str) -> XcpWatchTable {
+        let registry = self.registry.as_ref().unwrap();
+        let instance_list = registry.get_flattened_instance_list();
+        let mut table = XcpWatchTable::new();
+        for name in instance_list.find_instances_regex(expr, xcp_registry::McObjectType::Unspecified, None) {
+            if let Some(instance) = instance_list.get_instance(&name, xcp_registry::McObjectType::Unspecified, None) {
+                if !instance.dim_type.is_basic_type() || instance.size() != instance.value_size() {
+                    debug!("watch: {} skipped, not a scalar basic type", name);
+                    continue;
+                }
+                let a2l_type: A2lType = A2lType {
+                    size: instance.value_size(),
+                    encoding: instance.value_type().into(),
+                };
+                // Event relative addresses are only valid in the context of their event and can not be polled
+                let (ext, addr) = instance.get_address().get_a2l_addr(registry);
+                if instance.get_address().is_event_relative() || is_dyn_addr_ext(ext) {
+                    debug!("watch: {} skipped, event relative address", name);
+                    continue;
+                }
+                let a2l_addr: A2lAddr = A2lAddr {
+                    ext,
+                    addr,
+                    event: instance.event_id(),
+                };
+                table.add_row(XcpWatchRow::new(&name, a2l_addr, a2l_type));
+            }
+        }
+        table
+    }
+
+    /// Poll all values of a watch table once, t is the time in s
+    /// Rows with event relative addresses are skipped
+    #[allow(clippy::cast_possible_truncation)]
+    pub async fn poll_watch_table(&mut self, table: &mut XcpWatchTable, t: f64) -> Result<(), Box<dyn Error>> {
+        for row in table.get_rows_mut() {
+            if is_dyn_addr_ext(row.a2l_addr.ext) {
+                continue;
+            }
+            let resp = self.short_upload(row.a2l_addr.addr, row.a2l_addr.ext, row.a2l_type.size as u8).await?;
+            row.update(&resp[1..=row.a2l_type.size], t);
+        }
+        table.next_cycle();
+        Ok(())
+    }
+
+    /// Watch all objects matching a regular expression with rate polls per second for the given duration
+    pub async fn watch(&mut self, expr: &str, rate: f64, duration: Duration) -> Result<(), Box<dyn Error>> {
+        let mut table = self.create_watch_table(expr);
+        if table.is_empty() {
+            warn!("watch: no objects found matching {}", expr);
+            return Err(Box::new(XcpError::new(ERROR_NOT_FOUND, 0)) as Box<dyn Error>);
+        }
+        let cycle_time = Duration::from_secs_f64(1.0 / rate.max(0.01));
+        let start = tokio::time::Instant::now();
+        while start.elapsed() < duration {
+            self.poll_watch_table(&mut table, start.elapsed().as_secs_f64()).await?;
+            table.print();
+            tokio::time::sleep(cycle_time).await;
+        }
+        Ok(())
+    }
+
+    //------------------------------------------------------------------------
+    // XcpMeasurementObject, XcpMeasurementObjectHandle (index pointer to XcpCMeasurementObject),
+    //
+
+    /// Create a measurement object by name from the registry
+    /// name may be a regular expression matching exactly one measurement
+    pub fn create_measurement_object(&mut self, name: &str) -> Option<XcpMeasurementObjectHandle> {
+        let registry = self.registry.as_ref().unwrap();
+        // Typedef members and array elements are resolved by name, e.g. "struct.field" or "array._3"
+        match registry.resolve_instance(name, xcp_registry::McObjectType::Measurement, None) {
+            None => {
+                debug!("Measurement {} not found", name);
+                None
+            }
+            Some(instance) => {
+                let (ext, addr) = instance.get_address().get_a2l_addr(registry);
+                if instance.event_id().is_none() {
+                    log::error!("event_id for measurement object {} not found, addr = {}:0x{:0X}", name, ext, addr);
+                    return None;
+                }
+                let event = instance.event_id().unwrap();
+                if registry.event_list.find_event_id(event).is_some_and(|e| !e.is_daq()) {
+                    error!("Measurement object {}: event {} is a STIM only event, its variables can not be measured", name, event);
+                    return None;
+                }
+                let a2l_addr: A2lAddr = A2lAddr { ext, addr, event: Some(event) };
+                // Arrays of basic types are measured as a whole, with the element type and the dimensions
+                // Typedef instances are a blob of the typedef size, the x dimension of a blob is its size in bytes
+                let dim = instance.get_dim_type().get_dim();
+                let size = match instance.value_type() {
+                    McValueType::TypeDef(typedef_name) => registry.typedef_list.find_typedef(typedef_name).map_or(0, |t| t.size),
+                    McValueType::Blob(_) => 1,
+                    _ => instance.value_size(),
+                };
+                if size == 0 {
+                    error!("Measurement object {}: size of {:?} unknown", name, instance.value_type());
+                    return None;
+                }
+                let a2l_type: A2lType = A2lType {
+                    size,
+                    encoding: instance.value_type().into(),
+                };
+                let mut o = XcpClientMeasurementObject::new(name, a2l_addr, a2l_type);
+                o.dim = dim;
+                let mc_support_data = instance.get_mc_support_data();
+                if mc_support_data.factor.is_some() || mc_support_data.offset.is_some() {
+                    o.conversion = Some((mc_support_data.factor.unwrap_or(1.0), mc_support_data.offset.unwrap_or(0.0)));
+                }
+                debug!("Create measurement object {}: addr = {:08X} type = {:?}", name, a2l_addr.addr, a2l_type);
+                debug!("-> {:?} ", o);
+                self.measurement_object_list.push(o);
+                Some(XcpMeasurementObjectHandle(self.measurement_object_list.len() - 1))
+            }
+        }
+    }
+
+    pub fn get_measurement_object(&self, handle: XcpMeasurementObjectHandle) -> &XcpClientMeasurementObject {
+        &self.measurement_object_list[handle.0]
+    }
+
+    /// Names of the measurement configurations predefined in the A2L, which should be started automatically
+    pub fn get_auto_start_configs(&self) -> Vec<String> {
+        self.registry
+            .as_ref()
+            .map(|registry| registry.measurement_config_list.auto_start_configs().map(|c| c.name.to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Create the measurement objects of a measurement configuration predefined in the A2L, start with start_measurement
+    /// Signals not found are skipped, returns the number of measurement objects created
+    pub fn create_measurement_config(&mut self, name: &str) -> Result<usize, Box<dyn Error>> {
+        let Some(config) = self.registry.as_ref().and_then(|registry| registry.measurement_config_list.find_config(name)) else {
+            error!("Measurement configuration {} not found", name);
+            return Err(Box::new(XcpError::new(ERROR_NOT_FOUND, 0)) as Box<dyn Error>);
+        };
+        let measurements = config.measurements.clone();
+        let mut count = 0;
+        for m in &measurements {
+            if self.create_measurement_object(m).is_some() {
+                count += 1;
+            } else {
+                warn!("Measurement configuration {}: measurement {} not found", name, m);
+            }
+        }
+        info!("Measurement configuration {}: {} of {} signals", name, count, measurements.len());
+        Ok(count)
+    }
+
+    //------------------------------------------------------------------------
+    // DAQ init, start, stop
+    //
+
+    /// Get clock resolution in ns
+    pub fn get_timestamp_resolution(&self) -> u64 {
+        self.timestamp_resolution_ns
+    }
+
+    /// Get the UTC wall clock time of the last measurement start
+    pub fn get_measurement_start_utc(&self) -> Option<std::time::SystemTime> {
+        self.daq_start.map(|(utc, _)| utc)
+    }
+
+    /// Convert a DAQ timestamp in ns to UTC wall clock time, based on the last measurement start
+    pub fn daq_timestamp_to_utc(&self, timestamp_ns: u64) -> Option<std::time::SystemTime> {
+        let (utc, start_ns) = self.daq_start?;
+        if timestamp_ns >= start_ns {
+            utc.checked_add(Duration::from_nanos(timestamp_ns - start_ns))
+        } else {
+            utc.checked_sub(Duration::from_nanos(start_ns - timestamp_ns))
+        }
+    }
+
+    // Read the DAQ clock epoch (UTC in ns, DAQ clock in ns) from the calibration segment XCP_CLOCK_EPOCH_NAME of an xcp_lite server
+    // None, if the registry does not contain it or the upload fails
+    async fn read_server_clock_epoch(&mut self) -> Option<(u64, u64)> {
+        let mut values = [0u64; 2];
+        for (i, field) in ["utc_ns", "clock_ns"].iter().enumerate() {
+            let registry = self.registry.as_ref()?;
+            let name = format!("{}.{}", XCP_CLOCK_EPOCH_NAME, field);
+            let instance = registry.resolve_instance(&name, xcp_registry::McObjectType::Characteristic, None)?;
+            let (ext, addr) = instance.get_address().get_a2l_addr(registry);
+            let resp = self.short_upload(addr, ext, 8).await.ok()?;
+            values[i] = u64::from_le_bytes(resp.get(1..9)?.try_into().ok()?);
+        }
+        debug!("Server DAQ clock epoch: utc={}ns clock={}ns", values[0], values[1]);
+        Some((values[0], values[1]))
+    }
+
+    /// Start DAQ
+    pub async fn start_measurement(&mut self) -> Result<(), Box<dyn Error>> {
+        debug!("Start measurement");
+        if !self.capabilities.daq {
+            error!("DAQ measurement not supported by this XCP server");
+            return Err(Box::new(XcpError::new(CRC_CMD_UNKNOWN, CC_START_STOP_SYNCH)) as Box<dyn Error>);
+        }
+
+        // Size of the DAQ header and timestamp in the first ODT
+        let dto_header_size = self.daq_header_size as u16 + self.capabilities.daq_timestamp_size as u16;
+
+        // Init
+        let signal_count = self.measurement_object_list.len();
+        let mut daq_odt_entries: Vec<Vec<OdtEntry>> = Vec::with_capacity(8);
+
+        // Store all events in a hashmap (eventnumber, signalcount)
+        let mut event_map: HashMap<u16, u16> = HashMap::new();
+        let mut min_event: u16 = 0xFFFF;
+        let mut max_event: u16 = 0;
+        for i in 0..signal_count {
+            let event = self.measurement_object_list[i].get_a2l_addr().event.unwrap();
+            if event < min_event {
+                min_event = event;
+            }
+            if event > max_event {
+                max_event = event;
+            }
+            let count = event_map.entry(event).or_insert(0);
+            *count += 1;
+        }
+        let event_count: u16 = event_map.len() as u16;
+        debug!("event/daq count = {}", event_count);
+
+        // Transform the event hashmap to a sorted array
+        let mut event_list: Vec<(u16, u16)> = Vec::new();
+        for (event, count) in event_map.into_iter() {
+            event_list.push((event, count));
+        }
+        event_list.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Distribute the signals of each DAQ list (event) to ODTs, a new ODT is started when a DTO is full
+        // The first ODT of a DAQ list carries the timestamp
+        let mut daq_odts: Vec<Vec<Vec<usize>>> = Vec::with_capacity(event_list.len()); // Measurement object indices per ODT per DAQ list
+        for (event, _) in &event_list {
+            let mut entries: Vec<(usize, u16)> = Vec::new();
+            for (i, m) in self.measurement_object_list.iter().enumerate() {
+                if m.a2l_addr.event != Some(*event) {
+                    continue;
+                }
+                let Some(size) = u8::try_from(m.get_size()).ok().filter(|size| self.capabilities.check_odt_entry_size(usize::from(*size))) else {
+                    error!("{}: size {} not supported by DAQ granularity or max ODT entry size", m.name, m.get_size());
+                    return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+                };
+                entries.push((i, u16::from(size)));
+            }
+            let odts = match split_odt_entries(&entries, self.max_dto_size, dto_header_size, u16::from(self.daq_header_size)) {
+                Ok(odts) => odts,
+                Err(i) => {
+                    let m = &self.measurement_object_list[i];
+                    error!("{}: size {} exceeds the max DTO size {}", m.name, m.get_size(), self.max_dto_size);
+                    return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+                }
+            };
+            if odts.len() > MAX_ODT_COUNT {
+                error!("Event {}: {} ODTs exceed the max ODT count {} of a DAQ list", event, odts.len(), MAX_ODT_COUNT);
+                return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+            }
+            daq_odts.push(odts);
+        }
+
+        // Alloc a DAQ list for each event, and the spare DAQ lists for measurement sessions added while DAQ is running
+        assert!(event_count <= 1024, "event_count > 1024");
+        let daq_count: u16 = event_count;
+        let spare_count: u16 = self.daq_reserve.get_list_count();
+        self.free_daq().await?;
+        self.alloc_daq(daq_count + spare_count).await?;
+        debug!("alloc_daq count={}, spare={}", daq_count, spare_count);
+
+        // Alloc the ODTs for each DAQ list, one ODT for each spare DAQ list
+        for daq in 0..daq_count + spare_count {
+            let odt_count = u8::try_from(daq_odts.get(daq as usize).map_or(1, Vec::len)).expect("ODT count checked");
+            self.alloc_odt(daq, odt_count).await?;
+            debug!("Alloc daq={}, odt_count={}", daq, odt_count);
+        }
+
+        // Alloc ODT entries (signal count) for each ODT of each DAQ list
+        for daq in 0..daq_count {
+            for (odt, entries) in (0u8..).zip(&daq_odts[daq as usize]) {
+                let entry_count = u8::try_from(entries.len()).expect("ODT entry count checked");
+                self.alloc_odt_entries(daq, odt, entry_count).await?;
+                debug!("Alloc odt_entries: daq={}, odt={}, odt_entry_count={}", daq, odt, entries.len());
+            }
+        }
+        let spare_entry_count = self.daq_reserve.get_entry_count();
+        for daq in daq_count..daq_count + spare_count {
+            self.alloc_odt_entries(daq, 0, spare_entry_count).await?;
+            debug!("Alloc spare odt_entries: daq={}, odt={}, odt_entry_count={}", daq, 0, spare_entry_count);
+        }
+
+        // Create all ODT entries for each daq/event list and store information for the DAQ decoder
+        for (daq, odts) in (0u16..).zip(&daq_odts) {
+            let mut odt_entries = Vec::new();
+            for (odt, entries) in (0u8..).zip(odts) {
+                let header_size = if odt == 0 { dto_header_size } else { u16::from(self.daq_header_size) };
+                let mut odt_size: u16 = 0;
+                self.set_daq_ptr(daq, odt, 0).await?;
+                for &i in entries {
+                    let m = &mut self.measurement_object_list[i];
+                    let (a2l_addr, a2l_type, dim, size) = (m.a2l_addr, m.a2l_type, m.dim, m.get_size());
+                    m.daq = daq;
+                    m.odt = odt;
+                    m.offset = odt_size + header_size;
+                    m.measured = true;
+
+                    debug!(
+                        "WRITE_DAQ {} daq={}, odt={},  type={:?}, size={}, ext={}, addr=0x{:08X}, offset={}",
+                        m.name,
+                        daq,
+                        odt,
+                        a2l_type.encoding,
+                        size,
+                        a2l_addr.ext,
+                        a2l_addr.addr,
+                        odt_size + header_size
+                    );
+
+                    odt_entries.push(OdtEntry {
+                        name: m.name.clone(),
+                        a2l_type,
+                        a2l_addr,
+                        odt,
+                        offset: odt_size,
+                        conversion: m.conversion,
+                        dim,
+                    });
+                    let size = u8::try_from(size).expect("size checked");
+                    self.write_daq(a2l_addr.ext, a2l_addr.addr, size).await?;
+                    odt_size += u16::from(size);
+                }
+            } // odts
+
+            daq_odt_entries.push(odt_entries);
+        }
+
+        // Spare DAQ lists are not configured and not selected, until a measurement session uses them
+        for _ in 0..spare_count {
+            daq_odt_entries.push(Vec::new());
+        }
+        self.daq_reserve.set_spare_lists(daq_count..daq_count + spare_count);
+        self.sessions.iter_mut().flatten().for_each(|s| s.clear_daq_lists());
+
+        // Set DAQ list events
+        for daq in 0..daq_count {
+            let event = event_list[daq as usize].0;
+            self.set_daq_list_mode(daq, event).await?;
+            debug!("Set event: daq={}, event={}", daq, event);
+        }
+
+        // Select and prepare all DAQ lists
+        for daq in 0..daq_count {
+            self.select_daq_list(daq).await?;
+        }
+        self.prepare_selected_daq_lists().await?;
+
+        // Reset the DAQ decoder and set measurement start time
+        // Record the UTC wall clock time of the measurement start to map DAQ timestamps to absolute time
+        // The DAQ clock epoch published by the server is used, the local wall clock only if the server does not publish it
+        let daq_clock = self.get_daq_clock_raw().await?;
+        let daq_clock_ns = daq_clock * self.timestamp_resolution_ns;
+        let utc = match self.read_server_clock_epoch().await {
+            Some((utc_ns, clock_ns)) => std::time::UNIX_EPOCH + Duration::from_nanos(utc_ns.wrapping_add(daq_clock_ns.wrapping_sub(clock_ns))),
+            None => std::time::SystemTime::now(),
+        };
+        self.daq_start = Some((utc, daq_clock_ns));
+        self.daq_decoder.as_ref().unwrap().lock().start(daq_odt_entries, daq_clock);
+        let daq_events = event_list.iter().map(|(event, _)| Some(*event)).chain((0..spare_count).map(|_| None)).collect();
+        self.daq_loss.lock().start(self.daq_header_size, daq_events);
+
+        // Send running=true throught the DAQ control channel to the receive task
+        self.task_control.running = true;
+        self.tx_task_control.as_ref().unwrap().send(self.task_control).await.unwrap();
+
+        // Start DAQ
+        self.start_selected_daq_lists().await?;
+
+        Ok(())
+    }
+
+    /// Stop DAQ
+    pub async fn stop_measurement(&mut self) -> Result<(), Box<dyn Error>> {
+        debug!("Stop measurement");
+
+        // Stop DAQ
+        let res = self.stop_all_daq_lists().await;
+
+        // Send running=false throught the DAQ control channel to the receive task
+        self.task_control.running = false;
+        self.tx_task_control.as_ref().unwrap().send(self.task_control).await?;
+
+        // Stop the DAQ decoder
+        self.daq_decoder.as_ref().unwrap().lock().stop();
+
+        // Clear the measurement object list, the measurement sessions and the spare DAQ lists
+        self.measurement_object_list.clear();
+        self.sessions.clear();
+        self.daq_reserve.set_spare_lists(0..0);
+
+        res
+    }
+
+    //---------------------------------------------------------------------------------
+    // Calibration page management
+
+    /// Set all calibration segments to page 0, working page
+    pub async fn init_calibration_segments(&mut self) -> Result<(), Box<dyn Error>> {
+        let reg = self.registry.as_mut().unwrap();
+        for index in 0..reg.cal_seg_list.len() {
+            let ecu_page = self.get_ecu_page(index.try_into().unwrap()).await?;
+            let xcp_page = self.get_xcp_page(index.try_into().unwrap()).await?;
+            info!("Calibration segment {}: ecu_page={}, xcp_page={}", index, ecu_page, xcp_page);
+        }
+
+        // Set all segments to working page 0
+        info!("Set ECU page access to working page for all segments");
+        self.set_ecu_page(0).await?;
+        info!("Set XCP page access to working page for all segments");
+        self.set_xcp_page(0).await?;
+
+        Ok(())
+    }
+
+    //---------------------------------------------------------------------------------
+
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod xcp_client_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_split_odt_entries() {
+        // max_dto_size 12, 8 bytes DAQ header and timestamp in the first ODT, 4 bytes DAQ header in the following ODTs
+        let entries = [(0, 4), (1, 4), (2, 8), (3, 4)];
+        assert_eq!(split_odt_entries(&entries, 12, 8, 4), Ok(vec![vec![0], vec![1], vec![2], vec![3]]));
+        assert_eq!(split_odt_entries(&entries, 16, 8, 4), Ok(vec![vec![0, 1], vec![2, 3]]));
+        assert_eq!(split_odt_entries(&[], 16, 8, 4), Ok(vec![vec![]]));
+
+        // An entry which does not fit into an empty DTO
+        assert_eq!(split_odt_entries(&[(0, 4), (7, 9)], 12, 4, 4), Err(7));
+
+        // A max DTO size smaller than the header does not underflow
+        assert_eq!(split_odt_entries(&[(5, 1)], 6, 8, 4), Err(5));
+
+        // The ODT entry count limit starts a new ODT
+        let entries: Vec<(usize, u16)> = (0..MAX_ODT_ENTRY_COUNT + 1).map(|i| (i, 1)).collect();
+        let odts = split_odt_entries(&entries, u16::MAX, 8, 4).unwrap();
+        assert_eq!(odts.len(), 2);
+        assert_eq!(odts[1], vec![MAX_ODT_ENTRY_COUNT]);
+    }
+}
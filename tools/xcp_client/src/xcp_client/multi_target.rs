@@ -0,0 +1,559 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module multi_target
+// Multi target orchestration
+// One XcpClient per target, signals are namespaced as "target.signal"
+// DAQ data of all targets is decoded into one merged, time aligned recording
+// Timestamps are corrected for clock offset and drift of each target (see clock_sync)
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use parking_lot::Mutex;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use super::clock_sync::XcpClockSync;
+use super::xcp::*;
+use super::{A2lManager, OdtEntry, XcpClient, XcpDaqDecoder, XcpMeasurementObjectHandle, XcpTextDecoder};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Merged recording
+
+/// A single decoded measurement value
+/// The timestamp is in ns on the client time base, starting with the first target start
+#[derive(Debug, Clone)]
+pub struct XcpSample {
+    pub timestamp_ns: i64,
+    pub target: usize,
+    pub name: String,
+    pub value: f64,
+}
+
+/// Time aligned recording of DAQ data from multiple targets
+#[derive(Debug, Default)]
+pub struct XcpMergedRecording {
+    target_names: Vec<String>,
+    samples: Vec<XcpSample>,
+    start_instant: Option<std::time::Instant>,
+    start_time_utc: Option<std::time::SystemTime>,
+}
+
+impl XcpMergedRecording {
+    pub fn new() -> XcpMergedRecording {
+        XcpMergedRecording {
+            target_names: Vec::new(),
+            samples: Vec::new(),
+            start_instant: None,
+            start_time_utc: None,
+        }
+    }
+
+    /// Register a target, returns its index
+    pub fn add_target(&mut self, name: &str) -> usize {
+        self.target_names.push(name.to_string());
+        self.target_names.len() - 1
+    }
+
+    pub fn get_target_name(&self, target: usize) -> &str {
+        &self.target_names[target]
+    }
+
+    /// Client time in ns since the first target started measurement
+    pub fn get_client_time_ns(&mut self) -> i64 {
+        let start = *self.start_instant.get_or_insert_with(|| {
+            self.start_time_utc = Some(std::time::SystemTime::now());
+            std::time::Instant::now()
+        });
+        start.elapsed().as_nanos().try_into().unwrap_or(i64::MAX)
+    }
+
+    /// Absolute UTC start time of the recording, sample timestamps are relative to this time
+    pub fn get_start_time_utc(&self) -> Option<std::time::SystemTime> {
+        self.start_time_utc
+    }
+
+    pub fn push(&mut self, sample: XcpSample) {
+        self.samples.push(sample);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Remove and return all samples in the order of arrival, the start time is kept
+    pub fn take_samples(&mut self) -> Vec<XcpSample> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.start_instant = None;
+        self.start_time_utc = None;
+    }
+
+    /// All samples sorted by timestamp
+    pub fn get_samples(&mut self) -> &[XcpSample] {
+        self.samples.sort_by_key(|s| s.timestamp_ns);
+        &self.samples
+    }
+
+    /// Namespaced signal name "target.signal" of a sample
+    pub fn get_sample_name(&self, sample: &XcpSample) -> String {
+        format!("{}.{}", self.target_names[sample.target], sample.name)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// DAQ decoder of a single target writing into the merged recording
+
+pub struct XcpTargetDaqDecoder {
+    target: usize,
+    recording: Arc<Mutex<XcpMergedRecording>>,
+    odt_entries: Vec<Vec<OdtEntry>>,
+    timestamp_resolution_ns: u64,
+    daq_header_size: u8,
+    daq_timestamp_size: u8,
+    daq_timestamp: Vec<u64>, // last 64 bit raw timestamp per DAQ list
+    time_offset_ns: i64,     // target time to client time
+    physical: bool,          // Apply the conversion of the ODT entries
+}
+
+impl XcpTargetDaqDecoder {
+    pub fn new(target: usize, recording: Arc<Mutex<XcpMergedRecording>>) -> XcpTargetDaqDecoder {
+        XcpTargetDaqDecoder {
+            target,
+            recording,
+            odt_entries: Vec::new(),
+            timestamp_resolution_ns: 1,
+            daq_header_size: 4,
+            daq_timestamp_size: 4,
+            daq_timestamp: Vec::new(),
+            time_offset_ns: 0,
+            physical: false,
+        }
+    }
+
+    /// Record physical values, converted with the linear conversion from the registry, e.g. to reconstruct quantized signals
+    /// Default are raw values
+    pub fn set_physical(&mut self, physical: bool) {
+        self.physical = physical;
+    }
+
+    /// Offset to convert target time in ns to client time in ns
+    pub fn get_time_offset_ns(&self) -> i64 {
+        self.time_offset_ns
+    }
+
+    // Extend a 8, 16 or 32 bit raw timestamp to 64 bit, using the last timestamp of this DAQ list
+    fn extend_timestamp(&mut self, daq: usize, timestamp_raw: u64) -> u64 {
+        let bits = 8 * self.daq_timestamp_size as u32;
+        let mask = (1u64 << bits) - 1;
+        let t_last = self.daq_timestamp[daq];
+        let mut th = t_last >> bits;
+        if timestamp_raw < (t_last & mask) {
+            th += 1;
+        }
+        let t = (th << bits) | timestamp_raw;
+        self.daq_timestamp[daq] = t;
+        t
+    }
+
+    // Target time in ns of a 64 bit raw timestamp, saturating at i64::MAX
+    fn target_time_ns(&self, timestamp_raw64: u64) -> i64 {
+        i64::try_from(timestamp_raw64.saturating_mul(self.timestamp_resolution_ns)).unwrap_or(i64::MAX)
+    }
+}
+
+impl XcpDaqDecoder for XcpTargetDaqDecoder {
+    fn start(&mut self, odt_entries: Vec<Vec<OdtEntry>>, timestamp_raw64: u64) {
+        self.daq_timestamp = vec![timestamp_raw64; odt_entries.len()];
+        self.odt_entries = odt_entries;
+        let target_time_ns = self.target_time_ns(timestamp_raw64);
+        self.time_offset_ns = self.recording.lock().get_client_time_ns().saturating_sub(target_time_ns);
+        debug!("Target {} start, time offset = {} ns", self.target, self.time_offset_ns);
+    }
+
+    fn update_daq_list(&mut self, daq: u16, odt_entries: Vec<OdtEntry>) {
+        // Timestamps of a spare DAQ list continue from the latest timestamp of all DAQ lists
+        let t = self.daq_timestamp.iter().copied().max().unwrap_or(0);
+        if let (Some(e), Some(ts)) = (self.odt_entries.get_mut(daq as usize), self.daq_timestamp.get_mut(daq as usize)) {
+            *e = odt_entries;
+            *ts = t;
+        }
+    }
+
+    fn set_daq_properties(&mut self, timestamp_resolution: u64, daq_header_size: u8) {
+        self.timestamp_resolution_ns = timestamp_resolution;
+        self.daq_header_size = daq_header_size;
+    }
+
+    fn set_daq_timestamp_size(&mut self, timestamp_size: u8) {
+        self.daq_timestamp_size = timestamp_size;
+    }
+
+    fn decode(&mut self, lost: u32, buf: &[u8]) {
+        if lost > 0 {
+            warn!("Target {}: {} DAQ packets lost", self.target, lost);
+        }
+        let header_size = self.daq_header_size as usize;
+        let odt = buf.first().copied().unwrap_or(0);
+        let timestamp_size = if odt == 0 { self.daq_timestamp_size as usize } else { 0 }; // Only the first ODT of a DAQ list has a timestamp
+        if buf.len() < header_size + timestamp_size {
+            return;
+        }
+        let daq = match header_size {
+            4 => buf[2] as usize | (buf[3] as usize) << 8,
+            3 => buf[1] as usize | (buf[2] as usize) << 8,
+            _ => buf[1] as usize,
+        };
+        if daq >= self.odt_entries.len() {
+            warn!("Target {}: unexpected DAQ packet daq={} odt={}", self.target, daq, odt);
+            return;
+        }
+
+        // Timestamp in client time, receive time if the server has no DAQ timestamps
+        // The following ODTs of a DAQ list belong to the sample of the first ODT
+        let recording = Arc::clone(&self.recording);
+        let mut recording = recording.lock();
+        let timestamp_ns = if self.daq_timestamp_size == 0 {
+            recording.get_client_time_ns()
+        } else {
+            let t = if odt == 0 {
+                let timestamp_raw = buf[header_size..header_size + timestamp_size].iter().rev().fold(0u64, |t, b| (t << 8) | *b as u64);
+                self.extend_timestamp(daq, timestamp_raw)
+            } else {
+                self.daq_timestamp[daq]
+            };
+            self.target_time_ns(t).saturating_add(self.time_offset_ns)
+        };
+
+        // Decode the ODT entries of this ODT
+        // Arrays are decoded into one sample per element, with the element name, blobs and typedef instances are skipped
+        let data = &buf[header_size + timestamp_size..];
+        for e in self.odt_entries[daq].iter().filter(|e| e.odt == odt) {
+            let offset = e.offset as usize;
+            if offset + e.get_size() > data.len() {
+                warn!("Target {}: DAQ packet too short for {}", self.target, e.name);
+                continue;
+            }
+            for (i, value) in e.decode_elements(&data[offset..]).into_iter().enumerate() {
+                recording.push(XcpSample {
+                    timestamp_ns,
+                    target: self.target,
+                    name: e.get_element_name(i),
+                    value: if self.physical { e.to_physical(value) } else { value },
+                });
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Text decoder for targets, prints SERV_TEXT messages
+
+pub struct XcpTargetTextDecoder;
+
+impl XcpTextDecoder for XcpTargetTextDecoder {}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpMultiClient
+
+/// Target description, name, transport layer and address
+#[derive(Debug, Clone)]
+pub struct XcpTargetConfig {
+    pub name: String,
+    pub tcp: bool,
+    pub dest_addr: SocketAddr,
+}
+
+impl XcpTargetConfig {
+    /// Parse a targets config text
+    /// One target per line: <name> <udp|tcp> <ip:port>, empty lines and lines starting with # are ignored
+    pub fn parse(text: &str) -> Result<Vec<XcpTargetConfig>, Box<dyn Error>> {
+        let mut targets = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 || (fields[1] != "udp" && fields[1] != "tcp") {
+                error!("Targets config line {}: expected <name> <udp|tcp> <ip:port>, got '{}'", n + 1, line);
+                return Err(Box::new(XcpError::new(ERROR_GENERIC, 0)) as Box<dyn Error>);
+            }
+            targets.push(XcpTargetConfig {
+                name: fields[0].to_string(),
+                tcp: fields[1] == "tcp",
+                dest_addr: fields[2].parse()?,
+            });
+        }
+        Ok(targets)
+    }
+
+    /// Load a targets config file
+    pub fn load<P: AsRef<std::path::Path>>(path: &P) -> Result<Vec<XcpTargetConfig>, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        XcpTargetConfig::parse(&text)
+    }
+}
+
+/// Client session with multiple targets
+pub struct XcpMultiClient {
+    targets: Vec<(String, XcpClient)>,
+    recording: Arc<Mutex<XcpMergedRecording>>,
+    decoders: Vec<Arc<Mutex<XcpTargetDaqDecoder>>>,
+    clock_syncs: Vec<XcpClockSync>,
+}
+
+impl XcpMultiClient {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> XcpMultiClient {
+        XcpMultiClient {
+            targets: Vec::new(),
+            recording: Arc::new(Mutex::new(XcpMergedRecording::new())),
+            decoders: Vec::new(),
+            clock_syncs: Vec::new(),
+        }
+    }
+
+    /// Add a target with a new (not yet connected) client, target names must be unique and must not contain '.'
+    pub fn add_target(&mut self, name: &str, client: XcpClient) -> Result<usize, Box<dyn Error>> {
+        if name.contains('.') || self.targets.iter().any(|(n, _)| n == name) {
+            error!("Target name {} is not unique or contains '.'", name);
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, 0)) as Box<dyn Error>);
+        }
+        let target = self.recording.lock().add_target(name);
+        self.decoders.push(Arc::new(Mutex::new(XcpTargetDaqDecoder::new(target, Arc::clone(&self.recording)))));
+        self.clock_syncs.push(XcpClockSync::new());
+        self.targets.push((name.to_string(), client));
+        Ok(target)
+    }
+
+    /// Add all targets from a targets config
+    pub fn add_targets(&mut self, targets: &[XcpTargetConfig], bind_addr: SocketAddr) -> Result<(), Box<dyn Error>> {
+        for t in targets {
+            self.add_target(&t.name, XcpClient::new(t.tcp, t.dest_addr, bind_addr))?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    pub fn get_target_names(&self) -> Vec<&str> {
+        self.targets.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    pub fn get_client(&mut self, target: usize) -> &mut XcpClient {
+        &mut self.targets[target].1
+    }
+
+    pub fn find_target(&self, name: &str) -> Option<usize> {
+        self.targets.iter().position(|(n, _)| n == name)
+    }
+
+    /// Split a namespaced signal name "target.signal" into target index and signal name
+    pub fn split_name<'a>(&self, name: &'a str) -> Option<(usize, &'a str)> {
+        let (target, signal) = name.split_once('.')?;
+        Some((self.find_target(target)?, signal))
+    }
+
+    pub fn get_recording(&self) -> Arc<Mutex<XcpMergedRecording>> {
+        Arc::clone(&self.recording)
+    }
+
+    /// Record physical values of all targets, see XcpTargetDaqDecoder::set_physical
+    pub fn set_physical(&self, physical: bool) {
+        for decoder in &self.decoders {
+            decoder.lock().set_physical(physical);
+        }
+    }
+
+    /// Connect all targets
+    pub async fn connect(&mut self, connect_mode: u8) -> Result<(), Box<dyn Error>> {
+        for (i, (name, client)) in self.targets.iter_mut().enumerate() {
+            info!("Connect target {}", name);
+            client.connect(connect_mode, Arc::clone(&self.decoders[i]), XcpTargetTextDecoder).await?;
+        }
+        Ok(())
+    }
+
+    /// Disconnect all targets, errors are ignored
+    pub async fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        for (_, client) in self.targets.iter_mut() {
+            let _ = client.disconnect().await;
+        }
+        Ok(())
+    }
+
+    /// Upload the A2L of each target into its registry
+    pub async fn upload_a2l(&mut self, a2l_dir: &std::path::Path) -> Result<(), Box<dyn Error>> {
+        for (name, client) in self.targets.iter_mut() {
+            A2lManager::with_path(a2l_dir.join(name)).upload(client).await?;
+        }
+        Ok(())
+    }
+
+    /// Find measurements in all targets, returns namespaced names
+    pub fn find_measurements(&self, expr: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        for (target, client) in &self.targets {
+            names.extend(client.find_measurements(expr).into_iter().map(|n| format!("{}.{}", target, n)));
+        }
+        names
+    }
+
+    /// Create a measurement object by namespaced name "target.signal"
+    pub fn create_measurement_object(&mut self, name: &str) -> Option<(usize, XcpMeasurementObjectHandle)> {
+        let (target, signal) = self.split_name(name)?;
+        let handle = self.targets[target].1.create_measurement_object(signal)?;
+        Some((target, handle))
+    }
+
+    pub fn get_clock_sync(&self, target: usize) -> &XcpClockSync {
+        &self.clock_syncs[target]
+    }
+
+    /// Do one GET_DAQ_CLOCK round trip on each target to update the clock offset and drift estimation
+    /// Should be called periodically during measurement
+    pub async fn sync_clocks(&mut self) -> Result<(), Box<dyn Error>> {
+        for (i, (_, client)) in self.targets.iter_mut().enumerate() {
+            let t1 = self.recording.lock().get_client_time_ns();
+            let target_time_ns = client.get_daq_clock().await?;
+            let t2 = self.recording.lock().get_client_time_ns();
+            self.clock_syncs[i].add_round_trip(t1, t2, target_time_ns.try_into().unwrap_or(i64::MAX));
+        }
+        Ok(())
+    }
+
+    /// Correct the timestamps in the recording for the estimated clock offset and drift of each target
+    /// Returns the largest alignment error bound of all targets in ns
+    pub fn align_recording(&mut self) -> i64 {
+        let mut error_bound_ns = 0;
+        let offsets: Vec<i64> = self.decoders.iter().map(|d| d.lock().get_time_offset_ns()).collect();
+        for (i, clock_sync) in self.clock_syncs.iter().enumerate() {
+            if clock_sync.is_empty() {
+                warn!("Target {}: no clock sync round trips, timestamps are not corrected for drift", self.targets[i].0);
+                continue;
+            }
+            info!(
+                "Target {}: clock offset = {:.0} ns, drift = {:.3} ppm, error bound = {} ns",
+                self.targets[i].0,
+                clock_sync.get_offset_ns(),
+                clock_sync.get_drift_ppm(),
+                clock_sync.get_error_bound_ns()
+            );
+            error_bound_ns = error_bound_ns.max(clock_sync.get_error_bound_ns());
+        }
+        let mut recording = self.recording.lock();
+        for s in recording.samples.iter_mut() {
+            let clock_sync = &self.clock_syncs[s.target];
+            if !clock_sync.is_empty() {
+                s.timestamp_ns = clock_sync.to_client_time(s.timestamp_ns - offsets[s.target]);
+            }
+        }
+        error_bound_ns
+    }
+
+    /// Start measurement on all targets with measurement objects
+    pub async fn start_measurement(&mut self) -> Result<(), Box<dyn Error>> {
+        self.recording.lock().clear();
+        for c in self.clock_syncs.iter_mut() {
+            c.clear();
+        }
+        for (name, client) in self.targets.iter_mut() {
+            if client.measurement_object_list.is_empty() {
+                continue;
+            }
+            debug!("Start measurement on target {}", name);
+            client.start_measurement().await?;
+        }
+        Ok(())
+    }
+
+    /// Stop measurement on all targets, returns the first error
+    pub async fn stop_measurement(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut res = Ok(());
+        for (name, client) in self.targets.iter_mut() {
+            if !client.task_control.running {
+                continue;
+            }
+            debug!("Stop measurement on target {}", name);
+            let r = client.stop_measurement().await;
+            if res.is_ok() {
+                res = r;
+            }
+        }
+        res
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod multi_target_tests {
+
+    use super::*;
+    use crate::xcp_client::{A2lAddr, A2lType, A2lTypeEncoding};
+
+    fn odt_entry(name: &str, odt: u8, size: usize) -> OdtEntry {
+        OdtEntry {
+            name: name.to_string(),
+            a2l_type: A2lType {
+                size,
+                encoding: A2lTypeEncoding::Unsigned,
+            },
+            a2l_addr: A2lAddr { ext: 0, addr: 0, event: None },
+            odt,
+            offset: 0,
+            conversion: None,
+            dim: [1, 1],
+        }
+    }
+
+    #[test]
+    fn test_target_daq_decoder_odts() {
+        let recording = Arc::new(Mutex::new(XcpMergedRecording::new()));
+        let target = recording.lock().add_target("t1");
+        let mut decoder = XcpTargetDaqDecoder::new(target, Arc::clone(&recording));
+        decoder.set_daq_properties(1000, 4);
+        decoder.start(vec![vec![odt_entry("a", 0, 4), odt_entry("b", 1, 2), odt_entry("c", 2, 1)]], 0);
+
+        // The following ODTs have no timestamp and belong to the sample of the first ODT
+        decoder.decode(0, &[0, 0, 0, 0, 5, 0, 0, 0, 0x78, 0x56, 0x34, 0x12]);
+        decoder.decode(0, &[1, 0, 0, 0, 0x34, 0x12]);
+        decoder.decode(0, &[2, 0, 0, 0, 0x12]);
+        let samples = recording.lock().take_samples();
+        let values: Vec<(&str, f64)> = samples.iter().map(|s| (s.name.as_str(), s.value)).collect();
+        assert_eq!(values, vec![("a", f64::from(0x1234_5678u32)), ("b", f64::from(0x1234u16)), ("c", f64::from(0x12u8))]);
+        assert!(samples.iter().all(|s| s.timestamp_ns == samples[0].timestamp_ns));
+
+        // Unknown DAQ list
+        decoder.decode(0, &[0, 0, 1, 0, 5, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(recording.lock().is_empty());
+    }
+
+    #[test]
+    fn test_target_daq_decoder_timestamp_overflow() {
+        let recording = Arc::new(Mutex::new(XcpMergedRecording::new()));
+        let target = recording.lock().add_target("t1");
+        let mut decoder = XcpTargetDaqDecoder::new(target, Arc::clone(&recording));
+        decoder.set_daq_properties(u64::MAX / 2, 4);
+        decoder.start(vec![vec![odt_entry("a", 0, 1)]], u64::MAX / 2);
+        decoder.decode(0, &[0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 1]);
+        let samples = recording.lock().take_samples();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].value, 1.0);
+    }
+}
@@ -0,0 +1,266 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module raw_memory
+// Expert mode raw memory access with SET_MTA/UPLOAD/DOWNLOAD, for debugging targets without complete A2L coverage
+// There are no type or limit checks, every access must be confirmed explicitly by the caller with the confirmed parameter
+// The --read-mem and --write-mem options of the command line tool confirm the access with --yes-i-know
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+use std::fmt::Write;
+
+use xcp_registry::Registry;
+
+use super::XcpClient;
+use super::xcp::*;
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Parsing and formatting
+
+// Parse a decimal or 0x prefixed hexadecimal number
+fn parse_number(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    let res = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    };
+    res.map_err(|e| format!("invalid number '{}': {}", s, e))
+}
+
+/// Raw memory range ext:addr:len, e.g. "1:0x80001000:16"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XcpMemoryRange {
+    pub ext: u8,
+    pub addr: u32,
+    pub len: u32,
+}
+
+impl std::str::FromStr for XcpMemoryRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let [ext, addr, len] = parts[..] else {
+            return Err(format!("invalid memory range '{}', expected ext:addr:len", s));
+        };
+        Ok(XcpMemoryRange {
+            ext: u8::try_from(parse_number(ext)?).map_err(|_| format!("invalid address extension '{}'", ext))?,
+            addr: parse_number(addr)?,
+            len: parse_number(len)?,
+        })
+    }
+}
+
+/// Parse a memory address ext:addr, e.g. "1:0x80001000"
+pub fn parse_memory_address(s: &str) -> Result<(u8, u32), String> {
+    let Some((ext, addr)) = s.split_once(':') else {
+        return Err(format!("invalid memory address '{}', expected ext:addr", s));
+    };
+    let ext = u8::try_from(parse_number(ext)?).map_err(|_| format!("invalid address extension '{}'", ext))?;
+    Ok((ext, parse_number(addr)?))
+}
+
+/// Parse a string of hex bytes, e.g. "01 02 ff" or "0102ff"
+pub fn parse_hex_bytes(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    if digits.is_empty() || !digits.is_ascii() || !digits.len().is_multiple_of(2) {
+        return Err(format!("invalid hex bytes '{}'", s));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("invalid hex bytes '{}'", s)))
+        .collect()
+}
+
+/// Format data as hex dump with 16 bytes per line, address, hex bytes and printable ASCII characters
+#[allow(clippy::cast_possible_truncation)]
+pub fn dump_hex_data(addr: u32, data: &[u8]) -> String {
+    let mut s = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(s, "{:08X}: ", addr.wrapping_add(16 * i as u32));
+        for b in line {
+            let _ = write!(s, "{:02X} ", b);
+        }
+        s.push_str(&"   ".repeat(16 - line.len()));
+        s.extend(line.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }));
+        s.push('\n');
+    }
+    s
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Calibration segment inspection
+
+/// Format the content of a calibration segment as annotated hex dump
+/// Each field of the segment (the flattened typedef members and instances of the registry) is listed with offset, size, type and value bytes,
+/// gaps, overlapping fields and fields exceeding the segment are marked, followed by the complete hex dump
+pub fn format_cal_seg_dump(registry: &Registry, name: &str, data: &[u8]) -> Option<String> {
+    let segment = registry.cal_seg_list.into_iter().find(|s| s.get_name() == name)?;
+
+    // Fields in the address range of the segment, sorted by offset
+    let mut fields: Vec<(usize, usize, String, String)> = registry
+        .get_flattened_instance_list()
+        .into_iter()
+        .filter(|i| i.get_dim_type().is_basic_type())
+        .filter_map(|i| {
+            let (ext, addr) = i.get_address().get_a2l_addr(registry);
+            if ext != segment.addr_ext || addr < segment.addr || addr - segment.addr >= segment.size {
+                return None;
+            }
+            let [x_dim, y_dim] = i.get_dim_type().get_dim();
+            let type_str = match (x_dim, y_dim) {
+                (1, 1) => format!("{:?}", i.value_type()),
+                (x, 1) => format!("{:?}[{}]", i.value_type(), x),
+                (x, y) => format!("{:?}[{}][{}]", i.value_type(), y, x),
+            };
+            Some(((addr - segment.addr) as usize, i.get_dim_type().get_size(), i.get_name().to_string(), type_str))
+        })
+        .collect();
+    fields.sort_by_key(|f| f.0);
+
+    let mut s = String::new();
+    let _ = writeln!(s, "Calibration segment {} {}:0x{:08X} size {}", name, segment.addr_ext, segment.addr, segment.size);
+    let mut pos = 0;
+    for (offset, size, field_name, type_str) in &fields {
+        if *offset > pos {
+            let _ = writeln!(s, "  +{:04X} {:4} <gap>", pos, offset - pos);
+        }
+        let mark = if *offset < pos { " <overlap>" } else { "" };
+        let value = match data.get(*offset..offset + size) {
+            Some(bytes) => bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "),
+            None => "<exceeds segment>".to_string(),
+        };
+        let _ = writeln!(s, "  +{:04X} {:4} {:<40} {:<16} {}{}", offset, size, field_name, type_str, value, mark);
+        pos = pos.max(offset + size);
+    }
+    if data.len() > pos {
+        let _ = writeln!(s, "  +{:04X} {:4} <gap>", pos, data.len() - pos);
+    }
+    s.push_str(&dump_hex_data(segment.addr, data));
+    Some(s)
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Raw memory access
+
+impl XcpClient {
+    /// Read a raw memory range with SET_MTA and UPLOAD
+    /// Fails with ERROR_NOT_CONFIRMED unless confirmed
+    pub async fn read_memory(&mut self, range: XcpMemoryRange, confirmed: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+        if !confirmed {
+            error!("read_memory {}:0x{:08X}:{} refused, raw memory access must be confirmed", range.ext, range.addr, range.len);
+            return Err(Box::new(XcpError::new(ERROR_NOT_CONFIRMED, CC_UPLOAD)) as Box<dyn Error>);
+        }
+        self.set_mta(range.ext, range.addr).await?;
+        self.upload_memory_block(range.len).await
+    }
+
+    /// Write raw bytes to memory with SET_MTA and DOWNLOAD
+    /// Fails with ERROR_NOT_CONFIRMED unless confirmed
+    pub async fn write_memory(&mut self, ext: u8, addr: u32, data: &[u8], confirmed: bool) -> Result<(), Box<dyn Error>> {
+        if !confirmed {
+            error!("write_memory {}:0x{:08X}:{} refused, raw memory access must be confirmed", ext, addr, data.len());
+            return Err(Box::new(XcpError::new(ERROR_NOT_CONFIRMED, CC_DOWNLOAD)) as Box<dyn Error>);
+        }
+        warn!("Raw memory write {}:0x{:08X}: {:02X?}", ext, addr, data);
+        self.set_mta(ext, addr).await?;
+        self.download_memory_block(data).await
+    }
+
+    /// Upload a calibration segment of the registry and format it as annotated hex dump, see format_cal_seg_dump
+    pub async fn dump_cal_seg(&mut self, name: &str) -> Result<String, Box<dyn Error>> {
+        let Some(segment) = self.get_registry().cal_seg_list.into_iter().find(|s| s.get_name() == name) else {
+            error!("Calibration segment {} not found", name);
+            return Err(Box::new(XcpError::new(ERROR_NOT_FOUND, 0)) as Box<dyn Error>);
+        };
+        let (ext, addr, size) = (segment.addr_ext, segment.addr, segment.size);
+        self.set_mta(ext, addr).await?;
+        let data = self.upload_memory_block(size).await?;
+        Ok(format_cal_seg_dump(self.get_registry(), name, &data).unwrap_or_default())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod raw_memory_tests {
+
+    use super::*;
+    use xcp_registry::{McAddress, McDimType, McObjectType, McSupportData, McValueType};
+
+    #[test]
+    fn test_parse() {
+        let range: XcpMemoryRange = "1:0x80001000:16".parse().unwrap();
+        assert_eq!(
+            range,
+            XcpMemoryRange {
+                ext: 1,
+                addr: 0x80001000,
+                len: 16
+            }
+        );
+        assert_eq!("0:4096:0X10".parse::<XcpMemoryRange>().unwrap(), XcpMemoryRange { ext: 0, addr: 4096, len: 16 });
+        assert!("1:0x1000".parse::<XcpMemoryRange>().is_err());
+        assert!("256:0x1000:4".parse::<XcpMemoryRange>().is_err());
+        assert!("1:0xG000:4".parse::<XcpMemoryRange>().is_err());
+
+        assert_eq!(parse_memory_address("2:0x1234").unwrap(), (2, 0x1234));
+        assert!(parse_memory_address("0x1234").is_err());
+
+        assert_eq!(parse_hex_bytes("01 02 ff").unwrap(), [0x01, 0x02, 0xFF]);
+        assert_eq!(parse_hex_bytes("0102FF").unwrap(), [0x01, 0x02, 0xFF]);
+        assert!(parse_hex_bytes("").is_err());
+        assert!(parse_hex_bytes("012").is_err());
+        assert!(parse_hex_bytes("0x12").is_err());
+        assert!(parse_hex_bytes("ä1").is_err());
+    }
+
+    #[test]
+    fn test_dump_hex_data() {
+        let data: Vec<u8> = (0x40..0x52).collect();
+        let s = dump_hex_data(0x1000, &data);
+        let lines: Vec<&str> = s.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "00001000: 40 41 42 43 44 45 46 47 48 49 4A 4B 4C 4D 4E 4F @ABCDEFGHIJKLMNO");
+        assert_eq!(lines[1], format!("00001010: 50 51 {}PQ", "   ".repeat(14)));
+        assert_eq!(dump_hex_data(0, &[0x00, 0x20, 0x7F]), format!("00000000: 00 20 7F {}. .\n", "   ".repeat(13)));
+    }
+
+    #[test]
+    fn test_format_cal_seg_dump() {
+        let mut reg = Registry::new();
+        reg.cal_seg_list.add_cal_seg("seg", None, 12).unwrap();
+        let (ext, addr) = McAddress::get_calseg_ext_addr_base(0);
+        for (name, value_type, offset) in [("x", McValueType::Uword, 0), ("y", McValueType::Ulong, 4)] {
+            reg.instance_list
+                .add_instance(
+                    name,
+                    McDimType::new(value_type, 1, 1),
+                    McSupportData::new(McObjectType::Characteristic),
+                    McAddress::new_calseg_rel("seg", offset),
+                )
+                .unwrap();
+        }
+        let data: Vec<u8> = (0..12).collect();
+        let s = format_cal_seg_dump(&reg, "seg", &data).unwrap();
+        let lines: Vec<&str> = s.lines().collect();
+        assert_eq!(lines[0], format!("Calibration segment seg {}:0x{:08X} size 12", ext, addr));
+        assert!(lines[1].starts_with("  +0000    2 x") && lines[1].ends_with("00 01"));
+        assert_eq!(lines[2], "  +0002    2 <gap>");
+        assert!(lines[3].starts_with("  +0004    4 y") && lines[3].ends_with("04 05 06 07"));
+        assert_eq!(lines[4], "  +0008    4 <gap>");
+        assert_eq!(lines[5], format!("{:08X}: 00 01 02 03 04 05 06 07 08 09 0A 0B {}............", addr, "   ".repeat(4)));
+        assert!(format_cal_seg_dump(&reg, "unknown", &data).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_access_refused() {
+        let mut client = XcpClient::new(false, "127.0.0.1:5555".parse().unwrap(), "0.0.0.0:0".parse().unwrap());
+        let range = XcpMemoryRange { ext: 0, addr: 0x1000, len: 4 };
+        let e = client.read_memory(range, false).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<XcpError>().unwrap().get_error_code(), ERROR_NOT_CONFIRMED);
+        let e = client.write_memory(0, 0x1000, &[1, 2], false).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<XcpError>().unwrap().get_command(), CC_DOWNLOAD);
+    }
+}
@@ -0,0 +1,274 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module ring_recorder
+// Long duration recording into rotating segment files with bounded disk use
+// Decoded samples are written as CSV (timestamp_ns,name,value) into segment files <prefix>_<sequence>.csv, a new segment is started when the
+// segment duration or size limit is reached, only the last keep segments are kept. Segments of a previous run with the same prefix count
+// for the limit, the sequence number continues
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{OdtEntry, XcpDaqDecoder, XcpMergedRecording, XcpSample, XcpTargetDaqDecoder};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Segment files
+
+/// Write samples as CSV lines timestamp_ns,name,value
+pub fn write_samples_csv<W: Write>(writer: &mut W, samples: &[XcpSample]) -> std::io::Result<u64> {
+    let mut size = 0;
+    for s in samples {
+        let line = format!("{},{},{}\n", s.timestamp_ns, s.name, s.value);
+        writer.write_all(line.as_bytes())?;
+        size += line.len() as u64;
+    }
+    Ok(size)
+}
+
+pub(super) const CSV_HEADER: &str = "timestamp_ns,name,value\n";
+
+// Current segment file
+struct Segment {
+    writer: BufWriter<File>,
+    start_ns: i64,
+    size: u64,
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpRingRecorder
+
+/// DAQ decoder recording into rotating segment files
+/// Segments are rotated at max_duration of sample time or max_size bytes, whichever comes first, the oldest segments beyond keep are deleted
+pub struct XcpRingRecorder {
+    decoder: XcpTargetDaqDecoder,
+    recording: Arc<Mutex<XcpMergedRecording>>,
+    dir: PathBuf,
+    prefix: String,
+    keep: usize,
+    max_duration: Option<Duration>,
+    max_size: Option<u64>,
+    segments: VecDeque<PathBuf>, // Existing segment files, oldest first
+    sequence: u64,               // Sequence number of the next segment
+    segment: Option<Segment>,
+    failed: bool, // A file error stopped the recording
+}
+
+impl XcpRingRecorder {
+    /// Recorder writing segments <prefix>_<sequence>.csv into dir, keeping the last keep segments
+    pub fn new<P: AsRef<Path>>(dir: P, prefix: &str, keep: usize) -> std::io::Result<XcpRingRecorder> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        // Continue the sequence of existing segments
+        let mut existing: Vec<(u64, PathBuf)> = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let sequence = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix(prefix)?.strip_prefix('_')?.strip_suffix(".csv")?.parse::<u64>().ok());
+            if let Some(sequence) = sequence {
+                existing.push((sequence, path));
+            }
+        }
+        existing.sort();
+        let sequence = existing.last().map_or(0, |(s, _)| s + 1);
+
+        let recording = Arc::new(Mutex::new(XcpMergedRecording::new()));
+        let target = recording.lock().add_target(prefix);
+        Ok(XcpRingRecorder {
+            decoder: XcpTargetDaqDecoder::new(target, Arc::clone(&recording)),
+            recording,
+            dir,
+            prefix: prefix.to_string(),
+            keep: keep.max(1),
+            max_duration: None,
+            max_size: None,
+            segments: existing.into_iter().map(|(_, p)| p).collect(),
+            sequence,
+            segment: None,
+            failed: false,
+        })
+    }
+
+    /// Rotate after max_duration of sample time
+    pub fn set_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    /// Rotate after max_size bytes
+    pub fn set_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Segment files on disk, oldest first, the last one is the current segment
+    pub fn get_segments(&self) -> Vec<PathBuf> {
+        self.segments.iter().cloned().collect()
+    }
+
+    /// Check if a file error stopped the recording
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    // Check if the current segment is complete
+    fn is_segment_complete(&self, segment: &Segment, timestamp_ns: i64) -> bool {
+        self.max_duration
+            .is_some_and(|d| timestamp_ns - segment.start_ns >= i64::try_from(d.as_nanos()).unwrap_or(i64::MAX))
+            || self.max_size.is_some_and(|s| segment.size >= s)
+    }
+
+    // Close the current segment and start a new one, delete the oldest segments beyond keep
+    fn rotate(&mut self, start_ns: i64) -> std::io::Result<()> {
+        self.close()?;
+        let path = self.dir.join(format!("{}_{:06}.csv", self.prefix, self.sequence));
+        self.sequence += 1;
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writer.write_all(CSV_HEADER.as_bytes())?;
+        debug!("Ring recorder: new segment {}", path.display());
+        self.segments.push_back(path);
+        while self.segments.len() > self.keep {
+            if let Some(oldest) = self.segments.pop_front() {
+                debug!("Ring recorder: delete segment {}", oldest.display());
+                if let Err(e) = std::fs::remove_file(&oldest) {
+                    warn!("Ring recorder: could not delete {}: {}", oldest.display(), e);
+                }
+            }
+        }
+        self.segment = Some(Segment {
+            writer,
+            start_ns,
+            size: CSV_HEADER.len() as u64,
+        });
+        Ok(())
+    }
+
+    /// Flush and close the current segment
+    pub fn close(&mut self) -> std::io::Result<()> {
+        if let Some(mut segment) = self.segment.take() {
+            segment.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // Write the decoded samples into the segments
+    fn write_samples(&mut self, samples: &[XcpSample]) -> std::io::Result<()> {
+        for s in samples {
+            let complete = match &self.segment {
+                Some(segment) => self.is_segment_complete(segment, s.timestamp_ns),
+                None => true,
+            };
+            if complete {
+                self.rotate(s.timestamp_ns)?;
+            }
+            if let Some(segment) = &mut self.segment {
+                segment.size += write_samples_csv(&mut segment.writer, std::slice::from_ref(s))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl XcpDaqDecoder for XcpRingRecorder {
+    fn start(&mut self, odt_entries: Vec<Vec<OdtEntry>>, timestamp_raw64: u64) {
+        self.decoder.start(odt_entries, timestamp_raw64);
+    }
+
+    fn stop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("Ring recorder: {}", e);
+            self.failed = true;
+        }
+    }
+
+    fn set_daq_properties(&mut self, timestamp_resolution: u64, daq_header_size: u8) {
+        self.decoder.set_daq_properties(timestamp_resolution, daq_header_size);
+    }
+
+    fn set_daq_timestamp_size(&mut self, timestamp_size: u8) {
+        self.decoder.set_daq_timestamp_size(timestamp_size);
+    }
+
+    fn update_daq_list(&mut self, daq: u16, odt_entries: Vec<OdtEntry>) {
+        self.decoder.update_daq_list(daq, odt_entries);
+    }
+
+    fn decode(&mut self, lost: u32, data: &[u8]) {
+        self.decoder.decode(lost, data);
+        let samples = self.recording.lock().take_samples();
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.write_samples(&samples) {
+            error!("Ring recorder: recording stopped, {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod ring_recorder_tests {
+
+    use super::*;
+
+    fn samples(timestamps_ms: &[i64]) -> Vec<XcpSample> {
+        timestamps_ms
+            .iter()
+            .map(|t| XcpSample {
+                timestamp_ns: t * 1_000_000,
+                target: 0,
+                name: "counter".to_string(),
+                value: 1.0,
+            })
+            .collect()
+    }
+
+    fn file_names(recorder: &XcpRingRecorder) -> Vec<String> {
+        recorder.get_segments().iter().map(|p| p.file_name().unwrap().to_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn test_ring_recorder_duration() {
+        let dir = std::env::temp_dir().join(format!("xcp_client_ring_duration_{}", std::process::id()));
+        let mut recorder = XcpRingRecorder::new(&dir, "rec", 2).unwrap().set_max_duration(Duration::from_millis(100));
+        recorder.write_samples(&samples(&[0, 50, 100, 150, 200, 250, 300])).unwrap();
+        recorder.close().unwrap();
+
+        // Segments 0 (0, 50) and 1 (100, 150) are deleted, 2 (200, 250) and 3 (300) are kept
+        assert_eq!(file_names(&recorder), ["rec_000002.csv", "rec_000003.csv"]);
+        assert!(!dir.join("rec_000001.csv").exists());
+        let csv = std::fs::read_to_string(dir.join("rec_000002.csv")).unwrap();
+        assert_eq!(csv, "timestamp_ns,name,value\n200000000,counter,1\n250000000,counter,1\n");
+
+        // A new recorder continues the sequence, the existing segments count for keep
+        let mut recorder = XcpRingRecorder::new(&dir, "rec", 2).unwrap();
+        recorder.write_samples(&samples(&[0])).unwrap();
+        recorder.close().unwrap();
+        assert_eq!(file_names(&recorder), ["rec_000003.csv", "rec_000004.csv"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ring_recorder_size() {
+        let dir = std::env::temp_dir().join(format!("xcp_client_ring_size_{}", std::process::id()));
+        let line = "0,counter,1\n".len() as u64;
+        let mut recorder = XcpRingRecorder::new(&dir, "rec", 10).unwrap().set_max_size(CSV_HEADER.len() as u64 + 2 * line);
+        recorder.write_samples(&samples(&[0, 0, 0, 0, 0])).unwrap();
+        recorder.close().unwrap();
+        assert_eq!(file_names(&recorder), ["rec_000000.csv", "rec_000001.csv", "rec_000002.csv"]);
+        assert_eq!(std::fs::read_to_string(dir.join("rec_000002.csv")).unwrap(), "timestamp_ns,name,value\n0,counter,1\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
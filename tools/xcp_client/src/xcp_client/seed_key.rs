@@ -0,0 +1,143 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module seed_key
+// Unlock of protected resources with GET_SEED and UNLOCK
+// The key is computed from the seed by a XcpSeedKey implementation, a closure or a vendor seed and key shared library like in CANape,
+// which exports the ASAM function XCP_ComputeKeyFromSeed
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+
+use super::XcpClient;
+use super::commands::{GetSeed, GetStatus};
+use super::xcp::*;
+
+/// Key computation of a seed and key algorithm
+pub trait XcpSeedKey {
+    /// Compute the key for resource (one of RM_xxx) from seed
+    fn compute_key(&self, resource: u8, seed: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+impl<F: Fn(u8, &[u8]) -> Result<Vec<u8>, String>> XcpSeedKey for F {
+    fn compute_key(&self, resource: u8, seed: &[u8]) -> Result<Vec<u8>, String> {
+        self(resource, seed)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Vendor seed and key library
+
+/// Seed and key shared library (.so), with the function of the ASAM XCP seed and key DLL interface
+/// `XCP_ComputeKeyFromSeed(privilege, seed_len, seed, *key_len, key) -> 0 on success`
+#[cfg(target_os = "linux")]
+pub struct XcpSeedKeyLibrary {
+    handle: *mut libc::c_void,
+    compute_key: unsafe extern "C" fn(u8, u8, *const u8, *mut u8, *mut u8) -> u32,
+}
+
+#[cfg(target_os = "linux")]
+impl XcpSeedKeyLibrary {
+    /// Load a seed and key library
+    pub fn load<P: AsRef<std::path::Path>>(path: &P) -> Result<XcpSeedKeyLibrary, String> {
+        let path = path.as_ref();
+        let c_path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()).map_err(|e| e.to_string())?;
+        // SAFETY: dlopen and dlsym with valid C strings, the library initializers run here
+        unsafe {
+            let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(format!("{}: {}", path.display(), std::ffi::CStr::from_ptr(libc::dlerror()).to_string_lossy()));
+            }
+            let f = libc::dlsym(handle, c"XCP_ComputeKeyFromSeed".as_ptr());
+            if f.is_null() {
+                libc::dlclose(handle);
+                return Err(format!("{}: XCP_ComputeKeyFromSeed not found", path.display()));
+            }
+            Ok(XcpSeedKeyLibrary {
+                handle,
+                compute_key: std::mem::transmute::<*mut libc::c_void, unsafe extern "C" fn(u8, u8, *const u8, *mut u8, *mut u8) -> u32>(f),
+            })
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl XcpSeedKey for XcpSeedKeyLibrary {
+    fn compute_key(&self, resource: u8, seed: &[u8]) -> Result<Vec<u8>, String> {
+        let seed_len = u8::try_from(seed.len()).map_err(|_| "seed too long".to_string())?;
+        let mut key = vec![0u8; 255];
+        let mut key_len: u8 = 255; // Size of the key buffer in, length of the key out
+        // SAFETY: seed and key buffers with the given lengths
+        let res = unsafe { (self.compute_key)(resource, seed_len, seed.as_ptr(), &mut key_len, key.as_mut_ptr()) };
+        if res != 0 {
+            return Err(format!("XCP_ComputeKeyFromSeed failed with {}", res));
+        }
+        key.truncate(key_len as usize);
+        Ok(key)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for XcpSeedKeyLibrary {
+    fn drop(&mut self) {
+        // SAFETY: handle from dlopen, compute_key is not used after this
+        unsafe { libc::dlclose(self.handle) };
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpClient
+
+impl XcpClient {
+    /// Get the protection status of the resources, a set bit (RM_CAL_PAG, RM_DAQ, RM_STIM, RM_PGM) is a locked resource
+    pub async fn get_protection_status(&mut self) -> Result<u8, Box<dyn Error>> {
+        let data = self.send_typed(&GetStatus {}).await?;
+        Ok(data[2])
+    }
+
+    /// Unlock a resource (one of RM_CAL_PAG, RM_DAQ, RM_STIM, RM_PGM) with GET_SEED and UNLOCK, the key is computed by seed_key
+    /// Seed and key are transferred in several commands, if they exceed MAX_CTO
+    /// Returns the remaining locked resources, a resource which is not locked is not unlocked again
+    pub async fn unlock_resource(&mut self, resource: u8, seed_key: &dyn XcpSeedKey) -> Result<u8, Box<dyn Error>> {
+        // GET_SEED, mode 0 is the first part of the seed, mode 1 the remaining parts
+        let mut seed: Vec<u8> = Vec::new();
+        let mut mode = 0;
+        loop {
+            let data = self.send_typed(&GetSeed { mode, resource }).await?;
+            let remaining = data[1] as usize;
+            if remaining == 0 {
+                if mode == 0 {
+                    info!("Resource 0x{:02X} is not protected or already unlocked", resource);
+                    return self.get_protection_status().await;
+                }
+                break;
+            }
+            let part = &data[2..data.len().min(2 + remaining)];
+            seed.extend_from_slice(part);
+            if part.len() >= remaining {
+                break;
+            }
+            mode = 1;
+        }
+        debug!("GET_SEED resource=0x{:02X} seed={:02X?}", resource, seed);
+
+        let key = seed_key.compute_key(resource, &seed).map_err(|e| {
+            error!("Seed and key for resource 0x{:02X}: {}", resource, e);
+            Box::new(XcpError::new(CRC_ACCESS_LOCKED, CC_UNLOCK)) as Box<dyn Error>
+        })?;
+        let Ok(mut remaining) = u8::try_from(key.len()) else {
+            error!("Seed and key for resource 0x{:02X}: key too long", resource);
+            return Err(Box::new(XcpError::new(CRC_OUT_OF_RANGE, CC_UNLOCK)) as Box<dyn Error>);
+        };
+
+        // UNLOCK with the remaining key length and the next part of the key, the last response has the protection status
+        let mut status = 0;
+        for part in key.chunks((self.max_cto_size as usize).saturating_sub(2).max(1)) {
+            let data = self.send_command(XcpCommandBuilder::new(CC_UNLOCK).add_u8(remaining).add_u8_slice(part).build()).await?;
+            remaining -= u8::try_from(part.len()).expect("key length checked");
+            status = data[1];
+        }
+        info!("Unlock resource 0x{:02X}, locked resources 0x{:02X}", resource, status);
+        Ok(status)
+    }
+}
@@ -0,0 +1,329 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module sessions
+// Independent measurement sessions with their own signal sets, merged onto shared DAQ resources
+// Dynamic DAQ lists can only be allocated while DAQ is stopped, so start_measurement allocates spare DAQ lists, configured with set_daq_reserve
+// A session added while DAQ is running measures the signals, which are not measured yet, in spare DAQ lists started with START_STOP_DAQ_LIST,
+// the running DAQ lists are not touched. Signals already measured are shared with the running DAQ lists or other sessions
+// Configuring and starting a single DAQ list while other DAQ lists are running requires that the server accepts WRITE_DAQ, SET_DAQ_LIST_MODE
+// and START_STOP_DAQ_LIST in this state
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+use std::ops::Range;
+
+use super::commands::StartStopDaqList;
+use super::xcp::*;
+use super::{OdtEntry, XcpClient, XcpMeasurementObjectHandle};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpDaqReserve
+
+// Spare DAQ lists allocated by start_measurement, each with one ODT of entry_count ODT entries
+#[derive(Debug)]
+pub(super) struct XcpDaqReserve {
+    list_count: u16,
+    entry_count: u8,
+    spare_lists: Vec<(u16, bool)>, // DAQ list number, in use
+}
+
+impl XcpDaqReserve {
+    pub(super) fn new() -> XcpDaqReserve {
+        XcpDaqReserve {
+            list_count: 0,
+            entry_count: 0,
+            spare_lists: Vec::new(),
+        }
+    }
+
+    pub(super) fn get_list_count(&self) -> u16 {
+        self.list_count
+    }
+
+    pub(super) fn get_entry_count(&self) -> u8 {
+        self.entry_count
+    }
+
+    // Set the allocated spare DAQ lists, all unused
+    pub(super) fn set_spare_lists(&mut self, lists: Range<u16>) {
+        self.spare_lists = lists.map(|daq| (daq, false)).collect();
+    }
+
+    pub(super) fn acquire(&mut self) -> Option<u16> {
+        let spare = self.spare_lists.iter_mut().find(|(_, used)| !*used)?;
+        spare.1 = true;
+        Some(spare.0)
+    }
+
+    pub(super) fn release(&mut self, daq: u16) {
+        if let Some(spare) = self.spare_lists.iter_mut().find(|(d, _)| *d == daq) {
+            spare.1 = false;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpMeasurementSession
+
+/// Handle of a measurement session, index of the session in the XCP client
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct XcpSessionHandle(pub usize);
+
+#[derive(Debug)]
+pub(super) struct XcpMeasurementSession {
+    name: String,
+    signals: Vec<XcpMeasurementObjectHandle>,
+    daq_lists: Vec<u16>, // Spare DAQ lists owned by this session
+}
+
+impl XcpMeasurementSession {
+    // DAQ lists have been reallocated by start_measurement
+    pub(super) fn clear_daq_lists(&mut self) {
+        self.daq_lists.clear();
+    }
+}
+
+impl XcpClient {
+    /// Reserve spare DAQ lists for measurement sessions added while DAQ is running
+    /// Takes effect with the next start_measurement, each spare DAQ list measures up to entry_count signals of one event
+    pub fn set_daq_reserve(&mut self, list_count: u16, entry_count: u8) {
+        assert!(entry_count < 0x7C, "entry_count >= 0x7C");
+        self.daq_reserve.list_count = list_count;
+        self.daq_reserve.entry_count = entry_count;
+    }
+
+    /// Add a measurement session with its own signal set
+    /// Before start_measurement, the signals are measured together with all other measurement objects
+    /// While DAQ is running, the signals which are not measured yet are measured in one spare DAQ list for each event, the running DAQ lists continue
+    /// Returns an error, if a signal is not found or if the spare DAQ lists are exhausted, the session is not added then
+    pub async fn add_session(&mut self, name: &str, signals: &[&str]) -> Result<XcpSessionHandle, Box<dyn Error>> {
+        let first = self.measurement_object_list.len();
+        let mut handles = Vec::with_capacity(signals.len());
+        for signal in signals {
+            match self.create_measurement_object(signal) {
+                Some(h) => handles.push(h),
+                None => {
+                    error!("Session {}: measurement {} not found", name, signal);
+                    self.measurement_object_list.truncate(first);
+                    return Err(Box::new(XcpError::new(ERROR_A2L, 0)) as Box<dyn Error>);
+                }
+            }
+        }
+
+        let mut daq_lists = Vec::new();
+        if self.task_control.running {
+            // Share signals which are already measured, collect the events of the remaining signals
+            let mut events: Vec<u16> = Vec::new();
+            for i in first..self.measurement_object_list.len() {
+                let a = self.measurement_object_list[i].a2l_addr;
+                let measured = self.measurement_object_list[..first]
+                    .iter()
+                    .find(|m| m.measured && m.a2l_addr.ext == a.ext && m.a2l_addr.addr == a.addr && m.a2l_addr.event == a.event)
+                    .map(|m| (m.daq, m.odt, m.offset));
+                let m = &mut self.measurement_object_list[i];
+                if let Some((daq, odt, offset)) = measured {
+                    (m.daq, m.odt, m.offset, m.measured) = (daq, odt, offset, true);
+                    debug!("Session {}: {} shared with daq={}", name, m.name, daq);
+                } else if !events.contains(&a.event.unwrap()) {
+                    events.push(a.event.unwrap());
+                }
+            }
+
+            // Configure and start a spare DAQ list for each event
+            for event in events {
+                let res = match self.daq_reserve.acquire() {
+                    Some(daq) => {
+                        daq_lists.push(daq);
+                        self.start_spare_daq_list(daq, event, first).await
+                    }
+                    None => {
+                        error!("Session {}: no spare DAQ list left for event {}, see set_daq_reserve", name, event);
+                        Err(Box::new(XcpError::new(ERROR_LIMIT, CC_START_STOP_DAQ_LIST)) as Box<dyn Error>)
+                    }
+                };
+                if let Err(e) = res {
+                    for daq in daq_lists {
+                        let _ = self.stop_spare_daq_list(daq).await;
+                    }
+                    self.measurement_object_list.truncate(first);
+                    return Err(e);
+                }
+            }
+        }
+
+        info!("Add session {}: {} signals, spare DAQ lists {:?}", name, handles.len(), daq_lists);
+        self.sessions.push(Some(XcpMeasurementSession {
+            name: name.to_string(),
+            signals: handles,
+            daq_lists,
+        }));
+        Ok(XcpSessionHandle(self.sessions.len() - 1))
+    }
+
+    /// Get the measurement objects of a session, in the order of the signal names given to add_session
+    pub fn get_session_signals(&self, session: XcpSessionHandle) -> &[XcpMeasurementObjectHandle] {
+        self.sessions.get(session.0).and_then(Option::as_ref).map_or(&[], |s| s.signals.as_slice())
+    }
+
+    /// Remove a measurement session
+    /// While DAQ is running, the spare DAQ lists of the session are stopped and become available for new sessions
+    /// Spare DAQ lists with signals shared by other sessions are handed over to one of these sessions and keep running
+    /// Before start_measurement, the measurement objects of the session remain in the measurement object list
+    pub async fn remove_session(&mut self, session: XcpSessionHandle) -> Result<(), Box<dyn Error>> {
+        let Some(s) = self.sessions.get_mut(session.0).and_then(Option::take) else {
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, 0)) as Box<dyn Error>);
+        };
+        let mut res = Ok(());
+        for daq in s.daq_lists {
+            let objects = &self.measurement_object_list;
+            let owner = self
+                .sessions
+                .iter_mut()
+                .flatten()
+                .find(|other| other.signals.iter().any(|h| objects[h.0].measured && objects[h.0].daq == daq));
+            if let Some(owner) = owner {
+                debug!("Session {}: daq={} handed over to session {}", s.name, daq, owner.name);
+                owner.daq_lists.push(daq);
+            } else if let Err(e) = self.stop_spare_daq_list(daq).await {
+                res = Err(e);
+            }
+        }
+        info!("Remove session {}", s.name);
+        res
+    }
+
+    // Configure a spare DAQ list with the unmeasured signals of event, starting at measurement object index first, and start it
+    async fn start_spare_daq_list(&mut self, daq: u16, event: u16, first: usize) -> Result<(), Box<dyn Error>> {
+        let dto_header_size = self.daq_header_size as u16 + self.capabilities.daq_timestamp_size as u16;
+        let entry_count = self.daq_reserve.get_entry_count() as usize;
+        let mut odt_entries = Vec::new();
+        let mut odt_size: u16 = 0;
+        self.set_daq_ptr(daq, 0, 0).await?;
+        for i in first..self.measurement_object_list.len() {
+            let m = &self.measurement_object_list[i];
+            if m.measured || m.a2l_addr.event != Some(event) {
+                continue;
+            }
+            let (name, a2l_addr, a2l_type, conversion, dim) = (m.name.clone(), m.a2l_addr, m.a2l_type, m.conversion, m.dim);
+            if odt_entries.len() >= entry_count {
+                error!("{}: more than {} signals of event {} in a spare DAQ list, see set_daq_reserve", name, entry_count, event);
+                return Err(Box::new(XcpError::new(ERROR_LIMIT, CC_WRITE_DAQ)) as Box<dyn Error>);
+            }
+            let Some(size) = u8::try_from(m.get_size()).ok().filter(|size| self.capabilities.check_odt_entry_size(usize::from(*size))) else {
+                error!("{}: size {} not supported by DAQ granularity or max ODT entry size", name, m.get_size());
+                return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+            };
+            if odt_size + u16::from(size) > self.max_dto_size - dto_header_size {
+                return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+            }
+            debug!("WRITE_DAQ {} daq={}, odt=0, size={}, ext={}, addr=0x{:08X}", name, daq, size, a2l_addr.ext, a2l_addr.addr);
+            self.write_daq(a2l_addr.ext, a2l_addr.addr, size).await?;
+
+            let m = &mut self.measurement_object_list[i];
+            (m.daq, m.odt, m.offset, m.measured) = (daq, 0, odt_size + dto_header_size, true);
+            odt_entries.push(OdtEntry {
+                name,
+                a2l_type,
+                a2l_addr,
+                odt: 0,
+                offset: odt_size,
+                conversion,
+                dim,
+            });
+            odt_size += u16::from(size);
+        }
+
+        // Unused ODT entries of the spare DAQ list are written with size 0
+        for _ in odt_entries.len()..entry_count {
+            self.write_daq(0, 0, 0).await?;
+        }
+        self.set_daq_list_mode(daq, event).await?;
+
+        // Update the DAQ decoder before the first DAQ packet of this list arrives
+        self.daq_decoder.as_ref().unwrap().lock().update_daq_list(daq, odt_entries);
+        self.daq_loss.lock().set_daq_event(daq, Some(event));
+        self.start_stop_daq_list(1 /* start */, daq).await?;
+        Ok(())
+    }
+
+    // Stop a spare DAQ list and make it available for new sessions
+    async fn stop_spare_daq_list(&mut self, daq: u16) -> Result<(), Box<dyn Error>> {
+        let res = self.start_stop_daq_list(0 /* stop */, daq).await;
+        self.daq_decoder.as_ref().unwrap().lock().update_daq_list(daq, Vec::new());
+        self.daq_loss.lock().set_daq_event(daq, None);
+        for m in self.measurement_object_list.iter_mut().filter(|m| m.measured && m.daq == daq) {
+            m.measured = false;
+        }
+        self.daq_reserve.release(daq);
+        res
+    }
+
+    // START_STOP_DAQ_LIST for a single DAQ list, mode 0 = stop, 1 = start, 2 = select
+    pub(super) async fn start_stop_daq_list(&mut self, mode: u8, daq: u16) -> Result<(), Box<dyn Error>> {
+        self.send_typed(&StartStopDaqList { mode, daq }).await?;
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod sessions_tests {
+
+    use super::*;
+    use xcp_registry::{McAddress, McDimType, McObjectType, McSupportData, McValueType};
+
+    fn client() -> XcpClient {
+        let mut reg = xcp_registry::Registry::new();
+        for (name, addr) in [("a", 0x1000), ("b", 0x1004)] {
+            reg.instance_list
+                .add_instance(
+                    name,
+                    McDimType::new(McValueType::Ulong, 1, 1),
+                    McSupportData::new(McObjectType::Measurement),
+                    McAddress::new_a2l_with_event(1, addr, 0),
+                )
+                .unwrap();
+        }
+        let mut client = XcpClient::new(false, "127.0.0.1:5555".parse().unwrap(), "0.0.0.0:0".parse().unwrap());
+        client.set_registry(reg);
+        client
+    }
+
+    #[test]
+    fn test_daq_reserve() {
+        let mut reserve = XcpDaqReserve::new();
+        assert_eq!(reserve.acquire(), None);
+        reserve.set_spare_lists(3..5);
+        assert_eq!(reserve.acquire(), Some(3));
+        assert_eq!(reserve.acquire(), Some(4));
+        assert_eq!(reserve.acquire(), None);
+        reserve.release(3);
+        reserve.release(7);
+        assert_eq!(reserve.acquire(), Some(3));
+
+        let mut client = client();
+        client.set_daq_reserve(2, 8);
+        assert_eq!((client.daq_reserve.get_list_count(), client.daq_reserve.get_entry_count()), (2, 8));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_before_start() {
+        let mut client = client();
+        let s1 = client.add_session("s1", &["a", "b"]).await.unwrap();
+        let s2 = client.add_session("s2", &["b"]).await.unwrap();
+        assert_eq!(client.get_session_signals(s1).iter().map(|h| h.0).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(client.get_session_signals(s2).iter().map(|h| h.0).collect::<Vec<_>>(), [2]);
+
+        // A session with an unknown signal is not added, its measurement objects are removed
+        assert!(client.add_session("s3", &["a", "unknown"]).await.is_err());
+        assert_eq!(client.measurement_object_list.len(), 3);
+
+        client.remove_session(s1).await.unwrap();
+        assert!(client.get_session_signals(s1).is_empty());
+        assert!(client.remove_session(s1).await.is_err());
+        assert_eq!(client.get_session_signals(s2).len(), 1);
+    }
+}
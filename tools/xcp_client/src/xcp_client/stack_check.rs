@@ -0,0 +1,162 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module stack_check
+// Validation of event relative (stack) variables against a live target
+// The addresses of local variables are CFA offsets computed from the DWARF debug information, a wrong CFA offset of an event measures
+// another part of the stack frame without any error. A few DAQ samples of each event relative variable are checked for marker values
+// and plausibility (NaN, denormal or huge floats, bools other than 0/1, values outside the A2L limits), before the data is trusted
+// The --verify-stack-addressing option of the command line client belongs to the standalone tool in xcplib/tools/xcpclient
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use xcp_registry::{McAddress, McValueType};
+
+use super::{XcpClient, XcpMergedRecording};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Plausibility check
+
+/// Result of the check of an event relative variable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XcpStackCheckResult {
+    Ok,
+    NoSamples,      // Event not triggered during the check or variable not measured
+    MarkerMismatch, // The expected marker value was never seen
+    NotFinite,      // Float NaN or infinite
+    Garbage,        // Denormal or huge float, bool other than 0/1
+    OutOfRange,     // Outside the A2L limits
+}
+
+/// Check of an event relative variable, see XcpClient::verify_stack_addressing
+#[derive(Debug, Clone)]
+pub struct XcpStackCheck {
+    pub name: String,
+    pub event: u16,
+    pub samples: usize,
+    pub result: XcpStackCheckResult,
+    pub value: Option<f64>, // First suspicious value, or the last value if ok
+}
+
+impl XcpStackCheck {
+    /// The variable is likely measured from a wrong stack location
+    pub fn is_suspicious(&self) -> bool {
+        !matches!(self.result, XcpStackCheckResult::Ok | XcpStackCheckResult::NoSamples)
+    }
+}
+
+/// Check the raw sample values of a variable with the type value_type and the A2L limits min and max
+/// With a marker, at least one sample must have the marker value, the target application writes it to the variable
+/// Returns the result and the first suspicious value or the last value
+pub fn check_stack_values(value_type: &McValueType, min: Option<f64>, max: Option<f64>, values: &[f64], marker: Option<f64>) -> (XcpStackCheckResult, Option<f64>) {
+    for &v in values {
+        let result = match value_type {
+            McValueType::Float32Ieee | McValueType::Float64Ieee if !v.is_finite() => XcpStackCheckResult::NotFinite,
+            McValueType::Float32Ieee | McValueType::Float64Ieee if v != 0.0 && !(1e-30..=1e30).contains(&v.abs()) => XcpStackCheckResult::Garbage,
+            McValueType::Bool if v != 0.0 && v != 1.0 => XcpStackCheckResult::Garbage,
+            _ if min.is_some_and(|min| v < min) || max.is_some_and(|max| v > max) => XcpStackCheckResult::OutOfRange,
+            _ => continue,
+        };
+        return (result, Some(v));
+    }
+    match (values.last(), marker) {
+        (None, _) => (XcpStackCheckResult::NoSamples, None),
+        (Some(_), Some(marker)) if !values.contains(&marker) => (XcpStackCheckResult::MarkerMismatch, values.last().copied()),
+        (Some(v), _) => (XcpStackCheckResult::Ok, Some(*v)),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpClient
+
+impl XcpClient {
+    /// Get the names of all event relative (stack) measurement variables of the registry, typedef instances are flattened
+    pub fn get_stack_signals(&self) -> Vec<String> {
+        let Some(registry) = self.registry.as_ref() else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = Vec::new();
+        for instance in &registry.get_flattened_instance_list() {
+            // Registries loaded from A2L have raw A2L addresses, event relative is recognized by the address extension
+            let event_relative =
+                instance.get_address().is_event_relative() || (instance.event_id().is_some() && instance.get_address().get_a2l_addr(registry).0 == McAddress::XCP_ADDR_EXT_DYN);
+            if instance.is_measurement_object()
+                && event_relative
+                && !matches!(instance.value_type(), McValueType::Blob(_) | McValueType::TypeDef(_) | McValueType::Unknown)
+                && !names.iter().any(|n| n == instance.get_name())
+            {
+                names.push(instance.get_name().to_string());
+            }
+        }
+        names
+    }
+
+    /// Measure all event relative variables for duration and check the raw values, markers are expected values (name -> raw value)
+    /// The client must be connected with a XcpTargetDaqDecoder for target recording into recording, not in physical mode
+    /// DAQ must be stopped, the measurement object list is replaced, the samples of target are taken from the recording
+    /// Returns one check per variable, suspicious variables are logged with their event
+    pub async fn verify_stack_addressing(
+        &mut self,
+        recording: &Arc<Mutex<XcpMergedRecording>>,
+        target: usize,
+        duration: Duration,
+        markers: &HashMap<String, f64>,
+    ) -> Result<Vec<XcpStackCheck>, Box<dyn Error>> {
+        let names = self.get_stack_signals();
+        info!("Verify stack addressing of {} variables for {:?}", names.len(), duration);
+        self.measurement_object_list.clear();
+        let handles: Vec<_> = names.iter().filter_map(|name| self.create_measurement_object(name)).collect();
+        if handles.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.start_measurement().await?;
+        tokio::time::sleep(duration).await;
+        self.stop_measurement().await?;
+
+        let mut values: HashMap<&str, Vec<f64>> = HashMap::new();
+        let samples = recording.lock().take_samples();
+        for s in samples.iter().filter(|s| s.target == target) {
+            values.entry(s.name.as_str()).or_default().push(s.value);
+        }
+
+        let registry = self.registry.as_ref().unwrap();
+        let mut checks = Vec::with_capacity(names.len());
+        for name in &names {
+            let Some(instance) = registry.resolve_instance(name, xcp_registry::McObjectType::Measurement, None) else {
+                continue;
+            };
+            let Some(event) = instance.event_id() else {
+                continue;
+            };
+            // Explicit A2L limits are physical values, they apply to the raw values only without conversion
+            let mc_support_data = instance.get_mc_support_data();
+            let (min, max) = if mc_support_data.get_factor().is_none() && mc_support_data.get_offset().is_none() {
+                (mc_support_data.min, mc_support_data.max)
+            } else {
+                (None, None)
+            };
+            let v = values.get(name.as_str()).map_or(&[][..], Vec::as_slice);
+            let (result, value) = check_stack_values(instance.value_type(), min, max, v, markers.get(name).copied());
+            let check = XcpStackCheck {
+                name: name.clone(),
+                event,
+                samples: v.len(),
+                result,
+                value,
+            };
+            if check.is_suspicious() {
+                warn!("{}: {:?} value={:?}, CFA offset of event {} may be wrong", check.name, check.result, check.value, event);
+            } else {
+                debug!("{}: {:?} samples={}", check.name, check.result, check.samples);
+            }
+            checks.push(check);
+        }
+        Ok(checks)
+    }
+}
@@ -0,0 +1,176 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module stim
+// Stimulation (STIM) of signals of a bidirectional event (DAQ_STIM), for HIL-style bypassing
+// A STIM DAQ list is a spare DAQ list (see set_daq_reserve) configured with direction STIM, the client sends its ODT as DTO packet
+// and the server writes the values to the ODT entry addresses when the event is triggered
+// STIM DTOs have the same identification field as DAQ DTOs and no timestamp
+// xcp_lite servers do not process STIM DTOs, xcplib has no STIM support
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+
+use super::commands::SetDaqListMode;
+use super::xcp::*;
+use super::{A2lType, XcpClient, f64_to_value};
+
+/// A started STIM DAQ list, created by start_stimulation
+#[derive(Debug)]
+pub struct XcpStimHandle {
+    daq: u16,
+    event: u16,
+    entries: Vec<(String, A2lType)>, // Signal name and type, in ODT order
+}
+
+impl XcpStimHandle {
+    /// Get the event which applies the stimulated values
+    pub fn get_event(&self) -> u16 {
+        self.event
+    }
+
+    /// Get the stimulated signal names, in the order of the values of write_stim
+    pub fn get_signals(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+impl XcpClient {
+    /// Start the stimulation of signals, which must all belong to the same bidirectional event
+    /// Requires running DAQ and a free spare DAQ list, see set_daq_reserve, the number of signals is limited by its entry count
+    /// Returns an error, if a signal is not found, if the signals belong to different events or if the ODT does not fit into a DTO
+    pub async fn start_stimulation(&mut self, signals: &[&str]) -> Result<XcpStimHandle, Box<dyn Error>> {
+        if !self.task_control.running {
+            error!("start_stimulation: DAQ is not running");
+            return Err(Box::new(XcpError::new(ERROR_GENERIC, CC_START_STOP_DAQ_LIST)) as Box<dyn Error>);
+        }
+
+        // Resolve the signals
+        let registry = self.registry.as_ref().unwrap();
+        let mut event = None;
+        let mut odt_entries = Vec::with_capacity(signals.len());
+        for signal in signals {
+            let Some(instance) = registry.resolve_instance(signal, xcp_registry::McObjectType::Measurement, None) else {
+                error!("start_stimulation: measurement {} not found", signal);
+                return Err(Box::new(XcpError::new(ERROR_A2L, 0)) as Box<dyn Error>);
+            };
+            if event.is_some() && instance.event_id() != event {
+                error!("start_stimulation: {} does not belong to event {:?}", signal, event);
+                return Err(Box::new(XcpError::new(ERROR_A2L, 0)) as Box<dyn Error>);
+            }
+            event = instance.event_id();
+            let (ext, addr) = instance.get_address().get_a2l_addr(registry);
+            let a2l_type = A2lType {
+                size: instance.value_size(),
+                encoding: instance.value_type().into(),
+            };
+            odt_entries.push((signal.to_string(), a2l_type, ext, addr));
+        }
+        let Some(event) = event else {
+            error!("start_stimulation: no signals or no event");
+            return Err(Box::new(XcpError::new(ERROR_A2L, 0)) as Box<dyn Error>);
+        };
+
+        // Check the ODT size
+        let entry_count = self.daq_reserve.get_entry_count() as usize;
+        let odt_size: usize = odt_entries.iter().map(|(_, t, _, _)| t.size).sum();
+        if odt_entries.len() > entry_count || odt_size > usize::from(self.max_dto_size - u16::from(self.daq_header_size)) {
+            error!(
+                "start_stimulation: {} signals with {} bytes exceed a spare DAQ list, see set_daq_reserve",
+                odt_entries.len(),
+                odt_size
+            );
+            return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+        }
+        if let Some((name, t, _, _)) = odt_entries.iter().find(|(_, t, _, _)| !self.capabilities.check_odt_entry_size(t.size)) {
+            error!("{}: size {} not supported by DAQ granularity or max ODT entry size", name, t.size);
+            return Err(Box::new(XcpError::new(ERROR_ODT_SIZE, 0)) as Box<dyn Error>);
+        }
+
+        // Configure and start a spare DAQ list with direction STIM
+        let Some(daq) = self.daq_reserve.acquire() else {
+            error!("start_stimulation: no spare DAQ list left for event {}, see set_daq_reserve", event);
+            return Err(Box::new(XcpError::new(ERROR_LIMIT, CC_START_STOP_DAQ_LIST)) as Box<dyn Error>);
+        };
+        let res = self.start_stim_daq_list(daq, event, &odt_entries, entry_count).await;
+        if let Err(e) = res {
+            let _ = self.start_stop_daq_list(0 /* stop */, daq).await;
+            self.daq_reserve.release(daq);
+            return Err(e);
+        }
+
+        info!("Start stimulation of event {}: {} signals, daq={}", event, odt_entries.len(), daq);
+        Ok(XcpStimHandle {
+            daq,
+            event,
+            entries: odt_entries.into_iter().map(|(name, a2l_type, _, _)| (name, a2l_type)).collect(),
+        })
+    }
+
+    // Write the ODT entries of a spare DAQ list, set direction STIM and start it
+    async fn start_stim_daq_list(&mut self, daq: u16, event: u16, odt_entries: &[(String, A2lType, u8, u32)], entry_count: usize) -> Result<(), Box<dyn Error>> {
+        const XCP_DAQ_MODE_DIRECTION_STIM: u8 = 0x02;
+        self.set_daq_ptr(daq, 0, 0).await?;
+        for (name, a2l_type, ext, addr) in odt_entries {
+            let size = u8::try_from(a2l_type.size).expect("xcp_client currently supports only <256 byte values");
+            debug!("WRITE_DAQ STIM {} daq={}, odt=0, size={}, ext={}, addr=0x{:08X}", name, daq, size, ext, addr);
+            self.write_daq(*ext, *addr, size).await?;
+        }
+        // Unused ODT entries of the spare DAQ list are written with size 0
+        for _ in odt_entries.len()..entry_count {
+            self.write_daq(0, 0, 0).await?;
+        }
+        self.send_typed(&SetDaqListMode {
+            mode: XCP_DAQ_MODE_DIRECTION_STIM, // No timestamp
+            daq,
+            event,
+            prescaler: 1,
+            priority: 0,
+        })
+        .await?;
+        self.start_stop_daq_list(1 /* start */, daq).await
+    }
+
+    /// Send values for the signals of a STIM DAQ list, in the order of the signal names given to start_stimulation
+    /// The values are physical values, encoded with the type of each signal, the event applies the last values received
+    pub async fn write_stim(&mut self, stim: &XcpStimHandle, values: &[f64]) -> Result<(), Box<dyn Error>> {
+        if values.len() != stim.entries.len() {
+            error!("write_stim: {} values for {} signals", values.len(), stim.entries.len());
+            return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+        }
+
+        // Identification field of the DTO, ODT 0 of the DAQ list
+        let daq = stim.daq.to_le_bytes();
+        let mut dto: Vec<u8> = match self.daq_header_size {
+            4 => vec![0, 0, daq[0], daq[1]],
+            3 => vec![0, daq[0], daq[1]],
+            _ => vec![0, daq[0]],
+        };
+        for ((name, a2l_type), value) in stim.entries.iter().zip(values) {
+            let Some(bytes) = f64_to_value(*a2l_type, *value) else {
+                error!("write_stim: {} has unsupported type {:?}", name, a2l_type);
+                return Err(Box::new(XcpError::new(ERROR_TYPE_MISMATCH, 0)) as Box<dyn Error>);
+            };
+            dto.extend_from_slice(&bytes);
+        }
+
+        // Transport layer header, like XcpCommandBuilder
+        let len = u16::try_from(dto.len()).expect("DTO size checked by start_stimulation");
+        let mut buf = Vec::with_capacity(4 + dto.len());
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&dto);
+        trace!("write_stim: daq={}, {:?}", stim.daq, buf);
+        let socket = self.socket.as_ref().unwrap();
+        socket.send_to(&buf, self.dest_addr).await?;
+        Ok(())
+    }
+
+    /// Stop a STIM DAQ list, the spare DAQ list becomes available for new sessions or stimulations
+    pub async fn stop_stimulation(&mut self, stim: XcpStimHandle) -> Result<(), Box<dyn Error>> {
+        let res = self.start_stop_daq_list(0 /* stop */, stim.daq).await;
+        self.daq_reserve.release(stim.daq);
+        info!("Stop stimulation of event {}, daq={}", stim.event, stim.daq);
+        res
+    }
+}
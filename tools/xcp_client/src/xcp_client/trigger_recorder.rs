@@ -0,0 +1,293 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module trigger_recorder
+// Trigger based recording, like a scope trigger for field debugging
+// Decoded samples of the last pre trigger time are buffered in memory, when the trigger condition fires, the buffer and the samples until the
+// post trigger time are written into a capture file <prefix>_<n>.csv (timestamp_ns,name,value). The trigger is armed again after the capture
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::ring_recorder::{CSV_HEADER, write_samples_csv};
+use super::{OdtEntry, XcpDaqDecoder, XcpMergedRecording, XcpSample, XcpTargetDaqDecoder};
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpTriggerCondition
+
+/// Trigger condition on decoded samples
+#[derive(Debug, Clone, PartialEq)]
+pub enum XcpTriggerCondition {
+    /// Signal crosses the threshold upwards
+    Rising { signal: String, threshold: f64 },
+    /// Signal crosses the threshold downwards
+    Falling { signal: String, threshold: f64 },
+    /// Any sample of the master event, e.g. an event triggered by the application on a fault
+    Event(u16),
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpTriggerRecorder
+
+// Active capture after a trigger
+struct Capture {
+    writer: BufWriter<File>,
+    end_ns: i64,
+}
+
+/// DAQ decoder recording pre and post trigger windows into capture files
+pub struct XcpTriggerRecorder {
+    decoder: XcpTargetDaqDecoder,
+    recording: Arc<Mutex<XcpMergedRecording>>,
+    dir: PathBuf,
+    prefix: String,
+    condition: XcpTriggerCondition,
+    pre_ns: i64,
+    post_ns: i64,
+    buffer: VecDeque<XcpSample>,         // Samples of the last pre trigger time
+    last_values: HashMap<String, f64>,   // Last value of the trigger signal, for crossing detection
+    signal_events: HashMap<String, u16>, // Event of each measured signal, for the master event condition
+    capture: Option<Capture>,
+    captures: Vec<(i64, PathBuf)>, // Trigger time and file of each capture
+    failed: bool,                  // A file error stopped the recording
+}
+
+impl XcpTriggerRecorder {
+    /// Recorder writing captures <prefix>_<n>.csv into dir, with pre trigger and post trigger time
+    pub fn new<P: AsRef<Path>>(dir: P, prefix: &str, condition: XcpTriggerCondition, pre: Duration, post: Duration) -> std::io::Result<XcpTriggerRecorder> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let recording = Arc::new(Mutex::new(XcpMergedRecording::new()));
+        let target = recording.lock().add_target(prefix);
+        Ok(XcpTriggerRecorder {
+            decoder: XcpTargetDaqDecoder::new(target, Arc::clone(&recording)),
+            recording,
+            dir,
+            prefix: prefix.to_string(),
+            condition,
+            pre_ns: i64::try_from(pre.as_nanos()).unwrap_or(i64::MAX),
+            post_ns: i64::try_from(post.as_nanos()).unwrap_or(i64::MAX),
+            buffer: VecDeque::new(),
+            last_values: HashMap::new(),
+            signal_events: HashMap::new(),
+            capture: None,
+            captures: Vec::new(),
+            failed: false,
+        })
+    }
+
+    /// Trigger time in ns and file of each capture, the last one may still be recording
+    pub fn get_captures(&self) -> &[(i64, PathBuf)] {
+        &self.captures
+    }
+
+    /// Check if the trigger is armed, no capture is recording
+    pub fn is_armed(&self) -> bool {
+        self.capture.is_none()
+    }
+
+    /// Check if a file error stopped the recording
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    // Check the trigger condition for a sample
+    fn is_triggered(&mut self, s: &XcpSample) -> bool {
+        match &self.condition {
+            XcpTriggerCondition::Rising { signal, threshold } | XcpTriggerCondition::Falling { signal, threshold } if *signal == s.name => {
+                let last = self.last_values.insert(s.name.clone(), s.value);
+                match (&self.condition, last) {
+                    (XcpTriggerCondition::Rising { .. }, Some(last)) => last < *threshold && s.value >= *threshold,
+                    (XcpTriggerCondition::Falling { .. }, Some(last)) => last > *threshold && s.value <= *threshold,
+                    _ => false,
+                }
+            }
+            XcpTriggerCondition::Event(event) => self.signal_events.get(&s.name) == Some(event),
+            _ => false,
+        }
+    }
+
+    // Start a capture with the pre trigger buffer
+    fn start_capture(&mut self, trigger_ns: i64) -> std::io::Result<()> {
+        let path = self.dir.join(format!("{}_{}.csv", self.prefix, self.captures.len()));
+        info!("Trigger recorder: triggered at {} ns, capture {}", trigger_ns, path.display());
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writer.write_all(CSV_HEADER.as_bytes())?;
+        let pre: Vec<XcpSample> = self.buffer.drain(..).collect();
+        write_samples_csv(&mut writer, &pre)?;
+        self.capture = Some(Capture {
+            writer,
+            end_ns: trigger_ns.saturating_add(self.post_ns),
+        });
+        self.captures.push((trigger_ns, path));
+        Ok(())
+    }
+
+    // Flush and close the current capture, the trigger is armed again
+    fn close_capture(&mut self) -> std::io::Result<()> {
+        if let Some(mut capture) = self.capture.take() {
+            capture.writer.flush()?;
+            debug!("Trigger recorder: capture complete");
+        }
+        Ok(())
+    }
+
+    // Buffer, trigger and capture the decoded samples
+    fn process_samples(&mut self, samples: Vec<XcpSample>) -> std::io::Result<()> {
+        for s in samples {
+            if let Some(capture) = &mut self.capture {
+                if s.timestamp_ns <= capture.end_ns {
+                    write_samples_csv(&mut capture.writer, std::slice::from_ref(&s))?;
+                    self.is_triggered(&s); // Track the trigger signal, no retrigger during a capture
+                    continue;
+                }
+                self.close_capture()?;
+            }
+            let triggered = self.is_triggered(&s);
+            self.buffer.push_back(s);
+            if triggered {
+                let trigger_ns = self.buffer.back().map_or(0, |s| s.timestamp_ns);
+                self.start_capture(trigger_ns)?;
+            } else if let Some(latest) = self.buffer.back().map(|s| s.timestamp_ns) {
+                while self.buffer.front().is_some_and(|s| latest - s.timestamp_ns > self.pre_ns) {
+                    self.buffer.pop_front();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl XcpDaqDecoder for XcpTriggerRecorder {
+    fn start(&mut self, odt_entries: Vec<Vec<OdtEntry>>, timestamp_raw64: u64) {
+        self.signal_events = odt_entries.iter().flatten().filter_map(|e| e.a2l_addr.event.map(|event| (e.name.clone(), event))).collect();
+        self.buffer.clear();
+        self.last_values.clear();
+        self.decoder.start(odt_entries, timestamp_raw64);
+    }
+
+    fn stop(&mut self) {
+        if let Err(e) = self.close_capture() {
+            error!("Trigger recorder: {}", e);
+            self.failed = true;
+        }
+    }
+
+    fn set_daq_properties(&mut self, timestamp_resolution: u64, daq_header_size: u8) {
+        self.decoder.set_daq_properties(timestamp_resolution, daq_header_size);
+    }
+
+    fn set_daq_timestamp_size(&mut self, timestamp_size: u8) {
+        self.decoder.set_daq_timestamp_size(timestamp_size);
+    }
+
+    fn update_daq_list(&mut self, daq: u16, odt_entries: Vec<OdtEntry>) {
+        for e in &odt_entries {
+            if let Some(event) = e.a2l_addr.event {
+                self.signal_events.insert(e.name.clone(), event);
+            }
+        }
+        self.decoder.update_daq_list(daq, odt_entries);
+    }
+
+    fn decode(&mut self, lost: u32, data: &[u8]) {
+        self.decoder.decode(lost, data);
+        let samples = self.recording.lock().take_samples();
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.process_samples(samples) {
+            error!("Trigger recorder: recording stopped, {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod trigger_recorder_tests {
+
+    use super::*;
+
+    fn sample(timestamp_ms: i64, name: &str, value: f64) -> XcpSample {
+        XcpSample {
+            timestamp_ns: timestamp_ms * 1_000_000,
+            target: 0,
+            name: name.to_string(),
+            value,
+        }
+    }
+
+    // Signal x with the values at 0, 10, 20, .. ms
+    fn signal(values: &[f64]) -> Vec<XcpSample> {
+        values.iter().zip(0..).map(|(v, t)| sample(t * 10, "x", *v)).collect()
+    }
+
+    fn new_recorder(name: &str, condition: XcpTriggerCondition) -> (PathBuf, XcpTriggerRecorder) {
+        let dir = std::env::temp_dir().join(format!("xcp_client_trigger_{}_{}", name, std::process::id()));
+        let recorder = XcpTriggerRecorder::new(&dir, "cap", condition, Duration::from_millis(20), Duration::from_millis(20)).unwrap();
+        (dir, recorder)
+    }
+
+    fn capture_values(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|l| l.rsplit(',').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_trigger_rising() {
+        let condition = XcpTriggerCondition::Rising {
+            signal: "x".to_string(),
+            threshold: 5.0,
+        };
+        let (dir, mut recorder) = new_recorder("rising", condition);
+
+        // Triggers at 40 ms and again at 100 ms after the capture is complete, not at 60 ms during the capture
+        recorder.process_samples(signal(&[0.0, 1.0, 2.0, 3.0, 6.0, 1.0, 6.0, 1.0, 2.0, 3.0, 8.0])).unwrap();
+        assert!(!recorder.is_armed());
+        recorder.close_capture().unwrap();
+        assert!(recorder.is_armed());
+        let captures = recorder.get_captures();
+        assert_eq!(captures.iter().map(|(t, _)| *t).collect::<Vec<_>>(), [40_000_000, 100_000_000]);
+
+        // Pre trigger 20 ms, post trigger 20 ms
+        assert_eq!(capture_values(&captures[0].1), ["1", "2", "3", "6", "1", "6"]);
+        assert_eq!(capture_values(&captures[1].1), ["1", "2", "3", "8"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trigger_falling_and_event() {
+        let condition = XcpTriggerCondition::Falling {
+            signal: "x".to_string(),
+            threshold: 5.0,
+        };
+        let (dir, mut recorder) = new_recorder("falling", condition);
+        recorder.process_samples(signal(&[6.0, 5.0, 4.0])).unwrap();
+        assert_eq!(recorder.get_captures().iter().map(|(t, _)| *t).collect::<Vec<_>>(), [10_000_000]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Any sample of a signal of the master event
+        let (dir, mut recorder) = new_recorder("event", XcpTriggerCondition::Event(2));
+        recorder.signal_events = HashMap::from([("x".to_string(), 1), ("fault".to_string(), 2)]);
+        recorder.process_samples(signal(&[1.0, 2.0])).unwrap();
+        assert!(recorder.is_armed());
+        recorder.process_samples(vec![sample(25, "fault", 1.0)]).unwrap();
+        assert!(!recorder.is_armed());
+        recorder.close_capture().unwrap();
+        assert_eq!(capture_values(&recorder.get_captures()[0].1), ["1", "2", "1"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
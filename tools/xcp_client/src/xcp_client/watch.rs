@@ -0,0 +1,209 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module watch
+// Watch table for quick sanity checks of signals
+// Polled values are shown in a refreshing terminal table, changed values are highlighted and a d/dt estimate is calculated
+// The --watch option of the command line tool runs the table with watch(regex, rate, duration)
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::fmt::Write;
+
+use super::cal_history::value_to_f64;
+use super::{A2lAddr, A2lType};
+
+// ANSI terminal control sequences
+const ANSI_CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+const ANSI_HIGHLIGHT: &str = "\x1b[1;33m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpWatchRow
+
+/// A single watched signal with its last value, time of last change and d/dt estimate
+#[derive(Debug, Clone)]
+pub struct XcpWatchRow {
+    pub name: String,
+    pub a2l_addr: A2lAddr,
+    pub a2l_type: A2lType,
+    pub value: Option<f64>,
+    pub changed: bool,
+    pub rate: f64,          // d/dt in 1/s
+    last_time: Option<f64>, // s
+}
+
+impl XcpWatchRow {
+    pub fn new(name: &str, a2l_addr: A2lAddr, a2l_type: A2lType) -> XcpWatchRow {
+        XcpWatchRow {
+            name: name.to_string(),
+            a2l_addr,
+            a2l_type,
+            value: None,
+            changed: false,
+            rate: 0.0,
+            last_time: None,
+        }
+    }
+
+    /// Update with new raw value bytes polled at time t (s)
+    pub fn update(&mut self, bytes: &[u8], t: f64) {
+        let value = value_to_f64(self.a2l_type, bytes);
+        self.changed = false;
+        if let (Some(last_value), Some(last_time)) = (self.value, self.last_time) {
+            self.changed = value != last_value;
+            let dt = t - last_time;
+            if dt > 0.0 {
+                self.rate = (value - last_value) / dt;
+            }
+        }
+        self.value = Some(value);
+        self.last_time = Some(t);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// XcpWatchTable
+
+/// Table of watched signals
+#[derive(Debug, Default)]
+pub struct XcpWatchTable {
+    rows: Vec<XcpWatchRow>,
+    cycle: u64,
+}
+
+impl XcpWatchTable {
+    pub fn new() -> XcpWatchTable {
+        XcpWatchTable { rows: Vec::new(), cycle: 0 }
+    }
+
+    pub fn add_row(&mut self, row: XcpWatchRow) {
+        self.rows.push(row);
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn get_rows(&self) -> &[XcpWatchRow] {
+        &self.rows
+    }
+
+    pub fn get_rows_mut(&mut self) -> &mut [XcpWatchRow] {
+        &mut self.rows
+    }
+
+    pub fn next_cycle(&mut self) {
+        self.cycle += 1;
+    }
+
+    /// Render the table as text, changed values are highlighted if ansi is true
+    pub fn render(&self, ansi: bool) -> String {
+        let width = self.rows.iter().map(|r| r.name.len()).max().unwrap_or(4).max(4);
+        let mut s = String::new();
+        let _ = writeln!(s, "Watch cycle {}", self.cycle);
+        let _ = writeln!(s, "{:width$} {:>20} {:>16}  Address", "Name", "Value", "d/dt [1/s]", width = width);
+        for r in &self.rows {
+            let value = match r.value {
+                Some(v) => format!("{:20}", v),
+                None => format!("{:>20}", "-"),
+            };
+            if ansi && r.changed {
+                let _ = writeln!(
+                    s,
+                    "{:width$} {}{}{} {:16.4}  {}",
+                    r.name,
+                    ANSI_HIGHLIGHT,
+                    value,
+                    ANSI_RESET,
+                    r.rate,
+                    r.a2l_addr,
+                    width = width
+                );
+            } else {
+                let _ = writeln!(s, "{:width$} {} {:16.4}  {}", r.name, value, r.rate, r.a2l_addr, width = width);
+            }
+        }
+        s
+    }
+
+    /// Print the table, refreshing the terminal screen
+    pub fn print(&self) {
+        print!("{}{}", ANSI_CLEAR_SCREEN, self.render(true));
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod watch_tests {
+
+    use super::*;
+    use crate::xcp_client::A2lTypeEncoding;
+
+    fn row(name: &str) -> XcpWatchRow {
+        XcpWatchRow::new(
+            name,
+            A2lAddr {
+                ext: 0,
+                addr: 0x1000,
+                event: Some(1),
+            },
+            A2lType {
+                size: 2,
+                encoding: A2lTypeEncoding::Signed,
+            },
+        )
+    }
+
+    #[test]
+    fn test_watch_row_update() {
+        let mut r = row("x");
+        r.update(&10i16.to_le_bytes(), 1.0);
+        assert_eq!(r.value, Some(10.0));
+        assert!(!r.changed);
+        assert_eq!(r.rate, 0.0);
+
+        r.update(&(-10i16).to_le_bytes(), 1.5);
+        assert_eq!(r.value, Some(-10.0));
+        assert!(r.changed);
+        assert_eq!(r.rate, -40.0);
+
+        // An unchanged value has rate 0, the rate is not updated without time progress
+        r.update(&(-10i16).to_le_bytes(), 2.0);
+        assert!(!r.changed);
+        assert_eq!(r.rate, 0.0);
+        r.update(&0i16.to_le_bytes(), 2.0);
+        assert!(r.changed);
+        assert_eq!(r.rate, 0.0);
+    }
+
+    #[test]
+    fn test_watch_table_render() {
+        let mut table = XcpWatchTable::new();
+        assert!(table.is_empty());
+        table.add_row(row("signal"));
+        table.add_row(row("y"));
+        assert_eq!(table.len(), 2);
+
+        let s = table.render(true);
+        let lines: Vec<&str> = s.lines().collect();
+        assert_eq!(lines[0], "Watch cycle 0");
+        assert_eq!(lines[1], format!("Name   {:>20} {:>16}  Address", "Value", "d/dt [1/s]"));
+        assert_eq!(lines[2], format!("signal {:>20} {:16.4}  0:0x00001000 event 1", "-", 0.0));
+
+        table.next_cycle();
+        table.get_rows_mut()[0].update(&1i16.to_le_bytes(), 0.0);
+        table.get_rows_mut()[0].update(&2i16.to_le_bytes(), 1.0);
+        assert!(table.get_rows()[0].changed);
+        let s = table.render(true);
+        let lines: Vec<&str> = s.lines().collect();
+        assert_eq!(lines[0], "Watch cycle 1");
+        assert_eq!(lines[2], format!("signal {}{:20}{} {:16.4}  0:0x00001000 event 1", ANSI_HIGHLIGHT, 2.0, ANSI_RESET, 1.0));
+        assert!(!table.render(false).contains(ANSI_HIGHLIGHT));
+    }
+}
@@ -0,0 +1,357 @@
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Module xcp_on_can
+// XCP on CAN and CAN FD transport layer with Linux SocketCAN
+// XCP on CAN has no transport layer header, one CAN frame carries one CTO or DTO packet, the packet length is the frame length
+// Commands are sent with CAN_ID_MASTER, responses, events, service requests and DAQ packets are received with CAN_ID_SLAVE
+// Packets are limited by MAX_CTO and MAX_DTO of the server, 8 bytes with CAN and up to 64 bytes with CAN FD, memory blocks are transferred
+// in MAX_CTO sized commands and DAQ lists exceeding MAX_DTO are split into several ODTs
+// The CAN identifiers and the CAN FD parameters are taken from the IF_DATA XCP_ON_CAN of the A2L file, the bit timing is a property of the
+// SocketCAN network interface and is not configured here
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::error::Error;
+use std::path::Path;
+
+/// CAN frame bit 31 of a CAN identifier in A2L and SocketCAN, extended 29 bit identifier
+pub const CAN_ID_EXTENDED: u32 = 0x8000_0000;
+
+// Valid CAN FD frame lengths above 8 bytes
+const CAN_FD_LENGTHS: [usize; 7] = [12, 16, 20, 24, 32, 48, 64];
+
+/// XCP on CAN transport layer parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XcpCanConfig {
+    pub interface: String,      // SocketCAN network interface, e.g. can0 or vcan0
+    pub cmd_id: u32,            // CAN_ID_MASTER, identifier of commands, CAN_ID_EXTENDED set for 29 bit identifiers
+    pub res_id: u32,            // CAN_ID_SLAVE, identifier of responses, events, service requests and DAQ packets
+    pub fd: bool,               // CAN FD frames
+    pub brs: bool,              // CAN FD bit rate switch, the interface has a data phase bit rate (CAN_FD_DATA_TRANSFER_BAUDRATE)
+    pub max_dlc: usize,         // Max frame length, 8 or with CAN FD up to 64 (MAX_DLC)
+    pub max_dlc_required: bool, // Commands are padded to max_dlc (MAX_DLC_REQUIRED)
+    pub padding: u8,            // Fill value of the padding bytes
+}
+
+impl XcpCanConfig {
+    /// Classic CAN with 8 byte frames, command identifier cmd_id and response identifier res_id
+    pub fn new(interface: &str, cmd_id: u32, res_id: u32) -> XcpCanConfig {
+        XcpCanConfig {
+            interface: interface.to_string(),
+            cmd_id,
+            res_id,
+            fd: false,
+            brs: false,
+            max_dlc: 8,
+            max_dlc_required: false,
+            padding: 0x00,
+        }
+    }
+
+    /// CAN FD with frames up to max_dlc bytes and bit rate switch
+    pub fn new_fd(interface: &str, cmd_id: u32, res_id: u32, max_dlc: usize) -> XcpCanConfig {
+        XcpCanConfig {
+            fd: true,
+            brs: true,
+            max_dlc: max_dlc.clamp(8, 64),
+            ..XcpCanConfig::new(interface, cmd_id, res_id)
+        }
+    }
+
+    /// Transport layer parameters from the IF_DATA XCP_ON_CAN of an A2L text
+    /// CAN_ID_MASTER, CAN_ID_SLAVE and MAX_DLC_REQUIRED, a CAN_FD block selects CAN FD with MAX_DLC and bit rate switch if a
+    /// CAN_FD_DATA_TRANSFER_BAUDRATE is given. DAQ_LIST_CAN_ID is not supported, all DAQ packets are expected on CAN_ID_SLAVE
+    pub fn from_a2l_str(interface: &str, a2l: &str) -> Result<XcpCanConfig, String> {
+        let start = a2l.find("/begin XCP_ON_CAN").ok_or("No IF_DATA XCP_ON_CAN in A2L")?;
+        let end = a2l[start..].find("/end XCP_ON_CAN").ok_or("/begin XCP_ON_CAN without /end")? + start;
+        let mut text = a2l[start..end].to_string();
+        while let Some(c) = text.find("/*") {
+            let e = text[c..].find("*/").map_or(text.len(), |e| c + e + 2);
+            text.replace_range(c..e, " ");
+        }
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        let value = |i: usize| -> Result<u32, String> {
+            let t = tokens.get(i + 1).ok_or_else(|| format!("XCP_ON_CAN: value of {} expected", tokens[i]))?;
+            let v = match t.strip_prefix("0x").or_else(|| t.strip_prefix("0X")) {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => t.parse(),
+            };
+            v.map_err(|_| format!("XCP_ON_CAN: invalid value {} of {}", t, tokens[i]))
+        };
+
+        let mut config = XcpCanConfig::new(interface, 0, 0);
+        let (mut cmd_id, mut res_id) = (None, None);
+        let mut in_can_fd = false;
+        let mut depth = 0;
+        for i in 0..tokens.len() {
+            match tokens[i] {
+                "/begin" => {
+                    depth += 1;
+                    if tokens.get(i + 1) == Some(&"CAN_FD") {
+                        in_can_fd = true;
+                        config.fd = true;
+                        config.max_dlc = 64;
+                    }
+                }
+                "/end" => {
+                    depth -= 1;
+                    if tokens.get(i + 1) == Some(&"CAN_FD") {
+                        in_can_fd = false;
+                    }
+                }
+                "CAN_ID_MASTER" if depth == 1 => cmd_id = Some(value(i)?),
+                "CAN_ID_SLAVE" if depth == 1 => res_id = Some(value(i)?),
+                "MAX_DLC_REQUIRED" if depth == 1 || in_can_fd => config.max_dlc_required = true,
+                "MAX_DLC" if in_can_fd => config.max_dlc = (value(i)? as usize).clamp(8, 64),
+                "CAN_FD_DATA_TRANSFER_BAUDRATE" if in_can_fd => config.brs = true,
+                _ => {}
+            }
+        }
+        config.cmd_id = cmd_id.ok_or("XCP_ON_CAN: CAN_ID_MASTER missing")?;
+        config.res_id = res_id.ok_or("XCP_ON_CAN: CAN_ID_SLAVE missing")?;
+        Ok(config)
+    }
+
+    /// Transport layer parameters from the IF_DATA XCP_ON_CAN of an A2L file
+    pub fn from_a2l_file<P: AsRef<Path>>(interface: &str, path: &P) -> Result<XcpCanConfig, Box<dyn Error>> {
+        let a2l = std::fs::read_to_string(path)?;
+        Ok(XcpCanConfig::from_a2l_str(interface, &a2l).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?)
+    }
+
+    // Frame length of a packet, CAN FD frames have discrete lengths above 8 bytes, with MAX_DLC_REQUIRED all frames have max_dlc bytes
+    fn frame_len(&self, packet_len: usize) -> usize {
+        if self.max_dlc_required {
+            self.max_dlc
+        } else if packet_len <= 8 {
+            packet_len
+        } else {
+            CAN_FD_LENGTHS.into_iter().find(|l| *l >= packet_len).unwrap_or(64)
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// SocketCAN socket
+
+#[cfg(target_os = "linux")]
+pub(super) struct XcpCanSocket {
+    fd: tokio::io::unix::AsyncFd<std::os::fd::OwnedFd>,
+    config: XcpCanConfig,
+    ctr: std::sync::atomic::AtomicU16, // Counter of received packets for the transport layer header
+}
+
+#[cfg(target_os = "linux")]
+impl XcpCanSocket {
+    /// Open a raw CAN socket on the interface of config, receiving only frames with the response identifier
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap, clippy::cast_sign_loss)] // libc types, lengths <= CANFD_MTU
+    pub(super) fn open(config: &XcpCanConfig) -> std::io::Result<XcpCanSocket> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        let ifname = std::ffi::CString::new(config.interface.as_str()).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: plain libc calls with valid arguments, the file descriptor is owned by OwnedFd immediately after creation
+        unsafe {
+            let ifindex = libc::if_nametoindex(ifname.as_ptr());
+            if ifindex == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let raw = libc::socket(libc::PF_CAN, libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, libc::CAN_RAW);
+            if raw < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let fd = OwnedFd::from_raw_fd(raw);
+
+            // Receive only the response identifier
+            let mask = if config.res_id & CAN_ID_EXTENDED != 0 {
+                libc::CAN_EFF_FLAG | libc::CAN_RTR_FLAG | libc::CAN_EFF_MASK
+            } else {
+                libc::CAN_EFF_FLAG | libc::CAN_RTR_FLAG | libc::CAN_SFF_MASK
+            };
+            let filter = libc::can_filter {
+                can_id: config.res_id,
+                can_mask: mask,
+            };
+            Self::set_option(raw, libc::CAN_RAW_FILTER, &filter)?;
+            if config.fd {
+                Self::set_option(raw, libc::CAN_RAW_FD_FRAMES, &(1 as libc::c_int))?;
+            }
+
+            let mut addr: libc::sockaddr_can = std::mem::zeroed();
+            addr.can_family = libc::AF_CAN as libc::sa_family_t;
+            addr.can_ifindex = ifindex as libc::c_int;
+            if libc::bind(raw, (&raw const addr).cast(), size_of::<libc::sockaddr_can>() as libc::socklen_t) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            debug!("XCP on CAN: {} opened, {:?}", config.interface, config);
+            // The OwnedFd stays open and refers to the same socket for the lifetime of the AsyncFd
+            Ok(XcpCanSocket {
+                fd: tokio::io::unix::AsyncFd::register(fd)?,
+                config: config.clone(),
+                ctr: std::sync::atomic::AtomicU16::new(0),
+            })
+        }
+    }
+
+    // SAFETY: value must be the type the option expects
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap, clippy::cast_sign_loss)] // libc types, lengths <= CANFD_MTU
+    unsafe fn set_option<T>(fd: libc::c_int, option: libc::c_int, value: &T) -> std::io::Result<()> {
+        // SAFETY: value is a valid reference of size_of::<T>() bytes
+        if unsafe { libc::setsockopt(fd, libc::SOL_CAN_RAW, option, (value as *const T).cast(), size_of::<T>() as libc::socklen_t) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Send a packet with the XCP on ETH transport layer header (len, ctr) as built by XcpCommandBuilder
+    /// The header is removed, the packet is sent as one CAN frame with the command identifier
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap, clippy::cast_sign_loss)] // libc types, lengths <= CANFD_MTU
+    pub(super) async fn send(&self, buf: &[u8]) -> std::io::Result<usize> {
+        use std::os::fd::AsRawFd;
+
+        let packet = buf.get(4..).unwrap_or_default();
+        if packet.len() > self.config.max_dlc {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("XCP on CAN: packet with {} bytes exceeds the max frame length {}", packet.len(), self.config.max_dlc),
+            ));
+        }
+        // SAFETY: canfd_frame is plain old data, all zero is valid
+        let mut frame: libc::canfd_frame = unsafe { std::mem::zeroed() };
+        let len = self.config.frame_len(packet.len());
+        frame.can_id = self.config.cmd_id;
+        frame.len = len as u8;
+        frame.data[..packet.len()].copy_from_slice(packet);
+        frame.data[packet.len()..len].fill(self.config.padding);
+        let mtu = if self.config.fd {
+            if self.config.brs {
+                frame.flags = (libc::CANFD_BRS | libc::CANFD_FDF) as u8;
+            } else {
+                frame.flags = libc::CANFD_FDF as u8;
+            }
+            libc::CANFD_MTU
+        } else {
+            libc::CAN_MTU // can_frame has the same layout as the beginning of canfd_frame
+        };
+        loop {
+            let mut guard = self.fd.writable().await?;
+            // SAFETY: frame is valid for mtu bytes
+            let res = guard.try_io(|fd| match unsafe { libc::write(fd.as_raw_fd(), (&raw const frame).cast(), mtu) } {
+                n if n < 0 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            });
+            match res {
+                Ok(res) => return res.map(|_| buf.len()),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Receive a packet, returned with a XCP on ETH transport layer header (len, ctr), the receive task handles all transport layers the same way
+    /// The counter is incremented with every packet, CAN has no transport layer counter to detect lost packets
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap, clippy::cast_sign_loss)] // libc types, lengths <= CANFD_MTU
+    pub(super) async fn receive(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::os::fd::AsRawFd;
+
+        loop {
+            // SAFETY: canfd_frame is plain old data, all zero is valid
+            let mut frame: libc::canfd_frame = unsafe { std::mem::zeroed() };
+            let mut guard = self.fd.readable().await?;
+            // SAFETY: frame is valid for CANFD_MTU bytes
+            let res = guard.try_io(|fd| match unsafe { libc::read(fd.as_raw_fd(), (&raw mut frame).cast(), libc::CANFD_MTU) } {
+                n if n < 0 => Err(std::io::Error::last_os_error()),
+                n => Ok(n as usize),
+            });
+            let n = match res {
+                Ok(n) => n?,
+                Err(_would_block) => continue,
+            };
+            let len = frame.len as usize;
+            if (n != libc::CAN_MTU && n != libc::CANFD_MTU) || len == 0 || len > libc::CANFD_MAX_DLEN || len + 4 > buf.len() {
+                continue;
+            }
+            let ctr = self.ctr.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            buf[0..2].copy_from_slice(&(len as u16).to_le_bytes());
+            buf[2..4].copy_from_slice(&ctr.to_le_bytes());
+            buf[4..4 + len].copy_from_slice(&frame.data[..len]);
+            return Ok(len + 4);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(super) struct XcpCanSocket;
+
+#[cfg(not(target_os = "linux"))]
+impl XcpCanSocket {
+    pub(super) fn open(_config: &XcpCanConfig) -> std::io::Result<XcpCanSocket> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "XCP on CAN requires Linux SocketCAN"))
+    }
+
+    pub(super) async fn send(&self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    pub(super) async fn receive(&self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+}
+
+impl std::fmt::Debug for XcpCanSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "XcpCanSocket")
+    }
+}
+
+//--------------------------------------------------------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod xcp_on_can_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_can_frame_len() {
+        let can = XcpCanConfig::new("vcan0", 0x7E0, 0x7E1);
+        assert_eq!(can.frame_len(1), 1);
+        assert_eq!(can.frame_len(8), 8);
+
+        let mut can_fd = XcpCanConfig::new_fd("vcan0", 0x7E0, 0x7E1, 100);
+        assert_eq!(can_fd.max_dlc, 64);
+        assert_eq!(can_fd.frame_len(8), 8);
+        assert_eq!(can_fd.frame_len(9), 12);
+        assert_eq!(can_fd.frame_len(33), 48);
+        assert_eq!(can_fd.frame_len(64), 64);
+        can_fd.max_dlc = 32;
+        can_fd.max_dlc_required = true;
+        assert_eq!(can_fd.frame_len(1), 32);
+    }
+
+    #[test]
+    fn test_can_config_from_a2l() {
+        let a2l = r#"
+            /begin IF_DATA XCP
+              /begin XCP_ON_CAN 0x0100
+                CAN_ID_MASTER 0x80000700 /* extended */
+                CAN_ID_SLAVE 1793
+                BAUDRATE 500000
+                /begin CAN_FD
+                  MAX_DLC 32
+                  CAN_FD_DATA_TRANSFER_BAUDRATE 2000000
+                  MAX_DLC_REQUIRED
+                /end CAN_FD
+              /end XCP_ON_CAN
+            /end IF_DATA"#;
+        let config = XcpCanConfig::from_a2l_str("can0", a2l).unwrap();
+        assert_eq!(config.cmd_id, 0x700 | CAN_ID_EXTENDED);
+        assert_eq!(config.res_id, 0x701);
+        assert!(config.fd && config.brs && config.max_dlc_required);
+        assert_eq!(config.max_dlc, 32);
+
+        let config = XcpCanConfig::from_a2l_str("can0", "/begin XCP_ON_CAN 0x0100 CAN_ID_MASTER 0x7E0 CAN_ID_SLAVE 0x7E1 /end XCP_ON_CAN").unwrap();
+        assert_eq!(config, XcpCanConfig::new("can0", 0x7E0, 0x7E1));
+
+        assert!(XcpCanConfig::from_a2l_str("can0", "/begin XCP_ON_CAN 0x0100 CAN_ID_MASTER 0x7E0 /end XCP_ON_CAN").is_err());
+        assert!(XcpCanConfig::from_a2l_str("can0", "/begin XCP_ON_CAN 0x0100 CAN_ID_MASTER x /end XCP_ON_CAN").is_err());
+        assert!(XcpCanConfig::from_a2l_str("can0", "").is_err());
+    }
+}
@@ -0,0 +1,376 @@
+// test_protocol
+// End to end test of the XcpClient protocol paths and of the command line tool over UDP
+// The server is a minimal XCP-on-ETH server in this file, with a memory image and configurable block transfer modes,
+// it decodes the commands with the typed commands of the client
+
+// cargo test -p xcp_client --test test_protocol
+
+#![allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::net::{SocketAddr, UdpSocket};
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use xcp_client::commands::*;
+use xcp_client::xcp::*;
+use xcp_client::*;
+
+//-----------------------------------------------------------------------------
+// Test server
+
+const MAX_CTO: u8 = 8;
+const MEMORY_SIZE: u32 = 0x4000;
+const SERVER_ID: &str = "test_server";
+
+// Command and response counters of the test server
+#[derive(Debug, Default, Clone)]
+struct ServerStats {
+    uploads: usize,
+    upload_responses: usize,
+    downloads: usize,
+    download_nexts: usize,
+    download_responses: usize,
+}
+
+struct ServerState {
+    comm_mode_basic: u8,
+    comm_mode_optional: u8,
+    memory: Vec<u8>,
+    mta: usize,
+    block_remaining: usize, // Remaining bytes of a master block download
+    ctr: u16,
+    stats: ServerStats,
+}
+
+impl ServerState {
+    fn new(comm_mode_basic: u8, comm_mode_optional: u8) -> ServerState {
+        ServerState {
+            comm_mode_basic,
+            comm_mode_optional,
+            memory: vec![0; MEMORY_SIZE as usize],
+            mta: 0,
+            block_remaining: 0,
+            ctr: 0,
+            stats: ServerStats::default(),
+        }
+    }
+
+    // Transport layer message with header len and ctr
+    fn message(&mut self, response: &[u8]) -> Vec<u8> {
+        let mut msg = Vec::with_capacity(response.len() + 4);
+        msg.extend_from_slice(&u16::try_from(response.len()).unwrap().to_le_bytes());
+        msg.extend_from_slice(&self.ctr.to_le_bytes());
+        msg.extend_from_slice(response);
+        self.ctr = self.ctr.wrapping_add(1);
+        msg
+    }
+
+    fn positive(data: &[u8]) -> Vec<u8> {
+        let mut response = vec![0xFF];
+        response.extend_from_slice(data);
+        response
+    }
+
+    fn read(&mut self, size: usize) -> Option<Vec<u8>> {
+        let data = self.memory.get(self.mta..self.mta + size)?.to_vec();
+        self.mta += size;
+        Some(data)
+    }
+
+    fn write(&mut self, data: &[u8]) -> bool {
+        let Some(memory) = self.memory.get_mut(self.mta..self.mta + data.len()) else {
+            return false;
+        };
+        memory.copy_from_slice(data);
+        self.mta += data.len();
+        true
+    }
+
+    // Handle a command packet, returns the response packets
+    fn handle(&mut self, cmd: &[u8]) -> Vec<Vec<u8>> {
+        const ERROR_OUT_OF_RANGE: [u8; 2] = [0xFE, CRC_OUT_OF_RANGE];
+        let slave_block = self.comm_mode_basic & CMB_SLAVE_BLOCK_MODE != 0;
+        let master_block = self.comm_mode_optional & CMO_MASTER_BLOCK_MODE != 0;
+        match cmd[0] {
+            CC_CONNECT => {
+                let max_dto: u16 = 1500;
+                let [dto_lo, dto_hi] = max_dto.to_le_bytes();
+                vec![Self::positive(&[0x05, self.comm_mode_basic, MAX_CTO, dto_lo, dto_hi, 1, 1])]
+            }
+            CC_GET_COMM_MODE_INFO => vec![Self::positive(&[0, self.comm_mode_optional, 0, 4, 0, 3, 1])],
+            CC_GET_DAQ_PROCESSOR_INFO => vec![Self::positive(&[0x10, 0, 0, 1, 0, 0, 0xC0])],
+            CC_GET_DAQ_RESOLUTION_INFO => vec![Self::positive(&[1, 8, 1, 8, 0x34, 1, 0])],
+            CC_GET_ID => {
+                let mut data = vec![1, 0, 0];
+                data.extend_from_slice(&u32::try_from(SERVER_ID.len()).unwrap().to_le_bytes());
+                data.extend_from_slice(SERVER_ID.as_bytes());
+                vec![Self::positive(&data)]
+            }
+            CC_SET_MTA => {
+                let cmd = SetMta::decode(cmd).unwrap();
+                self.mta = cmd.addr as usize;
+                vec![Self::positive(&[])]
+            }
+            CC_SHORT_UPLOAD => {
+                let cmd = ShortUpload::decode(cmd).unwrap();
+                self.mta = cmd.addr as usize;
+                match self.read(cmd.size as usize) {
+                    Some(data) => vec![Self::positive(&data)],
+                    None => vec![ERROR_OUT_OF_RANGE.to_vec()],
+                }
+            }
+            CC_UPLOAD => {
+                self.stats.uploads += 1;
+                let size = Upload::decode(cmd).unwrap().size as usize;
+                if !slave_block && size > MAX_CTO as usize - 1 {
+                    return vec![ERROR_OUT_OF_RANGE.to_vec()];
+                }
+                let Some(data) = self.read(size) else {
+                    return vec![ERROR_OUT_OF_RANGE.to_vec()];
+                };
+                let responses: Vec<Vec<u8>> = data.chunks(MAX_CTO as usize - 1).map(Self::positive).collect();
+                self.stats.upload_responses += responses.len();
+                responses
+            }
+            CC_DOWNLOAD | CC_DOWNLOAD_NEXT => {
+                if cmd[0] == CC_DOWNLOAD {
+                    self.stats.downloads += 1;
+                } else {
+                    self.stats.download_nexts += 1;
+                }
+                let size = cmd[1] as usize;
+                let data = &cmd[2..];
+                if cmd[0] == CC_DOWNLOAD_NEXT && size != self.block_remaining {
+                    self.block_remaining = 0;
+                    return vec![vec![0xFE, CRC_SEQUENCE]];
+                }
+                if data.len() != size.min(MAX_CTO as usize - 2) || (!master_block && size > MAX_CTO as usize - 2) || !self.write(data) {
+                    self.block_remaining = 0;
+                    return vec![ERROR_OUT_OF_RANGE.to_vec()];
+                }
+                self.block_remaining = size - data.len();
+                if self.block_remaining > 0 {
+                    return Vec::new();
+                }
+                self.stats.download_responses += 1;
+                vec![Self::positive(&[])]
+            }
+            CC_DISCONNECT => vec![Self::positive(&[])],
+            _ => vec![vec![0xFE, CRC_CMD_UNKNOWN]],
+        }
+    }
+}
+
+// Test server on a local UDP port, stopped when dropped
+struct TestServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<ServerState>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl TestServer {
+    fn start(comm_mode_basic: u8, comm_mode_optional: u8) -> TestServer {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(ServerState::new(comm_mode_basic, comm_mode_optional)));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let state = Arc::clone(&state);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let mut buf = [0u8; 1024];
+                while !stop.load(Ordering::Relaxed) {
+                    let Ok((size, client_addr)) = socket.recv_from(&mut buf) else {
+                        continue;
+                    };
+                    let len = buf[0] as usize + ((buf[1] as usize) << 8);
+                    assert_eq!(len + 4, size, "test server: invalid transport layer header");
+                    let mut state = state.lock();
+                    if buf[4] == CC_CONNECT {
+                        state.ctr = 0;
+                    }
+                    for response in state.handle(&buf[4..size]) {
+                        let msg = state.message(&response);
+                        socket.send_to(&msg, client_addr).unwrap();
+                    }
+                }
+            })
+        };
+        TestServer {
+            addr,
+            state,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    fn get_stats(&self) -> ServerStats {
+        self.state.lock().stats.clone()
+    }
+
+    fn get_memory(&self, addr: usize, len: usize) -> Vec<u8> {
+        self.state.lock().memory[addr..addr + len].to_vec()
+    }
+
+    fn set_memory(&self, addr: usize, data: &[u8]) {
+        self.state.lock().memory[addr..addr + data.len()].copy_from_slice(data);
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Client
+
+struct TestTextDecoder;
+impl XcpTextDecoder for TestTextDecoder {}
+
+struct TestDaqDecoder;
+impl XcpDaqDecoder for TestDaqDecoder {
+    fn decode(&mut self, _lost: u32, _data: &[u8]) {}
+    fn start(&mut self, _odt_entries: Vec<Vec<OdtEntry>>, _timestamp_raw64: u64) {}
+    fn set_daq_properties(&mut self, _timestamp_resolution: u64, _daq_header_size: u8) {}
+}
+
+async fn connect(server: &TestServer) -> XcpClient {
+    let mut client = XcpClient::new(false, server.addr, "127.0.0.1:0".parse().unwrap());
+    client.connect(0, Arc::new(Mutex::new(TestDaqDecoder)), TestTextDecoder).await.unwrap();
+    client
+}
+
+//-----------------------------------------------------------------------------
+// Tests
+
+#[tokio::test]
+async fn test_connect_and_get_id() {
+    let server = TestServer::start(CMB_OPTIONAL, 0);
+    let mut client = connect(&server).await;
+    assert!(client.is_connected());
+    assert_eq!(client.max_cto_size, MAX_CTO);
+    assert_eq!(client.get_id(IDT_ASCII).await.unwrap(), (0, Some(SERVER_ID.to_string())));
+    assert_eq!(client.get_id_data(IDT_ASCII).await.unwrap(), SERVER_ID.as_bytes());
+
+    // GET_VERSION, GET_PAGE_PROCESSOR_INFO and TIME_CORRELATION_PROPERTIES are not supported by the test server
+    assert!(client.capabilities.is_degraded());
+
+    server.set_memory(0x100, &[1, 2, 3, 4]);
+    assert_eq!(client.short_upload(0x100, 0, 4).await.unwrap(), [0xFF, 1, 2, 3, 4]);
+    let e = client.short_upload(MEMORY_SIZE, 0, 4).await.unwrap_err();
+    assert_eq!(e.downcast_ref::<XcpError>().unwrap().get_error_code(), CRC_OUT_OF_RANGE);
+    client.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_block_transfer_modes() {
+    let data: Vec<u8> = (0..600u32).map(|i| (i * 7 % 251) as u8).collect();
+    let range = XcpMemoryRange {
+        ext: 0,
+        addr: 0x1001,
+        len: u32::try_from(data.len()).unwrap(),
+    };
+
+    // Slave and master block mode with interleaved mode, interleaved mode only, no block transfer modes
+    for (comm_mode_basic, comm_mode_optional) in [
+        (CMB_OPTIONAL | CMB_SLAVE_BLOCK_MODE, CMO_MASTER_BLOCK_MODE | CMO_INTERLEAVED_MODE),
+        (CMB_OPTIONAL, CMO_INTERLEAVED_MODE),
+        (0, 0),
+    ] {
+        for block_transfer in [true, false] {
+            let server = TestServer::start(comm_mode_basic, comm_mode_optional);
+            let mut client = connect(&server).await;
+            client.set_block_transfer(block_transfer);
+
+            client.write_memory(range.ext, range.addr, &data, true).await.unwrap();
+            assert_eq!(server.get_memory(range.addr as usize, data.len()), data);
+            assert_eq!(client.read_memory(range, true).await.unwrap(), data);
+
+            let stats = server.get_stats();
+            let slave_block = block_transfer && comm_mode_basic & CMB_SLAVE_BLOCK_MODE != 0;
+            let master_block = block_transfer && comm_mode_optional & CMO_MASTER_BLOCK_MODE != 0;
+            assert_eq!(stats.uploads < stats.upload_responses, slave_block, "{:?}", stats);
+            assert_eq!(stats.download_nexts > 0, master_block, "{:?}", stats);
+            assert_eq!(stats.download_responses, stats.downloads, "{:?}", stats);
+            client.disconnect().await.unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_download_out_of_range() {
+    let server = TestServer::start(CMB_OPTIONAL | CMB_SLAVE_BLOCK_MODE, CMO_MASTER_BLOCK_MODE | CMO_INTERLEAVED_MODE);
+    let mut client = connect(&server).await;
+
+    // The master block is rejected, the client stays in sequence with the server
+    let addr = MEMORY_SIZE - 10;
+    assert!(client.write_memory(0, addr, &[0x55; 20], true).await.is_err());
+    client.write_memory(0, 0x200, &[1, 2, 3], true).await.unwrap();
+    assert_eq!(client.read_memory(XcpMemoryRange { ext: 0, addr: 0x200, len: 3 }, true).await.unwrap(), [1, 2, 3]);
+    client.disconnect().await.unwrap();
+}
+
+//-----------------------------------------------------------------------------
+// Command line tool
+
+fn run_cli(server: &TestServer, args: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_xcp_client"))
+        .args(["--quiet", "--dest-addr", &server.addr.to_string(), "--bind-addr", "127.0.0.1:0"])
+        .args(args)
+        .output()
+        .unwrap();
+    (output.status.code(), String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn test_cli_raw_memory() {
+    let server = TestServer::start(CMB_OPTIONAL | CMB_SLAVE_BLOCK_MODE, CMO_MASTER_BLOCK_MODE | CMO_INTERLEAVED_MODE);
+
+    let (code, stdout) = run_cli(&server, &["--write-mem", "0:0x1000", "41 42 43 44", "--yes-i-know"]);
+    assert_eq!(code, Some(0), "{}", stdout);
+    assert_eq!(server.get_memory(0x1000, 4), b"ABCD");
+
+    let (code, stdout) = run_cli(&server, &["--read-mem", "0:0x1000:4", "--yes-i-know"]);
+    assert_eq!(code, Some(0), "{}", stdout);
+    assert_eq!(stdout, format!("00001000: 41 42 43 44 {}ABCD\nexit_code=0\n", "   ".repeat(12)));
+
+    // Raw memory access without confirmation is refused
+    let (code, stdout) = run_cli(&server, &["--read-mem", "0:0x1000:4"]);
+    assert_eq!(code, Some(XcpExitCode::Failure.get_code() as i32));
+    assert!(stdout.ends_with("exit_code=1\n"), "{}", stdout);
+}
+
+#[test]
+fn test_cli_connect_failed() {
+    // No server on this port
+    let server = TestServer::start(0, 0);
+    let addr = server.addr;
+    drop(server);
+    let output = Command::new(env!("CARGO_BIN_EXE_xcp_client"))
+        .args([
+            "--quiet",
+            "--dest-addr",
+            &addr.to_string(),
+            "--bind-addr",
+            "127.0.0.1:0",
+            "--read-mem",
+            "0:0x1000:4",
+            "--yes-i-know",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(XcpExitCode::ConnectFailed.get_code() as i32));
+}
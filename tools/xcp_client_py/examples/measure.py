@@ -0,0 +1,29 @@
+# Measure and calibrate the hello_xcp example with the xcp_client Python module
+# Start the example first: cargo r -p hello_xcp
+
+from xcp_client import XcpClient
+
+client = XcpClient("127.0.0.1:5555")
+client.connect()
+client.upload_a2l("hello_xcp.a2l")
+
+print("Characteristics:", client.find_characteristics(".*"))
+print("Measurements:", client.find_measurements(".*"))
+
+# Calibration
+name = client.find_characteristics("counter_max")[0]
+print(name, "=", client.get_value(name))
+client.set_value(name, 500)
+print(name, "=", client.get_value(name))
+client.undo()
+
+# Measurement with callback
+client.start_measurement(client.find_measurements("counter"))
+client.measure(1.0, lambda t, name, value: print(f"{t:>14} {name} = {value}"))
+client.stop_measurement()
+
+# Export
+df = client.to_dataframe()
+print(df)
+
+client.disconnect()
@@ -0,0 +1,223 @@
+//-----------------------------------------------------------------------------
+// Library crate xcp_client_py
+// Python bindings (pyo3) for the xcp_client library
+// Build the Python module with maturin: maturin develop --release
+// The async client is driven by a tokio runtime owned by each client object
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use parking_lot::Mutex;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use xcp_client::*;
+
+// Convert a client error into a Python exception
+fn py_err(e: Box<dyn Error>) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+//-----------------------------------------------------------------------------
+// XcpClient
+
+/// XCP on Ethernet client
+#[pyclass(name = "XcpClient", module = "xcp_client", unsendable)]
+pub struct PyXcpClient {
+    runtime: tokio::runtime::Runtime,
+    client: XcpClient,
+    recording: Arc<Mutex<XcpMergedRecording>>,
+    calibration_objects: HashMap<String, XcpCalibrationObjectHandle>,
+    samples: Vec<XcpSample>, // Samples drained from the recording, in the order of arrival
+}
+
+impl PyXcpClient {
+    // Get or create a calibration object by name
+    fn calibration_object(&mut self, name: &str) -> PyResult<XcpCalibrationObjectHandle> {
+        if let Some(handle) = self.calibration_objects.get(name) {
+            return Ok(*handle);
+        }
+        let handle = self.runtime.block_on(self.client.create_calibration_object(name)).map_err(py_err)?;
+        self.calibration_objects.insert(name.to_string(), handle);
+        Ok(handle)
+    }
+}
+
+#[pymethods]
+impl PyXcpClient {
+    /// Create a client for the XCP server at addr ("ip:port"), UDP by default
+    #[new]
+    #[pyo3(signature = (addr, tcp = false, bind = "0.0.0.0:0"))]
+    fn new(addr: &str, tcp: bool, bind: &str) -> PyResult<Self> {
+        let dest_addr: SocketAddr = addr.parse().map_err(|e| PyRuntimeError::new_err(format!("invalid address {}: {}", addr, e)))?;
+        let bind_addr: SocketAddr = bind.parse().map_err(|e| PyRuntimeError::new_err(format!("invalid address {}: {}", bind, e)))?;
+        let runtime = tokio::runtime::Runtime::new()?;
+        Ok(PyXcpClient {
+            runtime,
+            client: XcpClient::new(tcp, dest_addr, bind_addr),
+            recording: Arc::new(Mutex::new(XcpMergedRecording::new())),
+            calibration_objects: HashMap::new(),
+            samples: Vec::new(),
+        })
+    }
+
+    /// Connect to the XCP server
+    fn connect(&mut self) -> PyResult<()> {
+        let target = self.recording.lock().add_target("target");
+        let daq_decoder = Arc::new(Mutex::new(XcpTargetDaqDecoder::new(target, Arc::clone(&self.recording))));
+        self.runtime.block_on(self.client.connect(0, daq_decoder, XcpTargetTextDecoder)).map_err(py_err)
+    }
+
+    /// Disconnect from the XCP server
+    fn disconnect(&mut self) -> PyResult<()> {
+        self.runtime.block_on(self.client.disconnect()).map_err(py_err)
+    }
+
+    fn is_connected(&mut self) -> bool {
+        self.client.is_connected()
+    }
+
+    /// Upload the A2L file from the XCP server into a2l_path and load it
+    #[pyo3(signature = (a2l_path = "upload.a2l"))]
+    fn upload_a2l(&mut self, a2l_path: &str) -> PyResult<()> {
+        self.runtime.block_on(A2lManager::with_path(a2l_path).upload(&mut self.client)).map_err(py_err)?;
+        Ok(())
+    }
+
+    /// Load an A2L file
+    fn load_a2l(&mut self, a2l_path: &str) -> PyResult<()> {
+        self.runtime.block_on(A2lManager::with_path(a2l_path).load(&mut self.client)).map_err(py_err)?;
+        Ok(())
+    }
+
+    /// Names of all characteristics matching a regular expression
+    fn find_characteristics(&self, expr: &str) -> Vec<String> {
+        self.client.find_characteristics(expr)
+    }
+
+    /// Names of all measurements matching a regular expression
+    fn find_measurements(&self, expr: &str) -> Vec<String> {
+        self.client.find_measurements(expr)
+    }
+
+    //-------------------------------------------------------------------------
+    // Calibration
+
+    /// Read the current value of a characteristic from the XCP server
+    fn get_value(&mut self, name: &str) -> PyResult<f64> {
+        let handle = self.calibration_object(name)?;
+        self.runtime.block_on(self.client.read_value_u64(handle)).map_err(py_err)?;
+        let obj = self.client.get_calibration_object(handle);
+        let a2l_type = obj.get_a2l_type();
+        let bytes = obj.get_value_u64().to_le_bytes();
+        Ok(value_to_f64(a2l_type, &bytes))
+    }
+
+    /// Write a characteristic, the value is checked against its limits
+    fn set_value(&mut self, name: &str, value: f64) -> PyResult<()> {
+        let handle = self.calibration_object(name)?;
+        self.runtime.block_on(self.client.set_value_f64(handle, value)).map_err(py_err)
+    }
+
+    /// Undo the last calibration change, returns False if there is nothing to undo
+    fn undo(&mut self) -> PyResult<bool> {
+        self.runtime.block_on(self.client.undo()).map_err(py_err)
+    }
+
+    /// Redo the last undone calibration change, returns False if there is nothing to redo
+    fn redo(&mut self) -> PyResult<bool> {
+        self.runtime.block_on(self.client.redo()).map_err(py_err)
+    }
+
+    //-------------------------------------------------------------------------
+    // Measurement
+
+    /// Start DAQ measurement of the given signals, previously recorded samples are discarded
+    fn start_measurement(&mut self, signals: Vec<String>) -> PyResult<()> {
+        for name in &signals {
+            if self.client.create_measurement_object(name).is_none() {
+                return Err(PyRuntimeError::new_err(format!("measurement {} not found", name)));
+            }
+        }
+        self.recording.lock().clear();
+        self.samples.clear();
+        self.runtime.block_on(self.client.start_measurement()).map_err(py_err)
+    }
+
+    /// Stop DAQ measurement
+    fn stop_measurement(&mut self) -> PyResult<()> {
+        self.runtime.block_on(self.client.stop_measurement()).map_err(py_err)
+    }
+
+    /// Call callback(timestamp_ns, name, value) for each sample received since the last poll, returns the number of samples
+    fn poll(&mut self, callback: &Bound<'_, PyAny>) -> PyResult<usize> {
+        // Drain the new samples, the recording is used as a queue
+        let samples = self.recording.lock().take_samples();
+        let count = samples.len();
+        let start = self.samples.len();
+        self.samples.extend(samples);
+        for s in &self.samples[start..] {
+            callback.call1((s.timestamp_ns, s.name.as_str(), s.value))?;
+        }
+        Ok(count)
+    }
+
+    /// Measure for duration seconds, calling callback(timestamp_ns, name, value) for each received sample
+    #[pyo3(signature = (duration, callback))]
+    fn measure(&mut self, py: Python<'_>, duration: f64, callback: &Bound<'_, PyAny>) -> PyResult<usize> {
+        let end = std::time::Instant::now() + Duration::from_secs_f64(duration);
+        let mut count = 0;
+        while std::time::Instant::now() < end {
+            py.allow_threads(|| std::thread::sleep(Duration::from_millis(10)));
+            py.check_signals()?;
+            count += self.poll(callback)?;
+        }
+        Ok(count)
+    }
+
+    /// All recorded samples as a pandas DataFrame with the columns timestamp_ns, name and value
+    /// Returns a dict of columns, if pandas is not installed
+    #[pyo3(name = "to_dataframe")]
+    fn get_dataframe<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let pending = self.recording.lock().take_samples();
+        self.samples.extend(pending);
+        self.samples.sort_by_key(|s| s.timestamp_ns);
+        let (timestamps, names, values) = {
+            let samples = &self.samples;
+            (
+                samples.iter().map(|s| s.timestamp_ns).collect::<Vec<_>>(),
+                samples.iter().map(|s| s.name.clone()).collect::<Vec<_>>(),
+                samples.iter().map(|s| s.value).collect::<Vec<_>>(),
+            )
+        };
+        let columns = PyDict::new(py);
+        columns.set_item("timestamp_ns", timestamps)?;
+        columns.set_item("name", names)?;
+        columns.set_item("value", values)?;
+        match py.import("pandas") {
+            Ok(pandas) => pandas.getattr("DataFrame")?.call1((columns,)),
+            Err(_) => {
+                warn!("pandas not installed, returning a dict of columns");
+                Ok(columns.into_any())
+            }
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Python module
+
+// The Python module is named xcp_client like the library it wraps
+#[pymodule]
+#[pyo3(name = "xcp_client")]
+fn xcp_client_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyXcpClient>()?;
+    Ok(())
+}
@@ -0,0 +1,17 @@
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    // Generate the C header into OUT_DIR, include/xcp_lite.h is updated explicitly (see README.md)
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+    match cbindgen::Builder::new().with_crate(&crate_dir).with_config(config).generate() {
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{}/xcp_lite.h", out_dir));
+        }
+        Err(e) => {
+            println!("cargo:warning=C header generation failed: {}", e);
+        }
+    }
+}
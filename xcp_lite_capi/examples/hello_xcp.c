@@ -0,0 +1,53 @@
+// Minimal C application instrumented with the xcp_lite C API
+// Build: cargo build --release -p xcp_lite_capi
+//        cc -D_POSIX_C_SOURCE=200809L -Iinclude examples/hello_xcp.c -L../target/release -lxcp_lite_capi -o hello_xcp
+
+#include <time.h>
+
+#include "xcp_lite.h"
+
+// Calibration parameters
+typedef struct {
+    uint32_t counter_max;
+    double gain;
+} params_t;
+
+static const params_t params_default = {.counter_max = 1000, .gain = 1.0};
+
+int main(void) {
+    const uint8_t addr[4] = {0, 0, 0, 0};
+    if (!xcp_server_start("hello_xcp_c", "V1.0", 3, false, addr, 5555, 1024 * 64)) {
+        return 1;
+    }
+
+    // Calibration segment
+    uint16_t params = xcp_create_segment("params", (const uint8_t *)&params_default, sizeof(params_t));
+    XcpVariableDescriptor counter_max = {.name = "params.counter_max", .comment = "Max counter value", .value_type = XCP_VALUE_TYPE_U32, .x_dim = 1, .y_dim = 1, .offset = 0, .min = 0, .max = 10000};
+    xcp_register_characteristic("params", &counter_max);
+    XcpVariableDescriptor gain = {.name = "params.gain", .value_type = XCP_VALUE_TYPE_F64, .x_dim = 1, .y_dim = 1, .offset = 8};
+    xcp_register_characteristic("params", &gain);
+
+    // Measurement, addressed relative to the local variable counter
+    uint32_t counter = 0;
+    uint16_t event = xcp_create_event("mainloop");
+    XcpVariableDescriptor counter_desc = {.name = "counter", .comment = "Demo measurement variable", .value_type = XCP_VALUE_TYPE_U32, .x_dim = 1, .y_dim = 1, .offset = 0};
+    xcp_register_measurement(event, &counter_desc);
+
+    xcp_finalize();
+
+    while (xcp_server_status()) {
+        const params_t *p = (const params_t *)xcp_lock_segment(params);
+        counter++;
+        if (counter > p->counter_max) {
+            counter = 0;
+        }
+        xcp_unlock_segment(params);
+
+        xcp_event_trigger(event, (const uint8_t *)&counter);
+        struct timespec ts = {0, 10000000};
+        nanosleep(&ts, NULL);
+    }
+
+    xcp_server_stop();
+    return 0;
+}
@@ -0,0 +1,148 @@
+#ifndef XCP_LITE_H
+#define XCP_LITE_H
+
+/* Generated by cbindgen from xcp_lite_capi/src/lib.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Undefined event id or calibration segment index
+ */
+#define XCP_UNDEFINED 65535
+
+/**
+ * Value types of measurement and calibration variables
+ */
+enum XcpValueType
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  XCP_VALUE_TYPE_BOOL = 1,
+  XCP_VALUE_TYPE_U8 = 2,
+  XCP_VALUE_TYPE_U16 = 3,
+  XCP_VALUE_TYPE_U32 = 4,
+  XCP_VALUE_TYPE_U64 = 5,
+  XCP_VALUE_TYPE_I8 = 6,
+  XCP_VALUE_TYPE_I16 = 7,
+  XCP_VALUE_TYPE_I32 = 8,
+  XCP_VALUE_TYPE_I64 = 9,
+  XCP_VALUE_TYPE_F32 = 10,
+  XCP_VALUE_TYPE_F64 = 11,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum XcpValueType XcpValueType;
+#else
+typedef uint8_t XcpValueType;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+/**
+ * Descriptor of a measurement or calibration variable
+ * name is required, comment and unit may be NULL
+ * x_dim and y_dim are 1 for scalars
+ * factor and phys_offset define the physical conversion phys = factor * raw + phys_offset, factor 0 means no conversion
+ * min and max are used for calibration variables only, min == max means the limits of the value type
+ * value_type is one of XcpValueType, other values are rejected
+ */
+typedef struct XcpVariableDescriptor {
+  const char *name;
+  const char *comment;
+  const char *unit;
+  uint8_t value_type;
+  uint16_t x_dim;
+  uint16_t y_dim;
+  int32_t offset;
+  double factor;
+  double phys_offset;
+  double min;
+  double max;
+} XcpVariableDescriptor;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Initialize XCP and start the XCP on Ethernet server
+ * addr points to the 4 byte IPv4 address to bind to, NULL binds to any, tcp selects TCP instead of UDP
+ */
+bool xcp_server_start(const char *app_name,
+                      const char *app_revision,
+                      uint8_t log_level,
+                      bool tcp,
+                      const uint8_t *addr,
+                      uint16_t port,
+                      uint32_t queue_size);
+
+/**
+ * Check if the XCP server is running
+ */
+bool xcp_server_status(void);
+
+/**
+ * Stop the XCP server
+ */
+void xcp_server_stop(void);
+
+/**
+ * Finalize the registry and write the A2L file, call after all events, segments and variables are created
+ */
+bool xcp_finalize(void);
+
+/**
+ * Get the DAQ clock in ns
+ */
+uint64_t xcp_get_clock(void);
+
+/**
+ * Create an event, returns the event id or XCP_UNDEFINED
+ */
+uint16_t xcp_create_event(const char *name);
+
+/**
+ * Register a measurement variable with an address relative to the base address given to xcp_event_trigger
+ */
+bool xcp_register_measurement(uint16_t event_id,
+                              const struct XcpVariableDescriptor *desc);
+
+/**
+ * Trigger an event, base is the base address of the measurement variables registered for this event
+ */
+void xcp_event_trigger(uint16_t event_id, const uint8_t *base);
+
+/**
+ * Create a calibration segment, returns the segment index or XCP_UNDEFINED
+ * default_page is the constant default page ("FLASH") of size bytes, it must remain valid until the server is stopped
+ */
+uint16_t xcp_create_segment(const char *name,
+                            const uint8_t *default_page,
+                            uint16_t size);
+
+/**
+ * Register a calibration variable with an address relative to the start of the calibration segment
+ */
+bool xcp_register_characteristic(const char *segment_name,
+                                 const struct XcpVariableDescriptor *desc);
+
+/**
+ * Lock a calibration segment for consistent read access, returns a pointer to the active page
+ * Must be paired with xcp_unlock_segment, the pointer is valid until then
+ */
+const uint8_t *xcp_lock_segment(uint16_t index);
+
+/**
+ * Unlock a calibration segment, locked by xcp_lock_segment
+ */
+void xcp_unlock_segment(uint16_t index);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* XCP_LITE_H */
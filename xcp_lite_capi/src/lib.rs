@@ -0,0 +1,334 @@
+//-----------------------------------------------------------------------------
+// Library crate xcp_lite_capi
+// Stable C API of the xcp_lite XCP server, built as cdylib and staticlib
+// The C header is generated by cbindgen in build.rs into OUT_DIR, include/xcp_lite.h is the checked in copy
+//
+// Errors are reported by the return value and logged
+// A panic never unwinds into C, with the release profile of the workspace (panic = 'abort') it aborts the process,
+// with panic = 'unwind' it is caught and reported by the return value
+// Strings are null terminated UTF-8, names are copied and may be freed by the caller after the call
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{CStr, c_char};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use xcp_lite::registry::{self, McAddress, McDimType, McObjectType, McSupportData, McValueType};
+use xcp_lite::{Xcp, XcpEvent, XcpTransportLayer};
+
+//-----------------------------------------------------------------------------
+// Types
+
+/// Undefined event id or calibration segment index
+pub const XCP_UNDEFINED: u16 = 0xFFFF;
+
+/// Value types of measurement and calibration variables
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XcpValueType {
+    Bool = 1,
+    U8 = 2,
+    U16 = 3,
+    U32 = 4,
+    U64 = 5,
+    I8 = 6,
+    I16 = 7,
+    I32 = 8,
+    I64 = 9,
+    F32 = 10,
+    F64 = 11,
+}
+
+impl TryFrom<u8> for XcpValueType {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            1 => XcpValueType::Bool,
+            2 => XcpValueType::U8,
+            3 => XcpValueType::U16,
+            4 => XcpValueType::U32,
+            5 => XcpValueType::U64,
+            6 => XcpValueType::I8,
+            7 => XcpValueType::I16,
+            8 => XcpValueType::I32,
+            9 => XcpValueType::I64,
+            10 => XcpValueType::F32,
+            11 => XcpValueType::F64,
+            _ => return Err(value),
+        })
+    }
+}
+
+impl From<XcpValueType> for McValueType {
+    fn from(value_type: XcpValueType) -> Self {
+        match value_type {
+            XcpValueType::Bool => McValueType::Bool,
+            XcpValueType::U8 => McValueType::Ubyte,
+            XcpValueType::U16 => McValueType::Uword,
+            XcpValueType::U32 => McValueType::Ulong,
+            XcpValueType::U64 => McValueType::Ulonglong,
+            XcpValueType::I8 => McValueType::Sbyte,
+            XcpValueType::I16 => McValueType::Sword,
+            XcpValueType::I32 => McValueType::Slong,
+            XcpValueType::I64 => McValueType::Slonglong,
+            XcpValueType::F32 => McValueType::Float32Ieee,
+            XcpValueType::F64 => McValueType::Float64Ieee,
+        }
+    }
+}
+
+/// Descriptor of a measurement or calibration variable
+/// name is required, comment and unit may be NULL
+/// x_dim and y_dim are 1 for scalars
+/// factor and phys_offset define the physical conversion phys = factor * raw + phys_offset, factor 0 means no conversion
+/// min and max are used for calibration variables only, min == max means the limits of the value type
+/// value_type is one of XcpValueType, other values are rejected
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct XcpVariableDescriptor {
+    pub name: *const c_char,
+    pub comment: *const c_char,
+    pub unit: *const c_char,
+    pub value_type: u8, // XcpValueType
+    pub x_dim: u16,
+    pub y_dim: u16,
+    pub offset: i32, // Address offset relative to the event base address or the calibration segment start
+    pub factor: f64,
+    pub phys_offset: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+//-----------------------------------------------------------------------------
+// Helpers
+
+// Convert a C string to String, None if NULL or not UTF-8
+unsafe fn to_string(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    // @@@@ UNSAFE - Null terminated C string provided by the caller
+    unsafe { CStr::from_ptr(s) }.to_str().ok().map(str::to_string)
+}
+
+// Run f, catch a panic if panics unwind, with panic = 'abort' the process is aborted before catch_unwind returns
+fn guarded<R>(name: &str, err: R, f: impl FnOnce() -> R) -> R {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(r) => r,
+        Err(_) => {
+            error!("{} failed", name);
+            err
+        }
+    }
+}
+
+// Register a variable in the registry
+fn register_variable(desc: &XcpVariableDescriptor, object_type: McObjectType, address: McAddress) -> bool {
+    // @@@@ UNSAFE - C strings provided by the caller
+    let (name, comment, unit) = unsafe { (to_string(desc.name), to_string(desc.comment), to_string(desc.unit)) };
+    let Some(name) = name else {
+        error!("Variable descriptor without name");
+        return false;
+    };
+    let value_type = match XcpValueType::try_from(desc.value_type) {
+        Ok(value_type) => value_type,
+        Err(v) => {
+            error!("Variable descriptor {} with invalid value type {}", name, v);
+            return false;
+        }
+    };
+    let mut mc_support_data = McSupportData::new(object_type).set_comment(comment.unwrap_or_default());
+    if desc.factor != 0.0 {
+        mc_support_data = mc_support_data.set_linear(desc.factor, desc.phys_offset, unit.unwrap_or_default());
+    } else if let Some(unit) = unit {
+        mc_support_data = mc_support_data.set_unit(unit);
+    }
+    if object_type == McObjectType::Characteristic && desc.min != desc.max {
+        mc_support_data = mc_support_data.set_min(Some(desc.min)).set_max(Some(desc.max));
+    }
+    let dim_type = McDimType::new(value_type.into(), desc.x_dim.max(1), desc.y_dim.max(1));
+    let mut reg = registry::get_lock();
+    let Some(reg) = reg.as_mut() else {
+        error!("Could not register {}, registry already closed", name);
+        return false;
+    };
+    if let Err(e) = reg.instance_list.add_instance(name, dim_type, mc_support_data, address) {
+        error!("add_instance failed: {}", e);
+        return false;
+    }
+    true
+}
+
+//-----------------------------------------------------------------------------
+// Server
+
+/// Initialize XCP and start the XCP on Ethernet server
+/// addr points to the 4 byte IPv4 address to bind to, NULL binds to any, tcp selects TCP instead of UDP
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xcp_server_start(app_name: *const c_char, app_revision: *const c_char, log_level: u8, tcp: bool, addr: *const u8, port: u16, queue_size: u32) -> bool {
+    guarded("xcp_server_start", false, || {
+        // @@@@ UNSAFE - C strings and address provided by the caller
+        let (Some(app_name), Some(app_revision)) = (unsafe { to_string(app_name) }, unsafe { to_string(app_revision) }) else {
+            error!("xcp_server_start: invalid app_name or app_revision");
+            return false;
+        };
+        let addr: [u8; 4] = if addr.is_null() { [0, 0, 0, 0] } else { unsafe { *(addr as *const [u8; 4]) } };
+        let tl = if tcp { XcpTransportLayer::Tcp } else { XcpTransportLayer::Udp };
+        match Xcp::init(&app_name, &app_revision, log_level).start_server(tl, addr, port, queue_size) {
+            Ok(_) => true,
+            Err(e) => {
+                error!("xcp_server_start: {}", e);
+                false
+            }
+        }
+    })
+}
+
+/// Check if the XCP server is running
+#[unsafe(no_mangle)]
+pub extern "C" fn xcp_server_status() -> bool {
+    guarded("xcp_server_status", false, || Xcp::get().check_server())
+}
+
+/// Stop the XCP server
+#[unsafe(no_mangle)]
+pub extern "C" fn xcp_server_stop() {
+    guarded("xcp_server_stop", (), || Xcp::get().stop_server());
+}
+
+/// Finalize the registry and write the A2L file, call after all events, segments and variables are created
+#[unsafe(no_mangle)]
+pub extern "C" fn xcp_finalize() -> bool {
+    guarded("xcp_finalize", false, || match Xcp::get().finalize_registry() {
+        Ok(_) => true,
+        Err(e) => {
+            error!("xcp_finalize: {}", e);
+            false
+        }
+    })
+}
+
+/// Get the DAQ clock in ns
+#[unsafe(no_mangle)]
+pub extern "C" fn xcp_get_clock() -> u64 {
+    guarded("xcp_get_clock", 0, || Xcp::get().get_clock())
+}
+
+//-----------------------------------------------------------------------------
+// DAQ
+
+/// Create an event, returns the event id or XCP_UNDEFINED
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xcp_create_event(name: *const c_char) -> u16 {
+    guarded("xcp_create_event", XCP_UNDEFINED, || {
+        // @@@@ UNSAFE - C string provided by the caller
+        let Some(name) = (unsafe { to_string(name) }) else {
+            error!("xcp_create_event: invalid name");
+            return XCP_UNDEFINED;
+        };
+        // Event names live as long as the application
+        let name: &'static str = Box::leak(name.into_boxed_str());
+        Xcp::get().create_event(name).get_id()
+    })
+}
+
+/// Register a measurement variable with an address relative to the base address given to xcp_event_trigger
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xcp_register_measurement(event_id: u16, desc: *const XcpVariableDescriptor) -> bool {
+    guarded("xcp_register_measurement", false, || {
+        if desc.is_null() {
+            return false;
+        }
+        // @@@@ UNSAFE - Descriptor provided by the caller
+        let desc = unsafe { &*desc };
+        register_variable(desc, McObjectType::Measurement, McAddress::new_event_dyn(0, event_id, desc.offset))
+    })
+}
+
+/// Trigger an event, base is the base address of the measurement variables registered for this event
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xcp_event_trigger(event_id: u16, base: *const u8) {
+    guarded("xcp_event_trigger", (), || {
+        // @@@@ UNSAFE - C library call, base must be valid for all variables registered for this event
+        unsafe { XcpEvent::new(event_id, 0).trigger_ext(base) };
+    });
+}
+
+//-----------------------------------------------------------------------------
+// Calibration segments
+
+/// Create a calibration segment, returns the segment index or XCP_UNDEFINED
+/// default_page is the constant default page ("FLASH") of size bytes, it must remain valid until the server is stopped
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xcp_create_segment(name: *const c_char, default_page: *const u8, size: u16) -> u16 {
+    guarded("xcp_create_segment", XCP_UNDEFINED, || {
+        if name.is_null() || default_page.is_null() {
+            return XCP_UNDEFINED;
+        }
+        // @@@@ UNSAFE - C library call, the name is copied, default_page is owned by the caller
+        unsafe { xcp_lite::_capi::XcpCreateCalSeg(name, default_page as *const std::os::raw::c_void, size) }
+    })
+}
+
+/// Register a calibration variable with an address relative to the start of the calibration segment
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xcp_register_characteristic(segment_name: *const c_char, desc: *const XcpVariableDescriptor) -> bool {
+    guarded("xcp_register_characteristic", false, || {
+        if desc.is_null() {
+            return false;
+        }
+        // @@@@ UNSAFE - C string and descriptor provided by the caller
+        let (Some(segment_name), desc) = (unsafe { to_string(segment_name) }, unsafe { &*desc }) else {
+            error!("xcp_register_characteristic: invalid segment name");
+            return false;
+        };
+        register_variable(desc, McObjectType::Characteristic, McAddress::new_calseg_rel(segment_name, desc.offset))
+    })
+}
+
+/// Lock a calibration segment for consistent read access, returns a pointer to the active page
+/// Must be paired with xcp_unlock_segment, the pointer is valid until then
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xcp_lock_segment(index: u16) -> *const u8 {
+    guarded("xcp_lock_segment", std::ptr::null(), || {
+        // @@@@ UNSAFE - C library call, index must be a segment index returned by xcp_create_segment
+        unsafe { xcp_lite::_capi::XcpLockCalSeg(index) }
+    })
+}
+
+/// Unlock a calibration segment, locked by xcp_lock_segment
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xcp_unlock_segment(index: u16) {
+    guarded("xcp_unlock_segment", (), || {
+        // @@@@ UNSAFE - C library call, index must be a segment locked by xcp_lock_segment
+        unsafe {
+            xcp_lite::_capi::XcpUnlockCalSeg(index);
+        }
+    });
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod capi_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_header_up_to_date() {
+        let generated = std::fs::read_to_string(concat!(env!("OUT_DIR"), "/xcp_lite.h")).unwrap();
+        let checked_in = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/include/xcp_lite.h")).unwrap();
+        assert!(generated == checked_in, "include/xcp_lite.h is outdated, regenerate it with cbindgen");
+    }
+
+    #[test]
+    fn test_value_type() {
+        assert_eq!(XcpValueType::try_from(4), Ok(XcpValueType::U32));
+        assert_eq!(XcpValueType::try_from(0), Err(0));
+        assert_eq!(XcpValueType::try_from(12), Err(12));
+    }
+}
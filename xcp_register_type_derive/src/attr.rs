@@ -1,6 +1,7 @@
 // Field attribute parsing for the McRegisterType derive.
 //
 // Recognized classifiers: characteristic, axis, measurement (at most one per field).
+// An optional `conversion` attribute describes a conversion rule other than linear.
 // Numeric keys (min, max, step, factor, offset) take numeric literals (negative allowed).
 // Text keys take string literals. Errors are reported as `compile_error!` with a span, never
 // as panics.
@@ -25,6 +26,16 @@ pub(crate) enum Qualifier {
     ReadOnly,
 }
 
+/// Conversion rule of the `conversion` attribute.
+pub(crate) enum Conversion {
+    /// `rat_func = [a, b, c, d, e, f]`
+    RatFunc([f64; 6]),
+    /// `table = [(raw, phys), ...]` or `table_nointp = [(raw, phys), ...]`
+    Table(Vec<(f64, f64)>, bool),
+    /// `verbal = [(raw, "label"), ...]`
+    Verbal(Vec<(i64, String)>),
+}
+
 /// Parsed attributes for a single field.
 #[derive(Default)]
 pub(crate) struct FieldAttrs {
@@ -48,6 +59,8 @@ pub(crate) struct FieldAttrs {
     /// Bare `enum_type` flag (no value): defer to the field type's `#[derive(McRegisterEnum)]`
     /// impl for the backing integer type and the A2L enum unit string.
     pub enum_auto: bool,
+    /// Conversion rule from `#[conversion(...)]`, replaces `factor` and `offset`.
+    pub conversion: Option<Conversion>,
 }
 
 impl Default for Classifier {
@@ -62,6 +75,10 @@ pub(crate) fn parse_attrs(field: &Field) -> syn::Result<FieldAttrs> {
     let mut classifier_set = false;
 
     for attr in &field.attrs {
+        if attr.path().is_ident("conversion") {
+            parse_conversion(&mut attrs, attr)?;
+            continue;
+        }
         let classifier = if attr.path().is_ident("characteristic") {
             Classifier::Characteristic
         } else if attr.path().is_ident("axis") {
@@ -109,9 +126,84 @@ pub(crate) fn parse_attrs(field: &Field) -> syn::Result<FieldAttrs> {
         })?;
     }
 
+    if attrs.conversion.is_some() && (attrs.factor.is_some() || attrs.offset.is_some() || attrs.enum_type.is_some() || attrs.enum_auto) {
+        return Err(syn::Error::new_spanned(
+            &field.ident,
+            "`conversion` can not be combined with `factor`, `offset` or `enum_type`",
+        ));
+    }
+
     Ok(attrs)
 }
 
+/// Parse `#[conversion(rat_func = [..] | table = [..] | table_nointp = [..] | verbal = [..])]`.
+fn parse_conversion(attrs: &mut FieldAttrs, attr: &syn::Attribute) -> syn::Result<()> {
+    attr.parse_nested_meta(|meta| {
+        let key = meta
+            .path
+            .get_ident()
+            .map(|i| i.to_string())
+            .ok_or_else(|| meta.error("expected a conversion key identifier"))?;
+        if attrs.conversion.is_some() {
+            return Err(meta.error("only one conversion rule is allowed per field"));
+        }
+        let expr: Expr = meta.value()?.parse()?;
+        let Expr::Array(array) = &expr else {
+            return Err(meta.error(format!("`{key}` expects an array")));
+        };
+        let conversion = match key.as_str() {
+            "rat_func" => {
+                let coeffs = array.elems.iter().map(expr_to_f64).collect::<Option<Vec<f64>>>();
+                let coeffs = coeffs.and_then(|c| <[f64; 6]>::try_from(c).ok());
+                Conversion::RatFunc(coeffs.ok_or_else(|| meta.error("`rat_func` expects 6 numeric coefficients [a, b, c, d, e, f]"))?)
+            }
+            "table" | "table_nointp" => {
+                let mut pairs = Vec::with_capacity(array.elems.len());
+                for elem in &array.elems {
+                    let pair = expr_to_pair(elem).and_then(|(raw, phys)| Some((expr_to_f64(raw)?, expr_to_f64(phys)?)));
+                    pairs.push(pair.ok_or_else(|| meta.error(format!("`{key}` expects numeric pairs (raw, phys)")))?);
+                }
+                if pairs.is_empty() || pairs.windows(2).any(|w| w[0].0 >= w[1].0) {
+                    return Err(meta.error(format!("`{key}` expects pairs sorted by raw value")));
+                }
+                Conversion::Table(pairs, key == "table")
+            }
+            "verbal" => {
+                let mut pairs = Vec::with_capacity(array.elems.len());
+                for elem in &array.elems {
+                    let pair = expr_to_pair(elem).and_then(|(raw, label)| Some((expr_to_i64(raw)?, expr_to_string(label)?)));
+                    pairs.push(pair.ok_or_else(|| meta.error("`verbal` expects pairs (integer, \"label\")"))?);
+                }
+                Conversion::Verbal(pairs)
+            }
+            _ => {
+                return Err(meta.error(format!("unknown conversion key `{key}`")));
+            }
+        };
+        attrs.conversion = Some(conversion);
+        Ok(())
+    })
+}
+
+/// Split a tuple expression `(a, b)`.
+fn expr_to_pair(expr: &Expr) -> Option<(&Expr, &Expr)> {
+    match expr {
+        Expr::Tuple(t) if t.elems.len() == 2 => Some((&t.elems[0], &t.elems[1])),
+        Expr::Group(g) => expr_to_pair(&g.expr),
+        _ => None,
+    }
+}
+
+/// Evaluate an expression to i64, supporting unary negation.
+fn expr_to_i64(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(i), .. }) => i.base10_parse::<i64>().ok(),
+        Expr::Unary(u) if matches!(u.op, syn::UnOp::Neg(_)) => expr_to_i64(&u.expr).map(|v| -v),
+        Expr::Group(g) => expr_to_i64(&g.expr),
+        _ => None,
+    }
+}
+
 fn apply_key(attrs: &mut FieldAttrs, classifier: Classifier, key: &str, expr: &Expr, meta: &syn::meta::ParseNestedMeta<'_>) -> syn::Result<()> {
     // Validate the key is allowed for the classifier.
     if !key_allowed(classifier, key) {
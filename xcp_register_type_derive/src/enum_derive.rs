@@ -58,6 +58,8 @@ pub(crate) fn expand_enum(input: &DeriveInput) -> syn::Result<TokenStream2> {
                 #unit
             }
         }
+        // A fieldless enum with integer repr is plain old data
+        unsafe impl ::xcp_registry::MeasureSafe for #enum_ident {}
     };
 
     Ok(expanded)
@@ -17,10 +17,10 @@ mod attr;
 mod enum_derive;
 mod ty;
 
-use attr::{Classifier, FieldAttrs, Qualifier};
+use attr::{Classifier, Conversion, FieldAttrs, Qualifier};
 use ty::BaseType;
 
-#[proc_macro_derive(McRegisterType, attributes(characteristic, axis, measurement))]
+#[proc_macro_derive(McRegisterType, attributes(characteristic, axis, measurement, conversion))]
 pub fn derive_mc_register_type(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     match expand(&input) {
@@ -61,6 +61,9 @@ fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
     let mut nested_typedef_creation: Vec<TokenStream2> = Vec::new();
     let mut typedef_field_adds: Vec<TokenStream2> = Vec::new();
     let mut size_assertions: Vec<TokenStream2> = Vec::new();
+    let mut measure_safe_checks: Vec<TokenStream2> = Vec::new();
+    let mut measure_safe_types: Vec<&syn::Type> = Vec::new();
+    let mut field_types: Vec<&syn::Type> = Vec::new();
 
     for field in fields {
         let field_ident = field.ident.as_ref().expect("named field");
@@ -69,6 +72,15 @@ fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
         let ft = ty::parse_type(&field.ty)?;
         let attrs = attr::parse_attrs(field)?;
 
+        // MeasureSafe layout check of the field element type. Fields with an explicit
+        // `enum_type = "<int>"` are covered by the size assertion below instead.
+        field_types.push(&field.ty);
+        if attrs.enum_type.is_none() {
+            let elem_ty = ty::innermost_elem(&field.ty);
+            measure_safe_checks.push(quote! { && <#elem_ty as ::xcp_registry::MeasureSafe>::NO_PADDING });
+            measure_safe_types.push(elem_ty);
+        }
+
         // An `enum_type = "<int>"` attribute treats the field (a Rust enum / opaque type) as the
         // named integer scalar instead of a nested typedef. The enum labels are described by the
         // `unit` string. The macro cannot see the enum definition, so the underlying integer type
@@ -145,8 +157,40 @@ fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
         });
     }
 
+    // Field offsets are taken from `offset_of!`, so the registry describes the real layout of any
+    // repr (default Rust layout with reordered fields, `C`, `packed`). The offsets are u16 in the
+    // registry, which is checked at compile time instead of truncating silently.
+    if input.generics.params.is_empty() {
+        size_assertions.push(quote! {
+            const _: () = assert!(
+                ::core::mem::size_of::<#struct_ident>() <= 0x10000,
+                concat!("McRegisterType: `", #type_name, "` exceeds 64 KiB, field offsets do not fit u16")
+            );
+        });
+    }
+
+    // Structs without generics are plain old data if all fields are. The impl is bounded by the
+    // field element types, the bounds are higher-ranked (`for<'__a>`), so they are not rejected as
+    // trivially false: a struct with a field which is not MeasureSafe, e.g. a calibration-only
+    // nested struct from another crate, still compiles, registers and calibrates, only measuring
+    // it fails to compile. `enum_type = "<int>"` fields are only covered by their width check.
+    // The absence of padding is asserted when a trigger or capture API evaluates NO_PADDING for
+    // the type. The check is layout independent (size of the struct equals the sum of the field
+    // sizes), no `#[repr(C)]` is required, the measured bytes are described by the exact field
+    // offsets.
+    let measure_safe_impl = if input.generics.params.is_empty() {
+        quote! {
+            unsafe impl ::xcp_registry::MeasureSafe for #struct_ident where #( for<'__a> #measure_safe_types: ::xcp_registry::MeasureSafe, )* {
+                const NO_PADDING: bool = ::core::mem::size_of::<#struct_ident>() == 0 #( + ::core::mem::size_of::<#field_types>() )* #( #measure_safe_checks )*;
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     let expanded = quote! {
         #( #size_assertions )*
+        #measure_safe_impl
         impl #impl_generics ::xcp_registry::McRegisterType for #struct_ident #ty_generics #where_clause {
             fn mc_type_name() -> &'static str {
                 #type_name
@@ -218,6 +262,20 @@ fn build_support(obj: &TokenStream2, attrs: &FieldAttrs) -> TokenStream2 {
         let unit = attrs.unit.clone().unwrap_or_default();
         support = quote! { #support.set_linear(#factor, #offset, #unit) };
     }
+    if let Some(conversion) = &attrs.conversion {
+        let c = match conversion {
+            Conversion::RatFunc(coeffs) => quote! { ::xcp_registry::McConversion::RatFunc([#(#coeffs),*]) },
+            Conversion::Table(pairs, interpolate) => {
+                let (raw, phys): (Vec<f64>, Vec<f64>) = pairs.iter().copied().unzip();
+                quote! { ::xcp_registry::McConversion::Table { pairs: vec![#((#raw, #phys)),*], interpolate: #interpolate } }
+            }
+            Conversion::Verbal(pairs) => {
+                let (raw, label): (Vec<i64>, Vec<&String>) = pairs.iter().map(|(r, l)| (*r, l)).unzip();
+                quote! { ::xcp_registry::McConversion::Verbal(vec![#((#raw, #label.to_string())),*]) }
+            }
+        };
+        support = quote! { #support.set_conversion(Some(#c)) };
+    }
     if let Some(q) = attrs.qualifier {
         let qt = match q {
             Qualifier::Volatile => quote! { ::xcp_registry::McObjectQualifier::Volatile },
@@ -0,0 +1,232 @@
+//-----------------------------------------------------------------------------
+// Module a2l_preserve
+// Round-trip preservation of A2L content which is not derived from the registry
+// The registry holds only the objects it understands, manually added content (COMPU_METHODs, FUNCTIONs, GROUPs, user defined IF_DATA, ...)
+// would be lost when an A2L file is loaded and written again
+// merge_a2l_str takes the generated A2L text and adds the blocks of the original A2L text, which the registry does not own, verbatim
+
+use std::collections::HashSet;
+
+//-------------------------------------------------------------------------------------------------
+// A2L block scanner
+
+// A /begin .. /end block in A2L text
+#[derive(Debug)]
+struct A2lBlock {
+    keyword: String,
+    name: String,          // First parameter, string literals without quotes
+    start: usize,          // Position of /begin
+    end_start: usize,      // Position of /end
+    end: usize,            // Position after the /end keyword
+    parent: Option<usize>, // Index of the enclosing block
+}
+
+// Split A2L text into tokens (start, end), comments are skipped, string literals are one token
+pub(super) fn tokenize(text: &str) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if bytes[i..].starts_with(b"/*") {
+            i = text[i + 2..].find("*/").map_or(bytes.len(), |p| i + 2 + p + 2);
+        } else if bytes[i..].starts_with(b"//") {
+            i = text[i..].find('\n').map_or(bytes.len(), |p| i + p + 1);
+        } else if c == b'"' {
+            // String literal, quotes are escaped with \" or ""
+            let start = i;
+            i += 1;
+            while i < bytes.len() {
+                if bytes[i] == b'\\' {
+                    i += 2;
+                } else if bytes[i] == b'"' {
+                    if bytes.get(i + 1) == Some(&b'"') {
+                        i += 2;
+                    } else {
+                        i += 1;
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+            tokens.push((start, i.min(bytes.len())));
+        } else {
+            let start = i;
+            while i < bytes.len() && !bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            tokens.push((start, i));
+        }
+    }
+    tokens
+}
+
+// Scan all /begin .. /end blocks, in order of their /begin
+fn scan_blocks(text: &str) -> Result<Vec<A2lBlock>, String> {
+    let tokens = tokenize(text);
+    let token = |i: usize| tokens.get(i).map(|(s, e)| &text[*s..*e]);
+
+    let mut blocks: Vec<A2lBlock> = Vec::new();
+    let mut stack: Vec<usize> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match token(i) {
+            Some("/begin") => {
+                let keyword = token(i + 1).ok_or("Unexpected end of A2L text after /begin")?;
+                let name = token(i + 2).unwrap_or("").trim_matches('"');
+                stack.push(blocks.len());
+                blocks.push(A2lBlock {
+                    keyword: keyword.to_string(),
+                    name: name.to_string(),
+                    start: tokens[i].0,
+                    end_start: 0,
+                    end: 0,
+                    parent: stack.len().checked_sub(2).map(|p| stack[p]),
+                });
+                i += 2;
+            }
+            Some("/end") => {
+                let keyword = token(i + 1).ok_or("Unexpected end of A2L text after /end")?;
+                let index = stack.pop().ok_or_else(|| format!("/end {} without /begin", keyword))?;
+                if blocks[index].keyword != keyword {
+                    return Err(format!("/end {} does not match /begin {}", keyword, blocks[index].keyword));
+                }
+                blocks[index].end_start = tokens[i].0;
+                blocks[index].end = tokens[i + 1].1;
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+    if let Some(index) = stack.pop() {
+        return Err(format!("/begin {} without /end", blocks[index].keyword));
+    }
+    Ok(blocks)
+}
+
+//-------------------------------------------------------------------------------------------------
+// Merge
+
+// Module level blocks owned by the registry, the generated A2L is authoritative for these, even if they are missing there
+fn is_registry_owned(keyword: &str, name: &str) -> bool {
+    matches!(keyword, "MOD_PAR" | "MOD_COMMON" | "MEASUREMENT" | "CHARACTERISTIC" | "AXIS_PTS" | "INSTANCE" | "BLOB")
+        || keyword.starts_with("TYPEDEF_")
+        || (keyword == "IF_DATA" && name == "XCP")
+}
+
+// Objects which may carry user defined IF_DATA
+fn is_object(keyword: &str) -> bool {
+    matches!(keyword, "MEASUREMENT" | "CHARACTERISTIC" | "AXIS_PTS" | "INSTANCE")
+}
+
+/// Merge generated A2L text with the content of an original A2L text, which is not derived from the registry
+/// Module level blocks of the original (COMPU_METHOD, COMPU_VTAB, FUNCTION, GROUP, UNIT, RECORD_LAYOUT, user defined IF_DATA, ...),
+/// which do not exist in the generated A2L, are appended verbatim to the generated MODULE
+/// User defined IF_DATA of MEASUREMENT, CHARACTERISTIC, AXIS_PTS and INSTANCE objects is inserted into the generated objects with the same name
+/// Objects, MOD_PAR, MOD_COMMON, typedefs and IF_DATA XCP are owned by the registry and never taken from the original
+/// Only the first MODULE of both texts is merged
+pub fn merge_a2l_str(original: &str, generated: &str) -> Result<String, String> {
+    let orig_blocks = scan_blocks(original)?;
+    let gen_blocks = scan_blocks(generated)?;
+    let module = |blocks: &[A2lBlock]| blocks.iter().position(|b| b.keyword == "MODULE");
+    let orig_module = module(&orig_blocks).ok_or("No MODULE in original A2L")?;
+    let gen_module = module(&gen_blocks).ok_or("No MODULE in generated A2L")?;
+
+    // Module level blocks of the generated A2L
+    let gen_children: Vec<&A2lBlock> = gen_blocks.iter().filter(|b| b.parent == Some(gen_module)).collect();
+    let gen_keys: HashSet<(&str, &str)> = gen_children.iter().map(|b| (b.keyword.as_str(), b.name.as_str())).collect();
+
+    let mut inserts: Vec<(usize, String)> = Vec::new(); // Position in generated text and text to insert
+    let mut preserved = String::new();
+    for (index, block) in orig_blocks.iter().enumerate() {
+        if block.parent != Some(orig_module) {
+            continue;
+        }
+        let text = &original[block.start..block.end];
+        if is_object(&block.keyword) {
+            // User defined IF_DATA of objects
+            let Some(gen_object) = gen_children.iter().find(|b| b.keyword == block.keyword && b.name == block.name) else {
+                continue;
+            };
+            let gen_text = &generated[gen_object.start..gen_object.end];
+            for ifdata in orig_blocks.iter().filter(|b| b.parent == Some(index) && b.keyword == "IF_DATA" && b.name != "XCP") {
+                if !gen_text.contains(&format!("/begin IF_DATA {}", ifdata.name)) {
+                    inserts.push((gen_object.end_start, format!("{}\n", &original[ifdata.start..ifdata.end])));
+                }
+            }
+        } else if !is_registry_owned(&block.keyword, &block.name) && !gen_keys.contains(&(block.keyword.as_str(), block.name.as_str())) {
+            log::debug!("A2L merge: preserve {} {}", block.keyword, block.name);
+            preserved.push_str(text);
+            preserved.push('\n');
+        }
+    }
+    if !preserved.is_empty() {
+        inserts.push((gen_blocks[gen_module].end_start, format!("\n/* Preserved from original A2L */\n{}\n", preserved)));
+    }
+
+    // Apply the inserts from the back, positions before the insert position stay valid
+    inserts.sort_by_key(|(pos, _)| *pos);
+    let mut merged = generated.to_string();
+    for (pos, text) in inserts.iter().rev() {
+        merged.insert_str(*pos, text);
+    }
+    Ok(merged)
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod a2l_preserve_tests {
+
+    use super::*;
+
+    const ORIGINAL: &str = r#"
+ASAP2_VERSION 1 71
+/begin PROJECT p ""
+/begin MODULE m ""
+    /begin MOD_PAR "" EPK "old" /end MOD_PAR
+    /begin COMPU_METHOD manual "" LINEAR "%.1" "km/h" COEFFS_LINEAR 0.1 0 /end COMPU_METHOD
+    /begin FUNCTION f "a \"quoted\" /end FUNCTION text" /begin DEF_CHARACTERISTIC c /end DEF_CHARACTERISTIC /end FUNCTION
+    /begin IF_DATA CANAPE_EXT 100 /end IF_DATA
+    /begin IF_DATA XCP /begin PROTOCOL_LAYER 0x0104 /end PROTOCOL_LAYER /end IF_DATA
+    /begin MEASUREMENT gone "" UBYTE NO_COMPU_METHOD 0 0 0 255 ECU_ADDRESS 0x1 /end MEASUREMENT
+    /* /begin GROUP commented "" /end GROUP */
+    /begin CHARACTERISTIC c "" VALUE 0x80000000 U8 0 NO_COMPU_METHOD 0 255
+        /begin IF_DATA CANAPE_EXT 100 LINK_MAP "c" 0x0 0 0 0 1 0 0 /end IF_DATA
+    /end CHARACTERISTIC
+/end MODULE
+/end PROJECT
+"#;
+
+    const GENERATED: &str = r#"
+ASAP2_VERSION 1 71
+/begin PROJECT p ""
+/begin MODULE m ""
+/begin MOD_PAR "" EPK "new" /end MOD_PAR
+/begin CHARACTERISTIC c "" VALUE 0x80010000 U8 0 NO_COMPU_METHOD 0 255
+/end CHARACTERISTIC
+/end MODULE
+/end PROJECT
+"#;
+
+    #[test]
+    fn test_merge_a2l_str() {
+        let merged = merge_a2l_str(ORIGINAL, GENERATED).unwrap();
+        assert!(merged.contains(r#"/begin COMPU_METHOD manual "" LINEAR "%.1" "km/h" COEFFS_LINEAR 0.1 0 /end COMPU_METHOD"#));
+        assert!(merged.contains(r#"/begin FUNCTION f "a \"quoted\" /end FUNCTION text" /begin DEF_CHARACTERISTIC c /end DEF_CHARACTERISTIC /end FUNCTION"#));
+        assert!(merged.contains("/begin IF_DATA CANAPE_EXT 100 /end IF_DATA"));
+        assert!(merged.contains("0x80010000 U8 0 NO_COMPU_METHOD 0 255\n/begin IF_DATA CANAPE_EXT 100 LINK_MAP"));
+        assert!(merged.contains(r#"EPK "new""#) && !merged.contains(r#"EPK "old""#));
+        assert!(!merged.contains("PROTOCOL_LAYER") && !merged.contains("gone") && !merged.contains("commented"));
+        assert!(scan_blocks(&merged).is_ok());
+
+        // Idempotent
+        assert_eq!(merge_a2l_str(ORIGINAL, &merged).unwrap(), merged);
+
+        assert!(merge_a2l_str("/begin MODULE m \"\"", GENERATED).is_err());
+    }
+}
@@ -357,7 +357,32 @@ fn registry_load_a2lfile(registry: &mut Registry, a2l_file: &a2lfile::A2lFile) -
                     for e in daq.event {
                         // Process each event
                         info!("Event found in IF_DATA XCP: {} - {}", e.event_channel_name, e.event_channel_number);
-                        registry.event_list.add_event(McEvent::new(e.event_channel_name, 0, e.event_channel_number, 0)).unwrap();
+                        let mut event = McEvent::new(e.event_channel_name, 0, e.event_channel_number, 0);
+                        // Direction and consistency of the event channel
+                        event.stim = matches!(e.anon_enum4, aml_ifdata::AnonEnum4::DaqStim);
+                        event.stim_only = matches!(e.anon_enum4, aml_ifdata::AnonEnum4::Stim);
+                        event.consistency = match e.consistency.map(|c| c.anon_enum5) {
+                            Some(aml_ifdata::AnonEnum5::Event) => McEventConsistency::Event,
+                            Some(aml_ifdata::AnonEnum5::Odt) => McEventConsistency::Odt,
+                            Some(aml_ifdata::AnonEnum5::None) => McEventConsistency::None,
+                            _ => McEventConsistency::Daq,
+                        };
+                        registry.event_list.add_event(event).unwrap();
+                    }
+                }
+                // Ethernet transport layer parameters, UDP preferred, the address is used as connection default by clients
+                let eth = match (xcp.xcp_on_udp_ip, xcp.xcp_on_tcp_ip) {
+                    (Some(udp), _) => Some(("UDP", udp.udp_ip_parameters.port, udp.udp_ip_parameters.address.map(|a| a.address_v4))),
+                    (None, Some(tcp)) => Some(("TCP", tcp.tcp_ip_parameters.port, tcp.tcp_ip_parameters.address.map(|a| a.address_v4))),
+                    (None, None) => None,
+                };
+                if let Some((protocol, port, address)) = eth {
+                    match address.as_deref().unwrap_or("127.0.0.1").parse::<std::net::Ipv4Addr>() {
+                        Ok(addr) => {
+                            info!("Transport layer found in IF_DATA XCP: {} {}:{}", protocol, addr, port);
+                            registry.set_xcp_eth_params(protocol, addr, port);
+                        }
+                        Err(_) => warn!("IF_DATA XCP_ON_{}_IP: address {:?} is not an IPv4 address, ignored", protocol, address),
                     }
                 }
             } else {
@@ -473,9 +498,15 @@ fn registry_load_a2lfile(registry: &mut Registry, a2l_file: &a2lfile::A2lFile) -
         let addr = characteristic.address;
         let address = new_mc_address_from_a2l(registry, addr, addr_ext, event_id, convert_a2l_address);
 
-        let res = registry.instance_list.add_instance(name, dim_type, mc_support_data, address);
+        let res = registry.instance_list.add_instance(name.clone(), dim_type, mc_support_data, address);
         match res {
-            Ok(_) => {}
+            Ok(_) => {
+                if let Some(link) = characteristic.symbol_link.as_ref()
+                    && let Err(e) = registry.instance_list.set_symbol_link(&name, event_id, &link.symbol_name, link.offset)
+                {
+                    warn!("Failed to set symbol link of {}: {}", name, e);
+                }
+            }
             Err(e) => {
                 warn!("Failed to add characteristic instance: {}", e);
             }
@@ -562,9 +593,15 @@ fn registry_load_a2lfile(registry: &mut Registry, a2l_file: &a2lfile::A2lFile) -
 
 
         // Add measurement instance
-        let res = registry.instance_list.add_instance(unique_name, dim_type, mc_support_data, address);
+        let res = registry.instance_list.add_instance(unique_name.clone(), dim_type, mc_support_data, address);
         match res {
-            Ok(_) => {}
+            Ok(_) => {
+                if let Some(link) = measurement.symbol_link.as_ref()
+                    && let Err(e) = registry.instance_list.set_symbol_link(&unique_name, event_id, &link.symbol_name, link.offset)
+                {
+                    warn!("Failed to set symbol link of {}: {}", unique_name, e);
+                }
+            }
             Err(e) => {
                 warn!("Failed to add measurement instance: {}", e);
             }
@@ -729,5 +766,48 @@ fn registry_load_a2lfile(registry: &mut Registry, a2l_file: &a2lfile::A2lFile) -
         }
     }
 
+    //----------------------------------------------------------------------------------------------------------------
+    // Measurement configurations
+
+    // Add the sub groups of the root group Presets as measurement configurations
+    if let Some(sub_group) = module.group.get("Presets").and_then(|g| g.sub_group.as_ref()) {
+        for name in &sub_group.identifier_list {
+            let Some(group) = module.group.get(name) else {
+                warn!("Measurement configuration {} not found", name);
+                continue;
+            };
+            let measurements: Vec<&str> = group.ref_measurement.iter().flat_map(|r| r.identifier_list.iter().map(String::as_str)).collect();
+            let mut config = McMeasurementConfig::new(name.clone(), group.long_identifier.clone(), &measurements);
+            config.set_auto_start(group.annotation.iter().any(|a| a.annotation_label.as_ref().is_some_and(|l| l.label == MC_AUTO_START_LABEL)));
+            if let Err(e) = registry.measurement_config_list.add_config(config) {
+                warn!("Failed to add measurement configuration: {}", e);
+            }
+        }
+    }
+
     Ok(())
 }
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod a2l_reader_tests {
+
+    use super::*;
+    use crate::registry_test::{include_test_aml, write_test_a2l};
+
+    #[test]
+    fn a2l_load_xcp_eth_params() {
+        let mut reg = Registry::new();
+        reg.set_xcp_eth_params("TCP", std::net::Ipv4Addr::new(192, 168, 0, 10), 5556);
+        reg.event_list.add_event(McEvent::new("task", 0, 0, 0)).unwrap();
+        let a2l = write_test_a2l(&reg);
+        let a2l = include_test_aml(&a2l);
+
+        let mut reg2 = Registry::new();
+        reg2.load_a2l_str(&a2l, &A2lLoadOptions::default()).unwrap();
+        assert_eq!(reg2.get_xcp_eth_params(), Some(("TCP", std::net::Ipv4Addr::new(192, 168, 0, 10), 5556)));
+        assert!(reg2.event_list.find_event_id(0).is_some());
+    }
+}
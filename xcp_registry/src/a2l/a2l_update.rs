@@ -0,0 +1,134 @@
+//-----------------------------------------------------------------------------
+// Module a2l_update
+// Update the addresses of an existing A2L text from the symbols of a new ELF file, like the update command of a2ltool
+// Objects with a SYMBOL_LINK get the address of the symbol plus the offset, all other content is kept verbatim,
+// manual edits of the A2L file survive a rebuild of the application
+
+use std::collections::HashMap;
+
+use super::a2l_preserve::tokenize;
+
+// Position of the address parameter after the object name, None if the address is given by ECU_ADDRESS
+fn address_position(keyword: &str) -> Option<Option<usize>> {
+    match keyword {
+        "MEASUREMENT" => Some(None),
+        "CHARACTERISTIC" | "INSTANCE" => Some(Some(3)), // name long_identifier type/typedef address
+        "AXIS_PTS" | "BLOB" => Some(Some(2)),           // name long_identifier address
+        _ => None,
+    }
+}
+
+/// Update the addresses of MEASUREMENT, CHARACTERISTIC, AXIS_PTS, INSTANCE and BLOB objects with a SYMBOL_LINK
+/// The new address is the value of the linked symbol in symbols (name -> address) plus the SYMBOL_LINK offset
+/// Objects without SYMBOL_LINK and all other A2L content are not modified
+/// Returns the updated A2L text and a report with one line per changed address (~) and per symbol not found (!)
+pub fn update_a2l_addresses_str(text: &str, symbols: &HashMap<String, u64>) -> Result<(String, Vec<String>), String> {
+    let tokens = tokenize(text);
+    let token = |i: usize| tokens.get(i).map(|(s, e)| &text[*s..*e]);
+
+    let mut replacements: Vec<(usize, usize, String)> = Vec::new(); // Text range and new address
+    let mut report = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let (Some("/begin"), Some(keyword)) = (token(i), token(i + 1)) else {
+            i += 1;
+            continue;
+        };
+        let Some(position) = address_position(keyword) else {
+            i += 2;
+            continue;
+        };
+        let name = token(i + 2).ok_or_else(|| format!("Unexpected end of A2L text in {}", keyword))?;
+
+        // Find the address token and the SYMBOL_LINK on the object level, skip nested blocks
+        let mut address = position.map(|p| i + 2 + p);
+        let mut symbol_link = None;
+        let mut depth = 0;
+        let mut j = i + 2;
+        loop {
+            match token(j) {
+                None => return Err(format!("/begin {} {} without /end", keyword, name)),
+                Some("/begin") => depth += 1,
+                Some("/end") if depth == 0 => break,
+                Some("/end") => {
+                    depth -= 1;
+                    j += 1; // Skip the keyword of /end
+                }
+                Some("ECU_ADDRESS") if depth == 0 => address = Some(j + 1),
+                Some("SYMBOL_LINK") if depth == 0 => symbol_link = token(j + 1).zip(token(j + 2)),
+                _ => {}
+            }
+            j += 1;
+        }
+        i = j + 2;
+
+        let (Some(address), Some((symbol, offset))) = (address, symbol_link) else {
+            continue;
+        };
+        let symbol = symbol.trim_matches('"');
+        let offset: i64 = offset.parse().map_err(|_| format!("{} {}: invalid SYMBOL_LINK offset {}", keyword, name, offset))?;
+        let Some(value) = symbols.get(symbol) else {
+            report.push(format!("! {} {}: symbol {} not found, address not updated", keyword, name, symbol));
+            continue;
+        };
+        let new_address = i64::try_from(*value)
+            .ok()
+            .and_then(|v| u32::try_from(v + offset).ok())
+            .ok_or_else(|| format!("{} {}: address of symbol {} exceeds 32 bit", keyword, name, symbol))?;
+        let (start, end) = tokens.get(address).copied().ok_or_else(|| format!("{} {}: address expected", keyword, name))?;
+        let old = &text[start..end];
+        let old_address = old
+            .strip_prefix("0x")
+            .or_else(|| old.strip_prefix("0X"))
+            .map_or_else(|| old.parse().ok(), |hex| u32::from_str_radix(hex, 16).ok());
+        if old_address != Some(new_address) {
+            report.push(format!("~ {} {}: address {} -> 0x{:X}", keyword, name, old, new_address));
+            replacements.push((start, end, format!("0x{:X}", new_address)));
+        }
+    }
+
+    // Apply the replacements from the back, positions before the replacement stay valid
+    let mut updated = text.to_string();
+    for (start, end, address) in replacements.iter().rev() {
+        updated.replace_range(*start..*end, address);
+    }
+    Ok((updated, report))
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod a2l_update_tests {
+
+    use super::*;
+
+    const A2L: &str = r#"
+/begin MODULE m ""
+    /* Manually edited */
+    /begin MEASUREMENT counter "counter" UWORD NO_COMPU_METHOD 0 0 0 65535
+        ECU_ADDRESS 0x1000 SYMBOL_LINK "counter" 0
+        /begin IF_DATA CANAPE_EXT 100 LINK_MAP "counter" 0x1000 0 0 0 1 0 0 /end IF_DATA
+    /end MEASUREMENT
+    /begin CHARACTERISTIC params.gain "gain" VALUE 0x2000 F32 0 NO_COMPU_METHOD 0 10 SYMBOL_LINK "params" 4 /end CHARACTERISTIC
+    /begin INSTANCE params "" Params 0x2000 SYMBOL_LINK "params" 0 /end INSTANCE
+    /begin MEASUREMENT unlinked "" UBYTE NO_COMPU_METHOD 0 0 0 255 ECU_ADDRESS 0x3000 /end MEASUREMENT
+    /begin AXIS_PTS axis "" 0x4000 NO_INPUT_QUANTITY A 0 NO_COMPU_METHOD 8 0 10 SYMBOL_LINK "gone" 0 /end AXIS_PTS
+/end MODULE
+"#;
+
+    #[test]
+    fn test_update_a2l_addresses_str() {
+        let symbols = HashMap::from([("counter".to_string(), 0x1000), ("params".to_string(), 0x2200)]);
+        let (updated, report) = update_a2l_addresses_str(A2L, &symbols).unwrap();
+        assert!(updated.contains("/* Manually edited */"));
+        assert!(updated.contains(r#"ECU_ADDRESS 0x1000 SYMBOL_LINK "counter" 0"#));
+        assert!(updated.contains(r#"LINK_MAP "counter" 0x1000"#));
+        assert!(updated.contains(r#"VALUE 0x2204 F32"#));
+        assert!(updated.contains(r#"Params 0x2200 SYMBOL_LINK"#));
+        assert!(updated.contains("ECU_ADDRESS 0x3000 /end"));
+        assert!(updated.contains(r#""" 0x4000 NO_INPUT_QUANTITY"#));
+        assert_eq!(report.len(), 3);
+        assert!(report.iter().any(|r| r.starts_with("! AXIS_PTS axis")));
+    }
+}
@@ -127,6 +127,46 @@ fn write_conversion<'a>(writer: &mut A2lWriter, name: &'a str, instance_index: u
     if dim_type.value_type == McValueType::Bool {
         Ok("BOOL")
     }
+    // Conversion rule other than linear
+    else if let Some(conversion) = mc_support_data.get_conversion() {
+        if instance_index > 1 {
+            return Ok(name);
+        }
+        let layout: u8 = if dim_type.value_type == McValueType::Float32Ieee || dim_type.value_type == McValueType::Float64Ieee {
+            6
+        } else {
+            3
+        };
+        match conversion {
+            McConversion::RatFunc([a, b, c, d, e, f]) => {
+                writeln!(
+                    writer,
+                    r#"/begin COMPU_METHOD {name} "" RAT_FUNC "%.{layout}" "{unit}" COEFFS {a} {b} {c} {d} {e} {f} /end COMPU_METHOD"#
+                )?;
+            }
+            McConversion::Table { pairs, interpolate } => {
+                let tab_type = if *interpolate { "TAB_INTP" } else { "TAB_NOINTP" };
+                write!(writer, r#"/begin COMPU_TAB {name}.table "" {tab_type} {}"#, pairs.len())?;
+                for (raw, phys) in pairs {
+                    write!(writer, " {raw} {phys}")?;
+                }
+                writeln!(writer, " /end COMPU_TAB")?;
+                writeln!(
+                    writer,
+                    r#"/begin COMPU_METHOD {name} "" {tab_type} "%.{layout}" "{unit}" COMPU_TAB_REF {name}.table /end COMPU_METHOD"#
+                )?;
+            }
+            McConversion::Verbal(pairs) => {
+                write!(writer, r#"/begin COMPU_VTAB {name}.table "" TAB_VERB {}"#, pairs.len())?;
+                for (raw, label) in pairs {
+                    write!(writer, r#" {raw} "{}""#, label.replace('"', "\\\""))?;
+                }
+                writeln!(writer, " /end COMPU_VTAB")?;
+                writeln!(writer, r#"/begin COMPU_METHOD {name} "" TAB_VERB "%.0" "" COMPU_TAB_REF {name}.table /end COMPU_METHOD"#)?;
+            }
+        }
+        Ok(name)
+    }
     // Conversion: Write a conversion rule and return its name
     else if (factor - 1.0).abs() > f64::EPSILON || offset.abs() > f64::EPSILON {
         // For measurements with multiple tli instances, the conversion name is created only once on index 1
@@ -431,6 +471,16 @@ impl GenerateA2l for McEvent {
             )?;
         }
 
+        // Write comment with the value change mode, the tool receives samples only on a change or after the max interval
+        if self.on_change_max_interval_ns > 0 {
+            writeln!(writer, "\t\t\t/* on change, max interval = {} ns */", self.on_change_max_interval_ns)?;
+        }
+
+        // Write comment with the default measurement rate, which differs from the target cycle time
+        if self.default_cycle_time_ns > 0 && self.default_cycle_time_ns != self.target_cycle_time_ns {
+            writeln!(writer, "\t\t\t/* default measurement rate = {} ns */", self.default_cycle_time_ns)?;
+        }
+
         // long name 100+1 characters
         // short name 8+1 characters
         // TimeCycle 0
@@ -445,7 +495,19 @@ impl GenerateA2l for McEvent {
             let short_name = if name.len() > 8 { &name[name.len() - 8..] } else { name };
             write!(writer, "\t\t\t/begin EVENT \"{:.100}\" \"{}\" ", name, short_name)?;
         }
-        writeln!(writer, "{} DAQ 0xFF {} {} {} CONSISTENCY DAQ /end EVENT", id, time_cycle, time_unit, priority)
+        // Direction DAQ_STIM for bidirectional events, the tool may stimulate the values of this event, STIM for stimulation only events
+        let direction = if self.stim_only {
+            "STIM"
+        } else if self.stim {
+            "DAQ_STIM"
+        } else {
+            "DAQ"
+        };
+        writeln!(
+            writer,
+            "{} {} 0xFF {} {} {} CONSISTENCY {} /end EVENT",
+            id, direction, time_cycle, time_unit, priority, self.consistency
+        )
     }
 }
 
@@ -459,6 +521,11 @@ impl GenerateA2l for McApplication {
             writeln!(writer, "EPK \"{}\" ADDR_EPK 0x{:08X}", self.version.epk, self.version.epk_addr)?;
         }
 
+        // System constants
+        for (name, value) in &self.system_constants {
+            writeln!(writer, "SYSTEM_CONSTANT \"{}\" \"{}\"", name, value)?;
+        }
+
         Ok(())
     }
 }
@@ -487,14 +554,18 @@ impl GenerateA2l for McCalibrationSegment {
         writeln!(
             writer,
             r#"/begin IF_DATA XCP
-    /begin SEGMENT {} /*number*/ 2 /*pages*/ {} /* addr_ext*/ 0 0
-    /begin CHECKSUM XCP_ADD_44 MAX_BLOCK_SIZE 0xFFFF EXTERNAL_FUNCTION "" /end CHECKSUM
-    /begin PAGE 0x0 ECU_ACCESS_DONT_CARE XCP_READ_ACCESS_DONT_CARE XCP_WRITE_ACCESS_DONT_CARE /end PAGE
-    /begin PAGE 0x1 ECU_ACCESS_DONT_CARE XCP_READ_ACCESS_DONT_CARE XCP_WRITE_ACCESS_NOT_ALLOWED /end PAGE
-    /end SEGMENT
-/end IF_DATA"#,
-            self.index, self.addr_ext,
+    /begin SEGMENT {} /*number*/ {} /*pages*/ {} /* addr_ext*/ 0 0
+    /begin CHECKSUM XCP_ADD_44 MAX_BLOCK_SIZE 0xFFFF EXTERNAL_FUNCTION "" /end CHECKSUM"#,
+            self.index,
+            self.get_max_pages(),
+            self.addr_ext,
         )?;
+        for page in 0..self.get_max_pages() {
+            if let Some(properties) = self.get_page_properties(page) {
+                writeln!(writer, "    /begin PAGE 0x{:X} {} /end PAGE", page, properties)?;
+            }
+        }
+        writeln!(writer, "    /end SEGMENT\n/end IF_DATA")?;
 
         writeln!(writer, r#"/end MEMORY_SEGMENT"#,)?;
         //}
@@ -654,6 +725,26 @@ impl GenerateA2l for McTypeDef {
     }
 }
 
+// Linker symbol of an instance as SYMBOL_LINK, to update the address from a new ELF file by symbol name
+fn write_symbol_link(instance: &McInstance, writer: &mut A2lWriter) -> std::io::Result<()> {
+    if let Some((symbol, offset)) = instance.get_symbol_link() {
+        write!(writer, r#" SYMBOL_LINK "{}" {}"#, symbol, offset)?;
+    }
+    Ok(())
+}
+
+// Source location file:line of an instance as ANNOTATION, to navigate from the object in the tool to the source code
+fn write_source_annotation(instance: &McInstance, writer: &mut A2lWriter) -> std::io::Result<()> {
+    if let Some(source) = instance.get_source() {
+        write!(
+            writer,
+            r#" /begin ANNOTATION ANNOTATION_LABEL "SourceLocation" ANNOTATION_ORIGIN "" /begin ANNOTATION_TEXT "{}" /end ANNOTATION_TEXT /end ANNOTATION"#,
+            source.replace('\\', "/").replace('"', "\\\"")
+        )?;
+    }
+    Ok(())
+}
+
 //-------------------------------------------------------------------------------------------------
 // Generate MEASUREMENT or INSTANCE
 // Depending on RegistryMeasurement type
@@ -703,6 +794,8 @@ impl McInstance {
             let instance_name = self.get_unique_name(writer.registry);
             write!(writer, r#"/begin INSTANCE {instance_name} "{comment}" {type_name} 0x{addr:X} ECU_ADDRESS_EXTENSION {ext}"#)?;
             write_dimensions(dim_type, writer)?;
+            write_symbol_link(self, writer)?;
+            write_source_annotation(self, writer)?;
             if let Some(id) = event_id {
                 write_ifdata_event(id, writer)?;
             }
@@ -742,6 +835,8 @@ impl McInstance {
                 if ext != 0 {
                     write!(writer, " ECU_ADDRESS_EXTENSION {}", ext)?;
                 }
+                write_symbol_link(self, writer)?;
+                write_source_annotation(self, writer)?;
                 if let Some(id) = event_id {
                     write_ifdata_event(id, writer)?;
                 }
@@ -768,6 +863,8 @@ impl McInstance {
                     write!(writer, r#" STEP_SIZE {}"#, step.unwrap())?;
                 }
                 write_dimensions(dim_type, writer)?;
+                write_symbol_link(self, writer)?;
+                write_source_annotation(self, writer)?;
                 if let Some(id) = event_id {
                     write_ifdata_event(id, writer)?;
                 }
@@ -840,6 +937,8 @@ impl McInstance {
         if let McValueType::TypeDef(type_name) = &self.dim_type.value_type {
             write!(writer, r#"/begin INSTANCE {} "{}" {type_name} 0x{:X}"#, name, comment, addr)?;
             write_dimensions(dim_type, writer)?;
+            write_symbol_link(self, writer)?;
+            write_source_annotation(self, writer)?;
             writeln!(writer, r#" /end INSTANCE"#)?;
         }
         // All other value types: -> CHARACTERISTIC
@@ -885,6 +984,8 @@ impl McInstance {
             if ext != 0 {
                 write!(writer, " ECU_ADDRESS_EXTENSION {}", ext)?;
             }
+            write_symbol_link(self, writer)?;
+            write_source_annotation(self, writer)?;
 
             writeln!(writer, " /end CHARACTERISTIC")?;
         }
@@ -929,8 +1030,8 @@ impl<'a> A2lWriter<'a> {
         false
     }
 
-    fn write_a2l_head(&mut self, title_comment: &str, project_name: &str, project_description: &str, module_name: &str, project_no: &str) -> std::io::Result<()> {
-        writeln!(
+    fn write_a2l_project_head(&mut self, title_comment: &str, project_name: &str, project_description: &str, project_no: &str) -> std::io::Result<()> {
+        write!(
             self,
             r#"
 /* {title_comment} */
@@ -938,7 +1039,14 @@ ASAP2_VERSION 1 71
 /begin PROJECT {project_name} "{project_description}"
 /begin HEADER "" VERSION "1.0" PROJECT_NO {project_no} /end HEADER
 
-/begin MODULE {module_name} ""
+"#
+        )
+    }
+
+    fn write_a2l_module_head(&mut self, module_name: &str) -> std::io::Result<()> {
+        writeln!(
+            self,
+            r#"/begin MODULE {module_name} ""
         
     /include "XCP_104.aml"
     
@@ -1088,6 +1196,11 @@ ASAP2_VERSION 1 71
             "
         )?;
 
+        // STIM properties, if any event is bidirectional
+        if self.registry.event_list.into_iter().any(|e| e.stim || e.stim_only) {
+            writeln!(self, "\t\t\t/begin STIM GRANULARITY_ODT_ENTRY_SIZE_STIM_BYTE 0xF8 /end STIM")?;
+        }
+
         // Eventlist
         for e in &self.registry.event_list {
             e.write_a2l(self)?;
@@ -1139,10 +1252,11 @@ ASAP2_VERSION 1 71
 
         // GROUP
         // Group root measurement
+        // STIM only events have no measurement lists
         write!(self, "\n/begin GROUP Measurements \"\" ROOT /begin SUB_GROUP")?;
         for e in &self.registry.event_list {
             // Ignore all but the first event instance
-            if e.index > 1 {
+            if e.index > 1 || !e.is_daq() {
                 continue;
             }
             write!(self, " {}", e.name)?;
@@ -1152,7 +1266,7 @@ ASAP2_VERSION 1 71
         // Sub group for each event with event name as group name
         for event in &self.registry.event_list {
             // Ignore all but the first event instance, and compare events by name
-            if event.index > 1 {
+            if event.index > 1 || !event.is_daq() {
                 continue;
             }
             let event_name = &event.name;
@@ -1170,6 +1284,87 @@ ASAP2_VERSION 1 71
             writeln!(self, " /end REF_MEASUREMENT /end GROUP")?;
         }
 
+        if self.registry.get_measurement_presets_mode() || !self.registry.measurement_config_list.is_empty() {
+            self.write_a2l_measurement_presets()?;
+        }
+
+        Ok(())
+    }
+
+    // Measurement presets, the measurement configurations of the application and, if enabled, a GROUP preset_<event> for each event instance
+    // with measurements, with the default measurement rate in the description
+    // A tool opening the A2L offers them as ready-made measurement configurations
+    fn write_a2l_measurement_presets(&mut self) -> std::io::Result<()> {
+        let mut presets: Vec<(String, String, Vec<String>, bool)> = Vec::new();
+        for config in &self.registry.measurement_config_list {
+            let mut measurements: Vec<String> = Vec::new();
+            for name in &config.measurements {
+                let len = measurements.len();
+                measurements.extend(
+                    self.registry
+                        .instance_list
+                        .into_iter()
+                        .filter(|i| i.is_measurement_object() && i.name == *name)
+                        .map(|i| i.get_unique_name(self.registry).to_string()),
+                );
+                if measurements.len() == len {
+                    log::warn!("Measurement configuration {}: measurement {} not found", config.name, name);
+                }
+            }
+            presets.push((config.name.to_string(), config.comment.to_string(), measurements, config.auto_start));
+        }
+        let event_presets = self.registry.get_measurement_presets_mode();
+        for event in &self.registry.event_list {
+            if !event_presets || !event.is_daq() {
+                continue;
+            }
+            let measurements: Vec<String> = self
+                .registry
+                .instance_list
+                .into_iter()
+                .filter(|i| i.is_measurement_object() && i.address.get_event_id() == Some(event.id))
+                .map(|i| i.get_unique_name(self.registry).to_string())
+                .collect();
+            if measurements.is_empty() {
+                continue;
+            }
+            let event_name = event.get_unique_name(self.registry);
+            let rate = match event.get_default_cycle_time_ns() {
+                0 => "sporadic".to_string(),
+                t if t % 1_000_000 == 0 => format!("{} ms", t / 1_000_000),
+                t if t % 1_000 == 0 => format!("{} us", t / 1_000),
+                t => format!("{} ns", t),
+            };
+            let name = format!("preset_{}", event_name);
+            // A measurement configuration of the application, e.g. loaded from an A2L, replaces the generated preset
+            if self.registry.measurement_config_list.find_config(&name).is_some() {
+                continue;
+            }
+            presets.push((name, format!("Measurement preset of event {}, {}", event_name, rate), measurements, false));
+        }
+        if presets.is_empty() {
+            return Ok(());
+        }
+
+        write!(self, "\n/begin GROUP Presets \"\" ROOT /begin SUB_GROUP")?;
+        for (name, _, _, _) in &presets {
+            write!(self, " {name}")?;
+        }
+        writeln!(self, " /end SUB_GROUP /end GROUP")?;
+        for (name, comment, measurements, auto_start) in &presets {
+            write!(self, "/begin GROUP {name} \"{comment}\"")?;
+            if *auto_start {
+                write!(self, " /begin ANNOTATION ANNOTATION_LABEL \"{}\" /end ANNOTATION", MC_AUTO_START_LABEL)?;
+            }
+            if !measurements.is_empty() {
+                write!(self, " /begin REF_MEASUREMENT")?;
+                for m in measurements {
+                    write!(self, " {m}")?;
+                }
+                write!(self, " /end REF_MEASUREMENT")?;
+            }
+            writeln!(self, " /end GROUP")?;
+        }
         Ok(())
     }
 
@@ -1239,13 +1434,54 @@ ASAP2_VERSION 1 71
         Ok(())
     }
 
-    fn write_a2l_tail(&mut self) -> std::io::Result<()> {
-        self.write_all("\n/end MODULE\n/end PROJECT\n".as_bytes())
+    fn write_a2l_functions(&mut self) -> std::io::Result<()> {
+        // Functions where events are defined, set by the ELF reader from the DWARF subprogram info (set_event_location)
+        let mut functions: Vec<&str> = Vec::new();
+        for e in &self.registry.event_list {
+            if let Some(function) = e.function.as_deref()
+                && !functions.contains(&function)
+            {
+                functions.push(function);
+            }
+        }
+        if functions.is_empty() {
+            return Ok(());
+        }
+
+        // Write a FUNCTION for each function with the measurements of its events as LOC_MEASUREMENT
+        // Calibration objects are not assigned to functions, there is no DEF_CHARACTERISTIC
+        writeln!(self, "\n/* Functions */")?;
+        for function in functions {
+            let mut n = 0;
+            for instance in self.registry.instance_list.into_iter().filter(|i| i.is_measurement_object()) {
+                let Some(event) = instance.address.get_event_id().and_then(|id| self.registry.event_list.find_event_id(id)) else {
+                    continue;
+                };
+                if event.function.as_deref() == Some(function) {
+                    n += 1;
+                    if n == 1 {
+                        // C++ scope separators and other characters not allowed in A2L identifiers are replaced by '_'
+                        let name: String = function.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '.' { c } else { '_' }).collect();
+                        write!(self, "/begin FUNCTION {} \"\" /begin LOC_MEASUREMENT", name)?;
+                    }
+                    write!(self, " {}", instance.get_unique_name(self.registry))?;
+                }
+            }
+            if n > 0 {
+                writeln!(self, " /end LOC_MEASUREMENT /end FUNCTION")?;
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn write_a2l(&mut self, title_comment: &str, project_name: &str, project_description: &str, module_name: &str, project_no: &str) -> Result<(), std::io::Error> {
-        assert!(!project_name.is_empty() && !module_name.is_empty() && !project_no.is_empty());
-        self.write_a2l_head(title_comment, project_name, project_description, module_name, project_no)?;
+    fn write_a2l_project_tail(&mut self) -> std::io::Result<()> {
+        self.write_all("/end PROJECT\n".as_bytes())
+    }
+
+    // Write the registry as one MODULE
+    fn write_a2l_module(&mut self, module_name: &str) -> std::io::Result<()> {
+        self.write_a2l_module_head(module_name)?;
         self.write_a2l_modpar()?;
         if self.registry.has_xcp_params() {
             self.write_a2l_if_data()?;
@@ -1254,7 +1490,85 @@ ASAP2_VERSION 1 71
         self.write_a2l_measurements()?;
         self.write_a2l_characteristics()?;
         self.write_a2l_groups()?;
-        self.write_a2l_tail()?;
+        self.write_a2l_functions()?;
+        self.write_all("\n/end MODULE\n".as_bytes())
+    }
+
+    pub fn write_a2l(&mut self, title_comment: &str, project_name: &str, project_description: &str, module_name: &str, project_no: &str) -> Result<(), std::io::Error> {
+        assert!(!project_name.is_empty() && !module_name.is_empty() && !project_no.is_empty());
+        self.write_a2l_project_head(title_comment, project_name, project_description, project_no)?;
+        self.write_a2l_module(module_name)?;
+        self.write_a2l_project_tail()?;
         Ok(())
     }
+
+    /// Write a PROJECT with one MODULE per registry, the writer registry is not used
+    pub fn write_a2l_modules(
+        writer: &mut dyn Write,
+        title_comment: &str,
+        project_name: &str,
+        project_description: &str,
+        project_no: &str,
+        modules: &[(&str, &Registry)],
+    ) -> Result<(), std::io::Error> {
+        assert!(!project_name.is_empty() && !project_no.is_empty() && !modules.is_empty());
+        A2lWriter::new(&mut *writer, modules[0].1).write_a2l_project_head(title_comment, project_name, project_description, project_no)?;
+        for (module_name, registry) in modules {
+            assert!(!module_name.is_empty());
+            A2lWriter::new(&mut *writer, registry).write_a2l_module(module_name)?;
+        }
+        writer.write_all("/end PROJECT\n".as_bytes())
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod a2l_writer_tests {
+
+    use super::*;
+    use crate::registry_test::write_test_a2l;
+
+    #[test]
+    fn a2l_functions_from_event_location() {
+        let mut reg = Registry::new();
+        reg.event_list.add_event(McEvent::new("task", 0, 0, 0)).unwrap();
+        reg.event_list.add_event(McEvent::new("other", 0, 1, 0)).unwrap();
+        reg.event_list.add_event(McEvent::new("unknown", 0, 2, 0)).unwrap();
+        reg.event_list.set_event_location("task", 0, "ns::task_main", 0).unwrap();
+        reg.event_list.set_event_location("other", 0, "ns::task_main", 0).unwrap();
+        let mea = McSupportData::new(McObjectType::Measurement);
+        let ubyte = McDimType::new(McValueType::Ubyte, 1, 1);
+        for (name, event) in [("a", 0), ("b", 1), ("c", 2)] {
+            reg.instance_list
+                .add_instance(name, ubyte.clone(), mea.clone(), McAddress::new_event_dyn(0, event, 0))
+                .unwrap();
+        }
+
+        let a2l = write_test_a2l(&reg);
+        assert!(
+            a2l.contains("/begin FUNCTION ns__task_main \"\" /begin LOC_MEASUREMENT a b /end LOC_MEASUREMENT /end FUNCTION"),
+            "{}",
+            a2l
+        );
+        assert_eq!(a2l.matches("/begin FUNCTION").count(), 1);
+    }
+
+    #[test]
+    fn a2l_mod_par_epk_system_constants() {
+        let mut reg = Registry::new();
+        reg.application.set_version("EPK_1.0", 0x80000000);
+        reg.application.set_system_constant("SC_CHANNELS", "4");
+        reg.application.set_system_constant("SC_MODE", "debug");
+        reg.application.set_system_constant("SC_CHANNELS", "8");
+        assert_eq!(reg.application.get_system_constant("SC_CHANNELS"), Some("8"));
+        assert!(reg.application.get_system_constant("SC_OTHER").is_none());
+
+        let a2l = write_test_a2l(&reg);
+        let mod_par = &a2l[a2l.find("/begin MOD_PAR").unwrap()..a2l.find("/end MOD_PAR").unwrap()];
+        assert!(mod_par.contains("EPK \"EPK_1.0\" ADDR_EPK 0x80000000"), "{}", mod_par);
+        assert!(mod_par.contains("SYSTEM_CONSTANT \"SC_CHANNELS\" \"8\""), "{}", mod_par);
+        assert!(mod_par.contains("SYSTEM_CONSTANT \"SC_MODE\" \"debug\""), "{}", mod_par);
+    }
 }
@@ -2,6 +2,8 @@
 // Module a2l
 // Read, write and check A2L files
 
+pub mod a2l_preserve;
+pub mod a2l_update;
 #[cfg(feature = "a2l_reader")]
 pub mod a2l_reader;
 pub mod a2l_writer;
@@ -10,6 +12,53 @@ pub mod aml_ifdata;
 
 use super::*;
 
+//-------------------------------------------------------------------------------------------------
+// A2lLoadOptions
+
+/// Options for loading an A2L file into a registry
+/// Default keeps the typedef and instance structure of the A2L file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct A2lLoadOptions {
+    /// Print parser warnings to log
+    pub print_warnings: bool,
+    /// Strict mode parsing
+    pub strict: bool,
+    /// Perform additional consistency checks and print findings to log
+    pub check: bool,
+    /// Flatten nested typedefs to basic type instances with hierarchical names (instance.field)
+    pub flatten_typedefs: bool,
+    /// Mangle flattened names to identifiers, '.' is replaced by '_'
+    pub mangle_names: bool,
+    /// Load MEASUREMENT objects
+    pub include_measurements: bool,
+    /// Load CHARACTERISTIC and AXIS_PTS objects
+    pub include_characteristics: bool,
+}
+
+impl Default for A2lLoadOptions {
+    fn default() -> Self {
+        A2lLoadOptions {
+            print_warnings: true,
+            strict: false,
+            check: false,
+            flatten_typedefs: false,
+            mangle_names: false,
+            include_measurements: true,
+            include_characteristics: true,
+        }
+    }
+}
+
+impl A2lLoadOptions {
+    /// Flattened typedefs, for clients which do not support typedefs and instances
+    pub fn flattened() -> Self {
+        A2lLoadOptions {
+            flatten_typedefs: true,
+            ..A2lLoadOptions::default()
+        }
+    }
+}
+
 impl Registry {
     //---------------------------------------------------------------------------------------------------------
     // Check A2L file
@@ -19,7 +68,18 @@ impl Registry {
     #[cfg(feature = "a2l_reader")]
     pub fn check_a2l<P: AsRef<std::path::Path>>(&self, path: &P) -> Result<u32, String> {
         // Read A2L file into A2lFile
-        let res = a2lfile::load(path, None, true);
+        Self::check_a2lfile(a2lfile::load(path, None, true))
+    }
+
+    /// Check A2L text in memory
+    /// Syntax and consistency check of A2L content, without file system access
+    #[cfg(feature = "a2l_reader")]
+    pub fn check_a2l_str(&self, a2l_text: &str) -> Result<u32, String> {
+        Self::check_a2lfile(a2lfile::load_from_string(a2l_text, None, true))
+    }
+
+    #[cfg(feature = "a2l_reader")]
+    fn check_a2lfile(res: Result<(a2lfile::A2lFile, Vec<a2lfile::A2lError>), a2lfile::A2lError>) -> Result<u32, String> {
         match res {
             Ok((a2l_file, log_msgs)) => {
                 let mut warnings: u32 = 0;
@@ -50,21 +110,32 @@ impl Registry {
     /// Load A2L file into this registry
     /// # Arguments
     /// path - path to A2L file on disk
-    /// print_warnings - print warnings to log
-    /// strict - enable strict mode parsing
-    /// check - perform additional consistency checks and print warnings to log
-    /// flatten_typedefs - flatten nested typedefs to basic type instances with mangled names
+    /// options - parser, check and flattening options
+    /// Returns the number of warnings
     #[cfg(feature = "a2l_reader")]
-    pub fn load_a2l<P: AsRef<std::path::Path>>(&mut self, path: &P, print_warnings: bool, strict: bool, check: bool, flatten_typedefs: bool) -> Result<u32, String> {
+    pub fn load_a2l<P: AsRef<std::path::Path>>(&mut self, path: &P, options: &A2lLoadOptions) -> Result<u32, String> {
         //
         // Read A2L file from file into a2lfile::A2lFile data structure
-        let res = a2lfile::load(path, None, strict);
+        let res = a2lfile::load(path, None, options.strict);
+        self.load_a2lfile_result(res, options)
+    }
+
+    /// Load A2L text in memory into this registry, without file system access
+    /// Arguments as in load_a2l
+    #[cfg(feature = "a2l_reader")]
+    pub fn load_a2l_str(&mut self, a2l_text: &str, options: &A2lLoadOptions) -> Result<u32, String> {
+        let res = a2lfile::load_from_string(a2l_text, None, options.strict);
+        self.load_a2lfile_result(res, options)
+    }
+
+    #[cfg(feature = "a2l_reader")]
+    fn load_a2lfile_result(&mut self, res: Result<(a2lfile::A2lFile, Vec<a2lfile::A2lError>), a2lfile::A2lError>, options: &A2lLoadOptions) -> Result<u32, String> {
         match res {
             Ok((a2l_file, log_msgs)) => {
                 let mut warnings: u32 = 0;
 
                 // Print all log messages
-                if print_warnings {
+                if options.print_warnings {
                     log::info!("{} A2L warnings:", log_msgs.len());
                     for log_msg in log_msgs {
                         log::warn!("Warning: {}", log_msg);
@@ -73,7 +144,7 @@ impl Registry {
                 }
 
                 // Perform additional consistency checks on a2lfile::A2lFile
-                if check {
+                if options.check {
                     let log_msgs = a2l_file.check();
                     log::info!("{} A2L checker finding:", log_msgs.len());
                     for log_msg in log_msgs {
@@ -85,9 +156,21 @@ impl Registry {
                 // Load (merge) a2lfile::A2lFile data structure
                 self.load_a2lfile(&a2l_file)?;
 
-                // If requested, flatten nested typedefs to basic type instances with mangled names if required
-                if flatten_typedefs {
+                // If requested, flatten nested typedefs to basic type instances with hierarchical names
+                if options.flatten_typedefs {
                     self.flatten_typedefs();
+                    if options.mangle_names {
+                        self.instance_list.mangle_names();
+                    }
+                }
+
+                // Drop the object types not requested
+                if !options.include_measurements || !options.include_characteristics {
+                    self.instance_list.retain(|instance| match instance.object_type() {
+                        McObjectType::Measurement => options.include_measurements,
+                        McObjectType::Characteristic | McObjectType::Axis => options.include_characteristics,
+                        McObjectType::Unspecified => true,
+                    });
                 }
 
                 Ok(warnings)
@@ -118,10 +201,8 @@ impl Registry {
         // Write to A2L file
         log::info!("Write A2L file {:?}", path.as_ref());
         let a2l_file = std::fs::File::create(path)?;
-        let writer: &mut dyn std::io::Write = &mut std::io::LineWriter::new(a2l_file);
-        let mut a2l_writer = a2l_writer::A2lWriter::new(writer, self);
-
-        a2l_writer.write_a2l(title_comment, project_name, project_description, module_name, project_no)?;
+        let mut writer = std::io::LineWriter::new(a2l_file);
+        self.write_a2l_to_writer(&mut writer, title_comment, project_name, project_description, module_name, project_no)?;
 
         // Check A2L file just written
         #[cfg(not(feature = "a2l_reader"))]
@@ -149,4 +230,42 @@ impl Registry {
 
         Ok(())
     }
+
+    /// Write registry as A2L to a writer
+    pub fn write_a2l_to_writer(
+        &self,
+        writer: &mut dyn std::io::Write,
+        title_comment: &str,
+        project_name: &str,
+        project_description: &str,
+        module_name: &str,
+        project_no: &str,
+    ) -> Result<(), std::io::Error> {
+        let mut a2l_writer = a2l_writer::A2lWriter::new(writer, self);
+        a2l_writer.write_a2l(title_comment, project_name, project_description, module_name, project_no)
+    }
+
+    /// Write several registries as A2L with one MODULE per registry in one PROJECT
+    /// All modules are served by one XCP server, overlapping event ids or calibration segment indices fail with InvalidData (see partition_ids)
+    pub fn write_a2l_modules_to_writer(
+        writer: &mut dyn std::io::Write,
+        title_comment: &str,
+        project_name: &str,
+        project_description: &str,
+        project_no: &str,
+        modules: &[(&str, &Registry)],
+    ) -> Result<(), std::io::Error> {
+        let conflicts = Registry::check_id_partitions(modules);
+        if !conflicts.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, conflicts.join(", ")));
+        }
+        a2l_writer::A2lWriter::write_a2l_modules(writer, title_comment, project_name, project_description, project_no, modules)
+    }
+
+    /// Write registry as A2L text in memory, without file system access
+    pub fn write_a2l_to_string(&self, title_comment: &str, project_name: &str, project_description: &str, module_name: &str, project_no: &str) -> Result<String, std::io::Error> {
+        let mut buf: Vec<u8> = Vec::new();
+        self.write_a2l_to_writer(&mut buf, title_comment, project_name, project_description, module_name, project_no)?;
+        String::from_utf8(buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
 }
@@ -0,0 +1,63 @@
+//-----------------------------------------------------------------------------
+// Module crc
+// CRC32 of calibration pages and patch records, shared by the server and the client
+// CRC16 and CRC16 CCITT of the XCP BUILD_CHECKSUM checksum types
+
+/// CRC16 (polynomial 0x8005 reflected, initial value 0, XCP_CRC_16)
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for b in data {
+        crc ^= u16::from(*b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// CRC16 CCITT (polynomial 0x1021, initial value 0xFFFF, XCP_CRC_16_CITT)
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for b in data {
+        crc ^= u16::from(*b) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320, as zlib and PNG)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for b in data {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod crc_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_crc16() {
+        // Check values of CRC-16/ARC and CRC-16/CCITT-FALSE
+        assert_eq!(crc16(b"123456789"), 0xBB3D);
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29B1);
+        assert_eq!(crc16_ccitt(b""), 0xFFFF);
+    }
+}
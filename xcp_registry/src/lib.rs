@@ -20,14 +20,23 @@ use thiserror::Error;
 // Registry
 mod mc_registry;
 pub use mc_registry::McApplication;
+pub use mc_registry::McIdMapping;
 pub use mc_registry::Registry;
 
 // A2L reader and writer
 mod a2l;
+pub use a2l::A2lLoadOptions;
+pub use a2l::a2l_preserve::merge_a2l_str;
+pub use a2l::a2l_update::update_a2l_addresses_str;
+
+// JavaScript bindings for browser based A2L tooling
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 // McEvent
 mod mc_event;
 pub use mc_event::McEvent;
+pub use mc_event::McEventConsistency;
 pub use mc_event::McEventList;
 pub use mc_event::McEventListIterator;
 
@@ -36,6 +45,35 @@ mod mc_calseg;
 pub use mc_calseg::McCalibrationSegment;
 pub use mc_calseg::McCalibrationSegmentList;
 pub use mc_calseg::McCalibrationSegmentListIterator;
+pub use mc_calseg::McPageAccess;
+pub use mc_calseg::McPageProperties;
+
+// McCalPatch
+mod mc_cal_patch;
+pub use mc_cal_patch::McCalPatch;
+pub use mc_cal_patch::McCalPatchRecord;
+
+// CRC
+mod crc;
+pub use crc::crc16;
+pub use crc::crc16_ccitt;
+pub use crc::crc32;
+
+// Registry composition
+mod mc_compose;
+pub use mc_compose::McIdPartition;
+
+// Bandwidth estimation
+mod mc_bandwidth;
+pub use mc_bandwidth::McBandwidthEstimate;
+pub use mc_bandwidth::McDaqTransport;
+pub use mc_bandwidth::McEventBandwidth;
+
+// McMeasurementConfig
+mod mc_measurement_config;
+pub use mc_measurement_config::MC_AUTO_START_LABEL;
+pub use mc_measurement_config::McMeasurementConfig;
+pub use mc_measurement_config::McMeasurementConfigList;
 
 // McInstance
 mod mc_instance;
@@ -52,6 +90,7 @@ pub use mc_typedef::McTypeDefListIterator;
 
 // McObjectType, McSupportData
 mod mc_support;
+pub use mc_support::McConversion;
 pub use mc_support::McObjectQualifier;
 pub use mc_support::McObjectType;
 pub use mc_support::McSupportData;
@@ -78,6 +117,11 @@ pub use mc_register_type::McEnumType;
 pub use mc_register_type::McRegisterContext;
 pub use mc_register_type::McRegisterTarget;
 pub use mc_register_type::McRegisterType;
+pub use mc_register_type::MeasureSafe;
+
+// McFieldDesc (manual registration of foreign types)
+mod mc_field_desc;
+pub use mc_field_desc::McFieldDesc;
 
 // Re-export the derive macros (serde-style: one import brings the trait and the derive)
 pub use xcp_register_type_derive::McRegisterEnum;
@@ -112,6 +156,9 @@ pub enum RegistryError {
     #[error("registry error: index overflow")]
     IndexOverflow,
 
+    #[error("registry error: `{0}` exceeds the size of its type")]
+    OutOfBounds(String),
+
     #[error("unknown error")]
     Unknown,
 }
@@ -172,14 +219,12 @@ pub fn init() {
 /// # Panics
 /// If the registry is not initialized with registry::init
 /// # Returns
-/// None if the registry is closed
+/// None if the registry is closed, unless a registry is temporarily opened with register_into
 pub fn get_lock() -> parking_lot::lock_api::MutexGuard<'static, parking_lot::RawMutex, Option<Registry>> {
-    // Check if registry is closed, it should be None then
+    // Check if registry is closed, it should be None then (or hold the registry of register_into)
     #[cfg(not(test))]
     if CLOSED_REGISTRY.lock().is_some() {
-        let l = REGISTRY.lock();
-        assert!(l.is_none());
-        return l;
+        return REGISTRY.lock();
     }
 
     let l = REGISTRY.lock();
@@ -233,6 +278,24 @@ pub fn close() {
     // }
 }
 
+/// Run registration code against another registry than the singleton
+/// Registration functions (McRegisterType::mc_register, the derived register code) always use the singleton,
+/// reg is moved into the singleton slot while f runs, this is used to populate additional registries (e.g. of plugins) after the singleton has been closed
+/// Returns false and does not call f, if the singleton is still open
+pub fn register_into<F: FnOnce()>(reg: &mut Registry, f: F) -> bool {
+    // Serializes concurrent register_into calls, the singleton slot is empty outside of it
+    static SCOPE: Mutex<()> = Mutex::new(());
+    let _scope = SCOPE.lock();
+    if !is_closed() {
+        log::warn!("register_into: registry singleton is still open");
+        return false;
+    }
+    *REGISTRY.lock() = Some(std::mem::take(reg));
+    f();
+    *reg = REGISTRY.lock().take().unwrap_or_default();
+    true
+}
+
 // Expand a typedef-typed slot (a single struct, or an array/matrix of structs) into flattened
 // leaf instances. A scalar struct is expanded in place; an array of structs is unrolled element
 // by element, each element getting a dotted index suffix (`name._i`, or `name._iy_ix` for a 2D
@@ -310,8 +373,13 @@ fn collect_flattened_instances(
     }
 }
 
+// Build typedef (name,index) hashmap
+fn create_typedef_index(reg: &Registry) -> HashMap<&'static str, usize> {
+    reg.typedef_list.into_iter().enumerate().map(|(index, typedef)| (typedef.get_name(), index)).collect()
+}
+
 // Collect all instance leafs and create new instances with mangled names
-fn create_flattened_instance_list(reg: &mut Registry, typedef_index: &HashMap<&'static str, usize>) -> McInstanceList {
+fn create_flattened_instance_list(reg: &Registry, typedef_index: &HashMap<&'static str, usize>) -> McInstanceList {
     let mut flat_instance_list = McInstanceList::new();
     for instance in &reg.instance_list {
         let name: String = instance.get_name().to_string();
@@ -342,13 +410,7 @@ fn create_flattened_instance_list(reg: &mut Registry, typedef_index: &HashMap<&'
 pub fn flatten_registry(reg: &mut Registry) {
     log::info!("Flattening typedef structure in registry into mangled instance names !");
 
-    // Build typedef (name,index) hashmap
-    let typedef_index: &HashMap<&str, usize> = &reg
-        .typedef_list
-        .into_iter()
-        .enumerate()
-        .map(|(index, typedef)| (typedef.get_name(), index))
-        .collect::<std::collections::HashMap<_, _>>();
+    let typedef_index = &create_typedef_index(reg);
     // log::info!("Registry typedef index:");
     // log::Info!("{:#?}", typedef_index);
 
@@ -359,6 +421,89 @@ pub fn flatten_registry(reg: &mut Registry) {
     // }
 }
 
+// Basic type leaf instances of all instances, the registry is not modified
+fn get_flattened_instance_list(reg: &Registry) -> McInstanceList {
+    create_flattened_instance_list(reg, &create_typedef_index(reg))
+}
+
+// Copy of an instance with a new name
+fn copy_instance(instance: &McInstance, name: &str) -> McInstance {
+    let mut copy = McInstance::new(name.to_string(), instance.dim_type.clone(), instance.mc_support_data.clone(), instance.address);
+    copy.source = instance.source;
+    copy.symbol_link = instance.symbol_link;
+    copy
+}
+
+// Element of a basic type array or matrix instance, index suffix is "_ix" for arrays or "_iy_ix" for matrices
+fn get_array_element(array: &McInstance, name: &str, suffix: &str) -> Option<McInstance> {
+    if !array.get_dim_type().is_basic_type() {
+        return None;
+    }
+    let [x_dim, y_dim] = array.get_dim_type().get_dim();
+    let indices = suffix.strip_prefix('_')?.split('_').map(|s| s.parse::<u16>().ok()).collect::<Option<Vec<u16>>>()?;
+    let (iy, ix) = match indices[..] {
+        [ix] if y_dim <= 1 => (0, ix),
+        [iy, ix] if y_dim > 1 => (iy, ix),
+        _ => return None,
+    };
+    if ix >= x_dim || iy >= y_dim {
+        return None;
+    }
+    let element_index = iy as usize * x_dim as usize + ix as usize;
+    let mut address = array.address;
+    address.add_addr_offset(i32::try_from(element_index * array.value_size()).ok()?);
+    Some(McInstance::new(
+        name.to_string(),
+        McDimType::new(*array.value_type(), 1, 1),
+        array.mc_support_data.clone(),
+        address,
+    ))
+}
+
+// Resolve a typedef member or array element of an instance by its hierarchical name, without flattening the registry
+// Names are the names flatten_registry would create ("instance.field", "instance._i.field"),
+// elements of basic type arrays are addressed with the same index suffix ("instance.array._i", "instance.map._iy_ix")
+fn resolve_instance_member(reg: &Registry, name: &str, object_type: McObjectType, event_id: Option<u16>) -> Option<McInstance> {
+    // Basic type instance with exactly this name
+    if let Some(instance) = reg.instance_list.get_instance(name, object_type, event_id) {
+        return if instance.get_dim_type().is_basic_type() {
+            Some(copy_instance(instance, name))
+        } else {
+            None
+        };
+    }
+
+    // Find the root instance, instance names may contain '.', longest name first
+    let typedef_index = create_typedef_index(reg);
+    let root = name
+        .rmatch_indices('.')
+        .find_map(|(pos, _)| reg.instance_list.get_instance(&name[..pos], object_type, event_id))?;
+
+    // Leafs of the root instance
+    let mut leafs = McInstanceList::new();
+    match root.get_typedef_name().and_then(|typedef_name| typedef_index.get(typedef_name)) {
+        Some(i) => expand_typedef_slot(
+            reg,
+            &mut leafs,
+            &typedef_index,
+            root.get_name(),
+            root.get_address(),
+            0,
+            root.get_dim_type(),
+            reg.typedef_list.get(*i)?,
+        ),
+        None => leafs.push(copy_instance(root, root.get_name())),
+    }
+
+    // Leaf with this name or element of a basic type array leaf
+    if let Some(leaf) = leafs.get_instance(name, McObjectType::Unspecified, None) {
+        return Some(copy_instance(leaf, name));
+    }
+    name.rmatch_indices('.')
+        .find_map(|(pos, _)| leafs.get_instance(&name[..pos], McObjectType::Unspecified, None).map(|leaf| (leaf, pos)))
+        .and_then(|(leaf, pos)| get_array_element(leaf, name, &name[pos + 1..]))
+}
+
 //-------------------------------------------------------------------------------------------------
 //-------------------------------------------------------------------------------------------------
 // Test module
@@ -371,6 +516,18 @@ pub mod registry_test {
         *REGISTRY.lock() = Some(Registry::new());
         *CLOSED_REGISTRY.lock() = None;
     }
+
+    // A2L of a registry, written with the fixed header of the unit tests
+    #[cfg(test)]
+    pub(crate) fn write_test_a2l(reg: &Registry) -> String {
+        reg.write_a2l_to_string("test", "project", "", "module", "P1").unwrap()
+    }
+
+    // A2L with the XCP IF_DATA definitions included, to be loaded by the A2L reader
+    #[cfg(all(test, feature = "a2l_reader"))]
+    pub(crate) fn include_test_aml(a2l: &str) -> String {
+        a2l.replace(r#"/include "XCP_104.aml""#, include_str!("../../XCP_104.aml"))
+    }
 }
 
 #[cfg(test)]
@@ -446,4 +603,244 @@ mod flatten_tests {
         // 7 leaves from outer (3 * 2 + 1) plus 4 from arr (2 * 2)
         assert_eq!(reg.instance_list.len(), 11);
     }
+
+    #[test]
+    fn resolve_without_flattening() {
+        let mut reg = Registry::new();
+        let cal = McSupportData::new(McObjectType::Characteristic);
+
+        // typedef Inner { a: u8 @0, curve: [u16; 4] @2 }  size 10
+        reg.add_typedef("Inner", 10).unwrap();
+        reg.add_typedef_field("Inner", "a", McDimType::new(McValueType::Ubyte, 1, 1), cal.clone(), 0).unwrap();
+        reg.add_typedef_field("Inner", "curve", McDimType::new(McValueType::Uword, 4, 1), cal.clone(), 2).unwrap();
+
+        // instance arr: [Inner; 2] @ calseg offset 0x40, map: [[u8; 3]; 2] @ calseg offset 0x80
+        reg.instance_list
+            .add_instance(
+                "arr",
+                McDimType::new(McValueType::new_typedef("Inner"), 2, 1),
+                cal.clone(),
+                McAddress::new_calseg_rel("seg", 0x40),
+            )
+            .unwrap();
+        reg.instance_list
+            .add_instance("map", McDimType::new(McValueType::Ubyte, 3, 2), cal, McAddress::new_calseg_rel("seg", 0x80))
+            .unwrap();
+
+        let offset = |name: &str| reg.resolve_instance(name, McObjectType::Characteristic, None).map(|i| i.get_address().get_addr_offset());
+        assert_eq!(offset("arr._1.a"), Some(0x4A));
+        assert_eq!(offset("arr._1.curve"), Some(0x4C));
+        assert_eq!(offset("arr._1.curve._3"), Some(0x52));
+        assert_eq!(offset("map._1_2"), Some(0x85));
+        assert_eq!(offset("map"), Some(0x80));
+        assert_eq!(offset("arr._2.a"), None); // Out of bounds
+        assert_eq!(offset("arr._1"), None); // Not a basic type
+        assert_eq!(offset("arr._1.b"), None);
+        assert_eq!(reg.resolve_instance("arr._1.curve._3", McObjectType::Characteristic, None).unwrap().size(), 2);
+
+        // Same addresses as flattening
+        let a = reg.resolve_instance("arr._0.curve", McObjectType::Characteristic, None).unwrap();
+        flatten_registry(&mut reg);
+        assert_eq!(offset_of(&reg, "arr._0.curve"), a.get_address().get_addr_offset());
+    }
+
+    #[test]
+    fn mangle_and_retain() {
+        let mut reg = Registry::new();
+        let cal = McSupportData::new(McObjectType::Characteristic);
+        reg.add_typedef("Inner", 4).unwrap();
+        reg.add_typedef_field("Inner", "a", McDimType::new(McValueType::Ubyte, 1, 1), cal.clone(), 0).unwrap();
+        reg.instance_list
+            .add_instance("inner", McDimType::new(McValueType::new_typedef("Inner"), 1, 1), cal, McAddress::new_calseg_rel("seg", 0))
+            .unwrap();
+        reg.instance_list
+            .add_instance(
+                "m",
+                McDimType::new(McValueType::Ulong, 1, 1),
+                McSupportData::new(McObjectType::Measurement),
+                McAddress::new_event_abs(0, 0),
+            )
+            .unwrap();
+
+        flatten_registry(&mut reg);
+        reg.instance_list.mangle_names();
+        assert_eq!(offset_of(&reg, "inner_a"), 0);
+
+        reg.instance_list.retain(|i| i.object_type() != McObjectType::Measurement);
+        assert_eq!(reg.instance_list.len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+
+    fn test_registry(addr_offset: i32, unit: &str) -> Registry {
+        let mut reg = Registry::new();
+        reg.cal_seg_list.add_cal_seg("seg", None, 16).unwrap();
+        let cal = McSupportData::new(McObjectType::Characteristic).set_unit(unit.to_string());
+        reg.instance_list
+            .add_instance("a", McDimType::new(McValueType::Ubyte, 1, 1), cal.clone(), McAddress::new_calseg_rel("seg", 0))
+            .unwrap();
+        reg.instance_list
+            .add_instance("b", McDimType::new(McValueType::Uword, 1, 1), cal, McAddress::new_calseg_rel("seg", addr_offset))
+            .unwrap();
+        reg
+    }
+
+    #[test]
+    fn diff_registries() {
+        let reg1 = test_registry(2, "V");
+        assert!(reg1.diff(&test_registry(2, "V")).is_empty());
+
+        // Changed address and unit
+        let diffs = reg1.diff(&test_registry(4, "mV"));
+        assert_eq!(diffs.len(), 3, "{:?}", diffs);
+        assert!(diffs.contains(&"~ b: address seg+2 -> seg+4".to_string()));
+        assert!(diffs.iter().any(|d| d.starts_with("~ a: unit")));
+
+        // Added and removed instances
+        let mut reg2 = test_registry(2, "V");
+        reg2.instance_list
+            .add_instance(
+                "c",
+                McDimType::new(McValueType::Ulong, 1, 1),
+                McSupportData::new(McObjectType::Characteristic),
+                McAddress::new_calseg_rel("seg", 4),
+            )
+            .unwrap();
+        assert_eq!(reg1.diff(&reg2), vec!["+ Characteristic c".to_string()]);
+        assert_eq!(reg2.diff(&reg1), vec!["- Characteristic c".to_string()]);
+    }
+
+    #[test]
+    fn id_mapping_by_name() {
+        let mut reg = test_registry(2, "V");
+        reg.cal_seg_list.add_cal_seg("seg2", None, 8).unwrap();
+        reg.event_list.add_event(McEvent::new("task", 0, 0, 0)).unwrap();
+        reg.event_list.add_event(McEvent::new("task", 1, 1, 0)).unwrap();
+        reg.event_list.add_event(McEvent::new("local", 0, 2, 0)).unwrap();
+
+        // Target order differs, event local is unknown to the target
+        let mut target = Registry::new();
+        target.cal_seg_list.add_cal_seg("seg2", None, 8).unwrap();
+        target.cal_seg_list.add_cal_seg("seg", None, 32).unwrap();
+        target.event_list.add_event(McEvent::new("task", 1, 0, 0)).unwrap();
+        target.event_list.add_event(McEvent::new("task", 0, 2, 0)).unwrap();
+
+        let mapping = reg.get_id_mapping(&target);
+        assert_eq!(mapping.cal_segs, HashMap::from([(0, 1), (1, 0)]));
+        // Event local keeps id 2, so task keeps id 0 and task_1 can not get id 0
+        assert!(mapping.events.is_empty(), "{:?}", mapping.events);
+        assert_eq!(mapping.conflicts.len(), 4, "{:?}", mapping.conflicts);
+        assert!(mapping.conflicts.iter().any(|c| c.starts_with("segment seg size 16")));
+        assert!(mapping.conflicts.iter().any(|c| c.starts_with("event local not found")));
+    }
+
+    #[test]
+    fn update_mappings() {
+        let mut reg = test_registry(2, "V");
+        reg.cal_seg_list.add_cal_seg("seg2", None, 8).unwrap();
+        reg.event_list.add_event(McEvent::new("e0", 0, 0, 0)).unwrap();
+        reg.event_list.add_event(McEvent::new("e1", 0, 1, 0)).unwrap();
+        let mea = McSupportData::new(McObjectType::Measurement);
+        let ubyte = McDimType::new(McValueType::Ubyte, 1, 1);
+        reg.instance_list
+            .add_instance("dyn", ubyte.clone(), mea.clone(), McAddress::new_event_dyn(0, 0, 4))
+            .unwrap();
+        let (_, seg_addr) = McAddress::get_calseg_ext_addr_base(1);
+        reg.instance_list
+            .add_instance("raw_cal", ubyte.clone(), mea.clone(), McAddress::new_a2l(seg_addr + 3, 0))
+            .unwrap();
+        let dyn_addr = (1 << McAddress::XCP_ADDR_EXT_DYN_OFFSET_BITS) | 0x10008;
+        reg.instance_list
+            .add_instance(
+                "raw_dyn",
+                ubyte.clone(),
+                mea.clone(),
+                McAddress::new_a2l_with_event(1, dyn_addr, McAddress::XCP_ADDR_EXT_DYN),
+            )
+            .unwrap();
+        reg.instance_list.add_instance("abs", ubyte, mea, McAddress::new_a2l(0x1000, 1)).unwrap();
+
+        // Swap events and segments, dry run reports all changes and does not modify the registry
+        let swap = HashMap::from([(0, 1), (1, 0)]);
+        let json = reg.to_json_string().unwrap();
+        let report = reg.update_event_mapping(&swap, true);
+        assert_eq!(report.len(), 5, "{:?}", report);
+        assert!(report.contains(&"~ dyn: event 0 -> 1".to_string()));
+        assert!(report.contains(&"~ raw_dyn: address 2:0x00410008 -> 2:0x00010008".to_string()));
+        let report = reg.update_cal_seg_mapping(&swap, true);
+        assert_eq!(report.len(), 5, "{:?}", report);
+        assert!(report.contains(&"~ raw_cal: address 0:0x80010003 -> 0:0x80000003".to_string()));
+        assert_eq!(reg.to_json_string().unwrap(), json);
+
+        reg.update_event_mapping(&swap, false);
+        reg.update_cal_seg_mapping(&swap, false);
+        assert_eq!(reg.event_list.find_event("e0", 0).unwrap().get_id(), 1);
+        assert_eq!(reg.cal_seg_list.get_cal_seg_index("seg2"), Some(0));
+        assert_eq!(reg.cal_seg_list.get(1).unwrap().addr, McAddress::get_calseg_ext_addr_base(0).1);
+        let address = |name: &str| reg.instance_list.into_iter().find(|i| i.get_name() == name).unwrap().address;
+        assert_eq!(address("dyn").get_event_id(), Some(1));
+        assert_eq!(address("raw_cal").get_raw_a2l_addr(), (0, 0x80000003));
+        assert_eq!(address("raw_dyn").get_event_id(), Some(0));
+        assert_eq!(address("raw_dyn").get_raw_a2l_addr(), (2, 0x00010008));
+        assert_eq!(address("abs").get_raw_a2l_addr(), (1, 0x1000));
+    }
+
+    #[test]
+    fn json_string_roundtrip() {
+        let reg1 = test_registry(2, "V");
+        let json = reg1.to_json_string().unwrap();
+        let mut reg2 = Registry::new();
+        reg2.load_json_str(&json).unwrap();
+        assert!(reg1.diff(&reg2).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod layout_tests {
+    use super::*;
+
+    #[test]
+    fn layout_report() {
+        let mut reg = Registry::new();
+        let cal = McSupportData::new(McObjectType::Characteristic);
+
+        // Inner: u8, hole, u16, alignment 2
+        reg.add_typedef("Inner", 4).unwrap();
+        reg.add_typedef_field("Inner", "a", McDimType::new(McValueType::Ubyte, 1, 1), cal.clone(), 0).unwrap();
+        reg.add_typedef_field("Inner", "b", McDimType::new(McValueType::Uword, 1, 1), cal.clone(), 2).unwrap();
+
+        // Padded: u8, hole, u32, u8, padding, 8 bytes with fields ordered by alignment
+        reg.add_typedef("Padded", 12).unwrap();
+        reg.add_typedef_field("Padded", "a", McDimType::new(McValueType::Ubyte, 1, 1), cal.clone(), 0).unwrap();
+        reg.add_typedef_field("Padded", "b", McDimType::new(McValueType::Ulong, 1, 1), cal.clone(), 4).unwrap();
+        reg.add_typedef_field("Padded", "c", McDimType::new(McValueType::Ubyte, 1, 1), cal.clone(), 8).unwrap();
+
+        // Outer: Inner array and u32 without holes
+        reg.add_typedef("Outer", 12).unwrap();
+        reg.add_typedef_field("Outer", "inner", McDimType::new(McValueType::new_typedef("Inner"), 2, 1), cal.clone(), 0)
+            .unwrap();
+        reg.add_typedef_field("Outer", "x", McDimType::new(McValueType::Ulong, 1, 1), cal.clone(), 8).unwrap();
+
+        reg.cal_seg_list.add_cal_seg("seg", None, 16).unwrap();
+        reg.instance_list
+            .add_instance("page", McDimType::new(McValueType::new_typedef("Padded"), 1, 1), cal, McAddress::new_calseg_rel("seg", 0))
+            .unwrap();
+
+        let report = reg.layout_report();
+        assert_eq!(
+            report,
+            vec![
+                "typedef Inner: hole of 1 bytes at +1 between a and b".to_string(),
+                "typedef Padded: hole of 3 bytes at +1 between a and b".to_string(),
+                "typedef Padded: 3 padding bytes at +9, size 12".to_string(),
+                "typedef Padded: size 12, 8 with fields ordered by alignment, 4 bytes alignment waste".to_string(),
+                "segment seg: 3 unused bytes at +1".to_string(),
+                "segment seg: 7 unused bytes at +9".to_string(),
+                "segment seg: size 16, 10 bytes unused".to_string(),
+            ]
+        );
+    }
 }
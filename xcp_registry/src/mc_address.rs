@@ -250,6 +250,20 @@ impl McAddress {
         )
     }
 
+    // Set the event id of an address with event association
+    // For internal use only, when event ids are remapped
+    pub fn set_event_id(&mut self, event_id: u16) {
+        assert!(self.event_id.is_some(), "Address has no event association");
+        self.event_id = Some(event_id);
+    }
+
+    // Set the calibration segment of a segment relative address
+    // For internal use only, when calibration segments are renamed
+    pub fn set_calseg_name<T: Into<McIdentifier>>(&mut self, calseg_name: T) {
+        assert!(self.calseg_name.is_some(), "Address is not segment relative");
+        self.calseg_name = Some(calseg_name.into());
+    }
+
     /// Get relative address offset to event or calibration segment
     /// # Panics
     /// If the address is not segment or event relative
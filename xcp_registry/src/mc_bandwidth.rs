@@ -0,0 +1,254 @@
+//-----------------------------------------------------------------------------
+// Module mc_bandwidth
+// Estimation of the DAQ bandwidth of a measurement configuration, before the measurement is started
+// The signals of each event are packed into ODTs of the maximum DTO size, each ODT has a DAQ header and a transport layer message header,
+// the first ODT of each event also carries the DAQ timestamp
+// The estimate assumes ODT entries can be split at byte granularity, it is a lower bound for the real ODT allocation of a client
+// IP and UDP or TCP headers of the transport layer are not included
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::McInstanceList;
+use crate::McObjectType;
+use crate::McValueType;
+use crate::Registry;
+
+/// DAQ transport parameters and bandwidth budget for the bandwidth estimation
+#[derive(Debug, Clone, Copy)]
+pub struct McDaqTransport {
+    pub max_dto_size: u16,       // Maximum size of a DTO (XCP CONNECT response), including DAQ header and timestamp
+    pub daq_header_size: u8,     // Size of the ODT and DAQ list identification of each ODT
+    pub timestamp_size: u8,      // Size of the DAQ timestamp in the first ODT of an event
+    pub message_header_size: u8, // Size of the transport layer message header of each ODT (XCP on Ethernet: length and counter)
+    pub budget_bytes_per_s: u64, // Available transport bandwidth, 0 = unlimited
+}
+
+impl Default for McDaqTransport {
+    // xcplib defaults for XCP on UDP
+    fn default() -> Self {
+        McDaqTransport {
+            max_dto_size: 1468,
+            daq_header_size: 4,
+            timestamp_size: 4,
+            message_header_size: 4,
+            budget_bytes_per_s: 0,
+        }
+    }
+}
+
+/// Estimated bandwidth of one event
+#[derive(Debug, Clone, PartialEq)]
+pub struct McEventBandwidth {
+    pub event_name: String,       // Unique name of the event, "event <id>" if not in the registry
+    pub event_id: u16,            // Event id
+    pub cycle_time_ns: u32,       // Cycle time used for the estimate, 0 = sporadic event
+    pub signal_count: usize,      // Number of basic type signals (typedef instances count with all members)
+    pub payload_size: usize,      // Signal bytes per event cycle
+    pub odt_count: usize,         // Number of ODTs per event cycle
+    pub bytes_per_cycle: usize,   // Transferred bytes per event cycle, including headers and timestamp
+    pub bytes_per_s: Option<u64>, // Transferred bytes per second, None for sporadic events
+}
+
+/// Result of Registry::estimate_bandwidth
+#[derive(Debug, Clone, Default)]
+pub struct McBandwidthEstimate {
+    pub events: Vec<McEventBandwidth>, // Events sorted by id
+    pub total_bytes_per_s: u64,        // Sum of all cyclic events
+    pub budget_bytes_per_s: u64,       // Budget of the transport parameters, 0 = unlimited
+    pub unresolved: Vec<String>,       // Signals not found or not associated to an event
+}
+
+impl McBandwidthEstimate {
+    /// Check the total bandwidth of the cyclic events against the budget
+    /// Always true if the budget is unlimited
+    pub fn is_within_budget(&self) -> bool {
+        self.budget_bytes_per_s == 0 || self.total_bytes_per_s <= self.budget_bytes_per_s
+    }
+
+    /// Get the used share of the budget in percent, None if the budget is unlimited
+    #[allow(clippy::cast_precision_loss)]
+    pub fn get_budget_usage(&self) -> Option<f64> {
+        (self.budget_bytes_per_s > 0).then(|| self.total_bytes_per_s as f64 * 100.0 / self.budget_bytes_per_s as f64)
+    }
+}
+
+impl std::fmt::Display for McBandwidthEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = String::new();
+        for e in &self.events {
+            let rate = match e.bytes_per_s {
+                Some(bytes_per_s) => format!("{} bytes/s", bytes_per_s),
+                None => "sporadic".to_string(),
+            };
+            let _ = writeln!(
+                s,
+                "{:<32} id={:<4} cycle={:>10}ns signals={:<4} payload={:<6} odts={:<3} bytes/cycle={:<6} {}",
+                e.event_name, e.event_id, e.cycle_time_ns, e.signal_count, e.payload_size, e.odt_count, e.bytes_per_cycle, rate
+            );
+        }
+        let _ = write!(s, "Total {} bytes/s", self.total_bytes_per_s);
+        if let Some(usage) = self.get_budget_usage() {
+            let _ = write!(s, " of budget {} bytes/s ({:.1}%)", self.budget_bytes_per_s, usage);
+            if !self.is_within_budget() {
+                s.push_str(" EXCEEDS BUDGET");
+            }
+        }
+        for name in &self.unresolved {
+            let _ = write!(s, "\nUnresolved signal {}", name);
+        }
+        write!(f, "{}", s)
+    }
+}
+
+impl Registry {
+    /// Estimate the DAQ bandwidth of a measurement configuration
+    /// signals are the names of measurement objects, typedef members and array elements as accepted by resolve_instance,
+    /// typedef instances are measured with all their members
+    /// The cycle time of an event is taken from cycle_times (event id -> cycle time in ns) or the target cycle time of the event
+    /// Sporadic events without cycle time are listed, but not included in the total
+    pub fn estimate_bandwidth(&self, signals: &[&str], cycle_times: &HashMap<u16, u32>, transport: &McDaqTransport) -> McBandwidthEstimate {
+        let mut estimate = McBandwidthEstimate {
+            budget_bytes_per_s: transport.budget_bytes_per_s,
+            ..Default::default()
+        };
+
+        // Signal count and payload per event id
+        let mut events: HashMap<u16, (usize, usize)> = HashMap::new();
+        let mut leafs: Option<McInstanceList> = None;
+        for name in signals {
+            let resolved: Vec<(Option<u16>, usize)> = match self.resolve_instance(name, McObjectType::Unspecified, None) {
+                Some(instance) => vec![(
+                    instance.get_address().get_event_id(),
+                    signal_size(instance.get_dim_type().value_type, instance.get_dim_type().get_dim()),
+                )],
+                None => {
+                    let prefix = format!("{}.", name);
+                    leafs
+                        .get_or_insert_with(|| self.get_flattened_instance_list())
+                        .into_iter()
+                        .filter(|i| i.get_name().starts_with(&prefix))
+                        .map(|i| (i.get_address().get_event_id(), signal_size(i.get_dim_type().value_type, i.get_dim_type().get_dim())))
+                        .collect()
+                }
+            };
+            if resolved.is_empty() || resolved.iter().any(|(event_id, size)| event_id.is_none() || *size == 0) {
+                estimate.unresolved.push((*name).to_string());
+                continue;
+            }
+            for (event_id, size) in resolved {
+                let entry = events.entry(event_id.unwrap_or_default()).or_default();
+                entry.0 += 1;
+                entry.1 += size;
+            }
+        }
+
+        // ODTs and bandwidth per event
+        let first_odt_size = usize::from(transport.max_dto_size).saturating_sub(usize::from(transport.daq_header_size) + usize::from(transport.timestamp_size));
+        let odt_size = usize::from(transport.max_dto_size).saturating_sub(usize::from(transport.daq_header_size)).max(1);
+        let mut ids: Vec<u16> = events.keys().copied().collect();
+        ids.sort_unstable();
+        for event_id in ids {
+            let (signal_count, payload_size) = events[&event_id];
+            let odt_count = 1 + payload_size.saturating_sub(first_odt_size).div_ceil(odt_size);
+            let bytes_per_cycle =
+                payload_size + usize::from(transport.timestamp_size) + odt_count * (usize::from(transport.daq_header_size) + usize::from(transport.message_header_size));
+            let event = self.event_list.find_event_id(event_id);
+            let cycle_time_ns = cycle_times.get(&event_id).copied().or(event.map(|e| e.target_cycle_time_ns)).unwrap_or(0);
+            let bytes_per_s = (cycle_time_ns > 0).then(|| (bytes_per_cycle as u64 * 1_000_000_000).div_ceil(u64::from(cycle_time_ns)));
+            estimate.total_bytes_per_s += bytes_per_s.unwrap_or(0);
+            estimate.events.push(McEventBandwidth {
+                event_name: event.map_or_else(|| format!("event {}", event_id), |e| e.get_unique_name(self).into_owned()),
+                event_id,
+                cycle_time_ns,
+                signal_count,
+                payload_size,
+                odt_count,
+                bytes_per_cycle,
+                bytes_per_s,
+            });
+        }
+
+        estimate
+    }
+}
+
+// Size of a basic type signal, 0 for blobs, typedefs and unknown types
+fn signal_size(value_type: McValueType, [x_dim, y_dim]: [u16; 2]) -> usize {
+    match value_type {
+        McValueType::Blob(_) | McValueType::TypeDef(_) | McValueType::Unknown => 0,
+        value_type => value_type.get_size() * usize::from(x_dim) * usize::from(y_dim),
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod mc_bandwidth_tests {
+
+    use super::*;
+    use crate::{McAddress, McDimType, McEvent, McSupportData};
+
+    fn test_registry() -> Registry {
+        let mut reg = Registry::new();
+        reg.event_list.add_event(McEvent::new("fast", 0, 0, 1_000_000)).unwrap();
+        reg.event_list.add_event(McEvent::new("slow", 0, 1, 100_000_000)).unwrap();
+        reg.event_list.add_event(McEvent::new("sporadic", 0, 2, 0)).unwrap();
+        let mea = McSupportData::new(McObjectType::Measurement);
+        reg.add_typedef("Point", 16).unwrap();
+        reg.add_typedef_field("Point", "x", McDimType::new(McValueType::Float64Ieee, 1, 1), mea.clone(), 0).unwrap();
+        reg.add_typedef_field("Point", "y", McDimType::new(McValueType::Float64Ieee, 1, 1), mea.clone(), 8).unwrap();
+        reg.instance_list
+            .add_instance("counter", McDimType::new(McValueType::Ulong, 1, 1), mea.clone(), McAddress::new_event_dyn(0, 0, 0))
+            .unwrap();
+        reg.instance_list
+            .add_instance(
+                "point",
+                McDimType::new(McValueType::new_typedef("Point"), 1, 1),
+                mea.clone(),
+                McAddress::new_event_dyn(0, 0, 8),
+            )
+            .unwrap();
+        reg.instance_list
+            .add_instance("buffer", McDimType::new(McValueType::Ubyte, 3000, 1), mea.clone(), McAddress::new_event_dyn(0, 1, 0))
+            .unwrap();
+        reg.instance_list
+            .add_instance("state", McDimType::new(McValueType::Ubyte, 1, 1), mea, McAddress::new_event_dyn(0, 2, 0))
+            .unwrap();
+        reg
+    }
+
+    #[test]
+    fn test_estimate_bandwidth() {
+        let reg = test_registry();
+        let transport = McDaqTransport {
+            budget_bytes_per_s: 100_000,
+            ..Default::default()
+        };
+        let estimate = reg.estimate_bandwidth(&["counter", "point", "buffer", "state", "unknown"], &HashMap::new(), &transport);
+        assert_eq!(estimate.unresolved, vec!["unknown".to_string()]);
+        assert_eq!(estimate.events.len(), 3);
+
+        // fast: 4 + 2*8 payload, timestamp, one ODT with DAQ and message header, 1 ms
+        let fast = &estimate.events[0];
+        assert_eq!((fast.signal_count, fast.payload_size, fast.odt_count, fast.bytes_per_cycle), (3, 20, 1, 32));
+        assert_eq!(fast.bytes_per_s, Some(32_000));
+
+        // slow: 3000 payload in 3 ODTs, 100 ms
+        let slow = &estimate.events[1];
+        assert_eq!((slow.odt_count, slow.bytes_per_cycle), (3, 3000 + 4 + 3 * 8));
+        assert_eq!(slow.bytes_per_s, Some(30_280));
+
+        // sporadic: not included in the total
+        assert_eq!(estimate.events[2].bytes_per_s, None);
+        assert_eq!(estimate.total_bytes_per_s, 62_280);
+        assert!(estimate.is_within_budget());
+
+        // Faster cycle time of event fast exceeds the budget
+        let estimate = reg.estimate_bandwidth(&["counter", "point"], &HashMap::from([(0, 100_000)]), &transport);
+        assert_eq!(estimate.total_bytes_per_s, 320_000);
+        assert!(!estimate.is_within_budget());
+        assert!(estimate.to_string().contains("EXCEEDS BUDGET"));
+    }
+}
@@ -0,0 +1,205 @@
+//-----------------------------------------------------------------------------
+// Module mc_cal_patch
+// Compact binary calibration patch, exchanged between client and server
+// A patch is a list of records (calibration segment index, offset, data) with a CRC32 checksum per record
+// The client creates a patch from a set of changes, the server or the client applies all records of a patch at once
+//
+// Layout, little endian:
+//   header: magic "XCPP" (4), version (u8), reserved (u8), record count (u16)
+//   record: segment index (u16), offset (u32), length (u16), data (length), crc32 of the preceding record bytes (u32)
+
+use crate::crc32;
+
+//-------------------------------------------------------------------------------------------------
+// McCalPatchRecord
+
+/// Changed bytes of a calibration segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct McCalPatchRecord {
+    pub segment: u16, // Calibration segment index
+    pub offset: u32,  // Offset in the calibration segment
+    pub data: Vec<u8>,
+}
+
+impl McCalPatchRecord {
+    // Record length is limited to u16 by McCalPatch::add and from_bytes
+    #[allow(clippy::cast_possible_truncation)]
+    fn end(&self) -> u32 {
+        self.offset + self.data.len() as u32
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// McCalPatch
+
+/// Calibration patch, a list of changes to calibration segments
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct McCalPatch {
+    records: Vec<McCalPatchRecord>,
+}
+
+impl McCalPatch {
+    const MAGIC: &'static [u8; 4] = b"XCPP";
+    const VERSION: u8 = 1;
+
+    pub fn new() -> McCalPatch {
+        McCalPatch { records: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Records sorted by segment and offset
+    pub fn get_records(&self) -> &[McCalPatchRecord] {
+        &self.records
+    }
+
+    /// Add changed bytes of a calibration segment
+    /// Overlapping or adjacent changes of the same segment are merged into one record, the later change wins
+    pub fn add(&mut self, segment: u16, offset: u32, data: &[u8]) -> Result<(), String> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let len = u16::try_from(data.len()).map_err(|_| format!("Calibration patch record of segment {} exceeds {} bytes", segment, u16::MAX))?;
+        let end = offset.checked_add(u32::from(len)).ok_or("Calibration patch offset overflow")?;
+        let mut record = McCalPatchRecord {
+            segment,
+            offset,
+            data: data.to_vec(),
+        };
+
+        // Merge all records of the segment touching the new range
+        let mut i = 0;
+        while i < self.records.len() {
+            let r = &self.records[i];
+            if r.segment == segment && r.offset <= record.end() && offset <= r.end() {
+                let r = self.records.remove(i);
+                let start = r.offset.min(record.offset);
+                let mut merged = vec![0u8; (r.end().max(record.end()) - start) as usize];
+                merged[(r.offset - start) as usize..(r.end() - start) as usize].copy_from_slice(&r.data);
+                merged[(offset - start) as usize..(end - start) as usize].copy_from_slice(data);
+                record = McCalPatchRecord {
+                    segment,
+                    offset: start,
+                    data: merged,
+                };
+            } else {
+                i += 1;
+            }
+        }
+        if record.data.len() > u16::MAX as usize {
+            return Err(format!("Calibration patch record of segment {} exceeds {} bytes", segment, u16::MAX));
+        }
+        let pos = self.records.partition_point(|r| (r.segment, r.offset) < (segment, record.offset));
+        self.records.insert(pos, record);
+        Ok(())
+    }
+
+    /// Serialize to the binary patch format
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + self.records.iter().map(|r| r.data.len() + 12).sum::<usize>());
+        bytes.extend_from_slice(Self::MAGIC);
+        bytes.push(Self::VERSION);
+        bytes.push(0);
+        bytes.extend_from_slice(&(self.records.len() as u16).to_le_bytes());
+        for r in &self.records {
+            let start = bytes.len();
+            bytes.extend_from_slice(&r.segment.to_le_bytes());
+            bytes.extend_from_slice(&r.offset.to_le_bytes());
+            bytes.extend_from_slice(&(r.data.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&r.data);
+            let crc = crc32(&bytes[start..]);
+            bytes.extend_from_slice(&crc.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Parse the binary patch format, fails on checksum errors or truncated data
+    pub fn from_bytes(bytes: &[u8]) -> Result<McCalPatch, String> {
+        let take = |pos: &mut usize, n: usize| -> Result<&[u8], String> {
+            let s = bytes.get(*pos..*pos + n).ok_or("Calibration patch truncated")?;
+            *pos += n;
+            Ok(s)
+        };
+        let mut pos = 0;
+        if take(&mut pos, 4)? != Self::MAGIC {
+            return Err("Not a calibration patch".to_string());
+        }
+        let version = take(&mut pos, 2)?[0];
+        if version != Self::VERSION {
+            return Err(format!("Calibration patch version {} not supported", version));
+        }
+        let count = u16::from_le_bytes(take(&mut pos, 2)?.try_into().unwrap());
+        let mut records = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let start = pos;
+            let segment = u16::from_le_bytes(take(&mut pos, 2)?.try_into().unwrap());
+            let offset = u32::from_le_bytes(take(&mut pos, 4)?.try_into().unwrap());
+            let len = u16::from_le_bytes(take(&mut pos, 2)?.try_into().unwrap());
+            let data = take(&mut pos, len as usize)?.to_vec();
+            let crc = crc32(&bytes[start..pos]);
+            if u32::from_le_bytes(take(&mut pos, 4)?.try_into().unwrap()) != crc {
+                return Err(format!("Calibration patch record {} checksum error", i));
+            }
+            records.push(McCalPatchRecord { segment, offset, data });
+        }
+        if pos != bytes.len() {
+            return Err("Calibration patch has trailing data".to_string());
+        }
+        Ok(McCalPatch { records })
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod mc_cal_patch_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_cal_patch() {
+        let mut patch = McCalPatch::new();
+        patch.add(1, 8, &[1, 2]).unwrap();
+        patch.add(0, 4, &[9]).unwrap();
+        patch.add(1, 10, &[3]).unwrap(); // Adjacent
+        patch.add(1, 7, &[7, 8]).unwrap(); // Overlapping, overrides offset 8
+        patch.add(1, 20, &[5]).unwrap();
+        assert_eq!(
+            patch.get_records(),
+            &[
+                McCalPatchRecord {
+                    segment: 0,
+                    offset: 4,
+                    data: vec![9]
+                },
+                McCalPatchRecord {
+                    segment: 1,
+                    offset: 7,
+                    data: vec![7, 8, 2, 3]
+                },
+                McCalPatchRecord {
+                    segment: 1,
+                    offset: 20,
+                    data: vec![5]
+                },
+            ]
+        );
+
+        let bytes = patch.to_bytes();
+        assert_eq!(bytes.len(), 8 + 3 * 12 + 6);
+        assert_eq!(McCalPatch::from_bytes(&bytes).unwrap(), patch);
+
+        let mut corrupt = bytes.clone();
+        corrupt[20] ^= 1;
+        assert!(McCalPatch::from_bytes(&corrupt).is_err());
+        assert!(McCalPatch::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
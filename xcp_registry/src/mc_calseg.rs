@@ -1,6 +1,6 @@
 // Module mc_calseg
 // Types:
-//  McTypeDef, McTypeDefField
+//  McCalibrationSegment, McCalibrationSegmentList, McPageProperties, McPageAccess
 
 use std::borrow::Cow;
 
@@ -12,6 +12,90 @@ use super::McIdentifier;
 use super::Registry;
 use super::RegistryError;
 
+//-------------------------------------------------------------------------------------------------
+// Calibration pages
+
+/// Access of a calibration page by the ECU or by XCP, A2L PAGE ECU_ACCESS_xxx, XCP_READ_ACCESS_xxx and XCP_WRITE_ACCESS_xxx
+/// For the ECU access, the other side is XCP, for the XCP read and write access, the other side is the ECU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum McPageAccess {
+    NotAllowed,
+    WithoutOtherOnly, // Only if the other side does not access the page
+    WithOtherOnly,    // Only if the other side accesses the page too
+    DontCare,
+}
+
+impl McPageAccess {
+    // Bits of the XCP PAGE_PROPERTIES, without and with the other side
+    fn to_bits(self) -> u8 {
+        match self {
+            McPageAccess::NotAllowed => 0,
+            McPageAccess::WithoutOtherOnly => 1,
+            McPageAccess::WithOtherOnly => 2,
+            McPageAccess::DontCare => 3,
+        }
+    }
+
+    // A2L keyword suffix, other is the name of the other side
+    fn to_a2l(self, other: &str) -> String {
+        match self {
+            McPageAccess::NotAllowed => "NOT_ALLOWED".to_string(),
+            McPageAccess::WithoutOtherOnly => format!("WITHOUT_{}_ONLY", other),
+            McPageAccess::WithOtherOnly => format!("WITH_{}_ONLY", other),
+            McPageAccess::DontCare => "DONT_CARE".to_string(),
+        }
+    }
+}
+
+/// Properties of a calibration page, reported by GET_PAGE_INFO and written to the A2L PAGE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McPageProperties {
+    pub ecu_access: McPageAccess,
+    pub xcp_read_access: McPageAccess,
+    pub xcp_write_access: McPageAccess,
+    pub init_segment: Option<u8>, // Segment which initializes this page, None is the own segment
+}
+
+impl McPageProperties {
+    /// Working page (RAM), read and write access by ECU and XCP
+    pub const WORKING_PAGE: McPageProperties = McPageProperties {
+        ecu_access: McPageAccess::DontCare,
+        xcp_read_access: McPageAccess::DontCare,
+        xcp_write_access: McPageAccess::DontCare,
+        init_segment: None,
+    };
+
+    /// Reference page (FLASH) with the default values, read only for XCP
+    pub const REFERENCE_PAGE: McPageProperties = McPageProperties {
+        ecu_access: McPageAccess::DontCare,
+        xcp_read_access: McPageAccess::DontCare,
+        xcp_write_access: McPageAccess::NotAllowed,
+        init_segment: None,
+    };
+
+    /// Get the XCP PAGE_PROPERTIES byte of GET_PAGE_INFO
+    pub fn to_xcp_page_properties(&self) -> u8 {
+        self.ecu_access.to_bits() | (self.xcp_read_access.to_bits() << 2) | (self.xcp_write_access.to_bits() << 4)
+    }
+}
+
+impl std::fmt::Display for McPageProperties {
+    // A2L PAGE properties, e.g. ECU_ACCESS_DONT_CARE XCP_READ_ACCESS_DONT_CARE XCP_WRITE_ACCESS_NOT_ALLOWED
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "ECU_ACCESS_{} XCP_READ_ACCESS_{} XCP_WRITE_ACCESS_{}",
+            self.ecu_access.to_a2l("XCP"),
+            self.xcp_read_access.to_a2l("ECU"),
+            self.xcp_write_access.to_a2l("ECU")
+        )?;
+        if let Some(init_segment) = self.init_segment {
+            write!(f, " INIT_SEGMENT {}", init_segment)?;
+        }
+        Ok(())
+    }
+}
+
 //-------------------------------------------------------------------------------------------------
 // Calibration segments
 
@@ -77,6 +161,20 @@ impl McCalibrationSegment {
         self.mem_addr = mem_addr;
     }
 
+    /// Get the number of pages, a working page and a reference page
+    pub fn get_max_pages(&self) -> u8 {
+        2
+    }
+
+    /// Get the properties of a page, page 0 is the working page, page 1 the reference page with the default values
+    pub fn get_page_properties(&self, page: u8) -> Option<McPageProperties> {
+        match page {
+            0 => Some(McPageProperties::WORKING_PAGE),
+            1 => Some(McPageProperties::REFERENCE_PAGE),
+            _ => None,
+        }
+    }
+
     /// Get the full indexed name of the calibration segment
     /// The calibration segment name may not be unique, segments with the same name may be created by multiple thread instances of a task, this is indicated by index > 0
     /// The name is prefixed with the application name if prefix_names is set
@@ -275,3 +373,48 @@ impl<'a> IntoIterator for &'a mut McCalibrationSegmentList {
         McCalibrationSegmentListIteratorMut::new(self)
     }
 }
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod mc_calseg_tests {
+
+    use super::*;
+    use crate::registry_test::write_test_a2l;
+
+    #[test]
+    fn a2l_page_properties() {
+        let mut reg = Registry::new();
+        reg.set_xcp_eth_params("UDP", std::net::Ipv4Addr::new(127, 0, 0, 1), 5555);
+        let seg = reg.cal_seg_list.add_cal_seg("seg", Some(0), 16).unwrap();
+        assert_eq!(seg.get_page_properties(0).unwrap().to_xcp_page_properties(), 0x3F);
+        assert_eq!(seg.get_page_properties(1).unwrap().to_xcp_page_properties(), 0x0F);
+        assert!(seg.get_page_properties(2).is_none());
+
+        let a2l = write_test_a2l(&reg);
+        assert!(a2l.contains("/begin SEGMENT 0 /*number*/ 2 /*pages*/"), "{}", a2l);
+        assert!(
+            a2l.contains("/begin PAGE 0x0 ECU_ACCESS_DONT_CARE XCP_READ_ACCESS_DONT_CARE XCP_WRITE_ACCESS_DONT_CARE /end PAGE"),
+            "{}",
+            a2l
+        );
+        assert!(
+            a2l.contains("/begin PAGE 0x1 ECU_ACCESS_DONT_CARE XCP_READ_ACCESS_DONT_CARE XCP_WRITE_ACCESS_NOT_ALLOWED /end PAGE"),
+            "{}",
+            a2l
+        );
+
+        let properties = McPageProperties {
+            ecu_access: McPageAccess::WithoutOtherOnly,
+            xcp_read_access: McPageAccess::WithOtherOnly,
+            xcp_write_access: McPageAccess::NotAllowed,
+            init_segment: Some(1),
+        };
+        assert_eq!(properties.to_xcp_page_properties(), 0x09);
+        assert_eq!(
+            properties.to_string(),
+            "ECU_ACCESS_WITHOUT_XCP_ONLY XCP_READ_ACCESS_WITH_ECU_ONLY XCP_WRITE_ACCESS_NOT_ALLOWED INIT_SEGMENT 1"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//-----------------------------------------------------------------------------
+// Module mc_compose
+// Composition of several registries into one A2L, e.g. one registry per shared library with its own events and calibration segments
+// All parts are served by one XCP server and share its event id and calibration segment index space, each part gets its own partition
+// The parts are either written as distinct A2L MODULEs (Registry::write_a2l_modules_to_writer) or merged into one registry with a name prefix per part (Registry::compose)
+
+use std::collections::HashMap;
+
+use crate::McCalibrationSegment;
+use crate::McDimType;
+use crate::McEvent;
+use crate::McIdentifier;
+use crate::McMeasurementConfig;
+use crate::McTypeDef;
+use crate::McValueType;
+use crate::Registry;
+use crate::RegistryError;
+
+/// Partition of the event id and calibration segment index space for a part of a composed registry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct McIdPartition {
+    pub event_base: u16,   // Added to all event ids
+    pub cal_seg_base: u16, // Added to all calibration segment indices
+}
+
+impl McIdPartition {
+    pub fn new(event_base: u16, cal_seg_base: u16) -> McIdPartition {
+        McIdPartition { event_base, cal_seg_base }
+    }
+}
+
+// Prefix an identifier with "<prefix>."
+fn prefixed(prefix: &str, name: &str) -> McIdentifier {
+    if prefix.is_empty() {
+        McIdentifier::from(name.to_string())
+    } else {
+        McIdentifier::from(format!("{}.{}", prefix, name))
+    }
+}
+
+impl Registry {
+    /// Move all event ids and calibration segment indices of this registry into a partition of the id space
+    /// Addresses depending on the ids are updated with update_event_mapping and update_cal_seg_mapping
+    /// Returns a report of all changes, one line per change
+    pub fn partition_ids(&mut self, partition: McIdPartition) -> Result<Vec<String>, RegistryError> {
+        let mut events = HashMap::new();
+        for event in &self.event_list {
+            let id = event.get_id().checked_add(partition.event_base).ok_or(RegistryError::IndexOverflow)?;
+            events.insert(event.get_id(), id);
+        }
+        let mut cal_segs = HashMap::new();
+        for segment in &self.cal_seg_list {
+            let index = segment.get_index().checked_add(partition.cal_seg_base).ok_or(RegistryError::IndexOverflow)?;
+            cal_segs.insert(segment.get_index(), index);
+        }
+        let mut report = self.update_event_mapping(&events, false);
+        report.extend(self.update_cal_seg_mapping(&cal_segs, false));
+        Ok(report)
+    }
+
+    /// Check that the event ids and calibration segment indices of several registries do not overlap
+    /// Returns the conflicts, one line per conflict
+    pub fn check_id_partitions(parts: &[(&str, &Registry)]) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        let mut events: HashMap<u16, &str> = HashMap::new();
+        let mut cal_segs: HashMap<u16, &str> = HashMap::new();
+        for (name, registry) in parts {
+            for event in &registry.event_list {
+                if let Some(other) = events.insert(event.get_id(), name) {
+                    conflicts.push(format!("event id {} used by {} and {}", event.get_id(), other, name));
+                }
+            }
+            for segment in &registry.cal_seg_list {
+                if let Some(other) = cal_segs.insert(segment.get_index(), name) {
+                    conflicts.push(format!("calibration segment index {} used by {} and {}", segment.get_index(), other, name));
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Merge the events, calibration segments, typedefs, instances and measurement configurations of another registry into this registry
+    /// All names of the part are prefixed with "<prefix>.", references to typedefs, calibration segments and axis are renamed accordingly
+    /// The ids of the part must already be in their own partition (see partition_ids), id conflicts fail with RegistryError::Duplicate
+    pub fn compose(&mut self, part: &Registry, prefix: &str) -> Result<(), RegistryError> {
+        if let Some(conflict) = Registry::check_id_partitions(&[("registry", self), (prefix, part)]).into_iter().next() {
+            return Err(RegistryError::Duplicate(conflict));
+        }
+
+        for event in &part.event_list {
+            self.event_list.push(McEvent {
+                name: prefixed(prefix, event.get_name()),
+                index: event.index,
+                id: event.id,
+                target_cycle_time_ns: event.target_cycle_time_ns,
+                function: event.function,
+                unit: event.unit,
+                cfa: event.cfa,
+                on_change_max_interval_ns: event.on_change_max_interval_ns,
+                stim: event.stim,
+                stim_only: event.stim_only,
+                consistency: event.consistency,
+                default_cycle_time_ns: event.default_cycle_time_ns,
+            });
+        }
+
+        for segment in &part.cal_seg_list {
+            let name = prefixed(prefix, segment.get_name());
+            if self.cal_seg_list.into_iter().any(|s| s.name == name) {
+                return Err(RegistryError::Duplicate(name.to_string()));
+            }
+            self.cal_seg_list.push(McCalibrationSegment {
+                name,
+                index: segment.index,
+                addr: segment.addr,
+                addr_ext: segment.addr_ext,
+                mem_addr: segment.mem_addr,
+                size: segment.size,
+                number: segment.number,
+            });
+        }
+
+        let rename_type = |dim_type: &McDimType| -> McDimType {
+            let mut dim_type = dim_type.clone();
+            if let McValueType::TypeDef(name) = dim_type.value_type {
+                dim_type.value_type = McValueType::TypeDef(prefixed(prefix, &name));
+            }
+            dim_type
+        };
+
+        for typedef in &part.typedef_list {
+            let name = prefixed(prefix, typedef.get_name());
+            if self.typedef_list.find_typedef(&name).is_some() {
+                return Err(RegistryError::Duplicate(name.to_string()));
+            }
+            let mut new_typedef = McTypeDef::new(name, typedef.size);
+            for field in &typedef.fields {
+                new_typedef.add_field(field.get_name(), rename_type(field.get_dim_type()), field.get_mc_support_data().clone(), field.get_offset())?;
+            }
+            self.typedef_list.push(new_typedef);
+        }
+
+        for instance in &part.instance_list {
+            let mut address = *instance.get_address();
+            if let Some(calseg_name) = address.get_calseg_name() {
+                address.set_calseg_name(prefixed(prefix, &calseg_name));
+            }
+            let mut support_data = instance.get_mc_support_data().clone();
+            for name in [
+                &mut support_data.x_axis_ref,
+                &mut support_data.y_axis_ref,
+                &mut support_data.x_axis_input_quantity,
+                &mut support_data.y_axis_input_quantity,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                *name = prefixed(prefix, name);
+            }
+            self.instance_list
+                .add_instance(prefixed(prefix, instance.get_name()), rename_type(instance.get_dim_type()), support_data, address)?;
+        }
+
+        for config in &part.measurement_config_list {
+            self.measurement_config_list.add_config(McMeasurementConfig {
+                name: prefixed(prefix, &config.name),
+                comment: config.comment,
+                measurements: config.measurements.iter().map(|m| prefixed(prefix, m)).collect(),
+                auto_start: config.auto_start,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod mc_compose_tests {
+
+    use super::*;
+    use crate::{McAddress, McObjectType, McSupportData};
+
+    // A part with one event, one calibration segment, a measurement and a characteristic
+    fn test_part() -> Registry {
+        let mut reg = Registry::new();
+        reg.event_list.add_event(McEvent::new("task", 0, 0, 0)).unwrap();
+        reg.cal_seg_list.add_cal_seg("params", None, 4).unwrap();
+        reg.instance_list
+            .add_instance(
+                "counter",
+                McDimType::new(McValueType::Ulong, 1, 1),
+                McSupportData::new(McObjectType::Measurement),
+                McAddress::new_event_dyn(0, 0, 0),
+            )
+            .unwrap();
+        reg.instance_list
+            .add_instance(
+                "gain",
+                McDimType::new(McValueType::Ulong, 1, 1),
+                McSupportData::new(McObjectType::Characteristic),
+                McAddress::new_calseg_rel("params", 0),
+            )
+            .unwrap();
+        reg
+    }
+
+    #[test]
+    fn test_compose() {
+        let part1 = test_part();
+        let mut part2 = test_part();
+        assert_eq!(Registry::check_id_partitions(&[("lib1", &part1), ("lib2", &part2)]).len(), 2);
+
+        part2.partition_ids(McIdPartition::new(100, 10)).unwrap();
+        assert!(Registry::check_id_partitions(&[("lib1", &part1), ("lib2", &part2)]).is_empty());
+        assert_eq!(
+            part2.instance_list.into_iter().find(|i| i.get_name() == "counter").unwrap().address.get_event_id(),
+            Some(100)
+        );
+
+        // Distinct MODULEs
+        let mut a2l = Vec::new();
+        Registry::write_a2l_modules_to_writer(&mut a2l, "test", "project", "", "P1", &[("lib1", &part1), ("lib2", &part2)]).unwrap();
+        let a2l = String::from_utf8(a2l).unwrap();
+        assert!(a2l.contains("/begin MODULE lib1") && a2l.contains("/begin MODULE lib2"));
+        assert_eq!(a2l.matches("/end PROJECT").count(), 1);
+        assert!(Registry::write_a2l_modules_to_writer(&mut Vec::new(), "test", "project", "", "P1", &[("lib1", &part1), ("lib1b", &part1)]).is_err());
+
+        // One MODULE with name prefixes
+        let mut reg = Registry::new();
+        reg.compose(&part1, "lib1").unwrap();
+        reg.compose(&part2, "lib2").unwrap();
+        assert!(reg.compose(&part2, "lib3").is_err()); // Id conflict
+        assert_eq!(reg.event_list.len(), 2);
+        let gain = reg.instance_list.into_iter().find(|i| i.get_name() == "lib2.gain").unwrap();
+        assert_eq!(gain.address.get_calseg_name().unwrap(), "lib2.params");
+        assert!(reg.event_list.find_event("lib2.task", 0).is_some());
+    }
+}
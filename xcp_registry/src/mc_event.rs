@@ -13,6 +13,30 @@ use crate::RegistryError;
 use super::McIdentifier;
 use super::McText;
 
+//----------------------------------------------------------------------------------------------
+// McEventConsistency
+
+/// Data consistency of an event, the A2L CONSISTENCY of the event channel
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum McEventConsistency {
+    #[default]
+    Daq, // Consistency of each DAQ list
+    Event, // All DAQ lists of the event are consistent with each other
+    Odt,   // Consistency of single ODTs only
+    None,  // No consistency
+}
+
+impl std::fmt::Display for McEventConsistency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            McEventConsistency::Daq => write!(f, "DAQ"),
+            McEventConsistency::Event => write!(f, "EVENT"),
+            McEventConsistency::Odt => write!(f, "ODT"),
+            McEventConsistency::None => write!(f, "NONE"),
+        }
+    }
+}
+
 //----------------------------------------------------------------------------------------------
 // McEvent
 
@@ -28,6 +52,16 @@ pub struct McEvent {
     pub function: Option<McText>,  // Name of the function where the event is defined, used to find local variables for this event
     pub unit: Option<usize>,       // Index of the compilation unit where the event is defined, used to find local variables for this event
     pub cfa: i32,                  // Canonical stack frame address offset where the event is defined, used to access local variables for this event
+    #[serde(default)]
+    pub on_change_max_interval_ns: u32, // 0 -> every trigger is measured, otherwise value changes and a heartbeat after this interval only
+    #[serde(default)]
+    pub stim: bool, // Bidirectional event, DAQ lists of this event may be used for stimulation (STIM) too
+    #[serde(default)]
+    pub stim_only: bool, // Event with direction STIM only, its variables can not be measured
+    #[serde(default)]
+    pub consistency: McEventConsistency,
+    #[serde(default)]
+    pub default_cycle_time_ns: u32, // Default measurement rate recommended for tools, 0 -> target cycle time
 }
 
 impl McEvent {
@@ -42,6 +76,11 @@ impl McEvent {
             function: None,
             unit: None,
             cfa: 0,
+            on_change_max_interval_ns: 0,
+            stim: false,
+            stim_only: false,
+            consistency: McEventConsistency::Daq,
+            default_cycle_time_ns: 0,
         }
     }
 
@@ -63,6 +102,20 @@ impl McEvent {
         self.id = id;
     }
 
+    /// Get the default measurement rate as cycle time in ns, the target cycle time if not set explicitly, 0 for a sporadic event
+    pub fn get_default_cycle_time_ns(&self) -> u32 {
+        if self.default_cycle_time_ns > 0 {
+            self.default_cycle_time_ns
+        } else {
+            self.target_cycle_time_ns
+        }
+    }
+
+    /// Variables of this event can be measured with DAQ lists of direction DAQ
+    pub fn is_daq(&self) -> bool {
+        !self.stim_only
+    }
+
     /// Get the full indexed name of the event
     /// The event name may not be unique, events with the same name may be created by multiple thread instances of a task, this is indicated by index > 0
     pub fn get_unique_name(&self, registry: &Registry) -> Cow<'static, str> {
@@ -145,9 +198,16 @@ impl McEventList {
         self.0.iter().find(|e| e.unit == Some(unit_idx) && e.function.as_deref() == Some(function))
     }
 
+    /// All events defined in a function, each with the CFA offset of its own trigger site
+    /// Used to address the local variables of functions with multiple triggers
+    pub fn find_events_by_location<'a>(&'a self, unit_idx: usize, function: &'a str) -> impl Iterator<Item = &'a McEvent> {
+        self.0.iter().filter(move |e| e.unit == Some(unit_idx) && e.function.as_deref() == Some(function))
+    }
+
     /// Store the unit index and function name where the event is defined
     /// This is used to find local variables for this event
-    /// Multiple events may be defined in the same function
+    /// Multiple events may be defined in the same function, the CFA offset is stored per event, which is per trigger site
+    /// For a trigger in an inlined function, function is the inlined function and cfa the CFA offset of the function it is inlined into
     pub fn set_event_location(&mut self, name: &str, unit_idx: usize, function: &str, cfa: i32) -> Result<(), RegistryError> {
         if let Some(event) = self.0.iter_mut().find(|e| e.name == name) {
             event.unit = Some(unit_idx);
@@ -228,3 +288,73 @@ impl<'a> IntoIterator for &'a mut McEventList {
         McEventListIteratorMut::new(self)
     }
 }
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod mc_event_tests {
+
+    use super::*;
+    #[cfg(feature = "a2l_reader")]
+    use crate::registry_test::include_test_aml;
+    use crate::registry_test::write_test_a2l;
+    #[cfg(feature = "a2l_reader")]
+    use crate::{A2lLoadOptions, McAddress, McDimType, McObjectType, McSupportData, McValueType};
+
+    #[test]
+    fn a2l_stim_event() {
+        let mut reg = Registry::new();
+        reg.set_xcp_eth_params("UDP", std::net::Ipv4Addr::new(127, 0, 0, 1), 5555);
+        reg.event_list.add_event(McEvent::new("daq", 0, 0, 0)).unwrap();
+        let a2l = write_test_a2l(&reg);
+        assert!(!a2l.contains("DAQ_STIM") && !a2l.contains("/begin STIM"), "{}", a2l);
+
+        let mut event = McEvent::new("bypass", 0, 1, 0);
+        event.stim = true;
+        reg.event_list.add_event(event).unwrap();
+        let a2l = write_test_a2l(&reg);
+        assert!(a2l.contains(r#""bypass" 1 DAQ_STIM 0xFF"#), "{}", a2l);
+        assert!(a2l.contains(r#""daq" 0 DAQ 0xFF"#), "{}", a2l);
+        assert!(a2l.contains("/begin STIM GRANULARITY_ODT_ENTRY_SIZE_STIM_BYTE 0xF8 /end STIM"), "{}", a2l);
+
+        // Kept in JSON
+        let mut reg2 = Registry::new();
+        reg2.load_json_str(&reg.to_json_string().unwrap()).unwrap();
+        assert!(reg2.event_list.find_event_id(1).unwrap().stim);
+        assert!(!reg2.event_list.find_event_id(0).unwrap().stim);
+    }
+
+    #[test]
+    #[cfg(feature = "a2l_reader")]
+    fn a2l_stim_only_event() {
+        let mut reg = Registry::new();
+        reg.set_xcp_eth_params("UDP", std::net::Ipv4Addr::new(127, 0, 0, 1), 5555);
+        reg.event_list.add_event(McEvent::new("daq", 0, 0, 0)).unwrap();
+        let mut event = McEvent::new("bypass", 0, 1, 0);
+        event.stim_only = true;
+        event.consistency = McEventConsistency::Odt;
+        reg.event_list.add_event(event).unwrap();
+        let mea = McSupportData::new(McObjectType::Measurement);
+        for (name, event) in [("a", 0), ("b", 1)] {
+            reg.instance_list
+                .add_instance(name, McDimType::new(McValueType::Ubyte, 1, 1), mea.clone(), McAddress::new_event_dyn(0, event, 0))
+                .unwrap();
+        }
+
+        // No measurement group for the STIM only event
+        let a2l = write_test_a2l(&reg);
+        assert!(a2l.contains(r#""bypass" 1 STIM 0xFF 0 0 0 CONSISTENCY ODT"#), "{}", a2l);
+        assert!(a2l.contains("/begin GROUP Measurements \"\" ROOT /begin SUB_GROUP daq /end SUB_GROUP"), "{}", a2l);
+        assert!(!a2l.contains("/begin GROUP bypass"), "{}", a2l);
+
+        // Direction and consistency are loaded from A2L
+        let a2l = include_test_aml(&a2l);
+        let mut reg2 = Registry::new();
+        reg2.load_a2l_str(&a2l, &A2lLoadOptions::default()).unwrap();
+        let bypass = reg2.event_list.find_event_id(1).unwrap();
+        assert!(bypass.stim_only && !bypass.is_daq());
+        assert_eq!(bypass.consistency, McEventConsistency::Odt);
+        assert!(reg2.event_list.find_event_id(0).unwrap().is_daq());
+    }
+}
@@ -0,0 +1,173 @@
+//-----------------------------------------------------------------------------
+// Module mc_field_desc
+// Manual field descriptors for types which can not derive McRegisterType, e.g. types from third-party crates
+// A list of field descriptors creates the same typedef as the derive macro, offsets and types are given by the user and checked against the type size
+
+use super::McDimType;
+use super::McIdentifier;
+use super::McObjectType;
+use super::McSupportData;
+use super::McText;
+use super::McValueType;
+use super::Registry;
+use super::RegistryError;
+
+//-------------------------------------------------------------------------------------------------
+// McFieldDesc
+
+/// Description of a field of a foreign type, with name, offset and type
+/// Metadata is set with the builder methods, e.g. `McFieldDesc::new("kp", std::mem::offset_of!(Pid, kp), McValueType::Float32Ieee).set_min(0.0).set_unit("1/s")`
+/// Fields without object type become characteristics in a calibration segment and measurements of an event
+#[derive(Debug)]
+pub struct McFieldDesc {
+    name: McIdentifier,
+    offset: usize,
+    dim_type: McDimType,
+    object_type: Option<McObjectType>, // None is the default object type of the registration target
+    mc_support_data: McSupportData,
+}
+
+impl McFieldDesc {
+    /// Field of basic type value_type at offset in bytes from the start of the type
+    pub fn new<T: Into<McIdentifier>>(name: T, offset: usize, value_type: McValueType) -> Self {
+        McFieldDesc {
+            name: name.into(),
+            offset,
+            dim_type: McDimType::new(value_type, 1, 1),
+            object_type: None,
+            mc_support_data: McSupportData::new(McObjectType::Unspecified),
+        }
+    }
+
+    /// Array dimensions, curve (x_dim) or map (x_dim, y_dim)
+    pub fn set_dim(mut self, x_dim: u16, y_dim: u16) -> Self {
+        self.dim_type = McDimType::new(self.dim_type.value_type, x_dim, y_dim);
+        self
+    }
+
+    /// Object type, overrides the default of the registration target
+    pub fn set_object_type(mut self, object_type: McObjectType) -> Self {
+        self.object_type = Some(object_type);
+        self
+    }
+
+    pub fn set_min(mut self, min: f64) -> Self {
+        self.mc_support_data = self.mc_support_data.set_min(Some(min));
+        self
+    }
+
+    pub fn set_max(mut self, max: f64) -> Self {
+        self.mc_support_data = self.mc_support_data.set_max(Some(max));
+        self
+    }
+
+    pub fn set_step(mut self, step: f64) -> Self {
+        self.mc_support_data = self.mc_support_data.set_step(Some(step));
+        self
+    }
+
+    pub fn set_unit<T: Into<McText>>(mut self, unit: T) -> Self {
+        self.mc_support_data = self.mc_support_data.set_unit(unit);
+        self
+    }
+
+    pub fn set_comment<T: Into<McText>>(mut self, comment: T) -> Self {
+        self.mc_support_data = self.mc_support_data.set_comment(comment);
+        self
+    }
+
+    /// Linear conversion physical = factor * raw + offset
+    pub fn set_linear<T: Into<McText>>(mut self, factor: f64, offset: f64, unit: T) -> Self {
+        self.mc_support_data = self.mc_support_data.set_linear(factor, offset, unit);
+        self
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        self.name.as_str()
+    }
+
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn get_dim_type(&self) -> &McDimType {
+        &self.dim_type
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Registry
+
+impl Registry {
+    /// Add a typedef with size bytes from a list of field descriptors
+    /// Fields without object type get default_object_type
+    /// Returns an error, if a field exceeds the size of the type, or if the typedef or a field name already exists
+    pub fn add_typedef_from_fields<T: Into<McIdentifier>>(
+        &mut self,
+        type_name: T,
+        size: usize,
+        fields: Vec<McFieldDesc>,
+        default_object_type: McObjectType,
+    ) -> Result<(), RegistryError> {
+        let type_name: McIdentifier = type_name.into();
+        for field in &fields {
+            if field.dim_type.is_typedef() || field.offset + field.dim_type.get_size() > size {
+                return Err(RegistryError::OutOfBounds(format!("{}.{}", type_name, field.name)));
+            }
+        }
+        self.add_typedef(type_name, size)?;
+        for field in fields {
+            let mc_support_data = field.mc_support_data.set_object_type(field.object_type.unwrap_or(default_object_type));
+            // Offset fits u16, checked against size above, typedefs are limited to 64 KiB
+            let offset = u16::try_from(field.offset).map_err(|_| RegistryError::OutOfBounds(format!("{}.{}", type_name, field.name)))?;
+            self.add_typedef_field(type_name.as_str(), field.name, field.dim_type, mc_support_data, offset)?;
+        }
+        Ok(())
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test module
+
+#[cfg(test)]
+mod mc_field_desc_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_field_desc() {
+        // Layout of a foreign type
+        #[repr(C)]
+        struct Pid {
+            kp: f32,
+            ki: f32,
+            limits: [i16; 4],
+        }
+
+        let mut reg = Registry::new();
+        let fields = vec![
+            McFieldDesc::new("kp", std::mem::offset_of!(Pid, kp), McValueType::Float32Ieee).set_min(0.0).set_max(10.0),
+            McFieldDesc::new("ki", std::mem::offset_of!(Pid, ki), McValueType::Float32Ieee).set_unit("1/s"),
+            McFieldDesc::new("limits", std::mem::offset_of!(Pid, limits), McValueType::Sword)
+                .set_dim(4, 1)
+                .set_object_type(McObjectType::Measurement),
+        ];
+        reg.add_typedef_from_fields("Pid", std::mem::size_of::<Pid>(), fields, McObjectType::Characteristic)
+            .unwrap();
+        let typedef = reg.typedef_list.find_typedef("Pid").unwrap();
+        let kp = typedef.find_field("kp").unwrap();
+        assert_eq!(kp.mc_support_data.get_max(McValueType::Float32Ieee), Some(10.0));
+        assert!(kp.mc_support_data.is_characteristic());
+        let limits = typedef.find_field("limits").unwrap();
+        assert_eq!((limits.offset, limits.dim_type.get_dim()), (8, [4, 1]));
+        assert!(limits.mc_support_data.is_measurement_object());
+
+        // Field exceeding the type
+        let fields = vec![McFieldDesc::new("x", 6, McValueType::Ulong)];
+        assert!(matches!(
+            reg.add_typedef_from_fields("Small", 8, fields, McObjectType::Characteristic),
+            Err(RegistryError::OutOfBounds(_))
+        ));
+        assert!(reg.typedef_list.find_typedef("Small").is_none());
+    }
+}
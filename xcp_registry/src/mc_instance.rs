@@ -10,6 +10,7 @@ use super::McDimType;
 use super::McIdentifier;
 use super::McObjectType;
 use super::McSupportData;
+use super::McText;
 use super::McValueType;
 use super::Registry;
 use super::RegistryError;
@@ -27,6 +28,12 @@ pub struct McInstance {
     pub dim_type: McDimType,            // Type, metadata and matrix dimensions, recursion here if McValueType::TypeDef
     pub mc_support_data: McSupportData, // Metadata for the instance
     pub address: McAddress,             // Addressing information for the instance
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<McText>, // Source location file:line of the declaration or registration, emitted as A2L ANNOTATION
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol_link: Option<(McText, i32)>, // Linker symbol and offset of the instance address, emitted as A2L SYMBOL_LINK
 }
 
 impl McInstance {
@@ -36,6 +43,8 @@ impl McInstance {
             dim_type,
             mc_support_data,
             address,
+            source: None,
+            symbol_link: None,
         }
     }
 
@@ -51,6 +60,16 @@ impl McInstance {
         &self.address
     }
 
+    /// Get the source location file:line, if known
+    pub fn get_source(&self) -> Option<&'static str> {
+        self.source.map(|s| s.as_str())
+    }
+
+    /// Get the linker symbol and the offset of the instance address to the symbol, if known
+    pub fn get_symbol_link(&self) -> Option<(&'static str, i32)> {
+        self.symbol_link.map(|(symbol, offset)| (symbol.as_str(), offset))
+    }
+
     /// Get the instance name
     /// The instance name may not be unique
     pub fn get_name(&self) -> &'static str {
@@ -210,6 +229,50 @@ impl McInstanceList {
         self.0.append(other.0.as_mut());
     }
 
+    /// Keep only the instances for which f returns true
+    pub fn retain<F: FnMut(&McInstance) -> bool>(&mut self, f: F) {
+        self.0.retain(f);
+    }
+
+    /// Remove the instance name with event id event_id (None for calibration objects), returns the removed instance
+    pub fn remove(&mut self, name: &str, event_id: Option<u16>) -> Option<McInstance> {
+        let index = self.0.iter().position(|i| i.name == name && i.event_id() == event_id)?;
+        Some(self.0.remove(index))
+    }
+
+    /// Set the source location of the instance name with event id event_id (None for calibration objects)
+    /// The source location is the DWARF decl_file and decl_line of a variable, or the registration site in the application
+    pub fn set_source(&mut self, name: &str, event_id: Option<u16>, file: &str, line: u32) -> Result<(), RegistryError> {
+        let instance = self
+            .0
+            .iter_mut()
+            .find(|i| i.name == name && i.event_id() == event_id)
+            .ok_or_else(|| RegistryError::NotFound(name.to_string()))?;
+        instance.source = Some(format!("{}:{}", file, line).into());
+        Ok(())
+    }
+
+    /// Set the linker symbol and the address offset to the symbol of the instance name with event id event_id (None for calibration objects)
+    /// The symbol is used to update the address from a new ELF file by symbol name
+    pub fn set_symbol_link(&mut self, name: &str, event_id: Option<u16>, symbol: &str, offset: i32) -> Result<(), RegistryError> {
+        let instance = self
+            .0
+            .iter_mut()
+            .find(|i| i.name == name && i.event_id() == event_id)
+            .ok_or_else(|| RegistryError::NotFound(name.to_string()))?;
+        instance.symbol_link = Some((symbol.to_string().into(), offset));
+        Ok(())
+    }
+
+    /// Mangle hierarchical names of flattened instances to identifiers, '.' is replaced by '_'
+    pub fn mangle_names(&mut self) {
+        for instance in &mut self.0 {
+            if instance.name.as_str().contains('.') {
+                instance.name = instance.name.as_str().replace('.', "_").into();
+            }
+        }
+    }
+
     pub fn sort_by_name(&mut self) {
         self.0.sort_by(|a, b| a.name.cmp(&b.name));
     }
@@ -378,3 +441,79 @@ impl McInstanceList {
         self.0.iter_mut()
     }
 }
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod mc_instance_tests {
+
+    use super::*;
+    use crate::registry_test::write_test_a2l;
+    use crate::{McAddress, McDimType, McEvent, McObjectType, McSupportData, McValueType, update_a2l_addresses_str};
+
+    #[test]
+    fn a2l_source_annotation() {
+        let mut reg = Registry::new();
+        reg.event_list.add_event(McEvent::new("task", 0, 0, 0)).unwrap();
+        reg.cal_seg_list.add_cal_seg("seg", None, 4).unwrap();
+        reg.instance_list
+            .add_instance(
+                "counter",
+                McDimType::new(McValueType::Ulong, 1, 1),
+                McSupportData::new(McObjectType::Measurement),
+                McAddress::new_event_dyn(0, 0, 0),
+            )
+            .unwrap();
+        reg.instance_list
+            .add_instance(
+                "gain",
+                McDimType::new(McValueType::Ulong, 1, 1),
+                McSupportData::new(McObjectType::Characteristic),
+                McAddress::new_calseg_rel("seg", 0),
+            )
+            .unwrap();
+        reg.instance_list.set_source("counter", Some(0), "src\\main.rs", 42).unwrap();
+        assert!(reg.instance_list.set_source("counter", None, "src/main.rs", 42).is_err());
+
+        let a2l = write_test_a2l(&reg);
+        assert!(
+            a2l.contains(r#"ANNOTATION_LABEL "SourceLocation" ANNOTATION_ORIGIN "" /begin ANNOTATION_TEXT "src/main.rs:42""#),
+            "{}",
+            a2l
+        );
+        assert_eq!(a2l.matches("SourceLocation").count(), 1);
+
+        // Kept in JSON
+        let mut reg2 = Registry::new();
+        reg2.load_json_str(&reg.to_json_string().unwrap()).unwrap();
+        let counter = reg2.instance_list.into_iter().find(|i| i.get_name() == "counter").unwrap();
+        assert_eq!(counter.get_source(), Some("src\\main.rs:42"));
+    }
+
+    #[test]
+    fn a2l_symbol_link_update_addresses() {
+        let mut reg = Registry::new();
+        reg.cal_seg_list.add_cal_seg("seg", None, 8).unwrap();
+        reg.instance_list
+            .add_instance(
+                "gain",
+                McDimType::new(McValueType::Ulong, 1, 1),
+                McSupportData::new(McObjectType::Characteristic),
+                McAddress::new_calseg_rel("seg", 4),
+            )
+            .unwrap();
+        reg.instance_list.set_symbol_link("gain", None, "SEG", 4).unwrap();
+        assert!(reg.instance_list.set_symbol_link("gain", Some(0), "SEG", 4).is_err());
+
+        let a2l = write_test_a2l(&reg);
+        assert!(a2l.contains(r#"SYMBOL_LINK "SEG" 4"#), "{}", a2l);
+
+        // Only the address of the linked object changes
+        let symbols = std::collections::HashMap::from([("SEG".to_string(), 0x12340000)]);
+        let (updated, report) = update_a2l_addresses_str(&a2l, &symbols).unwrap();
+        assert_eq!(report.len(), 1, "{:?}", report);
+        assert!(updated.contains("0x12340004"));
+        assert_eq!(updated.lines().count(), a2l.lines().count());
+    }
+}
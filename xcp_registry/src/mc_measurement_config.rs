@@ -0,0 +1,212 @@
+//-----------------------------------------------------------------------------
+// Module mc_measurement_config
+// Measurement configurations predefined by the application, named sets of measurement signals
+// Each signal is acquired with its own event, so a configuration may cover multiple events
+// Written to the A2L as GROUPs in the Presets root group, a tool offers them as ready-made measurement configurations
+// A configuration marked as auto start is annotated with ANNOTATION_LABEL "auto_start", to be started by headless loggers on connect
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::RegistryError;
+
+use super::McIdentifier;
+use super::McText;
+
+/// A2L ANNOTATION_LABEL of measurement configurations which should be started automatically
+pub const MC_AUTO_START_LABEL: &str = "auto_start";
+
+//----------------------------------------------------------------------------------------------
+// McMeasurementConfig
+
+/// A named measurement configuration, a set of measurement signals
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct McMeasurementConfig {
+    pub name: McIdentifier,              // Name of the configuration, the A2L GROUP name
+    pub comment: McText,                 // Description of the configuration
+    pub measurements: Vec<McIdentifier>, // Names of the measurement signals
+    #[serde(default)]
+    pub auto_start: bool, // Start automatically, e.g. by a headless logger
+}
+
+impl McMeasurementConfig {
+    /// Create a new measurement configuration with name, description and the names of the measurement signals
+    pub fn new<T: Into<McIdentifier>, C: Into<McText>>(name: T, comment: C, measurements: &[&str]) -> Self {
+        McMeasurementConfig {
+            name: name.into(),
+            comment: comment.into(),
+            measurements: measurements.iter().map(|m| McIdentifier::from(m.to_string())).collect(),
+            auto_start: false,
+        }
+    }
+
+    /// Mark the configuration to be started automatically
+    pub fn set_auto_start(&mut self, auto_start: bool) {
+        self.auto_start = auto_start;
+    }
+}
+
+//----------------------------------------------------------------------------------------------
+// McMeasurementConfigList
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct McMeasurementConfigList(Vec<McMeasurementConfig>);
+
+impl McMeasurementConfigList {
+    pub fn new() -> Self {
+        McMeasurementConfigList(Vec::new())
+    }
+
+    /// Add a measurement configuration, the name must be unique
+    pub fn add_config(&mut self, config: McMeasurementConfig) -> Result<(), RegistryError> {
+        log::debug!("Registry add_measurement_config: {:?}", config);
+        if self.find_config(&config.name).is_some() {
+            return Err(RegistryError::Duplicate(config.name.to_string()));
+        }
+        self.0.push(config);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Find a measurement configuration by name
+    pub fn find_config(&self, name: &str) -> Option<&McMeasurementConfig> {
+        self.0.iter().find(|c| c.name == name)
+    }
+
+    /// All measurement configurations to be started automatically
+    pub fn auto_start_configs(&self) -> impl Iterator<Item = &McMeasurementConfig> {
+        self.0.iter().filter(|c| c.auto_start)
+    }
+}
+
+impl<'a> IntoIterator for &'a McMeasurementConfigList {
+    type Item = &'a McMeasurementConfig;
+    type IntoIter = std::slice::Iter<'a, McMeasurementConfig>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// Test
+
+#[cfg(test)]
+mod mc_measurement_config_tests {
+
+    use super::*;
+    use crate::registry_test::write_test_a2l;
+    #[cfg(feature = "a2l_reader")]
+    use crate::{A2lLoadOptions, registry_test::include_test_aml};
+    use crate::{McAddress, McDimType, McEvent, McObjectType, McSupportData, McValueType, Registry};
+
+    #[test]
+    fn a2l_measurement_presets() {
+        let mut reg = Registry::new();
+        reg.set_xcp_eth_params("UDP", std::net::Ipv4Addr::new(127, 0, 0, 1), 5555);
+        reg.event_list.add_event(McEvent::new("fast", 0, 0, 1_000_000)).unwrap();
+        let mut event = McEvent::new("slow", 0, 1, 0);
+        event.default_cycle_time_ns = 100_000_000;
+        reg.event_list.add_event(event).unwrap();
+        reg.event_list.add_event(McEvent::new("empty", 0, 2, 0)).unwrap();
+        let mea = McSupportData::new(McObjectType::Measurement);
+        for (name, event) in [("a", 0), ("b", 0), ("c", 1)] {
+            reg.instance_list
+                .add_instance(name, McDimType::new(McValueType::Ubyte, 1, 1), mea.clone(), McAddress::new_event_dyn(0, event, 0))
+                .unwrap();
+        }
+
+        let a2l = write_test_a2l(&reg);
+        assert!(a2l.contains("/* default measurement rate = 100000000 ns */"), "{}", a2l);
+        assert!(!a2l.contains("preset_"), "{}", a2l);
+
+        reg.set_measurement_presets_mode(true);
+        let a2l = write_test_a2l(&reg);
+        assert!(
+            a2l.contains("/begin GROUP Presets \"\" ROOT /begin SUB_GROUP preset_fast preset_slow /end SUB_GROUP /end GROUP"),
+            "{}",
+            a2l
+        );
+        assert!(
+            a2l.contains(r#"/begin GROUP preset_fast "Measurement preset of event fast, 1 ms" /begin REF_MEASUREMENT a b /end REF_MEASUREMENT /end GROUP"#),
+            "{}",
+            a2l
+        );
+        assert!(
+            a2l.contains(r#"/begin GROUP preset_slow "Measurement preset of event slow, 100 ms" /begin REF_MEASUREMENT c"#),
+            "{}",
+            a2l
+        );
+    }
+
+    #[test]
+    fn a2l_measurement_configs() {
+        let mut reg = Registry::new();
+        reg.set_xcp_eth_params("UDP", std::net::Ipv4Addr::new(127, 0, 0, 1), 5555);
+        reg.event_list.add_event(McEvent::new("fast", 0, 0, 0)).unwrap();
+        reg.event_list.add_event(McEvent::new("slow", 0, 1, 0)).unwrap();
+        let mea = McSupportData::new(McObjectType::Measurement);
+        for (name, event) in [("a", 0), ("b", 0), ("c", 1)] {
+            reg.instance_list
+                .add_instance(name, McDimType::new(McValueType::Ubyte, 1, 1), mea.clone(), McAddress::new_event_dyn(0, event, 0))
+                .unwrap();
+        }
+        let mut config = McMeasurementConfig::new("logger", "Standard logger", &["a", "c"]);
+        config.set_auto_start(true);
+        reg.add_measurement_config(config).unwrap();
+        reg.add_measurement_config(McMeasurementConfig::new("debug", "Debug", &["b", "unknown"])).unwrap();
+        assert!(reg.add_measurement_config(McMeasurementConfig::new("debug", "", &[])).is_err());
+
+        let a2l = write_test_a2l(&reg);
+        assert!(
+            a2l.contains("/begin GROUP Presets \"\" ROOT /begin SUB_GROUP logger debug /end SUB_GROUP /end GROUP"),
+            "{}",
+            a2l
+        );
+        assert!(
+            a2l.contains(
+                r#"/begin GROUP logger "Standard logger" /begin ANNOTATION ANNOTATION_LABEL "auto_start" /end ANNOTATION /begin REF_MEASUREMENT a c /end REF_MEASUREMENT /end GROUP"#
+            ),
+            "{}",
+            a2l
+        );
+        assert!(
+            a2l.contains(r#"/begin GROUP debug "Debug" /begin REF_MEASUREMENT b /end REF_MEASUREMENT /end GROUP"#),
+            "{}",
+            a2l
+        );
+        assert!(!a2l.contains("preset_"), "{}", a2l);
+
+        // Kept in JSON
+        let mut reg2 = Registry::new();
+        reg2.load_json_str(&reg.to_json_string().unwrap()).unwrap();
+        assert_eq!(reg2.measurement_config_list.len(), 2);
+        assert_eq!(reg2.measurement_config_list.auto_start_configs().next().unwrap().name, "logger");
+
+        // Loaded from A2L, together with the generated presets
+        #[cfg(feature = "a2l_reader")]
+        {
+            reg.set_measurement_presets_mode(true);
+            let a2l = write_test_a2l(&reg);
+            let a2l = include_test_aml(&a2l);
+            let mut reg2 = Registry::new();
+            reg2.load_a2l_str(&a2l, &A2lLoadOptions::default()).unwrap();
+            assert_eq!(reg2.measurement_config_list.len(), 4);
+            let logger = reg2.measurement_config_list.find_config("logger").unwrap();
+            assert!(logger.auto_start);
+            assert_eq!(logger.measurements, vec![McIdentifier::new("a"), McIdentifier::new("c")]);
+            assert!(!reg2.measurement_config_list.find_config("preset_fast").unwrap().auto_start);
+
+            // Written once again, the loaded presets replace the generated presets
+            reg2.set_measurement_presets_mode(true);
+            let a2l2 = write_test_a2l(&reg2);
+            assert_eq!(a2l2.matches("/begin GROUP preset_fast").count(), 1, "{}", a2l2);
+        }
+    }
+}
@@ -138,3 +138,34 @@ pub trait McEnumType {
     /// The A2L enum-format unit string: `<int> "<label>"` pairs joined by spaces.
     fn mc_enum_unit() -> &'static str;
 }
+
+//----------------------------------------------------------------------------------------------
+// MeasureSafe
+
+/// Marker for plain-old-data types, which may be measured by reading their memory as bytes.
+///
+/// Implemented for the basic types and arrays of them. `#[derive(McRegisterType)]` implements it
+/// for structs of any repr whose fields are all MeasureSafe (the registry has their exact field offsets),
+/// `#[derive(McRegisterEnum)]` for integer enums. Pointers, references and heap owning types never implement it. The trigger
+/// and capture APIs require it, so measuring such a type is a compile error instead of silently
+/// delivering addresses or padding bytes.
+///
+/// # Safety
+/// None of the bytes of a value may be a pointer, NO_PADDING must be false if the type has padding (uninitialized) bytes.
+pub unsafe trait MeasureSafe {
+    /// True if the type has no padding bytes, asserted at compile time when a trigger or capture API is instantiated for the type.
+    /// The derived impl compares the size of the struct with the sum of its field sizes.
+    #[doc(hidden)]
+    const NO_PADDING: bool = true;
+}
+
+macro_rules! impl_measure_safe {
+    ( $( $t:ty ),* ) => {
+        $( unsafe impl MeasureSafe for $t {} )*
+    };
+}
+impl_measure_safe!(bool, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+unsafe impl<T: MeasureSafe, const N: usize> MeasureSafe for [T; N] {
+    const NO_PADDING: bool = T::NO_PADDING;
+}
@@ -8,20 +8,26 @@ use serde::Serialize;
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
 
-//use super::McAddress;
+use super::McAddress;
 use super::McCalibrationSegmentList;
 use super::McDimType;
 use super::McEventList;
 use super::McIdentifier;
+use super::McInstance;
 use super::McInstanceList;
+use super::McMeasurementConfig;
+use super::McMeasurementConfigList;
 use super::McObjectType;
 use super::McSupportData;
 use super::McText;
 use super::McTypeDef;
 use super::McTypeDefList;
+use super::McValueType;
 use super::McXcpTransportLayer;
 use super::RegistryError;
 use super::flatten_registry;
+use super::get_flattened_instance_list;
+use super::resolve_instance_member;
 
 //-------------------------------------------------------------------------------------------------
 // McApplicationVersion
@@ -55,6 +61,8 @@ pub struct McApplication {
     pub name: McIdentifier,            // Name of the application, used as A2L filename and module name
     pub description: McText,           // Optional description of the application
     pub version: McApplicationVersion, // Version or EPK string with address
+    #[serde(default)]
+    pub system_constants: Vec<(McText, McText)>, // A2L MOD_PAR SYSTEM_CONSTANTs (name, value)
 }
 
 impl McApplication {
@@ -64,6 +72,7 @@ impl McApplication {
             name: "".into(),
             description: "".into(),
             version: McApplicationVersion::default(),
+            system_constants: Vec::new(),
         }
     }
 
@@ -101,6 +110,33 @@ impl McApplication {
     pub fn get_version(&self) -> &str {
         self.version.epk.as_str()
     }
+
+    /// Set a system constant, written as A2L MOD_PAR SYSTEM_CONSTANT, replaces the value of an existing system constant
+    pub fn set_system_constant<A: Into<McText>, B: Into<McText>>(&mut self, name: A, value: B) {
+        let (name, value): (McText, McText) = (name.into(), value.into());
+        log::debug!("Registry set system constant {}={}", name, value);
+        match self.system_constants.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.system_constants.push((name, value)),
+        }
+    }
+
+    /// Get the value of a system constant
+    pub fn get_system_constant(&self, name: &str) -> Option<&str> {
+        self.system_constants.iter().find(|(n, _)| n.as_str() == name).map(|(_, v)| v.as_str())
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+// McIdMapping
+
+/// Event id and calibration segment index mapping tables, created by Registry::get_id_mapping
+/// Apply with Registry::update_event_mapping and Registry::update_cal_seg_mapping
+#[derive(Debug, Default)]
+pub struct McIdMapping {
+    pub events: HashMap<u16, u16>,   // Event id -> target event id
+    pub cal_segs: HashMap<u16, u16>, // Calibration segment index -> target index
+    pub conflicts: Vec<String>,      // Unmatched objects and id conflicts
 }
 
 //-------------------------------------------------------------------------------------------------
@@ -119,6 +155,11 @@ pub struct Registry {
     #[serde(skip_deserializing)]
     prefix_names: bool,
 
+    // Write measurement presets (GROUPs preset_<event>) when writing A2L
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    measurement_presets: bool,
+
     // Application name and software version
     pub application: McApplication,
 
@@ -138,6 +179,10 @@ pub struct Registry {
 
     // All measurement and calibration objects, sorted list
     pub instance_list: McInstanceList,
+
+    // Measurement configurations predefined by the application
+    #[serde(default)]
+    pub measurement_config_list: McMeasurementConfigList,
 }
 
 impl Default for Registry {
@@ -152,12 +197,14 @@ impl Registry {
         Registry {
             flatten_typedefs: false,
             prefix_names: false,
+            measurement_presets: false,
             application: McApplication::new(),
             xcp_tl_params: None,
             event_list: McEventList::new(),
             cal_seg_list: McCalibrationSegmentList::new(),
             typedef_list: McTypeDefList::new(),
             instance_list: McInstanceList::new(),
+            measurement_config_list: McMeasurementConfigList::new(),
         }
     }
 
@@ -195,6 +242,12 @@ impl Registry {
         self.xcp_tl_params.is_some()
     }
 
+    /// Get the Ethernet transport layer parameters (protocol name "UDP" or "TCP", address, port), e.g. loaded from the A2L IF_DATA XCP_ON_UDP_IP or XCP_ON_TCP_IP
+    pub fn get_xcp_eth_params(&self) -> Option<(&'static str, Ipv4Addr, u16)> {
+        let tl = self.xcp_tl_params.as_ref()?;
+        Some((tl.protocol_name, tl.addr?, tl.port?))
+    }
+
     //---------------------------------------------------------------------------------------------------------
     // Modes
 
@@ -214,6 +267,22 @@ impl Registry {
         self.prefix_names
     }
 
+    /// Write a measurement preset for each event when writing A2L, a GROUP preset_<event> with all measurements of the event
+    pub fn set_measurement_presets_mode(&mut self, measurement_presets: bool) {
+        self.measurement_presets = measurement_presets;
+    }
+    pub fn get_measurement_presets_mode(&self) -> bool {
+        self.measurement_presets
+    }
+
+    //---------------------------------------------------------------------------------------------------------
+    // Measurement configurations
+
+    /// Add a predefined measurement configuration, written to the A2L as GROUP in the Presets root group
+    pub fn add_measurement_config(&mut self, config: McMeasurementConfig) -> Result<(), RegistryError> {
+        self.measurement_config_list.add_config(config)
+    }
+
     //---------------------------------------------------------------------------------------------------------
     // Typedefs
 
@@ -287,6 +356,25 @@ impl Registry {
         Ok(self.typedef_list.get_mut(index))
     }
 
+    //---------------------------------------------------------------------------------------------------------
+    // Instance removal
+    // Only possible while the registry is open, after the A2L is written the client tool relies on the instances
+    // Typedefs are kept, they may be used by other instances
+
+    /// Remove a measurement (event_id Some) or calibration object (event_id None) instance
+    /// Returns the removed instance or NotFound
+    pub fn remove_instance(&mut self, name: &str, event_id: Option<u16>) -> Result<McInstance, RegistryError> {
+        log::debug!("Registry remove_instance: {} event_id={:?}", name, event_id);
+        self.instance_list.remove(name, event_id).ok_or_else(|| RegistryError::NotFound(name.to_string()))
+    }
+
+    /// Remove all measurement instances of an event, returns the number of removed instances
+    pub fn remove_event_instances(&mut self, event_id: u16) -> usize {
+        let len = self.instance_list.len();
+        self.instance_list.retain(|i| i.event_id() != Some(event_id));
+        len - self.instance_list.len()
+    }
+
     //---------------------------------------------------------------------------------------------------------
 
     /// Collapses all typedefs to measurement and calibration objects with mangled names
@@ -294,6 +382,19 @@ impl Registry {
         flatten_registry(self);
     }
 
+    /// Get all basic type leaf instances with the names flatten_typedefs would create, without modifying the registry
+    pub fn get_flattened_instance_list(&self) -> McInstanceList {
+        get_flattened_instance_list(self)
+    }
+
+    /// Resolve a measurement or calibration object by name, without flattening the registry
+    /// Typedef members and array elements are resolved by the names flatten_typedefs would create, e.g. "cal_seg.test_ints.test_u8"
+    /// or "cal_seg.items._1.a", elements of basic type arrays with the same index suffix, e.g. "cal_seg.curve._3" or "cal_seg.map._1_2"
+    /// Returns a basic type instance with the address of the member, None if not found or not a basic type
+    pub fn resolve_instance(&self, name: &str, object_type: McObjectType, event_id: Option<u16>) -> Option<McInstance> {
+        resolve_instance_member(self, name, object_type, event_id)
+    }
+
     //---------------------------------------------------------------------------------------------------------
     // Set support data on a typedef field reachable from a named instance
 
@@ -367,59 +468,207 @@ impl Registry {
     }
 
     // ---------------------------------------------------------------------------------------------------------
-    // Update the calibration segment numbers from a mapping table
-    pub fn update_cal_seg_mapping(&mut self, mapping: &HashMap<u16, u16>) {
+    /// Build event id and calibration segment index mapping tables from a reference registry
+    /// The reference contains the real ids and indices of the target, e.g. read from the XCPlite BIN persistence file,
+    /// while this registry has been created offline (from ELF debug information) with unknown or dummy ids
+    /// Events are matched by name and instance index, calibration segments by name
+    /// Unmatched objects and id conflicts are reported, conflicting entries are not mapped
+    pub fn get_id_mapping(&self, reference: &Registry) -> McIdMapping {
+        let mut mapping = McIdMapping::default();
+
+        // Events
+        for event in &self.event_list {
+            match reference.event_list.find_event(event.get_name(), event.index) {
+                Some(r) => {
+                    if r.get_id() != event.get_id() {
+                        mapping.events.insert(event.get_id(), r.get_id());
+                    }
+                }
+                None => mapping
+                    .conflicts
+                    .push(format!("event {} not found in reference, id {} unchanged", event.get_name(), event.get_id())),
+            }
+        }
+        let ids: Vec<(String, u16)> = self.event_list.into_iter().map(|e| (e.get_unique_name(self).into_owned(), e.get_id())).collect();
+        Self::remove_id_conflicts("event", &ids, &mut mapping.events, &mut mapping.conflicts);
+
+        // Calibration segments
+        for segment in &self.cal_seg_list {
+            match reference.cal_seg_list.into_iter().find(|r| r.name == segment.name) {
+                Some(r) => {
+                    if r.size != segment.size {
+                        mapping
+                            .conflicts
+                            .push(format!("segment {} size {} differs from reference size {}", segment.get_name(), segment.size, r.size));
+                    }
+                    if r.get_index() != segment.get_index() {
+                        mapping.cal_segs.insert(segment.get_index(), r.get_index());
+                    }
+                }
+                None => mapping
+                    .conflicts
+                    .push(format!("segment {} not found in reference, index {} unchanged", segment.get_name(), segment.get_index())),
+            }
+        }
+        let indices: Vec<(String, u16)> = self.cal_seg_list.into_iter().map(|s| (s.get_name().to_string(), s.get_index())).collect();
+        Self::remove_id_conflicts("segment", &indices, &mut mapping.cal_segs, &mut mapping.conflicts);
+
+        for conflict in &mapping.conflicts {
+            log::warn!("Id mapping: {}", conflict);
+        }
+        mapping
+    }
+
+    // Remove mappings to ids which are kept by unmapped objects
+    // Removing a mapping keeps another id unchanged, repeat until there are no more conflicts
+    fn remove_id_conflicts(kind: &str, ids: &[(String, u16)], mapping: &mut HashMap<u16, u16>, conflicts: &mut Vec<String>) {
+        loop {
+            let conflict = ids.iter().find(|(_, id)| !mapping.contains_key(id) && mapping.values().any(|new_id| new_id == id));
+            let Some((name, id)) = conflict else { break };
+            conflicts.push(format!("{} {} keeps {}, which the reference assigns to another {}", kind, name, id, kind));
+            mapping.retain(|_, new_id| new_id != id);
+        }
+    }
+
+    /// Update the calibration segment indices from a mapping table (index -> new index)
+    /// Segment relative instances reference their segment by name and need no update
+    /// Segment base addresses derived from the index and raw A2L addresses within these segments (XCPlite segment relative address encoding) are rewritten
+    /// With dry_run, the registry is not modified
+    /// Returns a report of all changes, one line per change
+    pub fn update_cal_seg_mapping(&mut self, mapping: &HashMap<u16, u16>, dry_run: bool) -> Vec<String> {
+        let mut report = Vec::new();
+
+        // Segments, collect the address ranges (old base, size, new base) of segments with index based base address
+        let mut ranges: Vec<(u32, u32, u32)> = Vec::new();
         for segment in &mut self.cal_seg_list {
-            if let Some(new_index) = mapping.get(&segment.get_index()) {
-                segment.set_index(*new_index);
-                info!("Update calibration segment index {} -> {}", segment.get_index(), new_index);
+            let Some(new_index) = mapping.get(&segment.get_index()).copied() else {
+                continue;
+            };
+            report.push(format!("~ segment {}: index {} -> {}", segment.get_name(), segment.get_index(), new_index));
+            let (addr_ext, addr) = McAddress::get_calseg_ext_addr_base(segment.get_index());
+            let (new_addr_ext, new_addr) = McAddress::get_calseg_ext_addr_base(new_index);
+            let rebase = segment.addr_ext == addr_ext && segment.addr == addr;
+            if rebase {
+                report.push(format!(
+                    "~ segment {}: address {}:0x{:08X} -> {}:0x{:08X}",
+                    segment.get_name(),
+                    addr_ext,
+                    addr,
+                    new_addr_ext,
+                    new_addr
+                ));
+                ranges.push((addr, segment.size, new_addr));
+            }
+            if !dry_run {
+                segment.set_index(new_index);
+                if rebase {
+                    segment.addr_ext = new_addr_ext;
+                    segment.addr = new_addr;
+                }
             }
         }
 
-        // @@@@ XCPlite with absolute segment addressing mode needs no update
-        // Update of ADDR_MODE_A2L not checked
+        // Raw A2L addresses of instances in rebased segments
+        for instance in &mut self.instance_list {
+            if !instance.address.get_addr_mode().is_a2l() {
+                continue;
+            }
+            let (addr_ext, addr) = instance.address.get_raw_a2l_addr();
+            if addr_ext != McAddress::XCP_ADDR_EXT_SEG {
+                continue;
+            }
+            if let Some((base, _, new_base)) = ranges.iter().find(|(base, size, _)| addr >= *base && addr - *base < *size) {
+                let new_addr = addr - base + new_base;
+                report.push(format!("~ {}: address {}:0x{:08X} -> {}:0x{:08X}", instance.get_name(), addr_ext, addr, addr_ext, new_addr));
+                if !dry_run {
+                    instance.address.set_raw_a2l_addr(addr_ext, new_addr);
+                }
+            }
+        }
 
-        for instance in &self.instance_list {
-            if instance.address.is_segment_relative() {
-                // Not implemented
-                unimplemented!();
+        if !dry_run {
+            for line in &report {
+                info!("Update calibration segment mapping: {}", line);
             }
         }
+        report
     }
 
-    // Update the event id from a map (used by xcpclient to update the event id after connecting to the ECU)
-    pub fn update_event_mapping(&mut self, mapping: &HashMap<u16, u16>) {
+    /// Update the event ids from a mapping table (id -> new id), used by xcpclient to update the event ids after connecting to the ECU
+    /// The event of event relative, absolute and A2L (IF_DATA XCP) instance addresses is rewritten
+    /// Raw A2L addresses with XCPlite event relative address encoding (event id in the high bits of the address, address extension XCP_ADDR_EXT_DYN..) are rewritten
+    /// With dry_run, the registry is not modified
+    /// Returns a report of all changes, one line per change
+    pub fn update_event_mapping(&mut self, mapping: &HashMap<u16, u16>, dry_run: bool) -> Vec<String> {
+        let mut report = Vec::new();
+
         for event in &mut self.event_list {
-            if let Some(new_id) = mapping.get(&event.get_id()) {
-                info!("Update event {} id {} -> {}", event.get_name(), event.get_id(), new_id);
-                event.set_id(*new_id);
+            let Some(new_id) = mapping.get(&event.get_id()).copied() else {
+                continue;
+            };
+            report.push(format!("~ event {}: id {} -> {}", event.get_name(), event.get_id(), new_id));
+            if !dry_run {
+                event.set_id(new_id);
             }
         }
+
         for instance in &mut self.instance_list {
-            if instance.address.is_event_relative() {
-                unimplemented!();
+            let name = instance.get_name();
+            let address = &mut instance.address;
+
+            // Event association
+            if let Some(event_id) = address.get_event_id()
+                && let Some(new_id) = mapping.get(&event_id).copied()
+            {
+                report.push(format!("~ {}: event {} -> {}", name, event_id, new_id));
+                if !dry_run {
+                    address.set_event_id(new_id);
+                }
             }
-            if instance.address.get_addr_mode().is_a2l() {
-                // @@@@ TODO: Hardcoded XCPlite specific address encoding
-                let addr = instance.address.get_raw_a2l_addr();
-                if addr.0 >= 2 {
-                    unimplemented!();
-                    // let event_id: u16 = (addr.1 >> McAddress::XCP_ADDR_EXT_DYN_OFFSET_BITS) as u16;
-                    // info!("Checking address update for {}: {}:0x{:08X} event_id={}", instance.get_name(), addr.0, addr.1, event_id);
-                    // if let Some(new_id) = mapping.get(&event_id) {
-                    //     let new_addr: u32 = ((*new_id as u32) << 16) | (addr.1 & 0xFFFF);
-                    //     instance.address.set_raw_a2l_addr(addr.0, new_addr);
-                    //     log::info!(
-                    //         "XCPlite specific event id update in address of ‘{}‘: {}:0x{:08X} -> 0x{:08X}",
-                    //         instance.get_name(),
-                    //         addr.0,
-                    //         addr.1,
-                    //         new_addr
-                    //     );
-                    // }
+
+            // XCPlite event relative address encoding in raw A2L addresses
+            if address.get_addr_mode().is_a2l() {
+                let (addr_ext, addr) = address.get_raw_a2l_addr();
+                if !(McAddress::XCP_ADDR_EXT_DYN..McAddress::XCP_ADDR_EXT_DYN + 16).contains(&addr_ext) {
+                    continue;
+                }
+                #[allow(clippy::cast_possible_truncation)]
+                let event_id = (addr >> McAddress::XCP_ADDR_EXT_DYN_OFFSET_BITS) as u16;
+                let Some(new_id) = mapping.get(&event_id).copied() else {
+                    continue;
+                };
+                if (new_id as u32) > (u32::MAX >> McAddress::XCP_ADDR_EXT_DYN_OFFSET_BITS) {
+                    report.push(format!("! {}: event {} can not be encoded in the address, not updated", name, new_id));
+                    continue;
                 }
+                let new_addr = ((new_id as u32) << McAddress::XCP_ADDR_EXT_DYN_OFFSET_BITS) | (addr & McAddress::XCP_ADDR_EXT_DYN_OFFSET_MASK);
+                report.push(format!("~ {}: address {}:0x{:08X} -> {}:0x{:08X}", name, addr_ext, addr, addr_ext, new_addr));
+                if !dry_run {
+                    address.set_raw_a2l_addr(addr_ext, new_addr);
+                }
+            }
+        }
+
+        if !dry_run {
+            for line in &report {
+                info!("Update event mapping: {}", line);
             }
         }
+        report
+    }
+
+    //---------------------------------------------------------------------------------------------------------
+    // Memory usage
+
+    /// Estimated memory used by the registry in bytes
+    /// Based on the number of objects, names and texts are not included
+    pub fn get_memory_usage(&self) -> usize {
+        let field_count: usize = self.typedef_list.into_iter().map(|t| t.fields.len()).sum();
+        self.instance_list.len() * std::mem::size_of::<super::McInstance>()
+            + self.typedef_list.len() * std::mem::size_of::<McTypeDef>()
+            + field_count * std::mem::size_of::<super::McTypeDefField>()
+            + self.event_list.len() * std::mem::size_of::<super::McEvent>()
+            + self.cal_seg_list.len() * std::mem::size_of::<super::McCalibrationSegment>()
     }
 
     //---------------------------------------------------------------------------------------------------------
@@ -431,7 +680,7 @@ impl Registry {
         log::info!("Write JSON file {}", path.display());
         let json_file = std::fs::File::create(path)?;
         let mut writer = std::io::BufWriter::new(json_file);
-        let s = serde_json::to_string_pretty(&self).map_err(|e| std::io::Error::other(format!("serde_json::to_string failed: {}", e)))?;
+        let s = self.to_json_string()?;
         std::io::Write::write_all(&mut writer, s.as_ref())?;
         Ok(())
     }
@@ -446,4 +695,228 @@ impl Registry {
         *self = r;
         Ok(())
     }
+
+    /// Serialize registry to a JSON string
+    pub fn to_json_string(&self) -> Result<String, std::io::Error> {
+        serde_json::to_string_pretty(&self).map_err(|e| std::io::Error::other(format!("serde_json::to_string failed: {}", e)))
+    }
+
+    /// Deserialize registry from a JSON string
+    pub fn load_json_str(&mut self, json: &str) -> Result<(), std::io::Error> {
+        let r: Registry = serde_json::from_str(json).map_err(|e| std::io::Error::other(format!("serde_json::from_str failed: {}", e)))?;
+        *self = r;
+        Ok(())
+    }
+
+    //---------------------------------------------------------------------------------------------------------
+    // Compare registries
+    // Addresses are compared as stored, segment or event relative addresses are not resolved
+
+    /// Compare this registry with another registry
+    /// Returns a list of differences in events, calibration segments and instances
+    /// Lines start with '+' (only in other), '-' (only in self) or '~' (changed)
+    pub fn diff(&self, other: &Registry) -> Vec<String> {
+        let mut diffs = Vec::new();
+
+        // Events by name and index
+        for e in &self.event_list {
+            match other.event_list.find_event(e.get_name(), e.index) {
+                None => diffs.push(format!("- event {}", e.get_unique_name(self))),
+                Some(o) if o.get_id() != e.get_id() => diffs.push(format!("~ event {}: id {} -> {}", e.get_unique_name(self), e.get_id(), o.get_id())),
+                _ => {}
+            }
+        }
+        for o in &other.event_list {
+            if self.event_list.find_event(o.get_name(), o.index).is_none() {
+                diffs.push(format!("+ event {}", o.get_unique_name(other)));
+            }
+        }
+
+        // Calibration segments by name
+        for c in &self.cal_seg_list {
+            match other.cal_seg_list.into_iter().find(|o| o.get_name() == c.get_name()) {
+                None => diffs.push(format!("- segment {}", c.get_name())),
+                Some(o) if o.size != c.size || o.addr != c.addr || o.addr_ext != c.addr_ext => diffs.push(format!(
+                    "~ segment {}: {}:0x{:08X} size {} -> {}:0x{:08X} size {}",
+                    c.get_name(),
+                    c.addr_ext,
+                    c.addr,
+                    c.size,
+                    o.addr_ext,
+                    o.addr,
+                    o.size
+                )),
+                _ => {}
+            }
+        }
+        for o in &other.cal_seg_list {
+            if !self.cal_seg_list.into_iter().any(|c| c.get_name() == o.get_name()) {
+                diffs.push(format!("+ segment {}", o.get_name()));
+            }
+        }
+
+        // Instances by name and event
+        for i in &self.instance_list {
+            match other.instance_list.get_instance(i.get_name(), McObjectType::Unspecified, i.event_id()) {
+                None => diffs.push(format!("- {} {}", i.object_type(), i.get_name())),
+                Some(o) => {
+                    let name = i.get_name();
+                    if o.object_type() != i.object_type() {
+                        diffs.push(format!("~ {}: object type {} -> {}", name, i.object_type(), o.object_type()));
+                    }
+                    if o.get_dim_type() != i.get_dim_type() {
+                        diffs.push(format!("~ {}: type {} -> {}", name, i.get_dim_type(), o.get_dim_type()));
+                    }
+                    if o.get_address() != i.get_address() {
+                        diffs.push(format!("~ {}: address {} -> {}", name, address_str(i.get_address()), address_str(o.get_address())));
+                    }
+                    if o.unit() != i.unit() {
+                        diffs.push(format!("~ {}: unit \"{}\" -> \"{}\"", name, i.unit(), o.unit()));
+                    }
+                    if o.get_min() != i.get_min() || o.get_max() != i.get_max() {
+                        diffs.push(format!("~ {}: limits {:?}..{:?} -> {:?}..{:?}", name, i.get_min(), i.get_max(), o.get_min(), o.get_max()));
+                    }
+                }
+            }
+        }
+        for o in &other.instance_list {
+            if self.instance_list.get_instance(o.get_name(), McObjectType::Unspecified, o.event_id()).is_none() {
+                diffs.push(format!("+ {} {}", o.object_type(), o.get_name()));
+            }
+        }
+
+        diffs
+    }
+
+    //---------------------------------------------------------------------------------------------------------
+    // Layout analysis
+    // The alignment of a basic type is its size, the alignment of a typedef is the maximum alignment of its fields
+
+    /// Analyze the memory layout of the typedefs (measured structs and calibration pages) and the calibration segments
+    /// Reports holes between fields, padding bytes at the end of a typedef, the smaller size a typedef would have with its fields
+    /// ordered by alignment and bytes of calibration segments not covered by any calibration object
+    /// Returns a list of findings, one line per finding, empty if the layout has no holes and no padding
+    pub fn layout_report(&self) -> Vec<String> {
+        let mut report = Vec::new();
+
+        // Typedefs
+        for typedef in &self.typedef_list {
+            let name = typedef.get_name();
+            let mut fields: Vec<(usize, usize, &str)> = Vec::new();
+            let mut packed_size = 0;
+            let mut max_align = 1;
+            for field in &typedef.fields {
+                let Some((size, align)) = self.get_dim_type_layout(field.get_dim_type(), 0) else {
+                    report.push(format!("typedef {}: field {} of unknown size, layout not analyzed", name, field.get_name()));
+                    fields.clear();
+                    break;
+                };
+                fields.push((field.get_offset() as usize, size, field.get_name()));
+                packed_size += size;
+                max_align = max_align.max(align);
+            }
+            if fields.is_empty() {
+                continue;
+            }
+            fields.sort_by_key(|f| f.0);
+
+            let mut pos = 0;
+            let mut prev: Option<&str> = None;
+            for (offset, size, field_name) in &fields {
+                if *offset > pos {
+                    match prev {
+                        Some(prev) => report.push(format!("typedef {}: hole of {} bytes at +{} between {} and {}", name, offset - pos, pos, prev, field_name)),
+                        None => report.push(format!("typedef {}: hole of {} bytes at +0 before {}", name, offset, field_name)),
+                    }
+                }
+                pos = pos.max(offset + size);
+                prev = Some(field_name);
+            }
+            if typedef.size > pos {
+                report.push(format!("typedef {}: {} padding bytes at +{}, size {}", name, typedef.size - pos, pos, typedef.size));
+            }
+            let packed_size = packed_size.next_multiple_of(max_align);
+            if packed_size < typedef.size {
+                report.push(format!(
+                    "typedef {}: size {}, {} with fields ordered by alignment, {} bytes alignment waste",
+                    name,
+                    typedef.size,
+                    packed_size,
+                    typedef.size - packed_size
+                ));
+            }
+        }
+
+        // Calibration segments, covered by the basic type leafs of all instances in the address range of the segment
+        let leafs = self.get_flattened_instance_list();
+        for segment in &self.cal_seg_list {
+            let mut ranges: Vec<(u32, u32)> = leafs
+                .into_iter()
+                .filter_map(|i| {
+                    let (size, _) = self.get_dim_type_layout(i.get_dim_type(), 0)?;
+                    let (addr_ext, addr) = i.get_address().get_a2l_addr(self);
+                    if addr_ext != segment.addr_ext || addr < segment.addr || addr - segment.addr >= segment.size {
+                        return None;
+                    }
+                    let offset = addr - segment.addr;
+                    Some((offset, offset.saturating_add(u32::try_from(size).unwrap_or(u32::MAX))))
+                })
+                .collect();
+            ranges.sort_unstable();
+
+            let mut pos = 0;
+            let mut unused = 0;
+            for (start, end) in ranges {
+                if start > pos {
+                    report.push(format!("segment {}: {} unused bytes at +{}", segment.get_name(), start - pos, pos));
+                    unused += start - pos;
+                }
+                pos = pos.max(end);
+            }
+            if segment.size > pos {
+                report.push(format!("segment {}: {} unused bytes at +{}", segment.get_name(), segment.size - pos, pos));
+                unused += segment.size - pos;
+            }
+            if unused > 0 {
+                report.push(format!("segment {}: size {}, {} bytes unused", segment.get_name(), segment.size, unused));
+            }
+        }
+
+        report
+    }
+
+    // Size and alignment of a basic type or typedef array, None for blobs, unknown types and unknown or recursive typedefs
+    fn get_dim_type_layout(&self, dim_type: &McDimType, depth: usize) -> Option<(usize, usize)> {
+        let [x_dim, y_dim] = dim_type.get_dim();
+        let (size, align) = match dim_type.value_type {
+            McValueType::Blob(_) | McValueType::Unknown => return None,
+            McValueType::TypeDef(name) => {
+                if depth > self.typedef_list.len() {
+                    return None;
+                }
+                let typedef = self.typedef_list.find_typedef(name.as_str())?;
+                let mut align = 1;
+                for field in &typedef.fields {
+                    align = align.max(self.get_dim_type_layout(field.get_dim_type(), depth + 1)?.1);
+                }
+                (typedef.size, align)
+            }
+            value_type => (value_type.get_size(), value_type.get_size()),
+        };
+        Some((size * x_dim as usize * y_dim as usize, align))
+    }
+}
+
+// Short address text for diff output
+fn address_str(address: &McAddress) -> String {
+    if address.get_addr_mode().is_a2l() {
+        let (addr_ext, addr) = address.get_raw_a2l_addr();
+        format!("{}:0x{:08X}", addr_ext, addr)
+    } else if let Some(calseg_name) = address.get_calseg_name() {
+        format!("{}+{}", calseg_name, address.get_addr_offset())
+    } else if let Some(event_id) = address.get_event_id() {
+        format!("event {}+{}", event_id, address.get_addr_offset())
+    } else {
+        format!("0x{:08X}", address.get_addr_offset())
+    }
 }
@@ -1,6 +1,7 @@
 // Module mc_support
 // McObjectType - Copy,Clone
 // McObjectQualifier - Copy,Clone
+// McConversion - Clone
 // McSupportData - Clone with warning
 
 use serde::{Deserialize, Serialize};
@@ -101,6 +102,65 @@ impl std::fmt::Display for McObjectQualifier {
     }
 }
 
+//----------------------------------------------------------------------------------------------
+// McConversion
+
+/// Conversion rule, which is not a linear factor and offset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum McConversion {
+    /// Rational function with the A2L RAT_FUNC coefficients a, b, c, d, e, f
+    /// raw_value = (a * x^2 + b * x + c) / (d * x^2 + e * x + f), x is the physical value
+    RatFunc([f64; 6]),
+    /// Value table of (raw_value, physical_value) pairs, sorted by raw value
+    /// Linear interpolation between the pairs (TAB_INTP) or the physical value of the nearest raw value (TAB_NOINTP)
+    Table { pairs: Vec<(f64, f64)>, interpolate: bool },
+    /// Verbal table of (raw_value, label) pairs (TAB_VERB), for enums
+    Verbal(Vec<(i64, String)>),
+}
+
+impl McConversion {
+    /// Convert a raw value to the physical value
+    /// Rational functions with quadratic terms (a or d not zero) are not inverted, the raw value is returned
+    /// Verbal tables have no numeric physical value, the raw value is returned
+    pub fn convert(&self, value: f64) -> f64 {
+        match self {
+            McConversion::RatFunc([a, b, c, d, e, f]) => {
+                // raw * (e * x + f) = b * x + c  ->  x = (f * raw - c) / (b - e * raw)
+                let div = b - e * value;
+                if *a != 0.0 || *d != 0.0 || div == 0.0 { value } else { (f * value - c) / div }
+            }
+            McConversion::Table { pairs, interpolate } => {
+                let Some(i) = pairs.iter().position(|(raw, _)| *raw >= value) else {
+                    return pairs.last().map_or(value, |(_, phys)| *phys);
+                };
+                if i == 0 {
+                    return pairs[0].1;
+                }
+                let (r0, p0) = pairs[i - 1];
+                let (r1, p1) = pairs[i];
+                if *interpolate {
+                    p0 + (p1 - p0) * (value - r0) / (r1 - r0)
+                } else if value - r0 < r1 - value {
+                    p0
+                } else {
+                    p1
+                }
+            }
+            McConversion::Verbal(_) => value,
+        }
+    }
+}
+
+impl std::fmt::Display for McConversion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            McConversion::RatFunc(coeffs) => write!(f, "RatFunc{:?}", coeffs),
+            McConversion::Table { pairs, interpolate } => write!(f, "Table(n={}, interpolate={})", pairs.len(), interpolate),
+            McConversion::Verbal(pairs) => write!(f, "Verbal(n={})", pairs.len()),
+        }
+    }
+}
+
 //----------------------------------------------------------------------------------------------
 // McSupportData
 
@@ -121,6 +181,10 @@ pub struct McSupportData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub offset: Option<f64>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<McConversion>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "McText::is_empty")]
     pub unit: McText,
@@ -164,6 +228,7 @@ impl Default for McSupportData {
             qualifier: McObjectQualifier::Unspecified,
             factor: None,
             offset: None,
+            conversion: None,
             unit: McText::default(),
             comment: McText::default(),
             min: None,
@@ -187,6 +252,7 @@ impl Clone for McSupportData {
             qualifier: self.qualifier,
             factor: self.factor,
             offset: self.offset,
+            conversion: self.conversion.clone(),
             unit: self.unit,
             comment: self.comment,
             min: self.min,
@@ -215,6 +281,9 @@ impl std::fmt::Display for McSupportData {
         if self.offset.is_some() {
             write!(f, " offset={}", self.offset.unwrap())?;
         }
+        if let Some(conversion) = &self.conversion {
+            write!(f, " conversion={}", conversion)?;
+        }
         if !self.unit.is_empty() {
             write!(f, " unit={}", self.unit)?;
         }
@@ -260,6 +329,7 @@ impl McSupportData {
             qualifier: McObjectQualifier::Unspecified,
             factor: None,
             offset: None,
+            conversion: None,
             unit: McText::default(),
             comment: McText::default(),
             min: None,
@@ -274,10 +344,17 @@ impl McSupportData {
         }
     }
 
-    /// Returns true if any descriptive metadata field (unit, min, max, factor, offset, step, comment)
+    /// Returns true if any descriptive metadata field (unit, min, max, factor, offset, conversion, step, comment)
     /// has been explicitly set. Does not consider object_type or qualifier.
     pub fn has_metadata(&self) -> bool {
-        !self.unit.is_empty() || self.min.is_some() || self.max.is_some() || self.factor.is_some() || self.offset.is_some() || self.step.is_some() || !self.comment.is_empty()
+        !self.unit.is_empty()
+            || self.min.is_some()
+            || self.max.is_some()
+            || self.factor.is_some()
+            || self.offset.is_some()
+            || self.conversion.is_some()
+            || self.step.is_some()
+            || !self.comment.is_empty()
     }
 
     /// Merge only the fields that are explicitly set in `other` into `self`.
@@ -303,6 +380,9 @@ impl McSupportData {
         if other.offset.is_some() {
             self.offset = other.offset;
         }
+        if other.conversion.is_some() {
+            self.conversion = other.conversion;
+        }
         if other.step.is_some() {
             self.step = other.step;
         }
@@ -329,6 +409,9 @@ impl McSupportData {
 
     // Conversion rule
     pub fn convert(&self, value: f64) -> f64 {
+        if let Some(conversion) = &self.conversion {
+            return conversion.convert(value);
+        }
         let mut result = value;
         // physical_value = value * factor + offset !!
         if let Some(factor) = self.factor {
@@ -376,6 +459,16 @@ impl McSupportData {
         self
     }
 
+    /// Set a conversion rule, which replaces factor and offset
+    pub fn set_conversion(mut self, conversion: Option<McConversion>) -> Self {
+        self.conversion = conversion;
+        if self.conversion.is_some() {
+            self.factor = None;
+            self.offset = None;
+        }
+        self
+    }
+
     pub fn set_unit<T: Into<McText>>(mut self, unit: T) -> Self {
         self.unit = unit.into();
         self
@@ -574,6 +667,11 @@ impl McSupportData {
         None
     }
 
+    /// Get the conversion rule, None for linear or identity
+    pub fn get_conversion(&self) -> Option<&McConversion> {
+        self.conversion.as_ref()
+    }
+
     /// Get the physical unit as &'static str
     pub fn get_unit(&self) -> &'static str {
         self.unit.as_str()
@@ -598,6 +696,8 @@ impl McSupportData {
 mod mc_support_data_tests {
 
     use super::*;
+    use crate::registry_test::write_test_a2l;
+    use crate::{McAddress, McDimType, Registry};
 
     #[test]
     fn test_mc_support_data() {
@@ -648,4 +748,76 @@ mod mc_support_data_tests {
         assert_eq!(m3.unit.as_str(), "Json-Unit");
         assert_eq!(m3.comment.as_str(), "Json-Comment");
     }
+
+    #[test]
+    fn test_mc_conversion() {
+        let rat = McConversion::RatFunc([0.0, 4.0, 8.0, 0.0, 0.0, 1.0]); // raw = 4 * phys + 8
+        assert_eq!(rat.convert(16.0), 2.0);
+        let quadratic = McConversion::RatFunc([1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(quadratic.convert(4.0), 4.0);
+
+        let pairs = vec![(0.0, 0.0), (10.0, 100.0), (20.0, 150.0)];
+        let intp = McConversion::Table {
+            pairs: pairs.clone(),
+            interpolate: true,
+        };
+        assert_eq!(intp.convert(-1.0), 0.0);
+        assert_eq!(intp.convert(15.0), 125.0);
+        assert_eq!(intp.convert(30.0), 150.0);
+        let nointp = McConversion::Table { pairs, interpolate: false };
+        assert_eq!(nointp.convert(12.0), 100.0);
+        assert_eq!(nointp.convert(18.0), 150.0);
+
+        // Replaces factor and offset, limits are converted
+        let m = McSupportData::new(McObjectType::Characteristic).set_linear(2.0, 1.0, "").set_conversion(Some(intp));
+        assert_eq!(m.get_factor(), None);
+        assert_eq!(m.get_max(McValueType::Ubyte), Some(150.0));
+    }
+
+    #[test]
+    fn a2l_compu_methods() {
+        let mut reg = Registry::new();
+        reg.cal_seg_list.add_cal_seg("seg", None, 8).unwrap();
+        let conversions = [
+            ("rat", McConversion::RatFunc([0.0, 2.0, 1.0, 0.0, 0.0, 1.0])),
+            (
+                "tab",
+                McConversion::Table {
+                    pairs: vec![(0.0, 0.0), (100.0, 25.5)],
+                    interpolate: true,
+                },
+            ),
+            ("mode", McConversion::Verbal(vec![(0, "OFF".to_string()), (1, "ON".to_string())])),
+        ];
+        for (i, (name, conversion)) in conversions.into_iter().enumerate() {
+            reg.instance_list
+                .add_instance(
+                    name,
+                    McDimType::new(McValueType::Ubyte, 1, 1),
+                    McSupportData::new(McObjectType::Characteristic).set_unit("V").set_conversion(Some(conversion)),
+                    McAddress::new_calseg_rel("seg", i32::try_from(i).unwrap()),
+                )
+                .unwrap();
+        }
+
+        let a2l = write_test_a2l(&reg);
+        assert!(
+            a2l.contains(r#"/begin COMPU_METHOD rat "" RAT_FUNC "%.3" "V" COEFFS 0 2 1 0 0 1 /end COMPU_METHOD"#),
+            "{}",
+            a2l
+        );
+        assert!(a2l.contains(r#"/begin COMPU_TAB tab.table "" TAB_INTP 2 0 0 100 25.5 /end COMPU_TAB"#), "{}", a2l);
+        assert!(
+            a2l.contains(r#"/begin COMPU_METHOD tab "" TAB_INTP "%.3" "V" COMPU_TAB_REF tab.table /end COMPU_METHOD"#),
+            "{}",
+            a2l
+        );
+        assert!(a2l.contains(r#"/begin COMPU_VTAB mode.table "" TAB_VERB 2 0 "OFF" 1 "ON" /end COMPU_VTAB"#), "{}", a2l);
+
+        // Kept in JSON
+        let mut reg2 = Registry::new();
+        reg2.load_json_str(&reg.to_json_string().unwrap()).unwrap();
+        let tab = reg2.instance_list.into_iter().find(|i| i.get_name() == "tab").unwrap();
+        assert_eq!(tab.get_mc_support_data().convert(50.0), 12.75);
+    }
 }
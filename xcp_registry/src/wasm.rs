@@ -0,0 +1,71 @@
+//-----------------------------------------------------------------------------
+// Module wasm
+// JavaScript bindings for browser based A2L tooling, build with:
+//   cargo rustc -p xcp_registry --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//   wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/xcp_registry.wasm
+// All functions work on in-memory A2L or JSON text, no file system or network access
+// Each call uses its own registry, the global registry of the crate is not used
+
+use wasm_bindgen::prelude::*;
+
+use super::{A2lLoadOptions, Registry};
+
+// Convert an error into a JavaScript exception
+fn js_err<E: std::fmt::Display>(e: E) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+// Parse A2L text into a new registry, nested typedefs are kept
+fn load(a2l_text: &str) -> Result<Registry, JsValue> {
+    let mut reg = Registry::new();
+    reg.load_a2l_str(
+        a2l_text,
+        &A2lLoadOptions {
+            print_warnings: false,
+            ..A2lLoadOptions::default()
+        },
+    )
+    .map_err(js_err)?;
+    Ok(reg)
+}
+
+// Write a registry as A2L text, project and module are named after the application
+fn write(reg: &Registry) -> Result<String, JsValue> {
+    let name = reg.application.get_name();
+    reg.write_a2l_to_string("", name, "", name, "").map_err(js_err)
+}
+
+/// Syntax and consistency check of A2L text
+/// Returns the number of warnings, throws on syntax errors
+#[wasm_bindgen]
+pub fn a2l_check(a2l_text: &str) -> Result<u32, JsValue> {
+    Registry::new().check_a2l_str(a2l_text).map_err(js_err)
+}
+
+/// Parse A2L text and write it again in the canonical format of the A2L writer
+/// Normalized files can be compared with a text diff
+#[wasm_bindgen]
+pub fn a2l_normalize(a2l_text: &str) -> Result<String, JsValue> {
+    write(&load(a2l_text)?)
+}
+
+/// Compare two A2L texts
+/// Returns one line per difference in events, calibration segments and instances, see Registry::diff
+#[wasm_bindgen]
+pub fn a2l_diff(a2l_text_a: &str, a2l_text_b: &str) -> Result<Vec<String>, JsValue> {
+    Ok(load(a2l_text_a)?.diff(&load(a2l_text_b)?))
+}
+
+/// Convert A2L text to the JSON representation of the registry
+#[wasm_bindgen]
+pub fn a2l_to_json(a2l_text: &str) -> Result<String, JsValue> {
+    load(a2l_text)?.to_json_string().map_err(js_err)
+}
+
+/// Generate A2L text from the JSON representation of the registry
+#[wasm_bindgen]
+pub fn json_to_a2l(json: &str) -> Result<String, JsValue> {
+    let mut reg = Registry::new();
+    reg.load_json_str(json).map_err(js_err)?;
+    write(&reg)
+}